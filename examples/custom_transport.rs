@@ -0,0 +1,63 @@
+//! Talks to a BootROM behind a raw TCP serial bridge (e.g. ser2net in raw mode) by
+//! implementing `Transport` for a `TcpStream`.
+//!
+//! Usage: custom_transport <host:port>
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+use mtk_uartboot::{Session, Transport};
+
+struct TcpTransport(TcpStream);
+
+impl Read for TcpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for TcpTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Transport for TcpTransport {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.0.set_read_timeout(Some(timeout))
+    }
+
+    fn set_baud_rate(&mut self, _baudrate: u32) -> io::Result<()> {
+        // The bridge runs the UART at a fixed speed.
+        Ok(())
+    }
+
+    fn clear_input(&mut self) -> io::Result<()> {
+        let mut buf = [0; 256];
+        self.0.set_nonblocking(true)?;
+        while let Ok(n) = self.0.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+        }
+        self.0.set_nonblocking(false)
+    }
+}
+
+fn main() -> mtk_uartboot::Result<()> {
+    let addr = std::env::args().nth(1).expect("missing bridge address");
+    let stream = TcpStream::connect(addr)?;
+
+    let mut session = Session::new(Box::new(TcpTransport(stream)));
+    let mut brom = session.bootrom();
+    brom.handshake()?;
+    let hw_code = brom.get_hw_code()?;
+    let (hw_sub_code, hw_ver, sw_ver) = brom.get_hw_dict()?;
+    println!("hw code: {:#x}", hw_code);
+    println!("hw sub code: {:#x} hw ver: {:#x} sw ver: {:#x}", hw_sub_code, hw_ver, sw_ver);
+    Ok(())
+}
@@ -0,0 +1,41 @@
+//! Sends an FIP to a running BL2 and draws a progress bar from the session events.
+//!
+//! Usage: progress <serial port> <fip>
+
+use std::io::Write;
+use std::time::Duration;
+use mtk_uartboot::{Event, Session};
+
+const BAR_WIDTH: usize = 40;
+
+fn draw(event: &Event) {
+    match event {
+        Event::Progress { sent, total, .. } => {
+            let done = sent * BAR_WIDTH / (*total).max(1);
+            print!("\r[{}{}] {}/{} bytes", "#".repeat(done), " ".repeat(BAR_WIDTH - done), sent, total);
+            std::io::stdout().flush().unwrap();
+            if sent == total {
+                println!();
+            }
+        }
+        Event::PacketRejected { idx, .. } => println!("\npacket {} rejected, resending", idx),
+        _ => {}
+    }
+}
+
+fn main() -> mtk_uartboot::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let port_name = args.next().expect("missing serial port");
+    let fip = std::fs::read(args.next().expect("missing fip"))?;
+
+    let port = serialport::new(&port_name, 115200)
+        .timeout(Duration::from_secs(2))
+        .open()?;
+    let mut session = Session::new(Box::new(port));
+    session.on_event(draw);
+
+    let mut bl2 = session.bl2();
+    bl2.handshake()?;
+    bl2.send_fip(&fip)?;
+    bl2.go()
+}
@@ -0,0 +1,39 @@
+//! Loads an aarch64 payload through the BootROM and starts it.
+//!
+//! Usage: simple_boot <serial port> <payload> [load address]
+
+use std::time::Duration;
+use mtk_uartboot::{ByteLen, LoadAddr, Session};
+
+fn main() -> mtk_uartboot::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let port_name = args.next().expect("missing serial port");
+    let payload_path = args.next().expect("missing payload");
+    let load_addr = match args.next() {
+        Some(a) => LoadAddr(u32::from_str_radix(a.trim_start_matches("0x"), 16).expect("bad load address")),
+        None => LoadAddr(0x201000),
+    };
+
+    let port = serialport::new(&port_name, 115200)
+        .timeout(Duration::from_secs(2))
+        .open()?;
+    let payload = std::fs::read(payload_path)?;
+
+    let mut session = Session::new(Box::new(port));
+    let mut brom = session.bootrom();
+    println!("Waiting for the BootROM...");
+    brom.handshake()?;
+    println!("hw code: {:#x}", brom.get_hw_code()?);
+
+    let target_config = brom.get_target_config()?;
+    if target_config.secure_boot {
+        println!("Secure boot is enabled, giving up.");
+        return Ok(());
+    }
+
+    let checksum = brom.send_da(load_addr, ByteLen(0), &payload)?;
+    println!("Payload sent, checksum {:#x}", checksum);
+    brom.jump_da64(load_addr)?;
+    println!("Jumped to {}", load_addr);
+    Ok(())
+}
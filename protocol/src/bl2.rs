@@ -0,0 +1,542 @@
+//! Frames of the UART download protocol in Mediatek TF-A BL2.
+//!
+//! Like the BootROM protocol, command bytes and arguments are echoed back. The FIP is sent
+//! in packets which BL2 acknowledges with the index it expects and the checksum it computed.
+//!
+//! BL2 builds speaking version [`FLASH_VERSION`] or later also write images to the storage
+//! they boot from with [`CMD_FLASH`], to recover boards without a working bootloader. The
+//! command is followed by the target, echoed back: [`FLASH_TARGET_OFFSET`] and the byte
+//! offset as 8 bytes, or [`FLASH_TARGET_PARTITION`], the length of the partition name as
+//! one byte and the name. Then the length of the image is echoed as 4 bytes, and BL2 answers
+//! with a status byte, [`FLASH_OK`] if it can write that much there. The image follows in
+//! packets like the FIP, and each packet is acknowledged once it's written. Finally BL2
+//! reads the image back from the storage and answers with a status byte and the CRC-32 of
+//! what it read.
+//!
+//! From [`FLASH_RANGE_VERSION`] on, BL2 also tells what's on the storage with
+//! [`CMD_FLASH_CRC`], so only the blocks which changed need to be written: the target as for
+//! CMD_FLASH, then the length of the range and the length of the blocks, each echoed as 4
+//! bytes. BL2 answers with a status byte, [`FLASH_OK`] if it could read the range, and the
+//! CRC-32 of each block as 4 bytes, the last one possibly shorter. Both commands then also
+//! take [`FLASH_TARGET_PARTITION_AT`], a partition like [`FLASH_TARGET_PARTITION`] followed
+//! by the byte offset in it as 8 bytes.
+//!
+//! From [`FLASH_SWITCH_VERSION`] on, partitions the device boots from can be updated without
+//! a window in which a failed transfer leaves it unbootable, where the layout has two of
+//! them, like the boot0 and boot1 partitions of eMMC. [`CMD_FLASH_ALTERNATE`] is followed
+//! by the length of a partition name as one byte and the name, echoed back, and BL2 answers
+//! with a status byte, [`FLASH_NO_ALTERNATE`] if there's none, and the name of the partition
+//! the device doesn't boot from in its place, again as its length and the name. Once that
+//! one is written, [`CMD_FLASH_SWITCH`] with its name, in the same form, makes the device
+//! boot from it, answered with a status byte.
+//!
+//! From [`FLASH_INFO_VERSION`] on, [`CMD_FLASH_INFO`] tells what the storage is and how it's
+//! partitioned, so the host can check targets before sending anything. BL2 answers with a
+//! status byte, the kind of storage as one byte, e.g. [`STORAGE_EMMC`], the length of its
+//! blocks as 4 bytes, its capacity as 8 bytes and the number of partitions as one byte. Each
+//! partition follows as the length of its name as one byte, the name, and its start and
+//! length in bytes as 8 bytes each.
+//!
+//! The status bytes of the flash commands are ack codes, see [`Ack`]. From [`ACK_VERSION`]
+//! on, BL2 also answers what it can't take with [`ACK_NAK`] and an ack code in place of the
+//! echo: a command byte it doesn't implement with [`ACK_UNSUPPORTED`], the length of a FIP
+//! larger than it has room for with [`FLASH_TOO_LARGE`], and CMD_GO with [`ACK_BAD_MAGIC`]
+//! if what it received isn't a FIP. The flash commands answer [`ACK_CRC_ERROR`] when what BL2
+//! reads back from storage differs from what it received.
+//!
+//! The commands of each version of the protocol are in [`V1`], [`V2`], [`V3`], [`V4`], [`V5`]
+//! and [`V6`].
+
+use core::time::Duration;
+use crate::def::{CommandDef, Field, Variant};
+use super::{be16, be32};
+
+pub const HANDSHAKE_REQ: &[u8; 4] = b"mudl";
+pub const HANDSHAKE_RESP: &[u8; 4] = b"TF-A";
+
+pub const CMD_VERSION: u8 = 1;
+pub const CMD_SET_BAUDRATE: u8 = 2;
+pub const CMD_SEND_FIP: u8 = 3;
+pub const CMD_GO: u8 = 4;
+pub const CMD_FLASH: u8 = 5;
+pub const CMD_FLASH_CRC: u8 = 6;
+pub const CMD_FLASH_ALTERNATE: u8 = 7;
+pub const CMD_FLASH_SWITCH: u8 = 8;
+pub const CMD_FLASH_INFO: u8 = 9;
+
+/// First protocol version with CMD_FLASH
+pub const FLASH_VERSION: u8 = 2;
+/// First protocol version with CMD_FLASH_CRC and FLASH_TARGET_PARTITION_AT
+pub const FLASH_RANGE_VERSION: u8 = 3;
+/// First protocol version with CMD_FLASH_ALTERNATE and CMD_FLASH_SWITCH
+pub const FLASH_SWITCH_VERSION: u8 = 4;
+/// First protocol version with CMD_FLASH_INFO
+pub const FLASH_INFO_VERSION: u8 = 5;
+/// First protocol version answering what BL2 can't take with ACK_NAK
+pub const ACK_VERSION: u8 = 6;
+
+pub const FLASH_TARGET_OFFSET: u8 = 0;
+pub const FLASH_TARGET_PARTITION: u8 = 1;
+pub const FLASH_TARGET_PARTITION_AT: u8 = 2;
+
+/// Ack codes, the status bytes of the flash commands and what follows ACK_NAK
+pub const FLASH_OK: u8 = 0;
+pub const FLASH_NO_PARTITION: u8 = 1;
+pub const FLASH_TOO_LARGE: u8 = 2;
+pub const FLASH_WRITE_FAILED: u8 = 3;
+pub const FLASH_READ_FAILED: u8 = 4;
+pub const FLASH_NO_ALTERNATE: u8 = 5;
+pub const ACK_BAD_MAGIC: u8 = 6;
+pub const ACK_CRC_ERROR: u8 = 7;
+pub const ACK_UNSUPPORTED: u8 = 8;
+
+/// Sent by BL2 in place of an echo, followed by an ack code
+pub const ACK_NAK: u8 = 0x15;
+
+/// Kinds of storage of CMD_FLASH_INFO
+pub const STORAGE_EMMC: u8 = 1;
+pub const STORAGE_SD: u8 = 2;
+pub const STORAGE_SPI_NAND: u8 = 3;
+pub const STORAGE_SPI_NOR: u8 = 4;
+
+/// A status byte of CMD_FLASH
+pub const FLASH_STATUS: Field = Field::Status { len: 1, ok_max: FLASH_OK as u16 };
+
+/// Answered with the version of the protocol
+pub const VERSION: CommandDef = CommandDef {
+    read_only: true,
+    response: &[Field::Value(1)],
+    ..CommandDef::new("version", CMD_VERSION, &[])
+};
+/// The baud rate, BL2 switches to it right after echoing it
+pub const SET_BAUDRATE: CommandDef = CommandDef::new("set_baudrate", CMD_SET_BAUDRATE, &[4]);
+/// The length of the FIP, then its packets follow
+pub const SEND_FIP: CommandDef = CommandDef::new("send_fip", CMD_SEND_FIP, &[4]);
+pub const GO: CommandDef = CommandDef::new("go", CMD_GO, &[]);
+/// The target and the length of the image, see the module documentation, then the packets
+/// of the image follow and BL2 answers with the CRC-32 it read back
+pub const FLASH: CommandDef = CommandDef {
+    after_data: &[FLASH_STATUS, Field::Value(4)],
+    ..CommandDef::new("flash", CMD_FLASH, &[])
+};
+
+/// The target, the length of the range and the length of the blocks, see the module
+/// documentation, answered with a status byte and the CRC-32 of each block
+pub const FLASH_CRC: CommandDef = CommandDef {
+    read_only: true,
+    after_data: &[FLASH_STATUS],
+    ..CommandDef::new("flash_crc", CMD_FLASH_CRC, &[])
+};
+
+/// The name of a partition, answered with a status byte and the name of its alternate
+pub const FLASH_ALTERNATE: CommandDef = CommandDef {
+    read_only: true,
+    after_data: &[FLASH_STATUS],
+    ..CommandDef::new("flash_alternate", CMD_FLASH_ALTERNATE, &[])
+};
+/// The name of the partition to boot from, answered with a status byte
+pub const FLASH_SWITCH: CommandDef = CommandDef {
+    after_data: &[FLASH_STATUS],
+    ..CommandDef::new("flash_switch", CMD_FLASH_SWITCH, &[])
+};
+/// Answered with a status byte and the storage and its partitions
+pub const FLASH_INFO: CommandDef = CommandDef {
+    read_only: true,
+    after_data: &[FLASH_STATUS],
+    ..CommandDef::new("flash_info", CMD_FLASH_INFO, &[])
+};
+
+/// BL2 builds which boot a FIP
+pub const V1: Variant = Variant { name: "bl2", version: 1, commands: &[VERSION, SET_BAUDRATE, SEND_FIP, GO] };
+/// BL2 builds which also write images to storage
+pub const V2: Variant = Variant { name: "bl2", version: FLASH_VERSION, commands: &[VERSION, SET_BAUDRATE, SEND_FIP, GO, FLASH] };
+
+/// BL2 builds which also tell what's on the storage, to write only what changed
+pub const V3: Variant = Variant { name: "bl2", version: FLASH_RANGE_VERSION, commands: &[VERSION, SET_BAUDRATE, SEND_FIP, GO, FLASH, FLASH_CRC] };
+
+/// BL2 builds which also switch between two partitions the device boots from
+pub const V4: Variant = Variant {
+    name: "bl2",
+    version: FLASH_SWITCH_VERSION,
+    commands: &[VERSION, SET_BAUDRATE, SEND_FIP, GO, FLASH, FLASH_CRC, FLASH_ALTERNATE, FLASH_SWITCH],
+};
+
+/// BL2 builds which also tell the storage and its partitions
+pub const V5: Variant = Variant {
+    name: "bl2",
+    version: FLASH_INFO_VERSION,
+    commands: &[VERSION, SET_BAUDRATE, SEND_FIP, GO, FLASH, FLASH_CRC, FLASH_ALTERNATE, FLASH_SWITCH, FLASH_INFO],
+};
+
+/// BL2 builds which also tell why they refuse what they can't take
+pub const V6: Variant = Variant { version: ACK_VERSION, ..V5 };
+
+/// The latest version of the protocol, which has every command
+pub const LATEST: &Variant = &V6;
+
+/// The commands of BL2 answering version to CMD_VERSION, as far as they're known
+pub fn variant(version: u8) -> &'static Variant {
+    [&V1, &V2, &V3, &V4, &V5, &V6].into_iter().rev().find(|v| v.version <= version).unwrap_or(&V1)
+}
+
+/// An ack code of BL2, from a status byte of the flash commands or after ACK_NAK
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ack {
+    Ok,
+    NoPartition,
+    TooLarge,
+    WriteFailed,
+    ReadFailed,
+    NoAlternate,
+    BadMagic,
+    CrcError,
+    Unsupported,
+    Unknown(u8),
+}
+
+impl Ack {
+    pub fn from_code(code: u8) -> Ack {
+        match code {
+            FLASH_OK => Ack::Ok,
+            FLASH_NO_PARTITION => Ack::NoPartition,
+            FLASH_TOO_LARGE => Ack::TooLarge,
+            FLASH_WRITE_FAILED => Ack::WriteFailed,
+            FLASH_READ_FAILED => Ack::ReadFailed,
+            FLASH_NO_ALTERNATE => Ack::NoAlternate,
+            ACK_BAD_MAGIC => Ack::BadMagic,
+            ACK_CRC_ERROR => Ack::CrcError,
+            ACK_UNSUPPORTED => Ack::Unsupported,
+            code => Ack::Unknown(code),
+        }
+    }
+
+    pub fn code(self) -> u8 {
+        match self {
+            Ack::Ok => FLASH_OK,
+            Ack::NoPartition => FLASH_NO_PARTITION,
+            Ack::TooLarge => FLASH_TOO_LARGE,
+            Ack::WriteFailed => FLASH_WRITE_FAILED,
+            Ack::ReadFailed => FLASH_READ_FAILED,
+            Ack::NoAlternate => FLASH_NO_ALTERNATE,
+            Ack::BadMagic => ACK_BAD_MAGIC,
+            Ack::CrcError => ACK_CRC_ERROR,
+            Ack::Unsupported => ACK_UNSUPPORTED,
+            Ack::Unknown(code) => code,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Ack::Ok => "ok",
+            Ack::NoPartition => "no such partition",
+            Ack::TooLarge => "image larger than the target",
+            Ack::WriteFailed => "write failed",
+            Ack::ReadFailed => "read back failed",
+            Ack::NoAlternate => "no alternate partition to switch to",
+            Ack::BadMagic => "bad magic",
+            Ack::CrcError => "CRC error",
+            Ack::Unsupported => "unsupported command",
+            Ack::Unknown(_) => "unknown error",
+        }
+    }
+
+    /// What usually leads to it
+    pub fn explanation(self) -> &'static str {
+        match self {
+            Ack::Ok => "BL2 took it.",
+            Ack::NoPartition => "The partition table BL2 knows has no partition of that name.",
+            Ack::TooLarge => "The image is larger than the partition it's written to, or than the memory BL2 loads a FIP to.",
+            Ack::WriteFailed => "The storage refused the write, it may be write protected or worn out.",
+            Ack::ReadFailed => "The storage couldn't be read back after the write.",
+            Ack::NoAlternate => "The layout of the storage has no second copy of the partition to switch to.",
+            Ack::BadMagic => "What BL2 received doesn't start like a FIP, it may be another kind of image.",
+            Ack::CrcError => "What BL2 read back from the storage differs from what it received, the storage may have bad blocks.",
+            Ack::Unsupported => "BL2 doesn't implement the command, it speaks an older version of the protocol or is built without it.",
+            Ack::Unknown(_) => "BL2 answered with a code this version of the tool doesn't know.",
+        }
+    }
+}
+
+impl core::fmt::Display for Ack {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Ack::Unknown(code) => write!(f, "unknown error {:#04x}", code),
+            ack => f.write_str(ack.as_str()),
+        }
+    }
+}
+
+/// What a status byte of CMD_FLASH means
+pub fn flash_status_str(status: u8) -> &'static str {
+    Ack::from_code(status).as_str()
+}
+
+/// The name of a kind of storage of CMD_FLASH_INFO
+pub fn storage_kind_str(kind: u8) -> &'static str {
+    match kind {
+        STORAGE_EMMC => "eMMC",
+        STORAGE_SD => "SD card",
+        STORAGE_SPI_NAND => "SPI-NAND",
+        STORAGE_SPI_NOR => "SPI-NOR",
+        _ => "unknown storage",
+    }
+}
+
+/// CRC-32 as in zlib and Ethernet, which BL2 computes over the image read back from storage
+pub fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Whether a command only reads from the device, leaving its state alone
+pub fn is_read_only(cmd: u8) -> bool {
+    LATEST.command(cmd).is_some_and(|c| c.read_only)
+}
+
+/// Progress through the BL2 handshake. Unlike the BootROM one, a wrong response byte
+/// just means the current one is sent again.
+pub struct Handshake {
+    pub(crate) pos: usize,
+}
+
+impl Handshake {
+    pub const fn new() -> Handshake {
+        Handshake { pos: 0 }
+    }
+
+    /// The byte to send next
+    pub fn tx(&self) -> u8 {
+        HANDSHAKE_REQ[self.pos]
+    }
+
+    /// Feeds the byte received after tx() was sent. Returns true once the handshake is done.
+    pub fn rx(&mut self, rx_char: u8) -> bool {
+        if HANDSHAKE_RESP[self.pos] == rx_char {
+            self.pos += 1;
+        }
+        self.is_done()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.pos == HANDSHAKE_REQ.len()
+    }
+}
+
+impl Default for Handshake {
+    fn default() -> Handshake {
+        Handshake::new()
+    }
+}
+
+pub fn packet_checksum(fip: &[u8]) -> u16 {
+    let mut p = 0;
+    let mut csum: u32 = 0;
+    while fip.len() - p > 1 {
+        csum += be16(&fip[p..p + 2]) as u32;
+        p += 2;
+    }
+
+    if fip.len() != p {
+        csum += (fip[p] as u32) << 8;
+    }
+
+    while csum >> 16 != 0 {
+        csum = ((csum >> 16) & 0xffff) + (csum & 0xffff);
+    }
+
+    csum as u16
+}
+
+/// Header fields of a FIP packet in the order they are sent: index, length and checksum.
+pub struct PacketHeader {
+    pub idx: [u8; 4],
+    pub len: [u8; 2],
+    pub checksum: [u8; 2],
+}
+
+pub fn packet_header(idx: u32, fip: &[u8]) -> PacketHeader {
+    PacketHeader {
+        idx: u32::to_be_bytes(idx),
+        len: u16::to_be_bytes(fip.len() as u16),
+        checksum: u16::to_be_bytes(packet_checksum(fip)),
+    }
+}
+
+pub const PACKET_ACK_LEN: usize = 6;
+
+/// Outcome of a FIP packet according to its acknowledgement.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PacketAck {
+    Ok,
+    WrongIndex { expected: u32 },
+    WrongChecksum { received: u16 },
+}
+
+pub fn parse_packet_ack(idx: u32, checksum: u16, buf: &[u8; PACKET_ACK_LEN]) -> PacketAck {
+    let expected_idx = be32(&buf[0..4]);
+    let real_csum = be16(&buf[4..6]);
+    if expected_idx != idx {
+        PacketAck::WrongIndex { expected: expected_idx }
+    } else if real_csum != checksum {
+        PacketAck::WrongChecksum { received: real_csum }
+    } else {
+        PacketAck::Ok
+    }
+}
+
+pub const FIRST_PACKET_LEN: usize = 128;
+
+/// Packets start small and grow while they go through, up to just below 64KiB.
+pub fn next_packet_len(pkt_len: usize) -> usize {
+    if pkt_len < 32768 {
+        pkt_len * 2
+    } else if pkt_len < 65536 - 1024 {
+        pkt_len + 1024
+    } else {
+        pkt_len
+    }
+}
+
+/// Acknowledged packets in a row after which bigger packets are tried again
+const PROBE_AFTER: u32 = 32;
+/// Largest packet the length field allows
+const MAX_PACKET_LEN: usize = u16::MAX as usize;
+
+/// Packet sizes adapting to the link. Packets start small and grow while they go through
+/// without getting slower. A rejected packet halves the size and caps it there, as does a
+/// size which turned out slower than the one before, e.g. because an adapter falls behind
+/// with bigger bursts. After a run of good packets the cap is lifted again, a single garbled
+/// byte shouldn't slow down the rest of the transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AdaptiveSizing {
+    len: usize,
+    /// Largest size to use
+    ceiling: usize,
+    /// Size and bytes per second before the last growth, until a packet of the new size
+    /// tells whether it paid off
+    grown_from: Option<(usize, u64)>,
+    /// Packets acknowledged since the last rejection or lowered ceiling
+    streak: u32,
+}
+
+impl Default for AdaptiveSizing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AdaptiveSizing {
+    pub const fn new() -> Self {
+        AdaptiveSizing { len: FIRST_PACKET_LEN, ceiling: MAX_PACKET_LEN, grown_from: None, streak: 0 }
+    }
+
+    /// Size of the next packet
+    pub fn packet_len(&self) -> usize {
+        self.len
+    }
+
+    /// A packet of the current size went through, elapsed from sending its header to the
+    /// acknowledgement.
+    pub fn acked(&mut self, elapsed: Duration) {
+        let rate = self.len as u64 * 1_000_000 / (elapsed.as_micros() as u64).max(1);
+        self.streak += 1;
+        if let Some((prev_len, prev_rate)) = self.grown_from.take() {
+            if rate < prev_rate - prev_rate / 8 {
+                self.ceiling = prev_len;
+                self.len = prev_len;
+                self.streak = 0;
+                return;
+            }
+        }
+        if self.streak >= PROBE_AFTER {
+            self.ceiling = MAX_PACKET_LEN;
+        }
+        let next = next_packet_len(self.len).min(self.ceiling);
+        if next > self.len {
+            self.grown_from = Some((self.len, rate));
+            self.len = next;
+        }
+    }
+
+    /// BL2 rejected a packet of the current size.
+    pub fn rejected(&mut self) {
+        self.ceiling = (self.len / 2).max(FIRST_PACKET_LEN);
+        self.len = self.ceiling;
+        self.grown_from = None;
+        self.streak = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handshake_repeats_a_byte_until_answered() {
+        let mut handshake = Handshake::new();
+        assert_eq!(handshake.tx(), b'm');
+        assert!(!handshake.rx(b'x'));
+        assert_eq!(handshake.tx(), b'm');
+        for &answer in &HANDSHAKE_RESP[..3] {
+            assert!(!handshake.rx(answer));
+        }
+        assert!(handshake.rx(b'A'));
+    }
+
+    #[test]
+    fn packets_are_framed_with_their_checksum() {
+        assert_eq!(packet_checksum(&[0x12, 0x34, 0x56]), 0x6834);
+        // The carries wrap around like in the Internet checksum.
+        assert_eq!(packet_checksum(&[0xff, 0xff, 0x00, 0x02]), 0x0002);
+        let header = packet_header(3, &[0x12, 0x34, 0x56]);
+        assert_eq!((header.idx, header.len, header.checksum), ([0, 0, 0, 3], [0, 3], [0x68, 0x34]));
+    }
+
+    #[test]
+    fn acks_tell_what_went_wrong() {
+        assert_eq!(parse_packet_ack(3, 0x6834, &[0, 0, 0, 3, 0x68, 0x34]), PacketAck::Ok);
+        assert_eq!(parse_packet_ack(3, 0x6834, &[0, 0, 0, 2, 0x68, 0x34]), PacketAck::WrongIndex { expected: 2 });
+        assert_eq!(parse_packet_ack(3, 0x6834, &[0, 0, 0, 3, 0x68, 0x35]), PacketAck::WrongChecksum { received: 0x6835 });
+        for code in 0..=ACK_UNSUPPORTED + 1 {
+            assert_eq!(Ack::from_code(code).code(), code);
+        }
+        assert_eq!(flash_status_str(FLASH_NO_PARTITION), "no such partition");
+    }
+
+    #[test]
+    fn crc32_is_the_one_of_zlib() {
+        assert_eq!(crc32(0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(crc32(0, b"12345"), b"6789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn versions_have_their_commands() {
+        assert_eq!(variant(1).command(CMD_FLASH), None);
+        assert!(variant(2).command(CMD_FLASH).is_some());
+        assert_eq!(variant(3).command(CMD_FLASH_ALTERNATE), None);
+        assert!(variant(9).command(CMD_FLASH_INFO).is_some());
+        assert!(is_read_only(CMD_VERSION) && is_read_only(CMD_FLASH_CRC) && is_read_only(CMD_FLASH_INFO));
+        assert!(!is_read_only(CMD_SEND_FIP) && !is_read_only(CMD_GO) && !is_read_only(CMD_FLASH) && !is_read_only(CMD_FLASH_SWITCH));
+    }
+
+    #[test]
+    fn packets_shrink_when_rejected_and_grow_again() {
+        let mut sizing = AdaptiveSizing::new();
+        for _ in 0..4 {
+            sizing.acked(Duration::from_millis(1));
+        }
+        assert_eq!(sizing.packet_len(), FIRST_PACKET_LEN * 16);
+        sizing.rejected();
+        assert_eq!(sizing.packet_len(), FIRST_PACKET_LEN * 8);
+        for _ in 0..PROBE_AFTER {
+            sizing.acked(Duration::from_millis(1));
+        }
+        assert!(sizing.packet_len() > FIRST_PACKET_LEN * 8);
+    }
+}
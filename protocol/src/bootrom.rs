@@ -0,0 +1,281 @@
+//! Frames of the Mediatek BootROM download protocol.
+//!
+//! Every command byte and argument sent to the BootROM is echoed back, and most commands end
+//! with a 16-bit big-endian status word. The layouts of the commands are in [`V1`].
+
+use crate::def::{decode, frame_len, CommandDef, Field, Variant};
+
+pub const HANDSHAKE: [u8; 4] = [0xa0, 0x0a, 0x50, 0x05];
+
+pub const CMD_READ16: u8 = 0xd0;
+pub const CMD_READ32: u8 = 0xd1;
+pub const CMD_WRITE32: u8 = 0xd4;
+pub const CMD_JUMP_DA: u8 = 0xd5;
+pub const CMD_SEND_DA: u8 = 0xd7;
+pub const CMD_GET_TARGET_CONFIG: u8 = 0xd8;
+pub const CMD_SET_BAUDRATE: u8 = 0xdc;
+pub const CMD_JUMP_DA64: u8 = 0xde;
+pub const CMD_SEND_CERT: u8 = 0xe0;
+pub const CMD_SEND_AUTH: u8 = 0xe2;
+pub const CMD_SLA: u8 = 0xe3;
+pub const CMD_GET_SOC_ID: u8 = 0xe7;
+pub const CMD_GET_HW_DICT: u8 = 0xfc;
+pub const CMD_GET_HW_CODE: u8 = 0xfd;
+
+pub const STATUS_OK: u16 = 0;
+/// Highest status accepting the address and length of a read, or the length of an auth file
+/// or certificate. Higher ones are errors
+pub const STATUS_LEN_ACCEPTED_MAX: u16 = 0xff;
+pub const STATUS_BAUDRATE_TOO_HIGH: u16 = 0x1d1d;
+
+/// The status most commands end with
+pub const STATUS: Field = Field::Status { len: 2, ok_max: STATUS_OK };
+/// The status accepting the address and length of a read or write, or the length of data
+pub const LEN_STATUS: Field = Field::Status { len: 2, ok_max: STATUS_LEN_ACCEPTED_MAX };
+
+/// Address and word count, then the words follow
+pub const READ16: CommandDef = CommandDef {
+    read_only: true,
+    response: &[LEN_STATUS],
+    after_data: &[LEN_STATUS],
+    ..CommandDef::new("read16", CMD_READ16, &[4, 4])
+};
+pub const READ32: CommandDef = CommandDef { name: "read32", opcode: CMD_READ32, ..READ16 };
+/// Address and word count, then the words are echoed
+pub const WRITE32: CommandDef = CommandDef {
+    response: &[LEN_STATUS],
+    after_data: &[LEN_STATUS],
+    ..CommandDef::new("write32", CMD_WRITE32, &[4, 4])
+};
+pub const JUMP_DA: CommandDef = CommandDef { response: &[STATUS], ..CommandDef::new("jump_da", CMD_JUMP_DA, &[4]) };
+/// See [`send_da_args`], then the payload follows and the BootROM answers with its checksum
+pub const SEND_DA: CommandDef = CommandDef {
+    response: &[STATUS],
+    after_data: &[Field::Value(2), STATUS],
+    ..CommandDef::new("send_da", CMD_SEND_DA, &[4, 4, 4])
+};
+pub const GET_TARGET_CONFIG: CommandDef = CommandDef {
+    read_only: true,
+    response: &[Field::Value(4), STATUS],
+    ..CommandDef::new("get_target_config", CMD_GET_TARGET_CONFIG, &[])
+};
+pub const SET_BAUDRATE: CommandDef = CommandDef { response: &[STATUS], ..CommandDef::new("set_baudrate", CMD_SET_BAUDRATE, &[4]) };
+/// Address and [`JUMP_DA64_AARCH64`], then [`JUMP_DA64_MAGIC`] is echoed and answered
+/// with another status
+pub const JUMP_DA64: CommandDef = CommandDef { response: &[STATUS], ..CommandDef::new("jump_da64", CMD_JUMP_DA64, &[4, 1]) };
+/// Length, then the data follows and the BootROM answers with its checksum
+pub const SEND_CERT: CommandDef = CommandDef {
+    response: &[LEN_STATUS],
+    after_data: &[Field::Value(2), STATUS],
+    ..CommandDef::new("send_cert", CMD_SEND_CERT, &[4])
+};
+pub const SEND_AUTH: CommandDef = CommandDef { name: "send_auth", opcode: CMD_SEND_AUTH, ..SEND_CERT };
+/// Answered with the length of the challenge, which follows. The length of the signature is
+/// echoed, and after the signature the BootROM answers with a status
+pub const SLA: CommandDef = CommandDef {
+    response: &[STATUS, Field::Value(4)],
+    after_data: &[STATUS],
+    ..CommandDef::new("sla", CMD_SLA, &[])
+};
+/// Longest SLA challenge taken, they're 16 or 32 bytes
+pub const MAX_SLA_CHALLENGE_LEN: usize = 256;
+/// Answered with the length of the SoC ID, which follows, then a status. The ID is unique
+/// per chip, unlike the hw code. BootROMs of older SoCs don't have it
+pub const GET_SOC_ID: CommandDef = CommandDef {
+    read_only: true,
+    response: &[Field::Value(4)],
+    after_data: &[STATUS],
+    ..CommandDef::new("get_soc_id", CMD_GET_SOC_ID, &[])
+};
+/// Longest SoC ID taken, they're 32 bytes
+pub const MAX_SOC_ID_LEN: usize = 64;
+/// Answered with the hw sub code, hw version and sw version
+pub const GET_HW_DICT: CommandDef = CommandDef {
+    read_only: true,
+    response: &[Field::Value(2), Field::Value(2), Field::Value(2), STATUS],
+    ..CommandDef::new("get_hw_dict", CMD_GET_HW_DICT, &[])
+};
+pub const GET_HW_CODE: CommandDef = CommandDef {
+    read_only: true,
+    response: &[Field::Value(2), STATUS],
+    ..CommandDef::new("get_hw_code", CMD_GET_HW_CODE, &[])
+};
+
+/// The BootROM protocol of the SoCs supported so far
+pub const V1: Variant = Variant {
+    name: "bootrom",
+    version: 1,
+    commands: &[
+        READ16, READ32, WRITE32, JUMP_DA, SEND_DA, GET_TARGET_CONFIG, SET_BAUDRATE, JUMP_DA64, SEND_CERT, SEND_AUTH,
+        SLA, GET_SOC_ID, GET_HW_DICT, GET_HW_CODE,
+    ],
+};
+
+/// Whether a command only reads from the device, leaving its state alone
+pub fn is_read_only(cmd: u8) -> bool {
+    V1.command(cmd).is_some_and(|c| c.read_only)
+}
+
+/// Argument of CMD_JUMP_DA64 selecting aarch64
+pub const JUMP_DA64_AARCH64: u8 = 1;
+/// A magic number checked before resetting CPU to aarch64
+pub const JUMP_DA64_MAGIC: u8 = 100;
+
+/// Progress through the BootROM handshake.
+///
+/// Each byte of the sequence is answered with its bitwise inverse. Anything else
+/// restarts the sequence.
+pub struct Handshake {
+    pub(crate) pos: usize,
+}
+
+impl Handshake {
+    pub const fn new() -> Handshake {
+        Handshake { pos: 0 }
+    }
+
+    /// The byte to send next
+    pub fn tx(&self) -> u8 {
+        HANDSHAKE[self.pos]
+    }
+
+    /// Feeds the byte received after tx() was sent. Returns true once the handshake is done.
+    pub fn rx(&mut self, rx_char: u8) -> bool {
+        if HANDSHAKE[self.pos] == !rx_char {
+            self.pos += 1;
+        } else {
+            self.pos = 0;
+        }
+        self.is_done()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.pos == HANDSHAKE.len()
+    }
+}
+
+impl Default for Handshake {
+    fn default() -> Handshake {
+        Handshake::new()
+    }
+}
+
+pub const HW_CODE_RESP_LEN: usize = frame_len(GET_HW_CODE.response);
+
+/// Decodes the CMD_GET_HW_CODE response into the hw code and the status.
+pub fn parse_hw_code(buf: &[u8; HW_CODE_RESP_LEN]) -> (u16, u16) {
+    let v = decode(GET_HW_CODE.response, buf);
+    (v.get(0) as u16, v.get(1) as u16)
+}
+
+pub const HW_DICT_RESP_LEN: usize = frame_len(GET_HW_DICT.response);
+
+/// Decodes the CMD_GET_HW_DICT response into (hw sub code, hw ver, sw ver) and the status.
+pub fn parse_hw_dict(buf: &[u8; HW_DICT_RESP_LEN]) -> ((u16, u16, u16), u16) {
+    let v = decode(GET_HW_DICT.response, buf);
+    ((v.get(0) as u16, v.get(1) as u16, v.get(2) as u16), v.get(3) as u16)
+}
+
+/// Security features reported by CMD_GET_TARGET_CONFIG.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TargetConfig {
+    pub secure_boot: bool,
+    pub serial_link_authorization: bool,
+    pub download_agent_authorization: bool,
+}
+
+impl TargetConfig {
+    pub fn from_word(target_config: u32) -> TargetConfig {
+        TargetConfig {
+            secure_boot: target_config & 1 != 0,
+            serial_link_authorization: target_config & 2 != 0,
+            download_agent_authorization: target_config & 4 != 0,
+        }
+    }
+}
+
+pub const TARGET_CONFIG_RESP_LEN: usize = frame_len(GET_TARGET_CONFIG.response);
+
+/// Decodes the CMD_GET_TARGET_CONFIG response into the config and the status.
+pub fn parse_target_config(buf: &[u8; TARGET_CONFIG_RESP_LEN]) -> (TargetConfig, u16) {
+    let v = decode(GET_TARGET_CONFIG.response, buf);
+    (TargetConfig::from_word(v.get(0)), v.get(1) as u16)
+}
+
+/// Arguments of CMD_SEND_DA in the order they are sent: address, length without the
+/// signature, and signature length. None if the signature is longer than the DA.
+pub fn send_da_args(da_addr: u32, da_len: u32, sig_len: u32) -> Option<[[u8; 4]; 3]> {
+    Some([
+        u32::to_be_bytes(da_addr),
+        u32::to_be_bytes(da_len.checked_sub(sig_len)?),
+        u32::to_be_bytes(sig_len),
+    ])
+}
+
+pub const SEND_DA_RESP_LEN: usize = frame_len(SEND_DA.after_data);
+
+/// The checksum the BootROM computes over sent data, e.g. the DA with its signature: the
+/// XOR of its little-endian 16-bit words, an odd last byte padded with zero.
+pub fn da_checksum(data: &[u8]) -> u16 {
+    data.chunks(2).fold(0, |sum, word| sum ^ u16::from_le_bytes([word[0], *word.get(1).unwrap_or(&0)]))
+}
+
+/// Decodes the response after the data of CMD_SEND_DA, CMD_SEND_AUTH or CMD_SEND_CERT is
+/// sent into the checksum and the status.
+pub fn parse_send_da_resp(buf: &[u8; SEND_DA_RESP_LEN]) -> (u16, u16) {
+    let v = decode(SEND_DA.after_data, buf);
+    (v.get(0) as u16, v.get(1) as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handshake_restarts_on_a_wrong_answer() {
+        let mut handshake = Handshake::new();
+        assert!(!handshake.rx(!handshake.tx()));
+        assert!(!handshake.rx(0x5f));
+        assert_eq!(handshake.tx(), HANDSHAKE[0]);
+        for _ in 0..HANDSHAKE.len() - 1 {
+            assert!(!handshake.rx(!handshake.tx()));
+        }
+        assert!(handshake.rx(!handshake.tx()));
+    }
+
+    #[test]
+    fn responses_are_decoded() {
+        assert_eq!(parse_hw_code(&[0x79, 0x86, 0, 0]), (0x7986, 0));
+        assert_eq!(parse_hw_dict(&[0x8a, 0x00, 0xca, 0x00, 0x00, 0x01, 0x00, 0x00]), ((0x8a00, 0xca00, 1), 0));
+        let (config, status) = parse_target_config(&[0, 0, 0, 0x06, 0, 0]);
+        assert_eq!(status, 0);
+        assert_eq!(config, TargetConfig { secure_boot: false, serial_link_authorization: true, download_agent_authorization: true });
+        assert_eq!(parse_send_da_resp(&[0x12, 0x34, 0x1d, 0x0c]), (0x1234, 0x1d0c));
+    }
+
+    #[test]
+    fn send_da_takes_the_length_without_the_signature() {
+        assert_eq!(send_da_args(0x201000, 0x1100, 0x100), Some([[0, 0x20, 0x10, 0], [0, 0, 0x10, 0], [0, 0, 1, 0]]));
+        assert_eq!(send_da_args(0x201000, 0x100, 0x100), Some([[0, 0x20, 0x10, 0], [0; 4], [0, 0, 1, 0]]));
+        assert_eq!(send_da_args(0x201000, 0xff, 0x100), None);
+    }
+
+    #[test]
+    fn checksum_is_the_xor_of_little_endian_words() {
+        assert_eq!(da_checksum(&[]), 0);
+        assert_eq!(da_checksum(&[0x01, 0x02, 0x10, 0x20]), 0x2211);
+        assert_eq!(da_checksum(&[0x01, 0x02, 0x10]), 0x0211);
+    }
+
+    #[test]
+    fn only_reads_are_read_only() {
+        for cmd in [CMD_READ16, CMD_READ32, CMD_GET_TARGET_CONFIG, CMD_GET_SOC_ID, CMD_GET_HW_DICT, CMD_GET_HW_CODE] {
+            assert!(is_read_only(cmd), "{:#x}", cmd);
+        }
+        for cmd in [CMD_WRITE32, CMD_JUMP_DA, CMD_SEND_DA, CMD_SET_BAUDRATE, CMD_JUMP_DA64, CMD_SEND_CERT, CMD_SEND_AUTH, CMD_SLA] {
+            assert!(!is_read_only(cmd), "{:#x}", cmd);
+        }
+        // Unknown commands may do anything.
+        assert!(!is_read_only(0x00));
+    }
+}
@@ -0,0 +1,179 @@
+//! Commands of the download protocols as data: their command bytes and the layout of their
+//! frames, per protocol variant.
+//!
+//! The host drives a command from its [`CommandDef`]: it sends the command byte and the
+//! arguments, each echoed back, and decodes the answer. Another variant of a protocol, e.g.
+//! of an older BootROM or a preloader, is another table of definitions rather than another
+//! copy of the code.
+
+/// A field of what the device answers with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    /// A big-endian value of 1, 2 or 4 bytes
+    Value(u8),
+    /// A big-endian status of 1 or 2 bytes, success up to ok_max
+    Status { len: u8, ok_max: u16 },
+}
+
+impl Field {
+    pub const fn width(self) -> usize {
+        match self {
+            Field::Value(len) | Field::Status { len, .. } => len as usize,
+        }
+    }
+
+    /// Decodes the field from the start of buf
+    pub fn value(self, buf: &[u8]) -> u32 {
+        buf[..self.width()].iter().fold(0, |v, &b| v << 8 | b as u32)
+    }
+
+    /// Whether the value of a status field is a success, the status otherwise
+    pub fn check(self, value: u32) -> Result<(), u16> {
+        match self {
+            Field::Status { ok_max, .. } if value > ok_max as u32 => Err(value as u16),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The wire layout of a command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CommandDef {
+    /// What errors and logs call it
+    pub name: &'static str,
+    pub opcode: u8,
+    /// Whether it only reads from the device, leaving its state alone
+    pub read_only: bool,
+    /// Widths in bytes of the big-endian arguments, each echoed back after it's sent
+    pub args: &'static [u8],
+    /// What the device answers after the arguments
+    pub response: &'static [Field],
+    /// For commands followed by data, what the device answers after the data
+    pub after_data: &'static [Field],
+}
+
+impl CommandDef {
+    /// A command answering nothing but the echoes of its command byte and arguments
+    pub const fn new(name: &'static str, opcode: u8, args: &'static [u8]) -> CommandDef {
+        CommandDef { name, opcode, read_only: false, args, response: &[], after_data: &[] }
+    }
+}
+
+/// A version of a protocol, the commands it has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Variant {
+    pub name: &'static str,
+    pub version: u8,
+    pub commands: &'static [CommandDef],
+}
+
+impl Variant {
+    pub fn command(&self, opcode: u8) -> Option<&'static CommandDef> {
+        self.commands.iter().find(|c| c.opcode == opcode)
+    }
+
+    pub fn command_named(&self, name: &str) -> Option<&'static CommandDef> {
+        self.commands.iter().find(|c| c.name == name)
+    }
+}
+
+/// The length of a frame of fields in bytes
+pub const fn frame_len(fields: &[Field]) -> usize {
+    let mut len = 0;
+    let mut i = 0;
+    while i < fields.len() {
+        len += fields[i].width();
+        i += 1;
+    }
+    len
+}
+
+/// Fields a frame has at most
+pub const MAX_FIELDS: usize = 8;
+
+/// Bytes a frame has at most
+pub const MAX_FRAME_LEN: usize = 4 * MAX_FIELDS;
+
+/// The values of the fields of a frame, statuses included, in order
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Values {
+    values: [u32; MAX_FIELDS],
+    len: usize,
+}
+
+impl Values {
+    /// The value of field i. Panics if the frame has fewer fields
+    pub fn get(&self, i: usize) -> u32 {
+        self.as_slice()[i]
+    }
+
+    pub fn as_slice(&self) -> &[u32] {
+        &self.values[..self.len]
+    }
+}
+
+/// Encodes a big-endian value of width bytes into buf, returning the bytes
+pub fn encode(value: u32, width: u8, buf: &mut [u8; 4]) -> &[u8] {
+    *buf = value.to_be_bytes();
+    &buf[4 - width as usize..]
+}
+
+/// Decodes a frame of fields from buf, which is at least frame_len(fields) long.
+pub fn decode(fields: &[Field], buf: &[u8]) -> Values {
+    let mut values = Values { values: [0; MAX_FIELDS], len: fields.len() };
+    let mut p = 0;
+    for (value, field) in values.values.iter_mut().zip(fields) {
+        *value = field.value(&buf[p..]);
+        p += field.width();
+    }
+    values
+}
+
+/// The first status in the values which isn't a success
+pub fn check(fields: &[Field], values: &Values) -> Result<(), u16> {
+    fields.iter().zip(values.as_slice()).try_for_each(|(field, &value)| field.check(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: &[Field] = &[Field::Value(1), Field::Value(2), Field::Value(4), Field::Status { len: 2, ok_max: 0 }];
+
+    #[test]
+    fn values_are_encoded_big_endian() {
+        let mut buf = [0; 4];
+        assert_eq!(encode(0x1234_5678, 4, &mut buf), [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(encode(0x1234_5678, 2, &mut buf), [0x56, 0x78]);
+        assert_eq!(encode(0x1234_5678, 1, &mut buf), [0x78]);
+    }
+
+    #[test]
+    fn frames_are_decoded_field_by_field() {
+        assert_eq!(frame_len(FRAME), 9);
+        let values = decode(FRAME, &[0xab, 0x79, 0x86, 0x00, 0x20, 0x10, 0x00, 0x00, 0x00]);
+        assert_eq!(values.as_slice(), [0xab, 0x7986, 0x0020_1000, 0]);
+        assert_eq!(check(FRAME, &values), Ok(()));
+    }
+
+    #[test]
+    fn statuses_fail_above_their_maximum() {
+        let values = decode(FRAME, &[0, 0, 0, 0, 0, 0, 0, 0x1d, 0x1d]);
+        assert_eq!(check(FRAME, &values), Err(0x1d1d));
+        let len_status = Field::Status { len: 2, ok_max: 0xff };
+        assert_eq!(len_status.check(0xff), Ok(()));
+        assert_eq!(len_status.check(0x100), Err(0x100));
+        // Plain values are never an error, whatever they are.
+        assert_eq!(Field::Value(2).check(0xffff), Ok(()));
+    }
+
+    #[test]
+    fn commands_are_found_by_opcode_and_name() {
+        const PING: CommandDef = CommandDef::new("ping", 0x42, &[4]);
+        let variant = Variant { name: "test", version: 1, commands: &[PING] };
+        assert_eq!(variant.command(0x42), Some(&PING));
+        assert_eq!(variant.command_named("ping"), Some(&PING));
+        assert_eq!(variant.command(0x43), None);
+        assert!(!PING.read_only && PING.response.is_empty());
+    }
+}
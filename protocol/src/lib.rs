@@ -0,0 +1,25 @@
+//! Transport independent encoding and decoding of the BootROM and BL2 download protocols.
+//!
+//! Nothing in here does any IO or allocation, so the same frame definitions can be used by
+//! the host tool and by Rust code running on the device. The host-side `bootrom` and `bl2`
+//! modules feed received bytes into these functions and write out whatever they produce.
+//! The layouts of the commands are data, tables of [`def::CommandDef`] per version of a
+//! protocol, so another one is a table rather than more code.
+//! With the `wasm` feature, the crate builds to a WebAssembly module exporting them to
+//! JavaScript, see [`wasm`].
+
+#![no_std]
+
+pub mod bl2;
+pub mod bootrom;
+pub mod def;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub fn be16(buf: &[u8]) -> u16 {
+    u16::from_be_bytes([buf[0], buf[1]])
+}
+
+pub fn be32(buf: &[u8]) -> u32 {
+    u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]])
+}
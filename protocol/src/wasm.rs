@@ -0,0 +1,201 @@
+//! Exports for using the protocols from JavaScript, e.g. a browser page talking to the device
+//! over WebSerial. The module needs no imports and no glue code:
+//!
+//! ```text
+//! cargo rustc -p mtk_uartboot_protocol --release --target wasm32-unknown-unknown --features wasm --crate-type cdylib
+//! ```
+//!
+//! JavaScript does the IO and calls these functions for everything about the frames. Received
+//! frames and data to checksum are copied into the input buffer at `mtk_input()` first, frames
+//! to send and extra results are read from the output buffer at `mtk_output()`. Both are in
+//! the memory of the module, and values in the output buffer are little-endian.
+
+use core::cell::UnsafeCell;
+use crate::{bl2, bootrom};
+
+/// Size of the input buffer, enough for the largest FIP packet
+pub const INPUT_LEN: usize = 65536;
+/// Size of the output buffer
+pub const OUTPUT_LEN: usize = 16;
+
+struct Buffer<const N: usize>(UnsafeCell<[u8; N]>);
+
+// SAFETY: WebAssembly modules run on a single thread, and the buffers are only touched by
+// JavaScript between calls.
+unsafe impl<const N: usize> Sync for Buffer<N> {}
+
+static INPUT: Buffer<INPUT_LEN> = Buffer(UnsafeCell::new([0; INPUT_LEN]));
+static OUTPUT: Buffer<OUTPUT_LEN> = Buffer(UnsafeCell::new([0; OUTPUT_LEN]));
+
+/// The first len bytes of the input buffer
+fn input_slice(len: u32) -> &'static [u8] {
+    // SAFETY: see Sync, nothing writes to the buffer during a call.
+    let input = unsafe { &*INPUT.0.get() };
+    &input[..(len as usize).min(INPUT_LEN)]
+}
+
+fn input<const N: usize>() -> &'static [u8; N] {
+    input_slice(N as u32).try_into().unwrap()
+}
+
+fn output(values: &[&[u8]]) {
+    // SAFETY: see Sync, this is the only reference to the buffer during a call.
+    let out = unsafe { &mut *OUTPUT.0.get() };
+    let mut pos = 0;
+    for value in values {
+        out[pos..pos + value.len()].copy_from_slice(value);
+        pos += value.len();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn mtk_input() -> *mut u8 {
+    INPUT.0.get().cast()
+}
+
+#[no_mangle]
+pub extern "C" fn mtk_output() -> *const u8 {
+    OUTPUT.0.get().cast()
+}
+
+#[no_mangle]
+pub extern "C" fn mtk_brom_handshake_tx(pos: u32) -> u32 {
+    bootrom::Handshake { pos: pos as usize }.tx() as u32
+}
+
+/// Feeds the byte received after the one of pos, returning the new pos. The handshake is
+/// done at 4.
+#[no_mangle]
+pub extern "C" fn mtk_brom_handshake_rx(pos: u32, byte: u32) -> u32 {
+    let mut hs = bootrom::Handshake { pos: pos as usize };
+    hs.rx(byte as u8);
+    hs.pos as u32
+}
+
+#[no_mangle]
+pub extern "C" fn mtk_brom_is_read_only(cmd: u32) -> bool {
+    bootrom::is_read_only(cmd as u8)
+}
+
+/// Decodes the CMD_GET_HW_CODE response in the input buffer into hw code << 16 | status
+#[no_mangle]
+pub extern "C" fn mtk_brom_parse_hw_code() -> u32 {
+    let (code, status) = bootrom::parse_hw_code(input());
+    (code as u32) << 16 | status as u32
+}
+
+/// Decodes the CMD_GET_HW_DICT response in the input buffer. Returns the status and outputs
+/// the hw sub code, hw version and sw version as u16s.
+#[no_mangle]
+pub extern "C" fn mtk_brom_parse_hw_dict() -> u32 {
+    let ((sub_code, hw_ver, sw_ver), status) = bootrom::parse_hw_dict(input());
+    output(&[&sub_code.to_le_bytes(), &hw_ver.to_le_bytes(), &sw_ver.to_le_bytes()]);
+    status as u32
+}
+
+/// Decodes the CMD_GET_TARGET_CONFIG response in the input buffer into status << 16 and
+/// bits 0, 1 and 2 set for secure boot, SLA and DAA.
+#[no_mangle]
+pub extern "C" fn mtk_brom_parse_target_config() -> u32 {
+    let (cfg, status) = bootrom::parse_target_config(input());
+    (status as u32) << 16
+        | cfg.secure_boot as u32
+        | (cfg.serial_link_authorization as u32) << 1
+        | (cfg.download_agent_authorization as u32) << 2
+}
+
+/// Outputs the 12 bytes of CMD_SEND_DA arguments, each of the three to be echoed. Returns
+/// false, outputting nothing, if the signature is longer than the DA.
+#[no_mangle]
+pub extern "C" fn mtk_brom_send_da_args(da_addr: u32, da_len: u32, sig_len: u32) -> bool {
+    let Some([addr, len, sig_len]) = bootrom::send_da_args(da_addr, da_len, sig_len) else {
+        return false;
+    };
+    output(&[&addr, &len, &sig_len]);
+    true
+}
+
+/// Decodes the response after the data of CMD_SEND_DA in the input buffer into
+/// checksum << 16 | status
+#[no_mangle]
+pub extern "C" fn mtk_brom_parse_send_da_resp() -> u32 {
+    let (checksum, status) = bootrom::parse_send_da_resp(input());
+    (checksum as u32) << 16 | status as u32
+}
+
+/// Continues the checksum of sent data, starting at 0, with the first len bytes of the input
+/// buffer. All chunks but the last need an even length.
+#[no_mangle]
+pub extern "C" fn mtk_da_checksum(checksum: u32, len: u32) -> u32 {
+    (checksum as u16 ^ bootrom::da_checksum(input_slice(len))) as u32
+}
+
+/// The byte to send next in the BL2 handshake, pos is 0 at the start
+#[no_mangle]
+pub extern "C" fn mtk_bl2_handshake_tx(pos: u32) -> u32 {
+    bl2::Handshake { pos: pos as usize }.tx() as u32
+}
+
+/// Feeds the byte received after the one of pos, returning the new pos. The handshake is
+/// done at 4.
+#[no_mangle]
+pub extern "C" fn mtk_bl2_handshake_rx(pos: u32, byte: u32) -> u32 {
+    let mut hs = bl2::Handshake { pos: pos as usize };
+    hs.rx(byte as u8);
+    hs.pos as u32
+}
+
+#[no_mangle]
+pub extern "C" fn mtk_bl2_is_read_only(cmd: u32) -> bool {
+    bl2::is_read_only(cmd as u8)
+}
+
+/// Outputs the 8 bytes of the header of FIP packet idx, whose data is the first len bytes of
+/// the input buffer, and returns its checksum
+#[no_mangle]
+pub extern "C" fn mtk_bl2_packet_header(idx: u32, len: u32) -> u32 {
+    let header = bl2::packet_header(idx, input_slice(len));
+    output(&[&header.idx, &header.len, &header.checksum]);
+    u16::from_be_bytes(header.checksum) as u32
+}
+
+/// Decodes the acknowledgement of packet idx in the input buffer. Returns 0 if it went
+/// through, 1 with the expected index output as a u32 if BL2 wants another packet, or 2 with
+/// the checksum BL2 computed output as a u16.
+#[no_mangle]
+pub extern "C" fn mtk_bl2_parse_packet_ack(idx: u32, checksum: u32) -> u32 {
+    match bl2::parse_packet_ack(idx, checksum as u16, input()) {
+        bl2::PacketAck::Ok => 0,
+        bl2::PacketAck::WrongIndex { expected } => {
+            output(&[&expected.to_le_bytes()]);
+            1
+        }
+        bl2::PacketAck::WrongChecksum { received } => {
+            output(&[&received.to_le_bytes()]);
+            2
+        }
+    }
+}
+
+/// Continues the CRC-32 of an image written with CMD_FLASH, starting at 0, with the first
+/// len bytes of the input buffer
+#[no_mangle]
+pub extern "C" fn mtk_bl2_crc32(crc: u32, len: u32) -> u32 {
+    bl2::crc32(crc, input_slice(len))
+}
+
+#[no_mangle]
+pub extern "C" fn mtk_bl2_first_packet_len() -> u32 {
+    bl2::FIRST_PACKET_LEN as u32
+}
+
+#[no_mangle]
+pub extern "C" fn mtk_bl2_next_packet_len(len: u32) -> u32 {
+    bl2::next_packet_len(len as usize) as u32
+}
+
+#[cfg(target_arch = "wasm32")]
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    core::arch::wasm32::unreachable()
+}
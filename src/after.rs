@@ -0,0 +1,25 @@
+use clap::ValueEnum;
+
+/// What's done with the device once it's booted, so benches don't leave boards waiting in a
+/// bootloader.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum After {
+    /// Leave it running
+    #[default]
+    Stay,
+    /// Reset it, so it boots from its own storage
+    Reboot,
+    /// Switch it off
+    Poweroff,
+}
+
+impl After {
+    /// The U-Boot command doing it
+    pub fn command(self) -> Option<&'static str> {
+        match self {
+            After::Stay => None,
+            After::Reboot => Some("reset"),
+            After::Poweroff => Some("poweroff"),
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::queue::Job;
+use crate::report::json_string;
+
+/// An append-only log of who asked for what to be done to which device, one JSON object per
+/// line. Lines are synced as they're written and never rewritten.
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> AuditLog {
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .unwrap_or_else(|e| panic!("failed to open audit log {}: {}", path.display(), e));
+        AuditLog { file: Mutex::new(file) }
+    }
+
+    /// Records something that happened to a job, by whom, with more details as name/value
+    /// pairs, e.g. the identity of the device and the hashes of the images sent.
+    pub fn record(&self, event: &str, job: &Job, by: &str, details: &[(&str, String)]) {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let mut line = format!("{{\"time\":{},\"event\":{},\"job\":{},\"port\":{},\"by\":{}",
+                               time, json_string(event), job.id, json_string(&job.port), json_string(by));
+        for (name, value) in details {
+            line.push_str(&format!(",{}:{}", json_string(name), json_string(value)));
+        }
+        line.push_str("}\n");
+        let mut file = self.file.lock().unwrap();
+        // A job which can't be audited mustn't go unnoticed.
+        file.write_all(line.as_bytes()).and_then(|()| file.sync_data())
+            .expect("failed to write the audit log.");
+    }
+}
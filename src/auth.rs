@@ -0,0 +1,171 @@
+//! Providers of the secrets needed by secured devices.
+//!
+//! Devices with serial link authorization (SLA) send a challenge which has to be signed
+//! with the vendor key, and devices with download agent authorization (DAA) need a
+//! certificate for the payload. Where these come from differs a lot between setups, so
+//! it's behind the [`AuthProvider`] trait: implement it to use an HSM, a cloud KMS or a
+//! vendor server.
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+#[cfg(feature = "auth-server")]
+use crate::secret::Secret;
+
+pub trait AuthProvider: Send {
+    /// Signs an SLA challenge from the device with this hw code
+    fn sign_challenge(&mut self, hw_code: u16, challenge: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Returns the DAA certificate for the device with this hw code
+    fn certificate(&mut self, hw_code: u16) -> io::Result<Vec<u8>>;
+}
+
+/// Reads the certificate from a file.
+///
+/// Signing needs a private key this crate doesn't handle, so challenges are refused. Use
+/// [`CommandAuth`] with an external signer for SLA.
+#[derive(Debug)]
+pub struct FileAuth {
+    cert: PathBuf,
+}
+
+impl FileAuth {
+    pub fn new<P: Into<PathBuf>>(cert: P) -> FileAuth {
+        FileAuth { cert: cert.into() }
+    }
+}
+
+impl AuthProvider for FileAuth {
+    fn sign_challenge(&mut self, _hw_code: u16, _challenge: &[u8]) -> io::Result<Vec<u8>> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "file auth provider can't sign challenges"))
+    }
+
+    fn certificate(&mut self, _hw_code: u16) -> io::Result<Vec<u8>> {
+        std::fs::read(&self.cert)
+    }
+}
+
+/// A shell command line, run with `sh -c`, or `cmd /C` on Windows
+fn shell(cmd: &str) -> Command {
+    let mut command = Command::new(if cfg!(windows) { "cmd" } else { "sh" });
+    command.arg(if cfg!(windows) { "/C" } else { "-c" }).arg(cmd);
+    command
+}
+
+/// Runs shell commands which get the input on stdin and print the result on stdout.
+///
+/// The hw code is passed in `MTK_UARTBOOT_HW_CODE`, e.g. to pick the right key:
+///
+/// ```no_run
+/// use mtk_uartboot::auth::CommandAuth;
+///
+/// let auth = CommandAuth::new(
+///     "openssl pkeyutl -sign -inkey sla_$MTK_UARTBOOT_HW_CODE.pem",
+///     "cat daa_$MTK_UARTBOOT_HW_CODE.cert",
+/// );
+/// ```
+#[derive(Debug)]
+pub struct CommandAuth {
+    sign: String,
+    cert: String,
+}
+
+impl CommandAuth {
+    pub fn new<S: Into<String>, C: Into<String>>(sign: S, cert: C) -> CommandAuth {
+        CommandAuth { sign: sign.into(), cert: cert.into() }
+    }
+
+    fn run(cmd: &str, hw_code: u16, input: &[u8]) -> io::Result<Vec<u8>> {
+        let mut child = shell(cmd)
+            .env("MTK_UARTBOOT_HW_CODE", format!("{:#x}", hw_code))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        // Dropping stdin closes it, so the command sees the end of its input.
+        child.stdin.take().unwrap().write_all(input)?;
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!("auth command failed: {}", output.status)));
+        }
+        Ok(output.stdout)
+    }
+}
+
+impl AuthProvider for CommandAuth {
+    fn sign_challenge(&mut self, hw_code: u16, challenge: &[u8]) -> io::Result<Vec<u8>> {
+        CommandAuth::run(&self.sign, hw_code, challenge)
+    }
+
+    fn certificate(&mut self, hw_code: u16) -> io::Result<Vec<u8>> {
+        CommandAuth::run(&self.cert, hw_code, &[])
+    }
+}
+
+/// Gets signatures and certificates from an online authentication server.
+///
+/// The challenge is POSTed to `<endpoint>/sla/sign` and the certificate fetched from
+/// `<endpoint>/daa/cert`, both with the hw code in the `X-Hw-Code` header and the token as
+/// bearer authorization. Response bodies are the raw signature and certificate.
+#[cfg(feature = "auth-server")]
+#[derive(Debug)]
+pub struct ServerAuth {
+    endpoint: String,
+    token: Option<Secret<String>>,
+}
+
+#[cfg(feature = "auth-server")]
+impl ServerAuth {
+    pub fn new<E: Into<String>>(endpoint: E, token: Option<String>) -> ServerAuth {
+        ServerAuth {
+            endpoint: endpoint.into().trim_end_matches('/').to_owned(),
+            token: token.map(Secret::new),
+        }
+    }
+
+    /// Takes the endpoint from `MTK_UARTBOOT_AUTH_URL` and the token from
+    /// `MTK_UARTBOOT_AUTH_TOKEN`, so credentials don't end up on command lines.
+    pub fn from_env() -> Option<ServerAuth> {
+        let endpoint = std::env::var("MTK_UARTBOOT_AUTH_URL").ok()?;
+        Some(ServerAuth::new(endpoint, std::env::var("MTK_UARTBOOT_AUTH_TOKEN").ok()))
+    }
+
+    fn request(&self, method: &str, path: &str, hw_code: u16) -> ureq::Request {
+        let req = ureq::request(method, &format!("{}{}", self.endpoint, path))
+            .set("X-Hw-Code", &format!("{:#x}", hw_code));
+        match &self.token {
+            Some(token) => req.set("Authorization", &format!("Bearer {}", token.expose())),
+            None => req,
+        }
+    }
+
+    fn read_body(res: Result<ureq::Response, ureq::Error>) -> io::Result<Vec<u8>> {
+        let res = res.map_err(|e| io::Error::other(format!("auth server: {}", e)))?;
+        let mut body = Vec::new();
+        std::io::Read::read_to_end(&mut res.into_reader(), &mut body)?;
+        Ok(body)
+    }
+}
+
+#[cfg(feature = "auth-server")]
+impl AuthProvider for ServerAuth {
+    fn sign_challenge(&mut self, hw_code: u16, challenge: &[u8]) -> io::Result<Vec<u8>> {
+        ServerAuth::read_body(self.request("POST", "/sla/sign", hw_code).send_bytes(challenge))
+    }
+
+    fn certificate(&mut self, hw_code: u16) -> io::Result<Vec<u8>> {
+        ServerAuth::read_body(self.request("GET", "/daa/cert", hw_code).call())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_get_the_challenge_and_the_hw_code() {
+        let mut auth = CommandAuth::new("printf %s- \"$MTK_UARTBOOT_HW_CODE\"; cat", "echo cert; exit 3");
+        assert_eq!(auth.sign_challenge(0x7986, b"challenge").unwrap(), b"0x7986-challenge");
+        let err = auth.certificate(0x7986).unwrap_err();
+        assert!(err.to_string().starts_with("auth command failed: "), "{}", err);
+    }
+}
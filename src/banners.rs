@@ -0,0 +1,51 @@
+use regex::Regex;
+
+/// Versions in the banners of the boot stages: the name it's reported as and a regex with
+/// the version as its first group
+const BANNERS: &[(&str, &str)] = &[
+    // NOTICE:  BL2: v2.10.0(release):OpenWrt v2.10.0-r1 (mt7986-spim-nand-ddr4)
+    ("bl2", r"\bBL2: (v\S.*?)\s*$"),
+    ("bl31", r"\bBL31: (v\S.*?)\s*$"),
+    // I/TC: OP-TEE version: 4.0.0 (gcc version 12.3.0) #1
+    ("optee", r"OP-TEE version: (\S+)"),
+    // U-Boot SPL 2024.01 (Jan 08 2024 - 15:37:20 +0000)
+    ("uboot_spl", r"^U-Boot SPL (\d\S*)"),
+    // U-Boot 2024.01-OpenWrt-r24106 (Jan 08 2024 - 15:37:20 +0000)
+    ("uboot", r"^U-Boot (\d\S*)"),
+    // [    0.000000] Linux version 6.6.30 (builder@buildhost) ...
+    ("linux", r"Linux version (\S+)"),
+];
+
+/// The versions of the firmware which actually ran, as the stages printed them on the
+/// console, rather than those of the files which were sent.
+pub struct Banners {
+    patterns: Vec<(&'static str, Regex)>,
+    found: Vec<(&'static str, String)>,
+}
+
+impl Banners {
+    pub fn new() -> Banners {
+        let patterns = BANNERS.iter().map(|(name, re)| (*name, Regex::new(re).unwrap())).collect();
+        Banners { patterns, found: Vec::new() }
+    }
+
+    /// Looks for a banner in a console line. The first version of each stage is kept, a
+    /// reboot later on runs what's on the flash.
+    pub fn scan(&mut self, line: &str) {
+        let line = line.trim_start_matches(|c: char| c.is_whitespace() || c.is_control());
+        for (name, re) in &self.patterns {
+            if self.found.iter().any(|(n, _)| n == name) {
+                continue;
+            }
+            if let Some(version) = re.captures(line).and_then(|c| c.get(1)) {
+                self.found.push((name, version.as_str().to_owned()));
+                return;
+            }
+        }
+    }
+
+    /// The versions found since the last call, in the order the stages printed them
+    pub fn take(&mut self) -> Vec<(&'static str, String)> {
+        std::mem::take(&mut self.found)
+    }
+}
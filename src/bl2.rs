@@ -1,145 +1,519 @@
-use std::io::Write;
+use std::fmt;
+use std::ops::Range;
 use std::slice;
-use std::time::Duration;
-use serialport::{ClearBuffer, SerialPort};
+use std::time::{Duration, Instant};
+use crate::digest::{self, Sha256};
+use crate::error::{Error, Result};
+use crate::event::{Event, Events, Stage};
+use crate::extension::{Extensions, Frame};
+use crate::protocol::bl2::*;
+use crate::protocol::def::{self, CommandDef, Field, Values};
+use crate::session::PauseControl;
+use crate::transport::Transport;
+use crate::types::{Baud, ByteLen};
 
-const BL2_HANDSHAKE_REQ: &[u8] = "mudl".as_bytes();
-const BL2_HANDSHAKE_RESP: &[u8] = "TF-A".as_bytes();
+/// How the FIP is cut into packets.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PacketSizing {
+    /// Start small and grow the packets while they go through
+    Growing,
+    /// Start small and settle on the fastest size the link handles without errors, see
+    /// [`AdaptiveSizing`]
+    #[default]
+    Adaptive,
+    /// Always use packets of this size, so the bytes on the wire are the same for every run
+    Fixed(u16),
+}
+
+/// Summary of a finished FIP transfer, or of the transfer of an image written to storage.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FipTransfer {
+    /// Number of packets BL2 accepted
+    pub packets: u32,
+    /// Number of packets BL2 rejected and which were sent again
+    pub retries: u32,
+    /// SHA-256 over the headers and data of all accepted packets, in order
+    pub stream_digest: [u8; 32],
+}
 
-pub struct BL2 {
-    port: Box<dyn SerialPort>,
+/// Where [`Bl2::flash`] writes an image to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlashTarget<'a> {
+    /// Byte offset in the storage BL2 boots from
+    Offset(u64),
+    /// Partition of that name in the partition table BL2 knows, e.g. `fip`
+    Partition(&'a str),
+    /// Byte offset in a partition. Needs a BL2 speaking [`FLASH_RANGE_VERSION`] or later
+    PartitionAt(&'a str, u64),
 }
 
-impl BL2 {
-    pub fn new(port: Box<dyn SerialPort>) -> BL2 {
-        BL2 {
-            port
+impl<'a> FlashTarget<'a> {
+    /// The target this many bytes further in
+    pub fn at(self, offset: u64) -> FlashTarget<'a> {
+        match self {
+            FlashTarget::Offset(o) => FlashTarget::Offset(o + offset),
+            FlashTarget::Partition(name) => FlashTarget::PartitionAt(name, offset),
+            FlashTarget::PartitionAt(name, o) => FlashTarget::PartitionAt(name, o + offset),
         }
     }
+}
 
-    pub fn into_serial_port(self) -> Box<dyn SerialPort> {
-        self.port
+impl fmt::Display for FlashTarget<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlashTarget::Offset(offset) => write!(f, "offset {:#x}", offset),
+            FlashTarget::Partition(name) => write!(f, "partition {}", name),
+            FlashTarget::PartitionAt(name, offset) => write!(f, "partition {} at {:#x}", name, offset),
+        }
     }
+}
+
+/// Summary of an image written to storage by [`Bl2::flash`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlashWrite {
+    pub transfer: FipTransfer,
+    /// CRC-32 of the image, which BL2 read back from the storage
+    pub crc32: u32,
+}
 
-    pub fn handshake(&mut self) {
-        let mut i = 0;
+/// Summary of an image written to storage by [`Bl2::flash_changed`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlashDiff {
+    /// Blocks the image was compared in
+    pub blocks: usize,
+    /// The ranges of the image which differed from the storage and were written, adjacent
+    /// blocks merged
+    pub written: Vec<Range<usize>>,
+    /// CRC-32 of the whole image, which BL2 read back from the storage after the writes
+    pub crc32: u32,
+}
+
+/// A partition of the storage BL2 writes to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Partition {
+    pub name: String,
+    /// Byte offset in the storage
+    pub start: u64,
+    pub len: u64,
+}
+
+/// The storage BL2 writes to and how it's partitioned, from [`Bl2::storage_info`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StorageInfo {
+    /// The kind of storage, e.g. [`STORAGE_EMMC`], see
+    /// [`storage_kind_str`](crate::protocol::bl2::storage_kind_str)
+    pub kind: u8,
+    pub block_len: u32,
+    /// Length of the whole storage in bytes
+    pub capacity: u64,
+    pub partitions: Vec<Partition>,
+}
+
+impl StorageInfo {
+    pub fn partition(&self, name: &str) -> Option<&Partition> {
+        self.partitions.iter().find(|p| p.name == name)
+    }
+
+    /// The bytes of the storage a target can be written to, from where it starts to the end
+    /// of its partition or of the storage. None for partitions which don't exist.
+    pub fn room(&self, target: FlashTarget) -> Option<Range<u64>> {
+        let (start, end) = match target {
+            FlashTarget::Offset(offset) => (offset, self.capacity),
+            FlashTarget::Partition(name) => self.partition(name).map(|p| (p.start, p.start + p.len))?,
+            FlashTarget::PartitionAt(name, offset) => self.partition(name).map(|p| (p.start + offset, p.start + p.len))?,
+        };
+        Some(start.min(end)..end)
+    }
+
+    /// Whether writing len bytes to target may change the partition table. The table lives
+    /// outside of the partitions, like the GPT of eMMC in the first and the last blocks.
+    pub fn touches_table(&self, target: FlashTarget, len: u64) -> bool {
+        let FlashTarget::Offset(offset) = target else { return false };
+        let end = offset.saturating_add(len);
+        !self.partitions.iter().any(|p| p.start <= offset && end <= p.start + p.len)
+    }
+}
+
+/// How long BL2 may take to read back a written image and compute its CRC-32
+const FLASH_CHECK_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Commands of the BL2 UART download mode. Obtained from [`Session::bl2`](crate::Session::bl2).
+pub struct Bl2<'a> {
+    port: &'a mut dyn Transport,
+    events: &'a mut Events,
+    extensions: &'a mut Extensions,
+    /// What [`storage_info`](Bl2::storage_info) read, kept by the session
+    storage_info: &'a mut Option<StorageInfo>,
+    pause: PauseControl,
+    read_only: bool,
+    sizing: PacketSizing,
+}
+
+impl<'a> Bl2<'a> {
+    pub(crate) fn new(port: &'a mut dyn Transport, events: &'a mut Events, extensions: &'a mut Extensions,
+                      storage_info: &'a mut Option<StorageInfo>, pause: PauseControl, read_only: bool) -> Bl2<'a> {
+        Bl2 {
+            port,
+            events,
+            extensions,
+            storage_info,
+            pause,
+            read_only,
+            sizing: PacketSizing::default(),
+        }
+    }
+
+    pub fn set_packet_sizing(&mut self, sizing: PacketSizing) {
+        self.sizing = sizing;
+    }
+
+    pub fn handshake(&mut self) -> Result<()> {
+        let _span = self.events.span(Stage::Bl2, "handshake");
+        let mut hs = Handshake::new();
         let mut rx_char = 0;
-        self.port.set_timeout(Duration::from_millis(500)).unwrap();
-        while i < 4 {
-            self.port.write_all(&BL2_HANDSHAKE_REQ[i..i + 1])
-                .expect("failed to write to port.");
+        self.port.set_timeout(Duration::from_millis(500))?;
+        while !hs.is_done() {
+            self.port.write_all(slice::from_ref(&hs.tx()))?;
             if let Ok(()) = self.port.read_exact(slice::from_mut(&mut rx_char)) {
-                if BL2_HANDSHAKE_RESP[i] == rx_char {
-                    i += 1;
-                }
+                hs.rx(rx_char);
             }
         }
         std::thread::sleep(Duration::from_millis(200));
-        self.port.clear(ClearBuffer::Input).unwrap();
+        self.port.clear_input()?;
+        self.events.emit(Event::HandshakeDone(Stage::Bl2));
+        Ok(())
     }
 
-    fn echo(&mut self, buf: &[u8]) {
-        let mut rx_buf: Vec<u8> = vec![0; buf.len()];
-        self.port.write_all(buf).expect("failed to write to port.");
-        self.port.read_exact(rx_buf.as_mut_slice()).unwrap();
-        if buf != rx_buf {
-            panic!("returned data isn't the same. Tx: {:?} Rx: {:?}", buf, rx_buf);
+    /// Sends the command byte and arguments of cmd, unless it's refused by the read-only
+    /// mode, and reads its response
+    fn start(&mut self, cmd: &CommandDef, args: &[u32]) -> Result<Values> {
+        if self.read_only && !cmd.read_only {
+            return Err(Error::ReadOnly { cmd: cmd.opcode });
+        }
+        self.echo(cmd.name, slice::from_ref(&cmd.opcode))?;
+        for (&arg, &width) in args.iter().zip(cmd.args) {
+            self.echo(cmd.name, def::encode(arg, width, &mut [0; 4]))?;
         }
+        self.fields(cmd.name, cmd.response)
     }
 
-    fn read_be16(&mut self) -> u16 {
-        let mut rx_buf: Vec<u8> = vec![0; 2];
-        self.port.read_exact(rx_buf.as_mut_slice()).unwrap();
-        u16::from_be_bytes(rx_buf.try_into().unwrap())
+    /// Writes data and checks that BL2 echoes it back. Fails with [`Error::Ack`] if BL2
+    /// answers with ACK_NAK and an ack code instead.
+    fn echo(&mut self, cmd: &'static str, buf: &[u8]) -> Result<()> {
+        let Some(&first) = buf.first() else { return Ok(()) };
+        self.port.write_all(buf)?;
+        let mut received = vec![0; buf.len()];
+        self.port.read_exact(&mut received[..1])?;
+        if received[0] == ACK_NAK && first != ACK_NAK {
+            let mut code = 0;
+            self.port.read_exact(slice::from_mut(&mut code))?;
+            return Err(Error::Ack { cmd, ack: Ack::from_code(code) });
+        }
+        self.port.read_exact(&mut received[1..])?;
+        if received != buf {
+            return Err(Error::Echo { sent: buf.to_vec(), received });
+        }
+        Ok(())
     }
 
-    fn read_be32(&mut self) -> u32 {
-        let mut rx_buf: Vec<u8> = vec![0; 4];
-        self.port.read_exact(rx_buf.as_mut_slice()).unwrap();
-        u32::from_be_bytes(rx_buf.try_into().unwrap())
+    /// Reads the fields of an answer, failing with [`Error::Ack`] on a status byte which
+    /// isn't FLASH_OK
+    fn fields(&mut self, cmd: &'static str, fields: &[Field]) -> Result<Values> {
+        Frame::new(self.port).fields(cmd, fields).map_err(|e| match e {
+            Error::Status { cmd, status } => Error::Ack { cmd, ack: Ack::from_code(status as u8) },
+            e => e,
+        })
     }
 
-    pub fn version(&mut self) -> u8 {
-        self.echo(slice::from_ref(&1));
-        let mut rx_char = 0;
-        self.port.read_exact(slice::from_mut(&mut rx_char)).unwrap();
-        rx_char
+    /// Version of the UART download protocol implemented by BL2, see
+    /// [`variant`](crate::protocol::bl2::variant) for its commands
+    pub fn version(&mut self) -> Result<u8> {
+        let _span = self.events.span(Stage::Bl2, VERSION.name);
+        Ok(self.start(&VERSION, &[])?.get(0) as u8)
+    }
+
+    /// Switches both ends to a new baud rate. BL2 expects a new handshake afterwards.
+    pub fn set_baudrate(&mut self, baudrate: Baud) -> Result<()> {
+        let _span = self.events.span(Stage::Bl2, SET_BAUDRATE.name);
+        self.start(&SET_BAUDRATE, &[baudrate.0])?;
+        self.port.set_baud_rate(baudrate.0)?;
+        self.events.emit(Event::BaudrateChanged { stage: Stage::Bl2, baudrate });
+        Ok(())
+    }
+
+    fn send_fip_packet(&mut self, idx: u32, fip: &[u8], stream: &mut Sha256) -> Result<bool> {
+        let _span = self.events.span(Stage::Bl2, "packet");
+        let header = packet_header(idx, fip);
+        // BL2 doesn't refuse packets with ACK_NAK, they're acknowledged after their data.
+        let mut frame = Frame::new(self.port);
+        frame.echo(&header.idx)?;
+        frame.echo(&header.len)?;
+        frame.echo(&header.checksum)?;
+        self.port.write_all(fip)?;
+        self.port.drain()?;
+        let sent = Instant::now();
+
+        let mut ack = [0; PACKET_ACK_LEN];
+        self.port.read_exact(&mut ack)?;
+        match parse_packet_ack(idx, u16::from_be_bytes(header.checksum), &ack) {
+            PacketAck::Ok => {
+                stream.update(&header.idx);
+                stream.update(&header.len);
+                stream.update(&header.checksum);
+                stream.update(fip);
+                self.events.emit(Event::PacketAcked { idx, len: fip.len(), latency: sent.elapsed() });
+                Ok(true)
+            }
+            ack => {
+                self.events.emit(Event::PacketRejected { idx, ack });
+                Ok(false)
+            }
+        }
     }
 
-    pub fn set_baudrate(&mut self, baudrate: u32) {
-        self.echo(slice::from_ref(&2));
-        self.echo(&u32::to_be_bytes(baudrate));
-        self.port.set_baud_rate(baudrate).expect("failed to switch baud rate.");
+    /// Sends an FIP, resending packets until BL2 accepts each of them.
+    pub fn send_fip(&mut self, fip: &[u8]) -> Result<FipTransfer> {
+        let _span = self.events.span(Stage::Bl2, SEND_FIP.name);
+        self.port.set_timeout(Duration::from_secs(2))?;
+        self.start(&SEND_FIP, &[ByteLen::of(fip)?.0])?;
+        self.send_packets(fip)
     }
 
-    fn fip_packet_checksum(fip: &[u8]) -> u16 {
-        let mut p = 0;
-        let mut csum: u32 = 0;
-        while fip.len() - p > 1 {
-            let val = u16::from_be_bytes(fip[p..p + 2].try_into().unwrap());
-            csum += val as u32;
-            p += 2;
+    /// Writes an image to the storage BL2 boots from, e.g. to recover a board whose
+    /// bootloader is broken, and checks the CRC-32 of what BL2 reads back. Needs a BL2
+    /// speaking [`FLASH_VERSION`] of the protocol or later, see [`version`](Bl2::version).
+    pub fn flash(&mut self, target: FlashTarget, image: &[u8]) -> Result<FlashWrite> {
+        let _span = self.events.span(Stage::Bl2, FLASH.name);
+        if self.storage_info.as_ref().is_some_and(|info| info.touches_table(target, image.len() as u64)) {
+            *self.storage_info = None;
+        }
+        self.port.set_timeout(Duration::from_secs(2))?;
+        self.start(&FLASH, &[])?;
+        self.send_target(FLASH.name, target)?;
+        self.echo(FLASH.name, &u32::to_be_bytes(ByteLen::of(image)?.0))?;
+        self.fields(FLASH.name, &[FLASH_STATUS])?;
+        let transfer = self.send_packets(image)?;
+
+        // BL2 writes and reads back the image meanwhile.
+        let check_span = self.events.span(Stage::Bl2, "check");
+        self.port.set_timeout(FLASH_CHECK_TIMEOUT)?;
+        let received = self.fields(FLASH.name, FLASH.after_data)?.get(1);
+        drop(check_span);
+        self.port.set_timeout(Duration::from_secs(2))?;
+        let expected = digest::crc32(0, image);
+        if expected != received {
+            return Err(Error::Crc { expected, received });
         }
+        Ok(FlashWrite { transfer, crc32: received })
+    }
 
-        if fip.len() != p {
-            csum += (fip.last().unwrap().to_owned() as u32) << 8;
+    /// Sends the target of CMD_FLASH or CMD_FLASH_CRC
+    fn send_target(&mut self, cmd: &'static str, target: FlashTarget) -> Result<()> {
+        let name_len = |name: &str| u8::try_from(name.len()).map_err(|_| Error::Ack { cmd, ack: Ack::NoPartition });
+        match target {
+            FlashTarget::Offset(offset) => {
+                self.echo(cmd, &[FLASH_TARGET_OFFSET])?;
+                self.echo(cmd, &offset.to_be_bytes())?;
+            }
+            FlashTarget::Partition(name) => {
+                self.echo(cmd, &[FLASH_TARGET_PARTITION, name_len(name)?])?;
+                self.echo(cmd, name.as_bytes())?;
+            }
+            FlashTarget::PartitionAt(name, offset) => {
+                self.echo(cmd, &[FLASH_TARGET_PARTITION_AT, name_len(name)?])?;
+                self.echo(cmd, name.as_bytes())?;
+                self.echo(cmd, &offset.to_be_bytes())?;
+            }
         }
+        Ok(())
+    }
 
-        while csum >> 16 != 0 {
-            csum = ((csum >> 16) & 0xffff) + (csum & 0xffff);
+    /// The CRC-32 of each block_len bytes of the len bytes of storage at target, the last
+    /// block possibly shorter. Needs a BL2 speaking [`FLASH_RANGE_VERSION`] or later.
+    pub fn flash_crcs(&mut self, target: FlashTarget, len: u32, block_len: u32) -> Result<Vec<u32>> {
+        let _span = self.events.span(Stage::Bl2, FLASH_CRC.name);
+        let block_len = block_len.max(1);
+        self.start(&FLASH_CRC, &[])?;
+        self.send_target(FLASH_CRC.name, target)?;
+        self.echo(FLASH_CRC.name, &len.to_be_bytes())?;
+        self.echo(FLASH_CRC.name, &block_len.to_be_bytes())?;
+        // BL2 reads the whole range before it answers.
+        self.port.set_timeout(FLASH_CHECK_TIMEOUT)?;
+        let status = self.fields(FLASH_CRC.name, FLASH_CRC.after_data);
+        self.port.set_timeout(Duration::from_secs(2))?;
+        status?;
+        let mut crcs = vec![0; len.div_ceil(block_len) as usize * 4];
+        self.port.read_exact(&mut crcs)?;
+        Ok(crcs.chunks(4).map(|c| u32::from_be_bytes(c.try_into().unwrap())).collect())
+    }
+
+    /// Writes only the blocks of an image which differ from what's on the storage at target,
+    /// then checks the CRC-32 of the whole image read back. Rewriting a partition with a
+    /// slightly changed image takes a fraction of the time [`flash`](Bl2::flash) takes over a
+    /// slow link. block_len should be a multiple of the erase block of the storage. Needs a
+    /// BL2 speaking [`FLASH_RANGE_VERSION`] or later.
+    pub fn flash_changed(&mut self, target: FlashTarget, image: &[u8], block_len: u32) -> Result<FlashDiff> {
+        let block_len = block_len.max(1) as usize;
+        let len = ByteLen::of(image)?.0;
+        let crcs = self.flash_crcs(target, len, block_len as u32)?;
+        let mut written: Vec<Range<usize>> = Vec::new();
+        for (i, (block, crc)) in image.chunks(block_len).zip(crcs).enumerate() {
+            if digest::crc32(0, block) == crc {
+                continue;
+            }
+            let start = i * block_len;
+            match written.last_mut() {
+                Some(range) if range.end == start => range.end = start + block.len(),
+                _ => written.push(start..start + block.len()),
+            }
         }
+        for range in &written {
+            self.flash(target.at(range.start as u64), &image[range.clone()])?;
+        }
+        let crc32 = self.flash_crcs(target, len, len)?.first().copied().unwrap_or(0);
+        let expected = digest::crc32(0, image);
+        if crc32 != expected {
+            return Err(Error::Crc { expected, received: crc32 });
+        }
+        Ok(FlashDiff { blocks: image.len().div_ceil(block_len), written, crc32 })
+    }
+
+    /// Sends the length of a partition name and the name
+    fn send_name(&mut self, cmd: &'static str, name: &str) -> Result<()> {
+        let len = u8::try_from(name.len()).map_err(|_| Error::Ack { cmd, ack: Ack::NoPartition })?;
+        self.echo(cmd, &[len])?;
+        self.echo(cmd, name.as_bytes())
+    }
 
-        csum as u16
+    /// The partition the device doesn't boot from in place of the one named, to write a new
+    /// image to before switching to it with [`flash_switch`](Bl2::flash_switch). None if
+    /// the layout has no alternate of it. Needs a BL2 speaking [`FLASH_SWITCH_VERSION`] or
+    /// later.
+    pub fn flash_alternate(&mut self, name: &str) -> Result<Option<String>> {
+        let _span = self.events.span(Stage::Bl2, FLASH_ALTERNATE.name);
+        self.start(&FLASH_ALTERNATE, &[])?;
+        self.send_name(FLASH_ALTERNATE.name, name)?;
+        match self.fields(FLASH_ALTERNATE.name, FLASH_ALTERNATE.after_data) {
+            Ok(_) => {}
+            Err(Error::Ack { ack: Ack::NoAlternate, .. }) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let mut len = 0;
+        self.port.read_exact(slice::from_mut(&mut len))?;
+        let mut alternate = vec![0; len as usize];
+        self.port.read_exact(&mut alternate)?;
+        Ok(Some(String::from_utf8_lossy(&alternate).into_owned()))
     }
 
-    fn send_fip_packet(&mut self, idx: u32, fip: &[u8]) -> bool {
-        self.echo(&u32::to_be_bytes(idx));
-        self.echo(&u16::to_be_bytes(fip.len() as u16));
-        let checksum = BL2::fip_packet_checksum(fip);
-        self.echo(&u16::to_be_bytes(checksum));
-        self.port.write_all(fip).expect("failed to send fip packet.");
+    /// Makes the device boot from the partition named, an alternate found with
+    /// [`flash_alternate`](Bl2::flash_alternate) once the new image is written to it.
+    pub fn flash_switch(&mut self, name: &str) -> Result<()> {
+        let _span = self.events.span(Stage::Bl2, FLASH_SWITCH.name);
+        // Some layouts switch by changing the attributes of the partitions in the table.
+        *self.storage_info = None;
+        self.start(&FLASH_SWITCH, &[])?;
+        self.send_name(FLASH_SWITCH.name, name)?;
+        self.fields(FLASH_SWITCH.name, FLASH_SWITCH.after_data)?;
+        Ok(())
+    }
 
-        while self.port.bytes_to_write().unwrap() > 0 {
-            std::thread::sleep(Duration::from_millis(50));
+    /// The storage and its partitions. Read from BL2 once per session, and again only after
+    /// a write which may have changed the partition table, so checking one target after the
+    /// other doesn't cost a round trip over a slow link each. Needs a BL2 speaking
+    /// [`FLASH_INFO_VERSION`] or later.
+    pub fn storage_info(&mut self) -> Result<&StorageInfo> {
+        if self.storage_info.is_none() {
+            *self.storage_info = Some(self.read_storage_info()?);
         }
+        Ok(self.storage_info.as_ref().unwrap())
+    }
 
-        let expected_idx = self.read_be32();
-        let real_csum = self.read_be16();
-        if expected_idx != idx {
-            println!("Incorrect packet index: {} != {}", idx, expected_idx);
-            false
-        } else if real_csum != checksum {
-            println!("Incorrect checksum: {:#x} != {:#x}", real_csum, checksum);
-            false
-        } else {
-            true
+    fn read_storage_info(&mut self) -> Result<StorageInfo> {
+        let _span = self.events.span(Stage::Bl2, FLASH_INFO.name);
+        self.start(&FLASH_INFO, &[])?;
+        self.fields(FLASH_INFO.name, FLASH_INFO.after_data)?;
+        let mut header = [0; 14];
+        self.port.read_exact(&mut header)?;
+        let mut info = StorageInfo {
+            kind: header[0],
+            block_len: u32::from_be_bytes(header[1..5].try_into().unwrap()),
+            capacity: u64::from_be_bytes(header[5..13].try_into().unwrap()),
+            partitions: Vec::with_capacity(header[13] as usize),
+        };
+        for _ in 0..header[13] {
+            let mut len = 0;
+            self.port.read_exact(slice::from_mut(&mut len))?;
+            let mut name = vec![0; len as usize];
+            self.port.read_exact(&mut name)?;
+            let mut range = [0; 16];
+            self.port.read_exact(&mut range)?;
+            info.partitions.push(Partition {
+                name: String::from_utf8_lossy(&name).into_owned(),
+                start: u64::from_be_bytes(range[..8].try_into().unwrap()),
+                len: u64::from_be_bytes(range[8..].try_into().unwrap()),
+            });
         }
+        Ok(info)
     }
 
-    pub fn send_fip(&mut self, fip: &[u8]) {
-        self.port.set_timeout(Duration::from_secs(2)).unwrap();
-        self.echo(slice::from_ref(&3));
-        self.echo(&u32::to_be_bytes(fip.len() as u32));
+    /// Sends data in packets, resending them until BL2 accepts each of them.
+    fn send_packets(&mut self, fip: &[u8]) -> Result<FipTransfer> {
         let mut idx: u32 = 0;
-        let mut pkt_len = 128;
+        let mut retries: u32 = 0;
+        let mut stream = Sha256::new();
+        let mut adaptive = AdaptiveSizing::new();
+        let mut pkt_len = match self.sizing {
+            PacketSizing::Growing => FIRST_PACKET_LEN,
+            PacketSizing::Adaptive => adaptive.packet_len(),
+            PacketSizing::Fixed(len) => (len as usize).max(1),
+        };
 
         let mut p: usize = 0;
         while fip.len() - p > pkt_len {
-            if self.send_fip_packet(idx, &fip[p..p + pkt_len]) {
+            self.pause.wait(self.events, Stage::Bl2);
+            let started = Instant::now();
+            if self.send_fip_packet(idx, &fip[p..p + pkt_len], &mut stream)? {
                 idx += 1;
                 p += pkt_len;
-                if pkt_len < 32768 {
-                    pkt_len *= 2;
-                } else if pkt_len < 65536 - 1024 {
-                    pkt_len += 1024;
+                match self.sizing {
+                    PacketSizing::Growing => pkt_len = next_packet_len(pkt_len),
+                    PacketSizing::Adaptive => {
+                        adaptive.acked(started.elapsed());
+                        pkt_len = adaptive.packet_len();
+                    }
+                    PacketSizing::Fixed(_) => {}
+                }
+                self.events.emit(Event::Progress { stage: Stage::Bl2, sent: p, total: fip.len() });
+            } else {
+                retries += 1;
+                if self.sizing == PacketSizing::Adaptive {
+                    adaptive.rejected();
+                    pkt_len = adaptive.packet_len();
                 }
             }
         }
 
-        while !self.send_fip_packet(idx, &fip[p..]) {}
+        self.pause.wait(self.events, Stage::Bl2);
+        while !self.send_fip_packet(idx, &fip[p..], &mut stream)? {
+            retries += 1;
+        }
+        self.events.emit(Event::Progress { stage: Stage::Bl2, sent: fip.len(), total: fip.len() });
+        Ok(FipTransfer {
+            packets: idx + 1,
+            retries,
+            stream_digest: stream.finish(),
+        })
+    }
+
+    /// Boots the FIP that was sent
+    pub fn go(&mut self) -> Result<()> {
+        let _span = self.events.span(Stage::Bl2, GO.name);
+        self.start(&GO, &[])?;
+        Ok(())
     }
 
-    pub fn go(&mut self) {
-        self.echo(slice::from_ref(&4));
+    /// Runs a command added with [`Session::register`](crate::Session::register)
+    pub fn call(&mut self, name: &str, request: &[u8]) -> Result<Vec<u8>> {
+        self.extensions.call(self.port, self.events, self.read_only, Stage::Bl2, name, request)
     }
 }
@@ -1,162 +1,343 @@
-use std::io::Write;
 use std::slice;
-use std::time::Duration;
-use serialport::{ClearBuffer, SerialPort};
+use std::time::{Duration, Instant};
+use crate::auth::AuthProvider;
+use crate::error::{Error, Result};
+use crate::event::{Event, Events, Stage};
+use crate::extension::{Extensions, Frame};
+use crate::protocol::bootrom::*;
+use crate::protocol::def::{CommandDef, Values};
+use crate::session::PauseControl;
+use crate::transport::Transport;
+use crate::types::{Baud, ByteLen, LoadAddr};
 
-const BROM_HANDSHAKE: &[u8] = &[0xa0, 0x0a, 0x50, 0x05];
-pub struct BootROM {
-    port: Box<dyn SerialPort>,
+pub use crate::protocol::bootrom::TargetConfig;
+
+/// Size of the pieces the payload is written in, so progress can be reported
+const SEND_CHUNK_LEN: usize = 4096;
+
+/// Attempts at a command after switching the baud rate, the first bytes at a new rate may
+/// get garbled while the UARTs settle
+const SYNC_ATTEMPTS: usize = 5;
+
+/// Commands of the BootROM download mode. Obtained from [`Session::bootrom`](crate::Session::bootrom).
+pub struct BootRom<'a> {
+    port: &'a mut dyn Transport,
+    events: &'a mut Events,
+    extensions: &'a mut Extensions,
+    pause: PauseControl,
+    read_only: bool,
 }
 
-impl BootROM {
-    pub fn new(port: Box<dyn SerialPort>) -> BootROM {
-        BootROM {
-            port
+impl<'a> BootRom<'a> {
+    pub(crate) fn new(port: &'a mut dyn Transport, events: &'a mut Events, extensions: &'a mut Extensions,
+                      pause: PauseControl, read_only: bool) -> BootRom<'a> {
+        BootRom {
+            port,
+            events,
+            extensions,
+            pause,
+            read_only,
         }
     }
 
-    pub fn into_serial_port(self) -> Box<dyn SerialPort> {
-        self.port
+    /// Waits for the BootROM to answer the handshake. Keep sending it while the device
+    /// powers up. Anything else received meanwhile is skipped.
+    pub fn handshake(&mut self) -> Result<()> {
+        self.handshake_until(None)
     }
 
-    pub fn handshake(&mut self) {
-        let mut i = 0;
+    /// Like [`handshake`](Self::handshake), but gives up with [`Error::HandshakeTimeout`] if
+    /// the BootROM doesn't answer within timeout.
+    pub fn handshake_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.handshake_until(Some(Instant::now() + timeout))
+    }
+
+    fn handshake_until(&mut self, deadline: Option<Instant>) -> Result<()> {
+        let _span = self.events.span(Stage::BootRom, "handshake");
+        let mut hs = Handshake::new();
         let mut rx_char = 0;
-        self.port.set_timeout(Duration::from_millis(10)).unwrap();
-        while i < BROM_HANDSHAKE.len() {
-            self.port.write_all(&BROM_HANDSHAKE[i .. i+1])
-                .expect("failed to write to port.");
-            if let Ok(()) = self.port.read_exact(slice::from_mut(&mut rx_char)) {
-                if BROM_HANDSHAKE[i] == !rx_char {
-                    i += 1;
-                } else {
-                    i = 0;
+        // The slowest answer of the sequence so far
+        let mut latency = Duration::ZERO;
+        self.port.set_timeout(Duration::from_millis(10))?;
+        while !hs.is_done() {
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return Err(Error::HandshakeTimeout);
+            }
+            let tx_char = hs.tx();
+            let sent = Instant::now();
+            self.port.write_all(slice::from_ref(&tx_char))?;
+            match self.port.read_exact(slice::from_mut(&mut rx_char)) {
+                Ok(()) => {
+                    if rx_char != !tx_char {
+                        // Boot messages or line noise, which would shift every answer
+                        // after it by a byte.
+                        self.port.clear_input()?;
+                        latency = Duration::ZERO;
+                    } else {
+                        latency = latency.max(sent.elapsed());
+                    }
+                    hs.rx(rx_char);
+                }
+                // The BootROM dropped out of the sequence, e.g. after a garbled byte.
+                Err(_) => {
+                    hs = Handshake::new();
+                    latency = Duration::ZERO;
                 }
             }
         }
         std::thread::sleep(Duration::from_millis(200));
-        self.port.clear(ClearBuffer::Input).unwrap();
-        self.port.set_timeout(Duration::from_millis(500)).unwrap();
+        self.port.clear_input()?;
+        self.port.set_timeout(Duration::from_millis(500))?;
+        self.events.emit(Event::HandshakeLatency { stage: Stage::BootRom, latency });
+        self.events.emit(Event::HandshakeDone(Stage::BootRom));
+        Ok(())
     }
 
-    fn echo(&mut self, buf: &[u8]) {
-        let mut rx_buf: Vec<u8> =vec![0; buf.len()];
-        self.port.write_all(buf).expect("failed to write to port.");
-        self.port.read_exact(rx_buf.as_mut_slice()).unwrap();
-        if buf != rx_buf {
-            panic!("returned data isn't the same. Tx: {:?} Rx: {:?}", buf, rx_buf);
-        }
+    /// Sends the command byte and arguments of cmd, unless it's refused by the read-only
+    /// mode, and reads its response
+    fn start(&mut self, cmd: &CommandDef, args: &[u32]) -> Result<Values> {
+        Frame::new(self.port).start(cmd, args, self.read_only)
     }
 
-    fn read_be16(&mut self) -> u16 {
-        let mut rx_buf: Vec<u8> = vec![0; 2];
-        self.port.read_exact(rx_buf.as_mut_slice()).unwrap();
-        u16::from_be_bytes(rx_buf.try_into().unwrap())
+    /// Reads what cmd answers after its data
+    fn finish(&mut self, cmd: &CommandDef) -> Result<Values> {
+        Frame::new(self.port).fields(cmd.name, cmd.after_data)
     }
 
-    fn read_be32(&mut self) -> u32 {
-        let mut rx_buf: Vec<u8> = vec![0; 4];
-        self.port.read_exact(rx_buf.as_mut_slice()).unwrap();
-        u32::from_be_bytes(rx_buf.try_into().unwrap())
+    fn echo(&mut self, buf: &[u8]) -> Result<()> {
+        Frame::new(self.port).echo(buf)
     }
 
-    pub fn get_hw_code(&mut self) -> u16 {
-        self.echo(slice::from_ref(&0xfd));
-        let code = self.read_be16();
-        let ret = self.read_be16();
-        if ret != 0 {
-            panic!("status: {}", ret);
-        }
-        code
+    pub fn get_hw_code(&mut self) -> Result<u16> {
+        let _span = self.events.span(Stage::BootRom, GET_HW_CODE.name);
+        Ok(self.start(&GET_HW_CODE, &[])?.get(0) as u16)
     }
 
-    pub fn get_hw_dict(&mut self) -> (u16, u16, u16) {
-        self.echo(slice::from_ref(&0xfc));
-        let hw_sub_code = self.read_be16();
-        let hw_ver = self.read_be16();
-        let sw_ver = self.read_be16();
-        let ret = self.read_be16();
-        if ret != 0 {
-            panic!("status: {}", ret);
+    /// Returns the hw sub code, hw version and sw version
+    pub fn get_hw_dict(&mut self) -> Result<(u16, u16, u16)> {
+        let _span = self.events.span(Stage::BootRom, GET_HW_DICT.name);
+        let dict = self.start(&GET_HW_DICT, &[])?;
+        Ok((dict.get(0) as u16, dict.get(1) as u16, dict.get(2) as u16))
+    }
+
+    /// The SoC ID, unique per chip, of BootROMs which have CMD_GET_SOC_ID
+    pub fn get_soc_id(&mut self) -> Result<Vec<u8>> {
+        let _span = self.events.span(Stage::BootRom, GET_SOC_ID.name);
+        let len = self.start(&GET_SOC_ID, &[])?.get(0) as usize;
+        if len > MAX_SOC_ID_LEN {
+            return Err(Error::Io(std::io::ErrorKind::InvalidData.into()));
         }
-        (hw_sub_code, hw_ver, sw_ver)
+        let mut id = vec![0; len];
+        self.port.read_exact(&mut id)?;
+        self.finish(&GET_SOC_ID)?;
+        Ok(id)
     }
 
-    pub fn get_target_config(&mut self) -> (bool, bool, bool) {
-        self.echo(slice::from_ref(&0xd8));
-        let target_config = self.read_be32();
-        let ret = self.read_be16();
-        if ret != 0 {
-            panic!("status: {}", ret);
+    pub fn get_target_config(&mut self) -> Result<TargetConfig> {
+        Ok(TargetConfig::from_word(self.get_target_config_word()?))
+    }
+
+    /// The whole word of CMD_GET_TARGET_CONFIG, with the bits [`TargetConfig`] doesn't
+    /// decode, e.g. to tell units of different provenance apart
+    pub fn get_target_config_word(&mut self) -> Result<u32> {
+        let _span = self.events.span(Stage::BootRom, GET_TARGET_CONFIG.name);
+        Ok(self.start(&GET_TARGET_CONFIG, &[])?.get(0))
+    }
+
+    /// Sends the address and word count of CMD_READ16 or CMD_READ32, and reads the words
+    fn read_words(&mut self, cmd: &CommandDef, addr: u32, count: u32, word_len: usize) -> Result<Vec<u8>> {
+        let _span = self.events.span(Stage::BootRom, cmd.name);
+        self.start(cmd, &[addr, count])?;
+        let mut data = vec![0; count as usize * word_len];
+        self.port.read_exact(&mut data)?;
+        self.finish(cmd)?;
+        Ok(data)
+    }
+
+    /// Reads count 16-bit words of memory from addr
+    pub fn read16(&mut self, addr: u32, count: u32) -> Result<Vec<u16>> {
+        let data = self.read_words(&READ16, addr, count, 2)?;
+        Ok(data.chunks(2).map(|w| u16::from_be_bytes([w[0], w[1]])).collect())
+    }
+
+    /// Reads count 32-bit words of memory from addr
+    pub fn read32(&mut self, addr: u32, count: u32) -> Result<Vec<u32>> {
+        let data = self.read_words(&READ32, addr, count, 4)?;
+        Ok(data.chunks(4).map(|w| u32::from_be_bytes(w.try_into().unwrap())).collect())
+    }
+
+    /// Writes 32-bit words of memory from addr on, e.g. registers
+    pub fn write32(&mut self, addr: u32, words: &[u32]) -> Result<()> {
+        let _span = self.events.span(Stage::BootRom, WRITE32.name);
+        self.start(&WRITE32, &[addr, words.len() as u32])?;
+        for word in words {
+            self.echo(&u32::to_be_bytes(*word))?;
         }
-        let secure_boot = target_config & 1 != 0;
-        let serial_link_authorization = target_config & 2 != 0;
-        let download_agent_authorization = target_config & 4 != 0;
-        (secure_boot, serial_link_authorization, download_agent_authorization)
+        self.finish(&WRITE32)?;
+        Ok(())
     }
 
-    pub fn send_da(&mut self, da_addr: u32, sig_len: u32, da_buf: &[u8]) -> u16 {
-        self.echo(slice::from_ref(&0xd7));
-        self.echo(&u32::to_be_bytes(da_addr));
-        self.echo(&u32::to_be_bytes(da_buf.len() as u32 - sig_len));
-        self.echo(&u32::to_be_bytes(sig_len));
+    /// Loads a payload to da_addr. The last sig_len bytes of da_buf are its signature.
+    /// Returns the checksum computed by the BootROM, after checking it against the data.
+    pub fn send_da(&mut self, da_addr: LoadAddr, sig_len: ByteLen, da_buf: &[u8]) -> Result<u16> {
+        let _span = self.events.span(Stage::BootRom, SEND_DA.name);
+        let len = ByteLen::of(da_buf)?.0;
+        let args = send_da_args(da_addr.0, len, sig_len.0)
+            .ok_or(Error::SignatureTooLong { sig_len: sig_len.0, len })?
+            .map(u32::from_be_bytes);
+        self.start(&SEND_DA, &args)?;
 
-        let ret = self.read_be16();
-        if ret != 0 {
-            panic!("send_da cmd status: {}", ret);
+        let data_span = self.events.span(Stage::BootRom, "data");
+        let mut sent = 0;
+        for chunk in da_buf.chunks(SEND_CHUNK_LEN) {
+            self.pause.wait(self.events, Stage::BootRom);
+            self.port.write_all(chunk)?;
+            sent += chunk.len();
+            self.events.emit(Event::Progress { stage: Stage::BootRom, sent, total: da_buf.len() });
         }
+        self.port.drain()?;
+        drop(data_span);
 
-        self.port.write_all(da_buf).unwrap();
-        while self.port.bytes_to_write().unwrap() > 0 {
-            std::thread::sleep(Duration::from_millis(200));
+        let checksum = self.finish(&SEND_DA)?.get(0) as u16;
+        let expected = da_checksum(da_buf);
+        if checksum != expected {
+            return Err(Error::Checksum { expected, received: checksum });
         }
-        let checksum = self.read_be16();
-        let ret = self.read_be16();
-        if ret != 0 {
-            panic!("send_da cmd status: {}", ret);
+        Ok(checksum)
+    }
+
+    /// Sends data after its length, as CMD_SEND_AUTH and CMD_SEND_CERT take it, and checks
+    /// the checksum the BootROM computed
+    fn send_blob(&mut self, cmd: &CommandDef, data: &[u8]) -> Result<()> {
+        let _span = self.events.span(Stage::BootRom, cmd.name);
+        self.start(cmd, &[ByteLen::of(data)?.0])?;
+        self.port.write_all(data)?;
+        self.port.drain()?;
+        let checksum = self.finish(cmd)?.get(0) as u16;
+        let expected = da_checksum(data);
+        if checksum != expected {
+            return Err(Error::Checksum { expected, received: checksum });
         }
-        checksum
+        Ok(())
     }
 
-    pub fn jump_da(&mut self, da_addr: u32) {
-        self.echo(slice::from_ref(&0xd5));
-        self.echo(&u32::to_be_bytes(da_addr));
-        let ret = self.read_be16();
-        if ret != 0 {
-            panic!("jump_da cmd status: {}", ret);
+    /// Sends an auth file, e.g. the one of SP Flash Tool, to devices with SLA or DAA enabled
+    pub fn send_auth(&mut self, auth: &[u8]) -> Result<()> {
+        self.send_blob(&SEND_AUTH, auth)
+    }
+
+    /// Sends the DAA certificate, which allows loading a payload signed with its key
+    pub fn send_cert(&mut self, cert: &[u8]) -> Result<()> {
+        self.send_blob(&SEND_CERT, cert)
+    }
+
+    /// Answers the serial link authorization challenge of the device with a signature from
+    /// the provider. The challenge and the signature are each sent after their length.
+    pub fn sla(&mut self, hw_code: u16, auth: &mut dyn AuthProvider) -> Result<()> {
+        let _span = self.events.span(Stage::BootRom, SLA.name);
+        let len = self.start(&SLA, &[])?.get(1) as usize;
+        if len > MAX_SLA_CHALLENGE_LEN {
+            return Err(Error::Io(std::io::ErrorKind::InvalidData.into()));
+        }
+        let mut challenge = vec![0; len];
+        self.port.read_exact(&mut challenge)?;
+        let signature = auth.sign_challenge(hw_code, &challenge)?;
+        self.echo(&u32::to_be_bytes(ByteLen::of(&signature)?.0))?;
+        self.port.write_all(&signature)?;
+        self.port.drain()?;
+        Frame::new(self.port).fields("sla signature", SLA.after_data)?;
+        Ok(())
+    }
+
+    /// Starts the payload at da_addr in aarch32
+    pub fn jump_da(&mut self, da_addr: LoadAddr) -> Result<()> {
+        let _span = self.events.span(Stage::BootRom, JUMP_DA.name);
+        self.start(&JUMP_DA, &[da_addr.0])?;
+        Ok(())
+    }
+
+    /// Switches both ends to a new baud rate and checks that the BootROM answers at it.
+    pub fn set_baudrate(&mut self, baudrate: Baud) -> Result<()> {
+        let _span = self.events.span(Stage::BootRom, SET_BAUDRATE.name);
+        match self.start(&SET_BAUDRATE, &[baudrate.0]) {
+            Err(Error::Status { status: STATUS_BAUDRATE_TOO_HIGH, .. }) => return Err(Error::BaudrateTooHigh(baudrate)),
+            result => result?,
+        };
+        self.port.set_baud_rate(baudrate.0)?;
+        self.sync(baudrate)?;
+        self.events.emit(Event::BaudrateChanged { stage: Stage::BootRom, baudrate });
+        Ok(())
+    }
+
+    /// Brings both ends back to baudrate after [`set_baudrate`](Self::set_baudrate) failed,
+    /// whether the BootROM switched before or not.
+    pub fn recover_baudrate(&mut self, baudrate: Baud) -> Result<()> {
+        let _span = self.events.span(Stage::BootRom, "recover_baudrate");
+        // A link too bad for the sync may still get the command through.
+        if self.set_baudrate(baudrate).is_ok() {
+            return Ok(());
         }
+        self.port.set_baud_rate(baudrate.0)?;
+        self.sync(baudrate)?;
+        self.events.emit(Event::BaudrateChanged { stage: Stage::BootRom, baudrate });
+        Ok(())
     }
 
-    pub fn set_baudrate(&mut self, baudrate: u32) -> bool {
-        self.echo(slice::from_ref(&0xdc));
-        self.echo(&u32::to_be_bytes(baudrate));
-        let ret = self.read_be16();
-        if ret == 0 {
-            self.port.set_baud_rate(baudrate).unwrap();
-            true
-        } else if ret == 0x1d1d {
-            panic!("{} is too high for bootrom.", baudrate);
-        } else {
-            unreachable!();
+    /// Checks that the BootROM answers at the baud rate just switched to, with a command
+    /// changing nothing.
+    fn sync(&mut self, baudrate: Baud) -> Result<()> {
+        for _ in 0..SYNC_ATTEMPTS {
+            std::thread::sleep(Duration::from_millis(20));
+            self.port.clear_input()?;
+            if self.get_hw_code().is_ok() {
+                return Ok(());
+            }
         }
+        Err(Error::BaudrateSync(baudrate))
+    }
+
+    /// Resets the CPU into aarch64 and starts the payload at da_addr
+    pub fn jump_da64(&mut self, da_addr: LoadAddr) -> Result<()> {
+        let _span = self.events.span(Stage::BootRom, JUMP_DA64.name);
+        self.start(&JUMP_DA64, &[da_addr.0, JUMP_DA64_AARCH64 as u32])?;
+
+        self.echo(slice::from_ref(&JUMP_DA64_MAGIC))?;
+        Frame::new(self.port).fields("jump_da64 magic", JUMP_DA64.response)?;
+        Ok(())
     }
 
-    pub fn jump_da64(&mut self, da_addr: u32) {
-        self.echo(slice::from_ref(&0xde));
-        self.echo(&u32::to_be_bytes(da_addr));
+    /// Runs a command added with [`Session::register`](crate::Session::register)
+    pub fn call(&mut self, name: &str, request: &[u8]) -> Result<Vec<u8>> {
+        self.extensions.call(self.port, self.events, self.read_only, Stage::BootRom, name, request)
+    }
+}
 
-        // 1 for 64-bit
-        self.echo(slice::from_ref(&1));
-        let ret = self.read_be16();
-        if ret != 0 {
-            panic!("jump_da64 cmd status: {}", ret);
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use crate::extension::tests::device;
+    use crate::session::Session;
+    use super::*;
+
+    /// Signs nothing, the device is expected to fail before
+    struct Unused;
+
+    impl AuthProvider for Unused {
+        fn sign_challenge(&mut self, _hw_code: u16, _challenge: &[u8]) -> io::Result<Vec<u8>> {
+            unreachable!()
         }
 
-        // A magic number checked before resetting CPU to aarch64
-        self.echo(slice::from_ref(&100));
-        let ret = self.read_be16();
-        if ret != 0 {
-            panic!("jump_da64 magic status: {}", ret);
+        fn certificate(&mut self, _hw_code: u16) -> io::Result<Vec<u8>> {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn sla_challenge_too_long_is_refused() {
+        let mut session = Session::new(Box::new(device(&[CMD_SLA, 0, 0, 0xff, 0xff, 0xff, 0xff])));
+        match session.bootrom().sla(0x0950, &mut Unused) {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            result => panic!("{:?}", result),
         }
     }
 }
@@ -0,0 +1,231 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{ED25519, Ed25519KeyPair, KeyPair, UnparsedPublicKey};
+use serde::{Deserialize, Serialize};
+use mtk_uartboot::digest::{Sha256, to_hex};
+use crate::mmap::Mapped;
+
+/// Start of a bundle, followed by the length of the manifest as a little-endian u32, the
+/// manifest, its Ed25519 signature and the contents of the files in manifest order.
+const MAGIC: &[u8; 8] = b"MTKPKG01";
+const SIGNATURE_LEN: usize = 64;
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    /// Board profile of the boot, one of the files
+    profile: Option<String>,
+    /// Pipeline run instead of a single boot, one of the files
+    pipeline: Option<String>,
+    #[serde(default)]
+    file: Vec<FileEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileEntry {
+    name: String,
+    size: usize,
+    sha256: String,
+}
+
+/// A verified bundle of images with the profile or pipeline booting a board with them,
+/// for stations without network access. The bundle is mapped into memory rather than read,
+/// bundles with images of several GB are no problem.
+pub struct Bundle {
+    manifest: Manifest,
+    data: Mapped,
+    /// Where the files are in data
+    files: Vec<Range<usize>>,
+    /// Hash of the signed manifest, naming the bundle
+    id: String,
+}
+
+fn read_hex_key(path: &str) -> Result<Vec<u8>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let text = text.trim();
+    if text.len() != 64 {
+        return Err(format!("{} isn't an Ed25519 public key in hex", path));
+    }
+    (0..text.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(|_| format!("{} isn't an Ed25519 public key in hex", path)))
+        .collect()
+}
+
+/// Writes a new signing key to path, and its public key in hex to path.pub
+pub fn keygen(path: &str) -> Result<(), String> {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).map_err(|_| "failed to generate a key")?;
+    let key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+    fs::write(path, pkcs8.as_ref()).map_err(|e| format!("{}: {}", path, e))?;
+    fs::write(format!("{}.pub", path), format!("{}\n", to_hex(key.public_key().as_ref())))
+        .map_err(|e| format!("{}.pub: {}", path, e))
+}
+
+/// Hex SHA-256 of the bytes in range of a mapped file
+fn sha256(file: &Mapped, range: Range<usize>) -> String {
+    let mut sha256 = Sha256::new();
+    file.chunks(range).for_each(|chunk| sha256.update(chunk));
+    to_hex(&sha256.finish())
+}
+
+/// Writes a bundle of the files, signed with the key from keygen. The profile or pipeline
+/// is added to the files, and they refer to the other files by their names.
+pub fn create(out: &str, profile: Option<&str>, pipeline: Option<&str>, files: &[String], key: &str) -> Result<(), String> {
+    let pkcs8 = fs::read(key).map_err(|e| format!("{}: {}", key, e))?;
+    let key = Ed25519KeyPair::from_pkcs8(&pkcs8).map_err(|_| format!("{} isn't a signing key from bundle keygen", key))?;
+
+    let name_of = |path: &str| Path::new(path).file_name().map(|n| n.to_string_lossy().into_owned());
+    let mut manifest = Manifest {
+        profile: profile.and_then(name_of),
+        pipeline: pipeline.and_then(name_of),
+        file: Vec::new(),
+    };
+    // The files are mapped and copied from there, a bundle is never held in memory.
+    let mut contents = Vec::new();
+    for path in files.iter().map(String::as_str).chain(profile).chain(pipeline) {
+        let name = name_of(path).ok_or_else(|| format!("invalid file name {}", path))?;
+        if manifest.file.iter().any(|f| f.name == name) {
+            return Err(format!("two files are named {}", name));
+        }
+        let file = Mapped::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        manifest.file.push(FileEntry { name, size: file.len(), sha256: sha256(&file, 0..file.len()) });
+        contents.push(file);
+    }
+
+    let manifest = toml::to_string(&manifest).unwrap();
+    let write = || -> std::io::Result<()> {
+        let mut bundle = BufWriter::new(File::create(out)?);
+        bundle.write_all(MAGIC)?;
+        bundle.write_all(&(manifest.len() as u32).to_le_bytes())?;
+        bundle.write_all(manifest.as_bytes())?;
+        bundle.write_all(key.sign(manifest.as_bytes()).as_ref())?;
+        for file in &contents {
+            for chunk in file.chunks(0..file.len()) {
+                bundle.write_all(chunk)?;
+            }
+        }
+        bundle.into_inner()?.sync_all()
+    };
+    write().map_err(|e| format!("{}: {}", out, e))
+}
+
+/// Creates a directory of the system temp directory named prefix and a random suffix, which
+/// didn't exist before and only this user may access, like mkdtemp
+fn private_dir(prefix: &str) -> std::io::Result<PathBuf> {
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    loop {
+        let mut suffix = [0u8; 8];
+        SystemRandom::new().fill(&mut suffix).map_err(|_| std::io::Error::other("no random numbers"))?;
+        let dir = std::env::temp_dir().join(format!("{}-{}", prefix, to_hex(&suffix)));
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl Bundle {
+    /// Reads a bundle and checks its signature with the public key, and the files against
+    /// the signed manifest.
+    pub fn open(path: &str, public_key: &str) -> Result<Bundle, String> {
+        let data = Mapped::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        if !data.starts_with(MAGIC) || data.len() < MAGIC.len() + 4 {
+            return Err(format!("{} isn't a bundle", path));
+        }
+        let len = u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize;
+        let manifest_end = 12usize.checked_add(len).filter(|&end| end + SIGNATURE_LEN <= data.len())
+            .ok_or_else(|| format!("{} is truncated", path))?;
+        let manifest = &data[12..manifest_end];
+        let signature = &data[manifest_end..manifest_end + SIGNATURE_LEN];
+        UnparsedPublicKey::new(&ED25519, read_hex_key(public_key)?)
+            .verify(manifest, signature)
+            .map_err(|_| format!("the signature of {} doesn't match the key {}", path, public_key))?;
+
+        let id = to_hex(&Sha256::digest(manifest))[..16].to_owned();
+        let manifest: Manifest = std::str::from_utf8(manifest).ok()
+            .and_then(|m| toml::from_str(m).ok())
+            .ok_or_else(|| format!("invalid manifest in {}", path))?;
+        let mut files = Vec::new();
+        let mut p = manifest_end + SIGNATURE_LEN;
+        for entry in &manifest.file {
+            if entry.name.is_empty() || entry.name.contains(['/', '\\']) || entry.name == ".." {
+                return Err(format!("invalid file name {} in {}", entry.name, path));
+            }
+            let range = p.checked_add(entry.size).filter(|&end| end <= data.len()).map(|end| p..end)
+                .ok_or_else(|| format!("{} is truncated", path))?;
+            if sha256(&data, range.clone()) != entry.sha256 {
+                return Err(format!("{} in {} doesn't match its hash", entry.name, path));
+            }
+            p = range.end;
+            files.push(range);
+        }
+        if manifest.profile.is_some() == manifest.pipeline.is_some() {
+            return Err(format!("{} needs either a profile or a pipeline", path));
+        }
+        Ok(Bundle { manifest, data, files, id })
+    }
+
+    /// Prints the contents
+    pub fn list(&self) {
+        for entry in &self.manifest.file {
+            println!("{:>10}  {}  {}", entry.size, &entry.sha256[..16], entry.name);
+        }
+    }
+
+    /// The profile of the boot, if the bundle has one
+    pub fn profile(&self) -> Option<String> {
+        let name = self.manifest.profile.as_ref()?;
+        self.manifest.file.iter().zip(&self.files)
+            .find(|(e, _)| e.name == *name)
+            .map(|(_, range)| String::from_utf8_lossy(&self.data[range.clone()]).into_owned())
+    }
+
+    /// The name of the pipeline, if the bundle has one
+    pub fn pipeline(&self) -> Option<&str> {
+        self.manifest.pipeline.as_deref()
+    }
+
+    /// Writes the files into a new directory of the system temp directory, which only this
+    /// user may access, and returns it. Nothing there is reused, files another user planted
+    /// could be booted instead of the verified ones.
+    pub fn extract(&self) -> Result<PathBuf, String> {
+        let dir = private_dir(&format!("mtk_uartboot-bundle-{}", self.id)).map_err(|e| format!("{}: {}", std::env::temp_dir().display(), e))?;
+        for (entry, range) in self.manifest.file.iter().zip(&self.files) {
+            let path = dir.join(&entry.name);
+            let write = || -> std::io::Result<()> {
+                let mut file = File::options().write(true).create_new(true).open(&path)?;
+                for chunk in self.data.chunks(range.clone()) {
+                    file.write_all(chunk)?;
+                }
+                Ok(())
+            };
+            write().map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        Ok(dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_directories_are_new_and_private() {
+        let first = private_dir("mtk_uartboot-bundle-test").unwrap();
+        let second = private_dir("mtk_uartboot-bundle-test").unwrap();
+        assert_ne!(first, second);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&first).unwrap().permissions().mode() & 0o777, 0o700);
+        }
+        fs::remove_dir(first).unwrap();
+        fs::remove_dir(second).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use mtk_uartboot::{Event, Stage};
+
+/// Where the calibrations are kept, set unless --no-calibration is given
+static PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// How long the BootROM handshake waits for each answer anyway
+const HANDSHAKE_WAIT: Duration = Duration::from_millis(10);
+/// Longest read timeout a calibration asks for, bridges slower than that need fixing
+const MAX_TIMEOUT: Duration = Duration::from_millis(250);
+/// Longest pause between writes a calibration asks for
+const MAX_GAP: Duration = Duration::from_millis(20);
+
+/// The timing an adapter needed in the runs which succeeded with it
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Calibration {
+    /// The slowest answer to the BootROM handshake
+    pub handshake_latency: Duration,
+    /// The pause after each write, raised on runs in which BL2 rejected packets
+    pub chunk_gap: Duration,
+    pub runs: u32,
+}
+
+impl Calibration {
+    /// The shortest read timeout: twice the handshake latency, so the answers of the next
+    /// handshake aren't missed if they're a bit slower still
+    pub fn min_timeout(&self) -> Duration {
+        (self.handshake_latency * 2).min(MAX_TIMEOUT)
+    }
+
+    /// Whether the port needs to be used differently than by default
+    pub fn is_relaxed(&self) -> bool {
+        self.min_timeout() > HANDSHAKE_WAIT || !self.chunk_gap.is_zero()
+    }
+
+    /// Takes the measurements of a successful run in. A slower handshake than before counts
+    /// right away, a faster one brings the latency down by a quarter at most, so a single
+    /// quick run doesn't undo what the slow ones taught.
+    fn update(&mut self, latency: Option<Duration>, rejected: u32) {
+        if let Some(latency) = latency {
+            self.handshake_latency = latency.max(self.handshake_latency * 3 / 4);
+        }
+        if rejected > 0 {
+            self.chunk_gap = (self.chunk_gap * 2).max(Duration::from_millis(1)).min(MAX_GAP);
+        }
+        self.runs += 1;
+    }
+
+    fn parse(fields: &str) -> Calibration {
+        let mut calibration = Calibration::default();
+        for (key, value) in fields.split('\t').filter_map(|f| f.split_once('=')) {
+            let Ok(value) = value.parse::<u64>() else { continue };
+            match key {
+                "handshake_latency_us" => calibration.handshake_latency = Duration::from_micros(value),
+                "chunk_gap_us" => calibration.chunk_gap = Duration::from_micros(value),
+                "runs" => calibration.runs = value as u32,
+                _ => {}
+            }
+        }
+        calibration
+    }
+}
+
+impl std::fmt::Display for Calibration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "reads wait at least {} ms, {} ms pause after each write",
+               self.min_timeout().max(HANDSHAKE_WAIT).as_millis(), self.chunk_gap.as_millis())
+    }
+}
+
+/// The calibrations are kept in mtk_uartboot/calibration in ~/.local/share
+fn default_path() -> PathBuf {
+    std::env::var_os("XDG_DATA_HOME").filter(|d| !d.is_empty()).map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("mtk_uartboot/calibration")
+}
+
+/// Keeps the calibration of the adapters from one run to the next
+pub fn enable() {
+    PATH.set(default_path()).ok();
+}
+
+/// Names the adapter behind a serial port: by USB IDs and serial number, so it's found under
+/// another port name too, or by the port for others. None for bridges over the network,
+/// whose timing depends on more than the adapter.
+fn adapter(port: &str) -> Option<String> {
+    if port.contains("://") {
+        return None;
+    }
+    let path = std::fs::canonicalize(port).unwrap_or_else(|_| PathBuf::from(port));
+    let usb = serialport::available_ports().unwrap_or_default().into_iter()
+        .filter(|p| std::fs::canonicalize(&p.port_name).is_ok_and(|p| p == path))
+        .find_map(|p| match p.port_type {
+            serialport::SerialPortType::UsbPort(usb) => Some(match usb.serial_number {
+                Some(serial) => format!("usb:{:04x}:{:04x}:{}", usb.vid, usb.pid, serial),
+                None => format!("usb:{:04x}:{:04x}", usb.vid, usb.pid),
+            }),
+            _ => None,
+        });
+    Some(usb.unwrap_or_else(|| path.display().to_string()))
+}
+
+/// The calibrations in the file at path, by adapter, a line of tab separated fields each
+fn load(path: &Path) -> BTreeMap<String, Calibration> {
+    let text = std::fs::read_to_string(path).unwrap_or_default();
+    text.lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(adapter, fields)| (adapter.to_owned(), Calibration::parse(fields)))
+        .collect()
+}
+
+/// The calibration of the adapter behind a serial port, if it was used before
+pub fn lookup(port: &str) -> Option<Calibration> {
+    let path = PATH.get()?;
+    load(path).remove(&adapter(port)?)
+}
+
+/// Updates the calibration of the adapter behind a serial port with a successful run, in
+/// which the handshake took latency and BL2 rejected packets. Returns the calibration.
+pub fn record(port: &str, latency: Option<Duration>, rejected: u32) -> std::io::Result<Option<Calibration>> {
+    let (Some(path), Some(adapter)) = (PATH.get(), adapter(port)) else { return Ok(None) };
+    let mut calibrations = load(path);
+    let calibration = calibrations.entry(adapter).or_default();
+    calibration.update(latency, rejected);
+    let calibration = *calibration;
+    let text: String = calibrations.iter()
+        .map(|(adapter, c)| format!("{}\thandshake_latency_us={}\tchunk_gap_us={}\truns={}\n", adapter,
+                                    c.handshake_latency.as_micros(), c.chunk_gap.as_micros(), c.runs))
+        .collect();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Written aside and renamed, so runs on other ports never read half a file.
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    std::fs::write(&tmp, text)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(Some(calibration))
+}
+
+/// Keeps the handshake latency of the BootROM for the calibration
+#[derive(Clone, Default)]
+pub struct Latency(Arc<Mutex<Option<Duration>>>);
+
+impl Latency {
+    pub fn observer(&self) -> impl FnMut(&Event) + Send + 'static {
+        let latency = self.0.clone();
+        move |event| {
+            if let Event::HandshakeLatency { stage: Stage::BootRom, latency: l } = event {
+                *latency.lock().unwrap() = Some(*l);
+            }
+        }
+    }
+
+    pub fn get(&self) -> Option<Duration> {
+        *self.0.lock().unwrap()
+    }
+}
@@ -0,0 +1,88 @@
+use mtk_uartboot::Chip;
+use mtk_uartboot::protocol::bl2::{ACK_VERSION, FLASH_INFO_VERSION, FLASH_RANGE_VERSION, FLASH_SWITCH_VERSION, FLASH_VERSION};
+use crate::report::Report;
+use crate::{Args, EXIT_DEVICE_ERROR, fail};
+
+/// Something the device can do or not, as the handshakes told, and the options needing it
+pub struct Capability {
+    /// Its name in the report, as caps.KEY
+    pub key: &'static str,
+    pub name: &'static str,
+    pub supported: bool,
+    /// Why the device can or can't, e.g. the SoC or the version of the protocol
+    pub source: String,
+    /// The options given which need it
+    pub needed_by: Vec<&'static str>,
+}
+
+impl Capability {
+    fn new(key: &'static str, name: &'static str, supported: bool, source: String) -> Capability {
+        Capability { key, name, supported, source, needed_by: Vec::new() }
+    }
+
+    /// Marks it as needed by option if that was given
+    fn needed_by(mut self, option: &'static str, given: bool) -> Capability {
+        if given {
+            self.needed_by.push(option);
+        }
+        self
+    }
+
+    fn is_missing(&self) -> bool {
+        !self.supported && !self.needed_by.is_empty()
+    }
+}
+
+/// What the BootROM can do on the SoC it's on. An unknown SoC is given the benefit of the
+/// doubt where it can't be told.
+pub fn bootrom(args: &Args, chip: Option<&Chip>) -> Vec<Capability> {
+    let soc = chip.map_or("unknown SoC".to_owned(), |c| c.name.to_owned());
+    vec![
+        Capability::new("aarch64", "start payloads in aarch64", chip.is_none_or(|c| c.aarch64), soc.clone())
+            .needed_by("--aarch64", args.aarch64),
+        Capability::new("watchdog", "disable the watchdog", chip.is_some(), soc)
+            .needed_by("--disable-watchdog", args.disable_watchdog),
+    ]
+}
+
+/// What BL2 can do by the version of the UART download protocol it speaks
+pub fn bl2(args: &Args, version: u8) -> Vec<Capability> {
+    let since = |key, name, first: u8| {
+        Capability::new(key, name, version >= first, format!("version {:#x}, needs {:#x}", version, first))
+    };
+    vec![
+        since("flash", "write images to storage", FLASH_VERSION).needed_by("flash --image", args.image.is_some()),
+        since("flash_changed", "write only the blocks which changed", FLASH_RANGE_VERSION)
+            .needed_by("--changed-only", args.changed_only),
+        since("flash_switch", "switch to an alternate partition", FLASH_SWITCH_VERSION),
+        since("flash_info", "tell the partition table", FLASH_INFO_VERSION),
+        since("ack_codes", "say why it refuses something", ACK_VERSION),
+    ]
+}
+
+/// The capabilities of a stage, those missing for an option given marked
+pub fn render(stage: &str, caps: &[Capability]) -> String {
+    let mut out = format!("Capabilities of the {}:\n", stage);
+    for cap in caps {
+        let mark = if cap.is_missing() { "MISSING" } else if cap.supported { "yes" } else { "no" };
+        out.push_str(&format!("  {:<36} {:<8} {}", cap.name, mark, cap.source));
+        if cap.is_missing() {
+            out.push_str(&format!(", needed by {}", cap.needed_by.join(", ")));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Records the capabilities of a stage in the report, and fails with the capability report
+/// if an option given needs one the device lacks, before anything is sent to it
+pub fn check(stage: &str, caps: &[Capability], report: &mut Report) {
+    for cap in caps {
+        report.set(&format!("caps.{}", cap.key), cap.supported);
+    }
+    let missing: Vec<&str> = caps.iter().filter(|c| c.is_missing()).flat_map(|c| c.needed_by.iter().copied()).collect();
+    if !missing.is_empty() {
+        fail(EXIT_DEVICE_ERROR, format!("The {} can't do what {} asks for, nothing was sent to it.\n{}",
+                                        stage, missing.join(", "), render(stage, caps).trim_end()));
+    }
+}
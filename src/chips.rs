@@ -0,0 +1,124 @@
+//! What's known about the SoCs, looked up by the hw code their BootROM reports.
+
+use std::time::Duration;
+use crate::types::LoadAddr;
+
+/// Defaults for booting one SoC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Chip {
+    pub hw_code: u16,
+    pub name: &'static str,
+    /// Where the BootROM is expected to load the DA, the first payload. Mediatek TF-A
+    /// builds BL2 for this address.
+    pub load_addr: LoadAddr,
+    /// Whether the payload runs in aarch64, started with [`jump_da64`](crate::BootRom::jump_da64)
+    pub aarch64: bool,
+    /// Address of the watchdog mode register, at the start of the TOPRGU block
+    pub watchdog: u32,
+    /// Base address of the UART the BootROM downloads over, a 16550 with 32-bit registers
+    pub uart: u32,
+    /// The address space as the BootROM download mode sees it, in order
+    pub memory: &'static [Region],
+}
+
+/// What a region of the address space is, for checking where payloads are loaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionKind {
+    /// The BootROM itself, which can't be written
+    Rom,
+    /// SRAM the BootROM uses for its stack and data while it's running
+    Reserved,
+    /// SRAM free for payloads
+    Sram,
+    /// Registers of the peripherals
+    Mmio,
+    /// DRAM, which is only usable once a payload set it up
+    Dram,
+}
+
+/// A range of addresses of a SoC
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub name: &'static str,
+    pub kind: RegionKind,
+    pub start: u32,
+    /// The last address, so regions can end at the top of the address space
+    pub last: u32,
+}
+
+impl Region {
+    pub fn contains(&self, addr: u32) -> bool {
+        (self.start..=self.last).contains(&addr)
+    }
+}
+
+impl Chip {
+    /// The region addr is in, None if nothing is mapped there
+    pub fn region(&self, addr: u32) -> Option<&'static Region> {
+        self.memory.iter().find(|r| r.contains(addr))
+    }
+}
+
+const fn region(name: &'static str, kind: RegionKind, start: u32, last: u32) -> Region {
+    Region { name, kind, start, last }
+}
+
+/// The layout of the Cortex-A53 and A73 based SoCs: the BootROM at 0, its stack and data at
+/// the start of SRAM, the SRAM the DA is loaded to from 0x200000, peripherals from
+/// 0x10000000 and DRAM from 0x40000000.
+const A64_MEMORY: &[Region] = &[
+    region("BootROM", RegionKind::Rom, 0x0000_0000, 0x0001_ffff),
+    region("BootROM stack and data", RegionKind::Reserved, 0x0010_0000, 0x0010_ffff),
+    region("SRAM", RegionKind::Sram, 0x0011_0000, 0x0011_ffff),
+    region("L2 SRAM", RegionKind::Sram, 0x0020_0000, 0x0023_ffff),
+    region("registers", RegionKind::Mmio, 0x1000_0000, 0x3fff_ffff),
+    region("DRAM", RegionKind::Dram, 0x4000_0000, 0xffff_ffff),
+];
+
+/// The layout of the Cortex-A7 based MT7623, with DRAM from 0x80000000
+const MT7623_MEMORY: &[Region] = &[
+    region("BootROM", RegionKind::Rom, 0x0000_0000, 0x0001_ffff),
+    region("BootROM stack and data", RegionKind::Reserved, 0x0010_0000, 0x0010_ffff),
+    region("SRAM", RegionKind::Sram, 0x0011_0000, 0x0011_ffff),
+    region("L2 SRAM", RegionKind::Sram, 0x0020_0000, 0x0023_ffff),
+    region("registers", RegionKind::Mmio, 0x1000_0000, 0x7fff_ffff),
+    region("DRAM", RegionKind::Dram, 0x8000_0000, 0xffff_ffff),
+];
+
+/// Value for the watchdog mode register disabling the watchdog. Writes need the key in the
+/// top byte.
+pub const WATCHDOG_DISABLE: u32 = 0x2200_0000;
+/// Offset of the timeout register of the watchdog from its mode register
+pub const WATCHDOG_LENGTH: u32 = 0x04;
+/// Enable bit of the watchdog mode register
+const WATCHDOG_MODE_ENABLE: u32 = 1;
+
+/// The timeout the watchdog mode and timeout registers set, None if it's disabled. The
+/// timeout counts units of 512 ticks of the 32 kHz clock, less one, from bit 5 on.
+pub fn watchdog_timeout(mode: u32, length: u32) -> Option<Duration> {
+    if mode & WATCHDOG_MODE_ENABLE == 0 {
+        return None;
+    }
+    let units = u64::from((length >> 5) & 0x7ff) + 1;
+    Some(Duration::from_micros(units * 15_625))
+}
+
+const CHIPS: &[Chip] = &[
+    Chip { hw_code: 0x7622, name: "MT7622", load_addr: LoadAddr(0x201000), aarch64: true, watchdog: 0x1021_2000, uart: 0x1100_2000, memory: A64_MEMORY },
+    Chip { hw_code: 0x7623, name: "MT7623", load_addr: LoadAddr(0x201000), aarch64: false, watchdog: 0x1000_7000, uart: 0x1100_4000, memory: MT7623_MEMORY },
+    Chip { hw_code: 0x7981, name: "MT7981", load_addr: LoadAddr(0x201000), aarch64: true, watchdog: 0x1001_c000, uart: 0x1100_2000, memory: A64_MEMORY },
+    Chip { hw_code: 0x7986, name: "MT7986", load_addr: LoadAddr(0x201000), aarch64: true, watchdog: 0x1001_c000, uart: 0x1100_2000, memory: A64_MEMORY },
+    Chip { hw_code: 0x7988, name: "MT7988", load_addr: LoadAddr(0x201000), aarch64: true, watchdog: 0x1001_c000, uart: 0x1100_0000, memory: A64_MEMORY },
+    Chip { hw_code: 0x8168, name: "MT8365/MT8168", load_addr: LoadAddr(0x201000), aarch64: true, watchdog: 0x1000_7000, uart: 0x1100_2000, memory: A64_MEMORY },
+];
+
+/// The SoC with this hw code, if it's a known one
+pub fn lookup(hw_code: u16) -> Option<&'static Chip> {
+    CHIPS.iter().find(|chip| chip.hw_code == hw_code)
+}
+
+/// All known SoCs
+pub fn all() -> &'static [Chip] {
+    CHIPS
+}
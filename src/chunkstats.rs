@@ -0,0 +1,81 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use mtk_uartboot::Event;
+use crate::report::Report;
+
+/// How one FIP packet went through
+struct Chunk {
+    idx: u32,
+    offset: usize,
+    len: usize,
+    /// Time from the end of the packet to the acknowledgement BL2 accepted it with
+    latency: Duration,
+    /// Times BL2 rejected the packet before
+    retries: u32,
+}
+
+#[derive(Default)]
+struct State {
+    chunks: Vec<Chunk>,
+    /// Rejections of the packet being sent
+    retries: u32,
+}
+
+/// Per-packet statistics of the FIP transfer, for -v and -vv. Shows where in the image the
+/// link degrades, e.g. only once a USB hub starts to drop bytes under sustained load.
+pub struct ChunkStats {
+    state: Arc<Mutex<State>>,
+    verbose: u8,
+}
+
+impl ChunkStats {
+    pub fn new(verbose: u8) -> ChunkStats {
+        ChunkStats { state: Arc::default(), verbose }
+    }
+
+    /// Returns an event observer recording every packet, and printing it with -vv
+    pub fn observer(&self) -> impl FnMut(&Event) + Send + 'static {
+        let (state, verbose) = (self.state.clone(), self.verbose);
+        move |event| {
+            let mut state = state.lock().unwrap();
+            match *event {
+                Event::PacketRejected { .. } => state.retries += 1,
+                Event::PacketAcked { idx, len, latency } => {
+                    let offset = state.chunks.last().map_or(0, |c| c.offset + c.len);
+                    let retries = std::mem::take(&mut state.retries);
+                    if verbose >= 2 {
+                        println!("Packet {} at {:#x}: {} bytes, ack after {:.1} ms, {} retries",
+                                 idx, offset, len, latency.as_secs_f64() * 1000.0, retries);
+                    }
+                    state.chunks.push(Chunk { idx, offset, len, latency, retries });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Prints a summary with -v and adds every packet to the report. Latencies differ from
+    /// run to run, so they are only reported when asked for.
+    pub fn finish(&self, report: &mut Report) {
+        let state = self.state.lock().unwrap();
+        if self.verbose == 0 || state.chunks.is_empty() {
+            return;
+        }
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let latencies = state.chunks.iter().map(|c| c.latency);
+        let (min, max) = (latencies.clone().min().unwrap(), latencies.clone().max().unwrap());
+        let avg = latencies.sum::<Duration>() / state.chunks.len() as u32;
+        let slowest = state.chunks.iter().max_by_key(|c| c.latency).unwrap();
+        let retried = state.chunks.iter().filter(|c| c.retries > 0).count();
+        println!("FIP packets: {}, ack latency {:.1}/{:.1}/{:.1} ms (min/avg/max), {} resent.",
+                 state.chunks.len(), ms(min), ms(avg), ms(max), retried);
+        println!("Slowest ack: packet {} at {:#x}.", slowest.idx, slowest.offset);
+        report.set("fip.ack_ms.min", format!("{:.1}", ms(min)));
+        report.set("fip.ack_ms.avg", format!("{:.1}", ms(avg)));
+        report.set("fip.ack_ms.max", format!("{:.1}", ms(max)));
+        for c in &state.chunks {
+            report.set(&format!("fip.chunk.{}", c.idx),
+                       format!("offset={:#x} len={} ack_ms={:.1} retries={}", c.offset, c.len, ms(c.latency), c.retries));
+        }
+    }
+}
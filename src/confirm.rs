@@ -0,0 +1,58 @@
+//! Confirmation of operations which modify device storage.
+//!
+//! Before anything is written or erased, callers describe the changes and show them to
+//! the user together with the identity of the device, so a wrong port or a wrong board
+//! is noticed before it's too late.
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+/// What is known about the device which is about to be modified.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceIdentity {
+    pub port: String,
+    pub hw_code: u16,
+    pub hw_sub_code: u16,
+    pub hw_ver: u16,
+    pub sw_ver: u16,
+}
+
+impl fmt::Display for DeviceIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (hw code {:#x}, hw sub code {:#x}, hw ver {:#x}, sw ver {:#x})",
+               self.port, self.hw_code, self.hw_sub_code, self.hw_ver, self.sw_ver)
+    }
+}
+
+/// One region that is going to be overwritten or erased.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    /// What happens, e.g. "write" or "erase"
+    pub action: String,
+    /// Partition name or storage device
+    pub target: String,
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} [{:#x}..{:#x}] ({} bytes)",
+               self.action, self.target, self.offset, self.offset + self.len, self.len)
+    }
+}
+
+/// Lists the changes and asks for "yes" on input. Anything else, including end of input,
+/// is a refusal.
+pub fn confirm<R: BufRead, W: Write>(device: &DeviceIdentity, changes: &[Change], input: &mut R, output: &mut W) -> io::Result<bool> {
+    writeln!(output, "The following changes will be made to {}:", device)?;
+    for change in changes {
+        writeln!(output, "  {}", change)?;
+    }
+    write!(output, "Type 'yes' to continue: ")?;
+    output.flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(answer.trim() == "yes")
+}
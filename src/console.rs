@@ -0,0 +1,904 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, IsTerminal, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use clap::ValueEnum;
+use regex::Regex;
+use serialport::{ClearBuffer, SerialPort};
+use mtk_uartboot::Transport;
+use mtk_uartboot::stream::StreamEvent;
+use crate::banners::Banners;
+use crate::excerpt::{Excerpt, Excerpts};
+use crate::mtklog::LogDecoder;
+use crate::output;
+use crate::symbols::Symbols;
+use mtk_uartboot::Sink;
+
+const HIGHLIGHT_START: &str = "\x1b[1;31m";
+const HIGHLIGHT_END: &str = "\x1b[0m";
+
+/// Longest chunk read from the console before it's processed without a newline.
+const MAX_LINE_LEN: u64 = 256;
+
+/// Longest part of a console line, shown in chunks, which patterns, --grep and --highlight
+/// are matched against. Beyond it only the end of the line is kept.
+const MAX_MATCH_LEN: usize = 16 << 10;
+
+/// Chunks of console data read ahead of the lines being processed at most, 8 MiB of the
+/// largest ones. Beyond it, the thread reading ahead waits for them to be processed.
+const READ_AHEAD_CHUNKS: usize = 2048;
+
+/// Largest chunk read ahead at once
+const READ_AHEAD_CHUNK_LEN: usize = 4096;
+
+/// How long the read ahead thread waits for data before checking whether the wait is over
+const READ_SLICE: Duration = Duration::from_millis(50);
+
+/// Decides which console lines are shown and which parts of them are colorized.
+pub struct LineFilter {
+    grep: Vec<Regex>,
+    highlight: Vec<Regex>,
+    color: bool,
+}
+
+impl LineFilter {
+    /// Highlights are only colorized if color is set and stdout is a terminal.
+    pub fn new(grep: &[String], highlight: &[String], color: bool) -> LineFilter {
+        LineFilter {
+            grep: grep.iter()
+                .map(|r| Regex::new(r).expect("invalid --grep expression."))
+                .collect(),
+            highlight: highlight.iter()
+                .map(|r| Regex::new(r).expect("invalid --highlight expression."))
+                .collect(),
+            color: color && std::io::stdout().is_terminal(),
+        }
+    }
+
+    /// Whether it filters or colorizes lines, so it needs them whole
+    fn is_active(&self) -> bool {
+        !self.grep.is_empty() || (self.color && !self.highlight.is_empty())
+    }
+
+    /// Returns the line as it should be printed, or None if it's filtered out.
+    pub fn render(&self, line: &str) -> Option<String> {
+        if !self.grep.is_empty() && !self.grep.iter().any(|r| r.is_match(line)) {
+            return None;
+        }
+
+        if !self.color || self.highlight.is_empty() {
+            return Some(line.to_owned());
+        }
+
+        // Collect the matched ranges of all expressions, then merge overlaps
+        // so that nested escape sequences aren't produced.
+        let mut ranges: Vec<(usize, usize)> = self.highlight.iter()
+            .flat_map(|r| r.find_iter(line).map(|m| (m.start(), m.end())))
+            .filter(|(s, e)| s != e)
+            .collect();
+        ranges.sort();
+
+        let mut out = String::with_capacity(line.len());
+        let mut p = 0;
+        for (start, end) in ranges {
+            if end <= p {
+                continue;
+            }
+            let start = start.max(p);
+            out.push_str(&line[p..start]);
+            out.push_str(HIGHLIGHT_START);
+            out.push_str(&line[start..end]);
+            out.push_str(HIGHLIGHT_END);
+            p = end;
+        }
+        out.push_str(&line[p..]);
+        Some(out)
+    }
+}
+
+/// How non-text console data is presented.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BinaryView {
+    /// Print it like text, replacing invalid characters
+    Lossy,
+    /// Print a hexdump of it
+    Hexdump,
+}
+
+/// Where ANSI escape sequences, e.g. the colors of some U-Boot builds, are removed from the
+/// console output. The --console terminal always keeps them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum StripAnsi {
+    /// From the sinks, and from stdout when it isn't a terminal
+    Auto,
+    /// From the sinks and stdout
+    Always,
+    /// Nowhere
+    Never,
+}
+
+/// Removes ANSI escape sequences from text: CSI ones like colors and cursor movement, OSC
+/// ones like window titles, and those of two characters.
+fn strip_ansi(text: &str) -> Cow<'_, str> {
+    if !text.contains('\x1b') {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            // Parameters up to the final character
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // A string up to BEL or ESC \
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// Everything needed to present console output to the user.
+pub struct ConsoleView {
+    filter: LineFilter,
+    binary_view: BinaryView,
+    binary_out: Option<File>,
+    binary_offset: usize,
+    binary_run: usize,
+    in_binary: bool,
+    /// Data read after the line the last wait stopped at, for the next wait
+    pending: Vec<u8>,
+    /// The last line ended with CRs at the end of the data read, a LF coming next belongs to it
+    after_cr: bool,
+    symbols: Option<Symbols>,
+    banners: Banners,
+    excerpts: Option<Excerpts>,
+    /// Decodes the prefixes of known log formats, for printing the lines in columns
+    log: Option<LogDecoder>,
+    /// Whether escape sequences are removed from what's printed and from the sink lines
+    strip_shown: bool,
+    strip_logged: bool,
+    /// Where the console lines are sent besides the terminal
+    sinks: Vec<Box<dyn Sink>>,
+    /// The start of a line not emitted as an event yet, its end not read
+    unfinished: String,
+    /// The start of a line not printed yet, held for the filter until its end is read
+    held: String,
+}
+
+impl ConsoleView {
+    pub fn new(filter: LineFilter, binary_view: BinaryView, binary_out: Option<&str>) -> ConsoleView {
+        ConsoleView {
+            filter,
+            binary_view,
+            binary_out: binary_out.map(|p| File::create(p).expect("failed to create binary output file.")),
+            binary_offset: 0,
+            binary_run: 0,
+            in_binary: false,
+            pending: Vec::new(),
+            after_cr: false,
+            symbols: None,
+            banners: Banners::new(),
+            excerpts: None,
+            log: None,
+            sinks: Vec::new(),
+            strip_shown: !std::io::stdout().is_terminal(),
+            strip_logged: true,
+            unfinished: String::new(),
+            held: String::new(),
+        }
+    }
+
+    /// Sets where ANSI escape sequences are removed, they always are for the banners and
+    /// excerpts
+    pub fn set_strip_ansi(&mut self, strip: StripAnsi) {
+        self.strip_shown = match strip {
+            StripAnsi::Auto => !std::io::stdout().is_terminal(),
+            StripAnsi::Always => true,
+            StripAnsi::Never => false,
+        };
+        self.strip_logged = strip != StripAnsi::Never;
+    }
+
+    /// Names the code addresses in crash dumps after these symbols
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = Some(symbols);
+    }
+
+    /// Keeps the console lines around those matching the patterns of excerpts for the report
+    pub fn set_excerpts(&mut self, excerpts: Excerpts) {
+        self.excerpts = Some(excerpts);
+    }
+
+    /// Prints the lines of known log formats in columns and counts what the modules logged
+    pub fn set_log_decoder(&mut self, decoder: LogDecoder) {
+        self.log = Some(decoder);
+    }
+
+    /// Sends the console lines to sink too, unfiltered
+    pub fn add_sink(&mut self, sink: Box<dyn Sink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Tags the lines sent to the sinks from now on with the serial port they came from
+    pub fn set_serial(&mut self, serial: &str) {
+        for sink in &mut self.sinks {
+            sink.set_serial(serial);
+        }
+    }
+
+    /// The versions of the boot stages printed on the console since the last call
+    pub fn firmware_versions(&mut self) -> Vec<(&'static str, String)> {
+        self.banners.take()
+    }
+
+    /// The excerpts of the console found since the last call, and how many more matches
+    /// there were
+    pub fn excerpts(&mut self) -> (Vec<Excerpt>, usize) {
+        self.excerpts.as_mut().map_or((Vec::new(), 0), Excerpts::take)
+    }
+
+    /// The counts of the decoded log lines since the last call, for the report
+    pub fn log_counts(&mut self) -> Vec<(String, String)> {
+        self.log.as_mut().map_or(Vec::new(), LogDecoder::take)
+    }
+
+    fn is_text(buf: &[u8]) -> bool {
+        let text = match std::str::from_utf8(buf) {
+            Ok(t) => t,
+            // A multi-byte character cut off at the end of a chunk is still text.
+            Err(e) if e.error_len().is_none() => match std::str::from_utf8(&buf[..e.valid_up_to()]) {
+                Ok(t) => t,
+                Err(_) => return false,
+            },
+            Err(_) => return false,
+        };
+        text.chars().all(|c| !c.is_control() || matches!(c, '\t' | '\r' | '\n' | '\x1b' | '\x08' | '\x07'))
+    }
+
+    fn show_text(&mut self, line: &str) {
+        if self.in_binary {
+            self.end_binary();
+        }
+        let clean = strip_ansi(line);
+        self.emit_lines(&clean);
+        for l in lines(&clean) {
+            self.banners.scan(l);
+            if let Some(excerpts) = &mut self.excerpts {
+                excerpts.scan(l);
+            }
+            if let Some(log) = &mut self.log {
+                log.scan(l);
+            }
+        }
+        for l in lines(if self.strip_logged { &clean } else { line }) {
+            for sink in &mut self.sinks {
+                sink.line(l);
+            }
+        }
+        let decoded;
+        let line = match &self.log {
+            // The columns would be off with escape sequences in the prefixes.
+            Some(log) => {
+                decoded = clean.split_inclusive('\n').map(|l| {
+                    let text = l.trim_end_matches(['\r', '\n']);
+                    log.pretty(text) + &l[text.len()..]
+                }).collect::<String>();
+                decoded.as_str()
+            }
+            None if self.strip_shown => &clean,
+            None => line,
+        };
+        let annotated;
+        let line = match &self.symbols {
+            Some(symbols) => {
+                annotated = symbols.annotate(line);
+                &annotated
+            }
+            None => line,
+        };
+        if let Some(line) = self.render(line) {
+            print!("{}", line);
+        }
+    }
+
+    /// What to print of the text shown, with the filter applied to whole lines. The start of
+    /// a line is held until its end is read or it's longer than MAX_MATCH_LEN.
+    fn render(&mut self, text: &str) -> Option<String> {
+        if !self.filter.is_active() {
+            return Some(text.to_owned());
+        }
+        self.held.push_str(text);
+        if !self.held.ends_with('\n') && self.held.len() < MAX_MATCH_LEN {
+            return None;
+        }
+        self.filter.render(&std::mem::take(&mut self.held))
+    }
+
+    fn end_binary(&mut self) {
+        if self.binary_out.is_some() {
+            println!("[{} bytes of binary data written to file]", self.binary_run);
+        }
+        self.binary_run = 0;
+        self.in_binary = false;
+    }
+
+    fn show_binary(&mut self, buf: &[u8]) {
+        self.in_binary = true;
+        if let Some(f) = &mut self.binary_out {
+            f.write_all(buf).expect("failed to write binary output file.");
+        } else if self.binary_view == BinaryView::Hexdump {
+            for (i, row) in buf.chunks(16).enumerate() {
+                println!("{}", hexdump_row(self.binary_offset + i * 16, row));
+            }
+        } else {
+            print!("{}", String::from_utf8_lossy(buf));
+        }
+        self.binary_offset += buf.len();
+        self.binary_run += buf.len();
+    }
+
+    /// Emits the lines text completes as events. CRs at the end are kept until the next
+    /// data, a LF after them belongs to the same line.
+    fn emit_lines(&mut self, text: &str) {
+        self.unfinished.push_str(text);
+        while let Some((end, false)) = line_end(self.unfinished.as_bytes()) {
+            let line: String = self.unfinished.drain(..end).collect();
+            output::emit(StreamEvent::ConsoleLine { line: line.trim_end_matches(['\r', '\n']).to_owned() });
+        }
+    }
+
+    /// Shows a raw chunk read from the console
+    pub fn show(&mut self, buf: &[u8]) {
+        if ConsoleView::is_text(buf) {
+            self.show_text(&String::from_utf8_lossy(buf));
+        } else {
+            self.show_binary(buf);
+        }
+    }
+}
+
+/// Where the first line in buf ends: after a LF, or after CRs, which BootROMs and
+/// preloaders end lines with, together with a LF following them. Also whether the line
+/// ended with CRs at the end of buf, when a LF may still be on its way.
+fn line_end(buf: &[u8]) -> Option<(usize, bool)> {
+    let i = buf.iter().position(|&b| b == b'\n' || b == b'\r')?;
+    let mut end = i + 1;
+    if buf[i] == b'\r' {
+        while end < buf.len() && buf[end] == b'\r' {
+            end += 1;
+        }
+        if end == buf.len() {
+            return Some((end, true));
+        }
+        if buf[end] == b'\n' {
+            end += 1;
+        }
+    }
+    Some((end, false))
+}
+
+/// The lines of console text, without their line endings, whichever they are
+fn lines(text: &str) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let mut rest = text;
+    while let Some((end, _)) = line_end(rest.as_bytes()) {
+        lines.push(rest[..end].trim_end_matches(['\r', '\n']));
+        rest = &rest[end..];
+    }
+    if !rest.is_empty() {
+        lines.push(rest);
+    }
+    lines
+}
+
+/// Replaces the ending of a complete line with a LF, so lines ended by CRs don't overwrite
+/// each other on the terminal.
+fn normalize_line_end(line: &mut Vec<u8>) {
+    if matches!(line.last(), Some(b'\r' | b'\n')) {
+        while matches!(line.last(), Some(b'\r' | b'\n')) {
+            line.pop();
+        }
+        line.push(b'\n');
+    }
+}
+
+/// Reads a console line into line like read_until(b'\n', ...) of at most MAX_LINE_LEN bytes,
+/// but also taking CRs as line endings. A timeout with part of a line read fails, but
+/// leaves that part in line.
+fn read_line(reader: &mut impl BufRead, line: &mut Vec<u8>, after_cr: &mut bool) -> io::Result<()> {
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        if std::mem::take(after_cr) && buf[0] == b'\n' {
+            reader.consume(1);
+            continue;
+        }
+        let buf = &buf[..buf.len().min(MAX_LINE_LEN as usize - line.len())];
+        if let Some((end, at_buf_end)) = line_end(buf) {
+            line.extend_from_slice(&buf[..end]);
+            reader.consume(end);
+            *after_cr = at_buf_end;
+            return Ok(());
+        }
+        let n = buf.len();
+        line.extend_from_slice(buf);
+        reader.consume(n);
+        if line.len() as u64 >= MAX_LINE_LEN {
+            return Ok(());
+        }
+    }
+}
+
+/// Adds a chunk of a console line to what's kept of it for matching, the end of the line
+/// up to MAX_MATCH_LEN
+fn keep_for_matching(kept: &mut Vec<u8>, chunk: &[u8]) {
+    kept.extend_from_slice(chunk);
+    if kept.len() > MAX_MATCH_LEN {
+        kept.drain(..kept.len() - MAX_MATCH_LEN);
+    }
+}
+
+fn hexdump_row(offset: usize, row: &[u8]) -> String {
+    let hex: Vec<String> = row.iter().map(|b| format!("{:02x}", b)).collect();
+    let ascii: String = row.iter()
+        .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+        .collect();
+    format!("{:08x}  {:<47}  |{}|", offset, hex.join(" "), ascii)
+}
+
+/// The console as read ahead by a thread, in chunks as they were read
+struct ReadAhead {
+    rx: Receiver<io::Result<Vec<u8>>>,
+    buf: Vec<u8>,
+    /// How long reads wait for data
+    poll: Duration,
+}
+
+impl Read for ReadAhead {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.buf.is_empty() {
+            self.buf = match self.rx.recv_timeout(self.poll) {
+                Ok(data) => data?,
+                // The thread only ends on errors, which were passed on already.
+                Err(_) => return Err(io::ErrorKind::TimedOut.into()),
+            };
+        }
+        let n = out.len().min(self.buf.len());
+        out[..n].copy_from_slice(&self.buf[..n]);
+        self.buf.drain(..n);
+        Ok(n)
+    }
+}
+
+/// Runs wait with the console of port, read by a thread of its own meanwhile. Filters,
+/// patterns and sinks slower than the device then don't leave its output in the kernel
+/// until the buffers of the serial driver overflow. Output coming faster than wait takes it
+/// holds the thread back once READ_AHEAD_CHUNKS are waiting, nothing is dropped. Reads of
+/// wait fail with `TimedOut` after poll without data, the timeout of the port if None. What
+/// the thread read beyond what wait took is kept for the next wait.
+fn read_ahead<R>(port: &mut dyn Transport, view: &mut ConsoleView, poll: Option<Duration>, wait: impl FnOnce(&mut dyn Read, &mut ConsoleView) -> R) -> R {
+    // Transports which can't tell their timeout can't be read in slices, they're read
+    // directly.
+    let Some(timeout) = port.read_timeout() else {
+        if let Some(poll) = poll {
+            port.set_timeout(poll).ok();
+        }
+        return wait(port, view);
+    };
+    if port.set_timeout(READ_SLICE).is_err() {
+        return wait(port, view);
+    }
+    let stop = AtomicBool::new(false);
+    let (tx, rx) = mpsc::sync_channel(READ_AHEAD_CHUNKS);
+    let mut input = ReadAhead { rx, buf: Vec::new(), poll: poll.unwrap_or(timeout) };
+    let result = std::thread::scope(|s| {
+        let reader = &mut *port;
+        let reader = s.spawn(|| {
+            let mut chunk = vec![0u8; READ_AHEAD_CHUNK_LEN];
+            while !stop.load(Ordering::Relaxed) {
+                match reader.read(&mut chunk) {
+                    Ok(0) => {}
+                    Ok(n) => {
+                        if tx.send(Ok(chunk[..n].to_vec())).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                    Err(e) => {
+                        tx.send(Err(e)).ok();
+                        break;
+                    }
+                }
+            }
+        });
+        let result = wait(&mut input, view);
+        stop.store(true, Ordering::Relaxed);
+        view.pending.append(&mut input.buf);
+        // The thread may be held back by a full channel, what it read is kept until it ends.
+        while !reader.is_finished() {
+            if let Ok(Ok(data)) = input.rx.recv_timeout(READ_SLICE) {
+                view.pending.extend_from_slice(&data);
+            }
+        }
+        result
+    });
+    port.set_timeout(timeout).ok();
+    while let Ok(Ok(data)) = input.rx.try_recv() {
+        view.pending.extend_from_slice(&data);
+    }
+    result
+}
+
+/// Prints console output until a line for which found returns true shows up and returns
+/// that line.
+fn wait_until<F: FnMut(&str) -> bool>(port: &mut dyn Transport, view: &mut ConsoleView, mut found: F) -> Option<String> {
+    println!("==================================");
+    let ret = read_ahead(port, view, None, |input, view| {
+        let pending = Cursor::new(std::mem::take(&mut view.pending));
+        let mut reader = BufReader::new(pending.chain(input));
+        let mut uart_line: Vec<u8> = Vec::new();
+        // The line the chunks read belong to, long lines are read in several
+        let mut line: Vec<u8> = Vec::new();
+        let mut after_cr = view.after_cr;
+        let mut ret = None;
+        loop {
+            let res = read_line(&mut reader, &mut uart_line, &mut after_cr);
+            // A timeout with data pending means a line without a newline, e.g. a shell prompt.
+            if res.is_err() && uart_line.is_empty() {
+                break;
+            }
+            normalize_line_end(&mut uart_line);
+            view.show(&uart_line);
+            keep_for_matching(&mut line, &uart_line);
+            let text = String::from_utf8_lossy(&line);
+            if found(&text) {
+                ret = Some(text.into_owned());
+                break;
+            }
+            if res.is_err() {
+                break;
+            }
+            if uart_line.ends_with(b"\n") {
+                line.clear();
+            }
+            uart_line.clear();
+        }
+        // Keep what was read past the matching line, it belongs to the next wait.
+        let mut rest = reader.buffer().to_vec();
+        let (pending, _) = reader.into_inner().into_inner();
+        rest.extend_from_slice(&pending.get_ref()[pending.position() as usize..]);
+        view.pending = rest;
+        view.after_cr = after_cr;
+        ret
+    });
+
+    if view.in_binary {
+        view.end_binary();
+    }
+    println!("==================================");
+    if ret.is_none() {
+        println!("Timeout waiting for specified message.");
+    }
+    ret
+}
+
+/// Prints console output until a line containing the pattern shows up and returns that line.
+pub fn wait_for_match(port: &mut dyn Transport, pattern: &str, view: &mut ConsoleView) -> Option<String> {
+    wait_until(port, view, |line| line.contains(pattern))
+}
+
+/// Like wait_for_match, but for several patterns. Returns the index of the one found.
+#[cfg(feature = "pipeline")]
+pub fn wait_for_any(port: &mut dyn Transport, patterns: &[&str], view: &mut ConsoleView) -> Option<usize> {
+    let line = wait_until(port, view, |line| patterns.iter().any(|p| line.contains(p)))?;
+    patterns.iter().position(|p| line.contains(p))
+}
+
+/// Like wait_for_any, but gives up at the deadline even while the console keeps printing,
+/// e.g. a device stuck in a boot loop. Silence ends the wait after the timeout of the port.
+pub fn wait_for_any_until(port: &mut dyn Transport, patterns: &[&str], deadline: Instant, view: &mut ConsoleView) -> Option<usize> {
+    let line = wait_until(port, view, |line| Instant::now() >= deadline || patterns.iter().any(|p| line.contains(p)))?;
+    patterns.iter().position(|p| line.contains(p))
+}
+
+/// Like wait_for_any, but also matches a line the device hasn't finished, e.g. a login or
+/// shell prompt waiting for input, as soon as it's printed. Gives up after timeout of silence.
+/// Returns the index of the prompt found and the console output up to it.
+#[cfg(feature = "pipeline")]
+pub fn wait_for_prompt(port: &mut dyn Transport, prompts: &[&str], timeout: Duration, view: &mut ConsoleView) -> io::Result<Option<(usize, String)>> {
+    let mut output = String::new();
+    println!("==================================");
+    let found = read_ahead(port, view, Some(Duration::from_millis(100)), |input, view| {
+        let mut buf = std::mem::take(&mut view.pending);
+        let mut chunk = [0u8; MAX_LINE_LEN as usize];
+        let mut last_data = Instant::now();
+        // The start of the line already shown, when it was longer than a chunk
+        let mut line_start: Vec<u8> = Vec::new();
+        let found = 'wait: loop {
+            if view.after_cr && !buf.is_empty() {
+                view.after_cr = false;
+                if buf[0] == b'\n' {
+                    buf.remove(0);
+                }
+            }
+            while let Some((end, at_buf_end)) = line_end(&buf) {
+                let mut line: Vec<u8> = buf.drain(..end).collect();
+                view.after_cr = at_buf_end;
+                normalize_line_end(&mut line);
+                view.show(&line);
+                output += &String::from_utf8_lossy(&line);
+                keep_for_matching(&mut line_start, &line);
+                let whole = String::from_utf8_lossy(&line_start).into_owned();
+                line_start.clear();
+                if let Some(i) = prompts.iter().position(|p| whole.contains(p)) {
+                    break 'wait Some(i);
+                }
+            }
+            let partial = String::from_utf8_lossy(&buf).into_owned();
+            let mut whole = line_start.clone();
+            keep_for_matching(&mut whole, &buf);
+            if let Some(i) = prompts.iter().position(|p| String::from_utf8_lossy(&whole).contains(p)) {
+                view.show(&std::mem::take(&mut buf));
+                println!();
+                output += &partial;
+                break Some(i);
+            }
+            if buf.len() as u64 >= MAX_LINE_LEN {
+                view.show(&buf);
+                output += &partial;
+                line_start = whole;
+                buf.clear();
+            }
+            match input.read(&mut chunk) {
+                Ok(n) => {
+                    buf.extend_from_slice(&chunk[..n]);
+                    last_data = Instant::now();
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    if last_data.elapsed() >= timeout {
+                        if !buf.is_empty() {
+                            view.show(&std::mem::take(&mut buf));
+                            println!();
+                        }
+                        break None;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        // What came after the prompt belongs to the next wait.
+        view.pending = buf;
+        Ok(found)
+    })?;
+    if view.in_binary {
+        view.end_binary();
+    }
+    println!("==================================");
+    if found.is_none() {
+        println!("Timeout waiting for specified message.");
+    }
+    Ok(found.map(|i| (i, output)))
+}
+
+/// Like wait_for_match, but for a line matching a regex.
+#[cfg(feature = "pipeline")]
+pub fn wait_for_regex(port: &mut dyn Transport, re: &Regex, view: &mut ConsoleView) -> Option<String> {
+    wait_until(port, view, |line| re.is_match(line))
+}
+
+pub fn wait_for_line(port: &mut dyn Transport, pattern: &str, view: &mut ConsoleView) -> bool {
+    wait_for_match(port, pattern, view).is_some()
+}
+
+/// A console UART separate from the download UART.
+///
+/// It's read in the background from the moment it's opened, so what the device prints there
+/// while the download protocol runs on the other port is kept for the next wait.
+pub struct ConsolePort {
+    port: Box<dyn SerialPort>,
+    rx: Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+    timeout: Duration,
+    stop: Arc<AtomicBool>,
+}
+
+impl ConsolePort {
+    pub fn open(name: &str, baudrate: u32) -> ConsolePort {
+        println!("Using console port: {}", name);
+        let port = serialport::new(name, baudrate)
+            .timeout(Duration::from_millis(100))
+            .open().expect("Failed to open console port");
+        let mut reader = port.try_clone().expect("Failed to open console port");
+        let (tx, rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let reader_stop = stop.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 256];
+            while !reader_stop.load(Ordering::Relaxed) {
+                match reader.read(&mut buf) {
+                    Ok(0) => {}
+                    Ok(n) => {
+                        if tx.send(buf[..n].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                    Err(_) => break,
+                }
+            }
+        });
+        ConsolePort {
+            port,
+            rx,
+            buf: Vec::new(),
+            timeout: Duration::from_secs(2),
+            stop,
+        }
+    }
+}
+
+impl Drop for ConsolePort {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Read for ConsolePort {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.buf.is_empty() {
+            self.buf = match self.rx.recv_timeout(self.timeout) {
+                Ok(data) => data,
+                Err(RecvTimeoutError::Timeout) => return Err(io::ErrorKind::TimedOut.into()),
+                Err(RecvTimeoutError::Disconnected) => return Err(io::ErrorKind::BrokenPipe.into()),
+            };
+        }
+        let n = out.len().min(self.buf.len());
+        out[..n].copy_from_slice(&self.buf[..n]);
+        self.buf.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for ConsolePort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl Transport for ConsolePort {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn read_timeout(&self) -> Option<Duration> {
+        Some(self.timeout)
+    }
+
+    fn set_baud_rate(&mut self, baudrate: u32) -> io::Result<()> {
+        Ok(SerialPort::set_baud_rate(self.port.as_mut(), baudrate)?)
+    }
+
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.port.clear(ClearBuffer::Input)?;
+        self.buf.clear();
+        while self.rx.try_recv().is_ok() {}
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_filtered_whole() {
+        let filter = LineFilter::new(&["needle".to_owned()], &[], false);
+        let mut view = ConsoleView::new(filter, BinaryView::Lossy, None);
+        let start = "x".repeat(MAX_LINE_LEN as usize);
+        assert_eq!(view.render(&start), None);
+        assert_eq!(view.render("needle\n"), Some(format!("{}needle\n", start)));
+        assert_eq!(view.render(&start), None);
+        assert_eq!(view.render("hay\n"), None);
+    }
+
+    /// A device printing len bytes as fast as they're read
+    struct Flood {
+        len: usize,
+    }
+
+    impl Read for Flood {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.len);
+            if n == 0 {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            buf[..n].fill(b'x');
+            self.len -= n;
+            Ok(n)
+        }
+    }
+
+    impl Write for Flood {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for Flood {
+        fn set_timeout(&mut self, _timeout: Duration) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn read_timeout(&self) -> Option<Duration> {
+            Some(Duration::from_secs(1))
+        }
+
+        fn set_baud_rate(&mut self, _baudrate: u32) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn clear_input(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn output_beyond_the_read_ahead_is_kept() {
+        let len = READ_AHEAD_CHUNKS * READ_AHEAD_CHUNK_LEN + 12345;
+        let mut port = Flood { len };
+        let mut view = ConsoleView::new(LineFilter::new(&[], &[], false), BinaryView::Lossy, None);
+        let taken = read_ahead(&mut port, &mut view, None, |input, _| {
+            // Slower than the device, the channel fills up meanwhile.
+            std::thread::sleep(Duration::from_millis(300));
+            let mut buf = [0; 10];
+            input.read_exact(&mut buf).unwrap();
+            buf.len()
+        });
+        // What wasn't read ahead is still waiting at the device.
+        assert_eq!(taken + view.pending.len() + port.len, len);
+        assert!(view.pending.len() >= READ_AHEAD_CHUNKS * READ_AHEAD_CHUNK_LEN - taken);
+    }
+
+    #[test]
+    fn kept_line_is_bounded_to_its_end() {
+        let mut kept = Vec::new();
+        keep_for_matching(&mut kept, &vec![b'x'; MAX_MATCH_LEN]);
+        keep_for_matching(&mut kept, b"login:");
+        assert_eq!(kept.len(), MAX_MATCH_LEN);
+        assert!(kept.ends_with(b"xlogin:"));
+    }
+}
@@ -0,0 +1,502 @@
+use std::net::ToSocketAddrs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use mtk_uartboot::Session;
+use serde_yaml::Mapping;
+use crate::audit::AuditLog;
+use crate::explain;
+use crate::http::{self, Request, Response};
+use crate::metrics::{Metrics, Outcome};
+use crate::monitor::{list_ports, parse_profile};
+use crate::queue::{Job, JobState, Queue};
+use crate::report::{Report, json_string};
+use crate::tokens::{Role, Tokens};
+use crate::{Args, Failure, boot_with_report, console_view, guarded, print_event, resolve_serial, try_open_serial};
+use crate::{EXIT_DEVICE_ERROR, EXIT_FAILED, EXIT_NO_ANSWER, EXIT_NO_PORT, EXIT_POLICY};
+
+/// How the daemon runs, from the command line.
+pub struct Config {
+    /// Address the API listens on
+    pub listen: String,
+    /// Directory keeping the job queue
+    pub state_dir: String,
+    /// URL a JSON summary of every finished job is POSTed to
+    pub webhook: Option<String>,
+    /// File with the API tokens
+    pub tokens: Option<String>,
+    /// Whether submitters may write to the storage of devices, from --yes
+    pub yes: bool,
+}
+
+/// What the API and the scheduler share.
+struct Daemon {
+    config: Config,
+    queue: Mutex<Queue>,
+    metrics: Metrics,
+    audit: AuditLog,
+    /// None if the API is open
+    tokens: Option<Tokens>,
+    /// End of the maintenance window, if the station is in maintenance
+    maintenance: Mutex<Option<Instant>>,
+}
+
+impl Daemon {
+    /// The time left of the maintenance window, if the station is in maintenance. No jobs are
+    /// submitted or started then, running jobs finish.
+    fn maintenance_left(&self) -> Option<Duration> {
+        let until = (*self.maintenance.lock().unwrap())?;
+        until.checked_duration_since(Instant::now())
+    }
+}
+
+/// Report entries of a finished job which go into the audit log
+const AUDITED: &[&str] = &[
+    "device.hw_code", "device.hw_sub_code", "device.hw_ver", "device.sw_ver",
+    "payload.file", "payload.sha256", "fip.file", "fip.sha256",
+    "flash.image", "flash.target", "flash.alternate", "flash.len", "flash.sha256", "flash.crc32",
+];
+
+/// How often the queue is checked for jobs whose port became free or came online
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Length of a maintenance window if none is given
+const DEFAULT_MAINTENANCE_MINUTES: u64 = 30;
+
+fn is_online(ports: &std::collections::HashSet<String>, port: &str) -> bool {
+    let port = resolve_serial(port);
+    ports.contains(&port) || Path::new(&port).exists()
+}
+
+/// The outcome of a job the tool gave up, by the class of its exit status
+fn failure_outcome(failure: &Failure) -> Outcome {
+    match failure.exit_code {
+        EXIT_FAILED | EXIT_POLICY => Outcome::Failed,
+        EXIT_DEVICE_ERROR | EXIT_NO_ANSWER | EXIT_NO_PORT => Outcome::DeviceError,
+        _ => Outcome::InternalError,
+    }
+}
+
+/// Boots the device of a job. Also returns the report of what was sent, up to where it
+/// failed.
+fn boot_job(args: &Args, port: &str, metrics: &Metrics) -> (Outcome, Report) {
+    let mut report = Report::new(args.report_times);
+    let (serial, transport) = match try_open_serial(Some(port)) {
+        Ok(opened) => opened,
+        Err(failure) => {
+            println!("Error: {}", failure.msg);
+            return (failure_outcome(&failure), report);
+        }
+    };
+    // The panic hook has printed the error of a boot given up, e.g. for a missing image.
+    let outcome = guarded(|| {
+        let mut view = console_view(args, None);
+        let mut session = Session::new(transport);
+        let mut observer = metrics.observer();
+        session.on_event(move |event| {
+            print_event(event);
+            observer(event);
+        });
+        match boot_with_report(args, &mut session, &mut view, &serial, None, &mut report) {
+            Ok(true) => Outcome::Ok,
+            Ok(false) => Outcome::Failed,
+            Err(e) => {
+                println!("Error: {}", e);
+                explain::print_hint(&e);
+                Outcome::DeviceError
+            }
+        }
+    });
+    (outcome.unwrap_or_else(|failure| failure_outcome(&failure)), report)
+}
+
+/// POSTs the summary of a finished job to the webhook. The text field makes it show up as a
+/// message in Slack and compatible chat webhooks.
+fn notify(url: &str, job: &Job, duration: Duration) {
+    let text = format!("Job {} on {}: {}", job.id, job.port, job.state.name());
+    let body = format!("{{\"text\":{},\"job\":{},\"duration_s\":{:.1}}}",
+                       json_string(&text), job.to_json(), duration.as_secs_f64());
+    let result = ureq::post(url)
+        .set("Content-Type", "application/json")
+        .send_string(&body);
+    // Best effort, a chat being down doesn't fail the job.
+    if let Err(e) = result {
+        println!("Job {}: webhook failed: {}", job.id, e);
+    }
+}
+
+/// Starts pending jobs as their ports become free, each on a thread of its own.
+fn schedule(daemon: Arc<Daemon>) {
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        if daemon.maintenance_left().is_some() {
+            continue;
+        }
+        let ports = list_ports();
+        while let Some(job) = daemon.queue.lock().unwrap().start_next(|p| is_online(&ports, p)) {
+            println!("Job {}: booting {}.", job.id, job.port);
+            let daemon = daemon.clone();
+            std::thread::spawn(move || {
+                let start = Instant::now();
+                // Checked when the job was submitted, again as the daemon may have been
+                // restarted without --yes meanwhile.
+                let (outcome, report) = match parse_profile(&job.profile, daemon.config.yes) {
+                    Ok(args) => boot_job(&args, &job.port, &daemon.metrics),
+                    Err(e) => {
+                        println!("Job {}: invalid profile: {}", job.id, e);
+                        (Outcome::InternalError, Report::default())
+                    }
+                };
+                println!("Job {}: {}", job.id, if outcome.is_ok() { "ok" } else { "FAILED" });
+                daemon.metrics.job_done(outcome, start.elapsed());
+                let job = daemon.queue.lock().unwrap().finish(job.id, outcome.is_ok());
+                let mut details = vec![("outcome", outcome.class().to_owned())];
+                details.extend(AUDITED.iter().filter_map(|&key| Some((key, report.get(key)?.to_owned()))));
+                daemon.audit.record("finished", &job, &job.submitted_by, &details);
+                if let Some(url) = &daemon.config.webhook {
+                    notify(url, &job, start.elapsed());
+                }
+            });
+        }
+    }
+}
+
+fn job_id(path: &str) -> Option<u64> {
+    path.strip_prefix("/jobs/")?.parse().ok()
+}
+
+/// The profile keys a submitter may use: what is booted and how, and what is watched on the
+/// console. Everything else, e.g. keys running commands on the station, writing files on it
+/// or driving its GPIOs and power switches, needs the admin role.
+const SUBMIT_KEYS: &[&str] = &[
+    "payload", "load_addr", "load", "entry", "boot_args", "param_block", "param_word",
+    "next_payload", "next_aarch32", "next_marker", "next_boot_args", "verify", "payload_sig_len",
+    "auth", "cert", "aarch64", "aarch32", "disable_watchdog", "fip", "bl31_log_level", "dtbo",
+    "dtb_entry", "patch", "fip_patch", "replace", "patch_string", "patch_csv", "patch_row",
+    "handshake_timeout", "brom_load_baudrate", "bl2_load_baudrate", "max_rate", "write_timeout_ms",
+    "no_calibration", "digest_engine", "verbose", "quiet", "read_only", "dry_run", "deterministic",
+    "packet_len", "report_times", "verify_sig", "grep", "highlight", "excerpt", "excerpt_context",
+    "strip_ansi", "decode_log", "binary_view", "symbols", "wait_boot", "wait_boot_timeout",
+    "exit_on_idle", "exit_on_pattern", "ymodem", "ymodem_cmd", "prompt", "bl2_running",
+];
+
+/// The profile keys writing to the storage of the device, which anyone, admins too, may only
+/// use if the daemon was started with --yes
+const FLASH_KEYS: &[&str] = &["image", "partition", "offset", "changed_only", "block_len", "two_phase"];
+
+/// The keys of a profile the role may not use, with what they need. Writing to the storage
+/// needs flash.
+fn forbidden_keys(profile: &Mapping, role: Role, flash: bool) -> Vec<(String, &'static str)> {
+    profile.keys()
+        .map(|key| key.as_str().map_or_else(|| format!("{:?}", key), |k| k.replace('-', "_")))
+        .filter_map(|key| {
+            let needs = if FLASH_KEYS.contains(&key.as_str()) {
+                (!flash).then_some("--yes on the daemon")
+            } else {
+                (role < Role::Admin && !SUBMIT_KEYS.contains(&key.as_str())).then_some("the admin role")
+            };
+            Some((key, needs?))
+        })
+        .collect()
+}
+
+/// Checks the bearer token of a request, if the API needs one, and answers it. Without
+/// tokens, requests are made by the client address with every role, which is only allowed
+/// on a loopback address.
+fn handle(daemon: &Daemon, request: &Request) -> Response {
+    let (by, role) = match &daemon.tokens {
+        None => (request.peer.clone(), Role::Admin),
+        Some(tokens) => {
+            let token = request.header("authorization").and_then(|h| h.strip_prefix("Bearer "));
+            match token.and_then(|t| tokens.check(t)) {
+                Some((name, role)) => (name.to_owned(), role),
+                None => return Response::error(401, "a valid API token is needed"),
+            }
+        }
+    };
+    let needed = if request.method == "GET" { Role::Read } else { Role::Submit };
+    if role < needed {
+        return Response::error(403, &format!("this needs the {} role", needed.name()));
+    }
+    route(daemon, request, &by, role)
+}
+
+/// The maintenance state as a JSON object. The station is drained once no jobs are running.
+fn maintenance_json(daemon: &Daemon) -> String {
+    let running = daemon.queue.lock().unwrap().jobs().iter().filter(|j| j.state == JobState::Running).count();
+    let left = daemon.maintenance_left();
+    format!("{{\"maintenance\":{},\"remaining_s\":{},\"running\":{}}}",
+            left.is_some(), left.map_or(0, |l| l.as_secs()), running)
+}
+
+/// The end of a maintenance window of minutes started at now, None if it's beyond what the
+/// clock can tell
+fn maintenance_end(now: Instant, minutes: u64) -> Option<Instant> {
+    now.checked_add(Duration::from_secs(minutes.checked_mul(60)?))
+}
+
+fn route(daemon: &Daemon, request: &Request, by: &str, role: Role) -> Response {
+    let queue = &daemon.queue;
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/maintenance") => Response::json(200, maintenance_json(daemon)),
+        (_, "/maintenance") if role < Role::Admin =>
+            Response::error(403, "maintenance needs the admin role"),
+        ("POST", "/maintenance") => {
+            let minutes = match request.query("minutes").map(str::parse).transpose() {
+                Ok(m) => m.unwrap_or(DEFAULT_MAINTENANCE_MINUTES),
+                Err(_) => return Response::error(400, "invalid minutes"),
+            };
+            let Some(until) = maintenance_end(Instant::now(), minutes) else {
+                return Response::error(400, "invalid minutes");
+            };
+            *daemon.maintenance.lock().unwrap() = Some(until);
+            println!("Maintenance for {} minutes, started by {}.", minutes, by);
+            Response::json(200, maintenance_json(daemon))
+        }
+        ("DELETE", "/maintenance") => {
+            *daemon.maintenance.lock().unwrap() = None;
+            println!("Maintenance ended by {}.", by);
+            Response::json(200, maintenance_json(daemon))
+        }
+        ("GET", "/metrics") => {
+            let jobs = queue.lock().unwrap().jobs().to_vec();
+            let queued: Vec<(&str, usize)> = [JobState::Pending, JobState::Running].into_iter()
+                .map(|state| (state.name(), jobs.iter().filter(|j| j.state == state).count()))
+                .collect();
+            Response { status: 200, content_type: "text/plain; version=0.0.4", body: daemon.metrics.render(&queued) }
+        }
+        ("GET", "/jobs") => {
+            let jobs: Vec<String> = queue.lock().unwrap().jobs().iter().map(|j| j.to_json()).collect();
+            Response::json(200, format!("{{\"jobs\":[{}]}}", jobs.join(",")))
+        }
+        ("POST", "/jobs") => {
+            if let Some(left) = daemon.maintenance_left() {
+                return Response::error(503, &format!("the station is in maintenance for another {} s", left.as_secs()));
+            }
+            let Some(port) = request.query("port") else {
+                return Response::error(400, "the port parameter is missing");
+            };
+            let priority = match request.query("priority").map(str::parse).transpose() {
+                Ok(p) => p.unwrap_or(0),
+                Err(_) => return Response::error(400, "invalid priority"),
+            };
+            let profile = String::from_utf8_lossy(&request.body).into_owned();
+            let keys = match toml::from_str(&profile) {
+                Ok(keys) => keys,
+                Err(e) => return Response::error(400, &format!("invalid profile: {}", e)),
+            };
+            let forbidden: Vec<String> = forbidden_keys(&keys, role, daemon.config.yes).into_iter()
+                .map(|(key, needs)| format!("{} needs {}", key, needs))
+                .collect();
+            if !forbidden.is_empty() {
+                return Response::error(403, &forbidden.join(", "));
+            }
+            // The job runs with nobody to confirm it writes to the storage.
+            if let Err(e) = parse_profile(&profile, daemon.config.yes) {
+                return Response::error(400, &format!("invalid profile: {}", e));
+            }
+            let job = queue.lock().unwrap().submit(port, priority, by, profile);
+            daemon.audit.record("submitted", &job, by, &[("priority", priority.to_string())]);
+            Response::json(201, format!("{{\"id\":{}}}", job.id))
+        }
+        (method, path) if path.starts_with("/jobs/") => {
+            let Some(id) = job_id(path) else {
+                return Response::error(404, "no such job");
+            };
+            let mut queue = queue.lock().unwrap();
+            match method {
+                "GET" => match queue.get(id) {
+                    Some(job) => Response::json(200, job.to_json()),
+                    None => Response::error(404, "no such job"),
+                },
+                "DELETE" if role < Role::Admin && queue.get(id).is_some_and(|j| j.submitted_by != by) =>
+                    Response::error(403, "only admins may cancel jobs of others"),
+                "DELETE" => match queue.cancel(id) {
+                    Ok(()) => {
+                        let job = queue.get(id).unwrap();
+                        daemon.audit.record("cancelled", job, by, &[]);
+                        Response::json(200, job.to_json())
+                    }
+                    Err(e) if queue.get(id).is_none() => Response::error(404, &e),
+                    Err(e) => Response::error(409, &e),
+                },
+                _ => Response::error(405, "method not allowed"),
+            }
+        }
+        _ => Response::error(404, "not found"),
+    }
+}
+
+/// Whether every address listen resolves to is a loopback one, only reachable from this host
+fn is_loopback(listen: &str) -> bool {
+    listen.to_socket_addrs().is_ok_and(|mut addrs| addrs.all(|a| a.ip().is_loopback()))
+}
+
+/// Runs jobs submitted over the HTTP API on the serial ports of this station. Runs until
+/// killed.
+pub fn run(config: Config) {
+    // Without tokens everyone is an admin, who may run commands on the station.
+    if config.tokens.is_none() && !is_loopback(&config.listen) {
+        panic!("--listen {} is reachable from other hosts, which needs --tokens.", config.listen);
+    }
+    let state_dir = Path::new(&config.state_dir);
+    let (queue, interrupted) = Queue::open(state_dir);
+    let audit = AuditLog::open(&state_dir.join("audit.log"));
+    for job in &interrupted {
+        audit.record("interrupted", job, &job.submitted_by, &[]);
+    }
+    let tokens = config.tokens.as_deref().map(Tokens::load);
+    let listen = config.listen.clone();
+    let daemon = Arc::new(Daemon {
+        config,
+        queue: Mutex::new(queue),
+        metrics: Metrics::default(),
+        audit,
+        tokens,
+        maintenance: Mutex::new(None),
+    });
+    let scheduler = daemon.clone();
+    std::thread::spawn(move || schedule(scheduler));
+    println!("Daemon API listening on {}.", listen);
+    http::serve(&listen, move |request| handle(&daemon, request))
+        .unwrap_or_else(|e| panic!("failed to listen on {}: {}", listen, e));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_given_up_have_an_outcome() {
+        let args = parse_profile("payload = \"bl2.bin\"", false).unwrap();
+        let (outcome, _) = boot_job(&args, "/nonexistent/ttyUSB9", &Metrics::default());
+        assert_eq!(outcome, Outcome::DeviceError);
+        let outcome = guarded(|| -> Outcome { panic!("failed to open payload") }).unwrap_or_else(|f| failure_outcome(&f));
+        assert_eq!(outcome, Outcome::InternalError);
+        let outcome = guarded(|| -> Outcome { crate::fail(EXIT_POLICY, "denied".to_owned()) }).unwrap_or_else(|f| failure_outcome(&f));
+        assert_eq!(outcome, Outcome::Failed);
+    }
+
+    #[test]
+    fn storage_writes_need_yes_for_every_role() {
+        let profile: Mapping = toml::from_str("payload = \"bl2.bin\"\nimage = \"sysupgrade.bin\"").unwrap();
+        for role in [Role::Submit, Role::Admin] {
+            assert_eq!(forbidden_keys(&profile, role, false), [("image".to_owned(), "--yes on the daemon")]);
+            assert!(forbidden_keys(&profile, role, true).is_empty());
+        }
+    }
+
+    #[test]
+    fn keys_outside_the_submit_ones_need_the_admin_role() {
+        let profile: Mapping = toml::from_str("payload = \"bl2.bin\"\nwait-boot = \"login:\"\n\
+                                               post_boot_hook = \"./notify.sh\"\npower_cmd = \"relay off\"").unwrap();
+        for role in [Role::Read, Role::Submit] {
+            assert_eq!(forbidden_keys(&profile, role, false),
+                       [("post_boot_hook".to_owned(), "the admin role"), ("power_cmd".to_owned(), "the admin role")]);
+        }
+        assert!(forbidden_keys(&profile, Role::Admin, false).is_empty());
+        let profile: Mapping = serde_yaml::from_str("1: x").unwrap();
+        assert_eq!(forbidden_keys(&profile, Role::Submit, true), [("Number(1)".to_owned(), "the admin role")]);
+        assert!(forbidden_keys(&profile, Role::Admin, true).is_empty());
+    }
+
+    fn daemon(test: &str, yes: bool) -> Daemon {
+        let dir = std::env::temp_dir().join(format!("mtk_uartboot-daemon-test-{}-{}", std::process::id(), test));
+        std::fs::remove_dir_all(&dir).ok();
+        let tokens = ["read", "submit", "admin"].iter()
+            .map(|role| format!("[[token]]\nname = \"{0}er\"\nrole = \"{0}\"\nsha256 = \"{1}\"\n",
+                                role, mtk_uartboot::digest::to_hex(&mtk_uartboot::digest::Sha256::digest(role.as_bytes()))))
+            .collect::<String>();
+        let path = dir.with_extension("toml");
+        std::fs::write(&path, tokens).unwrap();
+        let (queue, _) = Queue::open(&dir);
+        Daemon {
+            config: Config { listen: "127.0.0.1:0".to_owned(), state_dir: dir.display().to_string(), webhook: None, tokens: None, yes },
+            queue: Mutex::new(queue),
+            metrics: Metrics::default(),
+            audit: AuditLog::open(&dir.join("audit.log")),
+            tokens: Some(Tokens::load(path.to_str().unwrap())),
+            maintenance: Mutex::new(None),
+        }
+    }
+
+    /// The status of a request made with the token, which is also the name of its role
+    fn status(daemon: &Daemon, token: Option<&str>, method: &str, target: &str, body: &str) -> u16 {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let request = Request {
+            peer: "127.0.0.1".to_owned(),
+            method: method.to_owned(),
+            path: path.to_owned(),
+            query: query.split('&').filter_map(|p| p.split_once('=')).map(|(n, v)| (n.to_owned(), v.to_owned())).collect(),
+            headers: token.map(|t| ("authorization".to_owned(), format!("Bearer {}", t))).into_iter().collect(),
+            body: body.as_bytes().to_vec(),
+        };
+        handle(daemon, &request).status
+    }
+
+    #[test]
+    fn requests_need_a_known_token() {
+        let daemon = daemon("tokens", false);
+        assert_eq!(status(&daemon, None, "GET", "/jobs", ""), 401);
+        assert_eq!(status(&daemon, Some("guess"), "GET", "/jobs", ""), 401);
+        assert_eq!(status(&daemon, Some("read"), "GET", "/jobs", ""), 200);
+    }
+
+    #[test]
+    fn each_role_does_what_it_may() {
+        let daemon = daemon("roles", false);
+        let boot = "payload = \"bl2.bin\"";
+        let hook = "payload = \"bl2.bin\"\npost_boot_hook = \"./notify.sh\"";
+        for (token, method, target, body, expected) in [
+            ("read", "GET", "/jobs", "", 200),
+            ("read", "GET", "/metrics", "", 200),
+            ("read", "GET", "/maintenance", "", 200),
+            ("read", "POST", "/jobs?port=/dev/ttyUSB0", boot, 403),
+            ("submit", "POST", "/jobs?port=/dev/ttyUSB0", boot, 201),
+            ("submit", "POST", "/jobs?port=/dev/ttyUSB0", hook, 403),
+            ("admin", "POST", "/jobs?port=/dev/ttyUSB0", hook, 201),
+            ("read", "DELETE", "/jobs/1", "", 403),
+            // Jobs of others
+            ("submit", "DELETE", "/jobs/2", "", 403),
+            ("admin", "DELETE", "/jobs/2", "", 200),
+            ("submit", "DELETE", "/jobs/1", "", 200),
+            ("submit", "POST", "/maintenance", "", 403),
+            ("submit", "DELETE", "/maintenance", "", 403),
+            ("admin", "POST", "/maintenance?minutes=5", "", 200),
+            ("admin", "POST", "/jobs?port=/dev/ttyUSB0", boot, 503),
+            ("admin", "DELETE", "/maintenance", "", 200),
+        ] {
+            assert_eq!(status(&daemon, Some(token), method, target, body), expected, "{} {} {}", token, method, target);
+        }
+    }
+
+    #[test]
+    fn submitted_profiles_are_checked() {
+        let daemon = daemon("profiles", false);
+        let flash = "payload = \"bl2.bin\"\nimage = \"sysupgrade.bin\"\npartition = \"ubi\"";
+        assert_eq!(status(&daemon, Some("admin"), "POST", "/jobs?port=/dev/ttyUSB0", flash), 403);
+        assert_eq!(status(&daemon, Some("submit"), "POST", "/jobs?port=/dev/ttyUSB0", "payload = "), 400);
+        assert_eq!(status(&daemon, Some("submit"), "POST", "/jobs?port=/dev/ttyUSB0&priority=high", "payload = \"bl2.bin\""), 400);
+        assert_eq!(status(&daemon, Some("submit"), "POST", "/jobs", "payload = \"bl2.bin\""), 400);
+        assert!(daemon.queue.lock().unwrap().jobs().is_empty());
+        let daemon = self::daemon("profiles-yes", true);
+        assert_eq!(status(&daemon, Some("submit"), "POST", "/jobs?port=/dev/ttyUSB0", flash), 201);
+    }
+
+    #[test]
+    fn maintenance_windows_beyond_the_clock_are_refused() {
+        let now = Instant::now();
+        assert_eq!(maintenance_end(now, 30), Some(now + Duration::from_secs(1800)));
+        assert_eq!(maintenance_end(now, u64::MAX), None);
+        assert_eq!(maintenance_end(now, u64::MAX / 60), None);
+    }
+
+    #[test]
+    fn only_loopback_addresses_are_local() {
+        assert!(is_loopback("127.0.0.1:8700"));
+        assert!(is_loopback("[::1]:8700"));
+        assert!(!is_loopback("0.0.0.0:8700"));
+        assert!(!is_loopback("10.0.4.17:8700"));
+        assert!(!is_loopback("not an address"));
+    }
+}
@@ -0,0 +1,322 @@
+//! SHA-256, used to identify the exact bytes sent to a device, and CRC-32, which BL2 checks
+//! images written to storage with.
+//!
+//! Both are computed by an [`Engine`]. [`Portable`] is plain Rust and works everywhere; with
+//! the `accel` feature, [`Accelerated`] uses the SHA and CRC instructions of the host CPU. The
+//! first engine of [`engines`] the CPU can run is used, unless [`set_engine`] picks another
+//! one, which may also be an engine of the caller's own.
+
+use std::sync::RwLock;
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// A running SHA-256 computation of an engine
+pub trait Hasher: Send {
+    fn update(&mut self, data: &[u8]);
+    fn finish(self: Box<Self>) -> [u8; 32];
+    fn box_clone(&self) -> Box<dyn Hasher>;
+}
+
+/// An implementation of the digests and checksums
+pub trait Engine: Sync {
+    /// Name to pick it by, e.g. with --digest-engine
+    fn name(&self) -> &'static str;
+    /// Whether the host CPU has the instructions it's fast with
+    fn available(&self) -> bool;
+    fn sha256(&self) -> Box<dyn Hasher>;
+    /// CRC-32 as in zlib, continuing from crc, 0 to start
+    fn crc32(&self, crc: u32, data: &[u8]) -> u32;
+}
+
+/// Plain Rust, the same speed on every host
+pub struct Portable;
+
+impl Engine for Portable {
+    fn name(&self) -> &'static str {
+        "portable"
+    }
+
+    fn available(&self) -> bool {
+        true
+    }
+
+    fn sha256(&self) -> Box<dyn Hasher> {
+        Box::new(PortableSha256::new())
+    }
+
+    fn crc32(&self, crc: u32, data: &[u8]) -> u32 {
+        crate::protocol::bl2::crc32(crc, data)
+    }
+}
+
+/// SHA-256 from the sha2 crate and CRC-32 from crc32fast, which use the SHA extensions and
+/// carry-less multiplication of x86 and the SHA-2 and CRC instructions of ARMv8. Both check
+/// for them at runtime and fall back to portable code themselves.
+#[cfg(feature = "accel")]
+pub struct Accelerated;
+
+#[cfg(feature = "accel")]
+impl Hasher for sha2::Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> [u8; 32] {
+        sha2::Digest::finalize(*self).into()
+    }
+
+    fn box_clone(&self) -> Box<dyn Hasher> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(feature = "accel")]
+impl Engine for Accelerated {
+    fn name(&self) -> &'static str {
+        "accelerated"
+    }
+
+    fn available(&self) -> bool {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        return std::is_x86_feature_detected!("sha") && std::is_x86_feature_detected!("pclmulqdq");
+        #[cfg(target_arch = "aarch64")]
+        return cfg!(feature = "accel-asm") && std::arch::is_aarch64_feature_detected!("sha2")
+            && std::arch::is_aarch64_feature_detected!("crc");
+        #[allow(unreachable_code)]
+        false
+    }
+
+    fn sha256(&self) -> Box<dyn Hasher> {
+        Box::new(<sha2::Sha256 as sha2::Digest>::new())
+    }
+
+    fn crc32(&self, crc: u32, data: &[u8]) -> u32 {
+        let mut hasher = crc32fast::Hasher::new_with_initial(crc);
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+/// The built-in engines, fastest first
+pub fn engines() -> &'static [&'static dyn Engine] {
+    &[#[cfg(feature = "accel")] &Accelerated, &Portable]
+}
+
+/// The engine picked with set_engine, None for the first available one
+static ENGINE: RwLock<Option<&'static dyn Engine>> = RwLock::new(None);
+
+/// Computes the digests with engine from now on, whether the CPU has what it's fast with or not
+pub fn set_engine(engine: &'static dyn Engine) {
+    *ENGINE.write().unwrap() = Some(engine);
+}
+
+/// The engine digests are computed with
+pub fn engine() -> &'static dyn Engine {
+    ENGINE.read().unwrap()
+        .unwrap_or_else(|| *engines().iter().find(|e| e.available()).unwrap())
+}
+
+/// The built-in engine of this name
+pub fn find(name: &str) -> Option<&'static dyn Engine> {
+    engines().iter().copied().find(|e| e.name() == name)
+}
+
+/// CRC-32 with the current engine
+pub fn crc32(crc: u32, data: &[u8]) -> u32 {
+    engine().crc32(crc, data)
+}
+
+/// SHA-256 with the engine current when it's created
+pub struct Sha256 {
+    hasher: Box<dyn Hasher>,
+}
+
+impl Sha256 {
+    pub fn new() -> Sha256 {
+        Sha256 { hasher: engine().sha256() }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    pub fn finish(self) -> [u8; 32] {
+        self.hasher.finish()
+    }
+
+    /// Hashes a whole buffer
+    pub fn digest(data: &[u8]) -> [u8; 32] {
+        let mut h = Sha256::new();
+        h.update(data);
+        h.finish()
+    }
+}
+
+impl Clone for Sha256 {
+    fn clone(&self) -> Sha256 {
+        Sha256 { hasher: self.hasher.box_clone() }
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Sha256 {
+        Sha256::new()
+    }
+}
+
+#[derive(Clone)]
+struct PortableSha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl PortableSha256 {
+    fn new() -> PortableSha256 {
+        PortableSha256 {
+            state: H0,
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    fn compress(state: &mut [u32; 8], block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        while !data.is_empty() {
+            let n = (64 - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + n].copy_from_slice(&data[..n]);
+            self.block_len += n;
+            data = &data[n..];
+            if self.block_len == 64 {
+                PortableSha256::compress(&mut self.state, &self.block);
+                self.block_len = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; 32] {
+        let bit_len = self.total_len * 8;
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bit_len.to_be_bytes());
+
+        let mut out = [0; 32];
+        for (o, s) in out.chunks_mut(4).zip(self.state) {
+            o.copy_from_slice(&s.to_be_bytes());
+        }
+        out
+    }
+}
+
+impl Hasher for PortableSha256 {
+    fn update(&mut self, data: &[u8]) {
+        PortableSha256::update(self, data);
+    }
+
+    fn finish(self: Box<Self>) -> [u8; 32] {
+        PortableSha256::finish(*self)
+    }
+
+    fn box_clone(&self) -> Box<dyn Hasher> {
+        Box::new(self.clone())
+    }
+}
+
+/// Formats a digest as lowercase hex
+pub fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn portable(data: &[u8]) -> String {
+        let mut hasher = Portable.sha256();
+        hasher.update(data);
+        to_hex(&hasher.finish())
+    }
+
+    #[test]
+    fn portable_sha256_matches_fips_180_4() {
+        assert_eq!(portable(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(portable(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(portable(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+                   "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+        assert_eq!(portable(&vec![b'a'; 1_000_000]), "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+    }
+
+    #[test]
+    fn portable_sha256_of_data_given_in_pieces() {
+        let data: Vec<u8> = (0..1000).map(|i| (i * 31 % 256) as u8).collect();
+        let mut hasher = Portable.sha256();
+        for piece in data.chunks(7) {
+            hasher.update(piece);
+        }
+        assert_eq!(to_hex(&hasher.finish()), portable(&data));
+    }
+
+    #[cfg(feature = "accel")]
+    #[test]
+    fn engines_agree() {
+        // Every length up to a few blocks, to cover where the padding starts and ends.
+        let data: Vec<u8> = (0..300).map(|i| (i * 7 + 3) as u8).collect();
+        for len in 0..data.len() {
+            let mut accelerated = Accelerated.sha256();
+            accelerated.update(&data[..len]);
+            assert_eq!(to_hex(&accelerated.finish()), portable(&data[..len]), "{} bytes", len);
+            assert_eq!(Accelerated.crc32(0, &data[..len]), Portable.crc32(0, &data[..len]), "{} bytes", len);
+        }
+    }
+}
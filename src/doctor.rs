@@ -0,0 +1,208 @@
+use std::io::IsTerminal;
+use std::time::{Duration, Instant};
+use mtk_uartboot::bootrom::TargetConfig;
+use mtk_uartboot::stream::StreamEvent;
+use mtk_uartboot::{Error, Session, chips};
+use crate::report::Report;
+use crate::{EXIT_DEVICE_ERROR, EXIT_SECURED, device_exit_code, explain, output};
+
+/// The checks by their key in the report and their name, in the order they're done
+const CHECKS: [(&str, &str); 6] = [
+    ("lock", "port lock"),
+    ("open", "port open"),
+    ("handshake", "handshake"),
+    ("hw_info", "hardware info"),
+    ("target_config", "target config"),
+    ("sram", "SRAM reads"),
+];
+
+/// Bytes of SRAM each of --sram-reads reads
+const SRAM_READ_LEN: u32 = 1024;
+
+const RESET_STYLE: &str = "\x1b[0m";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    /// Works, but a boot needs more than the defaults
+    Warn,
+    Fail,
+    /// Not done, because an earlier check failed or it wasn't asked for
+    Skip,
+}
+
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Status::Pass => "pass",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+            Status::Skip => "skip",
+        }
+    }
+
+    fn style(self) -> &'static str {
+        match self {
+            Status::Pass => "\x1b[32m",
+            Status::Warn => "\x1b[33m",
+            Status::Fail => "\x1b[1;31m",
+            Status::Skip => "",
+        }
+    }
+}
+
+/// The outcome of the checks of `doctor`, each printed as a line of the checklist when it's
+/// done
+pub struct Checklist {
+    done: Vec<(&'static str, Status)>,
+    /// The exit status of the first check which failed, 0 while none did
+    exit_code: i32,
+    /// The error talking to the device which failed a check, for its hint
+    error: Option<Error>,
+    color: bool,
+}
+
+impl Checklist {
+    pub fn new() -> Checklist {
+        Checklist { done: Vec::new(), exit_code: 0, error: None, color: std::io::stdout().is_terminal() }
+    }
+
+    fn add(&mut self, key: &'static str, status: Status, detail: &str) {
+        let name = CHECKS.iter().find(|(k, _)| *k == key).map_or(key, |(_, name)| name);
+        let mark = format!("{:<4}", status.name().to_uppercase());
+        if self.color && status != Status::Skip {
+            println!("  {}{}{}  {:<13}  {}", status.style(), mark, RESET_STYLE, name, detail);
+        } else {
+            println!("  {}  {:<13}  {}", mark, name, detail);
+        }
+        output::emit(StreamEvent::Check { name: key.to_owned(), status: status.name().to_owned(), detail: detail.to_owned() });
+        self.done.push((key, status));
+    }
+
+    pub fn pass(&mut self, key: &'static str, detail: &str) {
+        self.add(key, Status::Pass, detail);
+    }
+
+    pub fn warn(&mut self, key: &'static str, detail: &str) {
+        self.add(key, Status::Warn, detail);
+    }
+
+    pub fn skip(&mut self, key: &'static str, detail: &str) {
+        self.add(key, Status::Skip, detail);
+    }
+
+    /// Fails a check, the run exits with exit_code unless an earlier check failed already
+    pub fn fail(&mut self, key: &'static str, detail: &str, exit_code: i32) {
+        self.add(key, Status::Fail, detail);
+        if self.exit_code == 0 {
+            self.exit_code = exit_code;
+        }
+    }
+
+    /// Fails a check with an error talking to the device
+    pub fn error(&mut self, key: &'static str, e: Error) {
+        self.fail(key, &e.to_string(), device_exit_code(&e));
+        self.error.get_or_insert(e);
+    }
+
+    /// Skips the checks not done, records them all in the report and prints the verdict
+    /// with the hint for the error which failed a check. Returns the exit status.
+    pub fn finish(mut self, report: &mut Report) -> i32 {
+        for (key, _) in CHECKS {
+            if !self.done.iter().any(|(k, _)| *k == key) {
+                self.skip(key, "an earlier check failed");
+            }
+        }
+        for (key, status) in &self.done {
+            report.set(&format!("doctor.{}", key), status.name());
+        }
+        let count = |status| self.done.iter().filter(|(_, s)| *s == status).count();
+        let warnings = match count(Status::Warn) {
+            0 => String::new(),
+            1 => ", 1 warning".to_owned(),
+            n => format!(", {} warnings", n),
+        };
+        match count(Status::Fail) {
+            0 => println!("All checks passed{}.", warnings),
+            n => println!("{} of {} checks failed{}.", n, CHECKS.len(), warnings),
+        }
+        if let Some(e) = &self.error {
+            explain::print_hint(e);
+        }
+        self.exit_code
+    }
+}
+
+/// Checks the BootROM on the other side of the session with read-only commands: the
+/// handshake, what it tells about the device and, if sram_reads isn't 0, whether the SRAM
+/// payloads are loaded to reads back the same that many times.
+pub fn device(session: &mut Session, timeout: Duration, sram_reads: u32, checks: &mut Checklist, report: &mut Report) {
+    let mut brom = session.bootrom();
+    let start = Instant::now();
+    if let Err(e) = brom.handshake_timeout(timeout) {
+        return checks.error("handshake", e);
+    }
+    checks.pass("handshake", &format!("the BootROM answered after {:.2} s", start.elapsed().as_secs_f64()));
+
+    let (hw_code, (hw_sub_code, hw_ver, sw_ver)) = match brom.get_hw_code().and_then(|code| Ok((code, brom.get_hw_dict()?))) {
+        Ok(info) => info,
+        Err(e) => return checks.error("hw_info", e),
+    };
+    report.set("device.hw_code", format!("{:#x}", hw_code));
+    report.set("device.hw_sub_code", format!("{:#x}", hw_sub_code));
+    report.set("device.hw_ver", format!("{:#x}", hw_ver));
+    report.set("device.sw_ver", format!("{:#x}", sw_ver));
+    let ids = format!("hw code {:#x}, sub code {:#x}, hw ver {:#x}, sw ver {:#x}", hw_code, hw_sub_code, hw_ver, sw_ver);
+    let chip = chips::lookup(hw_code);
+    match chip {
+        Some(chip) => {
+            report.set("device.chip", chip.name);
+            checks.pass("hw_info", &format!("{}, {}", chip.name, ids));
+        }
+        None => checks.warn("hw_info", &format!("unknown SoC, payloads need --load-addr, {}", ids)),
+    }
+
+    let word = match brom.get_target_config_word() {
+        Ok(word) => word,
+        Err(e) => return checks.error("target_config", e),
+    };
+    report.set("device.target_config", format!("{:#x}", word));
+    let config = TargetConfig::from_word(word);
+    let mut needs = Vec::new();
+    if config.serial_link_authorization {
+        needs.push("serial link authorization needs --sla-sign-cmd or --auth");
+    }
+    if config.download_agent_authorization {
+        needs.push("download agent authorization needs --cert and a signed payload");
+    }
+    if config.secure_boot {
+        checks.fail("target_config", &format!("{:#x}, secure boot is enabled, the tool can't boot this device", word), EXIT_SECURED);
+    } else if needs.is_empty() {
+        checks.pass("target_config", &format!("{:#x}, no secure boot, SLA or DAA", word));
+    } else {
+        checks.warn("target_config", &format!("{:#x}, {}", word, needs.join(", ")));
+    }
+
+    let Some(chip) = chip.filter(|_| sram_reads > 0) else {
+        let why = if sram_reads == 0 { "--sram-reads not given" } else { "unknown SoC, its SRAM isn't known" };
+        return checks.skip("sram", why);
+    };
+    // Compared as read, without the retries of dumps, so a flaky link shows.
+    let addr = chip.load_addr.0;
+    let first = match brom.read32(addr, SRAM_READ_LEN / 4) {
+        Ok(words) => words,
+        Err(e) => return checks.error("sram", e),
+    };
+    for i in 1..sram_reads {
+        match brom.read32(addr, SRAM_READ_LEN / 4) {
+            Ok(words) if words == first => {}
+            Ok(words) => {
+                let differ = words.iter().zip(&first).filter(|(a, b)| a != b).count();
+                return checks.fail("sram", &format!("read {} of {:#x} differs from the first in {} words, the link loses or garbles bytes",
+                                                    i + 1, addr, differ), EXIT_DEVICE_ERROR);
+            }
+            Err(e) => return checks.error("sram", e),
+        }
+    }
+    checks.pass("sram", &format!("{} reads of {} bytes at {:#x} matched", sram_reads, SRAM_READ_LEN, addr));
+}
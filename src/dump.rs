@@ -0,0 +1,50 @@
+use std::io::Write;
+use std::time::Duration;
+use mtk_uartboot::{Error, Result, Session};
+
+/// Bytes read with one command, small enough that a failed read is cheap to retry
+const CHUNK_LEN: u32 = 1024;
+
+/// Attempts at reading a chunk before giving up
+const ATTEMPTS: usize = 3;
+
+/// Reads a chunk of memory, with 32-bit reads if it's aligned to them and 16-bit reads
+/// otherwise. Memory is little-endian.
+fn read_chunk(session: &mut Session, addr: u32, len: u32) -> Result<Vec<u8>> {
+    let mut brom = session.bootrom();
+    if addr.is_multiple_of(4) && len.is_multiple_of(4) {
+        Ok(brom.read32(addr, len / 4)?.into_iter().flat_map(u32::to_le_bytes).collect())
+    } else {
+        Ok(brom.read16(addr, len / 2)?.into_iter().flat_map(u16::to_le_bytes).collect())
+    }
+}
+
+/// Reads size bytes of memory from addr after the BootROM handshake, printing progress as
+/// what was done, e.g. Dumped. Chunks which come back short or garbled are read again,
+/// refused reads fail right away.
+pub fn run(session: &mut Session, addr: u32, size: u32, done: &str) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(size as usize);
+    while (data.len() as u32) < size {
+        let chunk_addr = addr + data.len() as u32;
+        let len = CHUNK_LEN.min(size - data.len() as u32);
+        let mut attempt = 1;
+        let chunk = loop {
+            match read_chunk(session, chunk_addr, len) {
+                Ok(chunk) => break chunk,
+                Err(e @ (Error::Io(_) | Error::Echo { .. })) if attempt < ATTEMPTS => {
+                    println!("\nReading {:#x} failed: {}. Retrying.", chunk_addr, e);
+                    // Let the rest of the answer arrive, it would be taken for the next one.
+                    std::thread::sleep(Duration::from_millis(100));
+                    session.transport().clear_input()?;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        data.extend_from_slice(&chunk);
+        print!("\r{} {}/{} bytes", done, data.len(), size);
+        std::io::stdout().flush().ok();
+    }
+    println!();
+    Ok(data)
+}
@@ -0,0 +1,88 @@
+use std::fmt;
+use std::io;
+use crate::protocol::bl2::Ack;
+use crate::types::Baud;
+
+/// Errors talking to the device.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The transport failed or timed out
+    Io(io::Error),
+    /// The device didn't echo back what was sent
+    Echo { sent: Vec<u8>, received: Vec<u8> },
+    /// The handshake got no answer in time
+    HandshakeTimeout,
+    /// The BootROM computed another checksum of the data than was sent
+    Checksum { expected: u16, received: u16 },
+    /// BL2 read back other data from storage than was written
+    Crc { expected: u32, received: u32 },
+    /// A command finished with a non-zero status
+    Status { cmd: &'static str, status: u16 },
+    /// BL2 refused a command with an ack code, see [`Ack`]
+    Ack { cmd: &'static str, ack: Ack },
+    /// The BootROM refused to switch to this baud rate
+    BaudrateTooHigh(Baud),
+    /// The BootROM didn't answer at the baud rate both ends switched to
+    BaudrateSync(Baud),
+    /// A state-changing command was refused because the session is read-only
+    ReadOnly { cmd: u8 },
+    /// An extension was registered with the command byte or name of a command the stage
+    /// already has
+    CommandTaken { name: &'static str, opcode: u8 },
+    /// No extension of this name is registered for the stage it was called on
+    UnknownCommand(String),
+    /// Data of this many bytes doesn't fit the 32-bit length fields of the protocols
+    TooLong(usize),
+    /// The signature of a payload is longer than the payload it's part of
+    SignatureTooLong { sig_len: u32, len: u32 },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Echo { sent, received } =>
+                write!(f, "returned data isn't the same. Tx: {:?} Rx: {:?}", sent, received),
+            Error::HandshakeTimeout => write!(f, "no answer to the handshake"),
+            Error::Checksum { expected, received } =>
+                write!(f, "checksum mismatch, expected {:#06x} but got {:#06x}", expected, received),
+            Error::Crc { expected, received } =>
+                write!(f, "CRC-32 of the data read back is {:#010x} instead of {:#010x}", received, expected),
+            Error::Status { cmd, status } => write!(f, "{} cmd status: {:#x}", cmd, status),
+            Error::Ack { cmd, ack } => write!(f, "BL2 refused {}: {}. {}", cmd, ack, ack.explanation()),
+            Error::BaudrateTooHigh(baudrate) => write!(f, "{} is too high for bootrom.", baudrate),
+            Error::BaudrateSync(baudrate) => write!(f, "no answer from bootrom at {} baud.", baudrate),
+            Error::ReadOnly { cmd } => write!(f, "command {:#x} refused in read-only mode.", cmd),
+            Error::CommandTaken { name, opcode } =>
+                write!(f, "extension {} ({:#x}) clashes with a command already known.", name, opcode),
+            Error::UnknownCommand(name) => write!(f, "no extension {} registered.", name),
+            Error::TooLong(len) => write!(f, "{} bytes don't fit the 32-bit lengths of the protocol.", len),
+            Error::SignatureTooLong { sig_len, len } =>
+                write!(f, "the signature of {} bytes is longer than the payload of {} bytes.", sig_len, len),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<serialport::Error> for Error {
+    fn from(e: serialport::Error) -> Error {
+        Error::Io(e.into())
+    }
+}
@@ -0,0 +1,150 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use crate::protocol::bl2::PacketAck;
+use crate::transport::{IoClock, IoTimes};
+use crate::types::Baud;
+
+/// The device side a session is currently talking to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum Stage {
+    BootRom,
+    Bl2,
+}
+
+/// Things happening during a session which callers may want to show or log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Event {
+    /// The handshake with the device completed
+    HandshakeDone(Stage),
+    /// The slowest answer of the BootROM handshake sequence which completed took latency,
+    /// from writing the byte to reading the answer. Bridges holding received bytes back
+    /// show here, the handshake waits only 10 ms for each answer.
+    HandshakeLatency { stage: Stage, latency: Duration },
+    /// Both ends switched to a new baud rate
+    BaudrateChanged { stage: Stage, baudrate: Baud },
+    /// Part of a payload or FIP has been sent
+    Progress { stage: Stage, sent: usize, total: usize },
+    /// BL2 rejected a FIP packet, which is then sent again
+    PacketRejected { idx: u32, ack: PacketAck },
+    /// BL2 accepted a FIP packet of len bytes, latency after it was sent completely
+    PacketAcked { idx: u32, len: usize, latency: Duration },
+    /// A transfer to stage stopped at a chunk boundary, see
+    /// [`Session::pause_control`](crate::Session::pause_control)
+    Paused { stage: Stage },
+    /// A paused transfer goes on, after it was held for paused
+    Resumed { stage: Stage, paused: Duration },
+    /// A protocol command finished. Only reported with
+    /// [`Session::set_profiling`](crate::Session::set_profiling)
+    Span(Span),
+}
+
+/// The time a protocol command took, and where it went. Spans of commands running within
+/// another one, like the packets of a FIP, are reported before the one they ran in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub stage: Stage,
+    /// The commands it ran in, outermost first, and its own name last, e.g.
+    /// `["send_fip", "packet"]`
+    pub stack: Vec<&'static str>,
+    pub start: Instant,
+    pub duration: Duration,
+    /// Time spent in writes to the transport, and in waiting for written bytes to leave
+    /// the host
+    pub write: Duration,
+    /// Time spent in reads from the transport, mostly waiting for the device to answer
+    pub read: Duration,
+    /// Time writes were held back by [`Session::set_max_rate`](crate::Session::set_max_rate)
+    pub throttle: Duration,
+}
+
+type Observer = Box<dyn FnMut(&Event) + Send>;
+
+/// Delivers events to the observer of a session, if there is one. Shared with the guards
+/// of open spans, which report them when they're dropped.
+#[derive(Default)]
+pub(crate) struct Events {
+    shared: Arc<Mutex<Shared>>,
+    profiling: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    observer: Option<Observer>,
+    clock: Arc<IoClock>,
+    /// The spans not finished yet, innermost last
+    open: Vec<(&'static str, Instant, IoTimes)>,
+}
+
+impl Shared {
+    fn emit(&mut self, event: Event) {
+        if let Some(observer) = &mut self.observer {
+            observer(&event);
+        }
+    }
+}
+
+fn lock(shared: &Mutex<Shared>) -> MutexGuard<'_, Shared> {
+    // An observer which panicked leaves nothing half updated.
+    shared.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Events {
+    pub(crate) fn set_observer(&mut self, observer: Observer) {
+        lock(&self.shared).observer = Some(observer);
+    }
+
+    pub(crate) fn emit(&mut self, event: Event) {
+        lock(&self.shared).emit(event);
+    }
+
+    /// Reports spans from now on, with the times clock adds up
+    pub(crate) fn set_profiling(&mut self, clock: Option<Arc<IoClock>>) {
+        self.profiling = clock.is_some();
+        if let Some(clock) = clock {
+            lock(&self.shared).clock = clock;
+        }
+    }
+
+    /// Starts the span of a command, which ends when the returned guard is dropped
+    pub(crate) fn span(&self, stage: Stage, name: &'static str) -> SpanGuard {
+        if !self.profiling {
+            return SpanGuard { shared: None, stage };
+        }
+        let mut shared = lock(&self.shared);
+        let now = shared.clock.now();
+        shared.open.push((name, Instant::now(), now));
+        SpanGuard { shared: Some(self.shared.clone()), stage }
+    }
+}
+
+/// An open span, see [`Events::span`]
+pub(crate) struct SpanGuard {
+    shared: Option<Arc<Mutex<Shared>>>,
+    stage: Stage,
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        let Some(shared) = &self.shared else {
+            return;
+        };
+        let mut shared = lock(shared);
+        let stack: Vec<&'static str> = shared.open.iter().map(|(name, _, _)| *name).collect();
+        let Some((_, start, before)) = shared.open.pop() else {
+            return;
+        };
+        let now = shared.clock.now();
+        let span = Span {
+            stage: self.stage,
+            stack,
+            start,
+            duration: start.elapsed(),
+            write: now.write - before.write,
+            read: now.read - before.read,
+            throttle: now.throttle - before.throttle,
+        };
+        shared.emit(Event::Span(span));
+    }
+}
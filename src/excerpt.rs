@@ -0,0 +1,68 @@
+use std::collections::VecDeque;
+use regex::Regex;
+
+/// Excerpts kept at most, so the report stays short when a pattern matches every line
+const MAX_EXCERPTS: usize = 20;
+
+/// Console lines around lines matching a pattern
+pub struct Excerpt {
+    /// The pattern the first matching line matched
+    pub pattern: String,
+    pub lines: Vec<String>,
+}
+
+/// Keeps the console lines around those matching the patterns, like grep -C, for reports
+/// which need the lines explaining a failure rather than the whole boot log.
+pub struct Excerpts {
+    patterns: Vec<Regex>,
+    context: usize,
+    /// The last lines which aren't in an excerpt, at most context of them
+    before: VecDeque<String>,
+    /// Lines still going into the last excerpt after its last matching line
+    after: usize,
+    found: Vec<Excerpt>,
+    /// Matches which didn't fit in MAX_EXCERPTS
+    dropped: usize,
+}
+
+impl Excerpts {
+    pub fn new(patterns: Vec<Regex>, context: usize) -> Excerpts {
+        Excerpts { patterns, context, before: VecDeque::new(), after: 0, found: Vec::new(), dropped: 0 }
+    }
+
+    /// Takes the next console line. Matches within the context of the last one extend its
+    /// excerpt instead of starting another.
+    pub fn scan(&mut self, line: &str) {
+        let matched = self.patterns.iter().find(|re| re.is_match(line));
+        // While after is set, there's an excerpt to extend.
+        match matched {
+            Some(_) if self.after > 0 => {
+                self.found.last_mut().unwrap().lines.push(line.to_owned());
+                self.after = self.context;
+            }
+            Some(_) if self.found.len() == MAX_EXCERPTS => self.dropped += 1,
+            Some(re) => {
+                let mut lines: Vec<String> = self.before.drain(..).collect();
+                lines.push(line.to_owned());
+                self.found.push(Excerpt { pattern: re.as_str().to_owned(), lines });
+                self.after = self.context;
+            }
+            None if self.after > 0 => {
+                self.found.last_mut().unwrap().lines.push(line.to_owned());
+                self.after -= 1;
+            }
+            None => {
+                self.before.push_back(line.to_owned());
+                if self.before.len() > self.context {
+                    self.before.pop_front();
+                }
+            }
+        }
+    }
+
+    /// The excerpts found since the last call, and how many matches didn't fit
+    pub fn take(&mut self) -> (Vec<Excerpt>, usize) {
+        self.after = 0;
+        (std::mem::take(&mut self.found), std::mem::take(&mut self.dropped))
+    }
+}
@@ -0,0 +1,300 @@
+use mtk_uartboot::Error;
+use mtk_uartboot::protocol::def::CommandDef;
+use mtk_uartboot::protocol::{bl2, bootrom};
+
+/// Something which commonly goes wrong in a stage. on has the kinds of errors it's the likely
+/// cause of, as error_kind names them, for the hint printed when one ends a run.
+pub struct Cause {
+    pub on: &'static [&'static str],
+    pub cause: &'static str,
+    pub fix: &'static str,
+}
+
+/// What a stage of the boot does and how it fails, for `explain` and the hints on errors
+pub struct Topic {
+    pub name: &'static str,
+    pub title: &'static str,
+    pub what: &'static str,
+    /// The commands of the stage, listed with their command bytes from the protocol tables
+    pub commands: &'static [CommandDef],
+    pub causes: &'static [Cause],
+}
+
+pub const TOPICS: &[Topic] = &[
+    Topic {
+        name: "handshake",
+        title: "BootROM handshake",
+        what: "After a reset with the download strap held, the BootROM listens on its UART for a \
+               moment before it boots from storage. The tool sends the 4 handshake bytes a0 0a 50 05 \
+               one at a time; the BootROM answers each with its complement. Once all 4 were \
+               answered, the BootROM stays in download mode and takes commands at 115200 baud.",
+        commands: &[],
+        causes: &[
+            Cause {
+                on: &["handshake_timeout", "io_timeout"],
+                cause: "The device isn't in download mode, it's already running its bootloader or isn't powered.",
+                fix: "Hold the download strap (often a button or the KPCOL0 pin) while resetting, or give --gpio-dl with --gpio-reset, and start the tool before the reset.",
+            },
+            Cause {
+                on: &["handshake_timeout"],
+                cause: "RX and TX are swapped, or the adapter runs at another voltage than the SoC's UART.",
+                fix: "Check the wiring and that the adapter is a 3.3V one; `linktest` checks the adapter on its own.",
+            },
+            Cause {
+                on: &["echo"],
+                cause: "The first bytes are answered with garbage, e.g. the boot log of the device got mixed in.",
+                fix: "Reset the device closer to the start of the tool, the handshake is only listened for briefly.",
+            },
+        ],
+    },
+    Topic {
+        name: "bootrom",
+        title: "BootROM commands",
+        what: "In download mode, the BootROM takes single byte commands. Each byte of a command and \
+               of its big-endian arguments is echoed back before the answer, so a byte lost on the \
+               line shows up right away. The tool reads the hw code and the target config first, to \
+               know the SoC and whether it needs authorization.",
+        commands: &[bootrom::GET_HW_CODE, bootrom::GET_TARGET_CONFIG, bootrom::GET_HW_DICT, bootrom::READ32, bootrom::WRITE32],
+        causes: &[
+            Cause {
+                on: &["echo"],
+                cause: "Bytes are lost or corrupted on the line, from a long cable, noise or a flaky adapter.",
+                fix: "Use shorter wires and a common ground; `linktest` measures the error rate of the link.",
+            },
+            Cause {
+                on: &["read_only"],
+                cause: "The session is read-only, which refuses commands changing the device's state.",
+                fix: "Leave out --read-only for a run which is meant to boot the device.",
+            },
+        ],
+    },
+    Topic {
+        name: "baudrate",
+        title: "Baud rate switch",
+        what: "The handshake always happens at 115200 baud. Before the payload is sent, the tool asks \
+               the BootROM to switch to --brom-load-baudrate; both ends switch and the tool checks that \
+               the BootROM still answers at the new rate. Older BootROMs refuse high rates, and some \
+               adapters can't generate them exactly.",
+        commands: &[bootrom::SET_BAUDRATE],
+        causes: &[
+            Cause {
+                on: &["baudrate_too_high", "status:set_baudrate"],
+                cause: "The BootROM of this SoC doesn't support the baud rate.",
+                fix: "Give a lower --brom-load-baudrate; `latency` shows which rates work.",
+            },
+            Cause {
+                on: &["baudrate_sync"],
+                cause: "The adapter or its driver can't reach the baud rate closely enough.",
+                fix: "Give a lower --brom-load-baudrate, or one the adapter divides exactly, like 921600.",
+            },
+        ],
+    },
+    Topic {
+        name: "sla",
+        title: "Serial link authorization",
+        what: "SoCs with SLA enabled in their efuses only take commands after a challenge-response: \
+               the BootROM sends a random challenge, which has to be signed with the key whose hash \
+               is burnt into the SoC. The tool gets the signature from --sla-sign-cmd.",
+        commands: &[bootrom::SLA, bootrom::SEND_AUTH],
+        causes: &[
+            Cause {
+                on: &["status:sla"],
+                cause: "The signature doesn't match the key of the SoC, or it was made for another challenge.",
+                fix: "Check that --sla-sign-cmd signs with the key of this device and the challenge it's given.",
+            },
+            Cause {
+                on: &["status:send_auth"],
+                cause: "The auth file is for another device, or the SoC has no key set for it.",
+                fix: "Give the auth file made for this SoC's key with --auth.",
+            },
+        ],
+    },
+    Topic {
+        name: "daa",
+        title: "Download agent authorization",
+        what: "SoCs with DAA enabled only run payloads signed with their key. A certificate is sent \
+               before the payload, and the signature is the last --payload-sig-len bytes of the \
+               payload, which the BootROM checks before it jumps to it.",
+        commands: &[bootrom::SEND_CERT, bootrom::SEND_DA],
+        causes: &[
+            Cause {
+                on: &["status:send_cert"],
+                cause: "The certificate is for another device or key.",
+                fix: "Give the certificate made for this SoC with --cert.",
+            },
+            Cause {
+                on: &["status:jump_da", "status:jump_da64"],
+                cause: "The payload isn't signed, or signed with another key than the one the SoC has.",
+                fix: "Sign the payload for this device and give the length of the signature with --payload-sig-len.",
+            },
+        ],
+    },
+    Topic {
+        name: "payload",
+        title: "Payload upload and jump",
+        what: "The payload, usually a BL2, is sent to the SRAM at the load address of the SoC. The \
+               BootROM answers with a 16 bit XOR checksum of what it received, which the tool \
+               compares with its own. Then the BootROM jumps to the load address, in aarch64 or \
+               aarch32 mode.",
+        commands: &[bootrom::SEND_DA, bootrom::JUMP_DA, bootrom::JUMP_DA64],
+        causes: &[
+            Cause {
+                on: &["checksum"],
+                cause: "Bytes of the payload were corrupted on the line.",
+                fix: "Lower --brom-load-baudrate or improve the wiring; `linktest` measures the error rate.",
+            },
+            Cause {
+                on: &["status:send_da"],
+                cause: "The load address or size doesn't fit the SRAM of the SoC, or with DAA the signature of the payload doesn't check out.",
+                fix: "Check --load-addr against the SoC, or leave it out to use the default of the hw code.",
+            },
+            Cause {
+                on: &["status:jump_da", "status:jump_da64"],
+                cause: "The BootROM refused to jump, e.g. to an address nothing was loaded at.",
+                fix: "Jump to the address the payload was sent to, with --aarch64 or --aarch32 matching the payload.",
+            },
+        ],
+    },
+    Topic {
+        name: "bl2",
+        title: "BL2 UART download",
+        what: "A Mediatek TF-A BL2 built with UART download support prints its banner and then \
+               waits for the tool, instead of loading the FIP from storage. The tool reads the \
+               version of the protocol BL2 speaks and may switch to a faster baud rate with it.",
+        commands: &[bl2::VERSION, bl2::SET_BAUDRATE],
+        causes: &[
+            Cause {
+                on: &["handshake_timeout", "io_timeout"],
+                cause: "The payload isn't a BL2 with UART download, or it crashed before it got there.",
+                fix: "Build BL2 with the UART download option, and check its console output for where it stops.",
+            },
+            Cause {
+                on: &["echo"],
+                cause: "The BL2 and the tool disagree on the baud rate.",
+                fix: "Give the --bl2-load-baudrate BL2 was built to switch to.",
+            },
+            Cause {
+                on: &["unsupported"],
+                cause: "BL2 doesn't implement a command the operation needs, it speaks an older version of the protocol.",
+                fix: "Use a BL2 built from a newer TF-A with UART download; the version it speaks is printed after the handshake.",
+            },
+        ],
+    },
+    Topic {
+        name: "fip",
+        title: "FIP upload",
+        what: "The FIP, with BL31 and the next stage like U-Boot, is sent to the BL2 in packets, each \
+               acknowledged. BL2 then jumps to BL31, and the console of the device takes over the line.",
+        commands: &[bl2::SEND_FIP, bl2::GO],
+        causes: &[
+            Cause {
+                on: &["echo", "io_timeout"],
+                cause: "Packets were lost at the baud rate of the transfer.",
+                fix: "Lower --bl2-load-baudrate or give --max-rate; `linktest` shows what the link carries.",
+            },
+            Cause {
+                on: &["status:send_fip", "status:go"],
+                cause: "The FIP is too large for the DRAM BL2 loads it to, or isn't a FIP.",
+                fix: "Check the image with `fip-info`.",
+            },
+        ],
+    },
+    Topic {
+        name: "flash",
+        title: "Writing to storage",
+        what: "A BL2 speaking version 2 of the protocol or later can write the image it's sent to a \
+               partition of the boot storage. It reads back what it wrote and answers with its \
+               CRC-32, which the tool compares with the image's.",
+        commands: &[bl2::FLASH],
+        causes: &[
+            Cause {
+                on: &["crc"],
+                cause: "What's read back differs from what was written, from bad blocks or a worn out flash.",
+                fix: "Write again, and check the storage if it keeps failing.",
+            },
+            Cause {
+                on: &["status:flash"],
+                cause: "The partition doesn't exist or the image is larger than it.",
+                fix: "Check --partition against the partition table of the device.",
+            },
+        ],
+    },
+];
+
+/// The kind of an error, as Cause::on names it
+fn error_kind(e: &Error) -> String {
+    match e {
+        Error::Io(e) if e.kind() == std::io::ErrorKind::TimedOut => "io_timeout".to_owned(),
+        Error::Io(_) => "io".to_owned(),
+        Error::Echo { .. } => "echo".to_owned(),
+        Error::HandshakeTimeout => "handshake_timeout".to_owned(),
+        Error::Checksum { .. } => "checksum".to_owned(),
+        Error::Crc { .. } => "crc".to_owned(),
+        Error::Ack { ack: bl2::Ack::Unsupported, .. } => "unsupported".to_owned(),
+        Error::Status { cmd, .. } | Error::Ack { cmd, .. } => format!("status:{}", cmd),
+        Error::BaudrateTooHigh(_) => "baudrate_too_high".to_owned(),
+        Error::BaudrateSync(_) => "baudrate_sync".to_owned(),
+        Error::ReadOnly { .. } => "read_only".to_owned(),
+        _ => "other".to_owned(),
+    }
+}
+
+pub fn topic(name: &str) -> Option<&'static Topic> {
+    TOPICS.iter().find(|t| t.name == name)
+}
+
+/// The most likely cause of an error, the first one of the topics in the order of the boot
+pub fn hint(e: &Error) -> Option<(&'static Topic, &'static Cause)> {
+    let kind = error_kind(e);
+    TOPICS.iter().find_map(|topic| {
+        topic.causes.iter().find(|c| c.on.contains(&kind.as_str())).map(|cause| (topic, cause))
+    })
+}
+
+/// Prints the hint for an error, if there's one
+pub fn print_hint(e: &Error) {
+    if let Some((topic, cause)) = hint(e) {
+        println!("Hint: {} {} (`mtk_uartboot explain {}` has more.)", cause.cause, cause.fix, topic.name);
+    }
+}
+
+/// Breaks text into lines of at most width columns, the first starting with first and the
+/// others with indent
+fn wrap(text: &str, first: &str, indent: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let lead = if out.is_empty() { first } else { indent };
+        if !line.is_empty() && lead.len() + line.chars().count() + 1 + word.chars().count() > width {
+            out.push_str(&format!("{}{}\n", lead, line));
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        out.push_str(&format!("{}{}\n", if out.is_empty() { first } else { indent }, line));
+    }
+    out
+}
+
+pub fn render(topic: &Topic) -> String {
+    let mut out = format!("{}\n\n{}", topic.title, wrap(topic.what, "", "", 80));
+    if !topic.commands.is_empty() {
+        let commands: Vec<String> = topic.commands.iter().map(|c| format!("{} ({:#04x})", c.name, c.opcode)).collect();
+        out.push_str(&format!("\nCommands:\n{}", wrap(&commands.join(", "), "  ", "  ", 80)));
+    }
+    out.push_str("\nCommon failures:\n");
+    for cause in topic.causes {
+        out.push_str(&wrap(cause.cause, "  - ", "    ", 80));
+        out.push_str(&wrap(cause.fix, "    ", "    ", 80));
+    }
+    out
+}
+
+/// Lists the topics with their titles
+pub fn list() -> String {
+    TOPICS.iter().map(|t| format!("  {:<10} {}\n", t.name, t.title)).collect()
+}
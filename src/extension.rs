@@ -0,0 +1,278 @@
+//! Commands the crate doesn't implement, driven through a [`Session`](crate::Session).
+//!
+//! Experimental BootROM payloads or BL2 builds may answer commands of their own. An
+//! [`Extension`] tells how one goes over the wire: its command byte, and how its request is
+//! written and its answer read within a [`Frame`]. Once registered with
+//! [`Session::register`](crate::Session::register), it runs by name with
+//! [`BootRom::call`](crate::BootRom::call) or [`Bl2::call`](crate::Bl2::call), within the
+//! read-only mode, events and transport settings of the session:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use mtk_uartboot::{Extension, Frame, Session, Stage};
+//!
+//! /// Reads an eFuse word, answered by a patched BootROM payload
+//! struct ReadFuse;
+//!
+//! impl Extension for ReadFuse {
+//!     fn stage(&self) -> Stage { Stage::BootRom }
+//!     fn name(&self) -> &'static str { "read_fuse" }
+//!     fn opcode(&self) -> u8 { 0xb0 }
+//!     fn read_only(&self) -> bool { true }
+//!
+//!     fn run(&mut self, frame: &mut Frame<'_>, request: &[u8]) -> mtk_uartboot::Result<Vec<u8>> {
+//!         // The index of the word, echoed like every argument
+//!         frame.echo(request)?;
+//!         frame.read(4)
+//!     }
+//! }
+//!
+//! # fn main() -> mtk_uartboot::Result<()> {
+//! let port = serialport::new("/dev/ttyUSB0", 115200).timeout(Duration::from_secs(2)).open()?;
+//! let mut session = Session::new(Box::new(port));
+//! session.register(ReadFuse)?;
+//! let mut brom = session.bootrom();
+//! brom.handshake()?;
+//! let word = brom.call("read_fuse", &3u32.to_be_bytes())?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::slice;
+use std::time::Duration;
+use crate::error::{Error, Result};
+use crate::event::{Events, Stage};
+use crate::protocol::{bl2, bootrom};
+use crate::protocol::def::{self, CommandDef, Field, Values, MAX_FRAME_LEN};
+use crate::transport::Transport;
+
+/// A command of a device side protocol extension.
+pub trait Extension: Send {
+    /// The device side which answers it
+    fn stage(&self) -> Stage;
+
+    /// What it's called with [`BootRom::call`](crate::BootRom::call) or
+    /// [`Bl2::call`](crate::Bl2::call), also used in errors and spans
+    fn name(&self) -> &'static str;
+
+    /// Its command byte, sent and echoed before [`run`](Self::run)
+    fn opcode(&self) -> u8;
+
+    /// Whether it only reads from the device, so read-only sessions allow it
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// Sends the request after the command byte went through, and returns the answer.
+    fn run(&mut self, frame: &mut Frame<'_>, request: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// The transport to the device while an [`Extension`] runs, with the framing the BootROM
+/// and BL2 protocols have in common.
+pub struct Frame<'a> {
+    port: &'a mut dyn Transport,
+}
+
+impl<'a> Frame<'a> {
+    pub(crate) fn new(port: &'a mut dyn Transport) -> Frame<'a> {
+        Frame { port }
+    }
+
+    /// Sends the command byte and the arguments of a built-in command, unless the read-only
+    /// mode refuses it, and reads its response
+    pub(crate) fn start(&mut self, cmd: &CommandDef, args: &[u32], read_only: bool) -> Result<Values> {
+        debug_assert_eq!(args.len(), cmd.args.len());
+        if read_only && !cmd.read_only {
+            return Err(Error::ReadOnly { cmd: cmd.opcode });
+        }
+        self.echo(slice::from_ref(&cmd.opcode))?;
+        for (&arg, &width) in args.iter().zip(cmd.args) {
+            self.echo(def::encode(arg, width, &mut [0; 4]))?;
+        }
+        self.fields(cmd.name, cmd.response)
+    }
+
+    /// Writes data and checks that the device echoes it back, as it does with command
+    /// bytes and arguments
+    pub fn echo(&mut self, data: &[u8]) -> Result<()> {
+        let mut received = vec![0; data.len()];
+        self.port.write_all(data)?;
+        self.port.read_exact(&mut received)?;
+        if received != data {
+            return Err(Error::Echo { sent: data.to_vec(), received });
+        }
+        Ok(())
+    }
+
+    /// Writes data which isn't echoed, e.g. a payload, and waits until it left the host
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.port.write_all(data)?;
+        self.port.drain()?;
+        Ok(())
+    }
+
+    /// Reads exactly len bytes
+    pub fn read(&mut self, len: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0; len];
+        self.port.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Reads a frame laid out like the answers of the built-in commands, see
+    /// [`protocol::def`](crate::protocol::def). A status which isn't a success fails with
+    /// [`Error::Status`] right away, the device sends nothing after it.
+    pub fn fields(&mut self, name: &'static str, fields: &[Field]) -> Result<Values> {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let mut p = 0;
+        for field in fields {
+            let bytes = &mut buf[p..p + field.width()];
+            self.port.read_exact(bytes)?;
+            field.check(field.value(bytes)).map_err(|status| Error::Status { cmd: name, status })?;
+            p += field.width();
+        }
+        Ok(def::decode(fields, &buf))
+    }
+
+    /// Reads a 16-bit big-endian status word as most BootROM commands end with, failing
+    /// with [`Error::Status`] unless it's 0
+    pub fn status(&mut self, name: &'static str) -> Result<()> {
+        self.fields(name, &[bootrom::STATUS]).map(drop)
+    }
+
+    /// Sets how long reads wait, e.g. for a command which takes the device a while
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        Ok(self.port.set_timeout(timeout)?)
+    }
+}
+
+/// The extensions registered on a session
+#[derive(Default)]
+pub(crate) struct Extensions {
+    list: Vec<Box<dyn Extension>>,
+}
+
+impl Extensions {
+    pub(crate) fn register(&mut self, extension: Box<dyn Extension>) -> Result<()> {
+        let (stage, name, opcode) = (extension.stage(), extension.name(), extension.opcode());
+        let builtin = match stage {
+            Stage::BootRom => &bootrom::V1,
+            Stage::Bl2 => bl2::LATEST,
+        };
+        let taken = builtin.command(opcode).is_some()
+            || self.list.iter().any(|e| e.stage() == stage && (e.opcode() == opcode || e.name() == name));
+        if taken {
+            return Err(Error::CommandTaken { name, opcode });
+        }
+        self.list.push(extension);
+        Ok(())
+    }
+
+    /// Runs the extension of that name, after sending its command byte
+    pub(crate) fn call(&mut self, port: &mut dyn Transport, events: &Events, read_only: bool, stage: Stage, name: &str, request: &[u8]) -> Result<Vec<u8>> {
+        let extension = self.list.iter_mut()
+            .find(|e| e.stage() == stage && e.name() == name)
+            .ok_or_else(|| Error::UnknownCommand(name.to_owned()))?;
+        let _span = events.span(stage, extension.name());
+        let opcode = extension.opcode();
+        if read_only && !extension.read_only() {
+            return Err(Error::ReadOnly { cmd: opcode });
+        }
+        let mut frame = Frame { port };
+        frame.echo(slice::from_ref(&opcode))?;
+        extension.run(&mut frame, request)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::{self, Read, Write};
+    use super::*;
+
+    /// A device answering with the bytes it was given, whatever is sent
+    #[derive(Default)]
+    pub(crate) struct Scripted {
+        answer: Vec<u8>,
+        pub(crate) sent: Vec<u8>,
+    }
+
+    impl Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.answer.len());
+            if len == 0 {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            buf[..len].copy_from_slice(&self.answer[..len]);
+            self.answer.drain(..len);
+            Ok(len)
+        }
+    }
+
+    impl Write for Scripted {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for Scripted {
+        fn set_timeout(&mut self, _timeout: Duration) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn set_baud_rate(&mut self, _baudrate: u32) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn clear_input(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn device(answer: &[u8]) -> Scripted {
+        Scripted { answer: answer.to_vec(), ..Scripted::default() }
+    }
+
+    #[test]
+    fn commands_are_echoed_and_answered() {
+        let mut port = device(&[bootrom::CMD_READ32, 0x00, 0x20, 0x10, 0x00, 0, 0, 0, 1, 0, 0]);
+        let values = Frame::new(&mut port).start(&bootrom::READ32, &[0x201000, 1], false).unwrap();
+        assert_eq!(values.as_slice(), [0]);
+        assert_eq!(port.sent, [bootrom::CMD_READ32, 0x00, 0x20, 0x10, 0x00, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn echo_mismatch_is_an_error() {
+        let mut port = device(&[bootrom::CMD_READ32, 0x00, 0x20, 0x11, 0x00]);
+        match Frame::new(&mut port).start(&bootrom::READ32, &[0x201000, 1], false) {
+            Err(Error::Echo { sent, received }) => {
+                assert_eq!(sent, [0x00, 0x20, 0x10, 0x00]);
+                assert_eq!(received, [0x00, 0x20, 0x11, 0x00]);
+            }
+            result => panic!("{:?}", result.map(|v| v.as_slice().to_vec())),
+        }
+    }
+
+    #[test]
+    fn status_fails_the_command() {
+        let mut port = device(&[bootrom::CMD_JUMP_DA, 0x00, 0x20, 0x10, 0x00, 0x1d, 0x0c]);
+        let result = Frame::new(&mut port).start(&bootrom::JUMP_DA, &[0x201000], false);
+        assert!(matches!(result, Err(Error::Status { cmd: "jump_da", status: 0x1d0c })));
+        let mut port = device(&[0, 0]);
+        assert!(Frame::new(&mut port).status("send_da").is_ok());
+    }
+
+    #[test]
+    fn read_only_refuses_commands_changing_the_device() {
+        let mut port = device(&[]);
+        let result = Frame::new(&mut port).start(&bootrom::JUMP_DA, &[0x201000], true);
+        assert!(matches!(result, Err(Error::ReadOnly { cmd: bootrom::CMD_JUMP_DA })));
+        assert!(port.sent.is_empty());
+        let mut port = device(&[bootrom::CMD_GET_HW_CODE, 0x79, 0x86, 0, 0]);
+        let values = Frame::new(&mut port).start(&bootrom::GET_HW_CODE, &[], true).unwrap();
+        assert_eq!(values.as_slice(), [0x7986, 0]);
+    }
+}
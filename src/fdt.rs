@@ -0,0 +1,467 @@
+use std::collections::HashMap;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+const HEADER_LEN: usize = 40;
+
+/// A node of a flattened device tree.
+#[derive(Clone, Debug, Default)]
+pub struct Node {
+    pub name: String,
+    pub props: Vec<(String, Vec<u8>)>,
+    pub children: Vec<Node>,
+}
+
+/// A device tree blob, parsed so overlays can be applied to it.
+#[derive(Clone, Debug)]
+pub struct Fdt {
+    pub root: Node,
+    boot_cpuid: u32,
+    /// The memory reservation block, including the terminating entry
+    rsvmap: Vec<u8>,
+}
+
+fn be32(data: &[u8], offset: usize) -> Result<u32, String> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| "truncated device tree".to_owned())
+}
+
+/// A phandle of an overlay moved past the delta phandles of the base tree
+fn add_phandle(phandle: u32, delta: u32) -> Result<u32, String> {
+    phandle.checked_add(delta).filter(|&p| p != u32::MAX)
+        .ok_or_else(|| "the phandles of the overlay and the device tree don't fit together".to_owned())
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+fn c_str(data: &[u8], offset: usize) -> Result<&str, String> {
+    let bytes = data.get(offset..).ok_or("truncated device tree")?;
+    let len = bytes.iter().position(|&b| b == 0).ok_or("unterminated string in device tree")?;
+    std::str::from_utf8(&bytes[..len]).map_err(|_| "invalid string in device tree".to_owned())
+}
+
+/// Returns the total size of the device tree blob at the start of data, if there's one
+pub fn blob_len(data: &[u8]) -> Option<usize> {
+    if be32(data, 0).ok()? != FDT_MAGIC {
+        return None;
+    }
+    let len = be32(data, 4).ok()? as usize;
+    (len >= HEADER_LEN && len <= data.len()).then_some(len)
+}
+
+impl Node {
+    pub fn prop(&self, name: &str) -> Option<&[u8]> {
+        self.props.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_slice())
+    }
+
+    fn set_prop(&mut self, name: &str, value: Vec<u8>) {
+        match self.props.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value,
+            None => self.props.push((name.to_owned(), value)),
+        }
+    }
+
+    fn phandle(&self) -> Option<u32> {
+        let value = self.prop("phandle").or_else(|| self.prop("linux,phandle"))?;
+        Some(u32::from_be_bytes(value.try_into().ok()?))
+    }
+
+    /// A child by name. A name without unit address also matches a child with one.
+    fn child(&self, name: &str) -> Option<usize> {
+        self.children.iter().position(|c| c.name == name).or_else(|| {
+            if name.contains('@') {
+                return None;
+            }
+            self.children.iter().position(|c| c.name.split('@').next() == Some(name))
+        })
+    }
+
+    fn find(&self, path: &str) -> Option<&Node> {
+        path.split('/').filter(|c| !c.is_empty())
+            .try_fold(self, |node, name| Some(&node.children[node.child(name)?]))
+    }
+
+    fn find_mut(&mut self, path: &str) -> Option<&mut Node> {
+        let mut node = self;
+        for name in path.split('/').filter(|c| !c.is_empty()) {
+            let i = node.child(name)?;
+            node = &mut node.children[i];
+        }
+        Some(node)
+    }
+
+    fn max_phandle(&self) -> u32 {
+        self.children.iter().map(Node::max_phandle).fold(self.phandle().unwrap_or(0), u32::max)
+    }
+
+    fn path_of_phandle(&self, phandle: u32, path: &str) -> Option<String> {
+        if self.phandle() == Some(phandle) {
+            return Some(if path.is_empty() { "/".to_owned() } else { path.to_owned() });
+        }
+        self.children.iter()
+            .find_map(|c| c.path_of_phandle(phandle, &format!("{}/{}", path, c.name)))
+    }
+
+    fn adjust_phandles(&mut self, delta: u32) -> Result<(), String> {
+        for (name, value) in &mut self.props {
+            if (name == "phandle" || name == "linux,phandle") && value.len() == 4 {
+                let phandle = u32::from_be_bytes(value[..].try_into().unwrap());
+                value.copy_from_slice(&add_phandle(phandle, delta)?.to_be_bytes());
+            }
+        }
+        for child in &mut self.children {
+            child.adjust_phandles(delta)?;
+        }
+        Ok(())
+    }
+
+    /// Adds delta to the phandle references listed in local_fixups, a tree mirroring this one
+    /// with lists of offsets into the properties.
+    fn adjust_references(&mut self, local_fixups: &Node, delta: u32) -> Result<(), String> {
+        for (name, offsets) in &local_fixups.props {
+            let value = self.props.iter_mut().find(|(n, _)| n == name)
+                .map(|(_, v)| v)
+                .ok_or_else(|| format!("local fixup for missing property {}", name))?;
+            for offset in offsets.chunks(4) {
+                let offset = u32::from_be_bytes(offset.try_into().map_err(|_| "invalid local fixup")?) as usize;
+                let field = offset.checked_add(4).and_then(|end| value.get_mut(offset..end))
+                    .ok_or("local fixup past the end of a property")?;
+                let phandle = u32::from_be_bytes((&*field).try_into().unwrap());
+                field.copy_from_slice(&add_phandle(phandle, delta)?.to_be_bytes());
+            }
+        }
+        for fixups in &local_fixups.children {
+            let i = self.child(&fixups.name).ok_or_else(|| format!("local fixup for missing node {}", fixups.name))?;
+            self.children[i].adjust_references(fixups, delta)?;
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, overlay: &Node) {
+        for (name, value) in &overlay.props {
+            self.set_prop(name, value.clone());
+        }
+        for child in &overlay.children {
+            match self.children.iter().position(|c| c.name == child.name) {
+                Some(i) => self.children[i].merge(child),
+                None => self.children.push(child.clone()),
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    strings: usize,
+    p: usize,
+}
+
+impl Parser<'_> {
+    fn token(&mut self) -> Result<u32, String> {
+        loop {
+            let token = be32(self.data, self.p)?;
+            self.p += 4;
+            if token != FDT_NOP {
+                return Ok(token);
+            }
+        }
+    }
+
+    /// Parses the node whose BEGIN_NODE token was just read
+    fn node(&mut self) -> Result<Node, String> {
+        let name = c_str(self.data, self.p)?.to_owned();
+        self.p = align4(self.p + name.len() + 1);
+        let mut node = Node { name, ..Node::default() };
+        loop {
+            match self.token()? {
+                FDT_PROP => {
+                    let len = be32(self.data, self.p)? as usize;
+                    let name = c_str(self.data, self.strings + be32(self.data, self.p + 4)? as usize)?.to_owned();
+                    let value = self.data.get(self.p + 8..self.p + 8 + len).ok_or("truncated device tree")?;
+                    node.props.push((name, value.to_vec()));
+                    self.p = align4(self.p + 8 + len);
+                }
+                FDT_BEGIN_NODE => node.children.push(self.node()?),
+                FDT_END_NODE => return Ok(node),
+                t => return Err(format!("unexpected token {:#x} in device tree", t)),
+            }
+        }
+    }
+}
+
+struct Writer {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    offsets: HashMap<String, u32>,
+}
+
+impl Writer {
+    fn node(&mut self, node: &Node) {
+        self.structure.extend_from_slice(&FDT_BEGIN_NODE.to_be_bytes());
+        self.structure.extend_from_slice(node.name.as_bytes());
+        self.structure.push(0);
+        self.structure.resize(align4(self.structure.len()), 0);
+        for (name, value) in &node.props {
+            let offset = *self.offsets.entry(name.clone()).or_insert_with(|| {
+                let offset = self.strings.len() as u32;
+                self.strings.extend_from_slice(name.as_bytes());
+                self.strings.push(0);
+                offset
+            });
+            self.structure.extend_from_slice(&FDT_PROP.to_be_bytes());
+            self.structure.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            self.structure.extend_from_slice(&offset.to_be_bytes());
+            self.structure.extend_from_slice(value);
+            self.structure.resize(align4(self.structure.len()), 0);
+        }
+        for child in &node.children {
+            self.node(child);
+        }
+        self.structure.extend_from_slice(&FDT_END_NODE.to_be_bytes());
+    }
+}
+
+impl Fdt {
+    pub fn parse(data: &[u8]) -> Result<Fdt, String> {
+        if be32(data, 0)? != FDT_MAGIC {
+            return Err("not a device tree blob".to_owned());
+        }
+        let off_struct = be32(data, 8)? as usize;
+        let off_strings = be32(data, 12)? as usize;
+        let off_rsvmap = be32(data, 16)? as usize;
+        let mut rsv_end = off_rsvmap;
+        while be32(data, rsv_end)? != 0 || be32(data, rsv_end + 4)? != 0
+            || be32(data, rsv_end + 8)? != 0 || be32(data, rsv_end + 12)? != 0 {
+            rsv_end += 16;
+        }
+        let mut parser = Parser { data, strings: off_strings, p: off_struct };
+        if parser.token()? != FDT_BEGIN_NODE {
+            return Err("device tree has no root node".to_owned());
+        }
+        let root = parser.node()?;
+        if parser.token()? != FDT_END {
+            return Err("device tree isn't terminated".to_owned());
+        }
+        Ok(Fdt {
+            root,
+            boot_cpuid: be32(data, 28)?,
+            rsvmap: data[off_rsvmap..rsv_end + 16].to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer { structure: Vec::new(), strings: Vec::new(), offsets: HashMap::new() };
+        writer.node(&self.root);
+        writer.structure.extend_from_slice(&FDT_END.to_be_bytes());
+
+        let off_rsvmap = HEADER_LEN;
+        let off_struct = off_rsvmap + self.rsvmap.len();
+        let off_strings = off_struct + writer.structure.len();
+        let total = off_strings + writer.strings.len();
+        let mut out = Vec::with_capacity(total);
+        for field in [FDT_MAGIC, total as u32, off_struct as u32, off_strings as u32, off_rsvmap as u32,
+                      17, 16, self.boot_cpuid, writer.strings.len() as u32, writer.structure.len() as u32] {
+            out.extend_from_slice(&field.to_be_bytes());
+        }
+        out.extend_from_slice(&self.rsvmap);
+        out.extend_from_slice(&writer.structure);
+        out.extend_from_slice(&writer.strings);
+        out
+    }
+
+    /// Applies an overlay compiled with symbols (dtc -@), like fdtoverlay does
+    pub fn apply(&mut self, overlay: &Fdt) -> Result<(), String> {
+        let mut overlay = overlay.root.clone();
+
+        // The overlay's own phandles move past the ones in use.
+        let delta = self.root.max_phandle();
+        overlay.adjust_phandles(delta)?;
+        if let Some(i) = overlay.child("__local_fixups__") {
+            let fixups = overlay.children.remove(i);
+            overlay.adjust_references(&fixups, delta)?;
+        }
+
+        // References to labels of the base tree.
+        if let Some(i) = overlay.child("__fixups__") {
+            let fixups = overlay.children.remove(i);
+            for (label, locations) in &fixups.props {
+                let path = self.symbol(label)?;
+                let phandle = self.root.find(&path).and_then(Node::phandle)
+                    .ok_or_else(|| format!("{} has no phandle", path))?;
+                for location in locations.split(|&b| b == 0).filter(|l| !l.is_empty()) {
+                    let location = String::from_utf8_lossy(location);
+                    let mut parts = location.rsplitn(3, ':');
+                    let (offset, prop, node) = (parts.next(), parts.next(), parts.next());
+                    let (Some(offset), Some(prop), Some(node)) = (offset, prop, node) else {
+                        return Err(format!("invalid fixup {}", location));
+                    };
+                    let offset: usize = offset.parse().map_err(|_| format!("invalid fixup {}", location))?;
+                    let field = overlay.find_mut(node)
+                        .and_then(|n| n.props.iter_mut().find(|(n, _)| n == prop))
+                        .and_then(|(_, v)| v.get_mut(offset..offset.checked_add(4)?))
+                        .ok_or_else(|| format!("invalid fixup {}", location))?;
+                    field.copy_from_slice(&phandle.to_be_bytes());
+                }
+            }
+        }
+
+        let symbols = overlay.child("__symbols__").map(|i| overlay.children.remove(i));
+        let mut targets = HashMap::new();
+        for fragment in &overlay.children {
+            let Some(content) = fragment.child("__overlay__").map(|i| &fragment.children[i]) else {
+                continue;
+            };
+            let target = if let Some(path) = fragment.prop("target-path") {
+                String::from_utf8_lossy(path).trim_end_matches('\0').to_owned()
+            } else if let Some(phandle) = fragment.prop("target").and_then(|t| Some(u32::from_be_bytes(t.try_into().ok()?))) {
+                self.root.path_of_phandle(phandle, "")
+                    .ok_or_else(|| format!("target of {} not found", fragment.name))?
+            } else {
+                return Err(format!("{} has no target", fragment.name));
+            };
+            self.root.find_mut(&target)
+                .ok_or_else(|| format!("target {} of {} not found", target, fragment.name))?
+                .merge(content);
+            targets.insert(fragment.name.clone(), target);
+        }
+
+        // Labels in the overlay become labels of the merged nodes.
+        if let Some(symbols) = symbols {
+            for (label, path) in &symbols.props {
+                let path = String::from_utf8_lossy(path).trim_end_matches('\0').to_owned();
+                let mut parts = path.trim_start_matches('/').splitn(3, '/');
+                let (fragment, rest) = (parts.next().unwrap_or(""), parts.nth(1).unwrap_or(""));
+                if let Some(target) = targets.get(fragment) {
+                    let merged = format!("{}/{}", target.trim_end_matches('/'), rest);
+                    let merged = match merged.trim_end_matches('/') {
+                        "" => "/",
+                        path => path,
+                    };
+                    let mut value = merged.as_bytes().to_vec();
+                    value.push(0);
+                    if self.root.child("__symbols__").is_none() {
+                        self.root.children.push(Node { name: "__symbols__".to_owned(), ..Node::default() });
+                    }
+                    self.root.find_mut("__symbols__").unwrap().set_prop(label, value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn symbol(&self, label: &str) -> Result<String, String> {
+        let path = self.root.find("__symbols__").and_then(|s| s.prop(label))
+            .ok_or_else(|| format!("label {} not found in the device tree, was it compiled with -@?", label))?;
+        Ok(String::from_utf8_lossy(path).trim_end_matches('\0').to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str, props: &[(&str, &[u8])], children: Vec<Node>) -> Node {
+        let props = props.iter().map(|(n, v)| (n.to_string(), v.to_vec())).collect();
+        Node { name: name.to_owned(), props, children }
+    }
+
+    fn fdt(root: Node) -> Fdt {
+        // Through a blob and back, so the writer and the parser are covered too.
+        let blob = Fdt { root, boot_cpuid: 0, rsvmap: vec![0; 16] }.to_bytes();
+        assert_eq!(blob_len(&blob), Some(blob.len()));
+        Fdt::parse(&blob).unwrap()
+    }
+
+    /// What `dtc -@` compiles from
+    ///
+    /// ```dts
+    /// / {
+    ///     soc {
+    ///         uart0: serial@11002000 { status = "disabled"; };
+    ///         pio: pinctrl@1001f000 { };
+    ///     };
+    /// };
+    /// ```
+    fn base() -> Fdt {
+        fdt(node("", &[("compatible", b"mediatek,mt7986a\0")], vec![
+            node("soc", &[], vec![
+                node("serial@11002000", &[("status", b"disabled\0"), ("phandle", &[0, 0, 0, 1])], vec![]),
+                node("pinctrl@1001f000", &[("phandle", &[0, 0, 0, 2])], vec![]),
+            ]),
+            node("__symbols__", &[("uart0", b"/soc/serial@11002000\0"), ("pio", b"/soc/pinctrl@1001f000\0")], vec![]),
+        ]))
+    }
+
+    /// What `dtc -@` compiles from
+    ///
+    /// ```dts
+    /// /plugin/;
+    /// &uart0 { status = "okay"; pinctrl-0 = <&uart0_pins>; };
+    /// &pio { uart0_pins: uart0-pins { function = "uart"; }; };
+    /// ```
+    fn overlay() -> Fdt {
+        let unresolved: &[u8] = &[0xff; 4];
+        fdt(node("", &[], vec![
+            node("fragment@0", &[("target", unresolved)], vec![
+                node("__overlay__", &[("status", b"okay\0"), ("pinctrl-0", &[0, 0, 0, 1])], vec![]),
+            ]),
+            node("fragment@1", &[("target", unresolved)], vec![
+                node("__overlay__", &[], vec![
+                    node("uart0-pins", &[("function", b"uart\0"), ("phandle", &[0, 0, 0, 1])], vec![]),
+                ]),
+            ]),
+            node("__symbols__", &[("uart0_pins", b"/fragment@1/__overlay__/uart0-pins\0")], vec![]),
+            node("__fixups__", &[("uart0", b"/fragment@0:target:0\0"), ("pio", b"/fragment@1:target:0\0")], vec![]),
+            node("__local_fixups__", &[], vec![
+                node("fragment@0", &[], vec![node("__overlay__", &[("pinctrl-0", &[0, 0, 0, 0])], vec![])]),
+            ]),
+        ]))
+    }
+
+    #[test]
+    fn overlay_is_applied_like_fdtoverlay() {
+        let mut fdt = base();
+        fdt.apply(&overlay()).unwrap();
+        let fdt = Fdt::parse(&fdt.to_bytes()).unwrap();
+
+        // As `fdtoverlay -i base.dtb -o merged.dtb overlay.dtbo` merges them: the phandles of
+        // the overlay move past the 2 of the base, and its labels point into the base.
+        let serial = fdt.root.find("/soc/serial@11002000").unwrap();
+        assert_eq!(serial.prop("status"), Some(&b"okay\0"[..]));
+        assert_eq!(serial.prop("pinctrl-0"), Some(&[0, 0, 0, 3][..]));
+        let pins = fdt.root.find("/soc/pinctrl@1001f000/uart0-pins").unwrap();
+        assert_eq!(pins.prop("function"), Some(&b"uart\0"[..]));
+        assert_eq!(pins.phandle(), Some(3));
+        assert_eq!(fdt.symbol("uart0_pins").unwrap(), "/soc/pinctrl@1001f000/uart0-pins");
+        assert_eq!(fdt.symbol("uart0").unwrap(), "/soc/serial@11002000");
+        assert!(fdt.root.children.iter().all(|c| !c.name.starts_with("fragment@") && c.name != "__fixups__"));
+    }
+
+    #[test]
+    fn overlay_needs_the_labels_of_the_base() {
+        let mut fdt = base();
+        fdt.root.children.retain(|c| c.name != "__symbols__");
+        assert!(fdt.apply(&overlay()).unwrap_err().contains("label uart0 not found"));
+    }
+
+    #[test]
+    fn phandles_past_the_last_one_are_refused() {
+        let mut fdt = base();
+        fdt.root.find_mut("/soc/pinctrl@1001f000").unwrap().set_prop("phandle", vec![0xff, 0xff, 0xff, 0xfe]);
+        assert!(fdt.apply(&overlay()).unwrap_err().contains("phandles"));
+    }
+
+    #[test]
+    fn fixups_past_the_end_of_a_property_are_refused() {
+        let mut overlay = overlay();
+        overlay.root.find_mut("/__fixups__").unwrap().set_prop("uart0", b"/fragment@0:target:18446744073709551615\0".to_vec());
+        assert!(base().apply(&overlay).unwrap_err().contains("invalid fixup"));
+    }
+}
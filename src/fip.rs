@@ -0,0 +1,170 @@
+/// Name of the ToC header of a Firmware Image Package, as in TF-A's firmware_image_package.h
+const TOC_HEADER_NAME: u32 = 0xaa64_0001;
+const TOC_HEADER_LEN: usize = 16;
+const TOC_ENTRY_LEN: usize = 40;
+
+/// UUIDs of the images TF-A's fiptool knows, by its names for them
+const KNOWN_UUIDS: &[(&str, &str)] = &[
+    ("tb-fw", "5ff9ec0b-4d22-3e4d-a544-c39d81c73f0a"),
+    ("scp-fw", "9766fd3d-89be-e849-ae5d-78a140608213"),
+    ("soc-fw", "47d4086d-4cfe-9846-9b95-2950cbbd5a00"),
+    ("tos-fw", "05d0e189-53dc-1347-8d2b-500a4b7a3e38"),
+    ("tos-fw-extra1", "0b70c29b-2a5a-7840-9f65-0a5682738288"),
+    ("tos-fw-extra2", "8ea87bb1-cfa2-3f4d-85fd-e7bba50220d9"),
+    ("nt-fw", "d6d0eea7-fcea-d54b-9782-9934f234b6e4"),
+    ("hw-config", "08b8f1d9-c9cf-9349-a962-6fbc6b7265cc"),
+    ("tb-fw-config", "26257c1a-dbc6-7f47-8d96-c4c4b0248021"),
+    ("soc-fw-config", "9979814b-0376-fb46-8c8e-8d267f7859e0"),
+    ("nt-fw-config", "28da9815-93e8-7e44-ac66-1aaf801550f9"),
+];
+
+/// TF-A's names for the boot stages, accepted for the images holding them
+const STAGE_NAMES: &[(&str, &str)] = &[
+    ("bl2", "tb-fw"),
+    ("scp_bl2", "scp-fw"),
+    ("bl31", "soc-fw"),
+    ("bl32", "tos-fw"),
+    ("bl33", "nt-fw"),
+];
+
+/// An image in a FIP.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub uuid: [u8; 16],
+    pub offset: usize,
+    pub size: usize,
+    pub flags: u64,
+}
+
+impl Entry {
+    /// The UUID as fiptool prints it
+    pub fn uuid_string(&self) -> String {
+        let hex: String = self.uuid.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+    }
+
+    /// fiptool's name for the image, if it's a known one
+    pub fn name(&self) -> Option<&'static str> {
+        let uuid = self.uuid_string();
+        KNOWN_UUIDS.iter().find(|(_, u)| *u == uuid).map(|(name, _)| *name)
+    }
+
+    /// The name, or the UUID of unknown images
+    pub fn label(&self) -> String {
+        self.name().map_or_else(|| self.uuid_string(), str::to_owned)
+    }
+}
+
+/// The table of contents of a Firmware Image Package.
+#[derive(Clone, Debug)]
+pub struct Fip {
+    pub entries: Vec<Entry>,
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+impl Fip {
+    pub fn parse(data: &[u8]) -> Result<Fip, String> {
+        if data.len() < TOC_HEADER_LEN || u32_at(data, 0) != TOC_HEADER_NAME {
+            return Err("not a FIP, the ToC header is missing".to_owned());
+        }
+        let mut entries = Vec::new();
+        let mut p = TOC_HEADER_LEN;
+        loop {
+            if p + TOC_ENTRY_LEN > data.len() {
+                return Err("the ToC isn't terminated".to_owned());
+            }
+            let uuid: [u8; 16] = data[p..p + 16].try_into().unwrap();
+            // The ToC ends with an entry with a null UUID, which fiptool points past the
+            // last image.
+            if uuid == [0; 16] {
+                let end = u64_at(data, p + 16);
+                if end > data.len() as u64 {
+                    return Err(format!("the FIP is truncated, it has {} of {} bytes", data.len(), end));
+                }
+                break;
+            }
+            let entry = Entry {
+                uuid,
+                offset: u64_at(data, p + 16) as usize,
+                size: u64_at(data, p + 24) as usize,
+                flags: u64_at(data, p + 32),
+            };
+            if entry.offset.checked_add(entry.size).is_none_or(|end| end > data.len()) {
+                return Err(format!("{} extends past the end of the FIP", entry.label()));
+            }
+            entries.push(entry);
+            p += TOC_ENTRY_LEN;
+        }
+        Ok(Fip { entries })
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.name() == Some(name))
+    }
+
+    /// Checks that Mediatek's BL2 can boot the FIP: it needs a BL31 and a BL33 to load.
+    pub fn check_bootable(&self) -> Result<(), String> {
+        for (stage, name) in [("BL31", "soc-fw"), ("BL33", "nt-fw")] {
+            if self.find(name).is_none() {
+                return Err(format!("the FIP has no {} ({})", stage, name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the contents of an image, named like fiptool does or by its boot stage, e.g.
+    /// bl33 for nt-fw. Returns the FIP laid out again.
+    pub fn replace(&self, data: &[u8], name: &str, image: Vec<u8>) -> Result<Vec<u8>, String> {
+        let lower = name.to_ascii_lowercase();
+        let label = STAGE_NAMES.iter().find(|(stage, _)| *stage == lower).map_or(name, |(_, n)| *n);
+        let i = self.entries.iter().position(|e| e.label() == label)
+            .ok_or_else(|| format!("the FIP has no {}", name))?;
+        let mut images = self.images(data);
+        images[i] = image;
+        Ok(self.rebuild(data, &images))
+    }
+
+    /// Lays the FIP out again with new contents for its images, in the same order and with
+    /// the same alignment.
+    pub fn rebuild(&self, data: &[u8], images: &[Vec<u8>]) -> Vec<u8> {
+        // The largest power of two up to a page all images were aligned to
+        let align = self.entries.iter()
+            .map(|e| 1usize << e.offset.trailing_zeros().min(12))
+            .min()
+            .unwrap_or(1);
+        let align_up = |n: usize| n.div_ceil(align) * align;
+
+        let toc_len = TOC_HEADER_LEN + TOC_ENTRY_LEN * (self.entries.len() + 1);
+        let mut offset = align_up(toc_len);
+        let mut toc = data[..TOC_HEADER_LEN].to_vec();
+        let mut body = Vec::new();
+        for (entry, image) in self.entries.iter().zip(images) {
+            toc.extend_from_slice(&entry.uuid);
+            toc.extend_from_slice(&(offset as u64).to_le_bytes());
+            toc.extend_from_slice(&(image.len() as u64).to_le_bytes());
+            toc.extend_from_slice(&entry.flags.to_le_bytes());
+            body.resize(offset - align_up(toc_len), 0);
+            body.extend_from_slice(image);
+            offset = align_up(offset + image.len());
+        }
+        // Like fiptool, the terminating entry points past the last image.
+        toc.extend_from_slice(&[0; 16]);
+        toc.extend_from_slice(&((align_up(toc_len) + body.len()) as u64).to_le_bytes());
+        toc.extend_from_slice(&[0; 16]);
+        toc.resize(align_up(toc_len), 0);
+        toc.extend_from_slice(&body);
+        toc
+    }
+
+    /// The contents of all images, in order
+    pub fn images(&self, data: &[u8]) -> Vec<Vec<u8>> {
+        self.entries.iter().map(|e| data[e.offset..e.offset + e.size].to_vec()).collect()
+    }
+}
@@ -0,0 +1,51 @@
+use mtk_uartboot::digest::{Sha256, to_hex};
+use crate::fip::{Entry, Fip};
+
+/// The start of the SHA-256 of an image, enough to tell images apart in the summary
+fn short_hash(data: &[u8]) -> String {
+    to_hex(&Sha256::digest(data))[..16].to_owned()
+}
+
+fn image<'a>(data: &'a [u8], entry: &Entry) -> &'a [u8] {
+    &data[entry.offset..entry.offset + entry.size]
+}
+
+/// Compares the images of two FIPs by UUID, size and hash and prints what changed. Returns
+/// whether the images are the same.
+pub fn run(a_path: &str, b_path: &str) -> Result<bool, String> {
+    let a = std::fs::read(a_path).map_err(|e| format!("{}: {}", a_path, e))?;
+    let b = std::fs::read(b_path).map_err(|e| format!("{}: {}", b_path, e))?;
+    let a_toc = Fip::parse(&a).map_err(|e| format!("{}: {}", a_path, e))?;
+    let b_toc = Fip::parse(&b).map_err(|e| format!("{}: {}", b_path, e))?;
+
+    let mut changed = 0;
+    let mut b_left: Vec<&Entry> = b_toc.entries.iter().collect();
+    for a_entry in &a_toc.entries {
+        let label = a_entry.label();
+        let Some(i) = b_left.iter().position(|e| e.uuid == a_entry.uuid) else {
+            println!("{:<14} removed   {} bytes, sha256 {}", label, a_entry.size, short_hash(image(&a, a_entry)));
+            changed += 1;
+            continue;
+        };
+        let b_entry = b_left.remove(i);
+        let (a_image, b_image) = (image(&a, a_entry), image(&b, b_entry));
+        if a_image == b_image {
+            println!("{:<14} same      {} bytes", label, a_entry.size);
+        } else {
+            println!("{:<14} changed   {} -> {} bytes, sha256 {} -> {}", label, a_entry.size, b_entry.size,
+                     short_hash(a_image), short_hash(b_image));
+            changed += 1;
+        }
+    }
+    for b_entry in b_left {
+        println!("{:<14} added     {} bytes, sha256 {}", b_entry.label(), b_entry.size, short_hash(image(&b, b_entry)));
+        changed += 1;
+    }
+
+    if changed == 0 {
+        println!("The images are the same.");
+    } else {
+        println!("{} image(s) differ.", changed);
+    }
+    Ok(changed == 0)
+}
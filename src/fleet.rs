@@ -0,0 +1,209 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use mtk_uartboot::bootrom::TargetConfig;
+
+/// How long a unit waits for the others to tell what they are, e.g. while they're powered
+/// one after another. The watchdog of its BootROM may be running meanwhile.
+const WAIT: Duration = Duration::from_secs(60);
+
+const OUTLIER_STYLE: &str = "\x1b[1;31m";
+const RESET_STYLE: &str = "\x1b[0m";
+
+/// The fields compared, as they're named in reports
+const FIELDS: [&str; 5] = ["hw_code", "hw_sub_code", "hw_ver", "sw_ver", "target_config"];
+
+/// What the BootROM of a unit tells about it
+pub struct Identity {
+    pub hw_code: u16,
+    pub hw_sub_code: u16,
+    pub hw_ver: u16,
+    pub sw_ver: u16,
+    /// The whole word of CMD_GET_TARGET_CONFIG
+    pub target_config: u32,
+}
+
+impl Identity {
+    /// The fields as they're shown, in the order of FIELDS
+    fn values(&self) -> [String; 5] {
+        let config = TargetConfig::from_word(self.target_config);
+        let flags: Vec<&str> = [(config.secure_boot, "SBC"), (config.serial_link_authorization, "SLA"),
+                                (config.download_agent_authorization, "DAA")]
+            .into_iter().filter(|(set, _)| *set).map(|(_, name)| name).collect();
+        let target_config = if flags.is_empty() {
+            format!("{:#x}", self.target_config)
+        } else {
+            format!("{:#x} {}", self.target_config, flags.join(","))
+        };
+        [format!("{:#x}", self.hw_code), format!("{:#x}", self.hw_sub_code), format!("{:#x}", self.hw_ver),
+         format!("{:#x}", self.sw_ver), target_config]
+    }
+}
+
+enum Unit {
+    /// Not through the handshake yet
+    Waiting,
+    Arrived([String; 5]),
+    /// Its run ended before it told what it is
+    Gone,
+}
+
+/// The units of a run on several ports, comparing what their BootROMs tell before anything
+/// is sent to any of them, so one of a different provenance stands out.
+pub struct Fleet {
+    units: Mutex<BTreeMap<String, Unit>>,
+    changed: Condvar,
+    /// Whether the table was printed for the ports expected last
+    printed: Mutex<bool>,
+}
+
+thread_local! {
+    /// The fleet and port of the unit the thread runs
+    static MEMBER: RefCell<Option<(Arc<Fleet>, String)>> = const { RefCell::new(None) };
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Fleet {
+    pub fn new() -> Arc<Fleet> {
+        Arc::new(Fleet { units: Mutex::new(BTreeMap::new()), changed: Condvar::new(), printed: Mutex::new(false) })
+    }
+
+    /// Waits for the unit on port to tell what it is, again if it did in an earlier run.
+    /// Units which already told stay in the comparison.
+    pub fn expect(&self, port: &str) {
+        lock(&self.units).insert(port.to_owned(), Unit::Waiting);
+        *lock(&self.printed) = false;
+    }
+
+    /// Makes the current thread run the unit on port, until the returned guard is dropped
+    pub fn join(self: &Arc<Self>, port: &str) -> Member {
+        MEMBER.set(Some((self.clone(), port.to_owned())));
+        Member
+    }
+
+    /// Records what the unit on port is, waits for the others and returns the fields in
+    /// which it differs from most of them
+    fn compare(&self, port: &str, identity: &Identity) -> Vec<&'static str> {
+        let mut units = lock(&self.units);
+        units.insert(port.to_owned(), Unit::Arrived(identity.values()));
+        self.changed.notify_all();
+        let deadline = Instant::now() + WAIT;
+        while units.values().any(|u| matches!(u, Unit::Waiting)) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            units = self.changed.wait_timeout(units, left).unwrap_or_else(|e| e.into_inner()).0;
+        }
+        let arrived: BTreeMap<&str, &[String; 5]> = units.iter()
+            .filter_map(|(port, unit)| match unit {
+                Unit::Arrived(values) => Some((port.as_str(), values)),
+                _ => None,
+            })
+            .collect();
+        let outliers = outliers(&arrived);
+        let waiting = units.values().filter(|u| matches!(u, Unit::Waiting)).count();
+        let mut printed = lock(&self.printed);
+        if !*printed {
+            *printed = true;
+            print!("{}", render(&arrived, &outliers, waiting, std::io::stdout().is_terminal()));
+        }
+        outliers.get(port).cloned().unwrap_or_default()
+    }
+}
+
+/// Leaves the fleet when the run of a unit ends, so the others don't wait for it if it
+/// didn't get through the handshake
+pub struct Member;
+
+impl Drop for Member {
+    fn drop(&mut self) {
+        let Some((fleet, port)) = MEMBER.take() else { return };
+        let mut units = lock(&fleet.units);
+        if let Some(unit @ Unit::Waiting) = units.get_mut(&port) {
+            *unit = Unit::Gone;
+            fleet.changed.notify_all();
+        }
+    }
+}
+
+/// Compares what the BootROM of the unit the thread runs told with the other units of its
+/// fleet, once they told too. Returns the fields in which it differs from most of them,
+/// None outside of a fleet.
+pub fn compare(identity: &Identity) -> Option<Vec<&'static str>> {
+    MEMBER.with_borrow(|member| member.as_ref().map(|(fleet, port)| fleet.compare(port, identity)))
+}
+
+/// The fields of each unit which differ from the value most units have. Where no value is
+/// held by more than half of them, none can be trusted and all units differ.
+fn outliers(units: &BTreeMap<&str, &[String; 5]>) -> BTreeMap<String, Vec<&'static str>> {
+    let mut outliers: BTreeMap<String, Vec<&'static str>> = BTreeMap::new();
+    if units.len() < 2 {
+        return outliers;
+    }
+    for (i, field) in FIELDS.iter().enumerate() {
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for values in units.values() {
+            *counts.entry(&values[i]).or_default() += 1;
+        }
+        let majority = counts.iter().find(|(_, &n)| n * 2 > units.len()).map(|(value, _)| *value);
+        for (port, values) in units {
+            if majority != Some(values[i].as_str()) {
+                outliers.entry(port.to_string()).or_default().push(field);
+            }
+        }
+    }
+    outliers
+}
+
+/// The table of the units, the fields in which they differ marked with * and in red on a
+/// terminal
+fn render(units: &BTreeMap<&str, &[String; 5]>, outliers: &BTreeMap<String, Vec<&'static str>>, waiting: usize, color: bool) -> String {
+    let header: Vec<String> = FIELDS.iter().map(|f| f.replace('_', " ")).collect();
+    let cells: Vec<(&str, Vec<(String, bool)>)> = units.iter()
+        .map(|(port, values)| {
+            let differs = outliers.get(*port);
+            (*port, values.iter().zip(FIELDS)
+                .map(|(value, field)| {
+                    let differs = differs.is_some_and(|d| d.contains(&field));
+                    (if differs { format!("{} *", value) } else { value.clone() }, differs)
+                })
+                .collect())
+        })
+        .collect();
+    let port_width = cells.iter().map(|(port, _)| port.len()).chain(["port".len()]).max().unwrap();
+    let widths: Vec<usize> = header.iter().enumerate()
+        .map(|(i, h)| cells.iter().map(|(_, row)| row[i].0.len()).chain([h.len()]).max().unwrap())
+        .collect();
+
+    let mut out = format!("BootROMs of {} ports:\n  {:<port_width$}", units.len(), "port");
+    for (h, width) in header.iter().zip(&widths) {
+        out.push_str(&format!("  {:<width$}", h));
+    }
+    out.push('\n');
+    for (port, row) in &cells {
+        out.push_str(&format!("  {:<port_width$}", port));
+        for ((text, differs), width) in row.iter().zip(&widths) {
+            let cell = format!("{:<width$}", text);
+            if *differs && color {
+                out.push_str(&format!("  {}{}{}", OUTLIER_STYLE, cell, RESET_STYLE));
+            } else {
+                out.push_str(&format!("  {}", cell));
+            }
+        }
+        out.truncate(out.trim_end().len());
+        out.push('\n');
+    }
+    if waiting > 0 {
+        out.push_str(&format!("  {} more didn't get through the handshake within {} s.\n", waiting, WAIT.as_secs()));
+    }
+    if outliers.is_empty() {
+        out.push_str("  All the same.\n");
+    }
+    out
+}
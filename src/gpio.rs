@@ -0,0 +1,119 @@
+use std::fs::File;
+use std::io;
+use std::time::Duration;
+
+/// A GPIO line of the host, e.g. of a Raspberry Pi, wired to the reset or a boot strap pin of
+/// the device. Given as N, N:active-low or N:active-high, where N is the line of the GPIO chip.
+#[derive(Clone, Debug)]
+pub struct GpioLine {
+    pub offset: u32,
+    pub active_low: bool,
+}
+
+impl GpioLine {
+    /// Parses a line, which is active low unless said otherwise if active_low is set.
+    pub fn parse(spec: &str, active_low: bool) -> Result<GpioLine, String> {
+        let (offset, active_low) = match spec.split_once(':') {
+            None => (spec, active_low),
+            Some((offset, "active-low")) => (offset, true),
+            Some((offset, "active-high")) => (offset, false),
+            Some((_, polarity)) => return Err(format!("expected active-low or active-high, not {}", polarity)),
+        };
+        let offset = offset.parse().map_err(|_| format!("invalid GPIO line {}", offset))?;
+        Ok(GpioLine { offset, active_low })
+    }
+}
+
+/// A line driven by this process until dropped, when it's released again.
+pub struct GpioOutput {
+    handle: File,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod chardev {
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use super::GpioLine;
+
+    // GPIO_GET_LINEHANDLE_IOCTL: _IOWR(0xb4, 0x03, struct gpiohandle_request)
+    const GET_LINEHANDLE: libc::Ioctl = 0xc16c_b403_u32 as libc::Ioctl;
+    // GPIOHANDLE_SET_LINE_VALUES_IOCTL: _IOWR(0xb4, 0x09, struct gpiohandle_data)
+    const SET_LINE_VALUES: libc::Ioctl = 0xc040_b409_u32 as libc::Ioctl;
+    const REQUEST_OUTPUT: u32 = 1 << 1;
+    const REQUEST_ACTIVE_LOW: u32 = 1 << 2;
+
+    #[repr(C)]
+    struct HandleRequest {
+        line_offsets: [u32; 64],
+        flags: u32,
+        default_values: [u8; 64],
+        consumer_label: [u8; 32],
+        lines: u32,
+        fd: libc::c_int,
+    }
+
+    pub fn request(chip: &str, line: &GpioLine, asserted: bool) -> io::Result<File> {
+        let chip = File::open(chip)?;
+        let mut request = HandleRequest {
+            line_offsets: [0; 64],
+            flags: REQUEST_OUTPUT | if line.active_low { REQUEST_ACTIVE_LOW } else { 0 },
+            default_values: [0; 64],
+            consumer_label: [0; 32],
+            lines: 1,
+            fd: -1,
+        };
+        request.line_offsets[0] = line.offset;
+        request.default_values[0] = asserted as u8;
+        request.consumer_label[..12].copy_from_slice(b"mtk_uartboot");
+        // SAFETY: the request has the layout of struct gpiohandle_request the request code says.
+        if unsafe { libc::ioctl(chip.as_raw_fd(), GET_LINEHANDLE, &mut request) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the kernel returned a new file descriptor of the line handle, owned by us.
+        Ok(unsafe { File::from_raw_fd(request.fd) })
+    }
+
+    pub fn set(handle: &File, asserted: bool) -> io::Result<()> {
+        let mut values = [0u8; 64];
+        values[0] = asserted as u8;
+        // SAFETY: values has the layout of struct gpiohandle_data the request code says.
+        if unsafe { libc::ioctl(handle.as_raw_fd(), SET_LINE_VALUES, values.as_mut_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+mod chardev {
+    use std::fs::File;
+    use std::io;
+    use super::GpioLine;
+
+    pub fn request(_chip: &str, _line: &GpioLine, _asserted: bool) -> io::Result<File> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "GPIOs are only supported on Linux"))
+    }
+
+    pub fn set(_handle: &File, _asserted: bool) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "GPIOs are only supported on Linux"))
+    }
+}
+
+impl GpioOutput {
+    /// Drives a line of the GPIO chip, e.g. /dev/gpiochip0, asserted or not.
+    pub fn request(chip: &str, line: &GpioLine, asserted: bool) -> io::Result<GpioOutput> {
+        Ok(GpioOutput { handle: chardev::request(chip, line, asserted)? })
+    }
+
+    pub fn set(&self, asserted: bool) -> io::Result<()> {
+        chardev::set(&self.handle, asserted)
+    }
+}
+
+/// Asserts a line for the duration, e.g. to reset the device, and releases it.
+pub fn pulse(chip: &str, line: &GpioLine, duration: Duration) -> io::Result<()> {
+    let output = GpioOutput::request(chip, line, true)?;
+    std::thread::sleep(duration);
+    output.set(false)
+}
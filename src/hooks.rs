@@ -0,0 +1,109 @@
+#[cfg(feature = "pipeline")]
+use std::io::Write;
+use std::process::Command;
+#[cfg(feature = "pipeline")]
+use std::process::Stdio;
+#[cfg(feature = "hooks")]
+use std::time::{Duration, Instant};
+
+/// A shell command line, run with `sh -c`, or `cmd /C` on Windows, like the auth commands,
+/// for the hooks, telemetry and pause commands
+pub(crate) fn shell(cmd: &str) -> Command {
+    let mut command = Command::new(if cfg!(windows) { "cmd" } else { "sh" });
+    command.arg(if cfg!(windows) { "/C" } else { "-c" }).arg(cmd);
+    command
+}
+
+/// Checks an interface name against a pattern which may end with a '*' wildcard.
+#[cfg(feature = "hooks")]
+fn netif_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    }
+}
+
+#[cfg(all(feature = "hooks", any(target_os = "linux", target_os = "android")))]
+fn list_netifs() -> Vec<String> {
+    match std::fs::read_dir("/sys/class/net") {
+        Ok(dir) => dir
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(all(feature = "hooks", not(any(target_os = "linux", target_os = "android"))))]
+fn list_netifs() -> Vec<String> {
+    panic!("Waiting for network interfaces is only supported on Linux.");
+}
+
+/// Waits for a network interface matching the pattern to appear and returns its name.
+#[cfg(feature = "hooks")]
+pub fn wait_for_netif(pattern: &str, timeout: Duration) -> Option<String> {
+    println!("Waiting for network interface {}...", pattern);
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        if let Some(name) = list_netifs().into_iter().find(|n| netif_matches(pattern, n)) {
+            println!("Network interface {} is up.", name);
+            return Some(name);
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+    println!("Timeout waiting for network interface {}.", pattern);
+    None
+}
+
+/// A hook, with the serial port in MTK_UARTBOOT_SERIAL
+#[cfg(feature = "hooks")]
+fn hook(cmd: &str, serial: &str) -> Command {
+    let mut command = shell(cmd);
+    command.env("MTK_UARTBOOT_SERIAL", serial);
+    command
+}
+
+/// Runs a shell command with the session details passed in MTK_UARTBOOT_* environment variables.
+#[cfg(feature = "hooks")]
+pub fn run_hook(cmd: &str, serial: &str, netif: Option<&str>) -> bool {
+    println!("Running hook: {}", cmd);
+    let mut command = hook(cmd, serial);
+    if let Some(netif) = netif {
+        command.env("MTK_UARTBOOT_NETIF", netif);
+    }
+
+    match command.status() {
+        Ok(status) if status.success() => true,
+        Ok(status) => {
+            println!("Hook failed: {}", status);
+            false
+        }
+        Err(e) => {
+            println!("Failed to run hook: {}", e);
+            false
+        }
+    }
+}
+
+/// Like run_hook, but with input written to the command's stdin, e.g. a label to print.
+#[cfg(feature = "pipeline")]
+pub fn pipe_to_hook(cmd: &str, serial: &str, input: &[u8]) -> bool {
+    println!("Running hook: {}", cmd);
+    let child = hook(cmd, serial).stdin(Stdio::piped()).spawn();
+    let status = child.and_then(|mut child| {
+        // Dropping stdin closes it, so the command sees the end of its input.
+        child.stdin.take().unwrap().write_all(input)?;
+        child.wait()
+    });
+    match status {
+        Ok(status) if status.success() => true,
+        Ok(status) => {
+            println!("Hook failed: {}", status);
+            false
+        }
+        Err(e) => {
+            println!("Failed to run hook: {}", e);
+            false
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+use crate::output::{self, Recorded};
+use crate::report::Report;
+
+/// Where --report-html writes the report at the end of the run
+static PATH: OnceLock<String> = OnceLock::new();
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;max-width:70em}\
+table{border-collapse:collapse;margin-bottom:1.5em}\
+td,th{border:1px solid #ccc;padding:.2em .6em;text-align:left;vertical-align:top}\
+pre{background:#f4f4f4;padding:.6em;overflow-x:auto}\
+.ok{color:#176b1c}.failed,.error{color:#b3141b}code{word-break:break-all}";
+
+/// Escapes text for HTML
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// The text of a JSON value of an event field: strings without their quotes and escapes,
+/// anything else as it is
+fn json_text(value: &str) -> String {
+    let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+        return value.to_owned();
+    };
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                out.extend(u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32));
+            }
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+fn seconds(t: Duration) -> String {
+    format!("{}.{:03}", t.as_secs(), t.subsec_millis())
+}
+
+/// A table of report entries, or nothing if there are none
+fn entry_table(out: &mut String, title: &str, entries: &[&(String, String)]) {
+    if entries.is_empty() {
+        return;
+    }
+    out.push_str(&format!("<h2>{}</h2>\n<table>\n", title));
+    for (key, value) in entries {
+        out.push_str(&format!("<tr><th>{}</th><td><code>{}</code></td></tr>\n", escape(key), escape(value)));
+    }
+    out.push_str("</table>\n");
+}
+
+/// Renders the events of the run and its report as a standalone HTML page, for attaching
+/// to tickets: the outcome, the device, the events with their times, the warnings, the
+/// hashes of what was sent, the console excerpts and all of the report.
+pub fn render(status: &str, error: Option<&str>, exit_code: i32, events: &[Recorded], report: &Report) -> String {
+    let entries = report.entries();
+    let mut out = String::new();
+    out.push_str(&format!("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>mtk_uartboot report</title>\
+                           <style>{}</style></head><body>\n", STYLE));
+    out.push_str(&format!("<h1>mtk_uartboot report</h1>\n<p>mtk_uartboot {}. Result: <b class=\"{}\">{}</b>, exit code {}.</p>\n",
+                          env!("CARGO_PKG_VERSION"), escape(status), escape(status), exit_code));
+    if let Some(error) = error {
+        out.push_str(&format!("<pre class=\"error\">{}</pre>\n", escape(error)));
+    }
+
+    let device: Vec<_> = entries.iter().filter(|(k, _)| k.starts_with("device.")).collect();
+    entry_table(&mut out, "Device", &device);
+
+    out.push_str("<h2>Events</h2>\n<table>\n<tr><th>Time (s)</th><th>Event</th><th>Details</th></tr>\n");
+    for (t, event, fields) in events {
+        let details: Vec<String> = fields.iter()
+            .filter(|(key, value)| key != "report" && value != "null")
+            .map(|(key, value)| format!("{}={}", escape(key), escape(&json_text(value))))
+            .collect();
+        out.push_str(&format!("<tr><td>{}</td><td>{}</td><td>{}</td></tr>\n", seconds(*t), escape(event), details.join(", ")));
+    }
+    out.push_str("</table>\n");
+
+    out.push_str("<h2>Warnings</h2>\n");
+    let warnings: Vec<String> = events.iter()
+        .filter(|(_, event, _)| event == "warning")
+        .flat_map(|(_, _, fields)| fields.iter().filter(|(k, _)| k == "message").map(|(_, v)| json_text(v)))
+        .collect();
+    if warnings.is_empty() {
+        out.push_str("<p>None.</p>\n");
+    } else {
+        out.push_str("<ul>\n");
+        for warning in &warnings {
+            out.push_str(&format!("<li>{}</li>\n", escape(warning)));
+        }
+        out.push_str("</ul>\n");
+    }
+
+    let hashes: Vec<_> = entries.iter().filter(|(k, _)| k.ends_with(".sha256") || k.ends_with(".crc32")).collect();
+    entry_table(&mut out, "Hashes", &hashes);
+
+    let excerpts: Vec<_> = entries.iter().filter(|(k, _)| k.starts_with("excerpt.") && k.ends_with(".pattern")).collect();
+    if !excerpts.is_empty() {
+        out.push_str("<h2>Console excerpts</h2>\n");
+        for (key, pattern) in excerpts {
+            let prefix = format!("{}line.", key.trim_end_matches("pattern"));
+            let lines: Vec<&str> = entries.iter().filter(|(k, _)| k.starts_with(&prefix)).map(|(_, v)| v.as_str()).collect();
+            out.push_str(&format!("<p>Around <code>{}</code>:</p>\n<pre>{}</pre>\n", escape(pattern), escape(&lines.join("\n"))));
+        }
+    }
+
+    let all: Vec<_> = entries.iter().collect();
+    entry_table(&mut out, "Report", &all);
+    out.push_str("</body></html>\n");
+    out
+}
+
+/// Starts recording the events for a report written to path when the run ends
+pub fn enable(path: &str) {
+    PATH.set(path.to_owned()).ok();
+    output::start_recording();
+}
+
+/// Writes the report if --report-html asked for one
+pub fn finish(status: &str, error: Option<&str>, exit_code: i32, report: &Report) {
+    if let Some(path) = PATH.get() {
+        let html = render(status, error, exit_code, &output::recorded(), report);
+        std::fs::write(path, html).unwrap_or_else(|e| println!("Can't write the HTML report to {}: {}", path, e));
+    }
+}
@@ -0,0 +1,223 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Largest request body accepted, enough for profiles and pipelines
+const MAX_BODY_LEN: usize = 1 << 20;
+
+/// An HTTP request to the daemon API.
+pub struct Request {
+    /// Address of the client
+    pub peer: String,
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    /// Names are lower case
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// Decodes %XX escapes and '+' of a URL query component
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok()
+                    .and_then(|h| u8::from_str_radix(h, 16).ok());
+                match hex {
+                    Some(b) => {
+                        out.push(b);
+                        i += 3;
+                        continue;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            b'+' => out.push(b' '),
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+impl Request {
+    pub fn read(stream: &mut TcpStream) -> io::Result<Request> {
+        let peer = stream.peer_addr().map_or_else(|_| "unknown".to_owned(), |a| a.ip().to_string());
+        Request::parse(peer, stream)
+    }
+
+    /// Reads a request made by peer from stream
+    fn parse<R: Read>(peer: String, stream: R) -> io::Result<Request> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid request line"));
+        };
+        let method = method.to_owned();
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let path = percent_decode(path);
+        let query = query.split('&').filter(|p| !p.is_empty())
+            .map(|p| {
+                let (name, value) = p.split_once('=').unwrap_or((p, ""));
+                (percent_decode(name), percent_decode(value))
+            })
+            .collect();
+
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.push((name.trim().to_ascii_lowercase(), value.trim().to_owned()));
+            }
+        }
+
+        let len: usize = headers.iter().find(|(n, _)| n == "content-length")
+            .and_then(|(_, v)| v.parse().ok())
+            .unwrap_or(0);
+        if len > MAX_BODY_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "request body too large"));
+        }
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body)?;
+        Ok(Request { peer, method, path, query, headers, body })
+    }
+
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// A header by its lower case name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+}
+
+/// A response of the daemon API.
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn json(status: u16, body: String) -> Response {
+        Response { status, content_type: "application/json", body }
+    }
+
+    /// A JSON error message
+    pub fn error(status: u16, message: &str) -> Response {
+        Response::json(status, format!("{{\"error\":{}}}", crate::report::json_string(message)))
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            201 => "Created",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            503 => "Service Unavailable",
+            _ => "Error",
+        }
+    }
+
+    fn write_to(&self, stream: &mut TcpStream) -> io::Result<()> {
+        write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+               self.status, self.reason(), self.content_type, self.body.len(), self.body)
+    }
+}
+
+/// Answers requests on addr with handler, each connection on a thread of its own. Runs
+/// until the listener fails.
+pub fn serve<F>(addr: &str, handler: F) -> io::Result<()>
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind(addr)?;
+    let handler = Arc::new(handler);
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        let handler = handler.clone();
+        std::thread::spawn(move || {
+            stream.set_read_timeout(Some(Duration::from_secs(10))).ok();
+            let response = match Request::read(&mut stream) {
+                Ok(request) => handler(&request),
+                Err(e) => Response::error(400, &e.to_string()),
+            };
+            // The client going away isn't the daemon's problem.
+            response.write_to(&mut stream).ok();
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &[u8]) -> io::Result<Request> {
+        Request::parse("192.0.2.1".to_owned(), text)
+    }
+
+    #[test]
+    fn requests_are_parsed() {
+        let request = parse(b"POST /jobs/a%20b?port=%2Fdev%2FttyUSB0&priority=-1&flag HTTP/1.1\r\n\
+                              Authorization: Bearer secret\r\nContent-Length: 5\r\n\r\nhello trailing").unwrap();
+        assert_eq!(request.peer, "192.0.2.1");
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/jobs/a b");
+        assert_eq!(request.query("port"), Some("/dev/ttyUSB0"));
+        assert_eq!(request.query("priority"), Some("-1"));
+        assert_eq!(request.query("flag"), Some(""));
+        assert_eq!(request.query("missing"), None);
+        assert_eq!(request.header("authorization"), Some("Bearer secret"));
+        assert_eq!(request.body, b"hello");
+    }
+
+    #[test]
+    fn query_escapes_are_decoded() {
+        for (escaped, decoded) in [("a+b", "a b"), ("%41%62", "Ab"), ("100%", "100%"), ("%4", "%4"),
+                                   ("%zz", "%zz"), ("%e2%9c%93", "\u{2713}")] {
+            assert_eq!(percent_decode(escaped), decoded, "{}", escaped);
+        }
+    }
+
+    #[test]
+    fn malformed_requests_are_refused() {
+        for text in [&b""[..], b"\r\n", b"GET\r\n"] {
+            let e = parse(text).err().unwrap();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        }
+        // The body ends before its length
+        let e = parse(b"POST /jobs HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort").err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn oversized_bodies_are_refused_before_reading_them() {
+        let text = format!("POST /jobs HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_LEN + 1);
+        let e = parse(text.as_bytes()).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "request body too large");
+    }
+}
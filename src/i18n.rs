@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+/// The operator facing messages by key, in English. A catalog given with --messages
+/// replaces them with translations. {0}, {1}, ... stand for the values put in.
+const MESSAGES: &[(&str, &str)] = &[
+    ("pass", "PASS"),
+    ("fail", "FAIL"),
+    ("passed", "{0} of {1} passed."),
+    ("scan_unit_id", "Scan unit ID for {0}: "),
+    ("the_device", "the device"),
+    ("retry_prompt", "Retry: enter port numbers, '{0}' for all failed ports, or nothing to quit: "),
+    // What's typed to retry all failed ports
+    ("retry_all", "a"),
+    ("invalid_port", "Invalid port number."),
+    ("pipeline_results", "Pipeline results:"),
+    ("port_ok", "ok"),
+    ("port_failed", "FAILED"),
+    ("step", "Pipeline step {0}/{1}: {2}"),
+    ("step_skipped", "Pipeline step {0}/{1}: {2} skipped"),
+    ("step_failed", "Pipeline step {0} failed."),
+    ("pipeline_done", "Pipeline done."),
+    ("retrying", "Retrying in {0}s, attempt {1}/{2}..."),
+    ("error", "Error: {0}"),
+    ("paused", "Paused at {0}."),
+    ("press_enter", "Press Enter to continue."),
+    ("continuing", "Continuing."),
+];
+
+static CATALOG: OnceLock<HashMap<&'static str, String>> = OnceLock::new();
+
+/// The number of values the English message of key takes
+fn arg_count(english: &str) -> usize {
+    (0..10).take_while(|i| english.contains(&format!("{{{}}}", i))).count()
+}
+
+/// Parses a catalog, lines of `key = text` with # comments. Keys it lacks stay English.
+fn parse(text: &str) -> Result<HashMap<&'static str, String>, String> {
+    let mut catalog = HashMap::new();
+    for (n, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let Some((key, message)) = line.split_once('=') else {
+            return Err(format!("line {}: expected key = text", n + 1));
+        };
+        let key = key.trim();
+        let Some(&(key, english)) = MESSAGES.iter().find(|(k, _)| *k == key) else {
+            return Err(format!("line {}: unknown message {}", n + 1, key));
+        };
+        // Only the space after = is cut, prompts end in one.
+        let message = message.strip_prefix(' ').unwrap_or(message);
+        if message.contains(&format!("{{{}}}", arg_count(english))) {
+            return Err(format!("line {}: {} only takes {} values", n + 1, key, arg_count(english)));
+        }
+        catalog.insert(key, message.to_owned());
+    }
+    Ok(catalog)
+}
+
+/// Loads the translations of --messages
+pub fn load(path: &str) -> Result<(), String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    CATALOG.set(parse(&text)?).map_err(|_| "already loaded".to_owned())
+}
+
+/// The message of key, translated if the catalog has it, with the values put in
+pub fn text(key: &'static str, args: &[&dyn Display]) -> String {
+    let message = CATALOG.get().and_then(|c| c.get(key)).map(String::as_str)
+        .or_else(|| MESSAGES.iter().find(|(k, _)| *k == key).map(|(_, english)| *english))
+        .unwrap_or(key);
+    args.iter().enumerate().fold(message.to_owned(), |text, (i, arg)| text.replace(&format!("{{{}}}", i), &arg.to_string()))
+}
+
+/// The English messages in the format of --messages, to start a translation from
+pub fn template() -> String {
+    let mut text = "# Messages of mtk_uartboot, one `key = text` per line. {0}, {1}, ... stand for values.\n".to_owned();
+    for (key, english) in MESSAGES {
+        text += &format!("{} = {}\n", key, english);
+    }
+    text
+}
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, Once, OnceLock};
+use std::time::SystemTime;
+use flate2::read::GzDecoder;
+use mtk_uartboot::digest::{Sha256, to_hex};
+
+/// Makes temporary file names unique between the workers of this process
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// An image being read ahead, set once it's done. None if reading it failed.
+type Slot = Arc<OnceLock<Option<Arc<Prepared>>>>;
+
+/// Images being read ahead by path, see [`prefetch`]
+static PREFETCHED: LazyLock<Mutex<HashMap<String, Slot>>> = LazyLock::new(Mutex::default);
+
+/// Bytes the entries of an [`ImageCache`] may take, the least recently used are removed
+/// beyond it
+const MAX_CACHE_LEN: u64 = 1 << 30;
+
+/// Warns once that the cache directory isn't private
+static NOT_PRIVATE: Once = Once::new();
+
+/// An on-disk cache of prepared images, keyed by the hash of the input and of what was done
+/// to it.
+///
+/// Workers booting many ports from the same images prepare each of them only once. Entries
+/// are written to a temporary file and renamed into place, so concurrent workers, even in
+/// different processes, never see partial entries. Each ends with the SHA-256 of the image,
+/// and one which doesn't match is made again. The directory must be one only this user may
+/// write to, or entries other users planted would be sent.
+pub struct ImageCache {
+    dir: PathBuf,
+    max_len: u64,
+}
+
+impl ImageCache {
+    /// Uses dir, or mtk_uartboot/images in the cache directory of the user if none is given
+    pub fn new(dir: Option<&str>) -> ImageCache {
+        let dir = match dir {
+            Some(d) => PathBuf::from(d),
+            None => std::env::var_os("XDG_CACHE_HOME").filter(|d| !d.is_empty()).map(PathBuf::from)
+                .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+                .map_or_else(|| std::env::temp_dir().join("mtk_uartboot-cache"), |d| d.join("mtk_uartboot").join("images")),
+        };
+        ImageCache { dir, max_len: MAX_CACHE_LEN }
+    }
+
+    /// Creates the directory only accessible to this user, or checks that only this user may
+    /// write to the one there is.
+    fn make_private(&self) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+            fs::DirBuilder::new().recursive(true).mode(0o700).create(&self.dir)?;
+            let meta = fs::symlink_metadata(&self.dir)?;
+            if !meta.is_dir() || meta.uid() != unsafe { libc::geteuid() } || meta.mode() & 0o022 != 0 {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                          format!("{} isn't a directory only this user may write to", self.dir.display())));
+            }
+            Ok(())
+        }
+        #[cfg(not(unix))]
+        fs::create_dir_all(&self.dir)
+    }
+
+    /// Returns what transform produces from input, running it only if it's not cached yet.
+    pub fn get_or_create<F>(&self, input: &[u8], transform: &str, f: F) -> io::Result<Vec<u8>>
+    where
+        F: FnOnce(&[u8]) -> io::Result<Vec<u8>>,
+    {
+        if let Err(e) = self.make_private() {
+            NOT_PRIVATE.call_once(|| println!("Warning: not caching prepared images: {}.", e));
+            return f(input);
+        }
+        let mut key = Sha256::new();
+        key.update(transform.as_bytes());
+        key.update(&[0]);
+        key.update(input);
+        let path = self.dir.join(to_hex(&key.finish()));
+        if let Some(data) = fs::read(&path).ok().and_then(checked) {
+            // Marks the entry as used for the eviction, failing only keeps it less long.
+            fs::File::options().append(true).open(&path).and_then(|f| f.set_modified(SystemTime::now())).ok();
+            return Ok(data);
+        }
+
+        let data = f(input)?;
+        // A cache which can't be written only costs making the entry again next time.
+        if self.store(&path, &data).is_ok() {
+            self.evict();
+        }
+        Ok(data)
+    }
+
+    fn store(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let tmp = path.with_extension(format!("tmp.{}.{}", std::process::id(), TMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let mut sha256 = Sha256::new();
+        sha256.update(data);
+        let mut file = fs::File::options().write(true).create_new(true).open(&tmp)?;
+        file.write_all(data).and_then(|_| file.write_all(&sha256.finish())).inspect_err(|_| {
+            fs::remove_file(&tmp).ok();
+        })?;
+        drop(file);
+        fs::rename(&tmp, path).inspect_err(|_| {
+            fs::remove_file(&tmp).ok();
+        })
+    }
+
+    /// Removes the least recently used entries while they take more than the bound. Another
+    /// process may remove them too, so failures are skipped.
+    fn evict(&self) {
+        let Ok(dir) = fs::read_dir(&self.dir) else { return };
+        let mut entries: Vec<(SystemTime, u64, PathBuf)> = dir.filter_map(|e| {
+            let e = e.ok()?;
+            // Only entries, temporary files of workers still writing are theirs.
+            let name = e.file_name();
+            if name.len() != 64 || !name.to_str()?.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            let meta = e.metadata().ok()?;
+            Some((meta.modified().ok()?, meta.len(), e.path()))
+        }).collect();
+        entries.sort_by_key(|(modified, _, _)| std::cmp::Reverse(*modified));
+        let mut len = 0;
+        for (_, entry_len, path) in entries {
+            len += entry_len;
+            if len > self.max_len {
+                fs::remove_file(path).ok();
+            }
+        }
+    }
+
+    /// Prepares the contents of the image file at path. Gzip compressed images, named *.gz,
+    /// are decompressed through the cache.
+    pub fn unpack(&self, path: &str, data: Vec<u8>) -> io::Result<Vec<u8>> {
+        if !path.ends_with(".gz") {
+            return Ok(data);
+        }
+        self.get_or_create(&data, "gunzip", |input| {
+            let mut out = Vec::new();
+            GzDecoder::new(input).read_to_end(&mut out)?;
+            Ok(out)
+        })
+    }
+}
+
+/// The image of a cache entry, if the SHA-256 it ends with matches
+fn checked(mut entry: Vec<u8>) -> Option<Vec<u8>> {
+    let len = entry.len().checked_sub(32)?;
+    let mut sha256 = Sha256::new();
+    sha256.update(&entry[..len]);
+    (sha256.finish()[..] == entry[len..]).then(|| {
+        entry.truncate(len);
+        entry
+    })
+}
+
+/// An image read, decompressed and hashed ahead of time.
+pub struct Prepared {
+    /// Size and modification time of the file when it was read, a file changed since is
+    /// read again
+    stamp: (u64, Option<SystemTime>),
+    /// Contents of the file
+    pub raw: Vec<u8>,
+    /// What [`ImageCache::unpack`] made of them, if it changed anything
+    pub unpacked: Option<Vec<u8>>,
+    sha256: [u8; 32],
+}
+
+impl Prepared {
+    fn data(&self) -> &[u8] {
+        self.unpacked.as_deref().unwrap_or(&self.raw)
+    }
+}
+
+fn stamp(path: &str) -> io::Result<(u64, Option<SystemTime>)> {
+    let meta = fs::metadata(path)?;
+    Ok((meta.len(), meta.modified().ok()))
+}
+
+#[cfg(feature = "pipeline")]
+fn prepare(path: &str, cache_dir: Option<&str>) -> io::Result<Prepared> {
+    let stamp = stamp(path)?;
+    let raw = fs::read(path)?;
+    let unpacked = match path.ends_with(".gz") {
+        true => Some(ImageCache::new(cache_dir).unpack(path, raw.clone())?),
+        false => None,
+    };
+    let mut prepared = Prepared { stamp, raw, unpacked, sha256: [0; 32] };
+    let mut sha256 = Sha256::new();
+    sha256.update(prepared.data());
+    prepared.sha256 = sha256.finish();
+    Ok(prepared)
+}
+
+/// Starts reading, decompressing and hashing the image at path in a background thread, so
+/// a later step finds it ready instead of waiting for slow storage. Images already being
+/// read ahead aren't read again.
+#[cfg(feature = "pipeline")]
+pub fn prefetch(path: &str, cache_dir: Option<&str>) {
+    use std::collections::hash_map::Entry;
+    let slot = match PREFETCHED.lock().unwrap().entry(path.to_owned()) {
+        Entry::Occupied(_) => return,
+        Entry::Vacant(e) => e.insert(Arc::default()).clone(),
+    };
+    let (path, cache_dir) = (path.to_owned(), cache_dir.map(str::to_owned));
+    std::thread::spawn(move || {
+        // Failures are left to the step reading the image, which reports them.
+        slot.set(prepare(&path, cache_dir.as_deref()).ok().map(Arc::new)).ok();
+    });
+}
+
+/// The image at path if it was read ahead and hasn't changed since, waiting for the read
+/// to finish.
+pub fn prefetched(path: &str) -> Option<Arc<Prepared>> {
+    let slot = PREFETCHED.lock().unwrap().get(path)?.clone();
+    let prepared = slot.wait().clone()?;
+    (stamp(path).ok()? == prepared.stamp).then_some(prepared)
+}
+
+/// SHA-256 of an image, taken from the images read ahead if it's one of them unchanged.
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let slots: Vec<_> = PREFETCHED.lock().unwrap().values().cloned().collect();
+    if let Some(prepared) = slots.iter().filter_map(|slot| slot.get()?.as_ref()).find(|p| p.data() == data) {
+        return prepared.sha256;
+    }
+    let mut sha256 = Sha256::new();
+    sha256.update(data);
+    sha256.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn cache(test: &str, max_len: u64) -> ImageCache {
+        let dir = std::env::temp_dir().join(format!("mtk_uartboot-cache-test-{}-{}", std::process::id(), test));
+        fs::remove_dir_all(&dir).ok();
+        ImageCache { dir, max_len }
+    }
+
+    fn entries(cache: &ImageCache) -> usize {
+        fs::read_dir(&cache.dir).unwrap().count()
+    }
+
+    #[test]
+    fn entries_are_made_once_per_input_and_transform() {
+        let cache = cache("once", MAX_CACHE_LEN);
+        let mut made = 0;
+        for (input, transform) in [(b"fip", "patch 1"), (b"fip", "patch 1"), (b"fip", "patch 2"), (b"FIP", "patch 1")] {
+            let data = cache.get_or_create(input, transform, |input| {
+                made += 1;
+                Ok([input, transform.as_bytes()].concat())
+            }).unwrap();
+            assert_eq!(data, [&input[..], transform.as_bytes()].concat());
+        }
+        assert_eq!(made, 3);
+        assert_eq!(entries(&cache), 3);
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted() {
+        let cache = cache("evict", 2500);
+        let make = |name: &str| cache.get_or_create(name.as_bytes(), "", |_| Ok(vec![0; 1000])).unwrap();
+        make("a");
+        std::thread::sleep(Duration::from_millis(20));
+        make("b");
+        std::thread::sleep(Duration::from_millis(20));
+        // Using a makes b the least recently used one.
+        make("a");
+        std::thread::sleep(Duration::from_millis(20));
+        make("c");
+        assert_eq!(entries(&cache), 2);
+        let made = |name: &str| {
+            let mut made = false;
+            cache.get_or_create(name.as_bytes(), "", |_| {
+                made = true;
+                Ok(vec![0; 1000])
+            }).unwrap();
+            made
+        };
+        assert!(!made("a") && !made("c"));
+        assert!(made("b"));
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn entries_not_matching_their_hash_are_made_again() {
+        let cache = cache("corrupt", MAX_CACHE_LEN);
+        cache.get_or_create(b"fip.gz", "gunzip", |_| Ok(b"fip".to_vec())).unwrap();
+        let entry = fs::read_dir(&cache.dir).unwrap().next().unwrap().unwrap().path();
+        fs::write(&entry, b"planted fip").unwrap();
+        let data = cache.get_or_create(b"fip.gz", "gunzip", |_| Ok(b"fip".to_vec())).unwrap();
+        assert_eq!(data, b"fip");
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn directory_others_may_write_to_is_not_used() {
+        use std::os::unix::fs::PermissionsExt;
+        let cache = cache("shared", MAX_CACHE_LEN);
+        fs::create_dir_all(&cache.dir).unwrap();
+        fs::set_permissions(&cache.dir, fs::Permissions::from_mode(0o777)).unwrap();
+        let data = cache.get_or_create(b"fip.gz", "gunzip", |_| Ok(b"fip".to_vec())).unwrap();
+        assert_eq!(data, b"fip");
+        assert_eq!(entries(&cache), 0);
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use mtk_uartboot::{Event, Stage};
+
+/// Stages in the order the boot flow completes them, with what they mean for the device
+const STAGES: &[(&str, &str)] = &[
+    ("brom.handshake", "BootROM handshake"),
+    ("brom.payload_sent", "payload sent to the BootROM"),
+    ("brom.jumped", "jump to the payload"),
+    ("bl2.handshake", "BL2 handshake"),
+    ("bl2.fip_sent", "FIP sent to BL2"),
+    ("bl2.go", "go command sent to BL2"),
+    ("console", "console steps"),
+];
+
+const DONE: &str = "done";
+
+/// How often transfer progress is written at most
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Inner {
+    file: File,
+    last_progress: Option<Instant>,
+}
+
+/// A file recording the completed stages of the current run.
+///
+/// Every completed stage is written and synced right away, so after a crash or kill the
+/// journal tells what was done to the device and what wasn't.
+#[derive(Clone, Default)]
+pub struct Journal {
+    inner: Option<Arc<Mutex<Inner>>>,
+    previous: Vec<String>,
+}
+
+impl Journal {
+    /// Starts a new journal at path. An unfinished journal left by a previous run is
+    /// summarized first, and its stages are kept when resuming.
+    pub fn open(path: &str, resume: bool) -> Journal {
+        let previous: Vec<String> = match fs::read_to_string(path) {
+            Ok(text) => text.lines().filter(|l| !l.starts_with("progress ")).map(str::to_owned).collect(),
+            Err(_) => Vec::new(),
+        };
+        let unfinished = !previous.is_empty() && previous.last().map(String::as_str) != Some(DONE);
+        if unfinished {
+            print_summary(&fs::read_to_string(path).unwrap_or_default());
+        }
+        let previous = if resume && unfinished { previous } else { Vec::new() };
+
+        let mut file = OpenOptions::new().create(true).write(true).truncate(true).open(path)
+            .expect("failed to create journal.");
+        for stage in &previous {
+            writeln!(file, "{}", stage).expect("failed to write journal.");
+        }
+        file.sync_data().expect("failed to write journal.");
+        Journal {
+            inner: Some(Arc::new(Mutex::new(Inner { file, last_progress: None }))),
+            previous,
+        }
+    }
+
+    /// Whether the resumed run already completed this stage
+    pub fn done_before(&self, stage: &str) -> bool {
+        self.previous.iter().any(|s| s == stage)
+    }
+
+    pub fn mark(&self, stage: &str) {
+        if let Some(inner) = &self.inner {
+            let mut inner = inner.lock().unwrap();
+            writeln!(inner.file, "{}", stage).expect("failed to write journal.");
+            inner.file.sync_data().expect("failed to write journal.");
+            inner.last_progress = None;
+        }
+    }
+
+    pub fn finish(&self) {
+        self.mark(DONE);
+    }
+
+    /// Records transfer progress, at most once per PROGRESS_INTERVAL
+    pub fn on_event(&self, event: &Event) {
+        let (stage, sent, total) = match event {
+            Event::Progress { stage, sent, total } => (stage, sent, total),
+            _ => return,
+        };
+        if let Some(inner) = &self.inner {
+            let mut inner = inner.lock().unwrap();
+            if inner.last_progress.is_some_and(|t| t.elapsed() < PROGRESS_INTERVAL) && sent != total {
+                return;
+            }
+            inner.last_progress = Some(Instant::now());
+            let stage = match stage {
+                Stage::BootRom => "brom",
+                Stage::Bl2 => "bl2",
+            };
+            // Best effort, failing to record progress isn't worth aborting a transfer.
+            writeln!(inner.file, "progress {} {}/{}", stage, sent, total).ok();
+        }
+    }
+}
+
+fn print_summary(journal: &str) {
+    let stages: Vec<&str> = journal.lines().filter(|l| !l.starts_with("progress ")).collect();
+    let progress = journal.lines().rfind(|l| l.starts_with("progress "));
+    println!("The previous run was interrupted.");
+    for (stage, description) in STAGES {
+        let state = if stages.contains(stage) { "done" } else { "not done" };
+        println!("  {}: {}", description, state);
+    }
+    if let Some(progress) = progress {
+        println!("  last transfer progress: {}", progress.trim_start_matches("progress "));
+    }
+}
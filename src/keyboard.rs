@@ -0,0 +1,97 @@
+use std::io::IsTerminal;
+use std::sync::Arc;
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use mtk_uartboot::PauseControl;
+
+/// Ctrl-A, the escape key like in the console, followed by p to pause or resume
+const ESCAPE: u8 = 0x01;
+const PAUSE_KEY: u8 = b'p';
+/// How often the thread reading the keys looks whether it should stop, in ms
+const POLL_INTERVAL: i32 = 100;
+
+static HINT: Once = Once::new();
+
+/// Pauses and resumes the transfers of a session with Ctrl-A p while it's alive. The keys
+/// are read as they're typed and not echoed, Ctrl-C still interrupts. Dropping it resumes
+/// a transfer left paused and gives the terminal back, for prompts reading lines.
+pub struct Keyboard {
+    pause: PauseControl,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    #[cfg(unix)]
+    saved: libc::termios,
+}
+
+impl Keyboard {
+    /// Starts reading the keys if stdin is a terminal, None if it isn't
+    #[cfg(unix)]
+    pub fn listen(pause: PauseControl) -> Option<Keyboard> {
+        if !std::io::stdin().is_terminal() {
+            return None;
+        }
+        // SAFETY: termios is plain data, filled in by tcgetattr before it's used.
+        let saved = unsafe {
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) < 0 {
+                return None;
+            }
+            let mut keys = saved;
+            keys.c_lflag &= !(libc::ICANON | libc::ECHO);
+            keys.c_cc[libc::VMIN] = 1;
+            keys.c_cc[libc::VTIME] = 0;
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &keys);
+            saved
+        };
+        HINT.call_once(|| println!("Ctrl-A p pauses the transfer at the next chunk and resumes it."));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let (pause, stop) = (pause.clone(), stop.clone());
+            std::thread::spawn(move || read_keys(&pause, &stop))
+        };
+        Some(Keyboard { pause, stop, thread: Some(thread), saved })
+    }
+
+    #[cfg(not(unix))]
+    pub fn listen(_pause: PauseControl) -> Option<Keyboard> {
+        None
+    }
+}
+
+/// Toggles the pause on Ctrl-A p until stop is set. Polls, so it doesn't keep a read
+/// pending on stdin which would take the first key typed after it.
+#[cfg(unix)]
+fn read_keys(pause: &PauseControl, stop: &AtomicBool) {
+    let mut escaped = false;
+    while !stop.load(Ordering::Relaxed) {
+        let mut fd = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+        // SAFETY: polls and reads a single descriptor into a buffer of the length given.
+        let mut key = 0u8;
+        unsafe {
+            if libc::poll(&mut fd, 1, POLL_INTERVAL) <= 0 || fd.revents & libc::POLLIN == 0 {
+                continue;
+            }
+            if libc::read(libc::STDIN_FILENO, (&mut key as *mut u8).cast(), 1) != 1 {
+                return;
+            }
+        }
+        if escaped && key == PAUSE_KEY {
+            pause.toggle();
+        }
+        escaped = key == ESCAPE && !escaped;
+    }
+}
+
+impl Drop for Keyboard {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+        self.pause.resume();
+        #[cfg(unix)]
+        // SAFETY: restores the settings tcgetattr returned.
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved) };
+    }
+}
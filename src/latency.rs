@@ -0,0 +1,133 @@
+use std::time::{Duration, Instant};
+use mtk_uartboot::{Chip, Transport};
+use crate::report::Report;
+
+/// Registers of the 16550 compatible UARTs, 32 bits apart
+const UART_RBR_THR: u32 = 0x00;
+const UART_LSR: u32 = 0x14;
+/// Bits of the line status register: a byte was received, the transmitter has room
+const LSR_DR: u32 = 0;
+const LSR_THRE: u32 = 5;
+
+/// The echo loop in aarch64: waits for a byte on the UART at base, waits until it can be
+/// sent and sends it back
+fn echo_a64(base: u32) -> [u32; 9] {
+    [
+        0x52a0_0000 | (base >> 16) << 5,             // movz w0, #base_hi, lsl #16
+        0x7280_0000 | (base & 0xffff) << 5,          // movk w0, #base_lo
+        0xb940_0001 | (UART_LSR / 4) << 10,          // loop: ldr w1, [x0, #LSR]
+        0x3607_ffe1 | LSR_DR << 19,                  //       tbz w1, #DR, loop
+        0xb940_0002 | (UART_RBR_THR / 4) << 10,      //       ldr w2, [x0, #RBR]
+        0xb940_0001 | (UART_LSR / 4) << 10,          // wait: ldr w1, [x0, #LSR]
+        0x3607_ffe1 | LSR_THRE << 19,                //       tbz w1, #THRE, wait
+        0xb900_0002 | (UART_RBR_THR / 4) << 10,      //       str w2, [x0, #THR]
+        0x17ff_fffa,                                 //       b loop
+    ]
+}
+
+/// The same loop in aarch32
+fn echo_a32(base: u32) -> [u32; 11] {
+    let (lo, hi) = (base & 0xffff, base >> 16);
+    [
+        0xe300_0000 | (lo >> 12) << 16 | (lo & 0xfff), // movw r0, #base_lo
+        0xe340_0000 | (hi >> 12) << 16 | (hi & 0xfff), // movt r0, #base_hi
+        0xe590_1000 | UART_LSR,                        // loop: ldr r1, [r0, #LSR]
+        0xe311_0000 | 1 << LSR_DR,                     //       tst r1, #DR
+        0x0aff_fffc,                                   //       beq loop
+        0xe590_2000 | UART_RBR_THR,                    //       ldr r2, [r0, #RBR]
+        0xe590_1000 | UART_LSR,                        // wait: ldr r1, [r0, #LSR]
+        0xe311_0000 | 1 << LSR_THRE,                   //       tst r1, #THRE
+        0x0aff_fffc,                                   //       beq wait
+        0xe580_2000 | UART_RBR_THR,                    //       str r2, [r0, #THR]
+        0xeaff_fff6,                                   //       b loop
+    ]
+}
+
+/// A payload for the SoC echoing everything it receives on the UART the BootROM talks on,
+/// at the baud rate the BootROM left it at
+pub fn echo_payload(chip: &Chip) -> Vec<u8> {
+    let words: &[u32] = if chip.aarch64 { &echo_a64(chip.uart) } else { &echo_a32(chip.uart) };
+    words.iter().flat_map(|w| w.to_le_bytes()).collect()
+}
+
+/// Round trip times of one size of writes at one baud rate
+struct RoundTrips {
+    times: Vec<Duration>,
+    lost: usize,
+}
+
+impl RoundTrips {
+    fn percentile(&self, p: usize) -> Duration {
+        let mut sorted = self.times.clone();
+        sorted.sort();
+        sorted[(sorted.len() - 1) * p / 100]
+    }
+
+    /// The mean difference between consecutive round trips, as RTP defines jitter
+    fn jitter(&self) -> Duration {
+        let diffs: Duration = self.times.windows(2).map(|w| w[0].abs_diff(w[1])).sum();
+        diffs / (self.times.len().saturating_sub(1).max(1) as u32)
+    }
+}
+
+/// Writes size bytes count times, each time waiting for their echo
+fn round_trips(port: &mut dyn Transport, size: usize, count: usize) -> RoundTrips {
+    let mut trips = RoundTrips { times: Vec::with_capacity(count), lost: 0 };
+    let mut echo = vec![0; size];
+    for i in 0..count {
+        let data: Vec<u8> = (0..size).map(|j| (i + j) as u8).collect();
+        let start = Instant::now();
+        let mut received = 0;
+        if port.write_all(&data).is_ok() {
+            while received < size {
+                match port.read(&mut echo[received..]) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => received += n,
+                }
+            }
+        }
+        if received == size && echo == data {
+            trips.times.push(start.elapsed());
+        } else {
+            trips.lost += 1;
+            // Whatever arrives late would be taken for the next echo.
+            std::thread::sleep(Duration::from_millis(50));
+            port.clear_input().ok();
+        }
+    }
+    trips
+}
+
+fn us(d: Duration) -> u128 {
+    d.as_micros()
+}
+
+/// Measures the round trips against the echo payload at the current baud rate, for every
+/// size of writes, and prints them with the time the bytes take on the wire. What's left
+/// over is the turnaround of the adapter, driver and payload, which a windowed transfer has
+/// to cover with data in flight.
+pub fn run(port: &mut dyn Transport, baudrate: u32, sizes: &[usize], count: usize, report: &mut Report) {
+    port.set_timeout(Duration::from_millis(500)).unwrap();
+    port.clear_input().ok();
+    println!("{:>6} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>5}", "bytes", "wire", "min", "median", "p99", "max", "jitter", "lost");
+    for &size in sizes {
+        let trips = round_trips(port, size, count);
+        // 10 bits per byte with start and stop bit, there and back
+        let wire = Duration::from_secs_f64(2.0 * 10.0 * size as f64 / baudrate as f64);
+        let key = format!("latency.{}.{}", baudrate, size);
+        report.set(&format!("{}.lost", key), trips.lost);
+        if trips.times.is_empty() {
+            println!("{:>6} {:>8} {:>44} {:>5}", size, us(wire), "no echo", trips.lost);
+            continue;
+        }
+        let (min, median, p99, max) = (trips.percentile(0), trips.percentile(50), trips.percentile(99), trips.percentile(100));
+        println!("{:>6} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>5}",
+                 size, us(wire), us(min), us(median), us(p99), us(max), us(trips.jitter()), trips.lost);
+        report.set(&format!("{}.min_us", key), us(min));
+        report.set(&format!("{}.median_us", key), us(median));
+        report.set(&format!("{}.p99_us", key), us(p99));
+        report.set(&format!("{}.max_us", key), us(max));
+        report.set(&format!("{}.jitter_us", key), us(trips.jitter()));
+    }
+    println!("Times in µs. wire is the time the bytes take on the line, there and back.");
+}
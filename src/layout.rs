@@ -0,0 +1,97 @@
+use mtk_uartboot::chips::{Chip, RegionKind};
+use crate::output;
+
+/// Something written to memory: the payload, a --load file or a mailbox
+pub struct Placement {
+    pub what: String,
+    pub addr: u32,
+    pub len: u32,
+    /// Written by the first payload once it's running, like the --next-payload
+    pub late: bool,
+}
+
+impl Placement {
+    /// The last address written, None if nothing is
+    fn last(&self) -> Option<u32> {
+        self.len.checked_sub(1).map(|len| self.addr.saturating_add(len))
+    }
+}
+
+/// What's wrong with a placement: an error if the BootROM or the payload would break, a
+/// warning if it only works under conditions
+pub enum Problem {
+    Error(String),
+    Warning(String),
+}
+
+/// Checks a placement against the memory map of the chip
+fn check_region(chip: &Chip, p: &Placement) -> Vec<Problem> {
+    let Some(last) = p.last() else { return Vec::new() };
+    let Some(region) = chip.region(p.addr) else {
+        return vec![Problem::Error(format!("{:#x} isn't mapped on {}", p.addr, chip.name))];
+    };
+    let mut problems = Vec::new();
+    match region.kind {
+        RegionKind::Rom => problems.push(Problem::Error(format!("{:#x} is in the {} of {}, which can't be written", p.addr, region.name, chip.name))),
+        RegionKind::Reserved => problems.push(Problem::Error(format!("{:#x} collides with the {} on {}", p.addr, region.name, chip.name))),
+        RegionKind::Mmio => problems.push(Problem::Error(format!("{:#x} is in the {} of {}, not memory", p.addr, region.name, chip.name))),
+        RegionKind::Dram if !p.late => problems.push(Problem::Warning(format!("{:#x} is in {}, which only works once a payload set it up", p.addr, region.name))),
+        RegionKind::Dram | RegionKind::Sram => {}
+    }
+    if !region.contains(last) {
+        let next = chip.region(last).map_or("unmapped addresses".to_owned(), |r| format!("the {}", r.name));
+        problems.push(Problem::Error(format!("it runs {:#x} bytes past the end of the {} at {:#x}, into {}",
+                                             last - region.last, region.name, region.last, next)));
+    }
+    problems
+}
+
+/// Checks where everything goes against the memory map of the chip and against each other.
+/// What the first payload writes later may take the place of what the BootROM wrote. Returns
+/// the problems of each placement, in order.
+pub fn check(chip: &Chip, placements: &[Placement]) -> Vec<Vec<Problem>> {
+    let mut problems: Vec<Vec<Problem>> = placements.iter().map(|p| check_region(chip, p)).collect();
+    for (i, a) in placements.iter().enumerate() {
+        for b in placements[..i].iter().filter(|b| b.late == a.late) {
+            let (Some(a_last), Some(b_last)) = (a.last(), b.last()) else { continue };
+            if a.addr <= b_last && b.addr <= a_last {
+                problems[i].push(Problem::Error(format!("it overlaps {} at {:#x}", b.what, a.addr.max(b.addr))));
+            }
+        }
+    }
+    problems
+}
+
+/// Prints where everything goes and what's wrong with it. Returns whether there were errors.
+pub fn print(chip: &Chip, placements: &[Placement]) -> bool {
+    println!("Memory map of {}:", chip.name);
+    for region in chip.memory {
+        println!("  {:#010x}-{:#010x} {}", region.start, region.last, region.name);
+    }
+    println!("Placement:");
+    let mut errors = false;
+    for (p, problems) in placements.iter().zip(check(chip, placements)) {
+        let region = chip.region(p.addr).map_or("unmapped", |r| r.name);
+        println!("  {:<24} {:#010x}-{:#010x} {:>8} bytes, {}", p.what, p.addr, p.last().unwrap_or(p.addr), p.len, region);
+        for problem in problems {
+            match problem {
+                Problem::Error(msg) => {
+                    errors = true;
+                    println!("    Error: {}.", msg);
+                }
+                Problem::Warning(msg) => println!("    Warning: {}.", msg),
+            }
+        }
+    }
+    errors
+}
+
+/// Prints the problems only, for boots which go ahead anyway
+pub fn warn(chip: &Chip, placements: &[Placement]) {
+    for (p, problems) in placements.iter().zip(check(chip, placements)) {
+        for problem in problems {
+            let (Problem::Error(msg) | Problem::Warning(msg)) = problem;
+            output::warning(&format!("{}: {}.", p.what, msg));
+        }
+    }
+}
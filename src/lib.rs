@@ -0,0 +1,70 @@
+//! Load and execute binaries over UART on Mediatek SoCs.
+//!
+//! A [`Session`] owns the [`Transport`] to the device, usually a serial port. From it,
+//! [`BootRom`] talks to the BootROM download mode to load and start a first payload, and
+//! [`Bl2`] talks to the UART download mode of a Mediatek TF-A BL2 to send it an FIP.
+//! Things happening inside these calls, like transfer progress, are reported as [`Event`]s.
+//! [`chips`] has the load address and other defaults of known SoCs by their hw code.
+//! The console output of the booted payload can be copied to any [`Sink`].
+//! Commands the crate doesn't know can be added as an [`Extension`].
+//! With the `serde` feature, `stream` has the events the tool writes with `--output json`.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use mtk_uartboot::{ByteLen, LoadAddr, Session};
+//!
+//! # fn main() -> mtk_uartboot::Result<()> {
+//! let port = serialport::new("/dev/ttyUSB0", 115200)
+//!     .timeout(Duration::from_secs(2))
+//!     .open()?;
+//! let payload = std::fs::read("bl2.bin")?;
+//!
+//! let mut session = Session::new(Box::new(port));
+//! let mut brom = session.bootrom();
+//! brom.handshake()?;
+//! println!("hw code: {:#x}", brom.get_hw_code()?);
+//! brom.send_da(LoadAddr(0x201000), ByteLen(0), &payload)?;
+//! brom.jump_da64(LoadAddr(0x201000))?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # Stability
+//!
+//! Everything exported from the crate root follows semantic versioning: breaking changes only
+//! come with a new major version, or a new minor version before 1.0. Enums which are expected
+//! to grow, like [`Event`] and [`Error`], are `#[non_exhaustive]`. The wire format definitions
+//! are re-exported from the `mtk_uartboot_protocol` crate as [`protocol`].
+
+pub mod auth;
+pub mod bl2;
+pub mod bootrom;
+pub mod chips;
+pub mod confirm;
+pub mod digest;
+pub mod error;
+pub mod event;
+pub mod extension;
+pub mod secret;
+pub mod session;
+pub mod sink;
+#[cfg(feature = "serde")]
+pub mod stream;
+pub mod transport;
+pub mod types;
+
+pub use mtk_uartboot_protocol as protocol;
+
+pub use auth::{AuthProvider, CommandAuth, FileAuth};
+#[cfg(feature = "auth-server")]
+pub use auth::ServerAuth;
+pub use bl2::{Bl2, FipTransfer, FlashDiff, FlashTarget, FlashWrite, PacketSizing, Partition, StorageInfo};
+pub use bootrom::BootRom;
+pub use chips::Chip;
+pub use error::{Error, Result};
+pub use event::{Event, Span, Stage};
+pub use extension::{Extension, Frame};
+pub use session::{PauseControl, Session};
+pub use sink::{Sink, SinkSpec};
+pub use transport::Transport;
+pub use types::{Baud, ByteLen, LoadAddr};
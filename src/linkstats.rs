@@ -0,0 +1,146 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use mtk_uartboot::{Event, Stage};
+use crate::report::Report;
+
+/// Error counters the host UART driver keeps for a port.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UartCounters {
+    pub frame: u32,
+    pub parity: u32,
+    pub overrun: u32,
+    pub buf_overrun: u32,
+}
+
+impl UartCounters {
+    pub fn total(&self) -> u32 {
+        self.frame + self.parity + self.overrun + self.buf_overrun
+    }
+
+    pub fn since(&self, before: &UartCounters) -> UartCounters {
+        UartCounters {
+            frame: self.frame.wrapping_sub(before.frame),
+            parity: self.parity.wrapping_sub(before.parity),
+            overrun: self.overrun.wrapping_sub(before.overrun),
+            buf_overrun: self.buf_overrun.wrapping_sub(before.buf_overrun),
+        }
+    }
+
+    /// Reads the counters of a port this process has open. Returns None where the OS or
+    /// the driver doesn't provide them.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn read(port: &str) -> Option<UartCounters> {
+        // struct serial_icounter_struct from linux/serial.h
+        #[repr(C)]
+        #[derive(Default)]
+        struct IcounterStruct {
+            cts: libc::c_int,
+            dsr: libc::c_int,
+            rng: libc::c_int,
+            dcd: libc::c_int,
+            rx: libc::c_int,
+            tx: libc::c_int,
+            frame: libc::c_int,
+            overrun: libc::c_int,
+            parity: libc::c_int,
+            brk: libc::c_int,
+            buf_overrun: libc::c_int,
+            reserved: [libc::c_int; 9],
+        }
+
+        #[cfg(target_os = "linux")]
+        use libc::TIOCGICOUNT;
+        // From asm-generic/ioctls.h, the libc crate lacks it on Android
+        #[cfg(target_os = "android")]
+        const TIOCGICOUNT: libc::Ioctl = 0x545d;
+
+        // The port is opened exclusively, so look up the descriptor already open for it.
+        let device = std::fs::canonicalize(port).ok()?;
+        let fd = std::fs::read_dir("/proc/self/fd").ok()?
+            .filter_map(|entry| entry.ok())
+            .find(|entry| std::fs::read_link(entry.path()).is_ok_and(|target| target == device))?
+            .file_name()
+            .to_str()?
+            .parse::<libc::c_int>().ok()?;
+        let mut counters = IcounterStruct::default();
+        // SAFETY: the kernel fills in a serial_icounter_struct, which counters has the layout of.
+        let ret = unsafe { libc::ioctl(fd, TIOCGICOUNT, &mut counters) };
+        if ret < 0 {
+            return None;
+        }
+        Some(UartCounters {
+            frame: counters.frame as u32,
+            parity: counters.parity as u32,
+            overrun: counters.overrun as u32,
+            buf_overrun: counters.buf_overrun as u32,
+        })
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn read(_port: &str) -> Option<UartCounters> {
+        None
+    }
+}
+
+/// Counts what the device accepted and rejected, and what the host UART got wrong, to tell
+/// whether a failed boot is down to the wiring.
+pub struct LinkStats {
+    port: String,
+    before: Option<UartCounters>,
+    accepted: Arc<AtomicU32>,
+    rejected: Arc<AtomicU32>,
+}
+
+impl LinkStats {
+    pub fn start(port: &str) -> LinkStats {
+        LinkStats {
+            port: port.to_owned(),
+            before: UartCounters::read(port),
+            accepted: Arc::new(AtomicU32::new(0)),
+            rejected: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    /// Returns an event observer counting the FIP packets BL2 accepts and rejects
+    pub fn observer(&self) -> impl FnMut(&Event) + Send + 'static {
+        let (accepted, rejected) = (self.accepted.clone(), self.rejected.clone());
+        move |event| match event {
+            Event::Progress { stage: Stage::Bl2, .. } => {
+                accepted.fetch_add(1, Ordering::Relaxed);
+            }
+            Event::PacketRejected { .. } => {
+                rejected.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+
+    /// Prints the link quality and adds it to the report
+    pub fn finish(&self, report: &mut Report) {
+        let accepted = self.accepted.load(Ordering::Relaxed);
+        let rejected = self.rejected.load(Ordering::Relaxed);
+        let host = self.before
+            .and_then(|before| Some(UartCounters::read(&self.port)?.since(&before)));
+        let errors = rejected + host.map_or(0, |h| h.total());
+        let score = if accepted + errors == 0 {
+            100
+        } else {
+            100 * accepted as u64 / (accepted + errors) as u64
+        };
+
+        let mut details = vec![format!("{} of {} packets rejected by BL2", rejected, accepted + rejected)];
+        report.set("link.packets_rejected", rejected);
+        if let Some(h) = host {
+            details.push(format!("{} framing, {} parity, {} overrun errors on the host UART",
+                                 h.frame, h.parity, h.overrun + h.buf_overrun));
+            report.set("link.frame_errors", h.frame);
+            report.set("link.parity_errors", h.parity);
+            report.set("link.overruns", h.overrun + h.buf_overrun);
+        }
+        report.set("link.quality", score);
+        println!("Link quality: {}% ({})", score, details.join(", "));
+        if score < 95 {
+            println!("The serial link is unreliable. Check the wiring and the ground connection, use shorter wires, or lower the baud rate.");
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::{Duration, Instant};
+use mtk_uartboot::Transport;
+use crate::linkstats::UartCounters;
+use crate::report::Report;
+
+/// Bytes written before reading the echo back, small enough for any UART FIFO and driver buffer
+const CHUNK_LEN: usize = 64;
+
+/// A pseudo-random pattern covering all byte values, so stuck bits and lost bytes both show up
+fn pattern(len: usize) -> Vec<u8> {
+    let mut state: u32 = 0x2545_f491;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// Sends the pattern at the current baud rate and returns the number of bytes which didn't
+/// come back, or came back wrong.
+fn test_rate(port: &mut dyn Transport, data: &[u8]) -> usize {
+    let mut errors = 0;
+    let mut echo = [0u8; CHUNK_LEN];
+    for chunk in data.chunks(CHUNK_LEN) {
+        if port.write_all(chunk).is_err() {
+            return data.len();
+        }
+        let echo = &mut echo[..chunk.len()];
+        let mut received = 0;
+        while received < echo.len() {
+            match port.read(&mut echo[received..]) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => received += n,
+            }
+        }
+        errors += chunk.len() - received;
+        errors += echo[..received].iter().zip(chunk).filter(|(a, b)| a != b).count();
+        if received < echo.len() {
+            // Whatever arrives late would be counted against the next chunk.
+            std::thread::sleep(Duration::from_millis(50));
+            port.clear_input().ok();
+        }
+    }
+    errors
+}
+
+/// Runs the pattern over a loopback (TX shorted to RX) or an echoing payload at every baud
+/// rate, and prints the fastest one which went through without errors. The bytes and
+/// errors of every rate, those of the host UART included, go into the report, as proof the
+/// link holds at the BL2 baud rate.
+pub fn run(port: &mut dyn Transport, serial: &str, baudrates: &[u32], len: usize, report: &mut Report) -> bool {
+    let data = pattern(len);
+    let mut fastest = None;
+    port.set_timeout(Duration::from_millis(200)).unwrap();
+    for &baudrate in baudrates {
+        if let Err(e) = port.set_baud_rate(baudrate) {
+            println!("{:>8}: not supported by the adapter ({})", baudrate, e);
+            report.set(&format!("linktest.{}.supported", baudrate), false);
+            continue;
+        }
+        port.clear_input().ok();
+        let before = UartCounters::read(serial);
+        let start = Instant::now();
+        let errors = test_rate(port, &data);
+        // Both directions, the pattern goes out and comes back
+        let rate = (2 * data.len()) as f64 / start.elapsed().as_secs_f64();
+        let host = before.and_then(|before| Some(UartCounters::read(serial)?.since(&before)));
+        report.set(&format!("linktest.{}.bytes", baudrate), data.len());
+        report.set(&format!("linktest.{}.errors", baudrate), errors);
+        let mut details = format!("{:.0} KB/s", rate / 1000.0);
+        if let Some(h) = host {
+            report.set(&format!("linktest.{}.host_errors", baudrate), h.total());
+            if h.total() > 0 {
+                details += &format!(", {} framing, {} parity, {} overrun errors on the host UART",
+                                    h.frame, h.parity, h.overrun + h.buf_overrun);
+            }
+        }
+        if errors == 0 && host.is_none_or(|h| h.total() == 0) {
+            println!("{:>8}: OK ({})", baudrate, details);
+            fastest = fastest.max(Some(baudrate));
+        } else {
+            println!("{:>8}: {} of {} bytes lost or corrupted ({})", baudrate, errors, data.len(), details);
+        }
+    }
+    match fastest {
+        Some(baudrate) => {
+            println!("Fastest reliable baud rate: {}", baudrate);
+            report.set("linktest.fastest", baudrate);
+            true
+        }
+        None => {
+            println!("No baud rate worked. Check that TX is connected to RX.");
+            false
+        }
+    }
+}
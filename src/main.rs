@@ -1,159 +1,3997 @@
-mod bootrom;
-mod bl2;
+// The print! and println! of all modules, starting the lines with the prefix of the thread
+// set with output::set_prefix.
+macro_rules! print {
+    ($($arg:tt)*) => { $crate::output::print(format_args!($($arg)*)) };
+}
+
+macro_rules! println {
+    () => { $crate::output::print(format_args!("\n")) };
+    ($($arg:tt)*) => { $crate::output::print(format_args!("{}\n", format_args!($($arg)*))) };
+}
+
+mod after;
+mod banners;
+mod calibration;
+mod capabilities;
+mod chunkstats;
+mod console;
+mod doctor;
+mod dump;
+mod excerpt;
+mod explain;
+mod fdt;
+mod fip;
+mod fipdiff;
+mod gpio;
+mod html;
+mod i18n;
+mod images;
+mod journal;
+mod keyboard;
+mod layout;
+mod latency;
+mod linktest;
+mod linkstats;
+mod matrix;
+mod mtklog;
+mod output;
+mod params;
+mod patch;
+mod pause;
+mod plugin;
+mod policy;
+mod portlock;
+mod power;
+mod probe;
+mod progress;
+mod realtime;
+mod report;
+mod rescue;
+#[cfg(any(feature = "tftp", feature = "ymodem"))]
+mod served;
+#[cfg(all(feature = "rfcomm", target_os = "linux"))]
+mod rfcomm;
+#[cfg(feature = "verify-sig")]
+mod signature;
+mod store;
+mod symbols;
+mod tcp;
+mod telemetry;
+mod terminal;
+mod timing;
+mod trace;
+#[cfg(feature = "websocket")]
+mod websocket;
+#[cfg(feature = "ymodem")]
+mod ymodem;
+mod hooks;
+#[cfg(feature = "ymodem")]
+mod trigger;
+#[cfg(feature = "tftp")]
+mod tftp;
+#[cfg(feature = "monitor")]
+mod monitor;
+#[cfg(feature = "daemon")]
+mod audit;
+#[cfg(feature = "bundle")]
+mod bundle;
+#[cfg(feature = "bundle")]
+mod mmap;
+#[cfg(feature = "daemon")]
+mod daemon;
+#[cfg(feature = "daemon")]
+mod http;
+#[cfg(feature = "daemon")]
+mod metrics;
+#[cfg(feature = "daemon")]
+mod queue;
+#[cfg(feature = "daemon")]
+mod tokens;
+#[cfg(feature = "pipeline")]
+mod fleet;
+#[cfg(feature = "pipeline")]
+mod operator;
+#[cfg(feature = "pipeline")]
+mod pipeline;
+#[cfg(feature = "profile")]
+mod profile;
+
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap::builder::Resettable;
+use clap_num::maybe_hex;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use mtk_uartboot::{AuthProvider, Baud, Bl2, BootRom, ByteLen, CommandAuth, Event, FileAuth, FlashTarget, LoadAddr, PacketSizing, Session, SinkSpec, Transport};
+use mtk_uartboot::bootrom::TargetConfig;
+use mtk_uartboot::confirm;
+use mtk_uartboot::chips;
+use mtk_uartboot::digest::{self, to_hex};
+use mtk_uartboot::protocol::bl2::{FLASH_INFO_VERSION, FLASH_SWITCH_VERSION, Ack, PacketAck, storage_kind_str};
+use mtk_uartboot::protocol::bootrom::HANDSHAKE;
+use mtk_uartboot::stream::{self, RunStage, StreamEvent};
+use excerpt::Excerpts;
+use console::{BinaryView, ConsolePort, ConsoleView, LineFilter, StripAnsi, wait_for_any_until, wait_for_line};
+use images::ImageCache;
+use journal::Journal;
+use keyboard::Keyboard;
+use layout::Placement;
+use after::After;
+use chunkstats::ChunkStats;
+use progress::ProgressBar;
+use linkstats::LinkStats;
+use fip::Fip;
+use patch::{BytePatch, LogLevel};
+use pause::{PausePoint, pause};
+use policy::Policy;
+use portlock::PortLock;
+use gpio::{GpioLine, GpioOutput};
+use power::PowerSwitch;
+use output::{OutputFormat, hex};
+use params::ParamBlock;
+use report::{Report, json_string};
+use symbols::Symbols;
+use telemetry::Telemetry;
+use timing::Timing;
+
+/// Exit status with --headless when a stage didn't complete, e.g. an expected message didn't show up
+const EXIT_FAILED: i32 = 1;
+/// Exit status with --headless when talking to the device failed
+const EXIT_DEVICE_ERROR: i32 = 3;
+/// Exit status with --headless on any other error, e.g. a missing image
+const EXIT_INTERNAL_ERROR: i32 = 4;
+/// Exit status when the serial port couldn't be opened
+const EXIT_NO_PORT: i32 = 5;
+/// Exit status when the device didn't answer in time, e.g. to the handshake
+const EXIT_NO_ANSWER: i32 = 6;
+/// Exit status when the device is secured in a way the tool can't boot it, e.g. secure boot
+const EXIT_SECURED: i32 = 7;
+/// Exit status when the policy doesn't allow the device or an image
+const EXIT_POLICY: i32 = 8;
+
+/// An error ending the run with its own exit status. It's raised as a panic like the other
+/// errors of the tool, so a pipeline worker hitting it only fails its port. That needs panics
+/// to unwind, which the release profile keeps them doing.
+struct Failure {
+    exit_code: i32,
+    msg: String,
+}
+
+fn fail(exit_code: i32, msg: String) -> ! {
+    std::panic::panic_any(Failure { exit_code, msg })
+}
+
+#[cfg(feature = "pipeline")]
+impl Failure {
+    /// The failure a panic stands for, its own or EXIT_INTERNAL_ERROR with its message
+    fn of_panic(payload: Box<dyn std::any::Any + Send>) -> Failure {
+        match payload.downcast::<Failure>() {
+            Ok(failure) => *failure,
+            Err(payload) => Failure { exit_code: EXIT_INTERNAL_ERROR, msg: panic_message(&*payload).unwrap_or("panic").to_owned() },
+        }
+    }
+}
+
+/// The message of a panic raised with panic! or expect
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> Option<&str> {
+    payload.downcast_ref::<String>().map(String::as_str).or_else(|| payload.downcast_ref::<&str>().copied())
+}
+
+/// Runs the part of a run done for one of several devices, returning the errors raised as
+/// panics on the way, e.g. a missing image, as its Failure so the other devices go on. The
+/// panic hook has printed the error already.
+#[cfg(feature = "pipeline")]
+fn guarded<T>(f: impl FnOnce() -> T) -> Result<T, Failure> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).map_err(Failure::of_panic)
+}
+
+/// The exit status for an error talking to the device
+fn device_exit_code(e: &mtk_uartboot::Error) -> i32 {
+    match e {
+        mtk_uartboot::Error::HandshakeTimeout | mtk_uartboot::Error::BaudrateSync(_) => EXIT_NO_ANSWER,
+        mtk_uartboot::Error::Io(e) if e.kind() == std::io::ErrorKind::TimedOut => EXIT_NO_ANSWER,
+        _ => EXIT_DEVICE_ERROR,
+    }
+}
+
+/// Utility to upload and execute binaries over UART for Mediatek SoCs.
+#[derive(Parser, Debug)]
+#[command(subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    args: BootArgs,
+}
+
+/// Options of the boot, given after `boot` or without a subcommand. Other subcommands take
+/// the ones they use, e.g. --serial, before their name.
+#[derive(clap::Args, Debug)]
+#[group(skip)]
+struct BootArgs {
+    #[command(flatten)]
+    link: LinkOptions,
+
+    #[command(flatten)]
+    bootrom: BootromOptions,
+
+    #[command(flatten)]
+    fip: FipOptions,
+
+    #[command(flatten)]
+    images: ImageOptions,
+
+    #[command(flatten)]
+    run: RunOptions,
+
+    #[command(flatten)]
+    console: ConsoleOptions,
+
+    #[command(flatten)]
+    steps: StepOptions,
+}
+
+/// Options of the fip subcommand, which starts at BL2 and has no payload
+#[derive(clap::Args, Debug)]
+#[group(skip)]
+struct FipArgs {
+    #[command(flatten)]
+    link: LinkOptions,
+
+    #[command(flatten)]
+    fip: FipOptions,
+
+    #[command(flatten)]
+    images: ImageOptions,
+
+    #[command(flatten)]
+    run: RunOptions,
+
+    #[command(flatten)]
+    console: ConsoleOptions,
+
+    #[command(flatten)]
+    steps: StepOptions,
+}
+
+/// Options of the flash subcommand, which is done once the image is written and sends no FIP
+#[derive(clap::Args, Debug)]
+#[group(skip)]
+struct FlashArgs {
+    #[command(flatten)]
+    link: LinkOptions,
+
+    #[command(flatten)]
+    bootrom: BootromOptions,
+
+    #[command(flatten)]
+    flash: FlashOptions,
+
+    #[command(flatten)]
+    images: ImageOptions,
+
+    #[command(flatten)]
+    run: RunOptions,
+
+    #[command(flatten)]
+    console: ConsoleOptions,
+}
+
+/// Options of the boots of pipelines, monitor and the daemon, given by profiles. They may
+/// also write an image to the storage after the payload.
+#[derive(Parser, Debug)]
+#[command(mut_arg("image", |a| a.conflicts_with("fip")))]
+struct ProfileArgs {
+    #[command(flatten)]
+    link: LinkOptions,
+
+    #[command(flatten)]
+    bootrom: BootromOptions,
+
+    #[command(flatten)]
+    fip: FipOptions,
+
+    #[command(flatten)]
+    flash: FlashOptions,
+
+    #[command(flatten)]
+    images: ImageOptions,
+
+    #[command(flatten)]
+    run: RunOptions,
+
+    #[command(flatten)]
+    console: ConsoleOptions,
+
+    #[command(flatten)]
+    steps: StepOptions,
+}
+
+/// How the device is reached, reset and talked to
+#[derive(clap::Args, Debug)]
+#[group(skip)]
+struct LinkOptions {
+    /// Serial port. auto sends the start of the BootROM handshake to every serial port
+    /// until one answers, and uses that one
+    #[arg(short, long, global = true)]
+    serial: Option<String>,
+
+    /// Wait for the serial port to appear instead of failing, so the tool can be started
+    /// before a board with the USB serial adapter on it is powered up
+    #[arg(long)]
+    wait_for_port: bool,
+
+    /// Use the serial port even if another run of the tool has it locked. Without it, a port
+    /// in use fails the run, naming the PID and stage of the run using it
+    #[arg(long, global = true)]
+    steal: bool,
+
+    /// Power cycle the device with this switch before the handshake, e.g. tasmota://host/1,
+    /// shelly://host/0, hidrelay:///dev/hidraw0/1 or http://pdu/outlet/3/{state}
+    #[arg(long, value_parser = power_url)]
+    power: Option<String>,
+
+    /// Milliseconds the power stays off when power cycling
+    #[arg(long, default_value_t = 1000)]
+    power_off_ms: u64,
+
+    /// Reset the device before the handshake by pulsing this host GPIO line, wired to its
+    /// reset pin. Given as N, N:active-low (the default) or N:active-high
+    #[arg(long, value_parser = |s: &str| GpioLine::parse(s, true))]
+    gpio_reset: Option<GpioLine>,
+
+    /// Hold the download mode boot strap of the device with this host GPIO line from before
+    /// the reset until the BootROM answers. Given as N, N:active-low or N:active-high (the
+    /// default)
+    #[arg(long, value_parser = |s: &str| GpioLine::parse(s, false))]
+    gpio_dl: Option<GpioLine>,
+
+    /// GPIO chip of --gpio-reset and --gpio-dl
+    #[arg(long, default_value = "/dev/gpiochip0")]
+    gpio_chip: String,
+
+    /// Milliseconds --gpio-reset is held asserted
+    #[arg(long, default_value_t = 100)]
+    gpio_reset_ms: u64,
+
+    /// Baud rate for loading bl2 payload
+    #[arg(long, default_value_t = 921600)]
+    bl2_load_baudrate: u32,
+
+    /// Limit the transfers to this many bytes per second, for level shifters or isolated
+    /// links which corrupt data at the full speed of the baud rate
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_rate: Option<u32>,
+
+    /// Milliseconds writes to the device may wait for the port to take the data,
+    /// independently of how long the protocols wait for answers. Slow USB adapters and
+    /// --max-rate may need more for large payloads
+    #[arg(long, default_value_t = 5000, value_parser = clap::value_parser!(u64).range(1..))]
+    write_timeout_ms: u64,
+
+    /// Don't reuse or update the timing calibrated for the serial adapter. After successful
+    /// runs, the slowest answer to the BootROM handshake and whether BL2 rejected packets
+    /// are kept per adapter, and later runs wait longer for answers or pause between writes
+    /// if it needed that
+    #[arg(long)]
+    no_calibration: bool,
+
+    /// Write every byte sent to and received from the serial port to FILE, with the time
+    /// and direction, as a hexdump, for debugging handshakes with new devices
+    #[arg(long, value_name = "FILE")]
+    trace: Option<String>,
+}
+
+/// The payload sent to the BootROM, and how
+#[derive(clap::Args, Debug)]
+#[group(skip)]
+struct BootromOptions {
+    /// Path to the binary code to be executed
+    #[arg(short, long, required_unless_present_any = ["read_only", "load"])]
+    payload: Option<String>,
+
+    /// Load address of the payload. Defaults to the one of the SoC, and has to be given for
+    /// SoCs not known by their hw code
+    #[arg(short, long, value_parser=maybe_hex::<u32>)]
+    load_addr: Option<u32>,
+
+    /// Load a file to an address after the payload, given as ADDR:FILE, e.g. a DRAM
+    /// calibration blob or a device tree the payload expects. Can be repeated, the files are
+    /// loaded in order
+    #[arg(long, value_parser = segment)]
+    load: Vec<(u32, String)>,
+
+    /// Address to jump to once everything is loaded, or what to jump to: payload or the FILE
+    /// of a --load file. Defaults to the payload, or to the first --load file without one
+    #[arg(long, value_parser = entry_point)]
+    entry: Option<EntryPoint>,
+
+    /// Words written with the BootROM before the jump, given as ADDR:WORD,WORD,..., for
+    /// payloads taking their boot arguments from a mailbox in memory
+    #[arg(long, value_parser = mailbox)]
+    boot_args: Option<(u32, Vec<u32>)>,
+
+    /// Write a parameter block to this address before the jump: the console baud rate, the
+    /// address of the --next-payload, the hw code and the --param-word values, behind a magic
+    /// and a version, so one payload build can be configured for every board
+    #[arg(long, value_parser=maybe_hex::<u32>)]
+    param_block: Option<u32>,
+
+    /// Extra word at the end of the parameter block, for payload specific settings. Can be
+    /// repeated
+    #[arg(long, value_parser=maybe_hex::<u32>, requires = "param_block")]
+    param_word: Vec<u32>,
+
+    /// Payload to load once the first one is running, given as ADDR:FILE. The first payload
+    /// has to take it over the download protocol of the BootROM, like a preloader does, e.g.
+    /// an aarch32 DRAM init stage handing over to an aarch64 BL2
+    #[arg(long, value_parser = segment)]
+    next_payload: Option<(u32, String)>,
+
+    /// Start the --next-payload in aarch32 instead of aarch64
+    #[arg(long, requires = "next_payload")]
+    next_aarch32: bool,
+
+    /// Wait for a console line containing this text from the first payload before the
+    /// handshake for the --next-payload, e.g. one it prints once DRAM is up
+    #[arg(long, requires = "next_payload")]
+    next_marker: Option<String>,
+
+    /// Words written before the jump to the --next-payload, as ADDR:WORD,WORD,...
+    #[arg(long, value_parser = mailbox, requires = "next_payload")]
+    next_boot_args: Option<(u32, Vec<u32>)>,
+
+    /// Read the payload and the --load files back with the BootROM once they're sent, and
+    /// don't jump to them if anything differs. For cables which corrupt data the checksum
+    /// doesn't catch
+    #[arg(long)]
+    verify: bool,
+
+    /// Read the memory the payload and the --load files are about to overwrite with the
+    /// BootROM before they're sent, and save it to this directory as sram-ADDR.bin, e.g. to
+    /// compare or restore the state experiments with the BootROM leave behind
+    #[arg(long, value_name = "DIR")]
+    save_sram: Option<String>,
+
+    /// KiB saved at most from the start of each region with --save-sram
+    #[arg(long, default_value_t = 64, value_name = "KIB")]
+    save_sram_kib: u32,
+
+    /// Length of the signature at the end of the payload, for devices with DAA enabled
+    #[arg(long, value_parser=maybe_hex::<u32>, default_value_t = 0)]
+    payload_sig_len: u32,
+
+    /// Auth file sent to devices with SLA or DAA enabled, e.g. auth_sv5.auth of SP Flash Tool
+    #[arg(long)]
+    auth: Option<String>,
+
+    /// DAA certificate sent to devices with DAA enabled
+    #[arg(long)]
+    cert: Option<String>,
+
+    /// Command signing the SLA challenge of devices with SLA enabled. It gets the challenge
+    /// on stdin and the hw code in MTK_UARTBOOT_HW_CODE, and prints the signature
+    #[arg(long)]
+    sla_sign_cmd: Option<String>,
+
+    /// Whether this is an aarch64 payload. The default of known SoCs
+    #[arg(short, long, default_value_t = false)]
+    aarch64: bool,
+
+    /// Start the payload in aarch32 on SoCs which default to aarch64
+    #[arg(long, conflicts_with = "aarch64")]
+    aarch32: bool,
+
+    /// Disable the watchdog of known SoCs before loading the payload, for payloads which
+    /// take longer to start than the watchdog waits
+    #[arg(long)]
+    disable_watchdog: bool,
+
+    /// Write bytes into the payload before sending it, given as OFFSET:HEX, or
+    /// OFFSET:HEX:EXPECTED to check the bytes which are replaced. Can be repeated
+    #[arg(long, value_parser = BytePatch::parse)]
+    #[cfg_attr(feature = "verify-sig", arg(conflicts_with = "verify_sig"))]
+    patch: Vec<BytePatch>,
+
+    /// Give up if the BootROM doesn't answer the handshake within this many seconds. By
+    /// default it's retried until the device is powered up
+    #[arg(long)]
+    handshake_timeout: Option<u64>,
+
+    /// Run the BootROM handshake with SCHED_FIFO, or a lower nice value where only that is
+    /// permitted, so a loaded host doesn't miss the short window the BootROM listens in.
+    /// Without CAP_SYS_NICE or an rtprio limit, the handshake runs as usual and it's said why
+    #[arg(long)]
+    rt: bool,
+
+    /// Baud rate for loading bootrom payload. Falls back to 115200 if the BootROM refuses it
+    /// or doesn't answer at it
+    #[arg(long, visible_alias = "brom-baudrate", default_value_t = 460800)]
+    brom_load_baudrate: u32,
+
+    /// Only read device information. No command changing the state of the device is sent
+    #[arg(long)]
+    read_only: bool,
+
+    /// Check where the payload, the --load files, the --next-payload and the mailboxes go
+    /// against the memory map of the SoC, and print it without sending anything. The SoC is
+    /// read from the device with read-only commands, or given with --soc. Exits with 1 if
+    /// anything collides
+    #[arg(long)]
+    dry_run: bool,
+
+    /// SoC for --dry-run, by name like MT7986 or by hw code, instead of asking the device
+    #[arg(long, requires = "dry_run")]
+    soc: Option<String>,
+}
+
+/// The FIP sent to BL2, and how
+#[derive(clap::Args, Debug)]
+#[group(skip)]
+struct FipOptions {
+    /// Path to an FIP payload. Use this to start an FIP using MTK BL2 built with UART download support
+    #[arg(short, long)]
+    fip: Option<String>,
+
+    /// Set the log level of BL31 in the FIP, for BL31 builds taking it from the host
+    #[arg(long, value_enum, requires = "fip")]
+    #[cfg_attr(feature = "verify-sig", arg(conflicts_with = "verify_sig"))]
+    bl31_log_level: Option<LogLevel>,
+
+    /// Apply this device tree overlay (compiled with dtc -@) to the device tree in the FIP
+    /// before sending it. Can be repeated
+    #[arg(long, requires = "fip")]
+    #[cfg_attr(feature = "verify-sig", arg(conflicts_with = "verify_sig"))]
+    dtbo: Vec<String>,
+
+    /// FIP image holding the device tree for --dtbo, e.g. nt-fw for one appended to U-Boot.
+    /// By default, the first image which is or ends with a device tree
+    #[arg(long, requires = "dtbo")]
+    dtb_entry: Option<String>,
+
+    /// Write bytes into the FIP before sending it, like --patch. The offset may be relative
+    /// to an image, e.g. soc-fw+0x1a40:1f2003d5. Can be repeated
+    #[arg(long, value_parser = BytePatch::parse, requires = "fip")]
+    #[cfg_attr(feature = "verify-sig", arg(conflicts_with = "verify_sig"))]
+    fip_patch: Vec<BytePatch>,
+
+    /// Replace an image of the FIP before sending it, given as IMAGE=FILE, e.g.
+    /// bl33=u-boot.bin to try a new U-Boot without rebuilding the FIP. Can be repeated
+    #[arg(long, value_parser = replacement, requires = "fip")]
+    replace: Vec<(String, String)>,
+
+    /// Send the FIP in fixed size packets, so the data on the wire is identical on every run
+    #[arg(long)]
+    deterministic: bool,
+
+    /// FIP packet size used with --deterministic
+    #[arg(long, default_value_t = 4096, value_parser = clap::value_parser!(u16).range(1..))]
+    packet_len: u16,
+}
+
+/// The image the flash subcommand writes to the storage of the device, and where
+#[derive(clap::Args, Debug)]
+#[group(skip)]
+struct FlashOptions {
+    /// Image the flash subcommand writes to the storage of the device
+    #[arg(long, requires = "flash_target")]
+    image: Option<String>,
+
+    /// Partition the image is written to, by its name in the partition table BL2 knows
+    #[arg(long, group = "flash_target")]
+    partition: Option<String>,
+
+    /// Byte offset in the storage BL2 boots from the image is written to
+    #[arg(long, value_parser=maybe_hex::<u64>, group = "flash_target")]
+    offset: Option<u64>,
+
+    /// Only write the blocks of the image which differ from what's on the storage, as BL2
+    /// tells by their CRC-32s, and check the whole image read back. Needs version 3 or later
+    /// of the UART download protocol
+    #[arg(long, requires = "image")]
+    changed_only: bool,
+
+    /// Length of the blocks --changed-only compares, a multiple of the erase block of the
+    /// storage
+    #[arg(long, value_parser=maybe_hex::<u32>, default_value = "0x20000", requires = "changed_only")]
+    block_len: u32,
+
+    /// Write the image to the alternate of the partition, e.g. boot1 for boot0, and switch
+    /// the device to boot from it once it's read back, so a failed transfer leaves the
+    /// previous image bootable. Done for the partitions of the bootloader without it. Needs
+    /// version 4 or later of the UART download protocol
+    #[arg(long, requires = "image")]
+    two_phase: bool,
+}
+
+/// Where the images come from, and what's changed in them before they're sent
+#[derive(clap::Args, Debug)]
+#[group(skip)]
+struct ImageOptions {
+    /// Replace @NAME@ tokens in the payload, the FIP and the YMODEM file with a value,
+    /// given as NAME=VALUE, e.g. MAC=00:0c:43:26:60:01. Can be repeated
+    #[arg(long)]
+    patch_string: Vec<String>,
+
+    /// CSV file with values of @NAME@ tokens, one unit per row. The header names the tokens
+    #[arg(long, requires = "patch_row")]
+    patch_csv: Option<String>,
+
+    /// Row of --patch-csv to take the values from, by its first column, e.g. a serial number
+    #[arg(long, requires = "patch_csv")]
+    patch_row: Option<String>,
+
+    /// Directory for images prepared from the given ones, e.g. decompressed *.gz images.
+    /// Only this user may write to it. Defaults to mtk_uartboot/images in the cache
+    /// directory of the user, e.g. ~/.cache
+    #[arg(long)]
+    cache_dir: Option<String>,
+
+    /// How SHA-256 and CRC-32 are computed: accelerated, with the SHA and CRC instructions of
+    /// the host CPU, or portable. Defaults to accelerated if the CPU has them. `digest-bench`
+    /// shows which is faster here
+    #[arg(long, global = true)]
+    digest_engine: Option<String>,
+
+    /// Directory of the image store, where images given as store:NAME are read from.
+    /// Defaults to mtk_uartboot/images in ~/.local/share
+    #[arg(long, global = true)]
+    store_dir: Option<String>,
+
+    /// Only boot devices and send images allowed by this policy file
+    #[arg(long)]
+    policy: Option<String>,
+
+    /// Only send images with a valid detached signature by this minisign or cosign public
+    /// key, in IMAGE.minisig or IMAGE.sig. The images can't be modified then
+    #[cfg(feature = "verify-sig")]
+    #[arg(long, conflicts_with_all = ["patch_string", "patch_csv"])]
+    verify_sig: Option<String>,
+}
+
+/// What the run reports and records, and where it stops
+#[derive(clap::Args, Debug)]
+#[group(skip)]
+struct RunOptions {
+    /// Preset for containers and flashing farms: no colors or prompts, --serial is required
+    /// (a /dev/serial/by-id name is enough), a JSON summary is the last line of output, and
+    /// exit statuses tell failures apart
+    #[arg(long)]
+    headless: bool,
+
+    /// Format of the output. json writes the events of the boot as JSON lines to stdout,
+    /// e.g. the device, the checksums of the transfers and the result with its exit status,
+    /// for automation
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Translations of the messages for line operators, e.g. the PASS/FAIL board, the
+    /// prompts and the pipeline steps. The messages subcommand prints a template
+    #[arg(long, value_name = "FILE")]
+    messages: Option<String>,
+
+    /// Print the ack latency and retries of the FIP packets, and add them to the report.
+    /// Twice to print every packet as it's acknowledged
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Don't show the progress and speed of the payload and FIP transfers
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Command printing telemetry as name=value pairs, e.g. voltage=4.98 temperature=41.5,
+    /// sampled while booting
+    #[arg(long)]
+    telemetry_cmd: Option<String>,
+
+    /// Milliseconds between telemetry samples
+    #[arg(long, default_value_t = 1000)]
+    telemetry_interval_ms: u64,
+
+    /// Log every telemetry sample to this file
+    #[arg(long, requires = "telemetry_cmd")]
+    telemetry_log: Option<String>,
+
+    /// Warn about possible brown-outs when the sampled voltage drops below this
+    #[arg(long, requires = "telemetry_cmd")]
+    min_voltage: Option<f64>,
+
+    /// Stop at this point of the boot flow, e.g. to attach a debugger before the loaded
+    /// image runs. Can be repeated
+    #[arg(long, value_enum)]
+    pause_at: Vec<PausePoint>,
+
+    /// Command to run when paused instead of waiting for Enter. The boot continues when
+    /// it exits, and is aborted if it fails
+    #[arg(long, requires = "pause_at")]
+    pause_cmd: Option<String>,
+
+    /// Write a report of what was sent, including SHA-256 hashes of the exact data, to this file
+    #[arg(long)]
+    report: Option<String>,
+
+    /// Also record in the report when the run started and ended by the wall clock, and when
+    /// every stage was reached by the monotonic clock, which suspending the host or setting
+    /// its clock doesn't disturb. Makes reports of identical runs differ
+    #[arg(long)]
+    report_times: bool,
+
+    /// Write a report for people to this HTML file: the outcome, the device, every event with
+    /// its time, the warnings, the hashes of what was sent and the console excerpts. Made of
+    /// the same events as --output json, e.g. to attach to a bring-up ticket
+    #[arg(long)]
+    report_html: Option<String>,
+
+    /// Append a record of every successful boot to this file: the hw code and versions of
+    /// the SoC, the kind of adapter and the baud rates, nothing identifying the host or the
+    /// device. `matrix FILE` turns the records into a compatibility table
+    #[arg(long)]
+    matrix: Option<String>,
+
+    /// Time every protocol command, split into writing, waiting for the device, rate
+    /// limiting and the host, and write the spans to FILE: a Chrome trace for Perfetto if it
+    /// ends in .json, folded stacks for flamegraph.pl or inferno otherwise
+    #[arg(long, value_name = "FILE")]
+    timing: Option<String>,
+
+    /// Record the completed stages in this file. An interrupted run is summarized on the next start
+    #[arg(long)]
+    journal: Option<String>,
+
+    /// Skip the stages an interrupted run recorded as done in the journal
+    #[arg(long, requires = "journal")]
+    resume: bool,
+
+    /// Write to the storage of the device without asking first, e.g. in scripts. Pipelines,
+    /// monitor and bundles only write to the storage with it, and the daemon only lets
+    /// submitters do so with it
+    #[arg(long)]
+    yes: bool,
+}
+
+/// How the console output of the device is shown and kept
+#[derive(clap::Args, Debug)]
+#[group(skip)]
+struct ConsoleOptions {
+    /// Only print console lines matching this regex. Can be repeated
+    #[arg(long)]
+    grep: Vec<String>,
+
+    /// Colorize parts of console lines matching this regex. Can be repeated
+    #[arg(long)]
+    highlight: Vec<String>,
+
+    /// Put the console lines around those matching this regex into the report, e.g.
+    /// "panic|ERROR", as a short excerpt of the boot log. Can be repeated
+    #[arg(long)]
+    excerpt: Vec<String>,
+
+    /// Console lines kept before and after each --excerpt match
+    #[arg(long, default_value_t = 5)]
+    excerpt_context: usize,
+
+    /// Remove ANSI escape sequences, e.g. the colors of some U-Boot builds, from the console
+    /// output. auto removes them from the --console-log sinks, and from stdout when it isn't
+    /// a terminal. The --console terminal keeps them
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = StripAnsi::Auto, num_args = 0..=1, default_missing_value = "always")]
+    strip_ansi: StripAnsi,
+
+    /// Print console lines of the known formats of the MediaTek preloader, TF-A, OP-TEE,
+    /// U-Boot and Linux in columns: the device time, the module, the level and the message.
+    /// Runs of hex bytes get their text appended. The report counts the lines of each module
+    /// and their device times under log.*. The --console-log sinks get the lines unchanged
+    #[arg(long)]
+    decode_log: bool,
+
+    /// How to print non-text data received on the console
+    #[arg(long, value_enum, default_value_t = BinaryView::Lossy)]
+    binary_view: BinaryView,
+
+    /// ELF image with symbols of the payload. Code addresses in crash dumps on the console
+    /// are shown with the function they're in
+    #[arg(long)]
+    symbols: Option<String>,
+
+    /// Write non-text data received on the console to this file instead of printing it
+    #[arg(long)]
+    binary_out: Option<String>,
+
+    /// Also send the console lines, unfiltered, to stdout, file:PATH, journald, syslog,
+    /// tcp:HOST:PORT or unix:PATH. Can be repeated
+    #[arg(long, value_name = "SINK")]
+    console_log: Vec<SinkSpec>,
+
+    /// Serial port of the console, for boards with the console on a separate UART. The
+    /// download protocol stays on --serial
+    #[arg(long)]
+    console_serial: Option<String>,
+
+    /// Baud rate of the console once the payload or FIP is running, and of --console-serial
+    #[arg(long, default_value_t = 115200)]
+    console_baudrate: u32,
+}
+
+/// What's done on the console once the device is booted
+#[derive(clap::Args, Debug)]
+#[group(skip)]
+struct StepOptions {
+    /// What to do with the device once it's booted. reboot pulses --gpio-reset or cycles
+    /// --power if given, poweroff switches --power off. Otherwise the command is typed at the
+    /// console prompt
+    #[arg(long, value_enum, default_value_t = After::Stay)]
+    after: After,
+
+    /// Console command for --after, instead of U-Boot's reset or poweroff, e.g. reboot for
+    /// a Linux shell
+    #[arg(long, requires = "after")]
+    after_cmd: Option<String>,
+
+    /// Finally wait for this console message showing the system booted, e.g. "login:" or
+    /// "Reached target Multi-User System", and fail if it doesn't show up. Can be repeated,
+    /// any of them will do
+    #[arg(long)]
+    wait_boot: Vec<String>,
+
+    /// Seconds to wait for --wait-boot, at most
+    #[arg(long, default_value_t = 300)]
+    wait_boot_timeout: u64,
+
+    /// After booting, stay on the console as a terminal like picocom, at --console-baudrate:
+    /// keys typed are sent to the device and what it prints is shown. Ctrl-A q exits
+    #[arg(long, conflicts_with = "headless")]
+    console: bool,
+
+    /// Close the --console terminal after this long without output from the device or keys
+    /// typed, e.g. 30s, 5m or 500ms
+    #[arg(long, value_parser = duration, requires = "console")]
+    exit_on_idle: Option<Duration>,
+
+    /// Close the --console terminal once the device prints a line matching this regex, e.g.
+    /// "login:". Can be repeated
+    #[arg(long, requires = "console")]
+    exit_on_pattern: Vec<String>,
+
+    /// A key typing text in the --console terminal, as KEY=TEXT, e.g. F2='setenv bootargs
+    /// console=ttyS0,115200\n'. KEY is F1 to F12, or ^A and a key typed after Ctrl-A. \n
+    /// in TEXT is Enter, \t Tab and \xHH any byte. Can be repeated
+    #[arg(long = "macro", value_name = "KEY=TEXT", value_parser = terminal::Macro::parse, requires = "console")]
+    macros: Vec<terminal::Macro>,
+
+    #[cfg(feature = "ymodem")]
+    /// Send this file over YMODEM to the booted bootloader (e.g. U-Boot loady)
+    #[arg(long)]
+    ymodem: Option<String>,
+
+    #[cfg(feature = "ymodem")]
+    /// Command typed into the console to start the YMODEM receiver
+    #[arg(long, default_value = "loady")]
+    ymodem_cmd: String,
+
+    /// Console prompt to wait for before typing commands into the booted bootloader
+    #[arg(long, default_value = "=> ")]
+    prompt: String,
+
+    /// Serve this directory over TFTP while commands are run in the booted bootloader
+    #[cfg(feature = "tftp")]
+    #[arg(long)]
+    tftp_root: Option<String>,
+
+    /// File from the TFTP directory to load with tftpboot
+    #[cfg(feature = "tftp")]
+    #[arg(long, requires = "tftp_root")]
+    tftp_file: Option<String>,
+
+    /// Address the TFTP server listens on
+    #[cfg(feature = "tftp")]
+    #[arg(long, default_value = "0.0.0.0:69")]
+    tftp_bind: String,
+
+    /// IP address of this host as seen by the device. Guessed from the default route if omitted
+    #[cfg(feature = "tftp")]
+    #[arg(long)]
+    tftp_host_ip: Option<String>,
+
+    /// Command typed into the console to fetch the TFTP file. {host_ip} and {file} are substituted
+    #[cfg(feature = "tftp")]
+    #[arg(long, default_value = "setenv serverip {host_ip}; tftpboot {file}")]
+    tftp_cmd: String,
+
+    #[cfg(feature = "ymodem")]
+    /// Serve files from this directory when the device prints <<UARTBOOT:SEND name>> requests,
+    /// until it prints <<UARTBOOT:DONE>>
+    #[arg(long)]
+    serve_dir: Option<String>,
+
+    #[cfg(feature = "ymodem")]
+    /// Seconds of console silence after which serving device requests is given up
+    #[arg(long, default_value_t = 60)]
+    serve_timeout: u64,
+
+    #[cfg(feature = "hooks")]
+    /// After booting, wait for this network interface to appear (e.g. usb0 or enx*)
+    #[arg(long)]
+    wait_netif: Option<String>,
+
+    #[cfg(feature = "hooks")]
+    /// Seconds to wait for the network interface
+    #[arg(long, default_value_t = 60)]
+    netif_timeout: u64,
+
+    #[cfg(feature = "hooks")]
+    /// Shell command to run after booting, once the serial port is closed. Can be repeated
+    #[arg(long)]
+    post_boot_hook: Vec<String>,
+}
+
+/// The options of the boot, whichever subcommand gave them. The groups they come from
+/// describe them.
+#[derive(Debug)]
+struct Args {
+    serial: Option<String>,
+    wait_for_port: bool,
+    steal: bool,
+    power: Option<String>,
+    power_off_ms: u64,
+    gpio_reset: Option<GpioLine>,
+    gpio_dl: Option<GpioLine>,
+    gpio_chip: String,
+    gpio_reset_ms: u64,
+    bl2_load_baudrate: u32,
+    max_rate: Option<u32>,
+    write_timeout_ms: u64,
+    no_calibration: bool,
+    trace: Option<String>,
+
+    payload: Option<String>,
+    load_addr: Option<u32>,
+    load: Vec<(u32, String)>,
+    entry: Option<EntryPoint>,
+    boot_args: Option<(u32, Vec<u32>)>,
+    param_block: Option<u32>,
+    param_word: Vec<u32>,
+    next_payload: Option<(u32, String)>,
+    next_aarch32: bool,
+    next_marker: Option<String>,
+    next_boot_args: Option<(u32, Vec<u32>)>,
+    verify: bool,
+    save_sram: Option<String>,
+    save_sram_kib: u32,
+    payload_sig_len: u32,
+    auth: Option<String>,
+    cert: Option<String>,
+    sla_sign_cmd: Option<String>,
+    aarch64: bool,
+    aarch32: bool,
+    disable_watchdog: bool,
+    patch: Vec<BytePatch>,
+    handshake_timeout: Option<u64>,
+    rt: bool,
+    brom_load_baudrate: u32,
+    read_only: bool,
+    dry_run: bool,
+    soc: Option<String>,
+
+    fip: Option<String>,
+    bl31_log_level: Option<LogLevel>,
+    dtbo: Vec<String>,
+    dtb_entry: Option<String>,
+    fip_patch: Vec<BytePatch>,
+    replace: Vec<(String, String)>,
+    deterministic: bool,
+    packet_len: u16,
+
+    image: Option<String>,
+    partition: Option<String>,
+    offset: Option<u64>,
+    changed_only: bool,
+    block_len: u32,
+    two_phase: bool,
+
+    patch_string: Vec<String>,
+    patch_csv: Option<String>,
+    patch_row: Option<String>,
+    cache_dir: Option<String>,
+    digest_engine: Option<String>,
+    store_dir: Option<String>,
+    policy: Option<String>,
+    #[cfg(feature = "verify-sig")]
+    verify_sig: Option<String>,
+
+    headless: bool,
+    output: OutputFormat,
+    messages: Option<String>,
+    verbose: u8,
+    quiet: bool,
+    telemetry_cmd: Option<String>,
+    telemetry_interval_ms: u64,
+    telemetry_log: Option<String>,
+    min_voltage: Option<f64>,
+    pause_at: Vec<PausePoint>,
+    pause_cmd: Option<String>,
+    report: Option<String>,
+    report_times: bool,
+    report_html: Option<String>,
+    matrix: Option<String>,
+    timing: Option<String>,
+    journal: Option<String>,
+    resume: bool,
+    yes: bool,
+
+    grep: Vec<String>,
+    highlight: Vec<String>,
+    excerpt: Vec<String>,
+    excerpt_context: usize,
+    strip_ansi: StripAnsi,
+    decode_log: bool,
+    binary_view: BinaryView,
+    symbols: Option<String>,
+    binary_out: Option<String>,
+    console_log: Vec<SinkSpec>,
+    console_serial: Option<String>,
+    console_baudrate: u32,
+
+    after: After,
+    after_cmd: Option<String>,
+    wait_boot: Vec<String>,
+    wait_boot_timeout: u64,
+    console: bool,
+    exit_on_idle: Option<Duration>,
+    exit_on_pattern: Vec<String>,
+    macros: Vec<terminal::Macro>,
+    #[cfg(feature = "ymodem")]
+    ymodem: Option<String>,
+    #[cfg(feature = "ymodem")]
+    ymodem_cmd: String,
+    prompt: String,
+    #[cfg(feature = "tftp")]
+    tftp_root: Option<String>,
+    #[cfg(feature = "tftp")]
+    tftp_file: Option<String>,
+    #[cfg(feature = "tftp")]
+    tftp_bind: String,
+    #[cfg(feature = "tftp")]
+    tftp_host_ip: Option<String>,
+    #[cfg(feature = "tftp")]
+    tftp_cmd: String,
+    #[cfg(feature = "ymodem")]
+    serve_dir: Option<String>,
+    #[cfg(feature = "ymodem")]
+    serve_timeout: u64,
+    #[cfg(feature = "hooks")]
+    wait_netif: Option<String>,
+    #[cfg(feature = "hooks")]
+    netif_timeout: u64,
+    #[cfg(feature = "hooks")]
+    post_boot_hook: Vec<String>,
+
+    /// BL2 is already running and waiting for the FIP, set by the fip subcommand
+    bl2_running: bool,
+
+    /// The board being rescued, whose SoC the device has to have, set by the rescue subcommand
+    rescue: Option<&'static rescue::Board>,
+}
+
+impl Args {
+    #[allow(clippy::too_many_arguments)]
+    fn new(link: LinkOptions, bootrom: BootromOptions, fip: FipOptions, flash: FlashOptions, images: ImageOptions,
+           run: RunOptions, console: ConsoleOptions, steps: StepOptions) -> Args {
+        let LinkOptions {
+            serial, wait_for_port, steal, power, power_off_ms, gpio_reset, gpio_dl, gpio_chip,
+            gpio_reset_ms, bl2_load_baudrate, max_rate, write_timeout_ms, no_calibration, trace,
+        } = link;
+        let BootromOptions {
+            payload, load_addr, load, entry, boot_args, param_block, param_word, next_payload,
+            next_aarch32, next_marker, next_boot_args, verify, save_sram, save_sram_kib,
+            payload_sig_len, auth, cert, sla_sign_cmd, aarch64, aarch32, disable_watchdog, patch,
+            handshake_timeout, rt, brom_load_baudrate, read_only, dry_run, soc,
+        } = bootrom;
+        let FipOptions {
+            fip, bl31_log_level, dtbo, dtb_entry, fip_patch, replace, deterministic, packet_len,
+        } = fip;
+        let FlashOptions {
+            image, partition, offset, changed_only, block_len, two_phase,
+        } = flash;
+        let ImageOptions {
+            patch_string, patch_csv, patch_row, cache_dir, digest_engine, store_dir, policy,
+            #[cfg(feature = "verify-sig")] verify_sig,
+        } = images;
+        let RunOptions {
+            headless, output, messages, verbose, quiet, telemetry_cmd, telemetry_interval_ms,
+            telemetry_log, min_voltage, pause_at, pause_cmd, report, report_times, report_html,
+            matrix, timing, journal, resume, yes,
+        } = run;
+        let ConsoleOptions {
+            grep, highlight, excerpt, excerpt_context, strip_ansi, decode_log, binary_view, symbols,
+            binary_out, console_log, console_serial, console_baudrate,
+        } = console;
+        let StepOptions {
+            after, after_cmd, wait_boot, wait_boot_timeout, console, exit_on_idle, exit_on_pattern,
+            macros, prompt,
+            #[cfg(feature = "ymodem")] ymodem,
+            #[cfg(feature = "ymodem")] ymodem_cmd,
+            #[cfg(feature = "tftp")] tftp_root,
+            #[cfg(feature = "tftp")] tftp_file,
+            #[cfg(feature = "tftp")] tftp_bind,
+            #[cfg(feature = "tftp")] tftp_host_ip,
+            #[cfg(feature = "tftp")] tftp_cmd,
+            #[cfg(feature = "ymodem")] serve_dir,
+            #[cfg(feature = "ymodem")] serve_timeout,
+            #[cfg(feature = "hooks")] wait_netif,
+            #[cfg(feature = "hooks")] netif_timeout,
+            #[cfg(feature = "hooks")] post_boot_hook,
+        } = steps;
+        Args {
+            serial, wait_for_port, steal, payload, load_addr, load, entry, boot_args, param_block,
+            param_word, next_payload, next_aarch32, next_marker, next_boot_args, verify, save_sram,
+            save_sram_kib, payload_sig_len, auth, cert, sla_sign_cmd, aarch64, aarch32,
+            disable_watchdog, fip, bl31_log_level, dtbo, dtb_entry, patch, fip_patch, replace,
+            patch_string, patch_csv, patch_row, handshake_timeout, rt, power, power_off_ms,
+            gpio_reset, gpio_dl, gpio_chip, gpio_reset_ms, brom_load_baudrate, bl2_load_baudrate,
+            max_rate, write_timeout_ms, no_calibration, cache_dir, digest_engine, store_dir,
+            headless, output, messages, trace, verbose, quiet, telemetry_cmd, telemetry_interval_ms,
+            telemetry_log, min_voltage, pause_at, pause_cmd, read_only, dry_run, soc, deterministic,
+            packet_len, report, report_times, report_html, matrix, timing, policy, journal, resume,
+            grep, highlight, excerpt, excerpt_context, strip_ansi, decode_log, binary_view, symbols,
+            binary_out, console_log, console_serial, console_baudrate, after, after_cmd, wait_boot,
+            wait_boot_timeout, console, exit_on_idle, exit_on_pattern, macros, prompt, image,
+            partition, offset, changed_only, block_len, two_phase, yes,
+            #[cfg(feature = "verify-sig")] verify_sig,
+            #[cfg(feature = "ymodem")] ymodem,
+            #[cfg(feature = "ymodem")] ymodem_cmd,
+            #[cfg(feature = "tftp")] tftp_root,
+            #[cfg(feature = "tftp")] tftp_file,
+            #[cfg(feature = "tftp")] tftp_bind,
+            #[cfg(feature = "tftp")] tftp_host_ip,
+            #[cfg(feature = "tftp")] tftp_cmd,
+            #[cfg(feature = "ymodem")] serve_dir,
+            #[cfg(feature = "ymodem")] serve_timeout,
+            #[cfg(feature = "hooks")] wait_netif,
+            #[cfg(feature = "hooks")] netif_timeout,
+            #[cfg(feature = "hooks")] post_boot_hook,
+            bl2_running: false, rescue: None,
+        }
+    }
+}
+
+impl From<BootArgs> for Args {
+    fn from(a: BootArgs) -> Args {
+        Args::new(a.link, a.bootrom, a.fip, unset(), a.images, a.run, a.console, a.steps)
+    }
+}
+
+impl From<FipArgs> for Args {
+    fn from(a: FipArgs) -> Args {
+        Args { bl2_running: true, ..Args::new(a.link, unset(), a.fip, unset(), a.images, a.run, a.console, a.steps) }
+    }
+}
+
+impl From<FlashArgs> for Args {
+    fn from(a: FlashArgs) -> Args {
+        let args = Args::new(a.link, a.bootrom, unset(), a.flash, a.images, a.run, a.console, unset());
+        Args { bl2_running: args.payload.is_none() && args.load.is_empty(), ..args }
+    }
+}
+
+impl From<ProfileArgs> for Args {
+    fn from(a: ProfileArgs) -> Args {
+        Args::new(a.link, a.bootrom, a.fip, a.flash, a.images, a.run, a.console, a.steps)
+    }
+}
+
+/// The options of a group the subcommand doesn't take, as if none were given. What they
+/// require of or conflict with options of other groups doesn't matter then.
+fn unset<T: clap::Args + FromArgMatches>() -> T {
+    let command = T::augment_args(clap::Command::new("unset"))
+        .mut_args(|arg| arg.required(false).required_unless_present(Resettable::Reset)
+            .requires(Resettable::Reset).conflicts_with(Resettable::Reset));
+    T::from_arg_matches(&command.get_matches_from(["unset"])).expect("options without values parse")
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Send the payload to the BootROM and the FIP to BL2, then run the console steps. The
+    /// same as giving the options without a subcommand
+    Boot(Box<BootArgs>),
+
+    /// Send the FIP to BL2 which is already running and waiting for it, e.g. after a failed
+    /// FIP transfer or when BL2 boots from flash. The BootROM stage is skipped
+    #[command(mut_arg("fip", |a| a.required(true)))]
+    Fip(Box<FipArgs>),
+
+    /// Write an image to the eMMC or flash of the device through BL2, to recover a board
+    /// without a working bootloader. The payload has to be a BL2 with the flash command of
+    /// the UART download protocol, version 2 or later. Without a payload, that BL2 has to be
+    /// running and waiting already, e.g. one booted from storage next to a broken FIP
+    #[command(mut_arg("payload", |a| a.required_unless_present(Resettable::Reset)))]
+    #[command(mut_arg("image", |a| a.required(true)))]
+    Flash(Box<FlashArgs>),
+
+    /// Bring back a soft-bricked board of a known kind, walking through the steps: wait for
+    /// the BootROM, load the BL2 and FIP of the board from --images and optionally TFTP boot a
+    /// recovery image to run sysupgrade from. Lists the boards without --board
+    #[command(mut_arg("payload", |a| a.required_unless_present(Resettable::Reset).hide(true)))]
+    #[command(mut_arg("fip", |a| a.hide(true)))]
+    Rescue {
+        /// The board, e.g. bpi-r3
+        #[arg(long)]
+        board: Option<String>,
+
+        /// Directory with BOARD-bl2.bin, a BL2 built to run from RAM and wait for the FIP on the
+        /// UART, and BOARD-fip.bin with BL31 and U-Boot
+        #[arg(long, default_value = ".")]
+        images: String,
+
+        /// Image from --images to TFTP boot from U-Boot, e.g. an OpenWrt initramfs
+        #[cfg(feature = "tftp")]
+        #[arg(long)]
+        recovery: Option<String>,
+
+        #[command(flatten)]
+        args: Box<BootArgs>,
+    },
+
+    /// Print the hw code, versions and security features of the device and exit. Only
+    /// read-only commands are sent
+    Info,
+
+    /// Declarative multi-stage bring-up
+    #[cfg(feature = "pipeline")]
+    #[command(subcommand)]
+    Pipeline(PipelineCommand),
+
+    /// Boot serial devices as they are plugged in
+    #[cfg(feature = "monitor")]
+    Monitor {
+        /// TOML profile with the long command line options to boot attached devices with
+        #[arg(long)]
+        on_attach: String,
+
+        /// Only boot devices whose port or /dev/serial/by-id name matches this pattern.
+        /// '*' matches anything. Can be repeated
+        #[arg(long)]
+        filter: Vec<String>,
+    },
+
+    /// Signed bundles of images with the profile or pipeline booting a board with them,
+    /// for stations without network access
+    #[cfg(feature = "bundle")]
+    #[command(subcommand)]
+    Bundle(BundleCommand),
+
+    /// Store of images sharing the data of identical FIP images and files between
+    /// versions, for stations keeping many of them. Boots read them as store:NAME
+    #[command(subcommand)]
+    Images(ImagesCommand),
+
+    /// Run boot jobs queued over an HTTP API on the serial ports of this station
+    #[cfg(feature = "daemon")]
+    Daemon {
+        /// Address the API listens on
+        #[arg(long, default_value = "127.0.0.1:8700")]
+        listen: String,
+
+        /// Directory keeping the job queue across restarts
+        #[arg(long)]
+        state_dir: String,
+
+        /// POST a JSON summary of every finished job to this URL, e.g. a Slack incoming webhook
+        #[arg(long)]
+        webhook: Option<String>,
+
+        /// TOML file with the API tokens and their roles. Without it, anyone on this host
+        /// has every role, and --listen must be a loopback address
+        #[arg(long)]
+        tokens: Option<String>,
+    },
+
+    /// Find the fastest reliable baud rate of the adapter and cable, with TX shorted to RX
+    /// or against a payload echoing everything back. No MTK device is needed
+    Linktest {
+        /// Baud rates to test
+        #[arg(long, value_delimiter = ',', default_value = "115200,230400,460800,921600,1500000,2000000,3000000")]
+        baudrates: Vec<u32>,
+
+        /// Bytes sent at every baud rate
+        #[arg(long, default_value_t = 16384)]
+        len: usize,
+    },
+
+    /// Measure the round trip latency and jitter of the link at several baud rates, against
+    /// a tiny payload for the SoC echoing everything back. The device is reset into download
+    /// mode for every rate, with --gpio-reset or --power if given, by hand otherwise
+    Latency {
+        /// Baud rates to measure, each set with the BootROM before the payload is sent
+        #[arg(long, value_delimiter = ',', default_value = "115200,460800,921600,1500000,3000000")]
+        baudrates: Vec<u32>,
+
+        /// Sizes of the writes whose echo is waited for, from single bytes to the window of
+        /// a transfer
+        #[arg(long, value_delimiter = ',', default_value = "1,16,64,256,1024")]
+        sizes: Vec<usize>,
+
+        /// Round trips per size and baud rate
+        #[arg(long, default_value_t = 200)]
+        count: usize,
+    },
+
+    /// List the serial ports with their USB IDs and descriptions, to find the one of the
+    /// device
+    ListPorts,
+
+    /// Print the messages for line operators in English, as `key = text` lines to translate
+    /// for --messages
+    Messages,
+
+    /// Print the records --matrix collected as a Markdown table of the SoCs and BootROM
+    /// versions, with the adapters and the fastest baud rates which worked, e.g. to submit
+    /// for the compatibility table of the README
+    Matrix {
+        file: String,
+    },
+
+    /// Hash data in memory with each digest engine and print how fast they are, to check
+    /// whether the instructions of the host CPU are used
+    DigestBench {
+        /// MiB of data to hash
+        #[arg(long, default_value_t = 256)]
+        mib: usize,
+    },
+
+    /// Describe what a stage of the boot does on the wire and what commonly makes it fail,
+    /// e.g. `explain handshake` or `explain daa`. The hints printed on errors come from the
+    /// same descriptions. Lists the topics without one
+    Explain {
+        topic: Option<String>,
+    },
+
+    /// Check that the device answers: do the BootROM handshake, read the hw code and exit.
+    /// Only read-only commands are sent. Exits with 3 if there's no answer
+    Ping {
+        /// Milliseconds to wait for the handshake
+        #[arg(long, default_value_t = 3000)]
+        timeout_ms: u64,
+    },
+
+    /// Check the port, the adapter and the device in download mode step by step, and print
+    /// a checklist of what passed. Only read-only commands are sent. The command to run
+    /// before filing a bug or blaming the hardware
+    Doctor {
+        /// Milliseconds to wait for the handshake
+        #[arg(long, default_value_t = 3000)]
+        timeout_ms: u64,
+
+        /// Read the first KiB of the SRAM payloads are loaded to this many times and check
+        /// every read gives the same, to find a link losing or garbling bytes
+        #[arg(long, default_value_t = 0)]
+        sram_reads: u32,
+    },
+
+    /// Read a region of memory with the BootROM read commands into a file, e.g. to check
+    /// SRAM or registers during bring-up. Only read-only commands are sent
+    Dump {
+        /// Start address, a multiple of 2
+        #[arg(long, value_parser=maybe_hex::<u32>)]
+        addr: u32,
+
+        /// Bytes to read, a multiple of 2
+        #[arg(long, value_parser=maybe_hex::<u32>)]
+        size: u32,
+
+        /// File the memory is written to
+        #[arg(long)]
+        out: String,
+    },
+
+    /// Read 32-bit words, e.g. registers or efuses, with the BootROM and print them. Only
+    /// read-only commands are sent
+    Peek {
+        /// Address of the first word, a multiple of 4
+        #[arg(value_parser=maybe_hex::<u32>)]
+        addr: u32,
+
+        /// Consecutive words to read
+        #[arg(value_parser=maybe_hex::<u32>, default_value_t = 1)]
+        count: u32,
+    },
+
+    /// Write 32-bit words with the BootROM, e.g. to disable the watchdog before sending
+    /// the payload
+    Poke {
+        /// Address of the first word, a multiple of 4
+        #[arg(value_parser=maybe_hex::<u32>)]
+        addr: u32,
+
+        /// Values of the consecutive words from addr on
+        #[arg(value_parser=maybe_hex::<u32>, required = true)]
+        values: Vec<u32>,
+    },
+
+    /// Compare the images of two FIPs by UUID, size and hash, to see what changed between
+    /// two firmware drops. Exits with 1 if they differ
+    FipDiff {
+        /// The old FIP
+        a: String,
+
+        /// The new FIP
+        b: String,
+    },
+
+    /// mtk_uartboot-NAME on PATH, a plugin
+    #[command(external_subcommand)]
+    Plugin(Vec<String>),
+}
+
+#[cfg(feature = "pipeline")]
+#[derive(Subcommand, Debug)]
+enum PipelineCommand {
+    /// Run the steps of a YAML pipeline file
+    Run {
+        /// Path to the pipeline file
+        file: String,
+
+        /// Run the pipeline on this serial port instead of the one in the file. Can be
+        /// repeated to run on several ports in parallel
+        #[arg(long)]
+        port: Vec<String>,
+
+        /// Show big PASS/FAIL blocks per port for line operators and offer to retry failed ports
+        #[arg(long)]
+        operator: bool,
+
+        /// Read a unit ID for every port from stdin before running, e.g. from a barcode scanner.
+        /// It's available as the unit_id fact and recorded in reports
+        #[arg(long)]
+        scan_unit_id: bool,
+
+        /// Compare the hw codes, versions and target config words the BootROMs of all ports
+        /// tell once they're through the handshake, and stop the units which differ from most
+        /// of them before anything is sent to any unit
+        #[arg(long)]
+        compare_config: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ImagesCommand {
+    /// Add an image, under its file name unless --name is given
+    Add {
+        file: String,
+
+        #[arg(long)]
+        name: Option<String>,
+    },
+
+    /// List the images and their sizes
+    List,
+
+    /// Write an image to a file
+    Get {
+        name: String,
+
+        #[arg(long)]
+        out: String,
+    },
+
+    /// Remove an image. The data only it used is deleted by gc
+    Rm {
+        name: String,
+    },
+
+    /// Delete the data no image uses anymore
+    Gc,
+}
+
+#[cfg(feature = "bundle")]
+#[derive(Subcommand, Debug)]
+enum BundleCommand {
+    /// Generate a signing key, and its public key as KEY.pub
+    Keygen {
+        /// Path of the signing key
+        key: String,
+    },
+
+    /// Create a signed bundle
+    Create {
+        /// Path of the bundle, e.g. mt7986-rfb.mtkpkg
+        #[arg(long)]
+        out: String,
+
+        /// Signing key from bundle keygen
+        #[arg(long)]
+        key: String,
+
+        /// Board profile booting with the files, like for monitor --on-attach
+        #[arg(long, required_unless_present = "pipeline", conflicts_with = "pipeline")]
+        profile: Option<String>,
+
+        /// Pipeline run with the files instead of a single boot
+        #[arg(long)]
+        pipeline: Option<String>,
+
+        /// Images and other files the profile or pipeline refers to by their names
+        files: Vec<String>,
+    },
+
+    /// Check the signature and contents of a bundle and list them
+    Verify {
+        bundle: String,
+
+        /// Public key the bundle must be signed with
+        #[arg(long)]
+        pubkey: String,
+    },
+
+    /// Verify a bundle and boot the device on --serial with it, or run its pipeline
+    Run {
+        bundle: String,
+
+        /// Public key the bundle must be signed with
+        #[arg(long)]
+        pubkey: String,
+    },
+}
+
+fn power_url(url: &str) -> Result<String, String> {
+    match PowerSwitch::parse(url) {
+        Some(_) => Ok(url.to_owned()),
+        None => Err("expected tasmota://host/N, shelly://host/N, hidrelay://device/N or http://...".to_owned()),
+    }
+}
+
+fn segment(s: &str) -> Result<(u32, String), String> {
+    match s.split_once(':') {
+        Some((addr, file)) if !file.is_empty() => Ok((maybe_hex::<u32>(addr)?, file.to_owned())),
+        _ => Err("expected ADDR:FILE, e.g. 0x40000000:dram.bin".to_owned()),
+    }
+}
+
+/// What --entry jumps to
+#[derive(Clone, Debug)]
+enum EntryPoint {
+    Addr(u32),
+    /// The payload, or the --load file of this name
+    File(String),
+}
+
+fn entry_point(s: &str) -> Result<EntryPoint, String> {
+    match maybe_hex::<u32>(s) {
+        Ok(addr) => Ok(EntryPoint::Addr(addr)),
+        Err(_) if !s.is_empty() => Ok(EntryPoint::File(s.to_owned())),
+        Err(e) => Err(e),
+    }
+}
+
+fn mailbox(s: &str) -> Result<(u32, Vec<u32>), String> {
+    let expected = || "expected ADDR:WORD,WORD,..., e.g. 0x110000:0x1,115200".to_owned();
+    let (addr, words) = s.split_once(':').ok_or_else(expected)?;
+    let words = words.split(',').map(maybe_hex::<u32>).collect::<Result<Vec<_>, _>>().map_err(|_| expected())?;
+    Ok((maybe_hex::<u32>(addr)?, words))
+}
+
+/// A duration in seconds, or with a unit: 500ms, 30s, 5m or 1h
+fn duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let n: u64 = number.parse().map_err(|_| "expected a duration like 30s, 5m or 500ms".to_owned())?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(n)),
+        "" | "s" => Ok(Duration::from_secs(n)),
+        "m" => Ok(Duration::from_secs(n * 60)),
+        "h" => Ok(Duration::from_secs(n * 3600)),
+        _ => Err(format!("unknown unit {}, expected ms, s, m or h", unit)),
+    }
+}
+
+fn replacement(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((image, file)) if !image.is_empty() && !file.is_empty() => Ok((image.to_owned(), file.to_owned())),
+        _ => Err("expected IMAGE=FILE, e.g. bl33=u-boot.bin".to_owned()),
+    }
+}
+
+fn print_event(event: &Event) {
+    match event {
+        Event::PacketRejected { idx, ack } => match ack {
+            PacketAck::WrongIndex { expected } =>
+                println!("Incorrect packet index: {} != {}", idx, expected),
+            PacketAck::WrongChecksum { received } =>
+                println!("Incorrect checksum on packet {}: {:#x}", idx, received),
+            PacketAck::Ok => {}
+        },
+        Event::Paused { .. } => println!("Paused, Ctrl-A p resumes."),
+        Event::Resumed { paused, .. } => println!("Resumed after {:.1} s.", paused.as_secs_f64()),
+        _ => {}
+    }
+}
+
+/// The token values of --patch-string and --patch-csv, if any are given
+fn token_values(args: &Args) -> Option<Vec<(String, String)>> {
+    if args.patch_string.is_empty() && args.patch_csv.is_none() {
+        return None;
+    }
+    Some(patch::load_vars(&args.patch_string, args.patch_csv.as_deref(), args.patch_row.as_deref())
+        .unwrap_or_else(|e| panic!("Can't read token values: {}", e)))
+}
+
+/// Replaces the tokens of --patch-string and --patch-csv in an image. what names it in messages.
+fn substitute_vars(args: &Args, image: &mut [u8], what: &str, fip: bool) {
+    let Some(vars) = token_values(args) else { return };
+    let count = if fip { patch::substitute_fip(image, &vars) } else { patch::substitute(image, &vars) }
+        .unwrap_or_else(|e| panic!("Can't replace tokens in the {}: {}", what, e));
+    println!("Replaced {} tokens in the {}.", count, what);
+}
+
+/// Reads an image, checking its signature if --verify-sig is given. what names it in messages.
+fn read_image(args: &Args, path: &str, what: &str) -> Vec<u8> {
+    let prefetched = images::prefetched(path);
+    let data = match &prefetched {
+        Some(image) => image.raw.clone(),
+        None => match path.strip_prefix("store:") {
+            Some(name) => store::Store::new(args.store_dir.as_deref()).get(name),
+            None => std::fs::read(path),
+        }.unwrap_or_else(|e| panic!("failed to open {}: {}", what, e)),
+    };
+    #[cfg(feature = "verify-sig")]
+    if let Some(key) = &args.verify_sig {
+        let key = signature::PublicKey::load(key).unwrap_or_else(|e| panic!("{}", e));
+        match key.verify(path, &data) {
+            Ok(Some(comment)) => println!("Signature of the {} verified, {}", what, comment),
+            Ok(None) => println!("Signature of the {} verified.", what),
+            Err(e) => panic!("Can't verify the {}: {}", what, e),
+        }
+    }
+    if let Some(image) = prefetched {
+        return image.unpacked.clone().unwrap_or(data);
+    }
+    ImageCache::new(args.cache_dir.as_deref()).unpack(path, data)
+        .unwrap_or_else(|e| panic!("failed to decompress {}: {}", what, e))
+}
+
+/// Makes the changes asked for to an image through the cache, so workers booting many ports
+/// from the same images make them once. changes describes all of them, with the hashes of the
+/// files they take, so an entry is only used for the same image made the same way.
+fn prepared(args: &Args, what: &str, data: Vec<u8>, changes: &[String], make: impl FnOnce(Vec<u8>) -> Vec<u8>) -> Vec<u8> {
+    if changes.is_empty() {
+        return make(data);
+    }
+    let mut made = false;
+    let prepared = ImageCache::new(args.cache_dir.as_deref())
+        .get_or_create(&data, &format!("{}\n{}", what, changes.join("\n")), |data| {
+            made = true;
+            Ok(make(data.to_vec()))
+        })
+        .unwrap_or_else(|e| panic!("Can't prepare the {}: {}", what, e));
+    if !made {
+        println!("The {} was prepared the same way before, it's taken from the cache.", what);
+    }
+    prepared
+}
+
+/// The secrets of secured devices from the command line: --sla-sign-cmd signs the SLA
+/// challenge and --cert is the DAA certificate.
+struct CliAuth {
+    sign: Option<CommandAuth>,
+    cert: Option<FileAuth>,
+}
+
+impl AuthProvider for CliAuth {
+    fn sign_challenge(&mut self, hw_code: u16, challenge: &[u8]) -> std::io::Result<Vec<u8>> {
+        let sign = self.sign.as_mut().ok_or_else(|| std::io::Error::other("--sla-sign-cmd is needed"))?;
+        sign.sign_challenge(hw_code, challenge)
+    }
+
+    fn certificate(&mut self, hw_code: u16) -> std::io::Result<Vec<u8>> {
+        let cert = self.cert.as_mut().ok_or_else(|| std::io::Error::other("--cert is needed"))?;
+        cert.certificate(hw_code)
+    }
+}
+
+/// Does the SLA challenge-response and sends the DAA certificate, if the device needs them.
+fn authenticate(args: &Args, brom_dev: &mut BootRom, hw_code: u16, config: &TargetConfig) -> mtk_uartboot::Result<()> {
+    if !config.serial_link_authorization && !config.download_agent_authorization {
+        return Ok(());
+    }
+    let mut auth = CliAuth {
+        sign: args.sla_sign_cmd.as_deref().map(|cmd| CommandAuth::new(cmd, "")),
+        cert: args.cert.as_deref().map(FileAuth::new),
+    };
+    if let Some(path) = &args.auth {
+        brom_dev.send_auth(&std::fs::read(path).expect("failed to open auth file."))?;
+        println!("Auth file sent.");
+    }
+    if config.serial_link_authorization {
+        println!("Serial link authorization...");
+        brom_dev.sla(hw_code, &mut auth)?;
+    }
+    if config.download_agent_authorization {
+        if args.payload_sig_len == 0 {
+            fail(EXIT_SECURED, "Download agent authorization enabled, the payload needs a signature (--payload-sig-len).".to_owned());
+        }
+        brom_dev.send_cert(&auth.certificate(hw_code)?)?;
+        println!("DAA certificate sent.");
+    }
+    Ok(())
+}
+
+/// The reset into download mode of reset_into_download, which runs while the handshake does
+struct Reset {
+    /// The download strap GPIO of --gpio-dl, held until the reset is finished
+    dl_strap: Option<GpioOutput>,
+    /// The threads resetting or power cycling the device, each with what it does
+    threads: Vec<(&'static str, std::thread::JoinHandle<std::io::Result<()>>)>,
+}
+
+impl Reset {
+    /// Releases the download strap and waits for the reset to finish, returns why it failed
+    fn finish(self) -> Result<(), String> {
+        drop(self.dl_strap);
+        for (what, thread) in self.threads {
+            match thread.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => return Err(format!("{} failed: {}", what, e)),
+                Err(_) => return Err(format!("{} failed", what)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Finishes the reset after the handshake. A failed reset is the error of a failed
+    /// handshake, since the device likely wasn't reset at all, and a warning otherwise.
+    fn finish_after(self, handshake: mtk_uartboot::Result<()>) -> mtk_uartboot::Result<()> {
+        match (self.finish(), handshake) {
+            (Err(e), Err(_)) => Err(mtk_uartboot::Error::Io(std::io::Error::other(e))),
+            (Err(e), Ok(())) => {
+                output::warning(&format!("{}, but the BootROM answered.", e));
+                Ok(())
+            }
+            (Ok(()), handshake) => handshake,
+        }
+    }
+}
+
+/// Resets the device with --gpio-reset or power cycles it with --power, if given, in the
+/// background while the handshake runs. Sets the download strap GPIO of --gpio-dl, held
+/// until the reset is finished.
+fn reset_into_download(args: &Args) -> Reset {
+    // The strap is sampled as the device comes out of reset, it's released once the BootROM
+    // answers so the next reset boots normally.
+    let dl_strap = args.gpio_dl.as_ref().map(|line| {
+        GpioOutput::request(&args.gpio_chip, line, true)
+            .unwrap_or_else(|e| panic!("Can't set the download strap GPIO {}: {}", line.offset, e))
+    });
+    let mut threads = Vec::new();
+    if let Some(line) = args.gpio_reset.clone() {
+        let (chip, pulse) = (args.gpio_chip.clone(), Duration::from_millis(args.gpio_reset_ms));
+        println!("Resetting the device...");
+        threads.push(("Reset", std::thread::spawn(move || {
+            let result = gpio::pulse(&chip, &line, pulse);
+            if let Err(e) = &result {
+                println!("Reset failed: {}", e);
+            }
+            result
+        })));
+    }
+    if let Some(url) = &args.power {
+        let switch = PowerSwitch::parse(url).unwrap();
+        let off_time = Duration::from_millis(args.power_off_ms);
+        println!("Power cycling the device...");
+        // The BootROM only waits briefly for a handshake after power up, so it runs meanwhile.
+        threads.push(("Power cycle", std::thread::spawn(move || {
+            let result = switch.cycle(off_time);
+            if let Err(e) = &result {
+                println!("Power cycle failed: {}", e);
+            }
+            result
+        })));
+    }
+    Reset { dl_strap, threads }
+}
+
+/// Raises the priority of this thread for --rt, and records how in the report
+fn raise_priority(report: &mut Report) -> Option<realtime::Raised> {
+    match realtime::raise() {
+        Ok(raised) => {
+            println!("Handshake with {}.", raised.priority);
+            report.set("rt", raised.priority);
+            Some(raised)
+        }
+        Err(e) => {
+            println!("Can't raise the priority of the handshake: {}.", e);
+            report.set("rt", "unavailable");
+            None
+        }
+    }
+}
+
+/// Fills in the images and boot options of a board for rescue, checking that the images are
+/// there before the operator is asked to do anything
+fn rescue_args(args: &mut Args, board: &'static rescue::Board, images: &str) {
+    let image = |kind: &str| {
+        let path = Path::new(images).join(format!("{}-{}.bin", board.name, kind));
+        if !path.exists() {
+            panic!("{} is missing. The rescue of a {} needs {}-bl2.bin, a BL2 built to run from RAM and wait for \
+                    the FIP on the UART (TF-A with BOOT_DEVICE=ram RAM_BOOT_UART_DL=1), and {}-fip.bin with BL31 and \
+                    U-Boot in --images.", path.display(), board.title, board.name, board.name);
+        }
+        path.to_string_lossy().into_owned()
+    };
+    args.payload = Some(image("bl2"));
+    args.fip = Some(image("fip"));
+    args.rescue = Some(board);
+}
+
+/// Hashes mib MiB with each digest engine, printing the throughput of SHA-256 and CRC-32
+fn digest_bench(mib: usize) {
+    let data: Vec<u8> = (0..mib << 20).map(|i| (i * 7 + (i >> 13)) as u8).collect();
+    let current = digest::engine().name();
+    for engine in digest::engines() {
+        let started = Instant::now();
+        let mut sha256 = engine.sha256();
+        sha256.update(&data);
+        sha256.finish();
+        let sha256_time = started.elapsed();
+        let started = Instant::now();
+        engine.crc32(0, &data);
+        let crc32_time = started.elapsed();
+        let rate = |t: Duration| mib as f64 / t.as_secs_f64().max(1e-9);
+        println!("{:<12} SHA-256 {:>8.0} MiB/s, CRC-32 {:>8.0} MiB/s{}{}", engine.name(), rate(sha256_time), rate(crc32_time),
+                 if engine.available() { "" } else { ", without the instructions it needs" },
+                 if engine.name() == current { " (used)" } else { "" });
+    }
+}
+
+/// The timeout of the watchdog the BootROM left running, None if it's off or its registers
+/// can't be read, e.g. before authentication
+fn watchdog_timeout(brom_dev: &mut BootRom, chip: &chips::Chip) -> Option<Duration> {
+    let words = brom_dev.read32(chip.watchdog, 2).ok()?;
+    chips::watchdog_timeout(words[0], words[1])
+}
+
+/// Tells how long the watchdog leaves for sending len bytes, warns if that's not enough, and
+/// returns whether there's time for --verify too. The watchdog runs since the reset, so the
+/// time left is at most the timeout less what passed since the handshake.
+fn watchdog_window(args: &Args, timeout: Duration, passed: Duration, len: usize, report: &mut Report) -> bool {
+    let left = timeout.saturating_sub(passed);
+    // 10 bits on the line for each byte, at the baud rate the BootROM is switched to
+    let sending = Duration::from_secs_f64(len as f64 * 10.0 / args.brom_load_baudrate as f64);
+    println!("The BootROM watchdog resets the device {:.1} s after it's started, at most {:.1} s are left.",
+             timeout.as_secs_f64(), left.as_secs_f64());
+    report.set("device.watchdog_timeout", format!("{:.3}", timeout.as_secs_f64()));
+    if sending >= left {
+        output::warning(&format!("Sending {} bytes at {} baud takes about {:.1} s, longer than the watchdog leaves. \
+                                  Give --disable-watchdog or a higher --brom-load-baudrate.",
+                                 len, args.brom_load_baudrate, sending.as_secs_f64()));
+        return args.verify;
+    }
+    // Reading back takes about as long as sending, the jump comes first.
+    if args.verify && sending * 2 >= left {
+        output::warning("Skipping --verify, reading the payload back would take longer than the watchdog leaves.");
+        return false;
+    }
+    args.verify
+}
+
+/// Loads and starts the payload, returning its load address. Returns None with --read-only,
+/// nothing is loaded then.
+fn load_bl2(args: &Args, session: &mut Session, policy: &Policy, report: &mut Report, journal: &Journal, next_len: Option<usize>) -> mtk_uartboot::Result<Option<LoadAddr>> {
+    let pause_control = session.pause_control();
+    let mut brom_dev = session.bootrom();
+
+    let raised = if args.rt { raise_priority(report) } else { None };
+    let reset = reset_into_download(args);
+    println!("Handshake...");
+    let handshake = match args.handshake_timeout {
+        Some(secs) => brom_dev.handshake_timeout(Duration::from_secs(secs)),
+        None => brom_dev.handshake(),
+    };
+    let handshake_done = Instant::now();
+    reset.finish_after(handshake)?;
+    drop(raised);
+    journal.mark("brom.handshake");
+    report.stamp("brom.handshake");
+    let hw_code = brom_dev.get_hw_code()?;
+    println!("hw code: {:#x}", hw_code);
+    let (hw_sub_code, hw_ver, sw_ver) = brom_dev.get_hw_dict()?;
+    println!("hw sub code: {:#x}", hw_sub_code);
+    println!("hw ver: {:#x}", hw_ver);
+    println!("sw ver: {:#x}", sw_ver);
+    report.set("device.hw_code", format!("{:#x}", hw_code));
+    report.set("device.hw_sub_code", format!("{:#x}", hw_sub_code));
+    report.set("device.hw_ver", format!("{:#x}", hw_ver));
+    report.set("device.sw_ver", format!("{:#x}", sw_ver));
+    let chip = chips::lookup(hw_code);
+    output::emit(StreamEvent::Device { hw_code: hex(hw_code), hw_sub_code: hex(hw_sub_code), hw_ver: hex(hw_ver),
+                                       sw_ver: hex(sw_ver), chip: chip.map(|c| c.name.to_owned()) });
+    match chip {
+        Some(chip) => {
+            println!("SoC: {}", chip.name);
+            report.set("device.chip", chip.name);
+        }
+        None => output::warning("unknown SoC, the load address has to be given with --load-addr."),
+    }
+    // A scanned unit ID is bound to the chip itself, where the BootROM tells its ID, and the
+    // policy may restrict the SoC IDs.
+    let mut soc_id = None;
+    if report.get("unit.id").is_some() || policy.checks_soc_id() {
+        match brom_dev.get_soc_id() {
+            Ok(id) => {
+                println!("SoC ID: {}", to_hex(&id));
+                report.set("device.soc_id", to_hex(&id));
+                soc_id = Some(to_hex(&id));
+            }
+            Err(e) if report.get("unit.id").is_some() =>
+                output::warning(&format!("the BootROM doesn't tell the SoC ID ({}), the unit ID is only recorded with the hw code.", e)),
+            Err(_) => {}
+        }
+    }
+
+    let target_config_word = brom_dev.get_target_config_word()?;
+    let target_config = TargetConfig::from_word(target_config_word);
+    report.set("device.target_config", format!("{:#x}", target_config_word));
+    #[cfg(feature = "pipeline")]
+    if let Some(differs) = fleet::compare(&fleet::Identity { hw_code, hw_sub_code, hw_ver, sw_ver, target_config: target_config_word }) {
+        report.set("fleet.differs", differs.join(","));
+        if !differs.is_empty() {
+            let fields: Vec<String> = differs.iter().map(|f| f.replace('_', " ")).collect();
+            fail(EXIT_POLICY, format!("The {} of this unit {} from most of the other ports. Nothing was sent to it.",
+                                      fields.join(", "), if fields.len() == 1 { "differs" } else { "differ" }));
+        }
+    }
+    output::emit(StreamEvent::TargetConfig { secure_boot: target_config.secure_boot,
+                                             serial_link_authorization: target_config.serial_link_authorization,
+                                             download_agent_authorization: target_config.download_agent_authorization });
+    if args.dry_run {
+        let chip = chip.unwrap_or_else(|| panic!("--dry-run doesn't know the memory map of hw code {:#x}.", hw_code));
+        dry_run(args, chip, next_len);
+    }
+    if args.read_only {
+        println!("secure boot: {}", target_config.secure_boot);
+        println!("serial link authorization: {}", target_config.serial_link_authorization);
+        println!("download agent authorization: {}", target_config.download_agent_authorization);
+        if let Some(timeout) = chip.and_then(|chip| watchdog_timeout(&mut brom_dev, chip)) {
+            println!("watchdog: resets the device {:.1} s after it's started", timeout.as_secs_f64());
+            report.set("device.watchdog_timeout", format!("{:.3}", timeout.as_secs_f64()));
+        }
+        return Ok(None);
+    }
+    if let Some(board) = args.rescue.filter(|b| b.hw_code != hw_code) {
+        let soc = chips::lookup(board.hw_code).map_or("?", |c| c.name);
+        fail(EXIT_FAILED, format!("This is hw code {:#x}, not the {} of a {}. Nothing was sent.", hw_code, soc, board.title));
+    }
+    capabilities::check("BootROM", &capabilities::bootrom(args, chip), report);
+    policy.check_hw_code(hw_code);
+    policy.check_soc_id(soc_id.as_deref());
+    if target_config.secure_boot {
+        fail(EXIT_SECURED, "Secure boot enabled.".to_owned());
+    }
+    // Without a payload, only the segments are loaded.
+    let load_addr = args.payload.as_ref().map(|_| match (args.load_addr, chip) {
+        (Some(addr), Some(chip)) if LoadAddr(addr) != chip.load_addr => {
+            output::warning(&format!("{} payloads are usually loaded to {:#x}.", chip.name, chip.load_addr));
+            LoadAddr(addr)
+        }
+        (Some(addr), _) => LoadAddr(addr),
+        (None, Some(chip)) => chip.load_addr,
+        (None, None) => panic!("--load-addr is needed for hw code {:#x}.", hw_code),
+    });
+    let aarch64 = args.aarch64 || (!args.aarch32 && chip.is_some_and(|c| c.aarch64));
+    let payload = args.payload.as_deref().zip(load_addr).map(|(path, addr)| {
+        let mut changes: Vec<String> = args.patch.iter().map(|patch| format!("patch {:?}", patch)).collect();
+        changes.extend(token_values(args).map(|vars| format!("tokens {:?}", vars)));
+        let payload = prepared(args, "payload", read_image(args, path, "payload"), &changes, |mut payload| {
+            for patch in &args.patch {
+                patch.apply(&mut payload).unwrap_or_else(|e| panic!("Can't patch the payload: {}", e));
+                println!("Patched {} bytes of the payload at {:#x}.", patch.bytes.len(), patch.offset);
+            }
+            substitute_vars(args, &mut payload, "payload", false);
+            payload
+        });
+        (path, addr, payload)
+    });
+    let segments: Vec<(&str, LoadAddr, Vec<u8>)> = args.load.iter()
+        .map(|(addr, path)| (path.as_str(), LoadAddr(*addr), read_image(args, path, &format!("segment {}", path))))
+        .collect();
+    let entry = entry_point_addr(args, load_addr, &segments);
+    if let Some(chip) = chip {
+        let payload = payload.as_ref().map(|(_, addr, data)| (*addr, data.len()));
+        layout::warn(chip, &placements(args, payload, &segments, next_len));
+    }
+    // Everything is checked before anything is written to the device.
+    let hashes: Vec<String> = payload.iter().chain(&segments).map(|(_, _, data)| to_hex(&images::sha256(data))).collect();
+    for sha256 in &hashes {
+        policy.check_payload(sha256);
+    }
+    authenticate(args, &mut brom_dev, hw_code, &target_config)?;
+    if args.disable_watchdog {
+        let chip = chip.unwrap_or_else(|| panic!("--disable-watchdog needs a known SoC, not hw code {:#x}.", hw_code));
+        brom_dev.write32(chip.watchdog, &[chips::WATCHDOG_DISABLE])?;
+        println!("Watchdog disabled.");
+    }
+    let watchdog = chip.filter(|_| !args.disable_watchdog).and_then(|chip| watchdog_timeout(&mut brom_dev, chip));
+    let mut verify = args.verify;
+    if let Some(timeout) = watchdog {
+        let len: usize = payload.iter().chain(&segments).map(|(_, _, data)| data.len()).sum();
+        verify = watchdog_window(args, timeout, handshake_done.elapsed(), len, report);
+    }
+    let brom_baudrate = match brom_dev.set_baudrate(Baud(args.brom_load_baudrate)) {
+        Ok(()) => args.brom_load_baudrate,
+        Err(e) if args.brom_load_baudrate != 115200 => {
+            println!("Falling back to 115200 baud: {}", e);
+            brom_dev.recover_baudrate(Baud(115200))?;
+            115200
+        }
+        Err(e) => return Err(e),
+    };
+    println!("Baud rate set to {}", brom_baudrate);
+    report.set("brom.baudrate", brom_baudrate);
+    if let Some(dir) = &args.save_sram {
+        let regions: Vec<(LoadAddr, usize)> = payload.iter().chain(&segments).map(|(_, addr, data)| (*addr, data.len())).collect();
+        save_sram(session, dir, args.save_sram_kib, chip, &regions, report)?;
+        brom_dev = session.bootrom();
+    }
+    let keys = Keyboard::listen(pause_control);
+    let mut hashes = hashes.iter();
+    if let Some((path, load_addr, payload)) = &payload {
+        let payload_sha256 = hashes.next().unwrap();
+        println!("sending payload to {:#x}...", load_addr);
+        let checksum = brom_dev.send_da(*load_addr, ByteLen(args.payload_sig_len), payload.as_slice())?;
+        println!("Checksum: {:#x}", checksum);
+        report.set("payload.file", path);
+        report.set("payload.load_addr", load_addr);
+        report.set("payload.len", payload.len());
+        report.set("payload.sha256", payload_sha256);
+        report.set("payload.checksum", format!("{:#06x}", checksum));
+        output::emit(StreamEvent::PayloadSent { load_addr: hex(load_addr.0), len: payload.len(),
+                                                sha256: payload_sha256.to_owned(), checksum: hex(checksum) });
+    }
+    for (i, ((path, addr, data), sha256)) in segments.iter().zip(hashes).enumerate() {
+        println!("sending {} to {:#x}...", path, addr);
+        let checksum = brom_dev.send_da(*addr, ByteLen(0), data)?;
+        println!("Checksum: {:#x}", checksum);
+        let key = format!("segment.{}", i);
+        report.set(&format!("{}.file", key), path);
+        report.set(&format!("{}.load_addr", key), addr);
+        report.set(&format!("{}.len", key), data.len());
+        report.set(&format!("{}.sha256", key), sha256);
+        report.set(&format!("{}.checksum", key), format!("{:#06x}", checksum));
+        output::emit(StreamEvent::SegmentSent { file: path.to_string(), load_addr: hex(addr.0), len: data.len(),
+                                                sha256: sha256.to_string(), checksum: hex(checksum) });
+    }
+    drop(keys);
+    if verify {
+        let loaded = payload.iter().map(|(_, addr, data)| ("payload", *addr, data))
+            .chain(segments.iter().map(|(path, addr, data)| (*path, *addr, data)));
+        for (what, addr, data) in loaded {
+            verify_loaded(session, what, addr, data)?;
+        }
+        report.set("payload.verified", true);
+        brom_dev = session.bootrom();
+    }
+    journal.mark("brom.payload_sent");
+    report.stamp("brom.payload_sent");
+
+    if brom_baudrate != 115200 {
+        println!("Setting baudrate back to 115200");
+        brom_dev.set_baudrate(Baud(115200))?;
+    }
+    if let Some((addr, words)) = &args.boot_args {
+        write_boot_args(&mut brom_dev, *addr, words, "boot", report)?;
+    }
+    if let Some(addr) = args.param_block {
+        let block = ParamBlock {
+            console_baudrate: args.console_baudrate,
+            next_addr: args.next_payload.as_ref().map_or(0, |(addr, _)| *addr),
+            flags: if args.next_aarch32 { 0 } else { params::FLAG_NEXT_AARCH64 },
+            hw_code: u32::from(hw_code),
+            extra: args.param_word.clone(),
+        };
+        let words = block.words();
+        brom_dev.write32(addr, &words)?;
+        println!("Parameter block of {} bytes written to {:#x}.", words.len() * 4, addr);
+        report.set("params.addr", format!("{:#x}", addr));
+        report.set("params.version", params::VERSION);
+        report.set("params.words", words.iter().map(|w| format!("{:#x}", w)).collect::<Vec<_>>().join(","));
+    }
+    pause(&args.pause_at, PausePoint::PayloadSent, args.pause_cmd.as_deref(), Some(entry.0));
+
+    if aarch64 {
+        println!("Jumping to {:#x} in aarch64...", entry);
+        brom_dev.jump_da64(entry)?;
+    } else {
+        println!("Jumping to {:#x} in aarch32...", entry);
+        brom_dev.jump_da(entry)?;
+    }
+    journal.mark("brom.jumped");
+    report.stamp("brom.jumped");
+    output::emit(StreamEvent::Jumped { addr: hex(entry.0), aarch64 });
+    Ok(Some(entry))
+}
+
+/// The address --entry jumps to. The payload, or else the first --load file, unless it says
+/// otherwise.
+fn entry_point_addr(args: &Args, load_addr: Option<LoadAddr>, segments: &[(&str, LoadAddr, Vec<u8>)]) -> LoadAddr {
+    match &args.entry {
+        Some(EntryPoint::Addr(addr)) => LoadAddr(*addr),
+        Some(EntryPoint::File(name)) if name == "payload" => load_addr.expect("--entry payload needs a payload."),
+        Some(EntryPoint::File(name)) => segments.iter()
+            .find(|(path, _, _)| path == name || Path::new(path).file_name().is_some_and(|f| f == name.as_str()))
+            .unwrap_or_else(|| panic!("--entry {} is neither payload nor a --load file.", name)).1,
+        None => load_addr.unwrap_or_else(|| segments[0].1),
+    }
+}
+
+/// Where the payload, the --load files, the mailboxes and the --next-payload of next_len
+/// bytes end up in memory
+fn placements(args: &Args, payload: Option<(LoadAddr, usize)>, segments: &[(&str, LoadAddr, Vec<u8>)], next_len: Option<usize>) -> Vec<Placement> {
+    let mut placed = Vec::new();
+    let mut place = |what: &str, addr: u32, len: usize, late: bool| placed.push(Placement { what: what.to_owned(), addr, len: len as u32, late });
+    if let Some((addr, len)) = payload {
+        place("payload", addr.0, len, false);
+    }
+    for (path, addr, data) in segments {
+        place(path, addr.0, data.len(), false);
+    }
+    if let Some((addr, words)) = &args.boot_args {
+        place("--boot-args", *addr, words.len() * 4, false);
+    }
+    if let Some(addr) = args.param_block {
+        place("--param-block", addr, params::len(args.param_word.len()) as usize, false);
+    }
+    if let (Some((addr, path)), Some(len)) = (&args.next_payload, next_len) {
+        place(path, *addr, len, true);
+    }
+    if let Some((addr, words)) = &args.next_boot_args {
+        place("--next-boot-args", *addr, words.len() * 4, true);
+    }
+    placed
+}
+
+/// Prints where everything would be loaded on chip and exits, with EXIT_FAILED if anything
+/// collides. Nothing is sent.
+fn dry_run(args: &Args, chip: &chips::Chip, next_len: Option<usize>) -> ! {
+    let load_addr = args.payload.as_ref().map(|_| args.load_addr.map_or(chip.load_addr, LoadAddr));
+    let payload = args.payload.as_deref().zip(load_addr).map(|(path, addr)| (addr, read_image(args, path, "payload").len()));
+    let segments: Vec<(&str, LoadAddr, Vec<u8>)> = args.load.iter()
+        .map(|(addr, path)| (path.as_str(), LoadAddr(*addr), read_image(args, path, &format!("segment {}", path))))
+        .collect();
+    if payload.is_none() && segments.is_empty() {
+        panic!("--dry-run needs a payload or --load files.");
+    }
+    let errors = layout::print(chip, &placements(args, payload, &segments, next_len));
+    let entry = entry_point_addr(args, load_addr, &segments);
+    let aarch64 = args.aarch64 || (!args.aarch32 && chip.aarch64);
+    println!("Entry: {:#x} in {}.", entry, if aarch64 { "aarch64" } else { "aarch32" });
+    if errors {
+        fail(EXIT_FAILED, "Collisions found, nothing was sent.".to_owned());
+    }
+    println!("Nothing collides, nothing was sent.");
+    std::process::exit(0);
+}
+
+/// The SoC --soc names, by name or hw code
+fn soc(name: &str) -> &'static chips::Chip {
+    let hw_code = maybe_hex::<u16>(name).ok();
+    chips::all().iter()
+        .find(|chip| Some(chip.hw_code) == hw_code || chip.name.split('/').any(|n| n.eq_ignore_ascii_case(name)))
+        .unwrap_or_else(|| panic!("Unknown SoC {}, known are {}.", name,
+                                  chips::all().iter().map(|c| c.name).collect::<Vec<_>>().join(", ")))
+}
+
+/// Writes the boot arguments of a payload to its mailbox before it's jumped to
+fn write_boot_args(brom_dev: &mut BootRom, addr: u32, words: &[u32], key: &str, report: &mut Report) -> mtk_uartboot::Result<()> {
+    brom_dev.write32(addr, words)?;
+    println!("{} boot argument words written to {:#x}.", words.len(), addr);
+    let words: Vec<String> = words.iter().map(|w| format!("{:#x}", w)).collect();
+    report.set(&format!("{}_args.addr", key), format!("{:#x}", addr));
+    report.set(&format!("{}_args.words", key), words.join(","));
+    Ok(())
+}
+
+/// Loads and starts the --next-payload, read and checked with its SHA-256 beforehand, through
+/// the first payload, which speaks the download protocol of the BootROM once it's running.
+/// Waits for the --next-marker of the first payload first.
+fn load_next_payload(args: &Args, session: &mut Session, console: &mut Option<ConsolePort>, view: &mut ConsoleView, next: (u32, &str, Vec<u8>, String), report: &mut Report) -> mtk_uartboot::Result<bool> {
+    let (addr, path, data, sha256) = next;
+    if let Some(marker) = &args.next_marker {
+        println!("Waiting for \"{}\" from the first payload...", marker);
+        if !wait_for_line(console_port(console, session), marker, view) {
+            return Ok(false);
+        }
+    }
+    let mut brom_dev = session.bootrom();
+    println!("Handshake with the first payload...");
+    brom_dev.handshake()?;
+    println!("sending {} to {:#x}...", path, addr);
+    let checksum = brom_dev.send_da(LoadAddr(addr), ByteLen(0), &data)?;
+    println!("Checksum: {:#x}", checksum);
+    report.set("next_payload.file", path);
+    report.set("next_payload.load_addr", LoadAddr(addr));
+    report.set("next_payload.len", data.len());
+    report.set("next_payload.sha256", &sha256);
+    report.set("next_payload.checksum", format!("{:#06x}", checksum));
+    if let Some((mailbox, words)) = &args.next_boot_args {
+        write_boot_args(&mut brom_dev, *mailbox, words, "next_boot", report)?;
+    }
+    if args.next_aarch32 {
+        println!("Jumping to {:#x} in aarch32...", addr);
+        brom_dev.jump_da(LoadAddr(addr))?;
+    } else {
+        println!("Jumping to {:#x} in aarch64...", addr);
+        brom_dev.jump_da64(LoadAddr(addr))?;
+    }
+    report.stamp("next_payload.jumped");
+    output::emit(StreamEvent::Jumped { addr: hex(addr), aarch64: !args.next_aarch32 });
+    Ok(true)
+}
+
+/// Reads data back from where it was loaded and fails the run if it differs from what was sent.
+/// Saves the first kib KiB of each region of memory about to be overwritten to dir, as far
+/// as the BootROM lets them be read. Regions which aren't SRAM on a known chip are left
+/// alone, DRAM isn't set up yet.
+fn save_sram(session: &mut Session, dir: &str, kib: u32, chip: Option<&chips::Chip>, regions: &[(LoadAddr, usize)], report: &mut Report) -> mtk_uartboot::Result<()> {
+    std::fs::create_dir_all(dir).unwrap_or_else(|e| panic!("Can't create {}: {}", dir, e));
+    for &(addr, len) in regions {
+        if let Some(region) = chip.and_then(|c| c.region(addr.0)).filter(|r| r.kind != chips::RegionKind::Sram) {
+            println!("Not saving {:#x}, it's in the {}, not SRAM.", addr.0, region.name);
+            continue;
+        }
+        if !addr.0.is_multiple_of(2) {
+            output::warning(&format!("{:#x} isn't saved, the BootROM only reads 16 or 32-bit words.", addr.0));
+            continue;
+        }
+        let len = (len as u32).min(kib * 1024).next_multiple_of(4);
+        let data = match dump::run(session, addr.0, len, "Saved") {
+            Ok(data) => data,
+            Err(mtk_uartboot::Error::Status { status, .. }) => {
+                output::warning(&format!("The BootROM refused to read {:#x} (status {:#x}), it isn't saved.", addr.0, status));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let path = Path::new(dir).join(format!("sram-{:#x}.bin", addr.0));
+        std::fs::write(&path, &data).unwrap_or_else(|e| panic!("Can't write {}: {}", path.display(), e));
+        println!("{:#x} bytes from {:#x} saved to {}.", len, addr.0, path.display());
+        let key = format!("sram.{:#x}", addr.0);
+        report.set(&format!("{}.file", key), path.display());
+        report.set(&format!("{}.len", key), len);
+        report.set(&format!("{}.sha256", key), to_hex(&images::sha256(&data)));
+    }
+    Ok(())
+}
+
+fn verify_loaded(session: &mut Session, what: &str, addr: LoadAddr, data: &[u8]) -> mtk_uartboot::Result<()> {
+    // 32-bit reads are quicker, the bytes past the end are ignored.
+    let len = data.len().next_multiple_of(4) as u32;
+    let read = dump::run(session, addr.0, len, "Read back")?;
+    match read.iter().zip(data).position(|(a, b)| a != b) {
+        None => println!("{} verified.", what),
+        Some(at) => fail(EXIT_DEVICE_ERROR, format!("{} differs from what was sent at {:#x}, not jumping to it.",
+                                                    what, addr.0 + at as u32)),
+    }
+    Ok(())
+}
+
+fn wait_bl2_handshake(port: &mut dyn Transport, view: &mut ConsoleView) -> bool {
+    port.set_timeout(Duration::from_secs(2)).unwrap();
+    println!("Waiting for BL2. Message below:");
+    wait_for_line(port, "Starting UART download handshake", view)
+}
+
+/// Reads the FIP, checks it and makes the changes asked for, so a broken one is refused
+/// before anything is sent.
+fn prepare_fip(args: &Args, fip: &str) -> Vec<u8> {
+    let replacements: Vec<(&str, &str, Vec<u8>)> = args.replace.iter()
+        .map(|(image, file)| (image.as_str(), file.as_str(), read_image(args, file, &format!("{} image", image))))
+        .collect();
+    let overlays: Vec<Vec<u8>> = args.dtbo.iter()
+        .map(|path| std::fs::read(path).expect("failed to open device tree overlay."))
+        .collect();
+    let mut changes: Vec<String> = replacements.iter()
+        .map(|(image, _, data)| format!("replace {} {}", image, to_hex(&images::sha256(data))))
+        .collect();
+    changes.extend(args.fip_patch.iter().map(|patch| format!("patch {:?}", patch)));
+    changes.extend(args.bl31_log_level.map(|level| format!("bl31 log level {:?}", level)));
+    changes.extend(token_values(args).map(|vars| format!("tokens {:?}", vars)));
+    changes.extend(overlays.iter().map(|overlay| format!("dtbo {} {:?}", to_hex(&images::sha256(overlay)), args.dtb_entry)));
+
+    prepared(args, "FIP", read_image(args, fip, "FIP"), &changes, |mut payload| {
+        for (image, file, data) in replacements {
+            let toc = Fip::parse(&payload).unwrap_or_else(|e| panic!("Can't replace {} in the FIP: {}", image, e));
+            payload = toc.replace(&payload, image, data)
+                .unwrap_or_else(|e| panic!("Can't replace {} in the FIP: {}", image, e));
+            println!("Replaced {} with {}.", image, file);
+        }
+        let toc = Fip::parse(&payload).and_then(|toc| toc.check_bootable().map(|()| toc))
+            .unwrap_or_else(|e| panic!("Invalid FIP {}: {}", fip, e));
+        let images: Vec<String> = toc.entries.iter().map(|e| format!("{} {} bytes", e.label(), e.size)).collect();
+        println!("FIP: {}.", images.join(", "));
+        for patch in &args.fip_patch {
+            patch.apply(&mut payload).unwrap_or_else(|e| panic!("Can't patch the FIP: {}", e));
+            println!("Patched {} bytes of the FIP at {}{:#x}.", patch.bytes.len(),
+                     patch.image.as_ref().map_or(String::new(), |i| format!("{}+", i)), patch.offset);
+        }
+        if let Some(level) = args.bl31_log_level {
+            patch::set_bl31_log_level(&mut payload, level)
+                .unwrap_or_else(|e| panic!("Can't set the BL31 log level: {}", e));
+            println!("BL31 log level set to {:?}.", level);
+        }
+        substitute_vars(args, &mut payload, "FIP", true);
+        if !overlays.is_empty() {
+            let (patched, entry) = patch::apply_overlays(&payload, &overlays, args.dtb_entry.as_deref())
+                .unwrap_or_else(|e| panic!("Can't apply device tree overlays: {}", e));
+            payload = patched;
+            println!("Applied {} device tree overlays to {}.", overlays.len(), entry);
+        }
+        payload
+    })
+}
+
+/// Failed FIP transfers at one baud rate before trying a lower one
+const FIP_ATTEMPTS_PER_BAUDRATE: u32 = 2;
+
+/// Baud rates to fall back to when FIP transfers keep failing, fastest first
+const FIP_BAUDRATES: &[u32] = &[3000000, 2000000, 1500000, 921600, 460800, 230400, 115200];
+
+/// Whether an error is the link garbling or losing data, which a retry may get through
+fn is_link_error(e: &mtk_uartboot::Error) -> bool {
+    match e {
+        mtk_uartboot::Error::Echo { .. } => true,
+        mtk_uartboot::Error::Io(e) => e.kind() == std::io::ErrorKind::TimedOut,
+        _ => false,
+    }
+}
+
+fn load_fip(session: &mut Session, args: &Args, payload: &[u8], load_addr: Option<LoadAddr>, report: &mut Report, journal: &Journal) -> mtk_uartboot::Result<()> {
+    if !args.replace.is_empty() {
+        let replaced: Vec<String> = args.replace.iter().map(|(image, file)| format!("{}={}", image, file)).collect();
+        report.set("fip.replace", replaced.join(","));
+    }
+    if !args.dtbo.is_empty() {
+        report.set("fip.dtbo", args.dtbo.join(","));
+    }
+    let fip_sha256 = to_hex(&images::sha256(payload));
+
+    let pause_control = session.pause_control();
+    let mut bl2_dev = session.bl2();
+    bl2_dev.handshake()?;
+    journal.mark("bl2.handshake");
+    report.stamp("bl2.handshake");
+    portlock::set_stage("FIP");
+    let version = bl2_dev.version()?;
+    println!("BL2 UART DL version: {:#x}", version);
+    output::emit(StreamEvent::Bl2Version { version: hex(version) });
+    capabilities::check("BL2", &capabilities::bl2(args, version), report);
+    if args.deterministic {
+        bl2_dev.set_packet_sizing(PacketSizing::Fixed(args.packet_len));
+    }
+    let mut baudrate = args.bl2_load_baudrate;
+    let mut failures = 0;
+    let keys = Keyboard::listen(pause_control);
+    let transfer = loop {
+        bl2_dev.set_baudrate(Baud(baudrate))?;
+        bl2_dev.handshake()?;
+        println!("Baudrate set to: {}", baudrate);
+        let e = match bl2_dev.send_fip(payload) {
+            Ok(transfer) => break transfer,
+            Err(e) if is_link_error(&e) => e,
+            Err(e) => return Err(e),
+        };
+        // A slow boot beats a failed one on a marginal link, the transfer starts over at a
+        // lower baud rate once it failed twice.
+        failures += 1;
+        output::emit(StreamEvent::FipFailed { baudrate, error: e.to_string() });
+        if failures < FIP_ATTEMPTS_PER_BAUDRATE {
+            println!("FIP transfer failed: {}. Retrying.", e);
+        } else {
+            let Some(&lower) = FIP_BAUDRATES.iter().find(|&&b| b < baudrate) else {
+                return Err(e);
+            };
+            println!("FIP transfer failed {} times at {} baud: {}. Retrying at {} baud.", failures, baudrate, e, lower);
+            baudrate = lower;
+            failures = 0;
+        }
+        // BL2 goes back to waiting for the handshake once it gives up on the transfer.
+        bl2_dev.handshake()?;
+    };
+    drop(keys);
+    println!("FIP sent.");
+    journal.mark("bl2.fip_sent");
+    report.stamp("bl2.fip_sent");
+    report.set("fip.file", args.fip.as_deref().unwrap_or_default());
+    report.set("fip.len", payload.len());
+    report.set("fip.sha256", &fip_sha256);
+    if args.deterministic {
+        report.set("fip.packet_len", args.packet_len);
+    }
+    report.set("fip.baudrate", baudrate);
+    report.set("fip.packets", transfer.packets);
+    report.set("fip.stream_sha256", to_hex(&transfer.stream_digest));
+    output::emit(StreamEvent::FipSent { len: payload.len(), sha256: fip_sha256.clone(), packets: transfer.packets });
+    pause(&args.pause_at, PausePoint::FipSent, args.pause_cmd.as_deref(), load_addr.map(|a| a.0));
+
+    bl2_dev.go()?;
+    journal.mark("bl2.go");
+    report.stamp("bl2.go");
+    Ok(())
+}
+
+/// Writes the image of the flash subcommand to the storage of the device through BL2.
+/// Partitions the device can't boot without, written through their alternate if there's one
+const CRITICAL_PARTITIONS: &[&str] = &["preloader", "bl2", "boot0", "boot1", "fip", "bootloader", "u-boot"];
+
+/// The partition to write the image to in place of the target, to switch to once it's
+/// written. None, after a warning for the critical partitions and --two-phase, if BL2 or the
+/// layout can't switch.
+fn flash_alternate(bl2_dev: &mut Bl2<'_>, args: &Args, version: u8, target: FlashTarget) -> mtk_uartboot::Result<Option<String>> {
+    let critical = matches!(target, FlashTarget::Partition(name) if CRITICAL_PARTITIONS.contains(&name.to_lowercase().as_str()));
+    if !critical && !args.two_phase {
+        return Ok(None);
+    }
+    let in_place = "it's written in place, a failed transfer can leave the device unbootable";
+    let FlashTarget::Partition(name) = target else {
+        output::warning(&format!("{} has no alternate to switch to, {}.", target, in_place));
+        return Ok(None);
+    };
+    if version < FLASH_SWITCH_VERSION {
+        output::warning(&format!("BL2 speaks version {:#x} of the UART download protocol, switching to the alternate of {} needs {:#x} or later, {}.",
+                                 version, name, FLASH_SWITCH_VERSION, in_place));
+        return Ok(None);
+    }
+    match bl2_dev.flash_alternate(name) {
+        Ok(Some(alternate)) => Ok(Some(alternate)),
+        Ok(None) => {
+            output::warning(&format!("The layout of the storage has no alternate of {}, {}.", name, in_place));
+            Ok(None)
+        }
+        Err(mtk_uartboot::Error::Ack { cmd: "flash_alternate", ack }) =>
+            fail(EXIT_DEVICE_ERROR, format!("BL2 can't find the alternate of {}: {}. {}", name, ack, ack.explanation())),
+        Err(e) => Err(e),
+    }
+}
+
+/// Fails unless len bytes fit the target, by the partition table BL2 tells, so nothing is
+/// sent for an image BL2 would refuse after the transfer
+fn check_room(bl2_dev: &mut Bl2<'_>, target: FlashTarget, len: usize) -> mtk_uartboot::Result<()> {
+    let info = bl2_dev.storage_info()?;
+    let Some(room) = info.room(target) else {
+        let names: Vec<&str> = info.partitions.iter().map(|p| p.name.as_str()).collect();
+        fail(EXIT_DEVICE_ERROR, format!("The storage has no {}, its partitions are: {}.", target, names.join(", ")));
+    };
+    if len as u64 > room.end - room.start {
+        fail(EXIT_DEVICE_ERROR, format!("The image of {} bytes doesn't fit {}, which has room for {} bytes.",
+                                        len, target, room.end - room.start));
+    }
+    Ok(())
+}
+
+/// Asks whether the changes may be made to the storage of the device in the report, unless --yes
+/// is given, and fails the run if they may not
+fn confirm_flash(args: &Args, report: &Report, changes: &[confirm::Change]) {
+    if args.yes {
+        return;
+    }
+    // Unknown if the BootROM was skipped, with BL2 already running.
+    let field = |key| report.get(key)
+        .and_then(|value: &str| u16::from_str_radix(value.trim_start_matches("0x"), 16).ok())
+        .unwrap_or_default();
+    let device = confirm::DeviceIdentity {
+        port: report.get("device.port").unwrap_or_default().to_owned(),
+        hw_code: field("device.hw_code"),
+        hw_sub_code: field("device.hw_sub_code"),
+        hw_ver: field("device.hw_ver"),
+        sw_ver: field("device.sw_ver"),
+    };
+    let confirmed = confirm::confirm(&device, changes, &mut std::io::stdin().lock(), &mut std::io::stdout())
+        .expect("failed to ask for confirmation.");
+    if !confirmed {
+        fail(EXIT_FAILED, "Writing to the storage wasn't confirmed, nothing was written. --yes writes without asking.".to_owned());
+    }
+}
+
+fn flash_image(session: &mut Session, args: &Args, image: &[u8], report: &mut Report) -> mtk_uartboot::Result<()> {
+    let requested = match (&args.partition, args.offset) {
+        (Some(name), _) => FlashTarget::Partition(name),
+        (None, offset) => FlashTarget::Offset(offset.unwrap()),
+    };
+    let pause_control = session.pause_control();
+    let mut bl2_dev = session.bl2();
+    bl2_dev.handshake()?;
+    let version = bl2_dev.version()?;
+    println!("BL2 UART DL version: {:#x}", version);
+    output::emit(StreamEvent::Bl2Version { version: hex(version) });
+    capabilities::check("BL2", &capabilities::bl2(args, version), report);
+    bl2_dev.set_baudrate(Baud(args.bl2_load_baudrate))?;
+    bl2_dev.handshake()?;
+    println!("Baudrate set to: {}", args.bl2_load_baudrate);
+    let info = if version >= FLASH_INFO_VERSION {
+        let info = bl2_dev.storage_info()?.clone();
+        println!("Storage: {} of {} bytes, {} partitions.", storage_kind_str(info.kind), info.capacity, info.partitions.len());
+        report.set("flash.storage", storage_kind_str(info.kind));
+        report.set("flash.capacity", info.capacity);
+        check_room(&mut bl2_dev, requested, image.len())?;
+        Some(info)
+    } else {
+        None
+    };
+    let alternate = flash_alternate(&mut bl2_dev, args, version, requested)?;
+    let target = match &alternate {
+        Some(alternate) => {
+            println!("Writing to {}, the alternate of {}, then switching to it.", alternate, requested);
+            FlashTarget::Partition(alternate)
+        }
+        None => requested,
+    };
+    if alternate.is_some() && version >= FLASH_INFO_VERSION {
+        check_room(&mut bl2_dev, target, image.len())?;
+    }
+    // Offsets in the storage where the partition table tells, else as given.
+    let offset = match (info.as_ref().and_then(|info| info.room(target)), target) {
+        (Some(room), _) => room.start,
+        (None, FlashTarget::Offset(offset)) => offset,
+        (None, _) => 0,
+    };
+    let change = confirm::Change {
+        action: if args.changed_only { "update" } else { "write" }.to_owned(),
+        target: target.to_string(),
+        offset,
+        len: image.len() as u64,
+    };
+    confirm_flash(args, report, &[change]);
+    let refused = |ack: Ack| -> ! {
+        fail(EXIT_DEVICE_ERROR, format!("BL2 can't write to {}: {}. {}", target, ack, ack.explanation()))
+    };
+    let keys = Keyboard::listen(pause_control);
+    let (crc32, packets) = if args.changed_only {
+        println!("Comparing {} bytes with {} in blocks of {:#x} bytes...", image.len(), target, args.block_len);
+        let diff = match bl2_dev.flash_changed(target, image, args.block_len) {
+            Ok(diff) => diff,
+            Err(mtk_uartboot::Error::Ack { cmd: "flash" | "flash_crc", ack }) => refused(ack),
+            Err(e) => return Err(e),
+        };
+        let changed: usize = diff.written.iter().map(|r| r.len()).sum();
+        if diff.written.is_empty() {
+            println!("The storage already holds the image, nothing written.");
+        } else {
+            println!("Wrote the {} bytes which changed, in {} ranges.", changed, diff.written.len());
+        }
+        println!("Image read back, CRC-32 {:#010x}.", diff.crc32);
+        report.set("flash.blocks", diff.blocks);
+        report.set("flash.changed_ranges", diff.written.len());
+        report.set("flash.changed_bytes", changed);
+        (diff.crc32, None)
+    } else {
+        println!("Writing {} bytes to {}...", image.len(), target);
+        let written = match bl2_dev.flash(target, image) {
+            Ok(written) => written,
+            Err(mtk_uartboot::Error::Ack { cmd: "flash", ack }) => refused(ack),
+            Err(e) => return Err(e),
+        };
+        println!("Image written and read back, CRC-32 {:#010x}.", written.crc32);
+        (written.crc32, Some(written.transfer.packets))
+    };
+    drop(keys);
+    if let Some(alternate) = &alternate {
+        match bl2_dev.flash_switch(alternate) {
+            Ok(()) => {}
+            Err(mtk_uartboot::Error::Ack { cmd: "flash_switch", ack }) =>
+                fail(EXIT_DEVICE_ERROR, format!("BL2 can't switch to {}: {}. {} still holds the previous image.",
+                                                alternate, ack, requested)),
+            Err(e) => return Err(e),
+        }
+        println!("The device boots from {} now, {} keeps the previous image.", alternate, requested);
+        output::emit(StreamEvent::FlashSwitched { from: requested.to_string(), to: alternate.to_string() });
+    }
+    let sha256 = to_hex(&images::sha256(image));
+    report.set("flash.image", args.image.as_deref().unwrap_or_default());
+    report.set("flash.target", requested);
+    if let Some(alternate) = &alternate {
+        report.set("flash.alternate", alternate);
+    }
+    report.set("flash.len", image.len());
+    report.set("flash.sha256", &sha256);
+    report.set("flash.crc32", format!("{:#010x}", crc32));
+    if let Some(packets) = packets {
+        report.set("flash.packets", packets);
+    }
+    output::emit(StreamEvent::FlashWritten { target: target.to_string(), len: image.len(), sha256: sha256.clone(), crc32: hex(crc32) });
+    Ok(())
+}
+
+#[cfg(feature = "ymodem")]
+fn send_ymodem(port: &mut dyn Transport, args: &Args, file: &str, view: &mut ConsoleView) -> bool {
+    let mut data = read_image(args, file, "YMODEM file");
+    substitute_vars(args, &mut data, "YMODEM file", false);
+    let name = Path::new(file).file_name().unwrap().to_string_lossy();
+    port.write_all(format!("{}\n", args.ymodem_cmd).as_bytes())
+        .expect("failed to write to port.");
+    println!("Sending {} over YMODEM...", name);
+    if let Err(e) = ymodem::send(port, &name, &data) {
+        println!("YMODEM transfer failed: {}", e);
+        return false;
+    }
+    println!("YMODEM transfer done.");
+
+    wait_for_line(port, &args.prompt, view)
+}
+
+#[cfg(feature = "tftp")]
+fn tftp_boot(port: &mut dyn Transport, args: &Args, file: &str, view: &mut ConsoleView) -> bool {
+    let root = args.tftp_root.as_deref().unwrap();
+    tftp::spawn(&args.tftp_bind, Path::new(root))
+        .expect("failed to start TFTP server.");
+    let host_ip = args.tftp_host_ip.clone()
+        .or_else(tftp::guess_host_ip)
+        .expect("failed to find the host IP address. Use --tftp-host-ip.");
+
+    let cmd = args.tftp_cmd
+        .replace("{host_ip}", &host_ip)
+        .replace("{file}", file);
+    println!("Running: {}", cmd);
+    port.write_all(format!("{}\n", cmd).as_bytes())
+        .expect("failed to write to port.");
+
+    // tftpboot may stay silent for a while when the link is coming up.
+    port.set_timeout(Duration::from_secs(30)).unwrap();
+    let found = wait_for_line(port, &args.prompt, view);
+    port.set_timeout(Duration::from_secs(2)).unwrap();
+    found
+}
+
+#[cfg(any(feature = "tftp", feature = "ymodem"))]
+fn needs_prompt(args: &Args) -> bool {
+    #[cfg(feature = "tftp")]
+    if args.tftp_file.is_some() {
+        return true;
+    }
+    #[cfg(feature = "ymodem")]
+    if args.ymodem.is_some() {
+        return true;
+    }
+    false
+}
+
+#[cfg(any(feature = "tftp", feature = "ymodem"))]
+fn needs_console(args: &Args) -> bool {
+    #[cfg(feature = "ymodem")]
+    if args.serve_dir.is_some() {
+        return true;
+    }
+    needs_prompt(args)
+}
+
+/// Finds a port given by its name in /dev/serial/by-id, which stays the same for a
+/// USB serial adapter no matter in which order adapters show up.
+fn resolve_serial(port: &str) -> String {
+    let by_id = Path::new("/dev/serial/by-id").join(port);
+    if !Path::new(port).exists() && !port.contains('/') && by_id.exists() {
+        return by_id.to_string_lossy().into_owned();
+    }
+    port.to_owned()
+}
+
+/// Waits until the serial port shows up, any port if none is given
+fn wait_for_port(port: Option<&str>) {
+    // Any port may turn out to be the one of the device.
+    let port = port.filter(|&p| p != AUTO_SERIAL);
+    let present = || match port {
+        Some(p) if p.contains("://") => true,
+        Some(p) => Path::new(&resolve_serial(p)).exists(),
+        None => serialport::available_ports().is_ok_and(|ports| !ports.is_empty()),
+    };
+    if present() {
+        return;
+    }
+    println!("Waiting for {} to appear...", port.unwrap_or("a serial port"));
+    while !present() {
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    // udev only sets up the permissions of the device node after it appeared.
+    std::thread::sleep(Duration::from_millis(500));
+}
+
+fn open_serial(port: Option<&str>) -> (String, Box<dyn Transport>) {
+    let (port, transport, _) = open_serial_observed(port);
+    (port, transport)
+}
+
+/// --serial which looks for the port of a device in BootROM download mode
+const AUTO_SERIAL: &str = "auto";
+
+/// Goes through the serial ports until the BootROM answers the first byte of the handshake
+/// on one, while the device is powered on or reset. Only that byte is sent, the handshake
+/// starts over on the port found.
+fn find_bootrom_port() -> String {
+    println!("Looking for the BootROM on all serial ports, power on or reset the device...");
+    loop {
+        let ports = serialport::available_ports().unwrap_or_default();
+        if ports.is_empty() {
+            fail(EXIT_NO_PORT, "No serial ports found.".to_owned());
+        }
+        for port in ports {
+            if answers_handshake(&port.port_name) {
+                println!("BootROM found on {}.", port.port_name);
+                return port.port_name;
+            }
+        }
+    }
+}
+
+fn answers_handshake(port: &str) -> bool {
+    // Ports in use by another run are left alone.
+    if portlock::owner(port).is_some() {
+        return false;
+    }
+    // Ports in use by something else, or which vanished meanwhile, just aren't the one.
+    let Ok(mut serial) = serialport::new(port, 115200).timeout(Duration::from_millis(20)).open() else {
+        return false;
+    };
+    serial.clear(serialport::ClearBuffer::Input).ok();
+    let mut answer = 0;
+    serial.write_all(&HANDSHAKE[..1]).is_ok()
+        && serial.read_exact(std::slice::from_mut(&mut answer)).is_ok()
+        && answer == !HANDSHAKE[0]
+}
+
+/// Prints the serial ports with what's known about the device behind them
+fn list_ports() {
+    let ports = serialport::available_ports().unwrap_or_else(|e| panic!("Can't list the serial ports: {}", e));
+    if ports.is_empty() {
+        println!("No serial ports found.");
+    }
+    for port in ports {
+        let description = match &port.port_type {
+            serialport::SerialPortType::UsbPort(usb) => {
+                let mut text = format!("USB {:04x}:{:04x}", usb.vid, usb.pid);
+                for field in [&usb.manufacturer, &usb.product].into_iter().flatten() {
+                    text += &format!(" {}", field);
+                }
+                if let Some(serial) = &usb.serial_number {
+                    text += &format!(" (serial number {})", serial);
+                }
+                if let Some(profile) = probe::detect(&port.port_name) {
+                    text += &format!(", {}", profile.name);
+                }
+                if let Some(bridge) = probe::bridge(&port.port_name) {
+                    text += &format!(", {} up to {} baud", bridge.name, bridge.max_baudrate);
+                }
+                text
+            }
+            serialport::SerialPortType::PciPort => "PCI".to_owned(),
+            serialport::SerialPortType::BluetoothPort => "Bluetooth".to_owned(),
+            serialport::SerialPortType::Unknown => "unknown".to_owned(),
+        };
+        let busy = portlock::owner(&port.port_name).map(|owner| format!(", in use by {}", owner)).unwrap_or_default();
+        println!("{:<24} {}{}", port.port_name, description, busy);
+    }
+}
+
+/// An event observer of the port itself, a WebSocket bridge showing how the session goes
+type PortObserver = Box<dyn FnMut(&Event) + Send>;
+
+/// A port opened: its name, the transport and the observer of a bridge
+type OpenedPort = (String, Box<dyn Transport>, Option<PortObserver>);
+
+fn open_serial_observed(port: Option<&str>) -> OpenedPort {
+    try_open_serial_observed(port).unwrap_or_else(|failure| fail(failure.exit_code, failure.msg))
+}
+
+/// Opens the serial port, or tells why it can't, for runs on several ports
+#[cfg(feature = "pipeline")]
+fn try_open_serial(port: Option<&str>) -> Result<(String, Box<dyn Transport>), Failure> {
+    let (port, transport, _) = try_open_serial_observed(port)?;
+    Ok((port, transport))
+}
+
+fn try_open_serial_observed(port: Option<&str>) -> Result<OpenedPort, Failure> {
+    let port = choose_serial(port);
+    println!("Using serial port: {}", port);
+    let lock = PortLock::acquire(&port)
+        .map_err(|owner| Failure { exit_code: EXIT_NO_PORT, msg: format!("Port {} busy by {}. --steal takes it over.", port, owner) })?;
+    let (transport, observer) = try_connect(&port).map_err(|msg| Failure { exit_code: EXIT_NO_PORT, msg })?;
+    Ok((port, portlock::wrap(lock, transport), observer))
+}
+
+/// The port --serial names, the one found in download mode for auto and the first one
+/// without it
+fn choose_serial(port: Option<&str>) -> String {
+    match port {
+        Some(AUTO_SERIAL) => find_bootrom_port(),
+        Some(p) => resolve_serial(p),
+        None => {
+            let ports = serialport::available_ports().unwrap_or_default();
+            let port = ports.into_iter().next().unwrap_or_else(|| fail(EXIT_NO_PORT, "No serial ports found.".to_owned()));
+            port.port_name
+        },
+    }
+}
+
+/// Opens the serial port or the bridge to it named port, or tells why it can't
+fn try_connect(port: &str) -> Result<(Box<dyn Transport>, Option<PortObserver>), String> {
+    #[cfg(all(feature = "rfcomm", target_os = "linux"))]
+    if let Some(address) = port.strip_prefix("rfcomm://") {
+        let bridge = rfcomm::Rfcomm::connect(address).map_err(|e| format!("Failed to connect to {}: {}", port, e))?;
+        return Ok((trace::wrap(port, Box::new(bridge)), None));
+    }
+    if let Some(address) = port.strip_prefix("tcp://") {
+        let bridge = tcp::Tcp::connect(address).map_err(|e| format!("Failed to connect to {}: {}", port, e))?;
+        return Ok((trace::wrap(port, Box::new(bridge)), None));
+    }
+    #[cfg(feature = "websocket")]
+    if port.starts_with("ws://") {
+        let bridge = websocket::WebSocket::connect(port).map_err(|e| format!("Failed to connect to {}: {}", port, e))?;
+        let observer = bridge.observer().map_err(|e| format!("Failed to connect to {}: {}", port, e))?;
+        return Ok((trace::wrap(port, Box::new(bridge)), Some(Box::new(observer))));
+    }
+    let serial = serialport::new(port, 115200)
+        .timeout(Duration::from_secs(2))
+        .open().map_err(|e| format!("Failed to open port: {}", e))?;
+    match probe::lower_latency(port) {
+        Ok(Some(previous)) => println!("USB latency timer of the FTDI bridge lowered from {} ms to 1 ms.", previous),
+        Ok(None) => {}
+        Err(e) => println!("Can't lower the USB latency timer of the FTDI bridge ({}), transfers are slower. Writing 1 to /sys/class/tty/{}/device/latency_timer as root fixes that.",
+                           e, Path::new(port).file_name().unwrap_or_default().to_string_lossy()),
+    }
+    let probe = probe::detect(port);
+    if let Some(profile) = probe {
+        println!("{} detected, writing in chunks of {} bytes.", profile.name, profile.chunk_len);
+    }
+    let calibration = calibration::lookup(port).filter(|c| c.is_relaxed());
+    if let Some(c) = calibration {
+        println!("Using the timing calibrated for this adapter: {}.", c);
+    }
+    match (probe, calibration) {
+        (None, None) => Ok((trace::wrap(port, Box::new(serial)), None)),
+        (probe, calibration) => {
+            let mut transport = match probe {
+                Some(profile) => probe::ProbeTransport::new(serial, profile),
+                None => probe::ProbeTransport::plain(serial),
+            };
+            if let Some(c) = calibration {
+                transport.relax(c.chunk_gap, c.min_timeout());
+            }
+            Ok((trace::wrap(port, Box::new(transport)), None))
+        }
+    }
+}
 
-use std::io::{BufRead, BufReader};
-use clap::Parser;
-use clap_num::maybe_hex;
-use std::time::Duration;
-use serialport::SerialPort;
+/// Lowers the BL2 baud rate to the fastest of FIP_BAUDRATES the USB to UART bridge of the
+/// port runs at, rather than losing two attempts at a rate the bridge can't do.
+fn fit_bridge(serial: &str, baudrate: u32) -> u32 {
+    let Some(bridge) = probe::bridge(serial) else {
+        return baudrate;
+    };
+    if baudrate <= bridge.max_baudrate {
+        return baudrate;
+    }
+    let fitting = FIP_BAUDRATES.iter().copied().find(|&b| b <= bridge.max_baudrate).unwrap_or(baudrate);
+    println!("{} runs at up to {} baud, loading the BL2 payload at {} baud instead of {}.",
+             bridge.name, bridge.max_baudrate, fitting, baudrate);
+    fitting
+}
 
-/// Utility to upload and execute binaries over UART for Mediatek SoCs.
-#[derive(Parser, Debug)]
-struct Args {
-    /// Serial port
-    #[arg(short, long)]
-    serial: Option<String>,
+fn console_view(args: &Args, binary_out: Option<&str>) -> ConsoleView {
+    let filter = LineFilter::new(&args.grep, &args.highlight, !args.headless);
+    let mut view = ConsoleView::new(filter, args.binary_view, binary_out);
+    view.set_strip_ansi(args.strip_ansi);
+    for spec in &args.console_log {
+        let sink = spec.open().unwrap_or_else(|e| panic!("Can't send the console to {}: {}", spec, e));
+        view.add_sink(sink);
+    }
+    if !args.excerpt.is_empty() {
+        let patterns = args.excerpt.iter()
+            .map(|r| regex::Regex::new(r).expect("invalid --excerpt expression."))
+            .collect();
+        view.set_excerpts(Excerpts::new(patterns, args.excerpt_context));
+    }
+    if args.decode_log {
+        view.set_log_decoder(mtklog::LogDecoder::new());
+    }
+    if let Some(path) = &args.symbols {
+        let elf = std::fs::read(path).expect("failed to open symbols file.");
+        view.set_symbols(Symbols::parse(&elf).expect("no function symbols found in symbols file."));
+    }
+    view
+}
 
-    /// Path to the binary code to be executed
-    #[arg(short, long)]
-    payload: String,
+/// The port the console is on, the separate console port if there is one.
+fn console_port<'a>(console: &'a mut Option<ConsolePort>, session: &'a mut Session) -> &'a mut dyn Transport {
+    match console {
+        Some(port) => port,
+        None => session.transport(),
+    }
+}
 
-    /// Load address of the payload
-    #[arg(short, long, value_parser=maybe_hex::<u32>, default_value_t = 0x201000)]
-    load_addr: u32,
+/// Runs the whole boot flow and returns whether every stage succeeded.
+fn boot(args: &Args, session: &mut Session, console: &mut Option<ConsolePort>, view: &mut ConsoleView, policy: &Policy, report: &mut Report, journal: &Journal) -> mtk_uartboot::Result<bool> {
+    let resumed = journal.done_before("brom.jumped");
+    // Rejecting an unsigned FIP only after the payload is running would leave the device
+    // half booted.
+    #[cfg(feature = "verify-sig")]
+    if args.verify_sig.is_some() && !args.read_only {
+        if let Some(payload) = &args.payload {
+            read_image(args, payload, "payload");
+        }
+        for (_, path) in &args.load {
+            read_image(args, path, &format!("segment {}", path));
+        }
+        #[cfg(feature = "ymodem")]
+        if let Some(file) = &args.ymodem {
+            read_image(args, file, "YMODEM file");
+        }
+    }
+    // Likewise for a FIP BL2 can't boot. It's checked, and signatures too, when it's read.
+    let fip = args.fip.as_ref()
+        .filter(|_| !args.read_only && !journal.done_before("bl2.go"))
+        .map(|path| prepare_fip(args, path));
+    // The policy allows the FIP or not before the payload is sent, not once BL2 runs it.
+    if let Some(fip) = &fip {
+        policy.check_fip(&to_hex(&images::sha256(fip)));
+    }
+    let image = args.image.as_ref()
+        .filter(|_| !args.read_only)
+        .map(|path| read_image(args, path, "image"));
+    let next_payload = args.next_payload.as_ref()
+        .filter(|_| !args.read_only && !resumed && !args.bl2_running)
+        .map(|(addr, path)| {
+            let data = read_image(args, path, "next payload");
+            let sha256 = to_hex(&images::sha256(&data));
+            policy.check_payload(&sha256);
+            (*addr, path.as_str(), data, sha256)
+        });
+    let load_addr = if resumed {
+        println!("Resuming, the payload is already running.");
+        args.load_addr.map(LoadAddr)
+    } else if args.bl2_running {
+        println!("Skipping the BootROM, BL2 is already running.");
+        args.load_addr.map(LoadAddr)
+    } else {
+        output::emit(StreamEvent::StageStarted { stage: RunStage::Bootrom });
+        load_bl2(args, session, policy, report, journal, next_payload.as_ref().map(|(_, _, data, _)| data.len()))?
+    };
+    if args.read_only {
+        return Ok(true);
+    }
+    if let Some(next) = next_payload {
+        if !load_next_payload(args, session, console, view, next, report)? {
+            return Ok(false);
+        }
+    }
+    if image.is_some() || fip.is_some() {
+        output::emit(StreamEvent::StageStarted { stage: RunStage::Bl2 });
+    }
+    if let Some(image) = image {
+        if !resumed && !args.bl2_running && !wait_bl2_handshake(console_port(console, session), view) {
+            return Ok(false);
+        }
+        flash_image(session, args, &image, report)?;
+        return Ok(true);
+    }
+    if let Some(payload) = fip {
+        if journal.done_before("bl2.handshake") {
+            println!("An interrupted FIP transfer can't be resumed. Reset the device and start over.");
+            return Ok(false);
+        }
+        // BL2 already printed its message when resuming, it's waiting for the handshake.
+        if !resumed && !args.bl2_running && !wait_bl2_handshake(console_port(console, session), view) {
+            return Ok(false);
+        }
+        load_fip(session, args, &payload, load_addr, report, journal)?;
+        wait_for_line(console_port(console, session), "Received FIP", view);
+    }
+    output::emit(StreamEvent::StageStarted { stage: RunStage::Console });
+    #[cfg(any(feature = "tftp", feature = "ymodem"))]
+    if !console_steps(args, console_port(console, session), view)? {
+        return Ok(false);
+    }
+    if !after_boot(args, console_port(console, session), view) {
+        return Ok(false);
+    }
+    if !args.wait_boot.is_empty() && !wait_os_boot(args, console_port(console, session), view, report)? {
+        return Ok(false);
+    }
+    journal.mark("console");
+    report.stamp("console");
+    portlock::set_stage("console");
+    Ok(true)
+}
 
-    /// Whether this is an aarch64 payload
-    #[arg(short, long, default_value_t = false)]
-    aarch64: bool,
+/// Adds the versions the boot stages printed on the console to the report, so fleets can
+/// check the firmware which actually ran, not just the files which were sent.
+fn record_firmware_versions(view: &mut ConsoleView, report: &mut Report) {
+    for (stage, version) in view.firmware_versions() {
+        println!("Firmware: {} {}", stage, version);
+        report.set(&format!("firmware.{}", stage), version);
+    }
+}
 
-    /// Path to an FIP payload. Use this to start an FIP using MTK BL2 built with UART download support
-    #[arg(short, long)]
-    fip: Option<String>,
+/// Adds the counts of the --decode-log lines to the report
+fn record_log_counts(view: &mut ConsoleView, report: &mut Report) {
+    for (key, value) in view.log_counts() {
+        report.set(&key, value);
+    }
+}
 
-    /// Baud rate for loading bootrom payload
-    #[arg(long, default_value_t = 460800)]
-    brom_load_baudrate: u32,
+/// Adds the console lines around the --excerpt matches to the report, a line per entry.
+fn record_excerpts(view: &mut ConsoleView, report: &mut Report) {
+    let (excerpts, dropped) = view.excerpts();
+    for (i, excerpt) in excerpts.iter().enumerate() {
+        report.set(&format!("excerpt.{}.pattern", i + 1), &excerpt.pattern);
+        for (j, line) in excerpt.lines.iter().enumerate() {
+            report.set(&format!("excerpt.{}.line.{}", i + 1, j + 1), line);
+        }
+    }
+    if dropped > 0 {
+        report.set("excerpt.dropped", dropped);
+    }
+}
 
-    /// Baud rate for loading bl2 payload
-    #[arg(long, default_value_t = 921600)]
-    bl2_load_baudrate: u32,
+/// Waits for one of the --wait-boot messages, so a run ends with evidence that the device
+/// boots what was flashed. Returns whether one showed up in time.
+fn wait_os_boot(args: &Args, port: &mut dyn Transport, view: &mut ConsoleView, report: &mut Report) -> mtk_uartboot::Result<bool> {
+    let timeout = Duration::from_secs(args.wait_boot_timeout);
+    port.set_baud_rate(args.console_baudrate)?;
+    port.set_timeout(timeout)?;
+    println!("Waiting for the system to boot:");
+    let patterns: Vec<&str> = args.wait_boot.iter().map(String::as_str).collect();
+    let found = wait_for_any_until(port, &patterns, Instant::now() + timeout, view);
+    port.set_timeout(Duration::from_secs(2))?;
+    let Some(i) = found else {
+        println!("The system didn't boot within {} seconds.", args.wait_boot_timeout);
+        return Ok(false);
+    };
+    println!("System booted.");
+    report.set("os.booted", patterns[i]);
+    output::emit(StreamEvent::OsBooted { marker: patterns[i].to_owned() });
+    Ok(true)
+}
+
+/// Reboots or powers off the device as --after says, with the reset GPIO or the power switch
+/// if there's one, else with a command at the console prompt. Returns whether it worked.
+fn after_boot(args: &Args, port: &mut dyn Transport, view: &mut ConsoleView) -> bool {
+    let done = match (args.after, &args.gpio_reset, &args.power) {
+        (After::Stay, ..) => return true,
+        (After::Reboot, Some(line), _) => {
+            println!("Resetting the device...");
+            gpio::pulse(&args.gpio_chip, line, Duration::from_millis(args.gpio_reset_ms))
+        }
+        (After::Reboot, None, Some(url)) => {
+            println!("Power cycling the device...");
+            PowerSwitch::parse(url).unwrap().cycle(Duration::from_millis(args.power_off_ms))
+        }
+        (After::Poweroff, _, Some(url)) => {
+            println!("Powering off the device...");
+            PowerSwitch::parse(url).unwrap().set(false)
+        }
+        (after, ..) => {
+            let cmd = args.after_cmd.as_deref().or(after.command()).unwrap();
+            // A new line gets a new prompt, also when earlier console steps took the last one.
+            if let Err(e) = port.set_baud_rate(args.console_baudrate).and_then(|()| port.write_all(b"\n")) {
+                println!("--after failed: {}", e);
+                return false;
+            }
+            println!("Waiting for console prompt:");
+            if !wait_for_line(port, &args.prompt, view) {
+                return false;
+            }
+            println!("Running: {}", cmd);
+            port.write_all(format!("{}\n", cmd).as_bytes())
+        }
+    };
+    if let Err(e) = done {
+        println!("--after failed: {}", e);
+        return false;
+    }
+    true
 }
 
-fn load_bl2(args: &Args, port: Box<dyn SerialPort>) -> Box<dyn SerialPort> {
-    let mut brom_dev = bootrom::BootROM::new(port);
+/// Steps run in the bootloader console once booting is done. Returns whether they all succeeded.
+#[cfg(any(feature = "tftp", feature = "ymodem"))]
+fn console_steps(args: &Args, port: &mut dyn Transport, view: &mut ConsoleView) -> mtk_uartboot::Result<bool> {
+    if !needs_console(args) {
+        return Ok(true);
+    }
 
-    println!("Handshake...");
-    brom_dev.handshake();
-    let hw_code = brom_dev.get_hw_code();
-    println!("hw code: {:#x}", hw_code);
-    let (hw_sub_code, hw_ver, sw_ver) = brom_dev.get_hw_dict();
-    println!("hw sub code: {:#x}", hw_sub_code);
-    println!("hw ver: {:#x}", hw_ver);
-    println!("sw ver: {:#x}", sw_ver);
+    port.set_baud_rate(args.console_baudrate)?;
+    #[cfg(feature = "ymodem")]
+    if let Some(dir) = &args.serve_dir {
+        let timeout = Duration::from_secs(args.serve_timeout);
+        if !trigger::serve(port, Path::new(dir), timeout, view) {
+            return Ok(false);
+        }
+    }
+    if !needs_prompt(args) {
+        return Ok(true);
+    }
 
-    let (sb, sla, daa) = brom_dev.get_target_config();
-    if sb {
-        panic!("Secure boot enabled.");
+    println!("Waiting for console prompt:");
+    if !wait_for_line(port, &args.prompt, view) {
+        return Ok(false);
     }
-    if sla {
-        panic!("Serial link authorization enabled.");
+    #[cfg(feature = "tftp")]
+    if let Some(file) = &args.tftp_file {
+        if !tftp_boot(port, args, file, view) {
+            return Ok(false);
+        }
     }
-    if daa {
-        panic!("Download agent authorization enabled.")
+    #[cfg(feature = "ymodem")]
+    if let Some(file) = &args.ymodem {
+        return Ok(send_ymodem(port, args, file, view));
     }
+    Ok(true)
+}
 
-    let payload = std::fs::read(&args.payload)
-        .expect("failed to open payload.");
-    brom_dev.set_baudrate(args.brom_load_baudrate);
-    println!("Baud rate set to {}", args.brom_load_baudrate);
-    println!("sending payload to {:#x}...", args.load_addr);
-    let checksum = brom_dev.send_da(args.load_addr, 0, payload.as_slice());
-    println!("Checksum: {:#x}", checksum);
+/// Boots as the given command line options say, for the boot steps of a pipeline. Also
+/// returns the identity of the device as (name, value) facts.
+#[cfg(feature = "pipeline")]
+fn boot_with(args: &Args, session: &mut Session, view: &mut ConsoleView, serial: &str, unit_id: Option<&str>) -> mtk_uartboot::Result<(bool, Vec<(&'static str, String)>)> {
+    let mut report = Report::new(args.report_times);
+    let result = boot_with_report(args, session, view, serial, unit_id, &mut report);
+    let device = ["hw_code", "hw_sub_code", "hw_ver", "sw_ver", "soc_id"].into_iter()
+        .filter_map(|name| Some((name, report.get(&format!("device.{}", name))?.to_owned())))
+        .collect();
+    Ok((result?, device))
+}
+
+/// Boots as the given command line options say, recording what was sent in report even
+/// if booting fails.
+///
+/// {port} and {unit_id} in the report path are substituted, so parallel runs don't
+/// overwrite each other's reports.
+#[cfg(feature = "pipeline")]
+fn boot_with_report(args: &Args, session: &mut Session, view: &mut ConsoleView, serial: &str, unit_id: Option<&str>, report: &mut Report) -> mtk_uartboot::Result<bool> {
+    let policy = args.policy.as_deref().map(Policy::load).unwrap_or_default();
+    if let Some(id) = unit_id {
+        report.set("unit.id", id);
+    }
+    session.set_read_only(args.read_only);
+    session.set_max_rate(args.max_rate);
+    session.set_write_timeout(Duration::from_millis(args.write_timeout_ms));
+    view.set_serial(serial);
+    report.set("device.port", serial);
+    let mut console = args.console_serial.as_deref().map(|port| ConsolePort::open(port, args.console_baudrate));
+    let result = boot(args, session, &mut console, view, &policy, report, &Journal::default());
+    record_firmware_versions(view, report);
+    record_excerpts(view, report);
+    record_log_counts(view, report);
+    report.stop_clock();
+    if let Some(path) = &args.report {
+        report.write(&report_path(path, serial, unit_id)).expect("failed to write report.");
+    }
+    result
+}
+
+/// The path of a report with {port} and {unit_id} substituted
+#[cfg(feature = "pipeline")]
+fn report_path(path: &str, serial: &str, unit_id: Option<&str>) -> String {
+    let port_name = std::path::Path::new(serial).file_name().map_or(serial.into(), |n| n.to_string_lossy());
+    path.replace("{port}", &port_name)
+        .replace("{unit_id}", unit_id.unwrap_or("unknown"))
+}
+
+/// Runs the pipeline on one port and returns how it went, or why the port couldn't be
+/// opened.
+#[cfg(feature = "pipeline")]
+fn run_pipeline_on(pipeline: &pipeline::Pipeline, port: Option<&str>, unit_id: Option<&str>, view: &mut ConsoleView) -> Result<operator::PortStatus, Failure> {
+    let (serial, port) = try_open_serial(port)?;
+    view.set_serial(&serial);
+    let mut session = Session::new(port);
+    session.on_event(print_event);
+    let mut facts = pipeline::Facts::default();
+    if let Some(id) = unit_id {
+        facts.set("unit_id", id.to_owned());
+    }
+    let ok = match pipeline.run(&mut session, &serial, view, &mut facts) {
+        Ok(ok) => ok,
+        Err(e) => {
+            println!("{}", i18n::text("error", &[&e]));
+            false
+        }
+    };
+    if let Some(path) = pipeline.report() {
+        let mut report = Report::default();
+        report.set("pipeline.status", if ok { "ok" } else { "failed" });
+        facts.add_to(&mut report);
+        report.write(&report_path(path, &serial, unit_id)).expect("failed to write report.");
+    }
+    let device = facts.get("soc_id").map(|id| format!("SoC ID {}", id))
+        .or_else(|| facts.get("hw_code").map(|c| format!("hw code {}", c)));
+    let unit = match (unit_id, device) {
+        (Some(id), Some(device)) => Some(format!("{} ({})", id, device)),
+        (id, device) => id.map(str::to_owned).or(device),
+    };
+    Ok(operator::PortStatus { port: serial, ok, unit })
+}
+
+/// Runs the pipeline on all ports in parallel.
+#[cfg(feature = "pipeline")]
+fn run_pipeline_parallel(args: &Args, pipeline: &pipeline::Pipeline, ports: &[String], unit_ids: &[Option<String>],
+                         fleet: Option<&std::sync::Arc<fleet::Fleet>>) -> Vec<operator::PortStatus> {
+    if args.binary_out.is_some() {
+        panic!("--binary-out can't be used with several ports.");
+    }
+    for port in ports {
+        if let Some(fleet) = fleet {
+            fleet.expect(port);
+        }
+    }
+    std::thread::scope(|s| {
+        let workers: Vec<_> = ports.iter().zip(unit_ids).map(|(port, unit_id)| {
+            s.spawn(move || {
+                output::set_prefix(&format!("[{}] ", port));
+                let _member = fleet.map(|f| f.join(port));
+                let failed = || operator::PortStatus { port: port.clone(), ok: false, unit: None };
+                guarded(|| {
+                    let mut view = console_view(args, None);
+                    run_pipeline_on(pipeline, Some(port), unit_id.as_deref(), &mut view).unwrap_or_else(|failure| {
+                        println!("Error: {}", failure.msg);
+                        failed()
+                    })
+                }).unwrap_or_else(|_| failed())
+            })
+        }).collect();
+        workers.into_iter().map(|w| w.join().expect("pipeline workers return their errors")).collect()
+    })
+}
 
-    println!("Setting baudrate back to 115200");
-    brom_dev.set_baudrate(115200);
+#[cfg(feature = "pipeline")]
+fn run_pipeline(args: &Args, file: &str, ports: &[String], operator_ui: bool, scan_unit_id: bool, compare_config: bool, view: &mut ConsoleView) {
+    let pipeline = pipeline::Pipeline::load(file, args.yes);
+    if compare_config && ports.len() < 2 {
+        panic!("--compare-config needs several --port to compare.");
+    }
+    if args.headless && (operator_ui || scan_unit_id) {
+        panic!("--operator and --scan-unit-id are interactive and can't be used with --headless.");
+    }
+    if args.headless && ports.is_empty() && pipeline.serial().or(args.serial.as_deref()).is_none() {
+        panic!("--headless needs a serial port in the pipeline, --serial or --port.");
+    }
+    if ports.len() <= 1 && !operator_ui {
+        let port = ports.first().map(String::as_str).or(pipeline.serial()).or(args.serial.as_deref());
+        let unit_id = if scan_unit_id { operator::scan_unit_id(&port.map_or_else(|| i18n::text("the_device", &[]), str::to_owned)) } else { None };
+        let status = run_pipeline_on(&pipeline, port, unit_id.as_deref(), view)
+            .unwrap_or_else(|failure| fail(failure.exit_code, failure.msg));
+        if args.headless {
+            print_pipeline_summary(std::slice::from_ref(&status));
+        }
+        if !status.ok {
+            std::process::exit(EXIT_FAILED);
+        }
+        return;
+    }
 
-    if args.aarch64 {
-        println!("Jumping to {:#x} in aarch64...", args.load_addr);
-        brom_dev.jump_da64(args.load_addr);
+    let ports: Vec<String> = match ports {
+        [] => vec![pipeline.serial().or(args.serial.as_deref())
+            .expect("--operator needs a serial port in the pipeline, --serial or --port.").to_owned()],
+        ports => ports.to_vec(),
+    };
+    let unit_ids: Vec<Option<String>> = ports.iter()
+        .map(|port| if scan_unit_id { operator::scan_unit_id(port) } else { None })
+        .collect();
+    let fleet = compare_config.then(fleet::Fleet::new);
+    let mut results = run_pipeline_parallel(args, &pipeline, &ports, &unit_ids, fleet.as_ref());
+    if !operator_ui {
+        println!("{}", i18n::text("pipeline_results", &[]));
+        for status in &results {
+            println!("  {}: {}", status.port, i18n::text(if status.ok { "port_ok" } else { "port_failed" }, &[]));
+        }
     } else {
-        println!("Jumping to {:#x} in aarch32...", args.load_addr);
-        brom_dev.jump_da(args.load_addr);
+        loop {
+            operator::show_board(&results);
+            let retry = operator::ask_retry(&results);
+            if retry.is_empty() {
+                break;
+            }
+            let retry_ports: Vec<String> = retry.iter().map(|&i| ports[i].clone()).collect();
+            let retry_ids: Vec<Option<String>> = retry.iter().map(|&i| unit_ids[i].clone()).collect();
+            for (i, status) in retry.into_iter().zip(run_pipeline_parallel(args, &pipeline, &retry_ports, &retry_ids, fleet.as_ref())) {
+                results[i] = status;
+            }
+        }
+    }
+    if args.headless {
+        print_pipeline_summary(&results);
     }
+    if results.iter().any(|s| !s.ok) {
+        std::process::exit(EXIT_FAILED);
+    }
+}
 
-    brom_dev.into_serial_port()
+/// Prints the one line JSON summary of a pipeline run with --headless
+#[cfg(feature = "pipeline")]
+fn print_pipeline_summary(results: &[operator::PortStatus]) {
+    let ports: Vec<String> = results.iter()
+        .map(|s| format!("{{\"port\":{},\"ok\":{},\"unit\":{}}}",
+                         json_string(&s.port), s.ok, s.unit.as_deref().map_or("null".to_owned(), json_string)))
+        .collect();
+    let status = if results.iter().all(|s| s.ok) { "ok" } else { "failed" };
+    println!("{{\"status\":{},\"ports\":[{}]}}", json_string(status), ports.join(","));
 }
 
-fn wait_for_line(port: Box<dyn SerialPort>, pattern: &str) -> (bool, Box<dyn SerialPort>) {
-    let mut reader = BufReader::new(port);
-    let mut uart_line = String::new();
-    let mut ret = false;
-    println!("==================================");
-    while let Ok(_len) = reader.read_line(&mut uart_line) {
-        print!("{}", uart_line);
-        if uart_line.contains(pattern) {
-            ret = true;
-            break;
+/// Checks that the device on --serial answers the BootROM handshake, for monitoring stations
+/// between jobs.
+fn ping(args: &Args, timeout: Duration) {
+    if args.headless && args.serial.is_none() {
+        panic!("--headless needs --serial.");
+    }
+    let (_, port) = open_serial(args.serial.as_deref());
+    let mut session = Session::new(port);
+    session.set_read_only(true);
+    let mut report = Report::default();
+    let mut brom = session.bootrom();
+    let result = brom.handshake_timeout(timeout).and_then(|()| brom.get_hw_code());
+    match result {
+        Ok(hw_code) => {
+            println!("ok: hw code {:#x}", hw_code);
+            report.set("device.hw_code", format!("{:#x}", hw_code));
+            if args.headless {
+                print_headless_summary("ok", None, &report);
+            }
+        }
+        Err(e) => {
+            println!("No answer: {}", e);
+            explain::print_hint(&e);
+            if args.headless {
+                print_headless_summary("error", Some(&e.to_string()), &report);
+            }
+            std::process::exit(device_exit_code(&e));
         }
-        uart_line.clear();
     }
-    println!("==================================");
-    if !ret {
-        println!("Timeout waiting for specified message.");
+}
+
+/// Runs the checks of `doctor` on --serial, exiting with the status of the first one which
+/// failed
+fn run_doctor(args: &Args, timeout: Duration, sram_reads: u32) {
+    let port = choose_serial(args.serial.as_deref());
+    println!("Checking {}:", port);
+    let mut checks = doctor::Checklist::new();
+    let mut report = Report::default();
+    if let Some(transport) = doctor_open(&port, &mut checks) {
+        let mut session = Session::new(transport);
+        session.set_read_only(true);
+        doctor::device(&mut session, timeout, sram_reads, &mut checks, &mut report);
     }
-    (ret, reader.into_inner())
+    let exit_code = checks.finish(&mut report);
+    if let Some(path) = &args.report {
+        report.write(path).expect("failed to write report.");
+    }
+    let status = if exit_code == 0 { "ok" } else { "failed" };
+    finish(args.headless, status, None, &report, exit_code);
 }
 
-fn wait_bl2_handshake(mut port: Box<dyn SerialPort>) -> (bool, Box<dyn SerialPort>) {
-    port.set_timeout(Duration::from_secs(2)).unwrap();
-    println!("Waiting for BL2. Message below:");
-    wait_for_line(port, "Starting UART download handshake")
+/// Locks and opens port for `doctor`, None if either failed
+fn doctor_open(port: &str, checks: &mut doctor::Checklist) -> Option<Box<dyn Transport>> {
+    let lock = match PortLock::acquire(port) {
+        Ok(Some(lock)) => {
+            checks.pass("lock", "no other run holds the port");
+            Some(lock)
+        }
+        Ok(None) => {
+            checks.warn("lock", "the lock file can't be written, other runs can't tell the port is in use");
+            None
+        }
+        Err(owner) => {
+            checks.fail("lock", &format!("held by {}, --steal takes it over", owner), EXIT_NO_PORT);
+            return None;
+        }
+    };
+    portlock::set_stage("doctor");
+    match try_connect(port) {
+        Ok((transport, _)) => {
+            let detail = match probe::bridge(port) {
+                _ if port.contains("://") => "connected to the bridge".to_owned(),
+                Some(bridge) => format!("{} bridge, up to {} baud", bridge.name, bridge.max_baudrate),
+                None => "opened at 115200 baud".to_owned(),
+            };
+            checks.pass("open", &detail);
+            Some(portlock::wrap(lock, transport))
+        }
+        Err(msg) => {
+            checks.fail("open", &msg, EXIT_NO_PORT);
+            None
+        }
+    }
 }
 
-fn load_fip(port: Box<dyn SerialPort>, baudrate: u32, fip: &str) {
-    let mut bl2_dev = bl2::BL2::new(port);
-    bl2_dev.handshake();
-    println!("BL2 UART DL version: {:#x}", bl2_dev.version());
-    bl2_dev.set_baudrate(baudrate);
-    bl2_dev.handshake();
-    println!("Baudrate set to: {}", baudrate);
+fn dump_memory(args: &Args, addr: u32, size: u32, out: &str) {
+    if !addr.is_multiple_of(2) || !size.is_multiple_of(2) {
+        panic!("--addr and --size must be multiples of 2, the BootROM reads 16 or 32-bit words.");
+    }
+    let (_, port) = open_serial(args.serial.as_deref());
+    let mut session = Session::new(port);
+    session.set_read_only(true);
+    println!("Handshake...");
+    let result = session.bootrom().handshake().and_then(|()| dump::run(&mut session, addr, size, "Dumped"));
+    match result {
+        Ok(data) => {
+            std::fs::write(out, data).expect("failed to write the dump.");
+            println!("{:#x} bytes from {:#x} written to {}.", size, addr, out);
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+            explain::print_hint(&e);
+            std::process::exit(device_exit_code(&e));
+        }
+    }
+}
 
-    let payload = std::fs::read(fip)
-        .expect("failed to open fip.");
-    bl2_dev.send_fip(&payload);
-    println!("FIP sent.");
+/// Runs f on the BootROM after the handshake, exiting with the status of the error if it fails.
+/// addr is checked to be word aligned first.
+fn with_bootrom<T>(args: &Args, addr: u32, read_only: bool, f: impl FnOnce(&mut BootRom) -> mtk_uartboot::Result<T>) -> T {
+    if !addr.is_multiple_of(4) {
+        panic!("The address must be a multiple of 4, the BootROM accesses 32-bit words.");
+    }
+    let (_, port) = open_serial(args.serial.as_deref());
+    let mut session = Session::new(port);
+    session.set_read_only(read_only);
+    println!("Handshake...");
+    let mut brom = session.bootrom();
+    match brom.handshake().and_then(|()| f(&mut brom)) {
+        Ok(result) => result,
+        Err(e) => {
+            println!("Error: {}", e);
+            explain::print_hint(&e);
+            std::process::exit(device_exit_code(&e));
+        }
+    }
+}
+
+/// Boots the echo payload at every baud rate and measures the round trips against it. The
+/// BootROM only switches the rate before the payload is sent, so the device goes back into
+/// download mode for every rate.
+fn measure_latency(args: &Args, baudrates: &[u32], sizes: &[usize], count: usize) {
+    let (_, port) = open_serial(args.serial.as_deref());
+    portlock::set_stage("latency");
+    let mut session = Session::new(port);
+    let mut report = Report::default();
+    let auto_reset = args.gpio_reset.is_some() || args.power.is_some();
+    let mut in_bootrom = false;
+    for &baudrate in baudrates {
+        println!("{} baud:", baudrate);
+        if !in_bootrom {
+            if !auto_reset {
+                println!("Reset the device into download mode.");
+            }
+            let reset = reset_into_download(args);
+            session.transport().set_baud_rate(115200).expect("failed to set baudrate.");
+            if let Err(e) = reset.finish_after(session.bootrom().handshake()) {
+                println!("Error: {}", e);
+                explain::print_hint(&e);
+                std::process::exit(device_exit_code(&e));
+            }
+            in_bootrom = true;
+        }
+        match boot_echo(&mut session, baudrate) {
+            Ok(()) => {
+                in_bootrom = false;
+                latency::run(session.transport(), baudrate, sizes, count, &mut report);
+            }
+            Err(e @ (mtk_uartboot::Error::BaudrateTooHigh(_) | mtk_uartboot::Error::BaudrateSync(_))) => {
+                println!("Not supported: {}", e);
+                report.set(&format!("latency.{}.supported", baudrate), false);
+                if let Err(e) = session.bootrom().recover_baudrate(Baud(115200)) {
+                    println!("Can't get back to 115200 baud: {}", e);
+                    in_bootrom = false;
+                }
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+                explain::print_hint(&e);
+                std::process::exit(device_exit_code(&e));
+            }
+        }
+    }
+    if let Some(path) = &args.report {
+        report.write(path).expect("failed to write report.");
+    }
+}
 
-    bl2_dev.go();
+/// Switches the BootROM to baudrate and starts the echo payload of the SoC
+fn boot_echo(session: &mut Session, baudrate: u32) -> mtk_uartboot::Result<()> {
+    let mut brom = session.bootrom();
+    let hw_code = brom.get_hw_code()?;
+    let chip = chips::lookup(hw_code)
+        .unwrap_or_else(|| panic!("No echo payload for hw code {:#x}, the SoC isn't known.", hw_code));
+    brom.write32(chip.watchdog, &[chips::WATCHDOG_DISABLE])?;
+    if baudrate != 115200 {
+        brom.set_baudrate(Baud(baudrate))?;
+    }
+    brom.send_da(chip.load_addr, ByteLen(0), &latency::echo_payload(chip))?;
+    if chip.aarch64 {
+        brom.jump_da64(chip.load_addr)
+    } else {
+        brom.jump_da(chip.load_addr)
+    }
+}
 
-    wait_for_line(bl2_dev.into_serial_port(), "Received FIP");
+fn peek(args: &Args, addr: u32, count: u32) {
+    let words = with_bootrom(args, addr, true, |brom| brom.read32(addr, count));
+    for (i, word) in words.iter().enumerate() {
+        println!("{:#010x}: {:#010x}", addr + 4 * i as u32, word);
+    }
 }
 
-fn open_serial(port: Option<&str>) -> Box<dyn SerialPort> {
-    let port = match port {
-        Some(p) => p.to_owned(),
-        None => {
-            let ports = serialport::available_ports().expect("No serial ports found.");
-            ports.into_iter().next().expect("No serial ports found.").port_name
+fn poke(args: &Args, addr: u32, values: &[u32]) {
+    with_bootrom(args, addr, args.read_only, |brom| brom.write32(addr, values));
+    println!("Wrote {} words from {:#010x}.", values.len(), addr);
+}
+
+fn run_images_command(args: &Args, command: &ImagesCommand) {
+    let store = store::Store::new(args.store_dir.as_deref());
+    match command {
+        ImagesCommand::Add { file, name } => {
+            let name = name.clone()
+                .unwrap_or_else(|| Path::new(file).file_name().unwrap_or_default().to_string_lossy().into_owned());
+            let data = std::fs::read(file).unwrap_or_else(|e| panic!("failed to open {}: {}", file, e));
+            let added = store.add(&name, &data).unwrap_or_else(|e| panic!("Can't add {}: {}", name, e));
+            println!("Added {} in {} parts, {} of its {} bytes are new to the store.", name, added.parts, added.new_bytes, data.len());
+        }
+        ImagesCommand::List => {
+            for (name, size) in store.list().unwrap_or_else(|e| panic!("Can't list the images: {}", e)) {
+                println!("{:>10}  {}", size, name);
+            }
+        }
+        ImagesCommand::Get { name, out } => {
+            let data = store.get(name).unwrap_or_else(|e| panic!("Can't read {}: {}", name, e));
+            std::fs::write(out, data).unwrap_or_else(|e| panic!("failed to write {}: {}", out, e));
+        }
+        ImagesCommand::Rm { name } => store.remove(name).unwrap_or_else(|e| panic!("Can't remove {}: {}", name, e)),
+        ImagesCommand::Gc => {
+            let collected = store.gc().unwrap_or_else(|e| panic!("Can't collect the unused data: {}", e));
+            println!("Deleted {} files, {} bytes.", collected.files, collected.bytes);
+        }
+    }
+}
+
+#[cfg(feature = "bundle")]
+fn run_bundle_command(args: &Args, command: &BundleCommand, view: &mut ConsoleView) {
+    match command {
+        BundleCommand::Keygen { key } => {
+            bundle::keygen(key).unwrap_or_else(|e| panic!("Can't generate a key: {}", e));
+            println!("Signing key written to {}, public key to {}.pub.", key, key);
+        }
+        BundleCommand::Create { out, key, profile, pipeline, files } => {
+            bundle::create(out, profile.as_deref(), pipeline.as_deref(), files, key)
+                .unwrap_or_else(|e| panic!("Can't create the bundle: {}", e));
+            println!("Bundle written to {}.", out);
+        }
+        BundleCommand::Verify { bundle, pubkey } => {
+            let bundle = bundle::Bundle::open(bundle, pubkey).unwrap_or_else(|e| panic!("{}", e));
+            bundle.list();
+            println!("The bundle is signed by the key and complete.");
+        }
+        BundleCommand::Run { bundle, pubkey } => run_bundle(args, bundle, pubkey, view),
+    }
+}
+
+/// Verifies a bundle, and runs it from a directory it's extracted to, so its profile or
+/// pipeline finds the files by their names.
+#[cfg(feature = "bundle")]
+fn run_bundle(args: &Args, path: &str, pubkey: &str, view: &mut ConsoleView) {
+    let bundle = bundle::Bundle::open(path, pubkey).unwrap_or_else(|e| panic!("{}", e));
+    let dir = bundle.extract().unwrap_or_else(|e| panic!("Can't extract the bundle: {}", e));
+    std::env::set_current_dir(&dir).expect("failed to change to the bundle directory.");
+    println!("Bundle verified, running it from {}.", dir.display());
+    if let Some(pipeline) = bundle.pipeline() {
+        return run_pipeline(args, pipeline, &[], false, false, false, view);
+    }
+
+    let profile = monitor::parse_profile(&bundle.profile().unwrap(), args.yes)
+        .unwrap_or_else(|e| panic!("invalid profile in the bundle: {}", e));
+    let (serial, port) = open_serial(args.serial.as_deref().or(profile.serial.as_deref()));
+    let mut session = Session::new(port);
+    session.on_event(print_event);
+    match boot_with(&profile, &mut session, view, &serial, None) {
+        Ok((true, _)) => {}
+        Ok((false, _)) => std::process::exit(EXIT_FAILED),
+        Err(e) => {
+            println!("Error: {}", e);
+            explain::print_hint(&e);
+            std::process::exit(device_exit_code(&e));
+        }
+    }
+}
+
+#[cfg(feature = "hooks")]
+fn run_post_boot_hooks(args: &Args, serial: &str) -> bool {
+    let netif = match &args.wait_netif {
+        Some(pattern) => match hooks::wait_for_netif(pattern, Duration::from_secs(args.netif_timeout)) {
+            Some(name) => Some(name),
+            None => return false,
         },
+        None => None,
     };
+    args.post_boot_hook.iter()
+        .all(|cmd| hooks::run_hook(cmd, serial, netif.as_deref()))
+}
 
-    println!("Using serial port: {}", port);
-    serialport::new(&port, 115200)
-        .timeout(Duration::from_secs(2))
-        .open().expect("Failed to open port")
+/// Prints the one line JSON summary of --headless
+fn print_headless_summary(status: &str, error: Option<&str>, report: &Report) {
+    println!("{{\"status\":{},\"error\":{},\"report\":{}}}",
+             json_string(status), error.map_or("null".to_owned(), json_string), report.to_json());
+}
+
+/// Ends the run with the JSON summary of --headless and the result event of --output json
+fn finish(headless: bool, status: &str, error: Option<&str>, report: &Report, exit_code: i32) {
+    if headless {
+        print_headless_summary(status, error, report);
+    }
+    output::emit(StreamEvent::Result { status: status.to_owned(), error: error.map(str::to_owned), exit_code,
+                                       report: report.entries().into_iter().collect() });
+    html::finish(status, error, exit_code, report);
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+}
+
+/// Makes panics, which are how most errors outside the protocol surface, end the run with
+/// the exit status of their [`Failure`], or with EXIT_INTERNAL_ERROR and the summaries with
+/// --headless or --output json. Panics of pipeline workers only fail their port.
+fn set_panic_hook(args: &Args) {
+    let headless = args.headless;
+    let summaries = headless || args.output == OutputFormat::Json || args.report_html.is_some();
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let failure = info.payload().downcast_ref::<Failure>();
+        match failure {
+            Some(failure) => println!("Error: {}", failure.msg),
+            None => default_hook(info),
+        }
+        if std::thread::current().name() != Some("main") || failure.is_none() && !summaries {
+            return;
+        }
+        let msg = panic_message(info.payload())
+            .or(failure.map(|f| f.msg.as_str()))
+            .unwrap_or("panic");
+        let exit_code = failure.map_or(EXIT_INTERNAL_ERROR, |f| f.exit_code);
+        finish(headless, "error", Some(msg), &Report::default(), exit_code);
+    }));
+}
+
+/// The command line, with the defaults of the options from the board profile if one is given
+#[cfg(feature = "profile")]
+fn cli_command() -> clap::Command {
+    let command = with_plugins(profile::add_args(Cli::command()));
+    match profile::selected(&command) {
+        Ok(None) => command,
+        Ok(Some((path, options))) => {
+            println!("Using profile {}", path.display());
+            profile::apply(command, &options).unwrap_or_else(|e| panic!("invalid profile {}: {}", path.display(), e))
+        }
+        Err(e) => panic!("failed to load the profile: {}", e),
+    }
+}
+
+#[cfg(not(feature = "profile"))]
+fn cli_command() -> clap::Command {
+    with_plugins(Cli::command())
+}
+
+/// Lists the plugins found on PATH in the help
+fn with_plugins(command: clap::Command) -> clap::Command {
+    let plugins = plugin::list();
+    if plugins.is_empty() {
+        return command;
+    }
+    command.after_help(format!("Plugins on PATH: {}", plugins.join(", ")))
+}
+
+/// Runs the plugin of an unknown subcommand, mtk_uartboot-NAME on PATH, with the options it
+/// shares in the environment: the serial port, found if it's auto, the report and the
+/// output format, and this executable to call back. The port isn't kept open, the plugin
+/// opens it itself.
+fn run_plugin(args: &Args, argv: &[String]) {
+    let (name, plugin_args) = argv.split_first().expect("clap gives the name of the subcommand");
+    let Some(path) = plugin::find(name) else {
+        eprintln!("error: unrecognized subcommand '{}', and there's no mtk_uartboot-{} on PATH.", name, name);
+        std::process::exit(2);
+    };
+    let mut command = std::process::Command::new(&path);
+    command.args(plugin_args);
+    if let Ok(exe) = std::env::current_exe() {
+        command.env("MTK_UARTBOOT_BIN", exe);
+    }
+    if let Some(serial) = args.serial.as_deref() {
+        let serial = if serial == AUTO_SERIAL { find_bootrom_port() } else { resolve_serial(serial) };
+        command.env("MTK_UARTBOOT_SERIAL", serial);
+    }
+    if let Some(report) = &args.report {
+        command.env("MTK_UARTBOOT_REPORT", report);
+    }
+    if args.output == OutputFormat::Json {
+        command.env("MTK_UARTBOOT_OUTPUT", "json");
+    }
+    let status = command.status().unwrap_or_else(|e| panic!("Can't run {}: {}", path.display(), e));
+    std::process::exit(status.code().unwrap_or(EXIT_FAILED));
 }
 
 fn main() {
+    let cli = Cli::from_arg_matches(&cli_command().get_matches()).unwrap_or_else(|e| e.exit());
+    let (mut args, command) = match cli.command {
+        Some(Command::Boot(args)) => (Args::from(*args), None),
+        Some(Command::Flash(args)) => (Args::from(*args), None),
+        Some(Command::Fip(args)) => (Args::from(*args), None),
+        Some(Command::Rescue { board: Some(name), images, #[cfg(feature = "tftp")] recovery, args }) => {
+            let mut args = Args::from(*args);
+            let board = rescue::find(&name).unwrap_or_else(|| panic!("no board {}, there are:\n{}", name, rescue::list()));
+            rescue_args(&mut args, board, &images);
+            #[cfg(feature = "tftp")]
+            if let Some(file) = recovery {
+                args.tftp_root = Some(images);
+                args.tftp_file = Some(file);
+                args.tftp_cmd = "setenv serverip {host_ip}; tftpboot {file} && bootm".to_owned();
+            }
+            (args, None)
+        }
+        command => (Args::from(cli.args), command),
+    };
+    if let Some(Command::Info) = command {
+        args.read_only = true;
+    }
+    if args.output == OutputFormat::Json {
+        output::enable_json().expect("failed to move the output to stderr.");
+    }
+    if let Some(path) = &args.report_html {
+        html::enable(path);
+    }
+    output::emit(StreamEvent::Started { version: env!("CARGO_PKG_VERSION").to_owned(), schema: stream::SCHEMA_VERSION });
+    if !args.no_calibration && !args.dry_run {
+        calibration::enable();
+    }
+    if let Some(path) = &args.trace {
+        trace::enable(path).expect("failed to create trace file.");
+    }
     println!("mtk_uartboot - {}", env!("CARGO_PKG_VERSION"));
-    let args = Args::parse();
+    set_panic_hook(&args);
+    if args.steal {
+        portlock::steal();
+    }
+    if let Some(name) = &args.digest_engine {
+        let names: Vec<&str> = digest::engines().iter().map(|e| e.name()).collect();
+        let engine = digest::find(name).unwrap_or_else(|| panic!("no digest engine {}, there are: {}", name, names.join(", ")));
+        digest::set_engine(engine);
+    }
+    if let Some(path) = &args.messages {
+        i18n::load(path).unwrap_or_else(|e| panic!("Can't load the messages of {}: {}", path, e));
+    }
+    if let Some(Command::Plugin(argv)) = &command {
+        return run_plugin(&args, argv);
+    }
+    let mut view = console_view(&args, args.binary_out.as_deref());
+    #[cfg(feature = "pipeline")]
+    if let Some(Command::Pipeline(PipelineCommand::Run { file, port, operator, scan_unit_id, compare_config })) = &command {
+        return run_pipeline(&args, file, port, *operator, *scan_unit_id, *compare_config, &mut view);
+    }
+    #[cfg(feature = "monitor")]
+    if let Some(Command::Monitor { on_attach, filter }) = &command {
+        return monitor::run(on_attach, filter, args.yes);
+    }
+    #[cfg(feature = "bundle")]
+    if let Some(Command::Bundle(command)) = &command {
+        return run_bundle_command(&args, command, &mut view);
+    }
+    #[cfg(feature = "daemon")]
+    if let Some(Command::Daemon { listen, state_dir, webhook, tokens }) = &command {
+        return daemon::run(daemon::Config {
+            listen: listen.clone(),
+            state_dir: state_dir.clone(),
+            webhook: webhook.clone(),
+            tokens: tokens.clone(),
+            yes: args.yes,
+        });
+    }
+    if let Some(Command::Linktest { baudrates, len }) = &command {
+        let (serial, mut port) = open_serial(args.serial.as_deref());
+        let mut report = Report::default();
+        portlock::set_stage("linktest");
+        let passed = linktest::run(port.as_mut(), &serial, baudrates, *len, &mut report);
+        if let Some(path) = &args.report {
+            report.write(path).expect("failed to write report.");
+        }
+        if !passed {
+            std::process::exit(EXIT_FAILED);
+        }
+        return;
+    }
+    if let Some(Command::Latency { baudrates, sizes, count }) = &command {
+        return measure_latency(&args, baudrates, sizes, *count);
+    }
+    if let Some(Command::ListPorts) = &command {
+        return list_ports();
+    }
+    if let Some(Command::Messages) = &command {
+        print!("{}", i18n::template());
+        return;
+    }
+    if let Some(Command::Rescue { board: None, .. }) = &command {
+        print!("Boards:\n{}", rescue::list());
+        return;
+    }
+    if let Some(Command::DigestBench { mib }) = &command {
+        return digest_bench(*mib);
+    }
+    if let Some(Command::Matrix { file }) = &command {
+        let text = std::fs::read_to_string(file).unwrap_or_else(|e| panic!("Can't read {}: {}", file, e));
+        print!("{}", matrix::table(&text));
+        return;
+    }
+    if let Some(Command::Explain { topic }) = &command {
+        match topic.as_deref().map(|name| (name, explain::topic(name))) {
+            None => print!("Topics:\n{}", explain::list()),
+            Some((_, Some(topic))) => print!("{}", explain::render(topic)),
+            Some((name, None)) => panic!("no topic {}, there are:\n{}", name, explain::list()),
+        }
+        return;
+    }
+    if let Some(Command::Ping { timeout_ms }) = &command {
+        return ping(&args, Duration::from_millis(*timeout_ms));
+    }
+    if let Some(Command::Doctor { timeout_ms, sram_reads }) = &command {
+        return run_doctor(&args, Duration::from_millis(*timeout_ms), *sram_reads);
+    }
+    if let Some(Command::Dump { addr, size, out }) = &command {
+        return dump_memory(&args, *addr, *size, out);
+    }
+    if let Some(Command::Peek { addr, count }) = &command {
+        return peek(&args, *addr, *count);
+    }
+    if let Some(Command::Poke { addr, values }) = &command {
+        return poke(&args, *addr, values);
+    }
+    if let Some(Command::Images(command)) = &command {
+        return run_images_command(&args, command);
+    }
+    if let Some(Command::FipDiff { a, b }) = &command {
+        match fipdiff::run(a, b) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(EXIT_FAILED),
+            Err(e) => panic!("Can't compare the FIPs: {}", e),
+        }
+    }
+
+    let policy = args.policy.as_deref().map(Policy::load).unwrap_or_default();
+    let journal = args.journal.as_deref()
+        .map(|path| Journal::open(path, args.resume))
+        .unwrap_or_default();
 
-    let port = open_serial(args.serial.as_deref());
-    let port = load_bl2(&args, port);
-    if let Some(fip_path) = &args.fip {
-        let (handshake_result, port) = wait_bl2_handshake(port);
-        if !handshake_result {
+    if args.headless && args.serial.is_none() {
+        panic!("--headless needs --serial.");
+    }
+    if args.headless && !args.pause_at.is_empty() && args.pause_cmd.is_none() {
+        panic!("--pause-at needs --pause-cmd with --headless.");
+    }
+    if args.wait_for_port {
+        wait_for_port(args.serial.as_deref());
+    }
+    if let Some(name) = &args.soc {
+        let next_len = args.next_payload.as_ref().map(|(_, path)| read_image(&args, path, "next payload").len());
+        dry_run(&args, soc(name), next_len);
+    }
+    if let Some(board) = args.rescue {
+        rescue::guide(board);
+    }
+    let (serial, port, mut port_observer) = open_serial_observed(args.serial.as_deref());
+    view.set_serial(&serial);
+    args.bl2_load_baudrate = fit_bridge(&serial, args.bl2_load_baudrate);
+    let link = LinkStats::start(&serial);
+    let chunks = ChunkStats::new(args.verbose);
+    let handshake_latency = calibration::Latency::default();
+    let mut session = Session::new(port);
+    let event_journal = journal.clone();
+    let mut latency_observer = handshake_latency.observer();
+    let mut link_observer = link.observer();
+    let mut chunk_observer = chunks.observer();
+    let mut progress_observer = (!args.quiet).then(|| ProgressBar::new(args.verbose < 2).observer());
+    let mut output_observer = output::observer();
+    let timing = args.timing.as_ref().map(|_| Timing::new());
+    let mut timing_observer = timing.as_ref().map(Timing::observer);
+    session.set_profiling(timing.is_some());
+    session.on_event(move |event| {
+        output_observer(event);
+        if let Some(observer) = &mut progress_observer {
+            observer(event);
+        }
+        print_event(event);
+        event_journal.on_event(event);
+        link_observer(event);
+        chunk_observer(event);
+        latency_observer(event);
+        if let Some(observer) = &mut port_observer {
+            observer(event);
+        }
+        if let Some(observer) = &mut timing_observer {
+            observer(event);
+        }
+    });
+    session.set_read_only(args.read_only || args.dry_run);
+    session.set_max_rate(args.max_rate);
+    session.set_write_timeout(Duration::from_millis(args.write_timeout_ms));
+    let mut report = Report::new(args.report_times);
+    report.set("device.port", &serial);
+    let telemetry = args.telemetry_cmd.as_deref().map(|cmd| {
+        let interval = Duration::from_millis(args.telemetry_interval_ms);
+        Telemetry::start(cmd, interval, args.telemetry_log.as_deref(), args.min_voltage)
+    });
+    let mut console = args.console_serial.as_deref().map(|port| ConsolePort::open(port, args.console_baudrate));
+    let result = boot(&args, &mut session, &mut console, &mut view, &policy, &mut report, &journal);
+    record_firmware_versions(&mut view, &mut report);
+    record_excerpts(&mut view, &mut report);
+    record_log_counts(&mut view, &mut report);
+    if !args.read_only {
+        link.finish(&mut report);
+        chunks.finish(&mut report);
+    }
+    if let (Some(timing), Some(path)) = (&timing, &args.timing) {
+        timing.finish(path).unwrap_or_else(|e| panic!("Can't write the timing to {}: {}", path, e));
+    }
+    if let Some(telemetry) = telemetry {
+        let summary = telemetry.stop();
+        report.set("telemetry.samples", summary.samples);
+        if let Some(v) = summary.min_voltage {
+            report.set("telemetry.min_voltage", v);
+        }
+        if let Some(t) = summary.max_temperature {
+            report.set("telemetry.max_temperature", t);
+        }
+        report.set("telemetry.voltage_dips", summary.dips);
+        if summary.dips > 0 {
+            println!("The supply voltage dropped below {} V {} times. Check the power supply if the boot failed.",
+                     args.min_voltage.unwrap(), summary.dips);
+        }
+    }
+    report.stop_clock();
+    if let Some(path) = &args.report {
+        report.write(path).expect("failed to write report.");
+    }
+    match result {
+        Ok(true) => {
+            journal.finish();
+            if !args.read_only {
+                let rejected = report.get("link.packets_rejected").and_then(|r| r.parse().ok()).unwrap_or(0);
+                match calibration::record(&serial, handshake_latency.get(), rejected) {
+                    Ok(Some(c)) if rejected > 0 => println!("BL2 rejected packets, the next runs with this adapter pause {} ms after each write.", c.chunk_gap.as_millis()),
+                    Ok(_) => {}
+                    Err(e) => output::warning(&format!("Can't keep the calibration of the adapter: {}", e)),
+                }
+            }
+            if let Some(path) = &args.matrix {
+                matrix::record(path, &serial, &report).unwrap_or_else(|e| panic!("Can't write to {}: {}", path, e));
+            }
+            if args.rescue.is_some() {
+                #[cfg(feature = "tftp")]
+                rescue::finished(args.tftp_file.as_deref());
+                #[cfg(not(feature = "tftp"))]
+                rescue::finished(None);
+            }
+        }
+        Ok(false) => {
+            if args.headless || args.output == OutputFormat::Json || args.report_html.is_some() {
+                finish(args.headless, "failed", None, &report, EXIT_FAILED);
+            }
             return;
         }
-        load_fip(port, args.bl2_load_baudrate, fip_path);
+        Err(e) => {
+            println!("Error: {}", e);
+            explain::print_hint(&e);
+            let exit_code = device_exit_code(&e);
+            finish(args.headless, "error", Some(&e.to_string()), &report, exit_code);
+        }
+    }
+    if args.console && !args.read_only {
+        let auto_exit = terminal::AutoExit {
+            idle: args.exit_on_idle,
+            patterns: args.exit_on_pattern.iter()
+                .map(|r| regex::Regex::new(r).expect("invalid --exit-on-pattern expression."))
+                .collect(),
+        };
+        let port = console_port(&mut console, &mut session);
+        if let Err(e) = port.set_baud_rate(args.console_baudrate).and_then(|()| terminal::run(port, &auto_exit, &args.macros)) {
+            println!("Console closed: {}", e);
+        }
+    }
+    drop(session);
+    #[cfg(feature = "hooks")]
+    if !args.read_only && !run_post_boot_hooks(&args, &serial) {
+        finish(args.headless, "failed", Some("post-boot hook failed"), &report, EXIT_FAILED);
     }
+    finish(args.headless, "ok", None, &report, 0);
 }
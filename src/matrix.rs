@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use crate::probe;
+use crate::report::Report;
+
+/// What a record keeps of a run, from the report. Nothing identifying the host, the device
+/// or the images: no port names, serial numbers, digests or times.
+const REPORT_KEYS: &[(&str, &str)] = &[
+    ("hw_code", "device.hw_code"),
+    ("hw_sub_code", "device.hw_sub_code"),
+    ("hw_ver", "device.hw_ver"),
+    ("sw_ver", "device.sw_ver"),
+    ("brom_baudrate", "brom.baudrate"),
+    ("bl2_baudrate", "fip.baudrate"),
+];
+
+/// The kind of adapter behind a serial port, by its USB IDs rather than its serial number
+fn adapter(port: &str) -> String {
+    if port.contains("://") {
+        return port.split("://").next().unwrap().to_owned();
+    }
+    if let Some(probe) = probe::detect(port) {
+        return probe.name.to_owned();
+    }
+    if let Some(bridge) = probe::bridge(port) {
+        return bridge.name.to_owned();
+    }
+    let usb = serialport::available_ports().unwrap_or_default().into_iter()
+        .filter(|p| p.port_name == port)
+        .find_map(|p| match p.port_type {
+            serialport::SerialPortType::UsbPort(usb) => Some(format!("USB {:04x}:{:04x}", usb.vid, usb.pid)),
+            _ => None,
+        });
+    usb.unwrap_or_else(|| "unknown".to_owned())
+}
+
+/// Appends the record of a successful boot to the matrix file at path, a line of tab
+/// separated key=value fields per run.
+pub fn record(path: &str, port: &str, report: &Report) -> io::Result<()> {
+    let mut fields = vec![format!("tool={}", env!("CARGO_PKG_VERSION"))];
+    for (name, key) in REPORT_KEYS {
+        if let Some(value) = report.get(key) {
+            fields.push(format!("{}={}", name, value));
+        }
+    }
+    fields.push(format!("adapter={}", adapter(port)));
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", fields.join("\t"))
+}
+
+/// Runs of one SoC revision
+#[derive(Default)]
+struct Row {
+    runs: usize,
+    adapters: BTreeMap<String, usize>,
+    brom_baudrate: u32,
+    bl2_baudrate: u32,
+}
+
+/// The records of the matrix file as a Markdown table, one row per hw code and BootROM
+/// version, with the adapters seen and the fastest baud rates which worked
+pub fn table(text: &str) -> String {
+    let mut rows: BTreeMap<(String, String, String, String), Row> = BTreeMap::new();
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        let fields: BTreeMap<&str, &str> = line.split('\t').filter_map(|f| f.split_once('=')).collect();
+        let field = |name: &str| fields.get(name).copied().unwrap_or("?").to_owned();
+        let row = rows.entry((field("hw_code"), field("hw_sub_code"), field("hw_ver"), field("sw_ver"))).or_default();
+        row.runs += 1;
+        *row.adapters.entry(field("adapter")).or_default() += 1;
+        let baudrate = |name: &str| fields.get(name).and_then(|b| b.parse().ok()).unwrap_or(0);
+        row.brom_baudrate = row.brom_baudrate.max(baudrate("brom_baudrate"));
+        row.bl2_baudrate = row.bl2_baudrate.max(baudrate("bl2_baudrate"));
+    }
+    let mut out = String::from("| SoC | hw code | hw sub code | hw ver | sw ver | runs | adapters | BootROM baud | BL2 baud |\n");
+    out.push_str("|---|---|---|---|---|---|---|---|---|\n");
+    for ((hw_code, hw_sub_code, hw_ver, sw_ver), row) in &rows {
+        let soc = u16::from_str_radix(hw_code.trim_start_matches("0x"), 16).ok()
+            .and_then(mtk_uartboot::chips::lookup)
+            .map_or("unknown", |chip| chip.name);
+        let adapters: Vec<String> = row.adapters.iter().map(|(name, runs)| format!("{} ({})", name, runs)).collect();
+        let baud = |b: u32| if b == 0 { "-".to_owned() } else { b.to_string() };
+        out.push_str(&format!("| {} | {} | {} | {} | {} | {} | {} | {} | {} |\n", soc, hw_code, hw_sub_code, hw_ver, sw_ver,
+                              row.runs, adapters.join(", "), baud(row.brom_baudrate), baud(row.bl2_baudrate)));
+    }
+    out
+}
@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use mtk_uartboot::{Event, Stage};
+
+/// Upper bounds in seconds of the duration histogram buckets, from a quick BootROM payload
+/// up to a slow FIP at a low baud rate
+const BUCKETS: &[f64] = &[1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+
+/// How a job ended, as counted in the metrics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    /// A stage didn't complete, e.g. an expected message didn't show up
+    Failed,
+    /// Talking to the device failed
+    DeviceError,
+    /// Anything else, e.g. a missing image
+    InternalError,
+}
+
+impl Outcome {
+    pub fn is_ok(self) -> bool {
+        self == Outcome::Ok
+    }
+
+    pub fn class(self) -> &'static str {
+        match self {
+            Outcome::Ok => "ok",
+            Outcome::Failed => "failed",
+            Outcome::DeviceError => "device_error",
+            Outcome::InternalError => "internal_error",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    counts: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: Duration) {
+        let value = value.as_secs_f64();
+        for (count, bound) in self.counts.iter_mut().zip(BUCKETS) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+
+    fn write(&self, out: &mut String, name: &str, label: &str) {
+        for (count, bound) in self.counts.iter().zip(BUCKETS) {
+            writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, label, bound, count).unwrap();
+        }
+        writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, label, self.count).unwrap();
+        writeln!(out, "{}_sum{{{}}} {}", name, label, self.sum).unwrap();
+        writeln!(out, "{}_count{{{}}} {}", name, label, self.count).unwrap();
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    jobs: BTreeMap<&'static str, u64>,
+    bytes: BTreeMap<&'static str, u64>,
+    durations: BTreeMap<&'static str, Histogram>,
+}
+
+/// Counters of the daemon, served at /metrics in the Prometheus text format.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Mutex<Inner>>,
+}
+
+fn stage_name(stage: Stage) -> &'static str {
+    match stage {
+        Stage::BootRom => "brom",
+        Stage::Bl2 => "bl2",
+    }
+}
+
+impl Metrics {
+    pub fn job_done(&self, outcome: Outcome, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        *inner.jobs.entry(outcome.class()).or_default() += 1;
+        inner.durations.entry("job").or_default().observe(duration);
+    }
+
+    /// An event observer counting the bytes sent and timing the transfers of a session,
+    /// from the handshake until everything is sent
+    pub fn observer(&self) -> impl FnMut(&Event) + Send + 'static {
+        let inner = self.inner.clone();
+        let mut started: Option<Instant> = None;
+        let mut last_sent = 0;
+        move |event| match event {
+            Event::HandshakeDone(_) => {
+                started.get_or_insert_with(Instant::now);
+                last_sent = 0;
+            }
+            Event::Progress { stage, sent, total } => {
+                let mut inner = inner.lock().unwrap();
+                *inner.bytes.entry(stage_name(*stage)).or_default() += sent.saturating_sub(last_sent) as u64;
+                last_sent = *sent;
+                if sent == total {
+                    if let Some(start) = started.take() {
+                        inner.durations.entry(stage_name(*stage)).or_default().observe(start.elapsed());
+                    }
+                    last_sent = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The metrics in the Prometheus text format. queued are the current numbers of jobs
+    /// by state.
+    pub fn render(&self, queued: &[(&str, usize)]) -> String {
+        let inner = self.inner.lock().unwrap();
+        let mut out = String::new();
+        out.push_str("# HELP mtk_uartboot_jobs Jobs in the queue by state.\n# TYPE mtk_uartboot_jobs gauge\n");
+        for (state, n) in queued {
+            writeln!(out, "mtk_uartboot_jobs{{state=\"{}\"}} {}", state, n).unwrap();
+        }
+        out.push_str("# HELP mtk_uartboot_jobs_finished_total Finished jobs by outcome.\n# TYPE mtk_uartboot_jobs_finished_total counter\n");
+        for outcome in [Outcome::Ok, Outcome::Failed, Outcome::DeviceError, Outcome::InternalError] {
+            let n = inner.jobs.get(outcome.class()).copied().unwrap_or(0);
+            writeln!(out, "mtk_uartboot_jobs_finished_total{{outcome=\"{}\"}} {}", outcome.class(), n).unwrap();
+        }
+        out.push_str("# HELP mtk_uartboot_bytes_sent_total Payload and FIP bytes sent.\n# TYPE mtk_uartboot_bytes_sent_total counter\n");
+        for stage in ["brom", "bl2"] {
+            let n = inner.bytes.get(stage).copied().unwrap_or(0);
+            writeln!(out, "mtk_uartboot_bytes_sent_total{{stage=\"{}\"}} {}", stage, n).unwrap();
+        }
+        out.push_str("# HELP mtk_uartboot_stage_duration_seconds Durations of the transfers and of whole jobs.\n# TYPE mtk_uartboot_stage_duration_seconds histogram\n");
+        for (stage, histogram) in &inner.durations {
+            histogram.write(&mut out, "mtk_uartboot_stage_duration_seconds", &format!("stage=\"{}\"", stage));
+        }
+        out
+    }
+}
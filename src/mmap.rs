@@ -0,0 +1,109 @@
+use std::io;
+use std::ops::{Deref, Range};
+
+/// Size of the chunks of [`Mapped::chunks`]
+const CHUNK_LEN: usize = 8 << 20;
+
+/// The contents of a file mapped into memory read-only, for images of several GB. Pages are
+/// read in by the kernel as they are touched, and the page cache keeps them rather than the
+/// heap. Going through the file with [`chunks`](Mapped::chunks) keeps the resident size of the
+/// process flat instead of growing with the file.
+///
+/// The file mustn't be truncated while mapped, touching the pages beyond the end then
+/// raises SIGBUS.
+pub struct Mapped {
+    #[cfg(unix)]
+    ptr: *const u8,
+    #[cfg(unix)]
+    len: usize,
+    #[cfg(not(unix))]
+    data: Vec<u8>,
+}
+
+// SAFETY: the mapping is read-only and private to this value.
+unsafe impl Send for Mapped {}
+unsafe impl Sync for Mapped {}
+
+impl Mapped {
+    #[cfg(unix)]
+    pub fn open(path: &str) -> io::Result<Mapped> {
+        use std::os::fd::AsRawFd;
+        let file = std::fs::File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::other("file too large to map"))?;
+        if len == 0 {
+            // Empty mappings aren't allowed.
+            return Ok(Mapped { ptr: std::ptr::NonNull::dangling().as_ptr(), len });
+        }
+        // SAFETY: a new private mapping, it doesn't alias anything. It stays valid after the
+        // file is closed.
+        let ptr = unsafe {
+            libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // The file is read front to back, let the kernel read ahead further.
+        // SAFETY: the range was just mapped.
+        unsafe { libc::madvise(ptr, len, libc::MADV_SEQUENTIAL) };
+        Ok(Mapped { ptr: ptr as *const u8, len })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open(path: &str) -> io::Result<Mapped> {
+        Ok(Mapped { data: std::fs::read(path)? })
+    }
+
+    /// The bytes in range, in chunks of 8 MiB. The pages of a chunk are dropped from the
+    /// process when the next one is taken, they are read from the page cache again if
+    /// they're touched later.
+    pub fn chunks(&self, range: Range<usize>) -> impl Iterator<Item = &[u8]> {
+        let mut done: Option<Range<usize>> = None;
+        self[range.clone()].chunks(CHUNK_LEN).inspect(move |chunk| {
+            let start = done.take().map_or(range.start, |done| {
+                self.release(done.clone());
+                done.end
+            });
+            done = Some(start..start + chunk.len());
+        })
+    }
+
+    #[cfg(unix)]
+    fn release(&self, range: Range<usize>) {
+        const PAGE_MASK: usize = 4095;
+        // madvise wants whole pages, the page before the range may be partly in it.
+        let start = (self.ptr as usize + range.start) & !PAGE_MASK;
+        let end = self.ptr as usize + range.end;
+        // SAFETY: the pages are in the mapping. Dropping pages of an unmodified private
+        // mapping keeps the contents, they are faulted in from the file again.
+        unsafe { libc::madvise(start as *mut libc::c_void, end - start, libc::MADV_DONTNEED) };
+    }
+
+    #[cfg(not(unix))]
+    fn release(&self, _range: Range<usize>) {}
+}
+
+impl Deref for Mapped {
+    type Target = [u8];
+
+    #[cfg(unix)]
+    fn deref(&self) -> &[u8] {
+        // SAFETY: ptr is mapped for len bytes, or dangling for none, until dropped.
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    #[cfg(not(unix))]
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mapped {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // SAFETY: unmaps what open mapped, nothing borrows it anymore.
+            unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde_yaml::Mapping;
+use crate::explain;
+use crate::pipeline::boot_args;
+use crate::{Args, Failure, boot_with, console_view, guarded, try_open_serial};
+
+/// How often the list of serial ports is checked for new ones, where the kernel doesn't
+/// announce them
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long to wait for an announcement before checking the list anyway, e.g. for a missed
+/// one
+const HOTPLUG_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Tells when serial devices may have been added. On Linux the kernel announces them as
+/// uevents on a netlink socket, elsewhere the list of ports is polled.
+struct Hotplug {
+    #[cfg(target_os = "linux")]
+    uevents: Option<uevent::Socket>,
+}
+
+impl Hotplug {
+    fn new() -> Hotplug {
+        Hotplug {
+            #[cfg(target_os = "linux")]
+            uevents: uevent::Socket::open().ok(),
+        }
+    }
+
+    /// Waits until a serial device may have been added or removed
+    fn wait(&self) {
+        #[cfg(target_os = "linux")]
+        if let Some(uevents) = &self.uevents {
+            uevents.wait_for_tty(HOTPLUG_TIMEOUT);
+            return;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod uevent {
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::time::{Duration, Instant};
+
+    /// The kernel's multicast group of uevents
+    const KERNEL_GROUP: u32 = 1;
+
+    /// A netlink socket receiving the uevents of the kernel, as udev does
+    pub struct Socket(OwnedFd);
+
+    impl Socket {
+        pub fn open() -> io::Result<Socket> {
+            let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, libc::NETLINK_KOBJECT_UEVENT) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let socket = Socket(unsafe { OwnedFd::from_raw_fd(fd) });
+            let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+            addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            addr.nl_groups = KERNEL_GROUP;
+            let len = std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+            if unsafe { libc::bind(fd, &addr as *const libc::sockaddr_nl as *const libc::sockaddr, len) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(socket)
+        }
+
+        /// Waits up to timeout for a tty device to be added or removed. Returns whether one
+        /// was.
+        pub fn wait_for_tty(&self, timeout: Duration) -> bool {
+            let deadline = Instant::now() + timeout;
+            let mut buf = [0u8; 8192];
+            loop {
+                let left = deadline.saturating_duration_since(Instant::now());
+                let mut poll = libc::pollfd { fd: self.0.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+                if left.is_zero() || unsafe { libc::poll(&mut poll, 1, left.as_millis() as libc::c_int) } <= 0 {
+                    return false;
+                }
+                let len = unsafe { libc::recv(self.0.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+                if len <= 0 {
+                    return false;
+                }
+                // A header line, e.g. add@/devices/.../tty/ttyUSB0, and KEY=value lines, all
+                // ending with a NUL.
+                let fields: Vec<&[u8]> = buf[..len as usize].split(|&b| b == 0).collect();
+                // Removals too, so a device plugged in again is seen as new.
+                let action = fields.contains(&&b"ACTION=add"[..]) || fields.contains(&&b"ACTION=remove"[..]);
+                if action && fields.contains(&&b"SUBSYSTEM=tty"[..]) {
+                    return true;
+                }
+            }
+        }
+    }
+}
+
+/// A port being booted, free again once this is dropped, however the boot ended
+struct Busy {
+    ports: Arc<Mutex<HashSet<String>>>,
+    port: String,
+}
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        // Not poisoned, it's only locked to insert and remove.
+        self.ports.lock().unwrap().remove(&self.port);
+    }
+}
+
+/// Checks a name against a pattern where '*' matches any run of characters.
+fn wildcard_matches(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let name = match name.strip_prefix(prefix) {
+                Some(n) => n,
+                None => return false,
+            };
+            (0..=name.len()).filter(|&i| name.is_char_boundary(i)).any(|i| wildcard_matches(rest, &name[i..]))
+        }
+    }
+}
+
+/// The names a port is known by: its device path and its links in /dev/serial/by-id.
+fn port_names(port: &str) -> Vec<String> {
+    let mut names = vec![port.to_owned()];
+    if let Ok(dir) = std::fs::read_dir("/dev/serial/by-id") {
+        for entry in dir.filter_map(|e| e.ok()) {
+            if entry.path().canonicalize().is_ok_and(|target| target == Path::new(port)) {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+    }
+    names
+}
+
+pub fn list_ports() -> HashSet<String> {
+    serialport::available_ports()
+        .map(|ports| ports.into_iter().map(|p| p.port_name).collect())
+        .unwrap_or_default()
+}
+
+/// Parses a profile, TOML whose keys are the long command line options of the boot. It may
+/// write to the storage if yes.
+pub fn parse_profile(text: &str, yes: bool) -> Result<Args, String> {
+    let options: Mapping = toml::from_str(text).map_err(|e| e.to_string())?;
+    boot_args(&options, yes)
+}
+
+fn load_profile(path: &str, yes: bool) -> Args {
+    let text = std::fs::read_to_string(path).expect("failed to open profile.");
+    parse_profile(&text, yes).unwrap_or_else(|e| panic!("invalid profile {}: {}", path, e))
+}
+
+/// Boots every serial device which shows up and matches one of the filters, or any device
+/// if there are no filters. Devices present at the start are left alone. Runs until killed.
+/// The profile may write to the storage if yes.
+pub fn run(profile: &str, filters: &[String], yes: bool) {
+    let args = Arc::new(load_profile(profile, yes));
+    let busy: Arc<Mutex<HashSet<String>>> = Arc::default();
+    let hotplug = Hotplug::new();
+    let mut known = list_ports();
+    println!("Waiting for serial devices to attach...");
+    loop {
+        hotplug.wait();
+        let ports = list_ports();
+        for port in ports.difference(&known) {
+            let names = port_names(port);
+            if !filters.is_empty() && !filters.iter().any(|f| names.iter().any(|n| wildcard_matches(f, n))) {
+                continue;
+            }
+            if !busy.lock().unwrap().insert(port.clone()) {
+                continue;
+            }
+            println!("{} attached, booting it.", port);
+            let args = args.clone();
+            let busy = Busy { ports: busy.clone(), port: port.clone() };
+            std::thread::spawn(move || {
+                // The by-id links may take a moment to show up after the device node.
+                std::thread::sleep(POLL_INTERVAL);
+                let ok = matches!(boot_attached(&args, &busy.port), Ok(true));
+                println!("{}: {}", busy.port, if ok { "ok" } else { "FAILED" });
+            });
+        }
+        known = ports;
+    }
+}
+
+/// Boots the device attached on port. Returns whether it booted, or why the port couldn't
+/// be opened or the boot was given up, e.g. for a missing image. Errors are printed already.
+fn boot_attached(args: &Args, port: &str) -> Result<bool, Failure> {
+    let (serial, transport) = try_open_serial(Some(port)).inspect_err(|failure| println!("Error: {}", failure.msg))?;
+    guarded(|| {
+        let mut view = console_view(args, None);
+        let mut session = mtk_uartboot::Session::new(transport);
+        session.on_event(crate::print_event);
+        match boot_with(args, &mut session, &mut view, &serial, None) {
+            Ok((ok, _)) => ok,
+            Err(e) => {
+                println!("Error: {}", e);
+                explain::print_hint(&e);
+                false
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_given_up_is_a_failure_and_frees_the_port() {
+        let ports: Arc<Mutex<HashSet<String>>> = Arc::default();
+        ports.lock().unwrap().insert("/dev/ttyUSB0".to_owned());
+        let busy = Busy { ports: ports.clone(), port: "/dev/ttyUSB0".to_owned() };
+        let result = std::thread::spawn(move || {
+            let _busy = busy;
+            guarded(|| -> bool { crate::fail(crate::EXIT_POLICY, "denied".to_owned()) })
+        }).join().unwrap();
+        assert!(matches!(result, Err(Failure { exit_code: crate::EXIT_POLICY, .. })));
+        assert!(ports.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn port_failing_to_open_is_a_failure() {
+        let args = parse_profile("payload = \"bl2.bin\"", false).unwrap();
+        let result = boot_attached(&args, "/nonexistent/ttyUSB9");
+        assert!(matches!(result, Err(Failure { exit_code: crate::EXIT_NO_PORT, .. })));
+    }
+
+    #[test]
+    fn filters_match_with_wildcards() {
+        assert!(wildcard_matches("usb-FTDI_*", "usb-FTDI_FT232R_USB_UART_A10K-if00-port0"));
+        assert!(wildcard_matches("/dev/ttyUSB*", "/dev/ttyUSB12"));
+        assert!(!wildcard_matches("usb-FTDI_*", "usb-Prolific_PL2303-if00-port0"));
+        assert!(wildcard_matches("*-if0*", "usb-FTDI-if00-port0"));
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use regex::Regex;
+
+/// A timestamp at the start of a line: [   1.234567] of Linux or U-Boot in seconds, or
+/// [1234] of the MediaTek preloader in milliseconds
+const TIME: &str = r"^\[\s*(\d+)(?:\.(\d+))?\]\s*";
+/// NOTICE:  BL2: v2.9(release) of TF-A, with the stage if it names one
+const TFA: &str = r"^(NOTICE|INFO|WARNING|ERROR|VERBOSE):\s+(?:(BL1|BL2|BL31|BL32):\s*)?";
+/// I/TC: OP-TEE version: 4.0.0 of OP-TEE
+const OPTEE: &str = r"^([DIWEF])/(TC|TA|LD):\s*";
+/// [PLFM] Init I2C: OK(0) of the preloader, tags of upper case letters, possibly several
+const TAGS: &str = r"^((?:\[[A-Z][A-Z0-9_]{0,15}\]\s*)+)";
+/// A run of at least 8 hex bytes separated by spaces, like in dumps of buffers
+const HEX_BLOB: &str = r"\b[0-9a-fA-F]{2}(?: [0-9a-fA-F]{2}){7,}\b";
+
+/// What the prefixes of a known log line format say
+#[derive(Debug, Default, PartialEq)]
+pub struct Decoded {
+    /// Seconds since the stage started, as the device counts them
+    pub time: Option<f64>,
+    /// The stage or module logging, lower case, e.g. bl2, optee or plfm
+    pub module: Option<String>,
+    /// error, warning, notice, info, debug or verbose
+    pub level: Option<&'static str>,
+    pub message: String,
+}
+
+/// Lines logged by a module
+struct Module {
+    lines: usize,
+    first: Option<f64>,
+    last: Option<f64>,
+}
+
+/// Decodes the log line formats of the MediaTek preloader, TF-A, OP-TEE, U-Boot and Linux,
+/// for printing them in columns and for counting what the modules logged.
+pub struct LogDecoder {
+    time: Regex,
+    tfa: Regex,
+    optee: Regex,
+    tags: Regex,
+    hex_blob: Regex,
+    modules: BTreeMap<String, Module>,
+    errors: usize,
+    warnings: usize,
+}
+
+fn tfa_level(level: &str) -> &'static str {
+    match level {
+        "ERROR" => "error",
+        "WARNING" => "warning",
+        "NOTICE" => "notice",
+        "INFO" => "info",
+        _ => "verbose",
+    }
+}
+
+fn optee_level(level: &str) -> &'static str {
+    match level {
+        "E" | "F" => "error",
+        "W" => "warning",
+        "I" => "info",
+        _ => "debug",
+    }
+}
+
+impl LogDecoder {
+    pub fn new() -> LogDecoder {
+        LogDecoder {
+            time: Regex::new(TIME).unwrap(),
+            tfa: Regex::new(TFA).unwrap(),
+            optee: Regex::new(OPTEE).unwrap(),
+            tags: Regex::new(TAGS).unwrap(),
+            hex_blob: Regex::new(HEX_BLOB).unwrap(),
+            modules: BTreeMap::new(),
+            errors: 0,
+            warnings: 0,
+        }
+    }
+
+    /// The prefixes of a line, None if it has none of a known format
+    pub fn decode(&self, line: &str) -> Option<Decoded> {
+        let mut decoded = Decoded::default();
+        let mut rest = line.trim_start_matches(|c: char| c.is_whitespace() || c.is_control());
+        if let Some(c) = self.time.captures(rest) {
+            let whole: f64 = c[1].parse().ok()?;
+            decoded.time = Some(match c.get(2) {
+                Some(fraction) => whole + format!("0.{}", fraction.as_str()).parse::<f64>().ok()?,
+                None => whole / 1000.0,
+            });
+            rest = &rest[c[0].len()..];
+        }
+        if let Some(c) = self.tfa.captures(rest) {
+            decoded.level = Some(tfa_level(&c[1]));
+            decoded.module = Some(c.get(2).map_or("tf-a".to_owned(), |s| s.as_str().to_lowercase()));
+            rest = &rest[c[0].len()..];
+        } else if let Some(c) = self.optee.captures(rest) {
+            decoded.level = Some(optee_level(&c[1]));
+            decoded.module = Some("optee".to_owned());
+            rest = &rest[c[0].len()..];
+        } else if let Some(c) = self.tags.captures(rest) {
+            let tags: Vec<String> = c[1].split(']').map(|t| t.trim().trim_start_matches('[').to_lowercase())
+                .filter(|t| !t.is_empty()).collect();
+            // Some preloaders log the level as a tag of its own.
+            let (levels, modules): (Vec<String>, Vec<String>) = tags.into_iter()
+                .partition(|t| matches!(t.as_str(), "err" | "error" | "warn" | "warning" | "info" | "dbg" | "debug"));
+            decoded.level = levels.first().map(|l| match l.as_str() {
+                "err" | "error" => "error",
+                "warn" | "warning" => "warning",
+                "info" => "info",
+                _ => "debug",
+            });
+            decoded.module = (!modules.is_empty()).then(|| modules.join("/"));
+            rest = &rest[c[0].len()..];
+        }
+        if decoded.time.is_none() && decoded.module.is_none() && decoded.level.is_none() {
+            return None;
+        }
+        decoded.message = rest.to_owned();
+        Some(decoded)
+    }
+
+    /// Appends the text of the runs of hex bytes in a line, e.g. |MTK_BLOADER_INFO|
+    fn annotate_hex(&self, line: &str) -> String {
+        let mut out = line.to_owned();
+        for blob in self.hex_blob.find_iter(line) {
+            let text: String = blob.as_str().split(' ')
+                .filter_map(|b| u8::from_str_radix(b, 16).ok())
+                .map(|b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                .collect();
+            out.push_str(&format!(" |{}|", text));
+        }
+        out
+    }
+
+    /// The line in columns: the time, the module, the level and the message, with the text
+    /// of hex blobs appended. Lines of unknown formats only get the text of hex blobs.
+    pub fn pretty(&self, line: &str) -> String {
+        let Some(decoded) = self.decode(line) else {
+            return self.annotate_hex(line);
+        };
+        let time = decoded.time.map_or(String::new(), |t| format!("{:.6}", t));
+        format!("{:>12} {:<10} {:<7} {}", time, decoded.module.as_deref().unwrap_or("-"),
+                decoded.level.unwrap_or("-"), self.annotate_hex(&decoded.message))
+    }
+
+    /// Counts a line towards the modules logging and the errors and warnings
+    pub fn scan(&mut self, line: &str) {
+        let Some(decoded) = self.decode(line) else { return };
+        match decoded.level {
+            Some("error") => self.errors += 1,
+            Some("warning") => self.warnings += 1,
+            _ => {}
+        }
+        if let Some(name) = decoded.module {
+            let module = self.modules.entry(name).or_insert(Module { lines: 0, first: None, last: None });
+            module.lines += 1;
+            module.first = module.first.or(decoded.time);
+            module.last = decoded.time.or(module.last);
+        }
+    }
+
+    /// The counts for the report since the last call: the errors and warnings, and the lines
+    /// of each module with the device times of the first and the last of them
+    pub fn take(&mut self) -> Vec<(String, String)> {
+        let mut entries = vec![("log.errors".to_owned(), self.errors.to_string()),
+                               ("log.warnings".to_owned(), self.warnings.to_string())];
+        for (name, module) in std::mem::take(&mut self.modules) {
+            entries.push((format!("log.{}.lines", name), module.lines.to_string()));
+            if let (Some(first), Some(last)) = (module.first, module.last) {
+                entries.push((format!("log.{}.first_time", name), format!("{:.6}", first)));
+                entries.push((format!("log.{}.last_time", name), format!("{:.6}", last)));
+            }
+        }
+        self.errors = 0;
+        self.warnings = 0;
+        entries
+    }
+}
@@ -0,0 +1,84 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+use crate::i18n;
+
+const PASS_STYLE: &str = "\x1b[1;97;42m";
+const FAIL_STYLE: &str = "\x1b[1;97;41m";
+const RESET_STYLE: &str = "\x1b[0m";
+
+/// Width of a status block on the board
+const BLOCK_WIDTH: usize = 72;
+
+/// The outcome of a pipeline on one port.
+pub struct PortStatus {
+    pub port: String,
+    pub ok: bool,
+    /// What identifies the unit on the port, e.g. its hw code
+    pub unit: Option<String>,
+}
+
+/// Prints a board of big PASS/FAIL blocks, one per port, readable from across the station.
+pub fn show_board(statuses: &[PortStatus]) {
+    let color = io::stdout().is_terminal();
+    println!();
+    for (i, status) in statuses.iter().enumerate() {
+        let (label, style) = if status.ok { (i18n::text("pass", &[]), PASS_STYLE) } else { (i18n::text("fail", &[]), FAIL_STYLE) };
+        let text = format!(" {:>2}  {}  {}  {}", i + 1, label, status.port, status.unit.as_deref().unwrap_or("-"));
+        if color {
+            let blank = " ".repeat(BLOCK_WIDTH);
+            println!("{}{}{}", style, blank, RESET_STYLE);
+            println!("{}{:<width$}{}", style, text, RESET_STYLE, width = BLOCK_WIDTH);
+            println!("{}{}{}", style, blank, RESET_STYLE);
+        } else {
+            println!("{}", text);
+        }
+    }
+    let passed = statuses.iter().filter(|s| s.ok).count();
+    println!("\n{}", i18n::text("passed", &[&passed, &statuses.len()]));
+}
+
+/// Asks for the ID of the unit on a port, as typed by a barcode or QR code scanner.
+/// Returns None if nothing was entered.
+pub fn scan_unit_id(port: &str) -> Option<String> {
+    print!("{}", i18n::text("scan_unit_id", &[&port]));
+    io::stdout().flush().ok();
+    let mut id = String::new();
+    if io::stdin().lock().read_line(&mut id).ok()? == 0 {
+        // End of input, the prompt line is still open.
+        println!();
+        return None;
+    }
+    Some(id.trim().to_owned()).filter(|id| !id.is_empty())
+}
+
+/// Asks the operator which failed ports to run again and returns their indices. Returns
+/// nothing without an interactive terminal.
+pub fn ask_retry(statuses: &[PortStatus]) -> Vec<usize> {
+    let failed: Vec<usize> = (0..statuses.len()).filter(|&i| !statuses[i].ok).collect();
+    if failed.is_empty() || !io::stdin().is_terminal() {
+        return Vec::new();
+    }
+    let all = i18n::text("retry_all", &[]);
+    loop {
+        print!("{}", i18n::text("retry_prompt", &[&all]));
+        io::stdout().flush().ok();
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer).unwrap_or(0) == 0 {
+            return Vec::new();
+        }
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Vec::new();
+        }
+        if answer == all {
+            return failed;
+        }
+        let picked: Option<Vec<usize>> = answer.split([' ', ','])
+            .filter(|n| !n.is_empty())
+            .map(|n| n.parse::<usize>().ok().filter(|&n| n >= 1 && n <= statuses.len()).map(|n| n - 1))
+            .collect();
+        match picked {
+            Some(picked) => return picked,
+            None => println!("{}", i18n::text("invalid_port", &[])),
+        }
+    }
+}
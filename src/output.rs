@@ -0,0 +1,168 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use mtk_uartboot::{Event, Stage};
+use mtk_uartboot::stream::StreamEvent;
+
+/// Where the events of --output json are written, the stdout the tool was started with
+static JSON: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// An event as --report-html keeps it: when it happened since recording started, its name and
+/// its fields, with JSON values
+pub type Recorded = (Duration, String, Vec<(String, String)>);
+
+/// The events kept for --report-html, once recording started
+static RECORDING: OnceLock<(Instant, Mutex<Vec<Recorded>>)> = OnceLock::new();
+
+thread_local! {
+    /// The prefix of the lines this thread prints, and whether its output is at the start of
+    /// a line
+    static PREFIX: RefCell<Option<(String, bool)>> = const { RefCell::new(None) };
+}
+
+/// How the tool reports what it does
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Messages for people
+    #[default]
+    Text,
+    /// JSON lines with the events on stdout, the messages for people go to stderr
+    Json,
+}
+
+/// Moves stdout to stderr, so the messages printed everywhere don't mix with the events,
+/// and keeps the original stdout for the events.
+pub fn enable_json() -> io::Result<()> {
+    io::stdout().flush()?;
+    #[cfg(unix)]
+    {
+        use std::os::fd::FromRawFd;
+        // SAFETY: only duplicates descriptors, the new one is owned by the File.
+        let out = unsafe {
+            let fd = libc::dup(libc::STDOUT_FILENO);
+            if fd < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            File::from_raw_fd(fd)
+        };
+        JSON.set(Mutex::new(out)).ok();
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    Err(io::Error::other("--output json needs a unix system"))
+}
+
+/// Starts the lines this thread prints from now on with prefix, e.g. the port a worker boots,
+/// so the output of workers running in parallel can be told apart
+#[cfg(feature = "pipeline")]
+pub fn set_prefix(prefix: &str) {
+    PREFIX.set(Some((prefix.to_owned(), true)));
+}
+
+/// Prints for the print! and println! of the tool, with the prefix of the thread. A line is
+/// written at once, so the lines of threads don't mix.
+pub fn print(args: fmt::Arguments) {
+    PREFIX.with_borrow_mut(|prefix| match prefix {
+        Some((prefix, at_line_start)) => std::print!("{}", prefixed(&args.to_string(), prefix, at_line_start)),
+        None => std::print!("{}", args),
+    })
+}
+
+/// text with prefix at the start of each line, after a LF or a CR, e.g. of a progress bar
+/// drawn again
+fn prefixed(text: &str, prefix: &str, at_line_start: &mut bool) -> String {
+    let mut out = String::with_capacity(text.len() + prefix.len());
+    for c in text.chars() {
+        if *at_line_start && c != '\n' && c != '\r' {
+            out.push_str(prefix);
+        }
+        out.push(c);
+        *at_line_start = c == '\n' || c == '\r';
+    }
+    out
+}
+
+/// Keeps the events from now on, for a report made of them at the end of the run
+pub fn start_recording() {
+    RECORDING.set((Instant::now(), Mutex::new(Vec::new()))).ok();
+}
+
+/// The events kept since start_recording
+pub fn recorded() -> Vec<Recorded> {
+    RECORDING.get().map(|(_, events)| events.lock().unwrap().clone()).unwrap_or_default()
+}
+
+/// Writes an event with --output json, and keeps it for --report-html. The console lines and
+/// progress are left out of the report, which has the console log and the timings already.
+pub fn emit(event: StreamEvent) {
+    let recording = RECORDING.get().filter(|_| !matches!(event, StreamEvent::ConsoleLine { .. } | StreamEvent::Progress { .. }));
+    if recording.is_none() && JSON.get().is_none() {
+        return;
+    }
+    let value = serde_json::to_value(&event).expect("events are plain data");
+    if let (Some((start, events)), serde_json::Value::Object(fields)) = (recording, &value) {
+        let fields = fields.iter()
+            .filter(|(key, _)| *key != "event")
+            .map(|(key, value)| (key.clone(), value.to_string()))
+            .collect();
+        events.lock().unwrap().push((start.elapsed(), event.name().to_owned(), fields));
+    }
+    let Some(out) = JSON.get() else {
+        return;
+    };
+    let line = format!("{}\n", value);
+    // A consumer which went away doesn't fail the boot.
+    let mut out = out.lock().unwrap();
+    out.write_all(line.as_bytes()).and_then(|()| out.flush()).ok();
+}
+
+/// Prints a warning, and emits it as an event
+pub fn warning(msg: &str) {
+    println!("Warning: {}", msg);
+    emit(StreamEvent::Warning { message: msg.to_owned() });
+}
+
+/// A value of the device as a string of its hex number, as the events have them
+pub fn hex(value: impl std::fmt::LowerHex) -> String {
+    format!("{:#x}", value)
+}
+
+/// How often the progress of a transfer is emitted
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Returns an event observer writing the events of the session which matter to automation
+pub fn observer() -> impl FnMut(&Event) + Send + 'static {
+    let mut last_progress: Option<(Stage, Instant)> = None;
+    move |event| match *event {
+        Event::HandshakeDone(stage) => emit(StreamEvent::Handshake { stage }),
+        Event::BaudrateChanged { stage, baudrate } => emit(StreamEvent::Baudrate { stage, baudrate: baudrate.0 }),
+        Event::Progress { stage, sent, total } => {
+            let due = last_progress.is_none_or(|(s, at)| s != stage || at.elapsed() >= PROGRESS_INTERVAL);
+            if due || sent >= total {
+                last_progress = Some((stage, Instant::now()));
+                emit(StreamEvent::Progress { stage, sent, total });
+            }
+        }
+        Event::PacketRejected { idx, .. } => emit(StreamEvent::PacketRejected { idx }),
+        Event::Paused { stage } => emit(StreamEvent::Paused { stage }),
+        Event::Resumed { stage, paused } => emit(StreamEvent::Resumed { stage, paused_ms: paused.as_millis() as u64 }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_start_with_the_prefix() {
+        let mut at_line_start = true;
+        assert_eq!(prefixed("Handshake...\nhw code: ", "[ttyUSB0] ", &mut at_line_start), "[ttyUSB0] Handshake...\n[ttyUSB0] hw code: ");
+        assert_eq!(prefixed("0x7986\n\n", "[ttyUSB0] ", &mut at_line_start), "0x7986\n\n");
+        assert_eq!(prefixed("\r[##  ] 1/2\r[####] 2/2", "[ttyUSB0] ", &mut at_line_start), "\r[ttyUSB0] [##  ] 1/2\r[ttyUSB0] [####] 2/2");
+        assert!(!at_line_start);
+    }
+}
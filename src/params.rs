@@ -0,0 +1,51 @@
+/// "MUBP", the first word of a parameter block as the payload reads it
+pub const MAGIC: u32 = u32::from_le_bytes(*b"MUBP");
+/// Version of the layout. Fields are only ever added at the end, before the extra words
+pub const VERSION: u32 = 1;
+
+/// Words of the block before the extra words: magic, version, length, console baud rate,
+/// next stage address, flags, hw code, number of extra words
+const HEADER_WORDS: usize = 8;
+
+/// Set in the flags if the next stage is started in aarch64
+pub const FLAG_NEXT_AARCH64: u32 = 1 << 0;
+
+/// Parameters the host hands the payload in memory before jumping to it, so one payload
+/// build serves boards differing in them. Written as 32-bit words in the byte order of the
+/// SoC:
+///
+/// | word | field                                   |
+/// |------|-----------------------------------------|
+/// | 0    | magic, "MUBP"                           |
+/// | 1    | version, 1                              |
+/// | 2    | length of the block in bytes            |
+/// | 3    | console baud rate                       |
+/// | 4    | address of the next stage, 0 if none    |
+/// | 5    | flags, bit 0: next stage is aarch64     |
+/// | 6    | hw code of the SoC                      |
+/// | 7    | number of extra words N                 |
+/// | 8..  | N extra words from --param-word         |
+/// | last | sum of all the words before, wrapping   |
+pub struct ParamBlock {
+    pub console_baudrate: u32,
+    pub next_addr: u32,
+    pub flags: u32,
+    pub hw_code: u32,
+    pub extra: Vec<u32>,
+}
+
+/// Length in bytes of a block with this many extra words
+pub fn len(extra: usize) -> u32 {
+    ((HEADER_WORDS + extra + 1) * 4) as u32
+}
+
+impl ParamBlock {
+    pub fn words(&self) -> Vec<u32> {
+        let len = len(self.extra.len());
+        let mut words = vec![MAGIC, VERSION, len, self.console_baudrate, self.next_addr,
+                             self.flags, self.hw_code, self.extra.len() as u32];
+        words.extend(&self.extra);
+        words.push(words.iter().fold(0u32, |sum, w| sum.wrapping_add(*w)));
+        words
+    }
+}
@@ -0,0 +1,299 @@
+use clap::ValueEnum;
+use crate::fdt::{self, Fdt};
+use crate::fip::Fip;
+
+/// Marker in supporting BL31 builds, followed by the log level as a little-endian u32. In
+/// C: `const volatile struct { char magic[16]; uint32_t level; } uartboot_log __used =
+/// { "MTK_UARTBOOT_LOG", LOG_LEVEL };` with BL31 calling `tf_log_set_max_level()` with it.
+const LOG_LEVEL_MARKER: &[u8; 16] = b"MTK_UARTBOOT_LOG";
+
+/// TF-A log levels
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LogLevel {
+    None,
+    Error,
+    Notice,
+    Warning,
+    Info,
+    Verbose,
+}
+
+impl LogLevel {
+    /// The LOG_LEVEL_* value of TF-A
+    fn value(self) -> u32 {
+        self as u32 * 10
+    }
+}
+
+/// Sets the log level of the BL31 in a FIP, if it was built to take it from the host.
+pub fn set_bl31_log_level(fip: &mut [u8], level: LogLevel) -> Result<(), String> {
+    let toc = Fip::parse(fip)?;
+    let bl31 = toc.find("soc-fw").ok_or("the FIP has no BL31")?;
+    let image = &mut fip[bl31.offset..bl31.offset + bl31.size];
+    let at = image.windows(LOG_LEVEL_MARKER.len() + 4)
+        .position(|w| w.starts_with(LOG_LEVEL_MARKER))
+        .ok_or("BL31 wasn't built to take the log level from the host")?;
+    let field = at + LOG_LEVEL_MARKER.len();
+    image[field..field + 4].copy_from_slice(&level.value().to_le_bytes());
+    Ok(())
+}
+
+/// Finds the device tree in an image: either the image is one, or it's appended to it like
+/// to u-boot.bin. Returns its offset and length.
+fn find_dtb(image: &[u8]) -> Option<(usize, usize)> {
+    if let Some(len) = fdt::blob_len(image) {
+        return Some((0, len));
+    }
+    (0..image.len().saturating_sub(3)).rev()
+        .filter(|&at| image[at..at + 4] == [0xd0, 0x0d, 0xfe, 0xed])
+        .find_map(|at| Some((at, fdt::blob_len(&image[at..])?)))
+}
+
+/// Applies device tree overlays to the device tree in a FIP, the one in the named image, or
+/// else in the first image which is or ends with a device tree. Returns the new FIP and the
+/// name of the image changed.
+pub fn apply_overlays(fip: &[u8], overlays: &[Vec<u8>], entry: Option<&str>) -> Result<(Vec<u8>, String), String> {
+    let toc = Fip::parse(fip)?;
+    let mut images = toc.images(fip);
+    let (i, (at, len)) = match entry {
+        Some(name) => {
+            let i = toc.entries.iter().position(|e| e.label() == name)
+                .ok_or_else(|| format!("the FIP has no {}", name))?;
+            (i, find_dtb(&images[i]).ok_or_else(|| format!("no device tree found in {}", name))?)
+        }
+        None => images.iter().enumerate()
+            .find_map(|(i, image)| fdt::blob_len(image).map(|len| (i, (0, len))))
+            .or_else(|| images.iter().enumerate().find_map(|(i, image)| Some((i, find_dtb(image)?))))
+            .ok_or("no device tree found in the FIP")?,
+    };
+
+    let image = &mut images[i];
+    let mut dtb = Fdt::parse(&image[at..at + len])?;
+    for overlay in overlays {
+        dtb.apply(&Fdt::parse(overlay)?)?;
+    }
+    image.splice(at..at + len, dtb.to_bytes());
+    Ok((toc.rebuild(fip, &images), toc.entries[i].label()))
+}
+
+/// Reads the values of --patch-string tokens: NAME=VALUE pairs given on the command line and
+/// the row of a CSV file whose first column is row. The CSV header names the tokens.
+pub fn load_vars(pairs: &[String], csv: Option<&str>, row: Option<&str>) -> Result<Vec<(String, String)>, String> {
+    let mut vars = Vec::new();
+    if let Some(path) = csv {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+        let header: Vec<&str> = lines.next().ok_or_else(|| format!("{} is empty", path))?
+            .split(',').map(str::trim).collect();
+        let row = row.ok_or("--patch-csv needs --patch-row")?;
+        let values: Vec<&str> = lines.map(|l| l.split(',').map(str::trim).collect::<Vec<_>>())
+            .find(|values| values.first() == Some(&row))
+            .ok_or_else(|| format!("no row {} in {}", row, path))?;
+        vars.extend(header.iter().zip(values).map(|(name, value)| (name.to_string(), value.to_owned())));
+    }
+    for pair in pairs {
+        let (name, value) = pair.split_once('=').ok_or_else(|| format!("expected NAME=VALUE, got {}", pair))?;
+        vars.retain(|(n, _)| n != name);
+        vars.push((name.to_owned(), value.to_owned()));
+    }
+    Ok(vars)
+}
+
+fn replace_all(s: &[u8], token: &[u8], value: &[u8]) -> (Vec<u8>, usize) {
+    let mut out = Vec::with_capacity(s.len());
+    let mut count = 0;
+    let mut i = 0;
+    while i < s.len() {
+        if s[i..].starts_with(token) {
+            out.extend_from_slice(value);
+            i += token.len();
+            count += 1;
+        } else {
+            out.push(s[i]);
+            i += 1;
+        }
+    }
+    (out, count)
+}
+
+/// The offset of the variables in a U-Boot environment blob, after the CRC32 and the flags
+/// byte of redundant environments, if data is one
+fn env_data_offset(data: &[u8]) -> Option<usize> {
+    let crc = u32::from_le_bytes(data.get(..4)?.try_into().unwrap());
+    [4, 5].into_iter().filter(|&at| data.len() > at + 1).find(|&at| {
+        let mut check = flate2::Crc::new();
+        check.update(&data[at..]);
+        check.sum() == crc
+    })
+}
+
+/// Replaces @NAME@ tokens in an image with their values, keeping its size. In a U-Boot
+/// environment blob, the variables are packed again and the CRC updated. Anywhere else a
+/// token must be followed by enough NUL bytes for the value, e.g. `char mac[32] = "@MAC@";`.
+/// Returns the number of tokens replaced.
+pub fn substitute(image: &mut [u8], vars: &[(String, String)]) -> Result<usize, String> {
+    let mut count = 0;
+    if let Some(at) = env_data_offset(image) {
+        let region = &mut image[at..];
+        let end = region.windows(2).position(|w| w == [0, 0]).map_or(region.len(), |p| p + 2);
+        let mut env = region[..end].to_vec();
+        for (name, value) in vars {
+            let (replaced, n) = replace_all(&env, format!("@{}@", name).as_bytes(), value.as_bytes());
+            env = replaced;
+            count += n;
+        }
+        if env.len() > region.len() {
+            return Err("the U-Boot environment doesn't fit its size with the values".to_owned());
+        }
+        let pad = region[region.len() - 1];
+        region[..env.len()].copy_from_slice(&env);
+        region[env.len()..].fill(pad);
+        let mut crc = flate2::Crc::new();
+        crc.update(region);
+        image[..4].copy_from_slice(&crc.sum().to_le_bytes());
+        return Ok(count);
+    }
+
+    for (name, value) in vars {
+        let token = format!("@{}@", name);
+        let mut from = 0;
+        while let Some(p) = image[from..].windows(token.len()).position(|w| w == token.as_bytes()) {
+            let at = from + p;
+            let field = token.len() + image[at + token.len()..].iter().take_while(|&&b| b == 0).count();
+            if value.len() >= field {
+                return Err(format!("no room for the value of {} at {:#x}, reserve it with NUL bytes after the token", token, at));
+            }
+            image[at..at + value.len()].copy_from_slice(value.as_bytes());
+            image[at + value.len()..at + field].fill(0);
+            from = at + value.len().max(1);
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Replaces @NAME@ tokens in every image of a FIP, see substitute()
+pub fn substitute_fip(fip: &mut [u8], vars: &[(String, String)]) -> Result<usize, String> {
+    let toc = Fip::parse(fip)?;
+    toc.entries.iter()
+        .map(|e| substitute(&mut fip[e.offset..e.offset + e.size], vars)
+            .map_err(|err| format!("{}: {}", e.label(), err)))
+        .sum()
+}
+
+/// Bytes to write at an offset of an image, given as [IMAGE+]OFFSET:HEX[:EXPECTED]. IMAGE
+/// makes the offset relative to an image of a FIP, and EXPECTED are the bytes which must be
+/// there before.
+#[derive(Clone, Debug)]
+pub struct BytePatch {
+    pub image: Option<String>,
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+    pub expected: Option<Vec<u8>>,
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    if s.is_empty() || !s.len().is_multiple_of(2) {
+        return Err(format!("expected an even number of hex digits, got {}", s));
+    }
+    (0..s.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| format!("invalid hex bytes {}", s)))
+        .collect()
+}
+
+impl BytePatch {
+    pub fn parse(s: &str) -> Result<BytePatch, String> {
+        let mut parts = s.split(':');
+        let (Some(location), Some(bytes)) = (parts.next(), parts.next()) else {
+            return Err("expected [IMAGE+]OFFSET:HEX[:EXPECTED]".to_owned());
+        };
+        let (image, offset) = match location.rsplit_once('+') {
+            Some((image, offset)) => (Some(image.to_owned()), offset),
+            None => (None, location),
+        };
+        let offset = match offset.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16),
+            None => offset.parse(),
+        }.map_err(|_| format!("invalid offset {}", offset))?;
+        let bytes = parse_hex(bytes)?;
+        let expected = parts.next().map(parse_hex).transpose()?;
+        if expected.as_ref().is_some_and(|e| e.len() != bytes.len()) {
+            return Err("the expected bytes must be as many as the new ones".to_owned());
+        }
+        if parts.next().is_some() {
+            return Err("expected [IMAGE+]OFFSET:HEX[:EXPECTED]".to_owned());
+        }
+        Ok(BytePatch { image, offset, bytes, expected })
+    }
+
+    /// Writes the bytes into data, which is a FIP if the patch names an image of one
+    pub fn apply(&self, data: &mut [u8]) -> Result<(), String> {
+        let base = match &self.image {
+            Some(name) => {
+                let toc = Fip::parse(data)?;
+                let entry = toc.entries.iter().find(|e| e.label() == *name)
+                    .ok_or_else(|| format!("the FIP has no {}", name))?;
+                if self.offset.checked_add(self.bytes.len()).is_none_or(|end| end > entry.size) {
+                    return Err(format!("{:#x} is past the end of {}", self.offset, name));
+                }
+                entry.offset
+            }
+            None => 0,
+        };
+        let field = base.checked_add(self.offset)
+            .and_then(|at| Some(at..at.checked_add(self.bytes.len())?))
+            .and_then(|range| data.get_mut(range))
+            .ok_or_else(|| format!("{:#x} is past the end of the image", self.offset))?;
+        if let Some(expected) = &self.expected {
+            if field != expected.as_slice() {
+                let found: String = field.iter().map(|b| format!("{:02x}", b)).collect();
+                return Err(format!("expected different bytes at {:#x}, found {}", self.offset, found));
+            }
+        }
+        field.copy_from_slice(&self.bytes);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A FIP with a BL31 of len bytes of 0xaa
+    fn fip(len: usize) -> Vec<u8> {
+        let bl31: Vec<u8> = (0..16).map(|i| u8::from_str_radix(&"47d4086d4cfe98469b952950cbbd5a00"[i * 2..i * 2 + 2], 16).unwrap()).collect();
+        let offset = 16 + 2 * 40;
+        let mut data = 0xaa64_0001u32.to_le_bytes().to_vec();
+        data.resize(16, 0);
+        data.extend(&bl31);
+        data.extend((offset as u64).to_le_bytes());
+        data.extend((len as u64).to_le_bytes());
+        data.extend(0u64.to_le_bytes());
+        data.extend([0; 16]);
+        data.extend(((offset + len) as u64).to_le_bytes());
+        data.resize(offset, 0);
+        data.resize(offset + len, 0xaa);
+        data
+    }
+
+    #[test]
+    fn patches_are_written_into_images_of_a_fip() {
+        let mut data = fip(32);
+        BytePatch::parse("soc-fw+0x1e:0102:aaaa").unwrap().apply(&mut data).unwrap();
+        assert_eq!(&data[data.len() - 3..], [0xaa, 1, 2]);
+        let err = BytePatch::parse("soc-fw+0x1f:0102").unwrap().apply(&mut data).unwrap_err();
+        assert_eq!(err, "0x1f is past the end of soc-fw");
+    }
+
+    #[test]
+    fn patches_past_the_end_are_refused() {
+        let mut data = vec![0; 16];
+        let past = |s: &str, data: &mut [u8]| BytePatch::parse(s).unwrap().apply(data).unwrap_err();
+        assert_eq!(past("0xf:0102", &mut data), "0xf is past the end of the image");
+        assert_eq!(past("0xffffffffffffffff:01", &mut data), "0xffffffffffffffff is past the end of the image");
+        let mut data = fip(32);
+        assert_eq!(past("soc-fw+0xffffffffffffffff:01", &mut data), "0xffffffffffffffff is past the end of soc-fw");
+        assert_eq!(past("soc-fw+0xfffffffffffffff0:01", &mut data), "0xfffffffffffffff0 is past the end of soc-fw");
+    }
+}
@@ -0,0 +1,75 @@
+use std::io::BufRead;
+use clap::ValueEnum;
+use crate::hooks;
+use crate::i18n;
+
+/// Points of the boot flow where it can stop, e.g. so a JTAG/SWD debugger can attach and set
+/// breakpoints in a freshly loaded image before it runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PausePoint {
+    /// The payload is in SRAM, but the BootROM hasn't jumped to it yet
+    PayloadSent,
+    /// The FIP has been sent, but BL2 hasn't booted it yet
+    FipSent,
+}
+
+impl PausePoint {
+    fn name(self) -> &'static str {
+        match self {
+            PausePoint::PayloadSent => "payload-sent",
+            PausePoint::FipSent => "fip-sent",
+        }
+    }
+}
+
+/// Waits at the point if it was asked for: until the command exits, or the user presses
+/// Enter if there's no command.
+///
+/// The command gets the point in `MTK_UARTBOOT_PAUSE` and the load address of the payload
+/// in `MTK_UARTBOOT_LOAD_ADDR`, if it's known. It failing aborts the boot.
+pub fn pause(points: &[PausePoint], point: PausePoint, cmd: Option<&str>, load_addr: Option<u32>) {
+    if !points.contains(&point) {
+        return;
+    }
+    println!("{}", i18n::text("paused", &[&point.name()]));
+    match cmd {
+        Some(cmd) => {
+            let mut command = hooks::shell(cmd);
+            command.env("MTK_UARTBOOT_PAUSE", point.name());
+            if let Some(addr) = load_addr {
+                command.env("MTK_UARTBOOT_LOAD_ADDR", format!("{:#x}", addr));
+            }
+            let status = command
+                .status()
+                .expect("failed to run pause command.");
+            if !status.success() {
+                panic!("Pause command failed: {}", status);
+            }
+        }
+        None => {
+            println!("{}", i18n::text("press_enter", &[]));
+            let mut line = String::new();
+            std::io::stdin().lock().read_line(&mut line).expect("failed to read stdin.");
+        }
+    }
+    println!("{}", i18n::text("continuing", &[]));
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_gets_the_point_and_the_load_address() {
+        let check = "test \"$MTK_UARTBOOT_PAUSE\" = payload-sent && test \"$MTK_UARTBOOT_LOAD_ADDR\" = 0x201000";
+        pause(&[PausePoint::PayloadSent], PausePoint::PayloadSent, Some(check), Some(0x201000));
+        // Not asked for, so the failing command isn't run.
+        pause(&[PausePoint::PayloadSent], PausePoint::FipSent, Some("exit 1"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Pause command failed")]
+    fn failing_command_aborts_the_boot() {
+        pause(&[PausePoint::FipSent], PausePoint::FipSent, Some("exit 1"), None);
+    }
+}
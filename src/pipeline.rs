@@ -0,0 +1,669 @@
+use clap::Parser;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use regex::Regex;
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use mtk_uartboot::Session;
+use mtk_uartboot::secret::Secret;
+use crate::console::{ConsoleView, wait_for_any, wait_for_line, wait_for_prompt, wait_for_regex};
+use crate::{Args, ProfileArgs, boot_with};
+use crate::hooks;
+use crate::i18n;
+use crate::images;
+use crate::power::PowerSwitch;
+use crate::report::Report;
+use crate::trace;
+
+/// A bring-up pipeline, a list of steps run one after another on one serial port.
+///
+/// ```yaml
+/// serial: /dev/ttyUSB0
+/// reset: ./power-cycle.sh
+/// steps:
+///   - run: ./power-cycle.sh
+///   - boot: { payload: bl2.bin, aarch64: true, fip: bl31-uboot.fip }
+///     retry: { attempts: 2, reset: true }
+///   - detect: { fact: storage, patterns: { emmc: "MMC: ", nand: "SPI-NAND: " } }
+///   - expect: { pattern: "=> " }
+///   - send: mmc write 0x46000000 0x400 0x2000
+///     when: { storage: emmc }
+///   - expect: { pattern: "blocks written: OK", timeout: 60 }
+///     when: { storage: emmc }
+///   - send: reset
+///   - login: { user: root, password_env: DUT_PASSWORD, timeout: 120 }
+///   - command: { send: cat /sys/class/net/eth0/address, fact: mac, regex: "([0-9a-f:]{17})" }
+///   - command: { send: cat /etc/openwrt_version, fact: version, regex: "(?m)^(23\\.05\\.[0-9]+)" }
+/// report: "report-{port}.txt"
+/// label:
+///   template: "{unit_id} {mac} {date}"
+///   command: lp -d labels
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pipeline {
+    serial: Option<String>,
+    /// Host command resetting the device, run between attempts of steps with `reset: true`
+    reset: Option<String>,
+    #[serde(with = "serde_yaml::with::singleton_map_recursive")]
+    steps: Vec<Entry>,
+    /// Label printed once all steps succeeded
+    label: Option<Label>,
+    /// Path of a report with the facts, written whether the steps succeeded or not. {port}
+    /// and {unit_id} are substituted like in the --report of boot steps
+    report: Option<String>,
+    /// Whether boot steps may write to the storage, from --yes
+    #[serde(skip)]
+    yes: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Label {
+    /// Text of the label. {name} is replaced by the fact of that name, {port} by the serial
+    /// port and {date} by the current date
+    template: String,
+    /// Shell command the label is piped to, e.g. a label printer driver
+    command: String,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    /// Facts which must all match for the step to run
+    when: Option<Mapping>,
+    /// What to do if the step fails
+    retry: Option<Retry>,
+    #[serde(flatten)]
+    step: Step,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Step {
+    /// Runs a shell command on the host, e.g. to reset or power cycle the device
+    Run(String),
+    /// Boots the device. The keys are the long command line options
+    Boot(Mapping),
+    /// Waits for a console line containing the pattern
+    Expect(Expect),
+    /// Types a line into the console
+    Send(String),
+    /// Changes the baud rate of the serial port
+    Baudrate(u32),
+    /// Waits for one of the patterns and sets a fact to the name of the one which showed up
+    Detect(Detect),
+    /// Waits for a line matching a regex and sets a fact to its first group
+    Capture(Capture),
+    /// Switches the power of the device
+    Power(Power),
+    /// Logs into the shell of the booted system on the console
+    Login(Login),
+    /// Runs a command in the shell and optionally sets a fact to what it printed
+    Command(ConsoleCommand),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Expect {
+    pattern: String,
+    /// Seconds of console silence after which waiting is given up
+    #[serde(default = "default_expect_timeout")]
+    timeout: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Detect {
+    fact: String,
+    /// The names of the patterns and the patterns, in the order they're listed, the first
+    /// which shows up in a line wins
+    #[serde(deserialize_with = "ordered_patterns")]
+    patterns: Vec<(String, String)>,
+    #[serde(default = "default_expect_timeout")]
+    timeout: u64,
+}
+
+/// How often a failed step is tried again.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Retry {
+    /// Attempts after the first one
+    attempts: u32,
+    /// Seconds to wait before the first retry
+    #[serde(default = "default_retry_delay")]
+    delay: f64,
+    /// Factor by which the delay grows with every retry
+    #[serde(default = "default_retry_backoff")]
+    backoff: f64,
+    /// Run the reset command of the pipeline before every retry
+    #[serde(default)]
+    reset: bool,
+}
+
+fn default_retry_delay() -> f64 {
+    1.0
+}
+
+fn default_retry_backoff() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Capture {
+    fact: String,
+    regex: String,
+    #[serde(default = "default_expect_timeout")]
+    timeout: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Power {
+    /// URL of the switch, like --power
+    switch: String,
+    action: PowerAction,
+    /// Seconds the power stays off when cycling
+    #[serde(default = "default_off_time")]
+    off_time: f64,
+}
+
+fn default_off_time() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+enum PowerAction {
+    On,
+    Off,
+    Cycle,
+}
+
+fn default_expect_timeout() -> u64 {
+    30
+}
+
+/// Reads a map of patterns in the order it's written, a HashMap would lose it
+fn ordered_patterns<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<(String, String)>, D::Error> {
+    let map = Mapping::deserialize(deserializer)?;
+    map.into_iter().map(|(name, pattern)| match (name, pattern) {
+        (Value::String(name), Value::String(pattern)) => Ok((name, pattern)),
+        _ => Err(serde::de::Error::custom("patterns must map names to strings")),
+    }).collect()
+}
+
+/// What the console shows when asked for the password
+const PASSWORD_PROMPT: &str = "assword:";
+
+/// What getty shows after a wrong user name or password
+const LOGIN_INCORRECT: &str = "incorrect";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Login {
+    user: String,
+    /// Password, if the user has one. Better given in the environment than in the file
+    password: Option<String>,
+    /// Environment variable with the password
+    password_env: Option<String>,
+    /// What getty shows when asking for the user name
+    #[serde(default = "default_login_prompt")]
+    login_prompt: String,
+    /// What the shell shows when it's ready for a command
+    #[serde(default = "default_shell_prompt")]
+    prompt: String,
+    /// Seconds of console silence after which waiting for a prompt is given up
+    #[serde(default = "default_expect_timeout")]
+    timeout: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConsoleCommand {
+    /// Command line typed into the shell
+    send: String,
+    /// Fact set to the first group of regex, or all of the match if it has none
+    fact: Option<String>,
+    /// Regex the output of the command must match, the step fails if it doesn't
+    regex: Option<String>,
+    /// What the shell shows when the command is done
+    #[serde(default = "default_shell_prompt")]
+    prompt: String,
+    #[serde(default = "default_expect_timeout")]
+    timeout: u64,
+}
+
+fn default_login_prompt() -> String {
+    "login:".to_owned()
+}
+
+fn default_shell_prompt() -> String {
+    "# ".to_owned()
+}
+
+impl Step {
+    fn describe(&self) -> String {
+        match self {
+            Step::Run(cmd) => format!("run {}", cmd),
+            Step::Boot(_) => "boot".to_owned(),
+            Step::Expect(e) => format!("expect {:?}", e.pattern),
+            Step::Send(line) => format!("send {:?}", line),
+            Step::Baudrate(baudrate) => format!("baudrate {}", baudrate),
+            Step::Detect(d) => format!("detect {}", d.fact),
+            Step::Capture(c) => format!("capture {}", c.fact),
+            Step::Power(p) => format!("power {:?} {}", p.action, p.switch),
+            Step::Login(l) => format!("login {}", l.user),
+            Step::Command(c) => format!("command {:?}", c.send),
+        }
+    }
+}
+
+/// Turns the options of a boot step into a command line, so boot steps take exactly
+/// what the command line takes. Writing to the storage needs yes, from --yes on the command
+/// line, as nobody is asked before.
+pub fn boot_args(options: &Mapping, yes: bool) -> Result<Args, String> {
+    let mut argv = vec!["mtk_uartboot".to_owned()];
+    for (key, value) in options {
+        let key = match key {
+            Value::String(k) => format!("--{}", k.replace('_', "-")),
+            _ => return Err(format!("invalid boot option {:?}", key)),
+        };
+        let values = match value {
+            Value::Sequence(values) => values.clone(),
+            v => vec![v.clone()],
+        };
+        for value in values {
+            match value {
+                Value::Bool(true) => argv.push(key.clone()),
+                Value::Bool(false) => {}
+                Value::String(s) => argv.extend([key.clone(), s]),
+                Value::Number(n) => argv.extend([key.clone(), n.to_string()]),
+                v => return Err(format!("invalid value for {}: {:?}", key, v)),
+            }
+        }
+    }
+    let mut args = Args::from(ProfileArgs::try_parse_from(argv).map_err(|e| e.to_string())?);
+    if args.journal.is_some() {
+        return Err("journal isn't supported in pipelines".to_owned());
+    }
+    if args.yes {
+        return Err("yes can only be given on the command line".to_owned());
+    }
+    if args.image.is_some() && !yes {
+        return Err("image writes to the storage of the device, which needs --yes on the command line".to_owned());
+    }
+    args.yes = yes;
+    Ok(args)
+}
+
+impl Pipeline {
+    /// Reads the pipeline at path. Its boot steps may write to the storage if yes.
+    pub fn load(path: &str, yes: bool) -> Pipeline {
+        let text = std::fs::read_to_string(path).expect("failed to open pipeline.");
+        let mut pipeline: Pipeline = serde_yaml::from_str(&text)
+            .unwrap_or_else(|e| panic!("invalid pipeline {}: {}", path, e));
+        pipeline.yes = yes;
+        // Check all boot steps up front instead of failing halfway through.
+        for entry in &pipeline.steps {
+            if let Step::Boot(options) = &entry.step {
+                if let Err(e) = boot_args(options, yes) {
+                    panic!("invalid boot step in {}: {}", path, e);
+                }
+            }
+            if let Step::Power(power) = &entry.step {
+                if PowerSwitch::parse(&power.switch).is_none() || !power.off_time.is_finite() || power.off_time < 0.0 {
+                    panic!("invalid power step in {}", path);
+                }
+            }
+            if let Step::Capture(capture) = &entry.step {
+                if let Err(e) = Regex::new(&capture.regex) {
+                    panic!("invalid capture regex in {}: {}", path, e);
+                }
+            }
+            if let Step::Command(command) = &entry.step {
+                match &command.regex {
+                    Some(regex) => if let Err(e) = Regex::new(regex) {
+                        panic!("invalid command regex in {}: {}", path, e);
+                    },
+                    None if command.fact.is_some() => panic!("command with a fact but no regex in {}", path),
+                    None => {}
+                }
+            }
+            if let Step::Login(login) = &entry.step {
+                if login.password.is_some() && login.password_env.is_some() {
+                    panic!("login in {} has both password and password_env.", path);
+                }
+            }
+            if entry.retry.as_ref().is_some_and(|r| ![r.delay, r.backoff].iter().all(|v| v.is_finite() && *v >= 0.0)) {
+                panic!("retry delay and backoff in {} can't be negative.", path);
+            }
+            if entry.retry.as_ref().is_some_and(|r| r.reset) && pipeline.reset.is_none() {
+                panic!("retry with reset in {}, but the pipeline has no reset command.", path);
+            }
+        }
+        pipeline
+    }
+
+    /// The serial port given in the file, if any
+    pub fn serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+
+    /// Path of the report given in the file, if any
+    pub fn report(&self) -> Option<&str> {
+        self.report.as_deref()
+    }
+
+    /// Runs all steps and returns whether every one of them succeeded. The facts found out
+    /// about the device are left in facts.
+    pub fn run(&self, session: &mut Session, serial: &str, view: &mut ConsoleView, facts: &mut Facts) -> mtk_uartboot::Result<bool> {
+        self.prefetch();
+        for (i, entry) in self.steps.iter().enumerate() {
+            let step = &entry.step;
+            if let Some(when) = &entry.when {
+                if !facts.matches(when) {
+                    println!("{}", i18n::text("step_skipped", &[&(i + 1), &self.steps.len(), &step.describe()]));
+                    continue;
+                }
+            }
+            println!("{}", i18n::text("step", &[&(i + 1), &self.steps.len(), &step.describe()]));
+            if !self.run_with_retries(entry, session, serial, view, facts)? {
+                println!("{}", i18n::text("step_failed", &[&(i + 1)]));
+                return Ok(false);
+            }
+        }
+        if let Some(label) = &self.label {
+            if !print_label(label, serial, facts) {
+                return Ok(false);
+            }
+        }
+        println!("{}", i18n::text("pipeline_done", &[]));
+        Ok(true)
+    }
+
+    /// Reads the images of all boot steps ahead, while the steps before them run.
+    fn prefetch(&self) {
+        for entry in &self.steps {
+            if let Step::Boot(options) = &entry.step {
+                let args = boot_args(options, self.yes).unwrap();
+                let cache_dir = args.cache_dir.as_deref();
+                for path in [&args.payload, &args.fip].into_iter().flatten().chain(args.load.iter().map(|(_, path)| path)) {
+                    images::prefetch(path, cache_dir);
+                }
+                #[cfg(feature = "ymodem")]
+                if let Some(path) = &args.ymodem {
+                    images::prefetch(path, cache_dir);
+                }
+            }
+        }
+    }
+
+    fn run_with_retries(&self, entry: &Entry, session: &mut Session, serial: &str, view: &mut ConsoleView, facts: &mut Facts) -> mtk_uartboot::Result<bool> {
+        let retry = match &entry.retry {
+            Some(r) => r,
+            None => return run_step(&entry.step, self.yes, session, serial, view, facts),
+        };
+        let mut delay = retry.delay;
+        for attempt in 0..=retry.attempts {
+            if attempt > 0 {
+                println!("{}", i18n::text("retrying", &[&delay, &(attempt + 1), &(retry.attempts + 1)]));
+                std::thread::sleep(Duration::from_secs_f64(delay));
+                delay *= retry.backoff;
+                if retry.reset && !hooks::run_hook(self.reset.as_deref().unwrap(), serial, None) {
+                    return Ok(false);
+                }
+            }
+            // Errors count as failed attempts, a retry is what they're there for.
+            match run_step(&entry.step, self.yes, session, serial, view, facts) {
+                Ok(true) => return Ok(true),
+                Ok(false) => {}
+                Err(e) => println!("{}", i18n::text("error", &[&e])),
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// What the pipeline found out about the device so far, e.g. its hw code.
+#[derive(Default)]
+pub struct Facts(HashMap<String, String>);
+
+fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+impl Facts {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    pub fn set(&mut self, name: &str, value: String) {
+        println!("Pipeline fact: {}={}", name, value);
+        self.0.insert(name.to_owned(), value);
+    }
+
+    fn value_matches(fact: &str, expected: &Value) -> bool {
+        match expected {
+            Value::Sequence(values) => values.iter().any(|v| Facts::value_matches(fact, v)),
+            Value::Number(n) => n.as_u64().is_some() && parse_number(fact) == n.as_u64(),
+            Value::String(s) => match (parse_number(fact), parse_number(s)) {
+                (Some(a), Some(b)) => a == b,
+                _ => fact == s,
+            },
+            Value::Bool(b) => fact == b.to_string(),
+            _ => false,
+        }
+    }
+
+    /// Adds the facts to report as fact.NAME, sorted by name so reports of the same device
+    /// are identical.
+    pub fn add_to(&self, report: &mut Report) {
+        let mut facts: Vec<_> = self.0.iter().collect();
+        facts.sort();
+        for (name, value) in facts {
+            report.set(&format!("fact.{}", name), value);
+        }
+    }
+
+    /// Whether every fact named in the condition is known and has one of the given values
+    fn matches(&self, condition: &Mapping) -> bool {
+        condition.iter().all(|(name, expected)| {
+            let fact = name.as_str().and_then(|n| self.0.get(n));
+            fact.is_some_and(|f| Facts::value_matches(f, expected))
+        })
+    }
+}
+
+fn run_step(step: &Step, yes: bool, session: &mut Session, serial: &str, view: &mut ConsoleView, facts: &mut Facts) -> mtk_uartboot::Result<bool> {
+    match step {
+        Step::Run(cmd) => Ok(hooks::run_hook(cmd, serial, None)),
+        Step::Boot(options) => {
+            let args = boot_args(options, yes).unwrap();
+            let (ok, device) = boot_with(&args, session, view, serial, facts.get("unit_id"))?;
+            for (name, value) in device {
+                facts.set(name, value);
+            }
+            Ok(ok)
+        }
+        Step::Power(power) => {
+            let switch = PowerSwitch::parse(&power.switch).unwrap();
+            let result = match power.action {
+                PowerAction::On => switch.set(true),
+                PowerAction::Off => switch.set(false),
+                PowerAction::Cycle => switch.cycle(Duration::from_secs_f64(power.off_time)),
+            };
+            if let Err(e) = &result {
+                println!("Power switch failed: {}", e);
+            }
+            Ok(result.is_ok())
+        }
+        Step::Capture(capture) => {
+            let re = Regex::new(&capture.regex).unwrap();
+            let port = session.transport();
+            port.set_timeout(Duration::from_secs(capture.timeout))?;
+            let line = wait_for_regex(port, &re, view);
+            port.set_timeout(Duration::from_secs(2))?;
+            let value = line.as_deref()
+                .and_then(|l| re.captures(l))
+                .and_then(|c| c.get(1).or(c.get(0)))
+                .map(|m| m.as_str().to_owned());
+            match value {
+                Some(value) => {
+                    facts.set(&capture.fact, value);
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        }
+        Step::Detect(detect) => {
+            let patterns: Vec<&str> = detect.patterns.iter().map(|(_, p)| p.as_str()).collect();
+            let port = session.transport();
+            port.set_timeout(Duration::from_secs(detect.timeout))?;
+            let found = wait_for_any(port, &patterns, view);
+            port.set_timeout(Duration::from_secs(2))?;
+            match found {
+                Some(i) => {
+                    facts.set(&detect.fact, detect.patterns[i].0.clone());
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        }
+        Step::Expect(expect) => {
+            let port = session.transport();
+            port.set_timeout(Duration::from_secs(expect.timeout))?;
+            let found = wait_for_line(port, &expect.pattern, view);
+            port.set_timeout(Duration::from_secs(2))?;
+            Ok(found)
+        }
+        Step::Login(login) => {
+            let password = match (&login.password, &login.password_env) {
+                (_, Some(var)) => match std::env::var(var) {
+                    Ok(password) => Some(Secret::new(password)),
+                    Err(_) => {
+                        println!("No password in the environment variable {}.", var);
+                        return Ok(false);
+                    }
+                },
+                (password, None) => password.clone().map(Secret::new),
+            };
+            let timeout = Duration::from_secs(login.timeout);
+            let port = session.transport();
+            // A new line gets a new prompt, also when it was printed before this step.
+            port.write_all(b"\n")?;
+            let logged_in = match wait_for_prompt(port, &[&login.login_prompt, &login.prompt], timeout, view)? {
+                Some((0, _)) => {
+                    println!("Logging in as {}...", login.user);
+                    port.write_all(format!("{}\n", login.user).as_bytes())?;
+                    match wait_for_prompt(port, &[&login.prompt, PASSWORD_PROMPT, LOGIN_INCORRECT], timeout, view)? {
+                        Some((0, _)) => true,
+                        Some((1, _)) => match password {
+                            Some(password) => {
+                                let hidden = trace::hide(serial);
+                                port.write_all(format!("{}\n", password.expose()).as_bytes())?;
+                                drop(hidden);
+                                matches!(wait_for_prompt(port, &[&login.prompt, LOGIN_INCORRECT], timeout, view)?, Some((0, _)))
+                            }
+                            None => {
+                                println!("The console asks for a password, but the login step has none.");
+                                false
+                            }
+                        },
+                        _ => false,
+                    }
+                }
+                // Still logged in from before
+                Some(_) => true,
+                None => false,
+            };
+            port.set_timeout(Duration::from_secs(2))?;
+            if !logged_in {
+                println!("Login as {} failed.", login.user);
+            }
+            Ok(logged_in)
+        }
+        Step::Command(command) => {
+            let port = session.transport();
+            port.write_all(format!("{}\n", command.send).as_bytes())?;
+            let found = wait_for_prompt(port, &[&command.prompt], Duration::from_secs(command.timeout), view)?;
+            port.set_timeout(Duration::from_secs(2))?;
+            let Some((_, output)) = found else {
+                return Ok(false);
+            };
+            let Some(regex) = &command.regex else {
+                return Ok(true);
+            };
+            // The first line is the command echoed back, the last one the next prompt.
+            let lines: Vec<&str> = output.lines().map(|l| l.trim_end_matches('\r')).collect();
+            let output = lines.get(1..lines.len().saturating_sub(1)).unwrap_or_default().join("\n");
+            let re = Regex::new(regex).unwrap();
+            let value = re.captures(&output)
+                .and_then(|c| c.get(1).or(c.get(0)))
+                .map(|m| m.as_str().to_owned());
+            match (value, &command.fact) {
+                (Some(value), Some(fact)) => facts.set(fact, value),
+                (Some(_), None) => {}
+                (None, _) => {
+                    println!("The output of {:?} doesn't match {:?}.", command.send, regex);
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        Step::Send(line) => {
+            session.transport().write_all(format!("{}\n", line).as_bytes())?;
+            Ok(true)
+        }
+        Step::Baudrate(baudrate) => {
+            session.transport().set_baud_rate(*baudrate)?;
+            Ok(true)
+        }
+    }
+}
+
+/// Today's date as YYYY-MM-DD in UTC
+fn date() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() / 86400) as i64;
+    // Civil from days, see https://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn print_label(label: &Label, serial: &str, facts: &Facts) -> bool {
+    let mut text = label.template
+        .replace("{port}", serial)
+        .replace("{date}", &date());
+    for (name, value) in &facts.0 {
+        text = text.replace(&format!("{{{}}}", name), value);
+    }
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    println!("Printing label:\n{}", text.trim_end());
+    hooks::pipe_to_hook(&label.command, serial, text.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_patterns_keep_their_order() {
+        let detect: Detect = serde_yaml::from_str(r#"{ fact: storage, patterns: { nand: "SPI-NAND: ", emmc: "MMC: ", nor: "SF: ", sd: "SD: " } }"#).unwrap();
+        let names: Vec<&str> = detect.patterns.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["nand", "emmc", "nor", "sd"]);
+        assert!(serde_yaml::from_str::<Detect>("{ fact: storage, patterns: { emmc: 3 } }").is_err());
+    }
+}
@@ -0,0 +1,51 @@
+use std::path::{Path, PathBuf};
+
+/// Executables named mtk_uartboot-NAME on PATH run as `mtk_uartboot NAME`, like cargo's
+const PREFIX: &str = "mtk_uartboot-";
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata().is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+fn file_name(name: &str) -> String {
+    format!("{}{}{}", PREFIX, name, std::env::consts::EXE_SUFFIX)
+}
+
+/// The executable of the plugin NAME, the first one on PATH
+pub fn find(name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) {
+        return None;
+    }
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(file_name(name)))
+        .find(|candidate| is_executable(candidate))
+}
+
+/// The names of the plugins on PATH, sorted
+pub fn list() -> Vec<String> {
+    let Some(path) = std::env::var_os("PATH") else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = std::env::split_paths(&path)
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| is_executable(&entry.path()))
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let name = name.strip_prefix(PREFIX)?.strip_suffix(std::env::consts::EXE_SUFFIX)?;
+            (!name.is_empty()).then(|| name.to_owned())
+        })
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
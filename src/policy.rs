@@ -0,0 +1,132 @@
+use std::fs;
+use crate::{EXIT_POLICY, fail};
+
+/// Restrictions on which devices this installation may boot and with which images.
+///
+/// The file has one `key=value` entry per line, `#` starts a comment:
+///
+/// ```text
+/// allow_hw_code=0x7986
+/// deny_hw_code=0x7981
+/// allow_soc_id=<hex SoC ID>
+/// deny_soc_id=<hex SoC ID>
+/// payload_sha256=<hex digest>
+/// fip_sha256=<hex digest>
+/// ```
+///
+/// Every key can be repeated. If any `allow_hw_code` or `allow_soc_id` is given, only those
+/// hw codes or SoC IDs are allowed. If any image digest is given, only images with one of the
+/// listed digests are sent.
+#[derive(Default)]
+pub struct Policy {
+    allow_hw_codes: Vec<u16>,
+    deny_hw_codes: Vec<u16>,
+    allow_soc_ids: Vec<String>,
+    deny_soc_ids: Vec<String>,
+    payload_sha256: Vec<String>,
+    fip_sha256: Vec<String>,
+}
+
+fn parse_hw_code(value: &str) -> Option<u16> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+impl Policy {
+    pub fn load(path: &str) -> Policy {
+        Policy::parse(&fs::read_to_string(path).expect("failed to open policy file."))
+    }
+
+    fn parse(text: &str) -> Policy {
+        let mut policy = Policy::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once('=')
+                .unwrap_or_else(|| panic!("policy line {}: expected key=value.", n + 1));
+            let (key, value) = (key.trim(), value.trim());
+            match key {
+                "allow_hw_code" | "deny_hw_code" => {
+                    let hw_code = parse_hw_code(value)
+                        .unwrap_or_else(|| panic!("policy line {}: invalid hw code {}.", n + 1, value));
+                    if key == "allow_hw_code" {
+                        policy.allow_hw_codes.push(hw_code);
+                    } else {
+                        policy.deny_hw_codes.push(hw_code);
+                    }
+                }
+                "allow_soc_id" | "deny_soc_id" => {
+                    let hex = value.strip_prefix("0x").unwrap_or(value);
+                    if hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                        panic!("policy line {}: invalid SoC ID {}.", n + 1, value);
+                    }
+                    let soc_ids = if key == "allow_soc_id" { &mut policy.allow_soc_ids } else { &mut policy.deny_soc_ids };
+                    soc_ids.push(hex.to_ascii_lowercase());
+                }
+                "payload_sha256" => policy.payload_sha256.push(value.to_ascii_lowercase()),
+                "fip_sha256" => policy.fip_sha256.push(value.to_ascii_lowercase()),
+                _ => panic!("policy line {}: unknown key {}.", n + 1, key),
+            }
+        }
+        policy
+    }
+
+    pub fn check_hw_code(&self, hw_code: u16) {
+        if self.deny_hw_codes.contains(&hw_code)
+            || (!self.allow_hw_codes.is_empty() && !self.allow_hw_codes.contains(&hw_code)) {
+            fail(EXIT_POLICY, format!("hw code {:#x} is not allowed by the policy.", hw_code));
+        }
+    }
+
+    /// Whether the SoC ID of the device has to be read for the policy
+    pub fn checks_soc_id(&self) -> bool {
+        !self.allow_soc_ids.is_empty() || !self.deny_soc_ids.is_empty()
+    }
+
+    /// Checks the SoC ID the BootROM told in hex, None if it doesn't tell it
+    pub fn check_soc_id(&self, soc_id: Option<&str>) {
+        if !self.checks_soc_id() {
+            return;
+        }
+        let Some(soc_id) = soc_id else {
+            fail(EXIT_POLICY, "the BootROM doesn't tell the SoC ID, which the policy restricts.".to_owned());
+        };
+        if self.deny_soc_ids.iter().any(|id| id == soc_id)
+            || (!self.allow_soc_ids.is_empty() && !self.allow_soc_ids.iter().any(|id| id == soc_id)) {
+            fail(EXIT_POLICY, format!("SoC ID {} is not allowed by the policy.", soc_id));
+        }
+    }
+
+    pub fn check_payload(&self, sha256: &str) {
+        if !self.payload_sha256.is_empty() && !self.payload_sha256.iter().any(|d| d == sha256) {
+            fail(EXIT_POLICY, format!("payload with SHA-256 {} is not allowed by the policy.", sha256));
+        }
+    }
+
+    pub fn check_fip(&self, sha256: &str) {
+        if !self.fip_sha256.is_empty() && !self.fip_sha256.iter().any(|d| d == sha256) {
+            fail(EXIT_POLICY, format!("FIP with SHA-256 {} is not allowed by the policy.", sha256));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::catch_unwind;
+
+    #[test]
+    fn soc_ids_are_allowed_and_denied() {
+        let policy = Policy::parse("allow_soc_id=0xA1B2C3\nallow_soc_id=d4e5f6\ndeny_soc_id=d4e5f6\n");
+        assert!(policy.checks_soc_id());
+        policy.check_soc_id(Some("a1b2c3"));
+        assert!(catch_unwind(|| policy.check_soc_id(Some("d4e5f6"))).is_err());
+        assert!(catch_unwind(|| policy.check_soc_id(Some("000000"))).is_err());
+        assert!(catch_unwind(|| policy.check_soc_id(None)).is_err());
+        Policy::parse("allow_hw_code=0x7986\n").check_soc_id(None);
+    }
+}
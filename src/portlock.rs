@@ -0,0 +1,284 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use mtk_uartboot::Transport;
+
+static STEAL: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// The locks taken on this thread, whose stage set_stage updates
+    static HELD: RefCell<Vec<Weak<PortLock>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Takes over ports locked by running processes too, for --steal
+pub fn steal() {
+    STEAL.store(true, Ordering::Relaxed);
+}
+
+/// Who holds a port
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Owner {
+    pub pid: u32,
+    pub stage: String,
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PID {} ({} stage)", self.pid, self.stage)
+    }
+}
+
+impl Owner {
+    fn parse(text: &str) -> Option<Owner> {
+        let mut pid = None;
+        let mut stage = String::new();
+        for line in text.lines() {
+            match line.split_once('=') {
+                Some(("pid", value)) => pid = value.parse().ok(),
+                Some(("stage", value)) => stage = value.to_owned(),
+                _ => {}
+            }
+        }
+        Some(Owner { pid: pid?, stage })
+    }
+
+    fn to_text(&self) -> String {
+        format!("pid={}\nstage={}\n", self.pid, self.stage)
+    }
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    // Signal 0 only checks that the process exists. EPERM means it does, as another user.
+    let alive = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+    alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
+
+/// /run/lock where it can be written to, the temporary directory otherwise
+fn lock_dir() -> PathBuf {
+    #[cfg(unix)]
+    {
+        let run_lock = Path::new("/run/lock");
+        let path = std::ffi::CString::new("/run/lock").unwrap();
+        if run_lock.is_dir() && unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0 {
+            return run_lock.to_owned();
+        }
+    }
+    std::env::temp_dir()
+}
+
+/// The file of the lock of port. Names of the same device, e.g. a /dev/serial/by-id link
+/// and the /dev/ttyUSB0 it points to, get the same lock.
+fn lock_path(port: &str) -> PathBuf {
+    let port = fs::canonicalize(port).map_or_else(|_| port.to_owned(), |path| path.to_string_lossy().into_owned());
+    let name: String = port.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    lock_dir().join(format!("mtk_uartboot-{}.lock", name))
+}
+
+/// Whoever else holds the lock of port, if it's held
+pub fn owner(port: &str) -> Option<Owner> {
+    let owner = Owner::parse(&fs::read_to_string(lock_path(port)).ok()?)?;
+    (owner.pid != std::process::id() && is_running(owner.pid)).then_some(owner)
+}
+
+/// Writes the file of a lock whole, so nobody reads it half written
+fn write_atomic(path: &Path, owner: &Owner) -> io::Result<PathBuf> {
+    let tmp = path.with_extension(format!("{}.tmp", owner.pid));
+    fs::write(&tmp, owner.to_text())?;
+    Ok(tmp)
+}
+
+/// A cooperative lock file on a serial port, so a second run on a port in use says who has
+/// it instead of both interleaving their bytes. The file names the PID holding the port and
+/// the stage it's in. Released when dropped, files of processes which are gone are stale and
+/// taken over.
+pub struct PortLock {
+    port: String,
+    path: PathBuf,
+    /// Set once another process took the port over with --steal
+    stolen: AtomicBool,
+}
+
+impl PortLock {
+    /// Locks port. Fails with the owner if another running process has it, unless --steal
+    /// is given. If the lock file can't be written, e.g. for lack of permissions, the port is
+    /// used without.
+    pub fn acquire(port: &str) -> Result<Option<Arc<PortLock>>, Owner> {
+        let path = lock_path(port);
+        let me = Owner { pid: std::process::id(), stage: "BootROM".to_owned() };
+        let tmp = match write_atomic(&path, &me) {
+            Ok(tmp) => tmp,
+            Err(e) => {
+                println!("Can't lock {} ({}), other runs won't see it's in use.", port, e);
+                return Ok(None);
+            }
+        };
+        let result = loop {
+            match fs::hard_link(&tmp, &path) {
+                Ok(()) => break Ok(true),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    println!("Can't lock {} ({}), other runs won't see it's in use.", port, e);
+                    break Ok(false);
+                }
+            }
+            match fs::read_to_string(&path).ok().and_then(|text| Owner::parse(&text)) {
+                Some(owner) if is_running(owner.pid) => {
+                    if !STEAL.load(Ordering::Relaxed) {
+                        break Err(owner);
+                    }
+                    println!("Taking {} over from {}.", port, owner);
+                }
+                // Left by a run which is gone, e.g. one which exited on an error
+                _ => {}
+            }
+            if let Err(e) = fs::remove_file(&path) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    println!("Can't lock {} ({}), other runs won't see it's in use.", port, e);
+                    break Ok(false);
+                }
+            }
+        };
+        fs::remove_file(&tmp).ok();
+        if !result? {
+            return Ok(None);
+        }
+        let lock = Arc::new(PortLock {
+            port: port.to_owned(),
+            path,
+            stolen: AtomicBool::new(false),
+        });
+        HELD.with(|held| {
+            let mut held = held.borrow_mut();
+            held.retain(|lock| lock.strong_count() > 0);
+            held.push(Arc::downgrade(&lock));
+        });
+        Ok(Some(lock))
+    }
+
+    /// Whether the file still names this process
+    fn is_ours(&self) -> bool {
+        let ours = fs::read_to_string(&self.path).ok()
+            .and_then(|text| Owner::parse(&text))
+            .is_some_and(|owner| owner.pid == std::process::id());
+        if !ours && !self.stolen.swap(true, Ordering::Relaxed) {
+            println!("Another run took {} over, its bytes may get mixed with these.", self.port);
+        }
+        ours
+    }
+
+    fn set_stage(&self, stage: &str) {
+        if !self.is_ours() {
+            return;
+        }
+        let me = Owner { pid: std::process::id(), stage: stage.to_owned() };
+        if let Ok(tmp) = write_atomic(&self.path, &me) {
+            if fs::rename(&tmp, &self.path).is_err() {
+                fs::remove_file(&tmp).ok();
+            }
+        }
+    }
+}
+
+impl Drop for PortLock {
+    fn drop(&mut self) {
+        if self.is_ours() {
+            fs::remove_file(&self.path).ok();
+        }
+    }
+}
+
+/// Records the stage the ports locked on this thread are in, shown to runs finding them busy
+pub fn set_stage(stage: &str) {
+    HELD.with(|held| {
+        for lock in held.borrow().iter().filter_map(Weak::upgrade) {
+            lock.set_stage(stage);
+        }
+    });
+}
+
+/// A transport holding the lock of its port while it's open
+struct Locked {
+    inner: Box<dyn Transport>,
+    _lock: Arc<PortLock>,
+}
+
+/// Wraps port so the lock is released once it's closed
+pub fn wrap(lock: Option<Arc<PortLock>>, port: Box<dyn Transport>) -> Box<dyn Transport> {
+    match lock {
+        Some(lock) => Box::new(Locked { inner: port, _lock: lock }),
+        None => port,
+    }
+}
+
+impl Read for Locked {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for Locked {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Transport for Locked {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.inner.set_timeout(timeout)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.inner.set_write_timeout(timeout)
+    }
+
+    fn read_timeout(&self) -> Option<Duration> {
+        self.inner.read_timeout()
+    }
+
+    fn set_baud_rate(&mut self, baudrate: u32) -> io::Result<()> {
+        self.inner.set_baud_rate(baudrate)
+    }
+
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.inner.clear_input()
+    }
+
+    fn drain(&mut self) -> io::Result<()> {
+        self.inner.drain()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn links_to_a_port_share_its_lock() {
+        let dir = std::env::temp_dir().join(format!("mtk_uartboot-portlock-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let port = dir.join("ttyUSB0");
+        let link = dir.join("usb-FTDI_FT232R-if00-port0");
+        fs::write(&port, b"").unwrap();
+        fs::remove_file(&link).ok();
+        std::os::unix::fs::symlink(&port, &link).unwrap();
+        assert_eq!(lock_path(&link.to_string_lossy()), lock_path(&port.to_string_lossy()));
+        assert_ne!(lock_path("tcp://host:2000"), lock_path(&port.to_string_lossy()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,168 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// How long a power switch has to accept the connection, and then to answer
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A power switch the device is connected to, given as a URL:
+///
+/// * `tasmota://host/N`: relay N of a Tasmota device
+/// * `shelly://host/N`: relay N of a Shelly (Gen1 API)
+/// * `hidrelay:///dev/hidrawX/N`: relay N of a USB HID relay board (dcttech and compatibles)
+/// * `http://host/path`: any HTTP API, e.g. of a networked PDU. `{state}` in the URL is
+///   replaced with `on` or `off`
+pub enum PowerSwitch {
+    Tasmota { host: String, relay: u8 },
+    Shelly { host: String, relay: u8 },
+    HidRelay { device: String, relay: u8 },
+    Http { url: String },
+}
+
+fn split_relay(rest: &str) -> Option<(String, u8)> {
+    let (path, relay) = rest.rsplit_once('/')?;
+    Some((path.to_owned(), relay.parse().ok()?))
+}
+
+impl PowerSwitch {
+    pub fn parse(url: &str) -> Option<PowerSwitch> {
+        let (scheme, rest) = url.split_once("://")?;
+        match scheme {
+            "tasmota" => split_relay(rest).map(|(host, relay)| PowerSwitch::Tasmota { host, relay }),
+            "shelly" => split_relay(rest).map(|(host, relay)| PowerSwitch::Shelly { host, relay }),
+            "hidrelay" => split_relay(rest).map(|(device, relay)| PowerSwitch::HidRelay { device, relay }),
+            "http" => Some(PowerSwitch::Http { url: url.to_owned() }),
+            _ => None,
+        }
+    }
+
+    pub fn set(&self, on: bool) -> io::Result<()> {
+        let state = if on { "on" } else { "off" };
+        match self {
+            PowerSwitch::Tasmota { host, relay } =>
+                http_get(host, &format!("/cm?cmnd=Power{}%20{}", relay, state)),
+            PowerSwitch::Shelly { host, relay } =>
+                http_get(host, &format!("/relay/{}?turn={}", relay, state)),
+            PowerSwitch::HidRelay { device, relay } => hid_relay_set(device, *relay, on),
+            PowerSwitch::Http { url } => {
+                let url = url.replace("{state}", state);
+                let rest = url.trim_start_matches("http://");
+                let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+                http_get(host, if path.is_empty() { "/" } else { path })
+            }
+        }
+    }
+
+    /// Switches off, waits for off_time and switches on again
+    pub fn cycle(&self, off_time: Duration) -> io::Result<()> {
+        self.set(false)?;
+        std::thread::sleep(off_time);
+        self.set(true)
+    }
+}
+
+/// Sends a plain HTTP GET request and checks for a 2xx status.
+fn http_get(host: &str, path: &str) -> io::Result<()> {
+    let addr = if host.contains(':') { host.to_owned() } else { format!("{}:80", host) };
+    let mut stream = connect(&addr)?;
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+    stream.write_all(format!("GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host).as_bytes())?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let status_line = String::from_utf8_lossy(&response);
+    let status = status_line.split_whitespace().nth(1).unwrap_or("");
+    if status.starts_with('2') {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("power switch returned HTTP status {}", status)))
+    }
+}
+
+/// Connects to the first address of addr which accepts within the timeout, a switch which is
+/// off the network doesn't stall the boot
+fn connect(addr: &str) -> io::Result<TcpStream> {
+    let mut last_err = io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", addr));
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, HTTP_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_err = e,
+        }
+    }
+    Err(last_err)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn hid_relay_set(device: &str, relay: u8, on: bool) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    // HIDIOCSFEATURE(9): _IOC(_IOC_READ | _IOC_WRITE, 'H', 0x06, 9)
+    const HIDIOCSFEATURE_9: libc::Ioctl = 0xc009_4806_u32 as libc::Ioctl;
+    let file = std::fs::OpenOptions::new().read(true).write(true).open(device)?;
+    let mut report = [0u8; 9];
+    report[1] = if on { 0xff } else { 0xfd };
+    report[2] = relay;
+    // SAFETY: the report buffer has the 9 bytes the request code says.
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), HIDIOCSFEATURE_9, report.as_mut_ptr()) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn hid_relay_set(_device: &str, _relay: u8, _on: bool) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "USB HID relays are only supported on Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn urls_are_parsed() {
+        assert!(matches!(PowerSwitch::parse("tasmota://10.0.0.5/2"),
+                         Some(PowerSwitch::Tasmota { host, relay: 2 }) if host == "10.0.0.5"));
+        assert!(matches!(PowerSwitch::parse("shelly://shelly1.lan:8080/0"),
+                         Some(PowerSwitch::Shelly { host, relay: 0 }) if host == "shelly1.lan:8080"));
+        assert!(matches!(PowerSwitch::parse("hidrelay:///dev/hidraw3/1"),
+                         Some(PowerSwitch::HidRelay { device, relay: 1 }) if device == "/dev/hidraw3"));
+        assert!(matches!(PowerSwitch::parse("http://pdu/outlet/4?state={state}"),
+                         Some(PowerSwitch::Http { url }) if url == "http://pdu/outlet/4?state={state}"));
+        for url in ["tasmota://10.0.0.5", "tasmota://10.0.0.5/x", "shelly://host/256", "https://pdu/on", "pdu/on"] {
+            assert!(PowerSwitch::parse(url).is_none(), "{}", url);
+        }
+    }
+
+    /// Answers one request with status and returns its request line
+    fn serve(status: &'static str) -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 512];
+            while !request.ends_with(b"\r\n\r\n") {
+                let len = stream.read(&mut buf).unwrap();
+                assert!(len > 0, "the request ended early");
+                request.extend_from_slice(&buf[..len]);
+            }
+            write!(stream, "HTTP/1.0 {}\r\n\r\n", status).unwrap();
+            String::from_utf8_lossy(&request).lines().next().unwrap().to_owned()
+        });
+        (addr, server)
+    }
+
+    #[test]
+    fn state_is_sent_to_http_switches() {
+        let (addr, server) = serve("200 OK");
+        let switch = PowerSwitch::parse(&format!("http://{}/outlet/4?state={{state}}", addr)).unwrap();
+        switch.set(false).unwrap();
+        assert_eq!(server.join().unwrap(), "GET /outlet/4?state=off HTTP/1.0");
+
+        let (addr, server) = serve("500 Internal Server Error");
+        let err = PowerSwitch::parse(&format!("tasmota://{}/1", addr)).unwrap().set(true).unwrap_err();
+        assert_eq!(err.to_string(), "power switch returned HTTP status 500");
+        assert_eq!(server.join().unwrap(), "GET /cm?cmnd=Power1%20on HTTP/1.0");
+    }
+}
@@ -0,0 +1,252 @@
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use mtk_uartboot::Transport;
+
+/// Quirks of the UART bridge of a debug probe, found by the USB VID:PID of its serial port.
+pub struct ProbeProfile {
+    pub name: &'static str,
+    ids: &'static [(u16, u16)],
+    /// Largest write the bridge takes at once without dropping bytes
+    pub chunk_len: usize,
+    /// Pause after each chunk, for bridges which accept data faster than they send it
+    pub chunk_gap: Duration,
+    /// Shortest read timeout, for bridges which hold received bytes back for a fixed latency.
+    /// The BootROM handshake waits only 10 ms for each answer otherwise.
+    pub min_timeout: Duration,
+}
+
+const PROBES: &[ProbeProfile] = &[
+    // Raspberry Pi Debug Probe, and Picos running debugprobe or the older picoprobe
+    ProbeProfile {
+        name: "Raspberry Pi debugprobe",
+        ids: &[(0x2e8a, 0x000c), (0x2e8a, 0x0004)],
+        chunk_len: 256,
+        chunk_gap: Duration::from_millis(1),
+        min_timeout: Duration::from_millis(50),
+    },
+    // DAPLink firmware, e.g. on NXP, micro:bit and many vendor boards
+    ProbeProfile {
+        name: "DAPLink",
+        ids: &[(0x0d28, 0x0204)],
+        chunk_len: 64,
+        chunk_gap: Duration::from_millis(2),
+        min_timeout: Duration::from_millis(50),
+    },
+    ProbeProfile {
+        name: "ST-LINK",
+        ids: &[(0x0483, 0x374b), (0x0483, 0x374e), (0x0483, 0x374f), (0x0483, 0x3752), (0x0483, 0x3753), (0x0483, 0x3754)],
+        chunk_len: 1024,
+        chunk_gap: Duration::ZERO,
+        min_timeout: Duration::from_millis(30),
+    },
+    ProbeProfile {
+        name: "Black Magic Probe",
+        ids: &[(0x1d50, 0x6018)],
+        chunk_len: 64,
+        chunk_gap: Duration::ZERO,
+        min_timeout: Duration::from_millis(30),
+    },
+    ProbeProfile {
+        name: "SEGGER J-Link",
+        ids: &[(0x1366, 0x0105), (0x1366, 0x1015), (0x1366, 0x1024), (0x1366, 0x1051), (0x1366, 0x1061)],
+        chunk_len: 512,
+        chunk_gap: Duration::ZERO,
+        min_timeout: Duration::from_millis(30),
+    },
+];
+
+/// A USB to UART bridge chip, found by the USB VID:PID of its serial port, and the fastest
+/// baud rate it runs at.
+pub struct Bridge {
+    pub name: &'static str,
+    ids: &'static [(u16, u16)],
+    /// Start of the USB product string, for chips sharing their IDs
+    product: Option<&'static str>,
+    pub max_baudrate: u32,
+}
+
+const BRIDGES: &[Bridge] = &[
+    Bridge { name: "CH340", ids: &[(0x1a86, 0x7523)], product: None, max_baudrate: 2_000_000 },
+    Bridge { name: "CH343", ids: &[(0x1a86, 0x55d3)], product: None, max_baudrate: 6_000_000 },
+    Bridge { name: "CH9102", ids: &[(0x1a86, 0x55d4)], product: None, max_baudrate: 4_000_000 },
+    Bridge { name: "FT232R", ids: &[(0x0403, 0x6001)], product: None, max_baudrate: 3_000_000 },
+    Bridge { name: "FT230X/FT231X", ids: &[(0x0403, 0x6015)], product: None, max_baudrate: 3_000_000 },
+    Bridge { name: "FT2232H/FT4232H/FT232H", ids: &[(0x0403, 0x6010), (0x0403, 0x6011), (0x0403, 0x6014)], product: None, max_baudrate: 12_000_000 },
+    // The CP210x share their IDs, only the product string tells them apart.
+    Bridge { name: "CP2102N", ids: &[(0x10c4, 0xea60)], product: Some("CP2102N"), max_baudrate: 3_000_000 },
+    Bridge { name: "CP2102", ids: &[(0x10c4, 0xea60)], product: Some("CP2102 "), max_baudrate: 921_600 },
+    Bridge { name: "CP2104", ids: &[(0x10c4, 0xea60)], product: Some("CP2104"), max_baudrate: 2_000_000 },
+];
+
+fn read_hex(path: &Path) -> Option<u16> {
+    u16::from_str_radix(std::fs::read_to_string(path).ok()?.trim(), 16).ok()
+}
+
+/// The sysfs directory of the USB device a serial port belongs to. Only found on Linux.
+fn usb_device(port: &str) -> Option<PathBuf> {
+    let name = Path::new(port).file_name()?;
+    let device = Path::new("/sys/class/tty").join(name).join("device").canonicalize().ok()?;
+    // The tty belongs to an interface, the IDs are on the USB device above it.
+    device.ancestors().take(3).find(|dir| dir.join("idVendor").exists()).map(Path::to_path_buf)
+}
+
+/// The USB VID:PID of a serial port, from sysfs. Only found on Linux.
+fn usb_id(port: &str) -> Option<(u16, u16)> {
+    let device = usb_device(port)?;
+    Some((read_hex(&device.join("idVendor"))?, read_hex(&device.join("idProduct"))?))
+}
+
+/// The USB to UART bridge the serial port belongs to, if it's a known one.
+pub fn bridge(port: &str) -> Option<&'static Bridge> {
+    let device = usb_device(port)?;
+    let id = (read_hex(&device.join("idVendor"))?, read_hex(&device.join("idProduct"))?);
+    let product = std::fs::read_to_string(device.join("product")).unwrap_or_default();
+    find_bridge(id, &product)
+}
+
+/// The bridge chip with a USB VID:PID and product string
+fn find_bridge(id: (u16, u16), product: &str) -> Option<&'static Bridge> {
+    BRIDGES.iter().find(|b| b.ids.contains(&id) && b.product.is_none_or(|p| product.starts_with(p)))
+}
+
+/// Lowers the USB latency timer of an FTDI bridge to 1 ms. The chip holds received bytes
+/// back for up to the timer, 16 ms by default, and every byte the BootROM echoes and
+/// every packet BL2 acknowledges waits for it, which takes longer than sending a packet
+/// from 1.5 Mbaud on. Returns the previous value if it changed it.
+pub fn lower_latency(port: &str) -> io::Result<Option<u32>> {
+    let Some(name) = Path::new(port).file_name() else {
+        return Ok(None);
+    };
+    let path = Path::new("/sys/class/tty").join(name).join("device").join("latency_timer");
+    let Some(previous) = std::fs::read_to_string(&path).ok().and_then(|s| s.trim().parse::<u32>().ok()) else {
+        return Ok(None);
+    };
+    if previous <= 1 {
+        return Ok(None);
+    }
+    std::fs::write(&path, "1")?;
+    Ok(Some(previous))
+}
+
+/// The profile of the debug probe the serial port belongs to, if it's a known one.
+pub fn detect(port: &str) -> Option<&'static ProbeProfile> {
+    find_probe(usb_id(port)?)
+}
+
+/// The debug probe with a USB VID:PID
+fn find_probe(id: (u16, u16)) -> Option<&'static ProbeProfile> {
+    PROBES.iter().find(|p| p.ids.contains(&id))
+}
+
+/// A serial port of a debug probe, written in chunks the bridge keeps up with, or of an
+/// adapter with a calibrated timing.
+pub struct ProbeTransport<T> {
+    inner: T,
+    chunk_len: usize,
+    chunk_gap: Duration,
+    min_timeout: Duration,
+}
+
+impl<T: Transport> ProbeTransport<T> {
+    pub fn new(inner: T, profile: &ProbeProfile) -> ProbeTransport<T> {
+        ProbeTransport { inner, chunk_len: profile.chunk_len, chunk_gap: profile.chunk_gap, min_timeout: profile.min_timeout }
+    }
+
+    /// A port written like any other, until it's relaxed
+    pub fn plain(inner: T) -> ProbeTransport<T> {
+        ProbeTransport { inner, chunk_len: usize::MAX, chunk_gap: Duration::ZERO, min_timeout: Duration::ZERO }
+    }
+
+    /// Pauses at least chunk_gap after each write and waits at least min_timeout for reads
+    pub fn relax(&mut self, chunk_gap: Duration, min_timeout: Duration) {
+        self.chunk_gap = self.chunk_gap.max(chunk_gap);
+        self.min_timeout = self.min_timeout.max(min_timeout);
+    }
+}
+
+impl<T: Transport> Read for ProbeTransport<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<T: Transport> Write for ProbeTransport<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(&buf[..buf.len().min(self.chunk_len)])?;
+        if !self.chunk_gap.is_zero() {
+            std::thread::sleep(self.chunk_gap);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Transport> Transport for ProbeTransport<T> {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.inner.set_timeout(timeout.max(self.min_timeout))
+    }
+
+    fn read_timeout(&self) -> Option<Duration> {
+        self.inner.read_timeout()
+    }
+
+    fn set_baud_rate(&mut self, baudrate: u32) -> io::Result<()> {
+        self.inner.set_baud_rate(baudrate)
+    }
+
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.inner.clear_input()
+    }
+
+    fn drain(&mut self) -> io::Result<()> {
+        self.inner.drain()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probes_are_found_by_their_id() {
+        for (id, name) in [
+            ((0x2e8a, 0x000c), Some("Raspberry Pi debugprobe")),
+            ((0x2e8a, 0x0004), Some("Raspberry Pi debugprobe")),
+            ((0x0d28, 0x0204), Some("DAPLink")),
+            ((0x0483, 0x374b), Some("ST-LINK")),
+            ((0x0483, 0x3754), Some("ST-LINK")),
+            ((0x1d50, 0x6018), Some("Black Magic Probe")),
+            ((0x1366, 0x0105), Some("SEGGER J-Link")),
+            ((0x1366, 0x1061), Some("SEGGER J-Link")),
+            // A bridge chip, and a Pico running something else
+            ((0x0403, 0x6001), None),
+            ((0x2e8a, 0x000a), None),
+        ] {
+            assert_eq!(find_probe(id).map(|p| p.name), name, "{:04x}:{:04x}", id.0, id.1);
+        }
+    }
+
+    #[test]
+    fn bridges_are_found_by_their_id_and_product() {
+        for (id, product, bridge) in [
+            ((0x1a86, 0x7523), "USB Serial", Some(("CH340", 2_000_000))),
+            ((0x1a86, 0x55d3), "USB Single Serial", Some(("CH343", 6_000_000))),
+            ((0x1a86, 0x55d4), "", Some(("CH9102", 4_000_000))),
+            ((0x0403, 0x6001), "FT232R USB UART", Some(("FT232R", 3_000_000))),
+            ((0x0403, 0x6015), "FT231X USB UART", Some(("FT230X/FT231X", 3_000_000))),
+            ((0x0403, 0x6014), "Single RS232-HS", Some(("FT2232H/FT4232H/FT232H", 12_000_000))),
+            ((0x10c4, 0xea60), "CP2102N USB to UART Bridge Controller", Some(("CP2102N", 3_000_000))),
+            ((0x10c4, 0xea60), "CP2102 USB to UART Bridge Controller", Some(("CP2102", 921_600))),
+            ((0x10c4, 0xea60), "CP2104 USB to UART Bridge Controller", Some(("CP2104", 2_000_000))),
+            // A CP210x which doesn't tell which one it is
+            ((0x10c4, 0xea60), "", None),
+            ((0x2e8a, 0x000c), "Debugprobe on Pico (CMSIS-DAP)", None),
+        ] {
+            assert_eq!(find_bridge(id, product).map(|b| (b.name, b.max_baudrate)), bridge, "{:?}", product);
+        }
+    }
+}
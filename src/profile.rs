@@ -0,0 +1,105 @@
+use std::path::PathBuf;
+use clap::{Arg, ArgMatches, Command, Id};
+use clap::builder::Resettable;
+use toml::{Table, Value};
+
+/// Adds --profile and --config to the command line of the tool.
+pub fn add_args(command: Command) -> Command {
+    command
+        .arg(Arg::new("profile").long("profile").value_name("NAME").global(true)
+            .help("Board profile with defaults for the options of the boot, NAME.toml in ~/.config/mtk_uartboot"))
+        .arg(Arg::new("config").long("config").value_name("PATH").global(true).conflicts_with("profile")
+            .help("Profile file to use instead of one in ~/.config/mtk_uartboot"))
+}
+
+/// Directory of the profiles, in the XDG config directory
+fn profile_dir() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()).map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("mtk_uartboot"))
+}
+
+/// The profile given on the command line, if any. Only --profile and --config are looked
+/// at, the rest may not parse until the profile filled in the options it needs.
+pub fn selected(command: &Command) -> Result<Option<(PathBuf, Table)>, String> {
+    let matches = match command.clone().ignore_errors(true).try_get_matches() {
+        Ok(m) => m,
+        Err(_) => return Ok(None),
+    };
+    let path = match (find(&matches, "config"), find(&matches, "profile")) {
+        (Some(path), _) => PathBuf::from(path),
+        (None, Some(name)) => profile_dir().ok_or("no home directory to find profiles in")?
+            .join(format!("{}.toml", name)),
+        (None, None) => return Ok(None),
+    };
+    let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let table = text.parse::<Table>().map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(Some((path, table)))
+}
+
+/// A value of the option id given before or after the subcommand
+fn find(matches: &ArgMatches, id: &str) -> Option<String> {
+    let own = matches.try_get_one::<String>(id).ok().flatten().cloned();
+    own.or_else(|| find(matches.subcommand()?.1, id))
+}
+
+/// Makes the options in the profile, keyed by their long names like in the profiles of
+/// monitor, the defaults of the boot options, so the command line still overrides them.
+/// Options given several times, e.g. --dtbo, replace the ones of the profile. The
+/// subcommands take the options they have, e.g. fip leaves the payload alone.
+pub fn apply(command: Command, profile: &Table) -> Result<Command, String> {
+    const SUBCOMMANDS: [&str; 3] = ["boot", "fip", "flash"];
+    for key in profile.keys() {
+        let known = std::iter::once(&command)
+            .chain(SUBCOMMANDS.iter().filter_map(|name| command.find_subcommand(name)))
+            .any(|command| option_id(command, key).is_some());
+        if !known {
+            return Err(format!("unknown option {} in the profile", key));
+        }
+    }
+    let mut command = apply_to(command, profile)?;
+    for name in SUBCOMMANDS {
+        let sub = command.find_subcommand(name).cloned();
+        if let Some(sub) = sub {
+            let sub = apply_to(sub, profile)?;
+            command = command.mut_subcommand(name, |_| sub);
+        }
+    }
+    Ok(command)
+}
+
+/// The id of the option of the command the profile key names, if it has one
+fn option_id(command: &Command, key: &str) -> Option<Id> {
+    let long = key.replace('_', "-");
+    command.get_arguments()
+        .find(|arg| arg.get_long() == Some(long.as_str()) && !["profile", "config"].contains(&arg.get_id().as_str()))
+        .map(|arg| arg.get_id().clone())
+}
+
+fn apply_to(mut command: Command, profile: &Table) -> Result<Command, String> {
+    for (key, value) in profile {
+        let Some(id) = option_id(&command, key) else {
+            continue;
+        };
+        let values = match value {
+            Value::Array(values) => values.iter().map(|v| to_string(key, v)).collect::<Result<Vec<_>, _>>()?,
+            v => vec![to_string(key, v)?],
+        };
+        // clap keeps defaults as &'static str, the profile is loaded once.
+        let values: Vec<&'static str> = values.into_iter().map(|v| &*v.leak()).collect();
+        // What the profile gives isn't missing anymore.
+        command = command.mut_arg(id, |arg| arg.default_values(values)
+            .required(false).required_unless_present(Resettable::Reset));
+    }
+    Ok(command)
+}
+
+fn to_string(key: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        v => Err(format!("invalid value for {}: {}", key, v)),
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+use mtk_uartboot::{Event, Stage};
+
+const BAR_WIDTH: usize = 30;
+/// Time between redraws, the FIP packets at the start come much faster than that
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The transfer being drawn
+struct Transfer {
+    stage: Stage,
+    started: Instant,
+    /// Bytes already sent at the first event, the time they took is unknown
+    first_sent: usize,
+    last_draw: Option<Instant>,
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// Bytes sent per second after the first event, None until that can be told
+fn rate(transfer: &Transfer, sent: usize) -> Option<f64> {
+    let elapsed = transfer.started.elapsed().as_secs_f64();
+    (sent > transfer.first_sent && elapsed > 0.0).then(|| (sent - transfer.first_sent) as f64 / elapsed)
+}
+
+/// Draws the progress of the payload and FIP transfers from the Progress events, with the
+/// effective speed and the time left, and prints the speed once a transfer is done. Only the
+/// speed is printed when stdout isn't a terminal, a log doesn't need a bar redrawn in it.
+pub struct ProgressBar {
+    draw: bool,
+    transfer: Option<Transfer>,
+}
+
+impl ProgressBar {
+    /// draw is false where something else prints while the transfer runs, e.g. with -vv
+    pub fn new(draw: bool) -> ProgressBar {
+        ProgressBar { draw: draw && std::io::stdout().is_terminal(), transfer: None }
+    }
+
+    /// Returns an event observer drawing the bar. It has to see the events before whatever
+    /// else prints them, it breaks the line of the bar for the messages.
+    pub fn observer(mut self) -> impl FnMut(&Event) + Send + 'static {
+        move |event| match *event {
+            Event::Progress { stage, sent, total } => self.progress(stage, sent, total),
+            Event::PacketRejected { .. } | Event::Paused { .. }
+                if self.transfer.as_ref().is_some_and(|t| t.last_draw.is_some()) => {
+                println!();
+                if let Some(transfer) = &mut self.transfer {
+                    transfer.last_draw = None;
+                }
+            }
+            // The time paused doesn't count towards the speed.
+            Event::Resumed { paused, .. } => {
+                if let Some(transfer) = &mut self.transfer {
+                    transfer.started += paused;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn progress(&mut self, stage: Stage, sent: usize, total: usize) {
+        let transfer = match &mut self.transfer {
+            Some(t) if t.stage == stage && sent >= t.first_sent => t,
+            t => t.insert(Transfer { stage, started: Instant::now(), first_sent: sent, last_draw: None }),
+        };
+        let rate = rate(transfer, sent);
+        if sent >= total {
+            if self.draw {
+                print!("\r\x1b[K");
+            }
+            if let Some(rate) = rate {
+                println!("Sent {} bytes in {}, {:.1} kB/s.", total, format_duration(transfer.started.elapsed()), rate / 1000.0);
+            }
+            self.transfer = None;
+            return;
+        }
+        if !self.draw || transfer.last_draw.is_some_and(|t| t.elapsed() < REDRAW_INTERVAL) {
+            return;
+        }
+        transfer.last_draw = Some(Instant::now());
+        let done = sent * BAR_WIDTH / total.max(1);
+        let speed = match rate {
+            Some(rate) => format!("{:.1} kB/s, {} left", rate / 1000.0,
+                                  format_duration(Duration::from_secs_f64((total - sent) as f64 / rate))),
+            None => "--.- kB/s".to_owned(),
+        };
+        print!("\r[{}{}] {}/{} bytes, {}  ", "#".repeat(done), " ".repeat(BAR_WIDTH - done), sent, total, speed);
+        std::io::stdout().flush().ok();
+    }
+}
@@ -0,0 +1,246 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::report::json_string;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Pending,
+    Running,
+    Ok,
+    Failed,
+    Cancelled,
+    /// The daemon stopped while the job was running, the device may be half booted
+    Interrupted,
+}
+
+impl JobState {
+    pub fn name(self) -> &'static str {
+        match self {
+            JobState::Pending => "pending",
+            JobState::Running => "running",
+            JobState::Ok => "ok",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+            JobState::Interrupted => "interrupted",
+        }
+    }
+}
+
+/// A boot of one device, queued until its port is free.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    pub port: String,
+    /// Jobs with a higher priority run first, jobs of the same priority in submission order
+    pub priority: i32,
+    pub state: JobState,
+    /// Who submitted the job
+    #[serde(default)]
+    pub submitted_by: String,
+    /// TOML profile whose keys are the long command line options of the boot, like for
+    /// monitor --on-attach
+    pub profile: String,
+}
+
+impl Job {
+    /// The job as a JSON object, without the profile
+    pub fn to_json(&self) -> String {
+        format!("{{\"id\":{},\"port\":{},\"priority\":{},\"state\":{},\"submitted_by\":{}}}",
+                self.id, json_string(&self.port), self.priority, json_string(self.state.name()),
+                json_string(&self.submitted_by))
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct QueueFile {
+    #[serde(default)]
+    job: Vec<Job>,
+}
+
+/// The jobs of the daemon, written to a file on every change so they survive restarts.
+pub struct Queue {
+    path: PathBuf,
+    jobs: Vec<Job>,
+}
+
+impl Queue {
+    /// Loads the queue kept in dir. Jobs which were running when the daemon stopped are
+    /// marked interrupted, they aren't run again on a device in an unknown state. Also
+    /// returns these jobs.
+    pub fn open(dir: &Path) -> (Queue, Vec<Job>) {
+        fs::create_dir_all(dir).expect("failed to create the state directory.");
+        let path = dir.join("queue.toml");
+        let mut jobs = match fs::read_to_string(&path) {
+            Ok(text) => toml::from_str::<QueueFile>(&text)
+                .unwrap_or_else(|e| panic!("invalid job queue {}: {}", path.display(), e))
+                .job,
+            Err(_) => Vec::new(),
+        };
+        let mut interrupted = Vec::new();
+        for job in &mut jobs {
+            if job.state == JobState::Running {
+                job.state = JobState::Interrupted;
+                interrupted.push(job.clone());
+            }
+        }
+        let queue = Queue { path, jobs };
+        queue.save();
+        (queue, interrupted)
+    }
+
+    fn save(&self) {
+        let text = toml::to_string(&QueueFile { job: self.jobs.clone() }).expect("failed to serialize the job queue.");
+        let tmp = self.path.with_extension("toml.tmp");
+        fs::write(&tmp, text).and_then(|()| fs::rename(&tmp, &self.path))
+            .expect("failed to write the job queue.");
+    }
+
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Job> {
+        self.jobs.iter().find(|j| j.id == id)
+    }
+
+    /// Adds a pending job and returns it
+    pub fn submit(&mut self, port: &str, priority: i32, submitted_by: &str, profile: String) -> Job {
+        let id = self.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1;
+        let job = Job {
+            id,
+            port: port.to_owned(),
+            priority,
+            state: JobState::Pending,
+            submitted_by: submitted_by.to_owned(),
+            profile,
+        };
+        self.jobs.push(job.clone());
+        self.save();
+        job
+    }
+
+    /// Cancels a pending job. Running jobs can't be cancelled, stopping them halfway would
+    /// leave the device half booted.
+    pub fn cancel(&mut self, id: u64) -> Result<(), String> {
+        let job = self.jobs.iter_mut().find(|j| j.id == id).ok_or("no such job")?;
+        if job.state != JobState::Pending {
+            return Err(format!("the job is {}", job.state.name()));
+        }
+        job.state = JobState::Cancelled;
+        self.save();
+        Ok(())
+    }
+
+    /// Marks the pending job to run next as running and returns it: the one with the
+    /// highest priority, oldest first, whose port is online and not busy with another job.
+    pub fn start_next<F: Fn(&str) -> bool>(&mut self, online: F) -> Option<Job> {
+        let busy: Vec<String> = self.jobs.iter()
+            .filter(|j| j.state == JobState::Running)
+            .map(|j| j.port.clone())
+            .collect();
+        let job = self.jobs.iter_mut()
+            .filter(|j| j.state == JobState::Pending && !busy.contains(&j.port) && online(&j.port))
+            .min_by_key(|j| (-(j.priority as i64), j.id))?;
+        job.state = JobState::Running;
+        let job = job.clone();
+        self.save();
+        Some(job)
+    }
+
+    /// Records how a running job went and returns it
+    pub fn finish(&mut self, id: u64, ok: bool) -> Job {
+        let job = self.jobs.iter_mut().find(|j| j.id == id).unwrap();
+        job.state = if ok { JobState::Ok } else { JobState::Failed };
+        let job = job.clone();
+        self.save();
+        job
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mtk_uartboot-queue-test-{}-{}", std::process::id(), test));
+        fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    fn states(queue: &Queue) -> Vec<JobState> {
+        queue.jobs().iter().map(|j| j.state).collect()
+    }
+
+    #[test]
+    fn jobs_run_by_priority_then_in_submission_order() {
+        let (mut queue, _) = Queue::open(&dir("order"));
+        let low = queue.submit("/dev/ttyUSB0", 0, "ci", String::new()).id;
+        let high = queue.submit("/dev/ttyUSB1", 5, "ci", String::new()).id;
+        let low_later = queue.submit("/dev/ttyUSB2", 0, "ci", String::new()).id;
+        let started: Vec<u64> = std::iter::from_fn(|| queue.start_next(|_| true)).map(|j| j.id).collect();
+        assert_eq!(started, [high, low, low_later]);
+        assert_eq!(states(&queue), [JobState::Running; 3]);
+    }
+
+    #[test]
+    fn jobs_wait_for_their_port() {
+        let (mut queue, _) = Queue::open(&dir("port"));
+        let first = queue.submit("/dev/ttyUSB0", 0, "ci", String::new()).id;
+        let second = queue.submit("/dev/ttyUSB0", 9, "ci", String::new()).id;
+        let offline = queue.submit("/dev/ttyUSB1", 0, "ci", String::new()).id;
+        let online = |port: &str| port != "/dev/ttyUSB1";
+        assert_eq!(queue.start_next(online).map(|j| j.id), Some(second));
+        // The port is busy until the job finished
+        assert!(queue.start_next(online).is_none());
+        assert_eq!(queue.finish(second, false).state, JobState::Failed);
+        assert_eq!(queue.start_next(online).map(|j| j.id), Some(first));
+        assert_eq!(queue.finish(first, true).state, JobState::Ok);
+        assert!(queue.start_next(online).is_none());
+        assert_eq!(queue.start_next(|_| true).map(|j| j.id), Some(offline));
+    }
+
+    #[test]
+    fn only_pending_jobs_are_cancelled() {
+        let (mut queue, _) = Queue::open(&dir("cancel"));
+        let running = queue.submit("/dev/ttyUSB0", 0, "ci", String::new()).id;
+        queue.start_next(|_| true);
+        let pending = queue.submit("/dev/ttyUSB0", 0, "ci", String::new()).id;
+        assert_eq!(queue.cancel(running), Err("the job is running".to_owned()));
+        assert_eq!(queue.cancel(pending), Ok(()));
+        assert_eq!(queue.cancel(pending), Err("the job is cancelled".to_owned()));
+        assert_eq!(queue.cancel(99), Err("no such job".to_owned()));
+        assert!(queue.start_next(|_| true).is_none());
+    }
+
+    #[test]
+    fn jobs_running_when_the_daemon_stopped_are_interrupted() {
+        let dir = dir("reopen");
+        let (mut queue, _) = Queue::open(&dir);
+        let profile = "payload = \"bl2.bin\"\n".to_owned();
+        let running = queue.submit("/dev/ttyUSB0", 0, "ci", profile.clone()).id;
+        queue.start_next(|_| true);
+        let pending = queue.submit("/dev/ttyUSB1", 3, "lab", profile.clone()).id;
+        drop(queue);
+
+        let (mut queue, interrupted) = Queue::open(&dir);
+        assert_eq!(interrupted.iter().map(|j| j.id).collect::<Vec<_>>(), [running]);
+        assert_eq!(states(&queue), [JobState::Interrupted, JobState::Pending]);
+        let job = queue.get(pending).unwrap();
+        assert_eq!((job.priority, job.submitted_by.as_str(), job.profile.as_str()), (3, "lab", profile.as_str()));
+        // Ids aren't reused
+        assert_eq!(queue.submit("/dev/ttyUSB0", 0, "ci", String::new()).id, pending + 1);
+        // Interrupted jobs aren't run again
+        assert_eq!(queue.start_next(|_| true).map(|j| j.id), Some(pending));
+        assert_eq!(queue.start_next(|_| true).map(|j| j.id), Some(pending + 1));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn jobs_are_shown_without_their_profile() {
+        let (mut queue, _) = Queue::open(&dir("json"));
+        let job = queue.submit("/dev/tty\"USB0", -2, "ci", "auth = \"secret\"".to_owned());
+        assert_eq!(job.to_json(), "{\"id\":1,\"port\":\"/dev/tty\\\"USB0\",\"priority\":-2,\"state\":\"pending\",\"submitted_by\":\"ci\"}");
+    }
+}
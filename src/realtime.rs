@@ -0,0 +1,71 @@
+use std::fmt;
+use std::io;
+
+/// SCHED_FIFO priority for --rt: above every normal thread, below the interrupt threads of
+/// the kernel, which the UART needs itself
+const FIFO_PRIORITY: i32 = 10;
+/// Nice value tried where SCHED_FIFO isn't permitted
+const NICE: i32 = -10;
+
+/// How the thread got scheduled
+#[derive(Clone, Copy, Debug)]
+pub enum Priority {
+    Fifo(i32),
+    Nice(i32),
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Priority::Fifo(priority) => write!(f, "SCHED_FIFO priority {}", priority),
+            Priority::Nice(nice) => write!(f, "nice {}", nice),
+        }
+    }
+}
+
+/// The calling thread running with a raised priority, back to how it was scheduled before
+/// once dropped
+pub struct Raised {
+    pub priority: Priority,
+    #[cfg(target_os = "linux")]
+    previous: (libc::c_int, libc::sched_param, libc::c_int),
+}
+
+/// Raises the priority of the calling thread: SCHED_FIFO, or a lower nice value where only
+/// that is permitted. So a loaded host doesn't leave the handshake waiting for the CPU while
+/// the BootROM's window closes. Fails with why neither was permitted.
+#[cfg(target_os = "linux")]
+pub fn raise() -> Result<Raised, String> {
+    let policy = unsafe { libc::sched_getscheduler(0) };
+    let mut param = libc::sched_param { sched_priority: 0 };
+    unsafe { libc::sched_getparam(0, &mut param) };
+    let nice = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+    let previous = (policy, param, nice);
+    let fifo = libc::sched_param { sched_priority: FIFO_PRIORITY };
+    if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &fifo) } == 0 {
+        return Ok(Raised { priority: Priority::Fifo(FIFO_PRIORITY), previous });
+    }
+    let fifo_error = io::Error::last_os_error();
+    if nice > NICE && unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, NICE) } == 0 {
+        return Ok(Raised { priority: Priority::Nice(NICE), previous });
+    }
+    Err(format!("{}, it needs CAP_SYS_NICE or an RLIMIT_RTPRIO, e.g. rtprio in limits.conf", fifo_error))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn raise() -> Result<Raised, String> {
+    Err(io::Error::from(io::ErrorKind::Unsupported).to_string())
+}
+
+impl Drop for Raised {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        {
+            let (policy, param, nice) = self.previous;
+            unsafe {
+                libc::sched_setscheduler(0, policy, &param);
+                libc::setpriority(libc::PRIO_PROCESS, 0, nice);
+            }
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::fs::File;
+use std::io::Write;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use mtk_uartboot::secret::redact_value;
+
+/// Milliseconds since the Unix epoch of a wall clock time, negative if it's before
+fn unix_ms(time: SystemTime) -> i128 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_millis() as i128,
+        Err(e) => -(e.duration().as_millis() as i128),
+    }
+}
+
+/// When a run started, by both clocks. Stages are timed by the monotonic one, which the wall
+/// clock being set, e.g. by NTP after a laptop resumed, doesn't move. The wall clock only
+/// anchors the run in time.
+struct Clock {
+    start: Instant,
+    wall_start: SystemTime,
+    last: Instant,
+}
+
+/// A record of what was sent to the device, written as `key=value` lines.
+///
+/// Entries are kept in the order the flow produces them and contain no timestamps, so two
+/// runs sending the same images produce an identical report, unless it's made to record
+/// times. Values of secret looking keys are redacted.
+#[derive(Default)]
+pub struct Report {
+    entries: Vec<(String, String)>,
+    clock: Option<Clock>,
+}
+
+impl Report {
+    /// A report which records when the run started and the stages were reached if times is
+    /// set, for --report-times
+    pub fn new(times: bool) -> Report {
+        let mut report = Report::default();
+        if times {
+            let clock = Clock { start: Instant::now(), wall_start: SystemTime::now(), last: Instant::now() };
+            report.set("time.start_unix_ms", unix_ms(clock.wall_start));
+            report.clock = Some(clock);
+        }
+        report
+    }
+
+    /// Records that a stage was reached: the milliseconds since the start of the run and since
+    /// the previous stage, both by the monotonic clock
+    pub fn stamp(&mut self, stage: &str) {
+        let Some(clock) = &mut self.clock else { return };
+        let now = Instant::now();
+        let (at, since) = (now - clock.start, now - clock.last);
+        clock.last = now;
+        self.set(&format!("time.{}.at_ms", stage), at.as_millis());
+        self.set(&format!("time.{}.ms", stage), since.as_millis());
+    }
+
+    /// Records the end of the run: its duration by the monotonic clock, and how far the wall
+    /// clock moved apart from it. That's the time the host was suspended, or by how much its
+    /// clock was set meanwhile, and durations computed from wall clock times are off by it.
+    pub fn stop_clock(&mut self) {
+        let Some(clock) = self.clock.take() else { return };
+        let total = clock.start.elapsed();
+        let wall_end = SystemTime::now();
+        self.set("time.end_unix_ms", unix_ms(wall_end));
+        self.set("time.total_ms", total.as_millis());
+        self.set("time.wall_skew_ms", unix_ms(wall_end) - unix_ms(clock.wall_start) - total.as_millis() as i128);
+    }
+
+    pub fn set<V: ToString>(&mut self, key: &str, value: V) {
+        self.entries.push((key.to_owned(), value.to_string()));
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// The entries as a JSON object, with secrets redacted like in the file
+    pub fn to_json(&self) -> String {
+        let fields: Vec<String> = self.entries().iter()
+            .map(|(key, value)| format!("{}:{}", json_string(key), json_string(value)))
+            .collect();
+        format!("{{{}}}", fields.join(","))
+    }
+
+    /// The entries in order, with secrets redacted like in the file
+    pub fn entries(&self) -> Vec<(String, String)> {
+        self.entries.iter()
+            .map(|(key, value)| (key.clone(), redact_value(key, value).into_owned()))
+            .collect()
+    }
+
+    pub fn write(&self, path: &str) -> std::io::Result<()> {
+        let mut f = File::create(path)?;
+        writeln!(f, "mtk_uartboot-report=1")?;
+        for (key, value) in self.entries() {
+            writeln!(f, "{}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Quotes s as a JSON string
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_redacted_by_key() {
+        let mut report = Report::new(false);
+        report.set("payload.sha256", "9f86d0");
+        report.set("dtbo:sla_key", "/keys/sla.pem");
+        report.set("auth_token", "a=b: c");
+        assert_eq!(report.to_json(),
+                   r#"{"payload.sha256":"9f86d0","dtbo:sla_key":"[redacted]","auth_token":"[redacted]"}"#);
+    }
+}
@@ -0,0 +1,63 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+/// A board `rescue` knows how to bring back. Its images are looked for as NAME-bl2.bin and
+/// NAME-fip.bin.
+#[derive(Debug)]
+pub struct Board {
+    pub name: &'static str,
+    pub title: &'static str,
+    pub hw_code: u16,
+    /// Where the debug UART of the board is
+    pub uart: &'static str,
+}
+
+pub const BOARDS: &[Board] = &[
+    Board { name: "bpi-r3", title: "Banana Pi BPI-R3", hw_code: 0x7986, uart: "the 3-pin debug UART header" },
+    Board { name: "bpi-r4", title: "Banana Pi BPI-R4", hw_code: 0x7988, uart: "the 3-pin debug UART header" },
+    Board { name: "bpi-r64", title: "Banana Pi BPI-R64", hw_code: 0x7622, uart: "the debug UART pins of the 40-pin header" },
+    Board { name: "gl-mt6000", title: "GL.iNet GL-MT6000", hw_code: 0x7986, uart: "the UART header on the board, inside the case" },
+    Board { name: "xiaomi-ax3000t", title: "Xiaomi AX3000T", hw_code: 0x7981, uart: "the UART pads on the board, inside the case" },
+    Board { name: "cudy-wr3000", title: "Cudy WR3000", hw_code: 0x7981, uart: "the UART pads on the board, inside the case" },
+];
+
+pub fn find(name: &str) -> Option<&'static Board> {
+    BOARDS.iter().find(|b| b.name == name)
+}
+
+/// Lists the boards with their titles and SoCs
+pub fn list() -> String {
+    BOARDS.iter().map(|b| {
+        let soc = mtk_uartboot::chips::lookup(b.hw_code).map_or("?", |c| c.name);
+        format!("  {:<16} {} ({})\n", b.name, b.title, soc)
+    }).collect()
+}
+
+/// Prints a step for the operator and waits for Enter, if there's someone at a terminal
+fn step(n: usize, text: &str, wait: bool) {
+    println!("Step {}: {}", n, text);
+    if wait && io::stdin().is_terminal() {
+        print!("Press Enter when done. ");
+        io::stdout().flush().ok();
+        io::stdin().lock().read_line(&mut String::new()).ok();
+    }
+}
+
+/// Walks the operator through preparing the board, up to where the tool waits for the BootROM
+pub fn guide(board: &Board) {
+    println!("Rescuing a {}.", board.title);
+    step(1, "Power the board off and unplug everything but the power supply.", true);
+    step(2, &format!("Connect a 3.3V USB serial adapter to {}: GND to GND, the adapter's RX to TX and \
+                      its TX to RX. Don't connect its VCC.", board.uart), true);
+    step(3, "Power the board on now. The BootROM only listens for a moment after power on, the \
+             tool is waiting for it.", false);
+}
+
+/// Tells the operator what's left to do once the tool is done
+pub fn finished(recovery: Option<&str>) {
+    match recovery {
+        Some(file) => step(4, &format!("{} is booting. Once it's up, copy the sysupgrade image of the board \
+                                        to /tmp, e.g. with scp, and flash it with `sysupgrade -n /tmp/<image>`.", file), false),
+        None => step(4, "U-Boot is running from RAM. Use its menu or commands to write a working image to the \
+                         storage of the board, or boot a recovery image with --recovery to run sysupgrade from.", false),
+    }
+}
@@ -0,0 +1,113 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::time::Duration;
+use mtk_uartboot::Transport;
+
+/// struct sockaddr_rc of <bluetooth/rfcomm.h>
+#[repr(C)]
+struct SockaddrRc {
+    family: libc::sa_family_t,
+    /// The address with the least significant byte first
+    bdaddr: [u8; 6],
+    channel: u8,
+}
+
+const BTPROTO_RFCOMM: libc::c_int = 3;
+
+/// A Bluetooth serial bridge, e.g. an HC-05 module wired to the UART of the device, reached
+/// over RFCOMM. The bridge runs the UART at a fixed speed.
+pub struct Rfcomm {
+    socket: File,
+}
+
+/// Parses MAC or MAC/CHANNEL, the part of an rfcomm:// URL after the scheme. The channel
+/// defaults to 1, the one of the serial port profile of most bridges.
+fn parse_address(address: &str) -> Option<([u8; 6], u8)> {
+    let (mac, channel) = match address.split_once('/') {
+        Some((mac, channel)) => (mac, channel.parse().ok()?),
+        None => (address, 1),
+    };
+    let bytes: Vec<u8> = mac.split(':').map(|b| u8::from_str_radix(b, 16)).collect::<Result<_, _>>().ok()?;
+    let mut bdaddr: [u8; 6] = bytes.try_into().ok()?;
+    bdaddr.reverse();
+    Some((bdaddr, channel))
+}
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl Rfcomm {
+    /// Connects to MAC or MAC/CHANNEL, e.g. 98:d3:31:fb:12:34
+    pub fn connect(address: &str) -> io::Result<Rfcomm> {
+        let (bdaddr, channel) = parse_address(address)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "expected rfcomm://MAC or rfcomm://MAC/CHANNEL"))?;
+        // SAFETY: plain socket call, the descriptor is owned by the File right after.
+        let fd = unsafe { libc::socket(libc::AF_BLUETOOTH, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, BTPROTO_RFCOMM) };
+        check(fd)?;
+        // SAFETY: fd is a new socket nothing else owns.
+        let socket = unsafe { File::from_raw_fd(fd) };
+        let addr = SockaddrRc { family: libc::AF_BLUETOOTH as libc::sa_family_t, bdaddr, channel };
+        // SAFETY: addr is a sockaddr_rc of the size given.
+        check(unsafe {
+            libc::connect(fd, &addr as *const SockaddrRc as *const libc::sockaddr, std::mem::size_of::<SockaddrRc>() as libc::socklen_t)
+        })?;
+        Ok(Rfcomm { socket })
+    }
+}
+
+impl Read for Rfcomm {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.socket.read(buf).map_err(|e| match e.kind() {
+            // A receive timeout shows up as EAGAIN on sockets.
+            io::ErrorKind::WouldBlock => io::ErrorKind::TimedOut.into(),
+            _ => e,
+        })
+    }
+}
+
+impl Write for Rfcomm {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Rfcomm {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        let tv = libc::timeval {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+        };
+        // SAFETY: tv is a timeval of the size given.
+        check(unsafe {
+            libc::setsockopt(self.socket.as_raw_fd(), libc::SOL_SOCKET, libc::SO_RCVTIMEO,
+                             &tv as *const libc::timeval as *const libc::c_void,
+                             std::mem::size_of::<libc::timeval>() as libc::socklen_t)
+        })
+    }
+
+    fn set_baud_rate(&mut self, _baudrate: u32) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn clear_input(&mut self) -> io::Result<()> {
+        let mut buf = [0u8; 256];
+        loop {
+            // SAFETY: buf is writable for its length.
+            let n = unsafe {
+                libc::recv(self.socket.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), libc::MSG_DONTWAIT)
+            };
+            if n <= 0 {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Keeping secrets out of logs, traces and reports.
+//!
+//! Logs and traces tend to end up attached to public bug reports, so keys, tokens and
+//! challenge responses must never be printed as they are.
+
+use std::borrow::Cow;
+use std::fmt;
+
+const REDACTED: &str = "[redacted]";
+
+/// Names of `key=value` and `key: value` fields whose value is always redacted
+const SECRET_KEYS: &[&str] = &["authorization", "key", "password", "secret", "signature", "token"];
+
+/// A value which is never shown by `Debug` or `Display`.
+///
+/// ```
+/// use mtk_uartboot::secret::Secret;
+///
+/// let token = Secret::new("hunter2".to_owned());
+/// assert_eq!(format!("{:?} {}", token, token), "[redacted] [redacted]");
+/// assert_eq!(token.expose(), "hunter2");
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Secret<T> {
+        Secret(value)
+    }
+
+    /// The actual value, for passing it on to where it's needed. Never log it.
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+fn is_secret_key(key: &str) -> bool {
+    let key = key.trim().to_ascii_lowercase();
+    SECRET_KEYS.iter().any(|s| key == *s || key.ends_with(&format!("_{}", s)) || key.ends_with(&format!("-{}", s)))
+}
+
+/// Redacts the value of a line which looks like a secret field, e.g. `auth_token=...` or
+/// `Authorization: Bearer ...`. Other lines are returned as they are.
+///
+/// ```
+/// use mtk_uartboot::secret::redact_line;
+///
+/// assert_eq!(redact_line("Authorization: Bearer abc"), "Authorization: [redacted]");
+/// assert_eq!(redact_line("sla_key=/keys/sla.pem"), "sla_key=[redacted]");
+/// assert_eq!(redact_line("payload.sha256=9f86d0"), "payload.sha256=9f86d0");
+/// assert_eq!(redact_line("hw code: 0x7986"), "hw code: 0x7986");
+/// ```
+pub fn redact_line(line: &str) -> Cow<'_, str> {
+    let sep = match line.find(['=', ':']) {
+        Some(sep) => sep,
+        None => return Cow::Borrowed(line),
+    };
+    if !is_secret_key(&line[..sep]) {
+        return Cow::Borrowed(line);
+    }
+    let value_start = sep + 1 + (line[sep + 1..].len() - line[sep + 1..].trim_start().len());
+    Cow::Owned(format!("{}{}", &line[..value_start], REDACTED))
+}
+
+/// Redacts the value of a field whose key looks like a secret one, like [`redact_line`] does
+/// for a whole line. Other values are returned as they are.
+///
+/// ```
+/// use mtk_uartboot::secret::redact_value;
+///
+/// assert_eq!(redact_value("sla_key", "/keys/sla.pem"), "[redacted]");
+/// assert_eq!(redact_value("dtbo:sla_key", "/keys/sla.pem"), "[redacted]");
+/// assert_eq!(redact_value("payload.sha256", "9f86d0"), "9f86d0");
+/// ```
+pub fn redact_value<'a>(key: &str, value: &'a str) -> Cow<'a, str> {
+    if is_secret_key(key) {
+        Cow::Borrowed(REDACTED)
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// Replaces every occurrence of the given secret values in text.
+///
+/// ```
+/// use mtk_uartboot::secret::redact;
+///
+/// assert_eq!(redact("curl -H 'X-Token: s3cr3t'", &["s3cr3t"]), "curl -H 'X-Token: [redacted]'");
+/// ```
+pub fn redact(text: &str, secrets: &[&str]) -> String {
+    let mut out = text.to_owned();
+    for secret in secrets.iter().filter(|s| !s.is_empty()) {
+        out = out.replace(secret, REDACTED);
+    }
+    out
+}
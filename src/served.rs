@@ -0,0 +1,31 @@
+use std::path::{Component, Path, PathBuf};
+
+/// Maps a file name the device asked for into the served directory, refusing anything
+/// outside of it. Names are relative to the directory even with a leading '/', as TFTP
+/// clients send them.
+pub fn resolve(root: &Path, name: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for c in Path::new(name.trim_start_matches('/')).components() {
+        match c {
+            Component::Normal(p) => path.push(p),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_stay_in_the_served_directory() {
+        let root = Path::new("/srv/images");
+        assert_eq!(resolve(root, "fip.bin"), Some(root.join("fip.bin")));
+        assert_eq!(resolve(root, "/boot/./Image"), Some(root.join("boot/Image")));
+        for name in ["../etc/passwd", "boot/../../etc/passwd", "//../etc/passwd"] {
+            assert_eq!(resolve(root, name), None, "{}", name);
+        }
+    }
+}
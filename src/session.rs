@@ -0,0 +1,152 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use crate::bl2::{Bl2, StorageInfo};
+use crate::bootrom::BootRom;
+use crate::error::Result;
+use crate::event::{Event, Events, Stage};
+use crate::extension::{Extension, Extensions};
+use crate::transport::{RateLimited, Transport};
+
+/// A connection to one device, from the BootROM up to whatever it boots into.
+pub struct Session {
+    transport: RateLimited,
+    events: Events,
+    extensions: Extensions,
+    storage_info: Option<StorageInfo>,
+    read_only: bool,
+    pause: PauseControl,
+}
+
+/// Holds the transfers of a session at the next chunk of a payload or packet of a FIP, and
+/// lets them go on later. Clones control the same session, from any thread, e.g. one
+/// reading keys while the main one sends. The BootROM and BL2 wait for the rest meanwhile,
+/// unless a watchdog resets the device first.
+#[derive(Clone, Default)]
+pub struct PauseControl(Arc<(Mutex<bool>, Condvar)>);
+
+impl PauseControl {
+    fn paused(&self) -> MutexGuard<'_, bool> {
+        self.0.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn pause(&self) {
+        *self.paused() = true;
+    }
+
+    pub fn resume(&self) {
+        *self.paused() = false;
+        self.0.1.notify_all();
+    }
+
+    /// Pauses if the transfers go on, resumes them if they're paused. Returns whether
+    /// they're paused now.
+    pub fn toggle(&self) -> bool {
+        let mut paused = self.paused();
+        *paused = !*paused;
+        self.0.1.notify_all();
+        *paused
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused()
+    }
+
+    /// Blocks while paused, between two chunks of a transfer to stage
+    pub(crate) fn wait(&self, events: &mut Events, stage: Stage) {
+        if !self.is_paused() {
+            return;
+        }
+        let since = Instant::now();
+        events.emit(Event::Paused { stage });
+        let mut paused = self.paused();
+        while *paused {
+            paused = self.0.1.wait(paused).unwrap_or_else(|e| e.into_inner());
+        }
+        drop(paused);
+        events.emit(Event::Resumed { stage, paused: since.elapsed() });
+    }
+}
+
+impl Session {
+    pub fn new(transport: Box<dyn Transport>) -> Session {
+        Session {
+            transport: RateLimited::new(transport),
+            events: Events::default(),
+            extensions: Extensions::default(),
+            storage_info: None,
+            read_only: false,
+            pause: PauseControl::default(),
+        }
+    }
+
+    /// Refuses every command which could change the state of the device. Only the
+    /// handshakes and queries are allowed then.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Paces everything written to the device to at most max_rate bytes per second, or lifts
+    /// the limit with None. For links which corrupt data at full speed, when a lower baud
+    /// rate isn't an option, e.g. because BL2 doesn't negotiate one.
+    pub fn set_max_rate(&mut self, max_rate: Option<u32>) {
+        self.transport.max_rate = max_rate;
+    }
+
+    /// Lets writes wait up to timeout for the device to take data, independently of the
+    /// timeouts the protocols wait for answers with. Until it's set, writes wait as long as
+    /// reads, which may be too short for large payloads on a slow or rate limited link.
+    pub fn set_write_timeout(&mut self, timeout: Duration) {
+        self.transport.write_timeout = Some(timeout);
+    }
+
+    /// Reports every protocol command as an [`Event::Span`] once it's done, with the time
+    /// it spent writing, reading and rate limited, to find out where the time of a boot
+    /// goes before optimizing it.
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.transport.clock.set_enabled(profiling);
+        self.events.set_profiling(profiling.then(|| self.transport.clock.clone()));
+    }
+
+    /// Calls f for every event of this session
+    pub fn on_event<F: FnMut(&Event) + Send + 'static>(&mut self, f: F) {
+        self.events.set_observer(Box::new(f));
+    }
+
+    /// Adds a command the crate doesn't implement, run with [`BootRom::call`] or
+    /// [`Bl2::call`]. Fails with [`Error::CommandTaken`](crate::Error::CommandTaken) if its
+    /// stage already has a command with the same byte or name.
+    pub fn register<E: Extension + 'static>(&mut self, extension: E) -> Result<()> {
+        self.extensions.register(Box::new(extension))
+    }
+
+    /// Forgets the storage BL2 told with [`Bl2::storage_info`], e.g. after a command added
+    /// with [`register`](Session::register) rewrote the partition table
+    pub fn forget_storage_info(&mut self) {
+        self.storage_info = None;
+    }
+
+    /// Pauses and resumes the transfers of this session, see [`PauseControl`]
+    pub fn pause_control(&self) -> PauseControl {
+        self.pause.clone()
+    }
+
+    /// Talks to the BootROM download mode
+    pub fn bootrom(&mut self) -> BootRom<'_> {
+        BootRom::new(&mut self.transport, &mut self.events, &mut self.extensions, self.pause.clone(), self.read_only)
+    }
+
+    /// Talks to the UART download mode of BL2
+    pub fn bl2(&mut self) -> Bl2<'_> {
+        Bl2::new(&mut self.transport, &mut self.events, &mut self.extensions, &mut self.storage_info,
+                 self.pause.clone(), self.read_only)
+    }
+
+    /// The underlying transport, e.g. to read the console of the booted payload
+    pub fn transport(&mut self) -> &mut dyn Transport {
+        &mut self.transport
+    }
+
+    pub fn into_transport(self) -> Box<dyn Transport> {
+        self.transport.inner
+    }
+}
@@ -0,0 +1,238 @@
+use std::fs;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use ring::signature::{ECDSA_P256_SHA256_ASN1, ED25519, UnparsedPublicKey};
+
+/// DER of a SubjectPublicKeyInfo up to the uncompressed point of a P-256 key
+const P256_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01,
+    0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+
+/// A public key release images are signed with.
+pub enum PublicKey {
+    /// From `minisign -G`, signatures are in IMAGE.minisig
+    Minisign { key_id: [u8; 8], key: [u8; 32] },
+    /// A P-256 key from `cosign generate-key-pair`, signatures from `cosign sign-blob` are
+    /// in IMAGE.sig
+    Cosign { point: Vec<u8> },
+}
+
+fn decode(line: &str) -> Option<Vec<u8>> {
+    STANDARD.decode(line.trim()).ok()
+}
+
+impl PublicKey {
+    pub fn load(path: &str) -> Result<PublicKey, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let invalid = || format!("{} isn't a minisign or cosign public key", path);
+        if let Some(pem) = text.trim().strip_prefix("-----BEGIN PUBLIC KEY-----") {
+            let base64: String = pem.lines().take_while(|l| !l.starts_with("-----END")).map(str::trim).collect();
+            let der = decode(&base64).ok_or_else(invalid)?;
+            let point = der.strip_prefix(P256_SPKI_PREFIX).filter(|p| p.len() == 65).ok_or_else(invalid)?;
+            return Ok(PublicKey::Cosign { point: point.to_vec() });
+        }
+        // The base64 line, with or without the comment line above it.
+        let data = text.lines()
+            .find(|l| !l.trim().is_empty() && !l.starts_with("untrusted comment:"))
+            .and_then(decode)
+            .filter(|d| d.len() == 42 && d.starts_with(b"Ed"))
+            .ok_or_else(invalid)?;
+        Ok(PublicKey::Minisign { key_id: data[2..10].try_into().unwrap(), key: data[10..].try_into().unwrap() })
+    }
+
+    /// Path of the detached signature of an image
+    pub fn signature_path(&self, image: &str) -> String {
+        match self {
+            PublicKey::Minisign { .. } => format!("{}.minisig", image),
+            PublicKey::Cosign { .. } => format!("{}.sig", image),
+        }
+    }
+
+    /// Checks the detached signature of the image at path, whose contents are data. Returns
+    /// the trusted comment of minisign signatures, e.g. when and which file was signed.
+    pub fn verify(&self, path: &str, data: &[u8]) -> Result<Option<String>, String> {
+        let sig_path = self.signature_path(path);
+        let text = fs::read_to_string(&sig_path).map_err(|e| format!("{}: {}", sig_path, e))?;
+        let invalid = || format!("{} isn't a valid signature", sig_path);
+        match self {
+            PublicKey::Minisign { key_id, key } => {
+                let lines: Vec<&str> = text.lines().collect();
+                let [_, signature, trusted, global, ..] = lines[..] else {
+                    return Err(invalid());
+                };
+                let signature = decode(signature).filter(|s| s.len() == 74).ok_or_else(invalid)?;
+                let trusted = trusted.strip_prefix("trusted comment: ").ok_or_else(invalid)?;
+                let global = decode(global).ok_or_else(invalid)?;
+                if signature[2..10] != key_id[..] {
+                    return Err(format!("{} is signed with another key", path));
+                }
+                let key = UnparsedPublicKey::new(&ED25519, key);
+                // Signatures from minisign 0.8 on are over the BLAKE2b hash of the file.
+                let result = match &signature[..2] {
+                    b"Ed" => key.verify(data, &signature[10..]),
+                    b"ED" => key.verify(&blake2b_512(data), &signature[10..]),
+                    _ => return Err(invalid()),
+                };
+                result.map_err(|_| format!("the signature of {} doesn't match", path))?;
+                let mut signed = signature[10..].to_vec();
+                signed.extend_from_slice(trusted.as_bytes());
+                key.verify(&signed, &global).map_err(|_| format!("the trusted comment of {} doesn't match", sig_path))?;
+                Ok(Some(trusted.to_owned()))
+            }
+            PublicKey::Cosign { point } => {
+                let signature = decode(&text).ok_or_else(invalid)?;
+                UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, point)
+                    .verify(data, &signature)
+                    .map_err(|_| format!("the signature of {} doesn't match", path))?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+const BLAKE2B_IV: [u64; 8] = [
+    0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+    0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+];
+
+const SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+fn mix(v: &mut [u64; 16], [a, b, c, d]: [usize; 4], x: u64, y: u64) {
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+fn compress(h: &mut [u64; 8], block: &[u8; 128], counter: u128, last: bool) {
+    let m: Vec<u64> = block.chunks(8).map(|w| u64::from_le_bytes(w.try_into().unwrap())).collect();
+    let mut v = [0; 16];
+    v[..8].copy_from_slice(h);
+    v[8..].copy_from_slice(&BLAKE2B_IV);
+    v[12] ^= counter as u64;
+    v[13] ^= (counter >> 64) as u64;
+    if last {
+        v[14] = !v[14];
+    }
+    for round in 0..12 {
+        let s = &SIGMA[round % 10];
+        mix(&mut v, [0, 4, 8, 12], m[s[0]], m[s[1]]);
+        mix(&mut v, [1, 5, 9, 13], m[s[2]], m[s[3]]);
+        mix(&mut v, [2, 6, 10, 14], m[s[4]], m[s[5]]);
+        mix(&mut v, [3, 7, 11, 15], m[s[6]], m[s[7]]);
+        mix(&mut v, [0, 5, 10, 15], m[s[8]], m[s[9]]);
+        mix(&mut v, [1, 6, 11, 12], m[s[10]], m[s[11]]);
+        mix(&mut v, [2, 7, 8, 13], m[s[12]], m[s[13]]);
+        mix(&mut v, [3, 4, 9, 14], m[s[14]], m[s[15]]);
+    }
+    for i in 0..8 {
+        h[i] ^= v[i] ^ v[i + 8];
+    }
+}
+
+/// BLAKE2b with a 64-byte digest and no key, as minisign hashes files.
+fn blake2b_512(data: &[u8]) -> [u8; 64] {
+    let mut h = BLAKE2B_IV;
+    h[0] ^= 0x0101_0000 ^ 64;
+    // The last block, even if empty or full, is compressed as the final one.
+    let last_len = if data.is_empty() { 0 } else { (data.len() - 1) % 128 + 1 };
+    let (full, rest) = data.split_at(data.len() - last_len);
+    for (i, block) in full.chunks(128).enumerate() {
+        compress(&mut h, block.try_into().unwrap(), ((i + 1) * 128) as u128, false);
+    }
+    let mut block = [0; 128];
+    block[..rest.len()].copy_from_slice(rest);
+    compress(&mut h, &block, data.len() as u128, true);
+
+    let mut out = [0; 64];
+    for (chunk, word) in out.chunks_mut(8).zip(h) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use super::*;
+    use crate::to_hex;
+
+    #[test]
+    fn blake2b_matches_rfc_7693() {
+        // The example of appendix A, and the empty message.
+        assert_eq!(to_hex(&blake2b_512(b"abc")),
+                   "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d17d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923");
+        assert_eq!(to_hex(&blake2b_512(b"")),
+                   "786a02f742015903c6c6fd852552d272912f4740e15847618a86e217f71f5419d25e1031afee585313896444934eb04b903a685b1448b755d56f701afe9be2ce");
+    }
+
+    #[test]
+    fn blake2b_of_whole_and_partial_blocks() {
+        let data: Vec<u8> = (0..256).map(|i| (i % 251) as u8).collect();
+        assert_eq!(to_hex(&blake2b_512(&data[..128])),
+                   "2319e3789c47e2daa5fe807f61bec2a1a6537fa03f19ff32e87eecbfd64b7e0e8ccff439ac333b040f19b0c4ddd11a61e24ac1fe0f10a039806c5dcc0da3d115");
+        assert_eq!(to_hex(&blake2b_512(&data[..129])),
+                   "f59711d44a031d5f97a9413c065d1e614c417ede998590325f49bad2fd444d3e4418be19aec4e11449ac1a57207898bc57d76a1bcf3566292c20c683a5c4648f");
+        assert_eq!(to_hex(&blake2b_512(&data)),
+                   "93463ac058b6163eb43be3f5bb32b28541498f4e3366f1effe253ad44e1e076e41c3616046027c82a7124f8f4746668ad10b12e8e25a95ac8f3151df01cd5a93");
+    }
+
+    /// Signs data like `minisign -S` does, prehashed unless legacy, and writes the public key
+    /// and the signature next to the image at path
+    fn minisign(path: &str, data: &[u8], legacy: bool) -> String {
+        let pair = Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap();
+        let key_id = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut public = b"Ed".to_vec();
+        public.extend_from_slice(&key_id);
+        public.extend_from_slice(pair.public_key().as_ref());
+        let (algorithm, signature) = match legacy {
+            true => (b"Ed", pair.sign(data)),
+            false => (b"ED", pair.sign(&blake2b_512(data))),
+        };
+        let mut line = algorithm.to_vec();
+        line.extend_from_slice(&key_id);
+        line.extend_from_slice(signature.as_ref());
+        let trusted = "timestamp:1792031082\tfile:bl2.img\thashed";
+        let mut signed = signature.as_ref().to_vec();
+        signed.extend_from_slice(trusted.as_bytes());
+        let global = pair.sign(&signed);
+        fs::write(path, data).unwrap();
+        fs::write(format!("{}.minisig", path), format!("untrusted comment: signature from minisign secret key\n{}\ntrusted comment: {}\n{}\n",
+                                                      STANDARD.encode(line), trusted, STANDARD.encode(global))).unwrap();
+        let key_path = format!("{}.pub", path);
+        fs::write(&key_path, format!("untrusted comment: minisign public key 0807060504030201\n{}\n", STANDARD.encode(public))).unwrap();
+        key_path
+    }
+
+    #[test]
+    fn minisign_signatures_round_trip() {
+        let dir = std::env::temp_dir().join(format!("mtk_uartboot-signature-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        for legacy in [false, true] {
+            let path = dir.join(format!("bl2-{}.img", legacy)).to_str().unwrap().to_owned();
+            let key = PublicKey::load(&minisign(&path, &data, legacy)).unwrap();
+            assert_eq!(key.verify(&path, &data).unwrap().as_deref(), Some("timestamp:1792031082\tfile:bl2.img\thashed"));
+            let mut tampered = data.clone();
+            tampered[500] ^= 1;
+            assert!(key.verify(&path, &tampered).unwrap_err().contains("doesn't match"));
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,240 @@
+//! Destinations for the console output of the device.
+//!
+//! A [`Sink`] gets every console line once the payload runs. The command line tool opens
+//! the sinks given with `--console-log` from a [`SinkSpec`], and embedders add their own,
+//! e.g. a closure pushing lines into a channel:
+//!
+//! ```
+//! use std::sync::mpsc;
+//! use mtk_uartboot::Sink;
+//!
+//! let (tx, rx) = mpsc::channel();
+//! let mut sink: Box<dyn Sink> = Box::new(move |line: &str| { tx.send(line.to_owned()).ok(); });
+//! sink.line("U-Boot 2024.01");
+//! assert_eq!(rx.recv().unwrap(), "U-Boot 2024.01");
+//! ```
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+#[cfg(unix)]
+use std::os::unix::net::{UnixDatagram, UnixStream};
+
+/// Receives the console lines of a device.
+pub trait Sink: Send {
+    /// Takes a console line, without its line ending. Sinks keep going when their
+    /// destination goes away, the console matters more than its copies.
+    fn line(&mut self, line: &str);
+
+    /// Tags the lines from now on with the serial port they came from
+    fn set_serial(&mut self, _serial: &str) {}
+}
+
+impl<F: FnMut(&str) + Send> Sink for F {
+    fn line(&mut self, line: &str) {
+        self(line)
+    }
+}
+
+/// A sink as given on the command line
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SinkSpec {
+    /// `stdout`, the unfiltered lines prefixed with the serial port
+    Stdout,
+    /// `file:PATH`, appended to the file
+    File(String),
+    /// `journald`, the systemd journal, with the serial port in the MTK_UARTBOOT_SERIAL field
+    Journald,
+    /// `syslog`, the local syslog daemon on /dev/log
+    Syslog,
+    /// `tcp:HOST:PORT`, a line per console line over a TCP connection
+    Tcp(String),
+    /// `unix:PATH`, a line per console line over a unix stream socket
+    Unix(String),
+}
+
+impl FromStr for SinkSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SinkSpec, String> {
+        let (kind, arg) = match s.split_once(':') {
+            Some((kind, arg)) => (kind, Some(arg)),
+            None => (s, None),
+        };
+        match (kind, arg) {
+            ("stdout", None) => Ok(SinkSpec::Stdout),
+            ("journald", None) => Ok(SinkSpec::Journald),
+            ("syslog", None) => Ok(SinkSpec::Syslog),
+            ("file", Some(path)) if !path.is_empty() => Ok(SinkSpec::File(path.to_owned())),
+            ("tcp", Some(addr)) if addr.contains(':') => Ok(SinkSpec::Tcp(addr.to_owned())),
+            ("unix", Some(path)) if !path.is_empty() => Ok(SinkSpec::Unix(path.to_owned())),
+            _ => Err(format!("unknown sink {}, expected stdout, journald, syslog, file:PATH, tcp:HOST:PORT or unix:PATH", s)),
+        }
+    }
+}
+
+impl fmt::Display for SinkSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkSpec::Stdout => write!(f, "stdout"),
+            SinkSpec::File(path) => write!(f, "file:{}", path),
+            SinkSpec::Journald => write!(f, "journald"),
+            SinkSpec::Syslog => write!(f, "syslog"),
+            SinkSpec::Tcp(addr) => write!(f, "tcp:{}", addr),
+            SinkSpec::Unix(path) => write!(f, "unix:{}", path),
+        }
+    }
+}
+
+impl SinkSpec {
+    /// Opens the destination
+    pub fn open(&self) -> io::Result<Box<dyn Sink>> {
+        Ok(match self {
+            SinkSpec::Stdout => Box::new(Lines::new(io::stdout())),
+            SinkSpec::File(path) => Box::new(Lines::new(open_append(path)?)),
+            SinkSpec::Journald => Box::new(Journald::open()?),
+            SinkSpec::Syslog => Box::new(Syslog::open()?),
+            SinkSpec::Tcp(addr) => Box::new(Lines::new(TcpStream::connect(addr)?)),
+            #[cfg(unix)]
+            SinkSpec::Unix(path) => Box::new(Lines::new(UnixStream::connect(path)?)),
+            #[cfg(not(unix))]
+            SinkSpec::Unix(_) => return Err(unsupported()),
+        })
+    }
+}
+
+fn open_append(path: &str) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(not(unix))]
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "needs a unix system")
+}
+
+/// Writes the lines as text, prefixed with the serial port once it's known, e.g. to a
+/// file or a socket.
+pub struct Lines<W> {
+    out: W,
+    serial: Option<String>,
+    failed: bool,
+}
+
+impl<W: Write> Lines<W> {
+    pub fn new(out: W) -> Lines<W> {
+        Lines { out, serial: None, failed: false }
+    }
+}
+
+impl<W: Write + Send> Sink for Lines<W> {
+    fn line(&mut self, line: &str) {
+        // A reader which went away once doesn't come back, don't retry for every line.
+        if self.failed {
+            return;
+        }
+        let text = match &self.serial {
+            Some(serial) => format!("{}: {}\n", serial, line),
+            None => format!("{}\n", line),
+        };
+        self.failed = self.out.write_all(text.as_bytes()).and_then(|_| self.out.flush()).is_err();
+    }
+
+    fn set_serial(&mut self, serial: &str) {
+        self.serial = Some(serial.to_owned());
+    }
+}
+
+const IDENTIFIER: &str = "mtk_uartboot";
+
+/// Facility user and severity info, as syslog priority
+const SYSLOG_PRIORITY: u8 = 14;
+
+/// The systemd journal, with the serial port in the MTK_UARTBOOT_SERIAL field
+pub struct Journald {
+    #[cfg(unix)]
+    socket: UnixDatagram,
+    serial: Option<String>,
+}
+
+impl Journald {
+    #[cfg(unix)]
+    pub fn open() -> io::Result<Journald> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect("/run/systemd/journal/socket")?;
+        Ok(Journald { socket, serial: None })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open() -> io::Result<Journald> {
+        Err(unsupported())
+    }
+}
+
+impl Sink for Journald {
+    fn line(&mut self, line: &str) {
+        let line = line.trim_end();
+        if line.is_empty() {
+            return;
+        }
+        // Native protocol of journald, one field per line. Lines have no newlines in them,
+        // so the simple form of fields does.
+        let mut fields = format!("MESSAGE={}\nPRIORITY=6\nSYSLOG_IDENTIFIER={}\nMTK_UARTBOOT_SOURCE=console\n",
+                                 line, IDENTIFIER);
+        if let Some(serial) = &self.serial {
+            fields += &format!("MTK_UARTBOOT_SERIAL={}\n", serial);
+        }
+        #[cfg(unix)]
+        self.socket.send(fields.as_bytes()).ok();
+        #[cfg(not(unix))]
+        let _ = fields;
+    }
+
+    fn set_serial(&mut self, serial: &str) {
+        self.serial = Some(serial.to_owned());
+    }
+}
+
+/// The local syslog daemon on /dev/log, lines prefixed with the serial port
+pub struct Syslog {
+    #[cfg(unix)]
+    socket: UnixDatagram,
+    serial: Option<String>,
+}
+
+impl Syslog {
+    #[cfg(unix)]
+    pub fn open() -> io::Result<Syslog> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect("/dev/log")?;
+        Ok(Syslog { socket, serial: None })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open() -> io::Result<Syslog> {
+        Err(unsupported())
+    }
+}
+
+impl Sink for Syslog {
+    fn line(&mut self, line: &str) {
+        let line = line.trim_end();
+        if line.is_empty() {
+            return;
+        }
+        // RFC 3164 without the timestamp and host name, which the daemon adds.
+        let message = match &self.serial {
+            Some(serial) => format!("<{}>{}[{}]: {}: {}", SYSLOG_PRIORITY, IDENTIFIER, std::process::id(), serial, line),
+            None => format!("<{}>{}[{}]: {}", SYSLOG_PRIORITY, IDENTIFIER, std::process::id(), line),
+        };
+        #[cfg(unix)]
+        self.socket.send(message.as_bytes()).ok();
+        #[cfg(not(unix))]
+        let _ = message;
+    }
+
+    fn set_serial(&mut self, serial: &str) {
+        self.serial = Some(serial.to_owned());
+    }
+}
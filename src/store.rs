@@ -0,0 +1,215 @@
+use std::fs::{self, File};
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
+use mtk_uartboot::digest::{Sha256, to_hex};
+use crate::fip::Fip;
+
+/// A content-addressed store of images for stations keeping many firmware versions.
+///
+/// An image is kept as a list of parts in `images/NAME`, each a line with the SHA-256 and
+/// the length of its data in `blobs/SHA256`. FIPs are split at their images, so versions
+/// which only differ in, e.g., U-Boot share the other images on disk, and other files are
+/// one part, shared with identical files under other names. Files are written to temporary
+/// files and renamed into place, so readers never see partial ones, and gc waits for images
+/// being added in other processes.
+pub struct Store {
+    dir: PathBuf,
+}
+
+/// What adding an image stored
+pub struct Added {
+    pub parts: usize,
+    /// Bytes of parts which weren't in the store yet
+    pub new_bytes: usize,
+}
+
+/// What gc deleted
+pub struct Collected {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Where the data of a FIP or another file is split into parts
+fn split(data: &[u8]) -> Vec<Range<usize>> {
+    let Ok(fip) = Fip::parse(data) else {
+        return std::iter::once(0..data.len()).collect();
+    };
+    let mut images: Vec<Range<usize>> = fip.entries.iter()
+        .map(|e| e.offset..e.offset + e.size)
+        .filter(|r| !r.is_empty())
+        .collect();
+    images.sort_by_key(|r| r.start);
+    // The ToC and the padding between the images are parts of their own.
+    let mut parts = Vec::new();
+    let mut p = 0;
+    for image in images {
+        if image.start < p {
+            continue;
+        }
+        if image.start > p {
+            parts.push(p..image.start);
+        }
+        p = image.end;
+        parts.push(image);
+    }
+    if p < data.len() {
+        parts.push(p..data.len());
+    }
+    parts
+}
+
+fn check_name(name: &str) -> io::Result<()> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid image name {}", name)));
+    }
+    Ok(())
+}
+
+/// Writes a file through a temporary file renamed into place
+fn write_atomic(path: &PathBuf, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)
+}
+
+/// Locks the store, shared while adding images and exclusively for gc. The lock is held
+/// until the file is closed.
+fn lock(file: &File, exclusive: bool) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsRawFd;
+        let op = if exclusive { libc::LOCK_EX } else { libc::LOCK_SH };
+        // SAFETY: flock only takes the descriptor, which is open.
+        if unsafe { libc::flock(file.as_raw_fd(), op) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (file, exclusive);
+    Ok(())
+}
+
+impl Store {
+    /// Uses dir, or mtk_uartboot/images in the XDG data directory if none is given
+    pub fn new(dir: Option<&str>) -> Store {
+        let dir = match dir {
+            Some(d) => PathBuf::from(d),
+            None => std::env::var_os("XDG_DATA_HOME").filter(|d| !d.is_empty()).map(PathBuf::from)
+                .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+                .unwrap_or_else(std::env::temp_dir)
+                .join("mtk_uartboot/images"),
+        };
+        Store { dir }
+    }
+
+    fn lock(&self, exclusive: bool) -> io::Result<File> {
+        fs::create_dir_all(&self.dir)?;
+        let file = File::create(self.dir.join("lock"))?;
+        lock(&file, exclusive)?;
+        Ok(file)
+    }
+
+    fn image_path(&self, name: &str) -> io::Result<PathBuf> {
+        check_name(name)?;
+        Ok(self.dir.join("images").join(name))
+    }
+
+    /// Stores data as the image name, replacing an image of that name.
+    pub fn add(&self, name: &str, data: &[u8]) -> io::Result<Added> {
+        let path = self.image_path(name)?;
+        let _lock = self.lock(false)?;
+        fs::create_dir_all(self.dir.join("blobs"))?;
+        fs::create_dir_all(self.dir.join("images"))?;
+        let mut recipe = String::new();
+        let mut added = Added { parts: 0, new_bytes: 0 };
+        for part in split(data) {
+            let part = &data[part];
+            let hash = to_hex(&Sha256::digest(part));
+            let blob = self.dir.join("blobs").join(&hash);
+            if !blob.exists() {
+                write_atomic(&blob, part)?;
+                added.new_bytes += part.len();
+            }
+            recipe += &format!("{} {}\n", hash, part.len());
+            added.parts += 1;
+        }
+        write_atomic(&path, recipe.as_bytes())?;
+        Ok(added)
+    }
+
+    /// The parts of the image name, as SHA-256 and length
+    fn parts(&self, name: &str) -> io::Result<Vec<(String, usize)>> {
+        let recipe = fs::read_to_string(self.image_path(name)?)?;
+        recipe.lines()
+            .map(|line| line.split_once(' ')
+                .and_then(|(hash, len)| Some((hash.to_owned(), len.parse().ok()?)))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("invalid image {}", name))))
+            .collect()
+    }
+
+    /// The data of the image name, checked against the hashes of its parts.
+    pub fn get(&self, name: &str) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        for (hash, len) in self.parts(name)? {
+            let part = fs::read(self.dir.join("blobs").join(&hash))?;
+            if part.len() != len || to_hex(&Sha256::digest(&part)) != hash {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("part {} of {} is corrupted", hash, name)));
+            }
+            data.extend_from_slice(&part);
+        }
+        Ok(data)
+    }
+
+    /// Names and sizes of the images, sorted by name
+    pub fn list(&self) -> io::Result<Vec<(String, usize)>> {
+        let mut images = Vec::new();
+        let dir = match fs::read_dir(self.dir.join("images")) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(images),
+            Err(e) => return Err(e),
+        };
+        for entry in dir {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if check_name(&name).is_ok() && !name.contains(".tmp.") {
+                let size = self.parts(&name)?.iter().map(|(_, len)| len).sum();
+                images.push((name, size));
+            }
+        }
+        images.sort();
+        Ok(images)
+    }
+
+    /// Removes the image name. Its data stays until gc deletes it.
+    pub fn remove(&self, name: &str) -> io::Result<()> {
+        fs::remove_file(self.image_path(name)?)
+    }
+
+    /// Deletes the parts no image uses anymore, and temporary files left behind by
+    /// interrupted writes.
+    pub fn gc(&self) -> io::Result<Collected> {
+        let _lock = self.lock(true)?;
+        let mut used = std::collections::HashSet::new();
+        for (name, _) in self.list()? {
+            used.extend(self.parts(&name)?.into_iter().map(|(hash, _)| hash));
+        }
+        let mut collected = Collected { files: 0, bytes: 0 };
+        for sub in ["blobs", "images"] {
+            let dir = match fs::read_dir(self.dir.join(sub)) {
+                Ok(dir) => dir,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for entry in dir {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.contains(".tmp.") || sub == "blobs" && !used.contains(&name) {
+                    collected.bytes += entry.metadata()?.len();
+                    collected.files += 1;
+                    fs::remove_file(entry.path())?;
+                }
+            }
+        }
+        Ok(collected)
+    }
+}
@@ -0,0 +1,113 @@
+//! The events `mtk_uartboot --output json` writes, for dashboards and test harnesses.
+//!
+//! Every line on stdout is one [`StreamEvent`], a JSON object naming the event in its `event`
+//! field. The schema is stable within a [`SCHEMA_VERSION`]: events and fields are added, but
+//! never removed, renamed or given another type. Events a consumer doesn't know yet read as
+//! [`StreamEvent::Unknown`], and fields it doesn't know are skipped. Values of the device,
+//! like hw codes and checksums, are strings of hex numbers, e.g. `"0x7986"`.
+//!
+//! ```
+//! use mtk_uartboot::Stage;
+//! use mtk_uartboot::stream::StreamEvent;
+//!
+//! let line = r#"{"event":"progress","stage":"bl2","sent":4096,"total":65536}"#;
+//! let event: StreamEvent = serde_json::from_str(line).unwrap();
+//! assert_eq!(event, StreamEvent::Progress { stage: Stage::Bl2, sent: 4096, total: 65536 });
+//! ```
+
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use crate::event::Stage;
+
+/// The version of the schema of the events, in [`StreamEvent::Started`]. Raised only by
+/// changes which break consumers.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The parts of a run, in the order they start
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStage {
+    /// Talking to the BootROM, from the handshake to the jump to the payload
+    Bootrom,
+    /// Talking to BL2, to send it the FIP or write to storage
+    Bl2,
+    /// Watching the console of what was booted
+    Console,
+}
+
+/// An event of a run of the tool, as written on a line with `--output json`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum StreamEvent {
+    /// The first event of every run
+    Started { version: String, schema: u32 },
+    StageStarted { stage: RunStage },
+    /// The handshake with stage completed
+    Handshake { stage: Stage },
+    Baudrate { stage: Stage, baudrate: u32 },
+    /// What the BootROM tells about the device, chip is the name of a known SoC
+    Device { hw_code: String, hw_sub_code: String, hw_ver: String, sw_ver: String, chip: Option<String> },
+    TargetConfig { secure_boot: bool, serial_link_authorization: bool, download_agent_authorization: bool },
+    /// Bytes of the transfer to stage sent so far, at most a few times per second
+    Progress { stage: Stage, sent: usize, total: usize },
+    PayloadSent { load_addr: String, len: usize, sha256: String, checksum: String },
+    /// A --load file was sent
+    SegmentSent { file: String, load_addr: String, len: usize, sha256: String, checksum: String },
+    Jumped { addr: String, aarch64: bool },
+    Bl2Version { version: String },
+    /// A FIP transfer failed, it's tried again at baudrate
+    FipFailed { baudrate: u32, error: String },
+    FipSent { len: usize, sha256: String, packets: u32 },
+    FlashSwitched { from: String, to: String },
+    FlashWritten { target: String, len: usize, sha256: String, crc32: String },
+    PacketRejected { idx: u32 },
+    Paused { stage: Stage },
+    Resumed { stage: Stage, paused_ms: u64 },
+    /// The booted OS printed marker, one of --wait-boot
+    OsBooted { marker: String },
+    /// A line the device printed on the console, without its line ending and escape
+    /// sequences
+    ConsoleLine { line: String },
+    Warning { message: String },
+    /// A check of `doctor`, status is pass, warn, fail or skip
+    Check { name: String, status: String, detail: String },
+    /// The last event of every run. status is ok, failed or error, and report has the
+    /// entries of --report.
+    Result { status: String, error: Option<String>, exit_code: i32, report: BTreeMap<String, String> },
+    /// An event of a newer version of the tool
+    #[serde(other)]
+    Unknown,
+}
+
+impl StreamEvent {
+    /// The name of the event, as in its `event` field
+    pub fn name(&self) -> &'static str {
+        match self {
+            StreamEvent::Started { .. } => "started",
+            StreamEvent::StageStarted { .. } => "stage_started",
+            StreamEvent::Handshake { .. } => "handshake",
+            StreamEvent::Baudrate { .. } => "baudrate",
+            StreamEvent::Device { .. } => "device",
+            StreamEvent::TargetConfig { .. } => "target_config",
+            StreamEvent::Progress { .. } => "progress",
+            StreamEvent::PayloadSent { .. } => "payload_sent",
+            StreamEvent::SegmentSent { .. } => "segment_sent",
+            StreamEvent::Jumped { .. } => "jumped",
+            StreamEvent::Bl2Version { .. } => "bl2_version",
+            StreamEvent::FipFailed { .. } => "fip_failed",
+            StreamEvent::FipSent { .. } => "fip_sent",
+            StreamEvent::FlashSwitched { .. } => "flash_switched",
+            StreamEvent::FlashWritten { .. } => "flash_written",
+            StreamEvent::PacketRejected { .. } => "packet_rejected",
+            StreamEvent::Paused { .. } => "paused",
+            StreamEvent::Resumed { .. } => "resumed",
+            StreamEvent::OsBooted { .. } => "os_booted",
+            StreamEvent::ConsoleLine { .. } => "console_line",
+            StreamEvent::Warning { .. } => "warning",
+            StreamEvent::Check { .. } => "check",
+            StreamEvent::Result { .. } => "result",
+            StreamEvent::Unknown => "unknown",
+        }
+    }
+}
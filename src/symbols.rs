@@ -0,0 +1,183 @@
+use std::ops::Range;
+use regex::Regex;
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+
+/// Function symbols of an ELF image, for naming the addresses in crash dumps.
+pub struct Symbols {
+    /// (start, end, name), sorted by start. end is start for symbols without a size.
+    funcs: Vec<(u64, u64, String)>,
+    register: Regex,
+    addr: Regex,
+}
+
+fn field(data: &[u8], range: Range<usize>) -> Option<u64> {
+    let bytes = data.get(range)?;
+    Some(bytes.iter().rev().fold(0, |v, &b| v << 8 | b as u64))
+}
+
+/// A field of the header or entry at base
+fn field_at(data: &[u8], base: usize, range: Range<usize>) -> Option<u64> {
+    field(data, base.checked_add(range.start)?..base.checked_add(range.end)?)
+}
+
+fn c_str(data: &[u8], offset: usize) -> Option<String> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    Some(String::from_utf8_lossy(&bytes[..len]).into_owned())
+}
+
+impl Symbols {
+    /// Reads the symbol table of a little-endian 32- or 64-bit ELF image.
+    pub fn parse(data: &[u8]) -> Option<Symbols> {
+        if data.get(..4)? != b"\x7fELF" || *data.get(5)? != 1 {
+            return None;
+        }
+        let is64 = *data.get(4)? == 2;
+        let (shoff, shentsize, shnum) = if is64 {
+            (field(data, 0x28..0x30)?, field(data, 0x3a..0x3c)?, field(data, 0x3c..0x3e)?)
+        } else {
+            (field(data, 0x20..0x24)?, field(data, 0x2e..0x30)?, field(data, 0x30..0x32)?)
+        };
+        // (type, offset, size, link) of a section header
+        let section = |i: u64| -> Option<(u32, usize, usize, u64)> {
+            let h = usize::try_from(shoff.checked_add(i.checked_mul(shentsize)?)?).ok()?;
+            let (kind, offset, size, link) = if is64 {
+                (field_at(data, h, 4..8)?, field_at(data, h, 0x18..0x20)?, field_at(data, h, 0x20..0x28)?, field_at(data, h, 0x28..0x2c)?)
+            } else {
+                (field_at(data, h, 4..8)?, field_at(data, h, 0x10..0x14)?, field_at(data, h, 0x14..0x18)?, field_at(data, h, 0x18..0x1c)?)
+            };
+            Some((kind as u32, usize::try_from(offset).ok()?, usize::try_from(size).ok()?, link))
+        };
+
+        let mut funcs = Vec::new();
+        for i in 0..shnum {
+            let (kind, offset, size, link) = section(i)?;
+            if kind != SHT_SYMTAB {
+                continue;
+            }
+            let (_, strtab, _, _) = section(link)?;
+            let entsize = if is64 { 24 } else { 16 };
+            for sym in (offset..offset.checked_add(size)?).step_by(entsize) {
+                let name = field_at(data, sym, 0..4)? as usize;
+                let (info, shndx, value, len) = if is64 {
+                    (field_at(data, sym, 4..5)?, field_at(data, sym, 6..8)?, field_at(data, sym, 8..16)?, field_at(data, sym, 16..24)?)
+                } else {
+                    // The lowest bit of Thumb function addresses is set.
+                    (field_at(data, sym, 12..13)?, field_at(data, sym, 14..16)?, field_at(data, sym, 4..8)? & !1, field_at(data, sym, 8..12)?)
+                };
+                // Section index 0 means the symbol is undefined in this image.
+                if info as u8 & 0xf == STT_FUNC && shndx != 0 && name != 0 {
+                    funcs.push((value, value.checked_add(len)?, c_str(data, strtab.checked_add(name)?)?));
+                }
+            }
+        }
+        if funcs.is_empty() {
+            return None;
+        }
+        funcs.sort();
+        Some(Symbols {
+            funcs,
+            register: Regex::new(r"(?i)\b(pc|lr|elr(_el\d)?|x30|ra)\b").unwrap(),
+            addr: Regex::new(r"\b0x[0-9a-fA-F]{4,16}\b").unwrap(),
+        })
+    }
+
+    /// Returns the function containing the address and the offset into it. Symbols without a
+    /// size only contain their own address.
+    fn lookup(&self, addr: u64) -> Option<(&str, u64)> {
+        let before = self.funcs.partition_point(|(start, _, _)| *start <= addr);
+        let (start, _, name) = self.funcs[..before].iter().rev()
+            .find(|(start, end, _)| if end > start { addr < *end } else { addr == *start })?;
+        Some((name, addr - start))
+    }
+
+    /// Appends function names to the addresses on lines naming the PC or the link register,
+    /// like in exception dumps. Other lines are returned unchanged.
+    pub fn annotate(&self, line: &str) -> String {
+        if !self.register.is_match(line) {
+            return line.to_owned();
+        }
+        self.addr.replace_all(line, |caps: &regex::Captures| {
+            let text = &caps[0];
+            match u64::from_str_radix(&text[2..], 16).ok().and_then(|a| self.lookup(a)) {
+                Some((name, 0)) => format!("{} <{}>", text, name),
+                Some((name, offset)) => format!("{} <{}+{:#x}>", text, name, offset),
+                None => text.to_owned(),
+            }
+        }).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A little-endian ELF64 image with a symbol table of (name, value, size, section index)
+    /// function symbols, behind the null symbol
+    fn elf64(funcs: &[(&str, u64, u64, u16)]) -> Vec<u8> {
+        let mut strtab = vec![0];
+        let mut symtab = vec![0; 24];
+        for (name, value, size, shndx) in funcs {
+            symtab.extend_from_slice(&(strtab.len() as u32).to_le_bytes());
+            symtab.extend_from_slice(&[0x10 | STT_FUNC, 0]);
+            symtab.extend_from_slice(&shndx.to_le_bytes());
+            symtab.extend_from_slice(&value.to_le_bytes());
+            symtab.extend_from_slice(&size.to_le_bytes());
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+        }
+        let mut data = vec![0; 64];
+        data[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        let symtab_at = data.len();
+        data.extend_from_slice(&symtab);
+        let strtab_at = data.len();
+        data.extend_from_slice(&strtab);
+        let shoff = data.len();
+        data[0x28..0x30].copy_from_slice(&(shoff as u64).to_le_bytes());
+        data[0x3a..0x3c].copy_from_slice(&64u16.to_le_bytes());
+        data[0x3c..0x3e].copy_from_slice(&3u16.to_le_bytes());
+        // The null section, the symbol table and its strings.
+        for (kind, offset, size, link) in [(0u32, 0, 0, 0u32), (SHT_SYMTAB, symtab_at, symtab.len(), 2), (3, strtab_at, strtab.len(), 0)] {
+            let mut header = [0; 64];
+            header[4..8].copy_from_slice(&kind.to_le_bytes());
+            header[0x18..0x20].copy_from_slice(&(offset as u64).to_le_bytes());
+            header[0x20..0x28].copy_from_slice(&(size as u64).to_le_bytes());
+            header[0x28..0x2c].copy_from_slice(&link.to_le_bytes());
+            data.extend_from_slice(&header);
+        }
+        data
+    }
+
+    #[test]
+    fn addresses_are_named_by_their_function() {
+        let symbols = Symbols::parse(&elf64(&[("bl31_main", 0x4000_1000, 0x100, 1), ("el3_exit", 0x4000_1080, 0, 1),
+                                                ("plat_panic", 0x4000_2000, 0, 1), ("memcpy", 0, 0, 0)])).unwrap();
+        assert_eq!(symbols.lookup(0x4000_1000), Some(("bl31_main", 0)));
+        assert_eq!(symbols.lookup(0x4000_10fc), Some(("bl31_main", 0xfc)));
+        assert_eq!(symbols.lookup(0x4000_1080), Some(("el3_exit", 0)));
+        // Past a symbol without a size, the sized function around it still counts.
+        assert_eq!(symbols.lookup(0x4000_1084), Some(("bl31_main", 0x84)));
+        assert_eq!(symbols.lookup(0x4000_1100), None);
+        assert_eq!(symbols.lookup(0x4000_2004), None);
+        assert_eq!(symbols.lookup(0), None);
+        assert_eq!(symbols.annotate("ELR_EL3: 0x400010a0 SPSR: 0x3c9"), "ELR_EL3: 0x400010a0 <bl31_main+0xa0> SPSR: 0x3c9");
+        assert_eq!(symbols.annotate("x0 = 0x40001000"), "x0 = 0x40001000");
+    }
+
+    #[test]
+    fn corrupt_images_are_refused() {
+        let data = elf64(&[("bl31_main", 0x4000_1000, 0x100, 1)]);
+        let mut huge_shoff = data.clone();
+        huge_shoff[0x28..0x30].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Symbols::parse(&huge_shoff).is_none());
+        let mut huge_size = data.clone();
+        let symtab_header = data.len() - 2 * 64;
+        huge_size[symtab_header + 0x20..symtab_header + 0x28].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Symbols::parse(&huge_size).is_none());
+        let overflowing = elf64(&[("bl31_main", u64::MAX - 4, 0x100, 1)]);
+        assert!(Symbols::parse(&overflowing).is_none());
+        assert!(Symbols::parse(&data[..data.len() - 64]).is_none());
+    }
+}
@@ -0,0 +1,70 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+use mtk_uartboot::Transport;
+
+/// A UART passed through a raw TCP socket by a helper, e.g. an Android app serving a USB
+/// serial adapter on an OTG port, or ser2net in raw mode. The helper runs the UART at a
+/// fixed speed.
+pub struct Tcp {
+    stream: TcpStream,
+}
+
+impl Tcp {
+    /// Connects to HOST:PORT, the part of a tcp:// URL after the scheme
+    pub fn connect(address: &str) -> io::Result<Tcp> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        Ok(Tcp { stream })
+    }
+}
+
+impl Read for Tcp {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf).map_err(|e| match e.kind() {
+            // A receive timeout shows up as EAGAIN on sockets.
+            io::ErrorKind::WouldBlock => io::ErrorKind::TimedOut.into(),
+            _ => e,
+        })
+    }
+}
+
+impl Write for Tcp {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Transport for Tcp {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        // A zero timeout means none to sockets.
+        self.stream.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))
+    }
+
+    fn read_timeout(&self) -> Option<Duration> {
+        self.stream.read_timeout().ok().flatten()
+    }
+
+    fn set_baud_rate(&mut self, _baudrate: u32) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.stream.set_nonblocking(true)?;
+        let mut buf = [0u8; 256];
+        let result = loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result
+    }
+}
@@ -0,0 +1,110 @@
+use std::fs::File;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use crate::hooks;
+use crate::output;
+
+/// What was seen while sampling
+#[derive(Default)]
+pub struct Summary {
+    pub samples: usize,
+    pub min_voltage: Option<f64>,
+    pub max_temperature: Option<f64>,
+    /// Samples with the voltage below the threshold
+    pub dips: usize,
+}
+
+/// Samples a telemetry command in the background, e.g. one reading a USB power meter or a
+/// thermal sensor of the board, while the device is being booted.
+///
+/// The command prints `name=value` pairs. `voltage` and `temperature` are tracked, and a
+/// voltage below the threshold is reported as a possible brown-out. Every sample is logged
+/// with the milliseconds since sampling started.
+pub struct Telemetry {
+    stop: Arc<AtomicBool>,
+    summary: Arc<Mutex<Summary>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+fn parse_sample(output: &str) -> Vec<(String, f64)> {
+    output.split_whitespace()
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            Some((name.to_owned(), value.parse().ok()?))
+        })
+        .collect()
+}
+
+fn run_sampler(cmd: &str) -> Option<String> {
+    match hooks::shell(cmd).output() {
+        Ok(o) if o.status.success() => Some(String::from_utf8_lossy(&o.stdout).into_owned()),
+        _ => None,
+    }
+}
+
+impl Telemetry {
+    pub fn start(cmd: &str, interval: Duration, log: Option<&str>, min_voltage: Option<f64>) -> Telemetry {
+        let stop = Arc::new(AtomicBool::new(false));
+        let summary = Arc::new(Mutex::new(Summary::default()));
+        let mut log = log.map(|p| File::create(p).expect("failed to create telemetry log."));
+        let cmd = cmd.to_owned();
+        let (thread_stop, thread_summary) = (stop.clone(), summary.clone());
+        let thread = std::thread::spawn(move || {
+            let start = Instant::now();
+            while !thread_stop.load(Ordering::Relaxed) {
+                if let Some(output) = run_sampler(&cmd) {
+                    let sample = parse_sample(&output);
+                    let mut summary = thread_summary.lock().unwrap();
+                    summary.samples += 1;
+                    for (name, value) in &sample {
+                        match name.as_str() {
+                            "voltage" => {
+                                summary.min_voltage = Some(summary.min_voltage.map_or(*value, |v| v.min(*value)));
+                                if min_voltage.is_some_and(|min| *value < min) {
+                                    summary.dips += 1;
+                                    output::warning(&format!("supply voltage dropped to {} V, the device may brown out.", value));
+                                }
+                            }
+                            "temperature" => {
+                                summary.max_temperature = Some(summary.max_temperature.map_or(*value, |t| t.max(*value)));
+                            }
+                            _ => {}
+                        }
+                    }
+                    if let Some(f) = &mut log {
+                        let fields: Vec<String> = sample.iter().map(|(n, v)| format!("{}={}", n, v)).collect();
+                        // Best effort, telemetry isn't worth aborting a boot.
+                        writeln!(f, "{} {}", start.elapsed().as_millis(), fields.join(" ")).ok();
+                    }
+                }
+                std::thread::sleep(interval);
+            }
+        });
+        Telemetry { stop, summary, thread: Some(thread) }
+    }
+
+    /// Stops sampling and returns what was seen
+    pub fn stop(mut self) -> Summary {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+        std::mem::take(&mut *self.summary.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn samples_are_read_from_the_command() {
+        let output = run_sampler("echo vbus=5.02 ibus=0.41 temp=x").unwrap();
+        assert_eq!(parse_sample(&output), [("vbus".to_owned(), 5.02), ("ibus".to_owned(), 0.41)]);
+        assert_eq!(run_sampler("echo vbus=5.0; exit 1"), None);
+    }
+}
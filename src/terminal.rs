@@ -0,0 +1,250 @@
+use std::io::{self, Read, Write};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use regex::Regex;
+use mtk_uartboot::Transport;
+
+/// Ctrl-A, the escape key of the terminal like in picocom. It's followed by q to exit, or by
+/// another Ctrl-A to send one.
+const ESCAPE: u8 = 0x01;
+
+/// The escape sequences xterm and most terminals send for F1 to F12
+const F_KEYS: [&[u8]; 12] = [
+    b"\x1bOP", b"\x1bOQ", b"\x1bOR", b"\x1bOS", b"\x1b[15~", b"\x1b[17~",
+    b"\x1b[18~", b"\x1b[19~", b"\x1b[20~", b"\x1b[21~", b"\x1b[23~", b"\x1b[24~",
+];
+
+/// The key of a macro
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MacroKey {
+    /// F1 to F12, by number
+    F(u8),
+    /// Ctrl-A followed by this key
+    Escaped(u8),
+}
+
+/// Text typed into the terminal by a single key, e.g. a long `setenv bootargs` line.
+#[derive(Clone, Debug)]
+pub struct Macro {
+    pub key: MacroKey,
+    pub text: Vec<u8>,
+}
+
+impl Macro {
+    /// Parses KEY=TEXT. KEY is F1 to F12, or ^A and a key typed after Ctrl-A, e.g. ^A1. In
+    /// TEXT, \n is Enter, \t is Tab, \xHH any byte and \\ a backslash.
+    pub fn parse(s: &str) -> Result<Macro, String> {
+        let (key, text) = s.split_once('=').ok_or("expected KEY=TEXT")?;
+        let key = if let Some(n) = key.strip_prefix(['F', 'f']) {
+            MacroKey::F(n.parse().ok().filter(|n| (1..=12).contains(n)).ok_or_else(|| format!("no key {}", key))?)
+        } else if let Some(k) = key.strip_prefix("^A").or_else(|| key.strip_prefix("^a")) {
+            match k.as_bytes() {
+                [k] if !b"qQxX?\x01".contains(k) => MacroKey::Escaped(*k),
+                _ => return Err(format!("{} isn't a key free for macros after Ctrl-A", k)),
+            }
+        } else {
+            return Err(format!("no key {}, expected F1 to F12 or ^A and a key", key));
+        };
+        Ok(Macro { key, text: unescape(text)? })
+    }
+}
+
+impl std::fmt::Display for MacroKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MacroKey::F(n) => write!(f, "F{}", n),
+            MacroKey::Escaped(k) => write!(f, "Ctrl-A {}", *k as char),
+        }
+    }
+}
+
+/// Replaces the escapes of a macro text with the bytes they stand for
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(text.len());
+    let mut bytes = text.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'n') => out.push(b'\r'),
+            Some(b't') => out.push(b'\t'),
+            Some(b'\\') => out.push(b'\\'),
+            Some(b'x') => {
+                let hex: Vec<u8> = bytes.by_ref().take(2).collect();
+                let byte = std::str::from_utf8(&hex).ok().filter(|h| h.len() == 2).and_then(|h| u8::from_str_radix(h, 16).ok())
+                    .ok_or_else(|| format!("invalid escape \\x{}", String::from_utf8_lossy(&hex)))?;
+                out.push(byte);
+            }
+            Some(b) => return Err(format!("invalid escape \\{}", b as char)),
+            None => return Err("the text ends with a backslash".to_owned()),
+        }
+    }
+    Ok(out)
+}
+
+/// Keeps the terminal on stdin in raw mode until dropped, so keys are passed on as they're
+/// typed, Ctrl-C included.
+struct RawMode {
+    #[cfg(unix)]
+    saved: Option<libc::termios>,
+}
+
+impl RawMode {
+    #[cfg(unix)]
+    fn enable() -> RawMode {
+        // SAFETY: termios is plain data, filled in by tcgetattr before it's used.
+        unsafe {
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) < 0 {
+                // Not a terminal, the input is passed on as it comes.
+                return RawMode { saved: None };
+            }
+            let mut raw = saved;
+            libc::cfmakeraw(&mut raw);
+            // Newlines printed meanwhile still start a new line.
+            raw.c_oflag = saved.c_oflag;
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw);
+            RawMode { saved: Some(saved) }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn enable() -> RawMode {
+        RawMode {}
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(saved) = &self.saved {
+            // SAFETY: restores the settings tcgetattr returned.
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved) };
+        }
+    }
+}
+
+/// When the terminal closes by itself, for unattended runs which only watch the output
+pub struct AutoExit {
+    /// After this long without output from the device or keys typed
+    pub idle: Option<Duration>,
+    /// Once the device prints a line matching one of these
+    pub patterns: Vec<Regex>,
+}
+
+impl AutoExit {
+    /// Whether the line the device is printing, maybe not finished yet, ends the terminal
+    fn matches(&self, line: &[u8]) -> Option<&Regex> {
+        if self.patterns.is_empty() {
+            return None;
+        }
+        let line = String::from_utf8_lossy(line);
+        self.patterns.iter().find(|re| re.is_match(&line))
+    }
+}
+
+/// Passes the keys typed to the port and prints what the device sends, like picocom, until
+/// Ctrl-A q is typed, auto_exit says so or the port fails. The keys of macros type their
+/// text instead, Ctrl-A ? lists them.
+pub fn run(port: &mut dyn Transport, auto_exit: &AutoExit, macros: &[Macro]) -> io::Result<()> {
+    if macros.is_empty() {
+        println!("Terminal ready, Ctrl-A q to exit.");
+    } else {
+        println!("Terminal ready, Ctrl-A q to exit, Ctrl-A ? lists the macros.");
+    }
+    let text = |key: MacroKey| macros.iter().rev().find(|m| m.key == key).map(|m| m.text.as_slice());
+    let (tx, rx) = mpsc::channel();
+    // Reading stdin blocks, the thread is left behind when the terminal is closed.
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 64];
+        while let Ok(n) = io::stdin().read(&mut buf) {
+            if n == 0 || tx.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    let _raw = RawMode::enable();
+    port.set_timeout(Duration::from_millis(20))?;
+    let mut stdout = io::stdout();
+    let mut buf = [0u8; 1024];
+    let mut escaped = false;
+    let mut line = Vec::new();
+    let mut active = Instant::now();
+    loop {
+        match port.read(&mut buf) {
+            Ok(n) => {
+                stdout.write_all(&buf[..n])?;
+                stdout.flush()?;
+                active = Instant::now();
+                for &b in &buf[..n] {
+                    if b == b'\n' {
+                        line.clear();
+                    } else {
+                        line.push(b);
+                    }
+                }
+                // Prompts don't end with a newline, the line so far is checked after every read.
+                if let Some(re) = auto_exit.matches(&line) {
+                    println!("\r\nConsole output matched {}, closing the terminal.\r", re);
+                    return Ok(());
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e),
+        }
+        if let Some(idle) = auto_exit.idle {
+            if active.elapsed() >= idle {
+                println!("\r\nNo console activity for {:?}, closing the terminal.\r", idle);
+                return Ok(());
+            }
+        }
+        while let Ok(keys) = rx.try_recv() {
+            active = Instant::now();
+            let mut out = Vec::with_capacity(keys.len());
+            let mut i = 0;
+            while i < keys.len() {
+                let key = keys[i];
+                i += 1;
+                // Terminals send the sequence of an F key in one go.
+                if !escaped && key == 0x1b {
+                    let f_key = F_KEYS.iter().zip(1..).find(|(seq, _)| keys[i - 1..].starts_with(seq));
+                    if let Some((seq, text)) = f_key.and_then(|(seq, n)| Some((seq, text(MacroKey::F(n))?))) {
+                        out.extend_from_slice(text);
+                        i += seq.len() - 1;
+                        continue;
+                    }
+                }
+                match (escaped, key) {
+                    (false, ESCAPE) => escaped = true,
+                    (false, _) => out.push(key),
+                    (true, b'q' | b'Q' | b'x' | b'X') => {
+                        port.write_all(&out)?;
+                        println!("\r");
+                        return Ok(());
+                    }
+                    (true, ESCAPE) => {
+                        escaped = false;
+                        out.push(ESCAPE);
+                    }
+                    (true, b'?') => {
+                        escaped = false;
+                        print!("\r\n");
+                        for m in macros {
+                            print!("{:<10} {}\r\n", m.key.to_string(), String::from_utf8_lossy(&m.text).escape_debug());
+                        }
+                        stdout.flush()?;
+                    }
+                    (true, _) if text(MacroKey::Escaped(key)).is_some() => {
+                        escaped = false;
+                        out.extend_from_slice(text(MacroKey::Escaped(key)).unwrap());
+                    }
+                    // Other keys after the escape are dropped, like in picocom.
+                    (true, _) => escaped = false,
+                }
+            }
+            port.write_all(&out)?;
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use std::fs::File;
+use std::io::Read;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::time::Duration;
+use crate::served;
+
+const OP_RRQ: u16 = 1;
+const OP_WRQ: u16 = 2;
+const OP_DATA: u16 = 3;
+const OP_ACK: u16 = 4;
+const OP_ERROR: u16 = 5;
+const OP_OACK: u16 = 6;
+
+const ERR_NOT_FOUND: u16 = 1;
+const ERR_ACCESS: u16 = 2;
+const ERR_ILLEGAL_OP: u16 = 4;
+
+const DEFAULT_BLKSIZE: usize = 512;
+const MAX_BLKSIZE: usize = 65464;
+const MAX_RETRIES: usize = 5;
+
+struct Request {
+    filename: String,
+    blksize: Option<usize>,
+    tsize: bool,
+}
+
+fn parse_request(pkt: &[u8]) -> Option<Request> {
+    let mut fields = pkt.split(|&b| b == 0).map(|f| String::from_utf8_lossy(f).into_owned());
+    let filename = fields.next()?;
+    let _mode = fields.next()?;
+    let mut req = Request { filename, blksize: None, tsize: false };
+    while let (Some(name), Some(val)) = (fields.next(), fields.next()) {
+        match name.to_ascii_lowercase().as_str() {
+            "blksize" => req.blksize = val.parse::<usize>().ok().map(|b| b.clamp(8, MAX_BLKSIZE)),
+            "tsize" => req.tsize = true,
+            _ => {}
+        }
+    }
+    Some(req)
+}
+
+fn error_packet(code: u16, msg: &str) -> Vec<u8> {
+    let mut pkt = Vec::new();
+    pkt.extend_from_slice(&u16::to_be_bytes(OP_ERROR));
+    pkt.extend_from_slice(&u16::to_be_bytes(code));
+    pkt.extend_from_slice(msg.as_bytes());
+    pkt.push(0);
+    pkt
+}
+
+/// Sends a packet and waits for the ACK of the given block, retransmitting on timeout.
+fn send_and_ack(sock: &UdpSocket, pkt: &[u8], block: u16) -> bool {
+    let mut buf = [0u8; 516];
+    for _ in 0..MAX_RETRIES {
+        sock.send(pkt).ok();
+        while let Ok(len) = sock.recv(&mut buf) {
+            if len >= 4 {
+                let op = u16::from_be_bytes([buf[0], buf[1]]);
+                let ack = u16::from_be_bytes([buf[2], buf[3]]);
+                if op == OP_ACK && ack == block {
+                    return true;
+                }
+                if op == OP_ERROR {
+                    return false;
+                }
+            }
+        }
+    }
+    false
+}
+
+fn serve_file(peer: SocketAddr, mut file: File, req: Request) {
+    let sock = match UdpSocket::bind("0.0.0.0:0") {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    if sock.connect(peer).is_err() {
+        return;
+    }
+    sock.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+    let blksize = req.blksize.unwrap_or(DEFAULT_BLKSIZE);
+    if req.blksize.is_some() || req.tsize {
+        let mut oack = Vec::new();
+        oack.extend_from_slice(&u16::to_be_bytes(OP_OACK));
+        if let Some(b) = req.blksize {
+            oack.extend_from_slice(format!("blksize\0{}\0", b).as_bytes());
+        }
+        if req.tsize {
+            let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+            oack.extend_from_slice(format!("tsize\0{}\0", size).as_bytes());
+        }
+        if !send_and_ack(&sock, &oack, 0) {
+            return;
+        }
+    }
+
+    let mut block: u16 = 1;
+    let mut sent: u64 = 0;
+    let mut data = vec![0u8; blksize];
+    loop {
+        let mut len = 0;
+        while len < blksize {
+            match file.read(&mut data[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(_) => return,
+            }
+        }
+
+        let mut pkt = Vec::with_capacity(len + 4);
+        pkt.extend_from_slice(&u16::to_be_bytes(OP_DATA));
+        pkt.extend_from_slice(&u16::to_be_bytes(block));
+        pkt.extend_from_slice(&data[..len]);
+        if !send_and_ack(&sock, &pkt, block) {
+            println!("TFTP: transfer of {} to {} failed.", req.filename, peer);
+            return;
+        }
+        sent += len as u64;
+        if len < blksize {
+            println!("TFTP: sent {} ({} bytes) to {}", req.filename, sent, peer);
+            return;
+        }
+        block = block.wrapping_add(1);
+    }
+}
+
+/// Starts a read-only TFTP server for files under root in a background thread.
+pub fn spawn(bind: &str, root: &Path) -> std::io::Result<()> {
+    let sock = UdpSocket::bind(bind)?;
+    let root = root.to_path_buf();
+    println!("TFTP server serving {} on {}", root.display(), sock.local_addr()?);
+
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 1024];
+        while let Ok((len, peer)) = sock.recv_from(&mut buf) {
+            if len < 4 {
+                continue;
+            }
+            let op = u16::from_be_bytes([buf[0], buf[1]]);
+            if op == OP_WRQ {
+                sock.send_to(&error_packet(ERR_ACCESS, "read-only server"), peer).ok();
+                continue;
+            } else if op != OP_RRQ {
+                sock.send_to(&error_packet(ERR_ILLEGAL_OP, "illegal operation"), peer).ok();
+                continue;
+            }
+
+            let req = match parse_request(&buf[2..len]) {
+                Some(r) => r,
+                None => continue,
+            };
+            let file = served::resolve(&root, &req.filename).and_then(|p| File::open(p).ok());
+            match file {
+                Some(f) => {
+                    std::thread::spawn(move || serve_file(peer, f, req));
+                }
+                None => {
+                    println!("TFTP: {} requested missing file {}", peer, req.filename);
+                    sock.send_to(&error_packet(ERR_NOT_FOUND, "file not found"), peer).ok();
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Guesses the address of this host by looking at the interface of the default route.
+pub fn guess_host_ip() -> Option<String> {
+    let sock = UdpSocket::bind("0.0.0.0:0").ok()?;
+    sock.connect("192.0.2.1:9").ok()?;
+    Some(sock.local_addr().ok()?.ip().to_string())
+}
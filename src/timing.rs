@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use mtk_uartboot::{Event, Span, Stage};
+
+fn stage_name(stage: Stage) -> &'static str {
+    match stage {
+        Stage::BootRom => "bootrom",
+        Stage::Bl2 => "bl2",
+    }
+}
+
+/// Where the time of a span went, apart from the spans within it
+#[derive(Clone, Copy, Default)]
+struct Split {
+    total: Duration,
+    write: Duration,
+    read: Duration,
+    throttle: Duration,
+}
+
+impl Split {
+    fn of(span: &Span) -> Split {
+        Split { total: span.duration, write: span.write, read: span.read, throttle: span.throttle }
+    }
+
+    fn add(&mut self, other: &Split) {
+        self.total += other.total;
+        self.write += other.write;
+        self.read += other.read;
+        self.throttle += other.throttle;
+    }
+
+    fn minus(&self, other: &Split) -> Split {
+        Split {
+            total: self.total.saturating_sub(other.total),
+            write: self.write.saturating_sub(other.write),
+            read: self.read.saturating_sub(other.read),
+            throttle: self.throttle.saturating_sub(other.throttle),
+        }
+    }
+
+    /// Time outside of transport calls, on the host
+    fn host(&self) -> Duration {
+        self.total.saturating_sub(self.write + self.read + self.throttle)
+    }
+}
+
+/// Collects the spans of the protocol commands for --timing, and writes them as a trace or
+/// as the folded stacks of a flamegraph.
+pub struct Timing {
+    spans: Arc<Mutex<Vec<Span>>>,
+}
+
+impl Timing {
+    pub fn new() -> Timing {
+        Timing { spans: Arc::default() }
+    }
+
+    /// Returns an event observer keeping the spans
+    pub fn observer(&self) -> impl FnMut(&Event) + Send + 'static {
+        let spans = self.spans.clone();
+        move |event| {
+            if let Event::Span(span) = event {
+                spans.lock().unwrap().push(span.clone());
+            }
+        }
+    }
+
+    /// Prints where the time of the commands went, and writes the spans to path: a Chrome
+    /// trace if it ends in .json, folded stacks otherwise.
+    pub fn finish(&self, path: &str) -> io::Result<()> {
+        let spans = self.spans.lock().unwrap();
+        let mut top = Split::default();
+        for span in spans.iter().filter(|s| s.stack.len() == 1) {
+            top.add(&Split::of(span));
+        }
+        println!("Time in protocol commands: {:.2} s, {:.2} s writing, {:.2} s waiting for the device, {:.2} s rate limited, {:.2} s in fixed delays and on the host.",
+                 top.total.as_secs_f64(), top.write.as_secs_f64(), top.read.as_secs_f64(),
+                 top.throttle.as_secs_f64(), top.host().as_secs_f64());
+        let text = if path.ends_with(".json") {
+            chrome_trace(&spans)
+        } else {
+            folded(&spans)
+        };
+        std::fs::write(path, text)?;
+        println!("Timing of {} commands written to {}.", spans.len(), path);
+        Ok(())
+    }
+}
+
+/// The Trace Event Format of chrome://tracing and Perfetto, a complete event per span, with
+/// the split of its time in the arguments.
+fn chrome_trace(spans: &[Span]) -> String {
+    let Some(epoch) = spans.iter().map(|s| s.start).min() else {
+        return "{\"traceEvents\":[]}\n".to_owned();
+    };
+    let events: Vec<String> = spans.iter().map(|span| chrome_event(span, epoch)).collect();
+    format!("{{\"traceEvents\":[\n{}\n],\"displayTimeUnit\":\"ms\"}}\n", events.join(",\n"))
+}
+
+fn chrome_event(span: &Span, epoch: Instant) -> String {
+    // Names are identifiers of the library, nothing to escape.
+    format!("{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":1,\"tid\":1,\
+             \"args\":{{\"write_us\":{},\"read_us\":{},\"throttle_us\":{}}}}}",
+            span.stack.last().unwrap_or(&""), stage_name(span.stage), (span.start - epoch).as_micros(), span.duration.as_micros(),
+            span.write.as_micros(), span.read.as_micros(), span.throttle.as_micros())
+}
+
+/// Folded stacks as flamegraph.pl and inferno take them, in microseconds. The time of every
+/// span is split into leaves for writing, reading and rate limiting, and what's left is its
+/// own, spent on the host.
+fn folded(spans: &[Span]) -> String {
+    // Spans within another one come before it, so their times are there to subtract.
+    let mut children: BTreeMap<String, Split> = BTreeMap::new();
+    let mut samples: BTreeMap<String, u128> = BTreeMap::new();
+    for span in spans {
+        let path = format!("{};{}", stage_name(span.stage), span.stack.join(";"));
+        let split = Split::of(span);
+        let own = split.minus(&children.remove(&path).unwrap_or_default());
+        let parent = path.rsplit_once(';').map(|(parent, _)| parent.to_owned()).unwrap_or_default();
+        children.entry(parent).or_default().add(&split);
+
+        for (leaf, time) in [("write", own.write), ("read", own.read), ("throttle", own.throttle)] {
+            *samples.entry(format!("{};{}", path, leaf)).or_default() += time.as_micros();
+        }
+        *samples.entry(path).or_default() += own.host().as_micros();
+    }
+    samples.iter()
+        .filter(|(_, &us)| us > 0)
+        .map(|(path, us)| format!("{} {}\n", path, us))
+        .collect()
+}
@@ -0,0 +1,111 @@
+use serde::Deserialize;
+use mtk_uartboot::digest::{Sha256, to_hex};
+
+/// What a token may do. Every role may do what the ones before it may.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Look at jobs and metrics
+    Read,
+    /// Submit and cancel jobs
+    Submit,
+    /// Submit jobs running commands on the station, e.g. post-boot hooks
+    Admin,
+}
+
+impl Role {
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Read => "read",
+            Role::Submit => "submit",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Token {
+    /// Who the token belongs to, recorded in the audit log
+    name: String,
+    role: Role,
+    /// SHA-256 of the token, so the file holds no secrets
+    sha256: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokensFile {
+    token: Vec<Token>,
+}
+
+/// The API tokens of the daemon.
+///
+/// ```toml
+/// [[token]]
+/// name = "ci"
+/// role = "submit"
+/// sha256 = "<hex digest of the token>"
+/// ```
+pub struct Tokens(Vec<Token>);
+
+impl Tokens {
+    pub fn load(path: &str) -> Tokens {
+        let text = std::fs::read_to_string(path).expect("failed to open tokens file.");
+        let file: TokensFile = toml::from_str(&text)
+            .unwrap_or_else(|e| panic!("invalid tokens file {}: {}", path, e));
+        Tokens(file.token)
+    }
+
+    /// The name and role of a bearer token, if it's a known one
+    pub fn check(&self, token: &str) -> Option<(&str, Role)> {
+        let digest = to_hex(&Sha256::digest(token.as_bytes()));
+        self.0.iter()
+            .find(|t| t.sha256.eq_ignore_ascii_case(&digest))
+            .map(|t| (t.name.as_str(), t.role))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(text: &str) -> Result<Tokens, toml::de::Error> {
+        toml::from_str::<TokensFile>(text).map(|file| Tokens(file.token))
+    }
+
+    fn entry(name: &str, role: &str, token: &str) -> String {
+        format!("[[token]]\nname = \"{}\"\nrole = \"{}\"\nsha256 = \"{}\"\n", name, role, to_hex(&Sha256::digest(token.as_bytes())))
+    }
+
+    #[test]
+    fn every_role_may_do_what_the_ones_before_it_may() {
+        assert!(Role::Read < Role::Submit && Role::Submit < Role::Admin);
+        for role in [Role::Read, Role::Submit, Role::Admin] {
+            let text = format!("[[token]]\nname = \"x\"\nrole = \"{}\"\nsha256 = \"\"\n", role.name());
+            assert_eq!(tokens(&text).unwrap().0[0].role, role);
+        }
+    }
+
+    #[test]
+    fn tokens_are_known_by_their_digest() {
+        let admin = entry("lab", "admin", "a-token");
+        let digest = to_hex(&Sha256::digest(b"a-token"));
+        let text = [entry("dashboard", "read", "r-token"), entry("ci", "submit", "s-token"),
+                     admin.replace(&digest, &digest.to_ascii_uppercase())].concat();
+        let tokens = tokens(&text).unwrap();
+        assert_eq!(tokens.check("r-token"), Some(("dashboard", Role::Read)));
+        assert_eq!(tokens.check("s-token"), Some(("ci", Role::Submit)));
+        // Upper case digests match too
+        assert_eq!(tokens.check("a-token"), Some(("lab", Role::Admin)));
+        assert_eq!(tokens.check("A-TOKEN"), None);
+        assert_eq!(tokens.check(""), None);
+    }
+
+    #[test]
+    fn invalid_tokens_files_are_refused() {
+        assert!(tokens(&entry("ci", "owner", "t")).is_err());
+        assert!(tokens(&format!("{}comment = \"x\"\n", entry("ci", "submit", "t"))).is_err());
+        assert!(tokens("[[token]]\nname = \"ci\"\nrole = \"submit\"\n").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use mtk_uartboot::Transport;
+
+/// The file of --trace and when tracing started, shared by the ports of parallel runs
+static TRACE: OnceLock<(Mutex<File>, Instant)> = OnceLock::new();
+
+/// The ports a secret is written to, e.g. a password typed into the console, once per guard
+static HIDDEN: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Starts tracing the ports opened from now on into the file at path.
+pub fn enable(path: &str) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "# mtk_uartboot {} trace, seconds since start, > to the device, < from it",
+             env!("CARGO_PKG_VERSION"))?;
+    TRACE.set((Mutex::new(file), Instant::now())).ok();
+    Ok(())
+}
+
+/// Wraps port in a transport writing all its traffic into the trace, if tracing is enabled.
+pub fn wrap(name: &str, port: Box<dyn Transport>) -> Box<dyn Transport> {
+    match TRACE.get() {
+        Some(_) => Box::new(Traced { name: name.to_owned(), inner: port }),
+        None => port,
+    }
+}
+
+/// Keeps what's written to a port out of the trace until dropped.
+#[cfg(feature = "pipeline")]
+pub struct Hidden(String);
+
+/// Hides what's written to the port named port from the trace while the returned guard
+/// lives. The other ports of a parallel run are traced as usual.
+#[cfg(feature = "pipeline")]
+pub fn hide(port: &str) -> Hidden {
+    HIDDEN.lock().unwrap().push(port.to_owned());
+    Hidden(port.to_owned())
+}
+
+#[cfg(feature = "pipeline")]
+impl Drop for Hidden {
+    fn drop(&mut self) {
+        let mut hidden = HIDDEN.lock().unwrap();
+        if let Some(i) = hidden.iter().position(|p| *p == self.0) {
+            hidden.swap_remove(i);
+        }
+    }
+}
+
+fn is_hidden(port: &str) -> bool {
+    HIDDEN.lock().unwrap().iter().any(|p| p == port)
+}
+
+/// Appends an entry to the trace: a line with the time, the port and what happened, and
+/// for data, a hexdump of it.
+fn record(port: &str, what: &str, data: &[u8]) {
+    let Some((file, start)) = TRACE.get() else {
+        return;
+    };
+    let mut text = format!("{:12.6} {} {}\n", start.elapsed().as_secs_f64(), port, what);
+    for (i, row) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = row.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = row.iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        text += &format!("    {:08x}  {:<47}  |{}|\n", i * 16, hex.join(" "), ascii);
+    }
+    // The trace is for debugging, failing to write it doesn't fail the boot.
+    let mut file = file.lock().unwrap();
+    file.write_all(text.as_bytes()).ok();
+}
+
+/// A transport recording everything going through it in the trace
+struct Traced {
+    name: String,
+    inner: Box<dyn Transport>,
+}
+
+impl Read for Traced {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.inner.read(buf);
+        match &result {
+            Ok(n) => record(&self.name, &format!("< {} bytes", n), &buf[..*n]),
+            // Timeouts are how consoles are polled, they'd drown the data.
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => record(&self.name, &format!("< error: {}", e), &[]),
+        }
+        result
+    }
+}
+
+impl Write for Traced {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.inner.write(buf);
+        match &result {
+            Ok(n) if is_hidden(&self.name) => record(&self.name, &format!("> {} bytes [redacted]", n), &[]),
+            Ok(n) => record(&self.name, &format!("> {} bytes", n), &buf[..*n]),
+            Err(e) => record(&self.name, &format!("> error: {}", e), &[]),
+        }
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Transport for Traced {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.inner.set_timeout(timeout)
+    }
+
+    fn read_timeout(&self) -> Option<Duration> {
+        self.inner.read_timeout()
+    }
+
+    fn set_baud_rate(&mut self, baudrate: u32) -> io::Result<()> {
+        record(&self.name, &format!("baud rate {}", baudrate), &[]);
+        self.inner.set_baud_rate(baudrate)
+    }
+
+    fn clear_input(&mut self) -> io::Result<()> {
+        record(&self.name, "input cleared", &[]);
+        self.inner.clear_input()
+    }
+
+    fn drain(&mut self) -> io::Result<()> {
+        self.inner.drain()
+    }
+}
+
+#[cfg(all(test, feature = "pipeline"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ports_are_hidden_one_by_one() {
+        let first = hide("/dev/ttyUSB0");
+        let second = hide("/dev/ttyUSB1");
+        let again = hide("/dev/ttyUSB1");
+        drop(first);
+        assert!(!is_hidden("/dev/ttyUSB0") && is_hidden("/dev/ttyUSB1"));
+        drop(again);
+        assert!(is_hidden("/dev/ttyUSB1"));
+        drop(second);
+        assert!(!is_hidden("/dev/ttyUSB1"));
+    }
+}
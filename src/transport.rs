@@ -0,0 +1,217 @@
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serialport::{ClearBuffer, SerialPort};
+
+/// A byte stream to the device, usually a serial port.
+///
+/// Implement this to run the protocols over something other than a local serial port,
+/// e.g. a network bridge.
+pub trait Transport: Read + Write + Send {
+    /// Sets how long reads wait for data before failing with `TimedOut`
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()>;
+
+    /// Sets how long writes wait for the device to take data before failing with
+    /// `TimedOut`. Transports with a single timeout, like serial ports, ignore it; the
+    /// transport of a [`Session`](crate::Session) keeps the two apart for any of them.
+    fn set_write_timeout(&mut self, _timeout: Duration) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// The timeout set with `set_timeout`, if the transport can tell. Readers polling the
+    /// transport in shorter slices keep the timeout of their caller with it.
+    fn read_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Changes the line speed. Transports without a baud rate of their own may ignore this.
+    fn set_baud_rate(&mut self, baudrate: u32) -> io::Result<()>;
+
+    /// Throws away received data that hasn't been read yet
+    fn clear_input(&mut self) -> io::Result<()>;
+
+    /// Blocks until everything written has actually left the host
+    fn drain(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl Transport for Box<dyn SerialPort> {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        Ok(SerialPort::set_timeout(self.as_mut(), timeout)?)
+    }
+
+    fn read_timeout(&self) -> Option<Duration> {
+        Some(SerialPort::timeout(self.as_ref()))
+    }
+
+    fn set_baud_rate(&mut self, baudrate: u32) -> io::Result<()> {
+        Ok(SerialPort::set_baud_rate(self.as_mut(), baudrate)?)
+    }
+
+    fn clear_input(&mut self) -> io::Result<()> {
+        Ok(self.clear(ClearBuffer::Input)?)
+    }
+
+    fn drain(&mut self) -> io::Result<()> {
+        self.flush()?;
+        while self.bytes_to_write()? > 0 {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        Ok(())
+    }
+}
+
+/// Time spent in the calls of a [`RateLimited`] transport since the session started.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct IoTimes {
+    pub(crate) read: Duration,
+    pub(crate) write: Duration,
+    pub(crate) throttle: Duration,
+}
+
+/// Adds up the time a transport spends reading, writing and rate limited, for profiling.
+/// Shared with the events of the session, which take the differences for every span.
+#[derive(Default)]
+pub(crate) struct IoClock {
+    enabled: AtomicBool,
+    read: AtomicU64,
+    write: AtomicU64,
+    throttle: AtomicU64,
+}
+
+impl IoClock {
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Runs f, adding the time it took to counter if the clock runs
+    fn time<T>(&self, counter: &AtomicU64, f: impl FnOnce() -> T) -> T {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return f();
+        }
+        let start = Instant::now();
+        let result = f();
+        counter.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        result
+    }
+
+    pub(crate) fn now(&self) -> IoTimes {
+        let load = |counter: &AtomicU64| Duration::from_nanos(counter.load(Ordering::Relaxed));
+        IoTimes { read: load(&self.read), write: load(&self.write), throttle: load(&self.throttle) }
+    }
+}
+
+/// A transport whose writes are paced to at most max_rate bytes per second, for links which
+/// corrupt data at the full speed of the baud rate. Without a rate, it's the inner transport.
+///
+/// With a write timeout, the single timeout of the inner transport is switched between the
+/// one of reads and the one of writes as needed, so waiting for answers can be short while
+/// large writes to a slow link still complete.
+pub(crate) struct RateLimited {
+    pub(crate) inner: Box<dyn Transport>,
+    pub(crate) max_rate: Option<u32>,
+    /// When the next byte may be written
+    next: Instant,
+    read_timeout: Option<Duration>,
+    pub(crate) write_timeout: Option<Duration>,
+    /// The timeout the inner transport is set to
+    current: Option<Duration>,
+    pub(crate) clock: Arc<IoClock>,
+}
+
+impl RateLimited {
+    pub(crate) fn new(inner: Box<dyn Transport>) -> RateLimited {
+        // Transports which can't tell their timeout get one with the first set_timeout.
+        let timeout = inner.read_timeout();
+        RateLimited {
+            inner,
+            max_rate: None,
+            next: Instant::now(),
+            read_timeout: timeout,
+            write_timeout: None,
+            current: timeout,
+            clock: Arc::default(),
+        }
+    }
+
+    /// Sets the inner transport to timeout, unless it already is
+    fn switch_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        match timeout {
+            Some(t) if self.current != timeout => {
+                self.inner.set_timeout(t)?;
+                self.current = timeout;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Read for RateLimited {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.write_timeout.is_some() {
+            self.switch_timeout(self.read_timeout)?;
+        }
+        let inner = &mut self.inner;
+        self.clock.time(&self.clock.read, || inner.read(buf))
+    }
+}
+
+impl Write for RateLimited {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.switch_timeout(self.write_timeout)?;
+        let (inner, clock) = (&mut self.inner, &self.clock);
+        let Some(rate) = self.max_rate.filter(|&r| r > 0) else {
+            return clock.time(&clock.write, || inner.write(buf));
+        };
+        // Chunks of 10 ms keep the pace even within large writes.
+        let chunk = (rate as usize / 100).max(1);
+        let now = Instant::now();
+        match self.next.checked_duration_since(now) {
+            Some(wait) => clock.time(&clock.throttle, || std::thread::sleep(wait)),
+            // Time spent idle isn't saved up for a burst.
+            None => self.next = now,
+        }
+        let n = clock.time(&clock.write, || inner.write(&buf[..buf.len().min(chunk)]))?;
+        self.next += Duration::from_secs_f64(n as f64 / rate as f64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let inner = &mut self.inner;
+        self.clock.time(&self.clock.write, || inner.flush())
+    }
+}
+
+impl Transport for RateLimited {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.inner.set_timeout(timeout)?;
+        self.read_timeout = Some(timeout);
+        self.current = Some(timeout);
+        Ok(())
+    }
+
+    fn set_write_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.write_timeout = Some(timeout);
+        Ok(())
+    }
+
+    fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout.or_else(|| self.inner.read_timeout())
+    }
+
+    fn set_baud_rate(&mut self, baudrate: u32) -> io::Result<()> {
+        self.inner.set_baud_rate(baudrate)
+    }
+
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.inner.clear_input()
+    }
+
+    fn drain(&mut self) -> io::Result<()> {
+        let inner = &mut self.inner;
+        self.clock.time(&self.clock.write, || inner.drain())
+    }
+}
@@ -0,0 +1,67 @@
+use std::path::Path;
+use std::time::Duration;
+use mtk_uartboot::Transport;
+use crate::console::{ConsoleView, wait_for_match};
+use crate::served;
+use crate::ymodem;
+
+const REQUEST_START: &str = "<<UARTBOOT:";
+const REQUEST_END: &str = ">>";
+
+/// An action requested by the code running on the device.
+#[derive(Debug, PartialEq, Eq)]
+enum Request {
+    /// Send a file from the served directory over YMODEM
+    Send(String),
+    /// Stop serving requests
+    Done,
+}
+
+fn parse_request(line: &str) -> Option<Request> {
+    let start = line.find(REQUEST_START)? + REQUEST_START.len();
+    let len = line[start..].find(REQUEST_END)?;
+    let mut words = line[start..start + len].split_whitespace();
+    match (words.next()?, words.next()) {
+        ("SEND", Some(name)) => Some(Request::Send(name.to_owned())),
+        ("DONE", None) => Some(Request::Done),
+        _ => None,
+    }
+}
+
+/// Watches the console for requests printed by the device and carries them out until
+/// the device says it's done. Returns false if the device went quiet before that or a
+/// transfer it requested failed.
+pub fn serve(port: &mut dyn Transport, dir: &Path, idle_timeout: Duration, view: &mut ConsoleView) -> bool {
+    println!("Serving device requests from {}:", dir.display());
+    loop {
+        port.set_timeout(idle_timeout).unwrap();
+        let line = match wait_for_match(port, REQUEST_START, view) {
+            Some(l) => l,
+            None => return false,
+        };
+
+        match parse_request(&line) {
+            Some(Request::Send(name)) => {
+                let data = match served::resolve(dir, &name).map(std::fs::read) {
+                    Some(Ok(d)) => d,
+                    _ => {
+                        println!("Device requested {}, which can't be read.", name);
+                        continue;
+                    }
+                };
+                println!("Device requested {}. Sending over YMODEM...", name);
+                if let Err(e) = ymodem::send(port, &name, &data) {
+                    println!("YMODEM transfer of {} failed: {}", name, e);
+                    return false;
+                }
+                println!("YMODEM transfer done.");
+            }
+            Some(Request::Done) => {
+                println!("Device is done with requests.");
+                port.set_timeout(Duration::from_secs(2)).unwrap();
+                return true;
+            }
+            None => println!("Ignoring malformed device request: {}", line.trim_end()),
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::fmt;
+use crate::error::{Error, Result};
+
+/// An address in the device's memory map
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LoadAddr(pub u32);
+
+/// A length in bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ByteLen(pub u32);
+
+/// A UART baud rate
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Baud(pub u32);
+
+impl fmt::Display for LoadAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+impl fmt::LowerHex for LoadAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for ByteLen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for Baud {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl ByteLen {
+    /// The length of a buffer, which must fit the 32-bit length fields of the protocols
+    pub fn of(buf: &[u8]) -> Result<ByteLen> {
+        u32::try_from(buf.len()).map(ByteLen).map_err(|_| Error::TooLong(buf.len()))
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use ring::rand::{SecureRandom, SystemRandom};
+use mtk_uartboot::{Event, Stage, Transport};
+
+/// Subprotocol offered to bridges, which may ignore it
+const SUBPROTOCOL: &str = "mtk-uartboot";
+/// Appended to the key of the handshake for the accept header of the answer, RFC 6455
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Largest frame sent, bigger writes take several calls
+const MAX_FRAME_LEN: usize = 1 << 16;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+/// A serial port behind a WebSocket, e.g. a browser page bridging a WebSerial port or a web
+/// bridge in a lab rack. Binary messages carry the bytes of the UART both ways. Text messages
+/// sent to the bridge are JSON objects: {"type":"baudrate","baudrate":N} asks it to switch
+/// the UART to another speed, {"type":"event",...} tells it how the session is going. Text
+/// messages of the bridge are ignored.
+pub struct WebSocket {
+    stream: TcpStream,
+    /// Received bytes not yet parsed into frames
+    raw: Vec<u8>,
+    /// UART bytes received but not read yet
+    pending: VecDeque<u8>,
+    /// Whether continuation frames belong to a text message
+    in_text: bool,
+}
+
+/// Sends a frame, masked as every frame of a client has to be.
+fn send_frame(stream: &mut TcpStream, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut mask = [0u8; 4];
+    SystemRandom::new().fill(&mut mask).map_err(|_| io::Error::other("no random numbers for the frame mask"))?;
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => frame.push(0x80 | len as u8),
+        len @ 126..=0xffff => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    stream.write_all(&frame)
+}
+
+/// Takes the first complete frame off buf, returning its opcode and payload.
+fn parse_frame(buf: &mut Vec<u8>) -> Option<(u8, Vec<u8>)> {
+    let (&b0, &b1) = (buf.first()?, buf.get(1)?);
+    let (len, mut pos) = match b1 & 0x7f {
+        126 => (u16::from_be_bytes(buf.get(2..4)?.try_into().unwrap()) as usize, 4),
+        127 => (u64::from_be_bytes(buf.get(2..10)?.try_into().unwrap()) as usize, 10),
+        len => (len as usize, 2),
+    };
+    let mask = if b1 & 0x80 != 0 {
+        pos += 4;
+        Some(<[u8; 4]>::try_from(buf.get(pos - 4..pos)?).unwrap())
+    } else {
+        None
+    };
+    let end = len.checked_add(pos)?;
+    if buf.len() < end {
+        return None;
+    }
+    let mut payload: Vec<u8> = buf.drain(..end).skip(pos).collect();
+    if let Some(mask) = mask {
+        payload.iter_mut().enumerate().for_each(|(i, b)| *b ^= mask[i % 4]);
+    }
+    Some((b0 & 0x0f, payload))
+}
+
+/// Splits ws://HOST[:PORT][/PATH] into an address to connect to, the host and the path.
+fn parse_url(url: &str) -> Option<(String, &str, &str)> {
+    let rest = url.strip_prefix("ws://")?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    let addr = if host.ends_with(']') || !host.contains(':') { format!("{}:80", host) } else { host.to_owned() };
+    Some((addr, host, path))
+}
+
+impl WebSocket {
+    /// Connects to a bridge at ws://HOST[:PORT][/PATH]
+    pub fn connect(url: &str) -> io::Result<WebSocket> {
+        let (addr, host, path) = parse_url(url)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "expected ws://HOST[:PORT][/PATH]"))?;
+        let mut stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+
+        let mut nonce = [0u8; 16];
+        SystemRandom::new().fill(&mut nonce).map_err(|_| io::Error::other("no random numbers for the handshake"))?;
+        let key = STANDARD.encode(nonce);
+        write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                        Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Protocol: {}\r\n\r\n",
+               path, host, key, SUBPROTOCOL)?;
+
+        // Byte by byte, the frames right after the headers aren't ours to read here.
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8];
+            if stream.read(&mut byte)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the bridge closed the connection"));
+            }
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8_lossy(&head);
+        let mut lines = head.lines();
+        let status = lines.next().unwrap_or_default();
+        if status.split_whitespace().nth(1) != Some("101") {
+            return Err(io::Error::other(format!("the bridge refused the WebSocket: {}", status)));
+        }
+        let digest = ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, format!("{}{}", key, ACCEPT_GUID).as_bytes());
+        let accept = STANDARD.encode(digest);
+        let accepted = lines.filter_map(|l| l.split_once(':'))
+            .any(|(name, value)| name.eq_ignore_ascii_case("sec-websocket-accept") && value.trim() == accept);
+        if !accepted {
+            return Err(io::Error::other("the bridge answered with a wrong Sec-WebSocket-Accept"));
+        }
+        Ok(WebSocket { stream, raw: Vec::new(), pending: VecDeque::new(), in_text: false })
+    }
+
+    /// Handles the frames received completely, returning whether there were any.
+    fn process_frames(&mut self) -> io::Result<bool> {
+        let mut any = false;
+        while let Some((opcode, payload)) = parse_frame(&mut self.raw) {
+            any = true;
+            match opcode {
+                OP_BINARY => {
+                    self.in_text = false;
+                    self.pending.extend(payload);
+                }
+                OP_TEXT => self.in_text = true,
+                OP_CONTINUATION if !self.in_text => self.pending.extend(payload),
+                OP_CLOSE => {
+                    // Best effort, the connection is going away anyway.
+                    send_frame(&mut self.stream, OP_CLOSE, &payload[..payload.len().min(2)]).ok();
+                    return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "the bridge closed the WebSocket"));
+                }
+                OP_PING => send_frame(&mut self.stream, OP_PONG, &payload)?,
+                _ => {}
+            }
+        }
+        Ok(any)
+    }
+
+    /// Returns an event observer sending the events of the session to the bridge
+    pub fn observer(&self) -> io::Result<impl FnMut(&Event) + Send + 'static> {
+        let mut stream = self.stream.try_clone()?;
+        Ok(move |event: &Event| {
+            let stage = |stage: &Stage| match stage {
+                Stage::BootRom => "brom",
+                Stage::Bl2 => "bl2",
+            };
+            let fields = match event {
+                Event::HandshakeDone(s) => format!("\"event\":\"handshake\",\"stage\":\"{}\"", stage(s)),
+                Event::BaudrateChanged { stage: s, baudrate } =>
+                    format!("\"event\":\"baudrate\",\"stage\":\"{}\",\"baudrate\":{}", stage(s), baudrate.0),
+                Event::Progress { stage: s, sent, total } =>
+                    format!("\"event\":\"progress\",\"stage\":\"{}\",\"sent\":{},\"total\":{}", stage(s), sent, total),
+                Event::PacketRejected { idx, .. } => format!("\"event\":\"packet_rejected\",\"idx\":{}", idx),
+                _ => return,
+            };
+            // Only for showing, a bridge which stopped listening fails the next write anyway.
+            send_frame(&mut stream, OP_TEXT, format!("{{\"type\":\"event\",{}}}", fields).as_bytes()).ok();
+        })
+    }
+}
+
+impl Read for WebSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if !self.pending.is_empty() {
+                let n = buf.len().min(self.pending.len());
+                for (dst, src) in buf.iter_mut().zip(self.pending.drain(..n)) {
+                    *dst = src;
+                }
+                return Ok(n);
+            }
+            if self.process_frames()? {
+                continue;
+            }
+            let mut chunk = [0u8; 4096];
+            let n = self.stream.read(&mut chunk).map_err(|e| match e.kind() {
+                // A receive timeout shows up as EAGAIN on sockets.
+                io::ErrorKind::WouldBlock => io::ErrorKind::TimedOut.into(),
+                _ => e,
+            })?;
+            if n == 0 {
+                return Ok(0);
+            }
+            self.raw.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+impl Write for WebSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(MAX_FRAME_LEN);
+        send_frame(&mut self.stream, OP_BINARY, &buf[..len])?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for WebSocket {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        // A zero timeout means none to sockets.
+        self.stream.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))
+    }
+
+    fn read_timeout(&self) -> Option<Duration> {
+        self.stream.read_timeout().ok().flatten()
+    }
+
+    fn set_baud_rate(&mut self, baudrate: u32) -> io::Result<()> {
+        send_frame(&mut self.stream, OP_TEXT, format!("{{\"type\":\"baudrate\",\"baudrate\":{}}}", baudrate).as_bytes())
+    }
+
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.stream.set_nonblocking(true)?;
+        let mut chunk = [0u8; 4096];
+        let result = loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => break Ok(()),
+                Ok(n) => self.raw.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result?;
+        self.process_frames()?;
+        self.pending.clear();
+        Ok(())
+    }
+}
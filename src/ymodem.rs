@@ -0,0 +1,218 @@
+use std::io;
+use std::slice;
+use std::time::{Duration, Instant};
+use mtk_uartboot::Transport;
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const CRC_REQ: u8 = b'C';
+const PAD: u8 = 0x1a;
+
+const MAX_RETRIES: usize = 10;
+
+/// Largest block, which the header block with the name and size of the file has to fit
+const MAX_BLOCK_LEN: usize = 1024;
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            if crc & 0x8000 != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+fn read_byte(port: &mut dyn Transport) -> Option<u8> {
+    let mut rx_char = 0;
+    match port.read_exact(slice::from_mut(&mut rx_char)) {
+        Ok(()) => Some(rx_char),
+        Err(_) => None,
+    }
+}
+
+fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, "YMODEM transfer cancelled by receiver")
+}
+
+fn wait_for_crc_request(port: &mut dyn Transport, timeout: Duration) -> io::Result<()> {
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        match read_byte(port) {
+            Some(CRC_REQ) => return Ok(()),
+            Some(CAN) => return Err(cancelled()),
+            _ => {}
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::TimedOut, "timeout waiting for YMODEM receiver"))
+}
+
+fn send_block(port: &mut dyn Transport, seq: u8, data: &[u8]) -> io::Result<()> {
+    let (header, len) = if data.len() > 128 { (STX, MAX_BLOCK_LEN) } else { (SOH, 128) };
+    let mut block = Vec::with_capacity(len + 5);
+    block.extend_from_slice(&[header, seq, !seq]);
+    block.extend_from_slice(data);
+    block.resize(len + 3, PAD);
+    let crc = crc16(&block[3..]);
+    block.extend_from_slice(&u16::to_be_bytes(crc));
+
+    for _ in 0..MAX_RETRIES {
+        port.write_all(&block)?;
+        match read_byte(port) {
+            Some(ACK) => return Ok(()),
+            Some(CAN) => return Err(cancelled()),
+            _ => port.clear_input()?,
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::TimedOut, format!("YMODEM block {} not acknowledged", seq)))
+}
+
+/// Sends a file to a YMODEM receiver, e.g. the `loady` command in U-Boot. Fails if the
+/// receiver cancels or stops answering, or if the name is too long for the header block.
+pub fn send(port: &mut dyn Transport, name: &str, data: &[u8]) -> io::Result<()> {
+    let mut header = Vec::with_capacity(128);
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(data.len().to_string().as_bytes());
+    header.push(0);
+    if header.len() > MAX_BLOCK_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("the name {} is too long for YMODEM", name)));
+    }
+    // Names too long for a 128 byte block get a 1 KiB one.
+    header.resize(if header.len() > 128 { MAX_BLOCK_LEN } else { 128 }, 0);
+
+    port.set_timeout(Duration::from_secs(2))?;
+    wait_for_crc_request(port, Duration::from_secs(60))?;
+    send_block(port, 0, &header)?;
+    wait_for_crc_request(port, Duration::from_secs(10))?;
+
+    for (i, chunk) in data.chunks(MAX_BLOCK_LEN).enumerate() {
+        send_block(port, (i + 1) as u8, chunk)?;
+    }
+
+    let mut acked = false;
+    for _ in 0..MAX_RETRIES {
+        port.write_all(slice::from_ref(&EOT))?;
+        match read_byte(port) {
+            Some(ACK) => {
+                acked = true;
+                break;
+            }
+            Some(NAK) => continue,
+            Some(CAN) => return Err(cancelled()),
+            _ => {}
+        }
+    }
+    if !acked {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "YMODEM end of transfer not acknowledged"));
+    }
+
+    // An empty header block ends the batch.
+    wait_for_crc_request(port, Duration::from_secs(10))?;
+    send_block(port, 0, &[0; 128])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use super::*;
+
+    /// A receiver answering with the bytes it was given, whatever is sent
+    #[derive(Default)]
+    struct Scripted {
+        answer: Vec<u8>,
+        sent: Vec<u8>,
+    }
+
+    impl Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.answer.len());
+            if len == 0 {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            buf[..len].copy_from_slice(&self.answer[..len]);
+            self.answer.drain(..len);
+            Ok(len)
+        }
+    }
+
+    impl Write for Scripted {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for Scripted {
+        fn set_timeout(&mut self, _timeout: Duration) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn set_baud_rate(&mut self, _baudrate: u32) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn clear_input(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn receiver(answer: &[u8]) -> Scripted {
+        Scripted { answer: answer.to_vec(), ..Scripted::default() }
+    }
+
+    #[test]
+    fn file_is_sent_in_blocks() {
+        let mut port = receiver(&[CRC_REQ, ACK, CRC_REQ, ACK, ACK, CRC_REQ, ACK]);
+        send(&mut port, "fit.itb", &[0x5a; 200]).unwrap();
+        // The header, the data in a 1 KiB block, the end and the empty header
+        assert_eq!(port.sent.len(), 133 + 1029 + 1 + 133);
+        assert_eq!(&port.sent[..15], b"\x01\x00\xfffit.itb\x00200\x00");
+        assert_eq!(&port.sent[133..136], [STX, 1, 0xfe]);
+    }
+
+    #[test]
+    fn long_names_get_a_1k_header_block() {
+        let name = "a".repeat(200);
+        let mut port = receiver(&[CRC_REQ, ACK, CRC_REQ, ACK, ACK, CRC_REQ, ACK]);
+        send(&mut port, &name, b"data").unwrap();
+        assert_eq!(&port.sent[..3], [STX, 0, 0xff]);
+        assert_eq!(&port.sent[3..203], name.as_bytes());
+    }
+
+    #[test]
+    fn names_too_long_for_any_block_are_refused() {
+        let mut port = receiver(&[CRC_REQ]);
+        let e = send(&mut port, &"a".repeat(1024), b"data").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(port.sent.is_empty());
+    }
+
+    #[test]
+    fn cancel_is_an_error() {
+        let mut port = receiver(&[CRC_REQ, CAN]);
+        let e = send(&mut port, "fit.itb", b"data").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
+    }
+
+    #[test]
+    fn blocks_never_acknowledged_are_an_error() {
+        let mut port = receiver(&[CRC_REQ]);
+        let e = send(&mut port, "fit.itb", b"data").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(port.sent.len(), MAX_RETRIES * 133);
+    }
+}
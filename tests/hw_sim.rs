@@ -0,0 +1,433 @@
+//! End-to-end tests of the tool against the scripted device of `sim`, run with
+//! `cargo test --features hw-sim`.
+
+mod sim;
+
+use std::path::PathBuf;
+use mtk_uartboot::stream::{self, RunStage, StreamEvent};
+use sim::{Device, FIP_PARTITION, Options, SOC_ID, fip, run_tool, run_tool_with_input};
+
+/// Writes data to a file in a directory of its own for the test
+fn image(test: &str, name: &str, data: &[u8]) -> String {
+    let dir: PathBuf = std::env::temp_dir().join(format!("mtk_uartboot-hw-sim-{}-{}", std::process::id(), test));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, data).unwrap();
+    path.to_str().unwrap().to_owned()
+}
+
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect()
+}
+
+#[test]
+fn boots_payload_and_fip() {
+    let payload = data(0x1234, 1);
+    let fip = fip(&data(40_000, 2), &data(260_000, 6));
+    let device = Device::start(Options::default());
+    let (status, output) = run_tool(&["--serial", &device.url, "--payload", &image("boot", "bl2.bin", &payload),
+                                      "--aarch64", "--fip", &image("boot", "fip.bin", &fip)]);
+    let log = device.finish();
+    assert!(status.success(), "{}", output);
+    assert_eq!(log.payload, Some((0x201000, payload)));
+    assert_eq!(log.jump, Some((0x201000, true)));
+    assert!(log.fip.as_deref() == Some(&fip[..]), "{}", output);
+    assert!(log.go);
+}
+
+#[test]
+fn patched_fip_is_prepared_once() {
+    let payload = image("cached", "bl2.bin", &data(0x1234, 1));
+    let fip_path = image("cached", "fip.bin", &fip(&data(40_000, 2), &data(260_000, 6)));
+    let cache_dir = PathBuf::from(&payload).with_file_name("cache");
+    let cache_dir = cache_dir.to_str().unwrap();
+    let mut outputs = Vec::new();
+    for _ in 0..2 {
+        let device = Device::start(Options::default());
+        let (status, output) = run_tool(&["--serial", &device.url, "--payload", &payload, "--aarch64", "--fip", &fip_path,
+                                          "--fip-patch", "soc-fw+0x10:c0ffee", "--cache-dir", cache_dir]);
+        let log = device.finish();
+        assert!(status.success(), "{}", output);
+        assert!(log.fip.unwrap().windows(3).any(|w| w == [0xc0, 0xff, 0xee]), "{}", output);
+        outputs.push(output);
+    }
+    assert!(outputs[0].contains("Patched 3 bytes of the FIP") && !outputs[0].contains("taken from the cache"), "{}", outputs[0]);
+    assert!(outputs[1].contains("The FIP was prepared the same way before") && !outputs[1].contains("Patched"), "{}", outputs[1]);
+}
+
+#[test]
+fn fip_denied_by_the_policy_stops_the_boot_before_the_payload() {
+    let payload = image("policy", "bl2.bin", &data(0x1234, 1));
+    let fip = image("policy", "fip.bin", &fip(&data(40_000, 2), &data(260_000, 6)));
+    let policy = image("policy", "policy.txt", format!("fip_sha256={}\n", "ab".repeat(32)).as_bytes());
+    let device = Device::start(Options::default());
+    let (status, output) = run_tool(&["--serial", &device.url, "--policy", &policy, "--payload", &payload, "--aarch64", "--fip", &fip]);
+    let log = device.finish();
+    assert_eq!(status.code(), Some(8), "{}", output);
+    assert!(output.contains("is not allowed by the policy"), "{}", output);
+    assert!(log.commands.is_empty() && log.payload.is_none() && log.fip.is_none(), "{:x?}", log.commands);
+}
+
+#[test]
+fn payload_denied_by_the_policy_stops_the_boot_before_any_write() {
+    let payload = image("policy_payload", "bl2.bin", &data(0x1234, 3));
+    let policy = image("policy_payload", "policy.txt", format!("payload_sha256={}\n", "ab".repeat(32)).as_bytes());
+    let device = Device::start(Options { bl2: false, ..Options::default() });
+    let (status, output) = run_tool(&["--serial", &device.url, "--policy", &policy, "--payload", &payload, "--aarch64", "--disable-watchdog"]);
+    let log = device.finish();
+    assert_eq!(status.code(), Some(8), "{}", output);
+    assert!(log.commands.iter().all(|&cmd| mtk_uartboot::protocol::bootrom::is_read_only(cmd)), "{:x?}", log.commands);
+}
+
+#[test]
+fn soc_id_denied_by_the_policy_is_not_booted() {
+    let payload = image("policy_soc_id", "bl2.bin", &data(0x1234, 4));
+    let soc_id: String = SOC_ID.iter().map(|b| format!("{:02x}", b)).collect();
+    let policy = image("policy_soc_id", "policy.txt", format!("deny_soc_id={}\n", soc_id).as_bytes());
+    let device = Device::start(Options { bl2: false, ..Options::default() });
+    let (status, output) = run_tool(&["--serial", &device.url, "--policy", &policy, "--payload", &payload, "--aarch64"]);
+    let log = device.finish();
+    assert_eq!(status.code(), Some(8), "{}", output);
+    assert!(output.contains(&format!("SoC ID {} is not allowed by the policy", soc_id)), "{}", output);
+    assert_eq!(log.payload, None);
+}
+
+#[test]
+fn boots_aarch32_payload_only() {
+    let payload = data(0x800, 3);
+    let device = Device::start(Options { bl2: false, ..Options::default() });
+    let (status, output) = run_tool(&["--serial", &device.url, "--payload", &image("aarch32", "payload.bin", &payload),
+                                      "--aarch32", "--load-addr", "0x201000"]);
+    let log = device.finish();
+    assert!(status.success(), "{}", output);
+    assert_eq!(log.payload, Some((0x201000, payload)));
+    assert_eq!(log.jump, Some((0x201000, false)));
+}
+
+#[test]
+fn failed_power_cycle_is_reported() {
+    let payload = data(0x800, 4);
+    let device = Device::start(Options { bl2: false, ..Options::default() });
+    // Nothing listens on port 1, so the switch refuses the connection.
+    let (status, output) = run_tool(&["--serial", &device.url, "--power", "http://127.0.0.1:1/{state}",
+                                      "--payload", &image("power", "payload.bin", &payload), "--aarch32", "--load-addr", "0x201000"]);
+    let log = device.finish();
+    assert!(status.success(), "{}", output);
+    assert!(output.contains("Power cycle failed: ") && output.contains("but the BootROM answered"), "{}", output);
+    assert_eq!(log.payload, Some((0x201000, payload)));
+}
+
+#[test]
+fn info_only_reads() {
+    let device = Device::start(Options { bl2: false, ..Options::default() });
+    let (status, output) = run_tool(&["--serial", &device.url, "info"]);
+    let log = device.finish();
+    assert!(status.success(), "{}", output);
+    assert!(output.contains("0x7986"), "{}", output);
+    assert!(log.commands.iter().all(|&cmd| mtk_uartboot::protocol::bootrom::is_read_only(cmd)), "{:x?}", log.commands);
+    assert!(log.payload.is_none() && log.jump.is_none());
+}
+
+#[test]
+fn corrupted_payload_is_not_jumped_to() {
+    let device = Device::start(Options { corrupt_checksum: true, ..Options::default() });
+    let (status, output) = run_tool(&["--serial", &device.url, "--payload", &image("corrupt", "bl2.bin", &data(0x400, 4)), "--aarch64"]);
+    let log = device.finish();
+    assert!(!status.success(), "{}", output);
+    assert!(log.payload.is_some());
+    assert!(log.jump.is_none());
+}
+
+#[test]
+fn dry_run_sends_nothing() {
+    let device = Device::start(Options { bl2: false, ..Options::default() });
+    let (status, output) = run_tool(&["--serial", &device.url, "--payload", &image("dry-run", "bl2.bin", &data(0x400, 5)), "--dry-run"]);
+    let log = device.finish();
+    assert!(status.success(), "{}", output);
+    assert!(output.contains("Placement:"), "{}", output);
+    assert!(log.payload.is_none() && log.jump.is_none());
+}
+
+#[test]
+fn rescue_boots_the_board() {
+    let bl2 = data(0x1000, 7);
+    let fip = fip(&data(1000, 8), &data(2000, 9));
+    image("rescue", "bpi-r3-bl2.bin", &bl2);
+    let images = image("rescue", "bpi-r3-fip.bin", &fip);
+    let images = images.trim_end_matches("bpi-r3-fip.bin");
+    let device = Device::start(Options::default());
+    let (status, output) = run_tool(&["--serial", &device.url, "rescue", "--board", "bpi-r3", "--images", images]);
+    let log = device.finish();
+    assert!(status.success(), "{}", output);
+    assert_eq!(log.payload, Some((0x201000, bl2)));
+    assert_eq!(log.jump, Some((0x201000, true)));
+    assert!(log.fip.as_deref() == Some(&fip[..]) && log.go, "{}", output);
+    assert!(output.contains("Step 4: U-Boot is running"), "{}", output);
+}
+
+#[test]
+fn rescue_refuses_another_soc() {
+    image("rescue-soc", "xiaomi-ax3000t-bl2.bin", &data(0x1000, 10));
+    let images = image("rescue-soc", "xiaomi-ax3000t-fip.bin", &fip(&data(100, 11), &data(100, 12)));
+    let images = images.trim_end_matches("xiaomi-ax3000t-fip.bin");
+    let device = Device::start(Options::default());
+    let (status, output) = run_tool(&["--serial", &device.url, "rescue", "--board", "xiaomi-ax3000t", "--images", images]);
+    let log = device.finish();
+    assert!(!status.success(), "{}", output);
+    assert!(output.contains("not the MT7981"), "{}", output);
+    assert!(log.payload.is_none() && log.jump.is_none());
+}
+
+#[test]
+fn html_report_has_the_events() {
+    let path = image("html", "report.html", b"");
+    let device = Device::start(Options { bl2: false, ..Options::default() });
+    let (status, output) = run_tool(&["--serial", &device.url, "--payload", &image("html", "bl2.bin", &data(0x400, 13)),
+                                      "--aarch64", "--report-html", &path]);
+    device.finish();
+    assert!(status.success(), "{}", output);
+    let html = std::fs::read_to_string(&path).unwrap();
+    for part in ["<b class=\"ok\">ok</b>", "<td>payload_sent</td>", "<td>jumped</td>", "<th>payload.sha256</th>"] {
+        assert!(html.contains(part), "{} missing from {}", part, html);
+    }
+}
+
+#[test]
+fn json_output_is_typed_events() {
+    let device = Device::start(Options { bl2: false, ..Options::default() });
+    let (status, output) = run_tool(&["--serial", &device.url, "--payload", &image("json", "bl2.bin", &data(0x400, 17)),
+                                      "--aarch64", "--output", "json"]);
+    device.finish();
+    assert!(status.success(), "{}", output);
+    // The messages for people went to stderr, into the same log.
+    let events: Vec<StreamEvent> = output.lines()
+        .filter(|line| line.starts_with("{\"event\":"))
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{}: {}", e, line)))
+        .collect();
+    assert!(matches!(events.first(), Some(StreamEvent::Started { schema: stream::SCHEMA_VERSION, .. })), "{:?}", events);
+    assert!(events.contains(&StreamEvent::StageStarted { stage: RunStage::Bootrom }), "{:?}", events);
+    assert!(events.iter().any(|e| matches!(e, StreamEvent::PayloadSent { len: 0x400, .. })), "{:?}", events);
+    assert!(events.contains(&StreamEvent::Jumped { addr: "0x201000".to_owned(), aarch64: true }), "{:?}", events);
+    assert!(!events.contains(&StreamEvent::Unknown), "{:?}", events);
+    match events.last() {
+        Some(StreamEvent::Result { status, exit_code: 0, report, .. }) =>
+            assert!(status == "ok" && report.contains_key("payload.sha256"), "{:?}", events),
+        last => panic!("ends with {:?}", last),
+    }
+}
+
+#[test]
+fn flash_writes_only_changed_blocks() {
+    let old = data(0x40000, 20);
+    let mut new = old.clone();
+    new[0x100] ^= 0xff;
+    new[0x31000..0x31010].fill(0);
+    let mut storage = vec![0xff; FIP_PARTITION];
+    storage.extend_from_slice(&old);
+    let device = Device::start(Options { storage: Some(storage), ..Options::default() });
+    let (status, output) = run_tool(&["--serial", &device.url, "flash", "--yes", "--payload", &image("flash", "bl2.bin", &data(0x1000, 21)),
+                                      "--aarch64", "--image", &image("flash", "fip.bin", &new), "--partition", "fip",
+                                      "--changed-only", "--block-len", "0x10000"]);
+    let log = device.finish();
+    assert!(status.success(), "{}", output);
+    assert_eq!(log.flash_writes, vec![(FIP_PARTITION, 0x10000), (FIP_PARTITION + 0x30000, 0x10000)], "{}", output);
+    assert!(log.storage.as_deref().is_some_and(|s| s[FIP_PARTITION..] == new[..]));
+}
+
+#[test]
+fn flash_is_not_written_unless_confirmed() {
+    let storage = vec![0xff; FIP_PARTITION + 0x10000];
+    let device = Device::start(Options { storage: Some(storage.clone()), ..Options::default() });
+    // Nobody answers the question on stdin.
+    let (status, output) = run_tool(&["--serial", &device.url, "flash", "--payload", &image("flash_ask", "bl2.bin", &data(0x1000, 39)),
+                                      "--aarch64", "--image", &image("flash_ask", "fip.bin", &data(0x8000, 40)), "--partition", "fip"]);
+    let log = device.finish();
+    assert!(!status.success(), "{}", output);
+    assert!(output.contains("write partition fip [0x1000..0x9000]"), "{}", output);
+    assert!(log.flash_writes.is_empty(), "{}", output);
+}
+
+#[test]
+fn pipeline_writes_to_the_storage_only_with_yes() {
+    let payload = image("flash_pipeline", "bl2.bin", &data(0x1000, 41));
+    let fip = image("flash_pipeline", "fip.bin", &data(0x8000, 42));
+    let pipeline = image("flash_pipeline", "pipeline.yaml",
+                         format!("steps:\n  - boot: {{ payload: {}, aarch64: true, image: {}, partition: fip }}\n", payload, fip).as_bytes());
+    let (status, output) = run_tool(&["pipeline", "run", &pipeline, "--port", "/dev/null"]);
+    assert!(!status.success(), "{}", output);
+    assert!(output.contains("image writes to the storage of the device, which needs --yes"), "{}", output);
+
+    let device = Device::start(Options { storage: Some(vec![0xff; FIP_PARTITION + 0x10000]), ..Options::default() });
+    let (status, output) = run_tool(&["--yes", "pipeline", "run", &pipeline, "--port", &device.url]);
+    let log = device.finish();
+    assert!(status.success(), "{}", output);
+    assert_eq!(log.flash_writes, vec![(FIP_PARTITION, 0x8000)], "{}", output);
+}
+
+#[test]
+fn flash_switches_to_the_alternate_partition() {
+    const FIP_B: usize = 0x41000;
+    let old = data(0x40000, 22);
+    let new = data(0x40000, 23);
+    let mut storage = vec![0xff; FIP_PARTITION];
+    storage.extend_from_slice(&old);
+    storage.extend_from_slice(&old);
+    let device = Device::start(Options { storage: Some(storage), fip_b: Some(FIP_B), ..Options::default() });
+    let (status, output) = run_tool(&["--serial", &device.url, "flash", "--yes", "--payload", &image("flash_ab", "bl2.bin", &data(0x1000, 24)),
+                                      "--aarch64", "--image", &image("flash_ab", "fip.bin", &new), "--partition", "fip"]);
+    let log = device.finish();
+    assert!(status.success(), "{}", output);
+    assert_eq!(log.flash_writes, vec![(FIP_B, new.len())], "{}", output);
+    assert_eq!(log.switched_to.as_deref(), Some("fip_b"));
+    assert_eq!(log.storage_info_reads, 1, "{}", output);
+    let storage = log.storage.unwrap();
+    assert!(storage[FIP_PARTITION..FIP_B] == old[..] && storage[FIP_B..] == new[..]);
+}
+
+#[test]
+fn flash_refuses_an_image_larger_than_the_partition() {
+    let mut storage = vec![0xff; FIP_PARTITION];
+    storage.extend_from_slice(&data(0x10000, 25));
+    let device = Device::start(Options { storage: Some(storage), ..Options::default() });
+    let (status, output) = run_tool(&["--serial", &device.url, "flash", "--payload", &image("flash_large", "bl2.bin", &data(0x1000, 26)),
+                                      "--aarch64", "--image", &image("flash_large", "fip.bin", &data(0x20000, 27)), "--partition", "fip"]);
+    let log = device.finish();
+    assert!(!status.success(), "{}", output);
+    assert!(output.contains("doesn't fit partition fip"), "{}", output);
+    assert!(log.flash_writes.is_empty());
+}
+
+#[test]
+fn bl2_refusing_the_fip_says_why() {
+    let fip = fip(&data(40_000, 28), &data(60_000, 29));
+    let device = Device::start(Options { fip_limit: Some(0x10000), ..Options::default() });
+    let (status, output) = run_tool(&["--serial", &device.url, "--payload", &image("fip_limit", "bl2.bin", &data(0x1000, 30)),
+                                      "--aarch64", "--fip", &image("fip_limit", "fip.bin", &fip)]);
+    let log = device.finish();
+    assert!(!status.success(), "{}", output);
+    assert!(output.contains("BL2 refused send_fip: image larger than the target."), "{}", output);
+    assert!(log.fip.is_none());
+}
+
+#[test]
+fn aarch64_is_refused_on_an_aarch32_soc() {
+    let device = Device::start(Options { hw_code: 0x7623, bl2: false, ..Options::default() });
+    let (status, output) = run_tool(&["--serial", &device.url, "--payload", &image("caps_brom", "payload.bin", &data(0x800, 31)),
+                                      "--aarch64"]);
+    let log = device.finish();
+    assert!(!status.success(), "{}", output);
+    assert!(output.contains("start payloads in aarch64") && output.contains("MISSING"), "{}", output);
+    assert_eq!(log.payload, None);
+}
+
+#[test]
+fn flash_is_refused_by_a_bl2_without_it() {
+    let device = Device::start(Options::default());
+    let (status, output) = run_tool(&["--serial", &device.url, "flash", "--payload", &image("caps_bl2", "bl2.bin", &data(0x1000, 32)),
+                                      "--aarch64", "--image", &image("caps_bl2", "fip.bin", &data(0x1000, 33)), "--partition", "fip"]);
+    let log = device.finish();
+    assert!(!status.success(), "{}", output);
+    assert!(output.contains("write images to storage") && output.contains("needed by flash --image"), "{}", output);
+    assert!(log.flash_writes.is_empty());
+}
+
+#[test]
+fn subcommands_refuse_the_options_of_stages_they_skip() {
+    for (args, option) in [
+        (&["fip", "--fip", "fip.bin", "--payload", "bl2.bin"][..], "--payload"),
+        (&["flash", "--image", "fip.bin", "--partition", "fip", "--fip", "fip.bin"][..], "--fip"),
+        (&["flash", "--image", "fip.bin", "--partition", "fip", "--wait-boot", "login:"][..], "--wait-boot"),
+        (&["boot", "--payload", "bl2.bin", "--image", "fip.bin"][..], "--image"),
+    ] {
+        let (status, output) = run_tool(args);
+        assert_eq!(status.code(), Some(2), "{}", output);
+        assert!(output.contains(&format!("unexpected argument '{}'", option)), "{}", output);
+    }
+}
+
+#[test]
+fn watchdog_too_short_for_the_payload_is_warned_about() {
+    // Enabled, with a timeout of 64 units of 15.625 ms
+    let device = Device::start(Options { bl2: false, watchdog: Some((1, (63 << 5) | 8)), ..Options::default() });
+    let (status, output) = run_tool(&["--serial", &device.url, "--payload", &image("watchdog", "payload.bin", &data(0x20000, 34)),
+                                      "--aarch64"]);
+    device.finish();
+    assert!(status.success(), "{}", output);
+    assert!(output.contains("resets the device 1.0 s after it's started"), "{}", output);
+    assert!(output.contains("longer than the watchdog leaves"), "{}", output);
+}
+
+#[test]
+fn unit_differing_from_the_fleet_is_stopped() {
+    let payload = image("fleet", "payload.bin", &data(0x800, 35));
+    let pipeline = image("fleet", "pipeline.yaml", format!("steps:\n  - boot: {{ payload: {}, aarch64: true }}\n", payload).as_bytes());
+    let devices: Vec<Device> = [0, 0, 4].into_iter()
+        .map(|target_config| Device::start(Options { bl2: false, target_config, ..Options::default() }))
+        .collect();
+    let mut args = vec!["pipeline", "run", &pipeline, "--compare-config"];
+    for device in &devices {
+        args.extend(["--port", &device.url]);
+    }
+    let (status, output) = run_tool(&args);
+    let logs: Vec<_> = devices.into_iter().map(Device::finish).collect();
+    assert!(!status.success(), "{}", output);
+    assert!(output.contains("BootROMs of 3 ports:") && output.contains("0x4 DAA *"), "{}", output);
+    assert!(output.contains("The target config of this unit differs from most of the other ports."), "{}", output);
+    assert!(logs[0].payload.is_some() && logs[1].payload.is_some(), "{}", output);
+    assert_eq!(logs[2].payload, None);
+}
+
+#[test]
+fn port_failing_to_open_only_fails_itself() {
+    let payload = image("port_fails", "payload.bin", &data(0x800, 47));
+    let pipeline = image("port_fails", "pipeline.yaml", format!("steps:\n  - boot: {{ payload: {}, aarch64: true }}\n", payload).as_bytes());
+    let device = Device::start(Options { bl2: false, ..Options::default() });
+    let (status, output) = run_tool(&["pipeline", "run", &pipeline, "--port", "/nonexistent/ttyUSB9", "--port", &device.url]);
+    let ok = format!("{}: ok", device.url);
+    let log = device.finish();
+    assert_eq!(status.code(), Some(1), "{}", output);
+    assert!(output.contains("/nonexistent/ttyUSB9: FAILED") && output.contains(&ok), "{}", output);
+    assert!(log.payload.is_some(), "{}", output);
+}
+
+#[test]
+fn unit_id_is_bound_to_the_soc_id() {
+    let payload = image("unit_id", "payload.bin", &data(0x800, 43));
+    let report = image("unit_id", "report.txt", b"");
+    let pipeline = image("unit_id", "pipeline.yaml",
+                         format!("steps:\n  - boot: {{ payload: {}, aarch64: true }}\nreport: {}\n", payload, report).as_bytes());
+    let device = Device::start(Options { bl2: false, ..Options::default() });
+    let (status, output) = run_tool_with_input(&["pipeline", "run", &pipeline, "--scan-unit-id", "--port", &device.url], "SN0042\n");
+    let log = device.finish();
+    assert!(status.success(), "{}", output);
+    assert!(log.commands.contains(&mtk_uartboot::protocol::bootrom::CMD_GET_SOC_ID), "{}", output);
+    let soc_id: String = SOC_ID.iter().map(|b| format!("{:02x}", b)).collect();
+    let report = std::fs::read_to_string(&report).unwrap();
+    assert!(report.contains("fact.unit_id=SN0042") && report.contains(&format!("fact.soc_id={}", soc_id)), "{}", report);
+}
+
+#[test]
+fn sram_is_saved_before_the_payload_overwrites_it() {
+    let sram = data(0x3000, 36);
+    let device = Device::start(Options { bl2: false, sram: Some((0x201000, sram.clone())), ..Options::default() });
+    let dir = std::env::temp_dir().join(format!("mtk_uartboot-hw-sim-{}-save_sram", std::process::id()));
+    let (status, output) = run_tool(&["--serial", &device.url, "--payload", &image("save_sram", "payload.bin", &data(0x2800, 37)),
+                                      "--aarch64", "--save-sram", dir.to_str().unwrap(), "--save-sram-kib", "8"]);
+    let log = device.finish();
+    assert!(status.success(), "{}", output);
+    assert_eq!(std::fs::read(dir.join("sram-0x201000.bin")).unwrap(), &sram[..0x2000]);
+    assert!(log.payload.is_some());
+}
+
+#[test]
+fn doctor_checks_the_device_and_its_sram() {
+    let device = Device::start(Options { bl2: false, target_config: 0x2, sram: Some((0x201000, data(0x400, 38))), ..Options::default() });
+    let (status, output) = run_tool(&["--serial", &device.url, "doctor", "--sram-reads", "3"]);
+    let log = device.finish();
+    assert!(status.success(), "{}", output);
+    for line in ["PASS  handshake", "PASS  hardware info  MT7986", "WARN  target config  0x2, serial link authorization",
+                 "PASS  SRAM reads     3 reads of 1024 bytes at 0x201000 matched", "All checks passed, 1 warning."] {
+        assert!(output.contains(line), "{} missing from {}", line, output);
+    }
+    assert!(log.payload.is_none());
+}
@@ -0,0 +1,427 @@
+//! A scripted Mediatek device for the end-to-end tests: the BootROM download mode and, once
+//! the payload was jumped to, the UART download mode of BL2, served on a TCP socket the tool
+//! is pointed to with `--serial tcp://`. It records what it was sent.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Command, ExitStatus};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use mtk_uartboot::protocol::{bl2, bootrom};
+
+/// How the device behaves
+#[derive(Clone)]
+pub struct Options {
+    pub hw_code: u16,
+    /// Whether the payload is a BL2 waiting for the FIP once it runs
+    pub bl2: bool,
+    /// Answer send_da with a checksum off by one, as if the payload was corrupted on the line
+    pub corrupt_checksum: bool,
+    /// The storage BL2 writes to with the flash commands of protocol version 6, an eMMC with a
+    /// partition fip from FIP_PARTITION to its end. BL2 only speaks version 1 without it
+    pub storage: Option<Vec<u8>>,
+    /// Where the fip_b partition starts, the alternate of fip which ends there. fip has no
+    /// alternate without it
+    pub fip_b: Option<usize>,
+    /// The longest FIP BL2 takes, longer ones are refused with ACK_NAK
+    pub fip_limit: Option<usize>,
+    /// The mode and timeout registers of the watchdog, read with read32. Disabled without
+    pub watchdog: Option<(u32, u32)>,
+    /// The word get_target_config answers with
+    pub target_config: u32,
+    /// Memory read32 reads at an address, zero elsewhere
+    pub sram: Option<(u32, Vec<u8>)>,
+}
+
+/// Where the fip partition starts on the storage
+pub const FIP_PARTITION: usize = 0x1000;
+
+/// What get_soc_id answers with
+pub const SOC_ID: [u8; 32] = *b"\x5e\x1f\x07\xa3unit-0042-of-the-device-sim\x00";
+
+impl Default for Options {
+    fn default() -> Options {
+        Options { hw_code: 0x7986, bl2: true, corrupt_checksum: false, storage: None, fip_b: None, fip_limit: None, watchdog: None, target_config: 0, sram: None }
+    }
+}
+
+/// What the device was sent
+#[derive(Debug, Default)]
+pub struct Log {
+    /// The BootROM commands, in order
+    pub commands: Vec<u8>,
+    /// Load address and data of the payload
+    pub payload: Option<(u32, Vec<u8>)>,
+    /// Address jumped to, and whether in aarch64
+    pub jump: Option<(u32, bool)>,
+    pub fip: Option<Vec<u8>>,
+    /// Whether BL2 was told to boot the FIP
+    pub go: bool,
+    /// The storage after the flash commands, and the offsets and lengths written
+    pub storage: Option<Vec<u8>>,
+    pub flash_writes: Vec<(usize, usize)>,
+    /// The partition BL2 was told to boot from
+    pub switched_to: Option<String>,
+    /// How many times the partition table was asked for
+    pub storage_info_reads: usize,
+}
+
+pub struct Device {
+    pub url: String,
+    thread: JoinHandle<Log>,
+}
+
+impl Device {
+    pub fn start(options: Options) -> Device {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("tcp://{}", listener.local_addr().unwrap());
+        let thread = thread::spawn(move || {
+            let mut log = Log::default();
+            let (stream, _) = listener.accept().unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(20))).unwrap();
+            let mut line = Line { stream };
+            // The connection closing ends the script wherever it is.
+            let _ = run(&mut line, &options, &mut log);
+            log
+        });
+        Device { url, thread }
+    }
+
+    /// Waits for the tool to disconnect and returns what it sent
+    pub fn finish(self) -> Log {
+        self.thread.join().unwrap()
+    }
+}
+
+struct Line {
+    stream: TcpStream,
+}
+
+impl Line {
+    fn read(&mut self, n: usize) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0; n];
+        self.stream.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.stream.write_all(buf)
+    }
+
+    /// Reads n bytes and echoes them, as the BootROM and BL2 do with arguments
+    fn echo(&mut self, n: usize) -> io::Result<Vec<u8>> {
+        let buf = self.read(n)?;
+        self.write(&buf)?;
+        Ok(buf)
+    }
+
+    fn echo32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.echo(4)?.try_into().unwrap()))
+    }
+}
+
+fn run(line: &mut Line, options: &Options, log: &mut Log) -> io::Result<()> {
+    let mut pos = 0;
+    while pos < bootrom::HANDSHAKE.len() {
+        let c = line.read(1)?[0];
+        if c == bootrom::HANDSHAKE[pos] {
+            line.write(&[!c])?;
+            pos += 1;
+        } else {
+            pos = 0;
+        }
+    }
+    loop {
+        let cmd = line.echo(1)?[0];
+        log.commands.push(cmd);
+        match cmd {
+            bootrom::CMD_GET_HW_CODE => line.write(&[(options.hw_code >> 8) as u8, options.hw_code as u8, 0, 0])?,
+            bootrom::CMD_GET_HW_DICT => line.write(&[0x8a, 0x00, 0xca, 0x00, 0, 0, 0, 0])?,
+            bootrom::CMD_GET_SOC_ID => {
+                line.write(&(SOC_ID.len() as u32).to_be_bytes())?;
+                line.write(&SOC_ID)?;
+                line.write(&[0, 0])?;
+            }
+            bootrom::CMD_GET_TARGET_CONFIG => {
+                line.write(&options.target_config.to_be_bytes())?;
+                line.write(&[0, 0])?;
+            }
+            bootrom::CMD_SET_BAUDRATE => {
+                line.echo(4)?;
+                line.write(&[0, 0])?;
+            }
+            bootrom::CMD_READ32 => {
+                let addr = line.echo32()?;
+                let count = line.echo32()?;
+                line.write(&[0, 0])?;
+                let watchdog = mtk_uartboot::chips::lookup(options.hw_code).map(|chip| chip.watchdog);
+                let (mode, length) = options.watchdog.unwrap_or_default();
+                for word in (0..count).map(|i| addr + 4 * i) {
+                    let value = match watchdog {
+                        Some(base) if word == base => mode,
+                        Some(base) if word == base + mtk_uartboot::chips::WATCHDOG_LENGTH => length,
+                        _ => match &options.sram {
+                            Some((start, data)) if word >= *start => data.get((word - start) as usize..(word - start) as usize + 4)
+                                .map_or(0, |w| u32::from_le_bytes(w.try_into().unwrap())),
+                            _ => 0,
+                        },
+                    };
+                    line.write(&value.to_be_bytes())?;
+                }
+                line.write(&[0, 0])?;
+            }
+            bootrom::CMD_SEND_DA => {
+                let addr = line.echo32()?;
+                let len = line.echo32()?;
+                line.echo(4)?;
+                line.write(&[0, 0])?;
+                let data = line.read(len as usize)?;
+                let checksum = bootrom::da_checksum(&data).wrapping_add(options.corrupt_checksum as u16);
+                line.write(&checksum.to_be_bytes())?;
+                line.write(&[0, 0])?;
+                log.payload = Some((addr, data));
+            }
+            bootrom::CMD_JUMP_DA => {
+                log.jump = Some((line.echo32()?, false));
+                line.write(&[0, 0])?;
+                break;
+            }
+            bootrom::CMD_JUMP_DA64 => {
+                let addr = line.echo32()?;
+                let mode = line.echo(1)?[0];
+                line.write(&[0, 0])?;
+                line.echo(1)?;
+                line.write(&[0, 0])?;
+                log.jump = Some((addr, mode == bootrom::JUMP_DA64_AARCH64));
+                break;
+            }
+            _ => panic!("unexpected BootROM command {:#04x}", cmd),
+        }
+    }
+    if options.bl2 {
+        line.write(b"NOTICE:  BL2: v2.9(release)\r\nNOTICE:  Starting UART download handshake ...\r\n")?;
+        log.storage = options.storage.clone();
+        run_bl2(line, log, options)?;
+    }
+    Ok(())
+}
+
+fn bl2_handshake(line: &mut Line) -> io::Result<()> {
+    let mut pos = 0;
+    while pos < bl2::HANDSHAKE_REQ.len() {
+        if line.read(1)?[0] == bl2::HANDSHAKE_REQ[pos] {
+            line.write(&bl2::HANDSHAKE_RESP[pos..pos + 1])?;
+            pos += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Receives len bytes in packets, acknowledging each of them
+fn receive_packets(line: &mut Line, len: usize) -> io::Result<Vec<u8>> {
+    let mut received = Vec::with_capacity(len);
+    while received.len() < len {
+        let idx = line.echo(4)?;
+        let pkt_len = u16::from_be_bytes(line.echo(2)?.try_into().unwrap());
+        let checksum = line.echo(2)?;
+        let data = line.read(pkt_len as usize)?;
+        assert_eq!(bl2::packet_checksum(&data).to_be_bytes(), checksum[..], "checksum of packet {:?}", idx);
+        received.extend_from_slice(&data);
+        line.write(&idx)?;
+        line.write(&checksum)?;
+    }
+    Ok(received)
+}
+
+/// The range of the storage a partition takes, None for partitions which don't exist
+fn partition(name: &[u8], storage_len: usize, fip_b: Option<usize>) -> Option<(usize, usize)> {
+    match (name, fip_b) {
+        (b"fip", _) => Some((FIP_PARTITION, fip_b.unwrap_or(storage_len))),
+        (b"fip_b", Some(start)) => Some((start, storage_len)),
+        _ => None,
+    }
+}
+
+/// Receives the length of a partition name and the name
+fn partition_name(line: &mut Line) -> io::Result<Vec<u8>> {
+    let name_len = line.echo(1)?[0] as usize;
+    line.echo(name_len)
+}
+
+/// Receives the target of the flash commands and returns the range of the storage it
+/// leaves, None for partitions which don't exist
+fn flash_target(line: &mut Line, storage_len: usize, fip_b: Option<usize>) -> io::Result<Option<(usize, usize)>> {
+    let offset = |line: &mut Line| -> io::Result<usize> { Ok(u64::from_be_bytes(line.echo(8)?.try_into().unwrap()) as usize) };
+    let kind = line.echo(1)?[0];
+    if kind == bl2::FLASH_TARGET_OFFSET {
+        return Ok(Some((offset(line)?, storage_len)));
+    }
+    let name = partition_name(line)?;
+    let at = if kind == bl2::FLASH_TARGET_PARTITION_AT { offset(line)? } else { 0 };
+    Ok(partition(&name, storage_len, fip_b).map(|(start, end)| (start + at, end)))
+}
+
+fn run_bl2(line: &mut Line, log: &mut Log, options: &Options) -> io::Result<()> {
+    let fip_b = options.fip_b;
+    let version = if log.storage.is_some() { bl2::ACK_VERSION } else { 1 };
+    bl2_handshake(line)?;
+    loop {
+        let cmd = line.read(1)?[0];
+        if bl2::variant(version).command(cmd).is_none() {
+            line.write(&[bl2::ACK_NAK, bl2::ACK_UNSUPPORTED])?;
+            continue;
+        }
+        line.write(&[cmd])?;
+        match cmd {
+            bl2::CMD_VERSION => line.write(&[version])?,
+            bl2::CMD_SET_BAUDRATE => {
+                line.echo(4)?;
+                bl2_handshake(line)?;
+            }
+            bl2::CMD_SEND_FIP => {
+                let len_bytes = line.read(4)?;
+                let len = u32::from_be_bytes(len_bytes[..].try_into().unwrap()) as usize;
+                if options.fip_limit.is_some_and(|limit| len > limit) {
+                    line.write(&[bl2::ACK_NAK, bl2::FLASH_TOO_LARGE])?;
+                    continue;
+                }
+                line.write(&len_bytes)?;
+                log.fip = Some(receive_packets(line, len)?);
+            }
+            bl2::CMD_FLASH => {
+                let storage_len = log.storage.as_ref().expect("flash without storage").len();
+                let target = flash_target(line, storage_len, fip_b)?;
+                let len = line.echo32()? as usize;
+                let Some((start, end)) = target else {
+                    line.write(&[bl2::FLASH_NO_PARTITION])?;
+                    continue;
+                };
+                if start + len > end {
+                    line.write(&[bl2::FLASH_TOO_LARGE])?;
+                    continue;
+                }
+                line.write(&[bl2::FLASH_OK])?;
+                let data = receive_packets(line, len)?;
+                log.storage.as_mut().unwrap()[start..start + len].copy_from_slice(&data);
+                log.flash_writes.push((start, len));
+                line.write(&[bl2::FLASH_OK])?;
+                line.write(&bl2::crc32(0, &data).to_be_bytes())?;
+            }
+            bl2::CMD_FLASH_CRC => {
+                let storage = log.storage.as_ref().expect("flash_crc without storage");
+                let target = flash_target(line, storage.len(), fip_b)?;
+                let len = line.echo32()? as usize;
+                let block_len = line.echo32()? as usize;
+                let Some((start, end)) = target else {
+                    line.write(&[bl2::FLASH_NO_PARTITION])?;
+                    continue;
+                };
+                if start + len > end {
+                    line.write(&[bl2::FLASH_TOO_LARGE])?;
+                    continue;
+                }
+                line.write(&[bl2::FLASH_OK])?;
+                for block in storage[start..start + len].chunks(block_len) {
+                    line.write(&bl2::crc32(0, block).to_be_bytes())?;
+                }
+            }
+            bl2::CMD_FLASH_ALTERNATE => {
+                let name = partition_name(line)?;
+                match (name.as_slice(), fip_b) {
+                    (b"fip", Some(_)) => {
+                        line.write(&[bl2::FLASH_OK])?;
+                        line.write(&[5])?;
+                        line.write(b"fip_b")?;
+                    }
+                    _ => line.write(&[bl2::FLASH_NO_ALTERNATE])?,
+                }
+            }
+            bl2::CMD_FLASH_SWITCH => {
+                let name = partition_name(line)?;
+                log.switched_to = Some(String::from_utf8_lossy(&name).into_owned());
+                line.write(&[bl2::FLASH_OK])?;
+            }
+            bl2::CMD_FLASH_INFO => {
+                log.storage_info_reads += 1;
+                let capacity = log.storage.as_ref().expect("flash_info without storage").len();
+                let names: &[&[u8]] = if fip_b.is_some() { &[b"fip", b"fip_b"] } else { &[b"fip"] };
+                line.write(&[bl2::FLASH_OK, bl2::STORAGE_EMMC])?;
+                line.write(&512u32.to_be_bytes())?;
+                line.write(&(capacity as u64).to_be_bytes())?;
+                line.write(&[names.len() as u8])?;
+                for name in names {
+                    let (start, end) = partition(name, capacity, fip_b).unwrap();
+                    line.write(&[name.len() as u8])?;
+                    line.write(name)?;
+                    line.write(&(start as u64).to_be_bytes())?;
+                    line.write(&((end - start) as u64).to_be_bytes())?;
+                }
+            }
+            bl2::CMD_GO => {
+                log.go = true;
+                line.write(b"NOTICE:  BL31: v2.9(release)\r\nU-Boot 2024.01\r\n")?;
+                return Ok(());
+            }
+            cmd => panic!("unexpected BL2 command {:#04x}", cmd),
+        }
+    }
+}
+
+/// A FIP of a BL31 and a BL33 with these contents, laid out like fiptool does
+pub fn fip(bl31: &[u8], bl33: &[u8]) -> Vec<u8> {
+    const SOC_FW: &str = "47d4086d4cfe98469b952950cbbd5a00";
+    const NT_FW: &str = "d6d0eea7fcead54b97829934f234b6e4";
+    let uuid = |hex: &str| (0..16).map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap()).collect::<Vec<u8>>();
+    let mut fip = Vec::new();
+    fip.extend_from_slice(&0xaa64_0001u32.to_le_bytes());
+    fip.extend_from_slice(&[0; 12]);
+    let mut offset = 16 + 3 * 40;
+    for (name, image) in [(SOC_FW, bl31), (NT_FW, bl33)] {
+        fip.extend(uuid(name));
+        fip.extend_from_slice(&(offset as u64).to_le_bytes());
+        fip.extend_from_slice(&(image.len() as u64).to_le_bytes());
+        fip.extend_from_slice(&[0; 8]);
+        offset += image.len();
+    }
+    fip.extend_from_slice(&[0; 16]);
+    fip.extend_from_slice(&(offset as u64).to_le_bytes());
+    fip.extend_from_slice(&[0; 16]);
+    fip.extend_from_slice(bl31);
+    fip.extend_from_slice(bl33);
+    fip
+}
+
+/// Runs the tool with args, killing it if it takes longer than a minute
+pub fn run_tool(args: &[&str]) -> (ExitStatus, String) {
+    run_tool_with_input(args, "")
+}
+
+/// Runs the tool like [`run_tool`], with input typed into its stdin, e.g. answers to its
+/// prompts
+pub fn run_tool_with_input(args: &[&str], input: &str) -> (ExitStatus, String) {
+    let out = std::env::temp_dir().join(format!("mtk_uartboot-hw-sim-{}-{:?}.log", std::process::id(), thread::current().id()));
+    let log = std::fs::File::create(&out).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mtk_uartboot"))
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(log.try_clone().unwrap())
+        .stderr(log)
+        .spawn()
+        .unwrap();
+    // Closed once written, so a prompt asking for more reads the end of input.
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if started.elapsed() > Duration::from_secs(60) {
+            child.kill().unwrap();
+            break child.wait().unwrap();
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    let output = std::fs::read_to_string(&out).unwrap();
+    std::fs::remove_file(&out).ok();
+    (status, output)
+}